@@ -0,0 +1,192 @@
+use crate::{common::with_device, SharedOptions};
+
+use probe_rs::benchmark::{self, BenchmarkOptions, BenchmarkReport, Direction};
+
+use anyhow::Result;
+
+use std::str::FromStr;
+use std::time::Duration;
+
+/// The ways in which the benchmark results can be printed.
+pub(crate) enum BenchmarkFormat {
+    /// A table with the read and write throughput of every measurement.
+    Table,
+    /// A single JSON object with all measurements.
+    Json,
+}
+
+impl FromStr for BenchmarkFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_lowercase()[..] {
+            "table" => Ok(BenchmarkFormat::Table),
+            "json" => Ok(BenchmarkFormat::Json),
+            _ => Err(format!("Benchmark format '{}' is unknown.", s)),
+        }
+    }
+}
+
+pub(crate) fn run_benchmark(
+    shared_options: &SharedOptions,
+    address: Option<u32>,
+    duration_ms: u64,
+    halted_only: bool,
+    stream_block: u32,
+    format: BenchmarkFormat,
+) -> Result<()> {
+    with_device(shared_options, |mut session| {
+        let options = BenchmarkOptions {
+            address,
+            duration: Duration::from_millis(duration_ms),
+            running: !halted_only,
+            stream_block_size: Some(stream_block).filter(|&size| size > 0),
+            ..Default::default()
+        };
+
+        let report = benchmark::run(&mut session, &options)?;
+
+        match format {
+            BenchmarkFormat::Table => print_table(&report),
+            BenchmarkFormat::Json => println!("{}", report_to_json(&report)),
+        }
+
+        Ok(())
+    })
+}
+
+fn print_table(report: &BenchmarkReport) {
+    println!(
+        "Speed {} kHz, RAM at {:#010x}",
+        report.speed_khz, report.address
+    );
+    println!();
+    println!(
+        "{:<8} {:<7} {:>9} {:>14} {:>14}",
+        "Core", "Access", "Block", "Read", "Write"
+    );
+
+    // The measurements of a block come in pairs, one for every direction.
+    let writes = report
+        .measurements
+        .iter()
+        .filter(|m| m.direction == Direction::Write);
+
+    for write in writes {
+        let read = report.measurements.iter().find(|m| {
+            m.direction == Direction::Read
+                && m.core == write.core
+                && m.width == write.width
+                && m.block_size == write.block_size
+        });
+
+        println!(
+            "{:<8} {:<7} {:>9} {:>14} {:>14}",
+            write.core.to_string(),
+            write.width.to_string(),
+            format_size(u64::from(write.block_size)),
+            read.map(|m| format_throughput(m.bytes_per_second()))
+                .unwrap_or_default(),
+            format_throughput(write.bytes_per_second()),
+        );
+    }
+
+    if !report.rtt.is_empty() {
+        println!();
+        for rtt in &report.rtt {
+            println!(
+                "RTT polling ({}): {:.0} polls/s",
+                rtt.core,
+                rtt.polls_per_second()
+            );
+        }
+    }
+
+    if !report.stream.is_empty() {
+        println!();
+        for stream in &report.stream {
+            println!(
+                "Streaming {} blocks ({}): {}, {:.0} reads/s",
+                format_size(u64::from(stream.block_size)),
+                stream.core,
+                format_throughput(stream.bytes_per_second()),
+                stream.reads_per_second()
+            );
+        }
+    }
+}
+
+fn format_size(bytes: u64) -> String {
+    if bytes >= 1024 && bytes % 1024 == 0 {
+        format!("{} KiB", bytes / 1024)
+    } else {
+        format!("{} B", bytes)
+    }
+}
+
+fn format_throughput(bytes_per_second: f64) -> String {
+    if bytes_per_second >= 1024.0 * 1024.0 {
+        format!("{:.2} MiB/s", bytes_per_second / (1024.0 * 1024.0))
+    } else if bytes_per_second >= 1024.0 {
+        format!("{:.1} KiB/s", bytes_per_second / 1024.0)
+    } else {
+        format!("{:.0} B/s", bytes_per_second)
+    }
+}
+
+fn report_to_json(report: &BenchmarkReport) -> String {
+    let measurements: Vec<String> = report
+        .measurements
+        .iter()
+        .map(|m| {
+            format!(
+                r#"{{"core":"{}","direction":"{}","width":{},"block_size":{},"bytes":{},"time_us":{},"bytes_per_second":{:.0}}}"#,
+                m.core,
+                m.direction,
+                m.width.bits(),
+                m.block_size,
+                m.bytes,
+                m.duration.as_micros(),
+                m.bytes_per_second()
+            )
+        })
+        .collect();
+
+    let rtt: Vec<String> = report
+        .rtt
+        .iter()
+        .map(|r| {
+            format!(
+                r#"{{"core":"{}","polls":{},"time_us":{},"polls_per_second":{:.0}}}"#,
+                r.core,
+                r.polls,
+                r.duration.as_micros(),
+                r.polls_per_second()
+            )
+        })
+        .collect();
+
+    let stream: Vec<String> = report
+        .stream
+        .iter()
+        .map(|s| {
+            format!(
+                r#"{{"core":"{}","block_size":{},"reads":{},"time_us":{},"bytes_per_second":{:.0}}}"#,
+                s.core,
+                s.block_size,
+                s.reads,
+                s.duration.as_micros(),
+                s.bytes_per_second()
+            )
+        })
+        .collect();
+
+    format!(
+        r#"{{"speed_khz":{},"address":{},"measurements":[{}],"rtt":[{}],"stream":[{}]}}"#,
+        report.speed_khz,
+        report.address,
+        measurements.join(","),
+        rtt.join(","),
+        stream.join(",")
+    )
+}
@@ -1,11 +1,18 @@
 use crate::SharedOptions;
 
 use probe_rs::{
-    architecture::arm::ap::AccessPortError, config::TargetSelector, flashing::FileDownloadError,
-    DebugProbeError, Error, Probe, Session,
+    architecture::arm::{ap::AccessPortError, lock::RecoveryOption},
+    flashing::FileDownloadError,
+    session_config::SessionConfigError,
+    AttachMethod, DebugProbeError, DebugProbeSelector, Error, Probe, Session, SessionConfig,
+    SpeedSelection,
 };
 
+use std::collections::HashMap;
 use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
 use thiserror::Error;
 
 use anyhow::Result;
@@ -34,6 +41,12 @@ pub enum CliError {
     ),
     MissingArgument,
     UnableToOpenProbe(Option<&'static str>),
+    InvalidProbeAliases(PathBuf, #[source] toml::de::Error),
+    SessionConfig(
+        #[source]
+        #[from]
+        SessionConfigError,
+    ),
     ProbeRs(
         #[source]
         #[from]
@@ -55,12 +68,22 @@ impl fmt::Display for CliError {
                 None => write!(f, "Unable to open probe."),
                 Some(details) => write!(f, "Unable to open probe: {}", details),
             },
+            InvalidProbeAliases(ref path, ref e) => {
+                write!(
+                    f,
+                    "The probe alias file {} is invalid: {}",
+                    path.display(),
+                    e
+                )
+            }
+            SessionConfig(ref e) => e.fmt(f),
             ProbeRs(ref e) => e.fmt(f),
         }
     }
 }
 
-pub(crate) fn open_probe(index: Option<usize>) -> Result<Probe, CliError> {
+/// Opens the probe with the given index, and shares it with other processes if `share` is set.
+pub(crate) fn open_probe(index: Option<usize>, share: bool) -> Result<Probe, CliError> {
     let available_probes = Probe::list_all();
 
     let device = match index {
@@ -81,10 +104,171 @@ pub(crate) fn open_probe(index: Option<usize>) -> Result<Probe, CliError> {
         }
     };
 
-    let probe = device.open()?;
+    let probe = if share {
+        Probe::open_shared(device)?
+    } else {
+        device.open()?
+    };
     Ok(probe)
 }
 
+/// The file which maps probe aliases to selectors, relative to the home directory.
+const PROBE_ALIAS_FILE: &str = ".config/probe-rs/probes.toml";
+
+/// Returns the selector `probe` is an alias for, or `probe` itself if it is a selector.
+///
+/// The aliases are read from [PROBE_ALIAS_FILE], which contains lines like
+/// `rack-slot-3 = "usb:1-3.2"`. Selectors always contain a colon, so names
+/// without one are aliases.
+fn resolve_probe_alias(probe: &str) -> Result<String, CliError> {
+    if probe.contains(':') {
+        return Ok(probe.to_owned());
+    }
+
+    let unknown_alias = CliError::UnableToOpenProbe(Some(
+        "Unknown probe alias, aliases are defined in ~/.config/probe-rs/probes.toml",
+    ));
+
+    let home = match std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE")) {
+        Some(home) => PathBuf::from(home),
+        None => return Err(unknown_alias),
+    };
+    let path = home.join(PROBE_ALIAS_FILE);
+
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(unknown_alias),
+        Err(e) => return Err(e.into()),
+    };
+
+    let aliases: HashMap<String, String> =
+        toml::from_str(&content).map_err(|e| CliError::InvalidProbeAliases(path.clone(), e))?;
+
+    match aliases.get(probe) {
+        Some(selector) => {
+            log::debug!("Probe alias {} selects {}", probe, selector);
+            Ok(selector.clone())
+        }
+        None => Err(unknown_alias),
+    }
+}
+
+/// Resolves the configuration of the session from the flags in `shared_options` and
+/// the attach profile of the project, see [SessionConfig] for the order.
+pub(crate) fn session_config(shared_options: &SharedOptions) -> Result<SessionConfig, CliError> {
+    let protocol = match &shared_options.protocol {
+        Some(protocol) => Some(
+            protocol
+                .parse()
+                .map_err(|_e| CliError::UnableToOpenProbe(Some("Error while parsing protocol")))?,
+        ),
+        None => None,
+    };
+
+    let explicit = SessionConfig {
+        chip: shared_options.chip.clone(),
+        probe: shared_options.probe.clone(),
+        speed: shared_options.speed,
+        protocol,
+        reset_type: shared_options.reset_type.clone(),
+        rtt_scan_range: None,
+    };
+
+    let mut config = SessionConfig::resolve(
+        explicit,
+        &std::env::current_dir()?,
+        shared_options.profile.as_deref(),
+    )?;
+
+    // The flags which select the probe or the speed differently win over the profile too.
+    if shared_options.n.is_some() {
+        config.probe = None;
+    }
+    if shared_options.increase_speed {
+        config.speed = None;
+    }
+
+    Ok(config)
+}
+
+/// Opens the probe selected in `shared_options` and configures its protocol.
+pub(crate) fn open_configured_probe(shared_options: &SharedOptions) -> Result<Probe, CliError> {
+    let config = session_config(shared_options)?;
+    open_probe_with_config(shared_options, &config)
+}
+
+/// Opens the probe selected in `config` and configures it.
+fn open_probe_with_config(
+    shared_options: &SharedOptions,
+    config: &SessionConfig,
+) -> Result<Probe, CliError> {
+    let selector = match &config.probe {
+        Some(probe) => Some(resolve_probe_alias(probe)?),
+        None => None,
+    };
+
+    let mut probe = match &selector {
+        Some(address) if address.starts_with("tcp://") => Probe::open_remote(address)?,
+        Some(selector) => {
+            let selector = selector
+                .parse::<DebugProbeSelector>()
+                .map_err(|_e| CliError::UnableToOpenProbe(Some("Error while parsing probe")))?;
+
+            if shared_options.share {
+                Probe::open_shared(selector)?
+            } else {
+                Probe::open(selector)?
+            }
+        }
+        None => open_probe(shared_options.n, shared_options.share)?,
+    };
+
+    config.configure_probe(&mut probe)?;
+
+    if shared_options.increase_speed {
+        probe.set_speed_selection(SpeedSelection::Adaptive { increase: true });
+    }
+
+    if let Some(freeze) = shared_options.freeze_watchdogs {
+        probe.set_freeze_watchdogs(freeze);
+    }
+
+    if !shared_options.freeze.is_empty() {
+        probe.set_frozen_peripherals(shared_options.freeze.clone());
+    }
+
+    Ok(probe)
+}
+
+/// How long `--attach-on-wakeup` waits for the target, unless `--attach-timeout` is given.
+const DEFAULT_WAKEUP_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Returns the [AttachMethod] which was selected with the shared options.
+pub(crate) fn attach_method(shared_options: &SharedOptions) -> AttachMethod {
+    if shared_options.connect_under_reset {
+        AttachMethod::UnderReset {
+            assert_time: shared_options
+                .reset_assert_time
+                .map(Duration::from_millis)
+                .unwrap_or(AttachMethod::DEFAULT_ASSERT_TIME),
+            settle_time: shared_options
+                .reset_settle_time
+                .map(Duration::from_millis)
+                .unwrap_or(AttachMethod::DEFAULT_SETTLE_TIME),
+        }
+    } else if shared_options.software_reset {
+        AttachMethod::SoftwareOnly
+    } else if shared_options.attach_on_wakeup {
+        AttachMethod::on_wakeup(
+            shared_options
+                .attach_timeout
+                .unwrap_or(DEFAULT_WAKEUP_TIMEOUT),
+        )
+    } else {
+        AttachMethod::Normal
+    }
+}
+
 /// Takes a closure that is handed an `DAPLink` instance and then executed.
 /// After the closure is done, the USB device is always closed,
 /// even in an error case inside the closure!
@@ -92,26 +276,40 @@ pub(crate) fn with_device<F>(shared_options: &SharedOptions, f: F) -> Result<()>
 where
     F: FnOnce(Session) -> Result<()>,
 {
-    let mut probe = open_probe(shared_options.n)?;
+    let config = session_config(shared_options)?;
+    let probe = open_probe_with_config(shared_options, &config)?;
+
+    let target_selector = config.target_selector();
+
+    let session = if shared_options.no_halt {
+        probe.attach_running(target_selector)
+    } else {
+        probe.attach_with_method(target_selector, attach_method(shared_options))
+    };
 
-    let target_selector = match &shared_options.chip {
-        Some(identifier) => identifier.into(),
-        None => TargetSelector::Auto,
+    // The library can not know how the chip is unlocked with the CLI.
+    let mut session = match session {
+        Err(Error::DeviceLocked {
+            family_hint,
+            recovery_options,
+        }) if recovery_options
+            .iter()
+            .any(|option| matches!(option, RecoveryOption::Unlock(_))) =>
+        {
+            return Err(anyhow::Error::new(Error::DeviceLocked {
+                family_hint,
+                recovery_options,
+            })
+            .context("The chip is locked. Run `probe-rs-cli erase --allow-erase-all --recover --chip <chip>` to erase it completely and remove the lock."));
+        }
+        session => session?,
     };
 
-    if let Some(ref protocol) = shared_options.protocol {
-        probe.select_protocol(
-            protocol
-                .parse()
-                .map_err(|_e| CliError::UnableToOpenProbe(Some("Error while parsing protocol")))?,
-        )?;
+    if let Some(waited) = session.woke_up_after() {
+        eprintln!("The target woke up after {:.1?}.", waited);
     }
 
-    let session = if shared_options.connect_under_reset {
-        probe.attach_under_reset(target_selector)?
-    } else {
-        probe.attach(target_selector)?
-    };
+    config.configure_session(&mut session)?;
 
     f(session)
 }
@@ -0,0 +1,36 @@
+use crate::{common::session_config, SharedOptions};
+
+use probe_rs::session_config::{find_profile_file, PROFILE_FILE};
+use structopt::StructOpt;
+
+use anyhow::Result;
+
+#[derive(StructOpt)]
+pub(crate) enum ConfigCommand {
+    /// Store the configuration which the given flags and profile select as an attach profile.
+    /// It is written to the .probe-rs.toml file in the current directory or its parents
+    #[structopt(name = "save")]
+    Save {
+        /// The name of the profile. The `default` profile is used without --profile
+        name: String,
+
+        #[structopt(flatten)]
+        shared: SharedOptions,
+    },
+}
+
+pub(crate) fn run(command: ConfigCommand) -> Result<()> {
+    match command {
+        ConfigCommand::Save { name, shared } => {
+            let config = session_config(&shared)?;
+
+            let dir = std::env::current_dir()?;
+            let path = find_profile_file(&dir).unwrap_or_else(|| dir.join(PROFILE_FILE));
+            config.save_profile(&path, &name)?;
+
+            println!("Saved the profile '{}' to {}.", name, path.display());
+        }
+    }
+
+    Ok(())
+}
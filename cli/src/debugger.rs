@@ -2,8 +2,10 @@ use crate::common::CliError;
 
 use capstone::Capstone;
 use probe_rs::architecture::arm::CortexDump;
-use probe_rs::debug::DebugInfo;
-use probe_rs::{Core, CoreRegisterAddress, MemoryInterface};
+use probe_rs::debug::{
+    BreakpointKind, BreakpointLocation, BreakpointManager, DebugInfo, StepRecorder,
+};
+use probe_rs::{CoreRegisterAddress, MemoryInterface, Session};
 use std::fs::File;
 use std::{io::prelude::*, time::Duration};
 
@@ -26,24 +28,75 @@ impl DebugCli {
             help_text: "Step a single instruction",
 
             function: |cli_data, _args| {
-                let cpu_info = cli_data.core.step()?;
+                let pending = cli_data.recording.prepare(&mut cli_data.session.core(0)?)?;
+                let cpu_info = cli_data.breakpoints.step(cli_data.session)?;
+                cli_data
+                    .recording
+                    .record(&mut cli_data.session.core(0)?, pending)?;
                 println!("Core stopped at address 0x{:08x}", cpu_info.pc);
 
                 Ok(CliState::Continue)
             },
         });
 
+        cli.add_command(Command {
+            name: "step_back",
+            help_text: "Undo the last recorded step",
+
+            function: |cli_data, _args| {
+                match cli_data
+                    .recording
+                    .step_back(&mut cli_data.session.core(0)?)?
+                {
+                    Some(pc) => println!(
+                        "Core stopped at address 0x{:08x}, {} recorded steps left",
+                        pc,
+                        cli_data.recording.len()
+                    ),
+                    None => println!("No steps are recorded"),
+                }
+
+                Ok(CliState::Continue)
+            },
+        });
+
+        cli.add_command(Command {
+            name: "reverse_continue",
+            help_text: "Undo the recorded steps until a breakpoint is reached",
+
+            function: |cli_data, _args| {
+                let breakpoints: Vec<u32> = cli_data
+                    .breakpoints
+                    .breakpoints()
+                    .iter()
+                    .filter_map(|breakpoint| breakpoint.address())
+                    .collect();
+
+                match cli_data
+                    .recording
+                    .reverse_continue(&mut cli_data.session.core(0)?, &breakpoints)?
+                {
+                    Some(pc) => println!("Core stopped at address 0x{:08x}", pc),
+                    None => println!("No steps are recorded"),
+                }
+
+                Ok(CliState::Continue)
+            },
+        });
+
         cli.add_command(Command {
             name: "halt",
             help_text: "Stop the CPU",
 
             function: |cli_data, _args| {
-                let cpu_info = cli_data.core.halt(Duration::from_millis(100))?;
+                let mut core = cli_data.session.core(0)?;
+
+                let cpu_info = core.halt(Duration::from_millis(100))?;
                 println!("Core stopped at address 0x{:08x}", cpu_info.pc);
 
                 let mut code = [0u8; 16 * 2];
 
-                cli_data.core.read_8(cpu_info.pc, &mut code)?;
+                core.read_8(cpu_info.pc, &mut code)?;
 
                 /*
                 let instructions = cli_data
@@ -73,14 +126,14 @@ impl DebugCli {
             help_text: "Show current status of CPU",
 
             function: |cli_data, _args| {
-                let status = cli_data.core.status()?;
+                let mut core = cli_data.session.core(0)?;
+
+                let status = core.status()?;
 
                 println!("Status: {:?}", &status);
 
                 if status.is_halted() {
-                    let pc = cli_data
-                        .core
-                        .read_core_reg(cli_data.core.registers().program_counter())?;
+                    let pc = core.read_core_reg(core.registers().program_counter())?;
                     println!("Core halted at address {:#010x}", pc);
                 }
 
@@ -93,7 +146,8 @@ impl DebugCli {
             help_text: "Resume execution of the CPU",
 
             function: |cli_data, _args| {
-                cli_data.core.run()?;
+                discard_recording(cli_data);
+                cli_data.breakpoints.run(cli_data.session)?;
 
                 Ok(CliState::Continue)
             },
@@ -111,6 +165,8 @@ impl DebugCli {
             help_text: "Read 32bit value from memory",
 
             function: |cli_data, args| {
+                let mut core = cli_data.session.core(0)?;
+
                 let address_str = args.get(0).ok_or(CliError::MissingArgument)?;
 
                 let address = parse_u32_hex(address_str);
@@ -120,14 +176,23 @@ impl DebugCli {
                     .map(|c| c.parse::<usize>().expect("Couldn't parse number of words"))
                     .unwrap_or(1);
 
-                let mut buff = vec![0u32; num_words];
+                let mut buff = vec![0u8; num_words * 4];
 
-                cli_data.core.read_32(address, &mut buff)?;
+                let read = core.read_partial(address, &mut buff)?;
 
-                for (offset, word) in buff.iter().enumerate() {
+                for (offset, word) in buff[..read].chunks_exact(4).enumerate() {
+                    let word = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
                     println!("0x{:08x} = 0x{:08x}", address + (offset * 4) as u32, word);
                 }
 
+                if read < buff.len() {
+                    println!(
+                        "0x{:08x}..0x{:08x} is not readable",
+                        address + read as u32,
+                        address + buff.len() as u32
+                    );
+                }
+
                 Ok(CliState::Continue)
             },
         });
@@ -137,13 +202,15 @@ impl DebugCli {
             help_text: "Write a 32bit value to memory",
 
             function: |cli_data, args| {
+                let mut core = cli_data.session.core(0)?;
+
                 let address_str = args.get(0).ok_or(CliError::MissingArgument)?;
                 let address = parse_u32_hex(address_str);
 
                 let data_str = args.get(1).ok_or(CliError::MissingArgument)?;
                 let data = parse_u32_hex(data_str);
 
-                cli_data.core.write_word_32(address, data)?;
+                core.write_word_32(address, data)?;
 
                 Ok(CliState::Continue)
             },
@@ -151,15 +218,41 @@ impl DebugCli {
 
         cli.add_command(Command {
             name: "break",
-            help_text: "Set a breakpoint at a specifc address",
+            help_text: "Set a breakpoint at an address (0x...), a symbol (name[+offset]) or a source line (file:line[:column])",
 
             function: |cli_data, args| {
-                let address_str = args.get(0).ok_or(CliError::MissingArgument)?;
-                let address = parse_u32_hex(address_str);
-
-                cli_data.core.set_hw_breakpoint(address)?;
+                if args.is_empty() {
+                    return Err(CliError::MissingArgument);
+                }
 
-                println!("Set new breakpoint at address {:#08x}", address);
+                let location: BreakpointLocation = match args.join(" ").parse() {
+                    Ok(location) => location,
+                    Err(message) => {
+                        println!("{}", message);
+                        return Ok(CliState::Continue);
+                    }
+                };
+
+                let breakpoint = cli_data.breakpoints.add(
+                    cli_data.session,
+                    cli_data.debug_info.as_ref(),
+                    location,
+                )?;
+
+                match (breakpoint.verified(), breakpoint.address()) {
+                    (true, Some(address)) => println!(
+                        "Set breakpoint {} at address {:#010x} ({})",
+                        breakpoint.id(),
+                        address,
+                        breakpoint.location()
+                    ),
+                    _ => println!(
+                        "Breakpoint {} at {} is not verified: {}",
+                        breakpoint.id(),
+                        breakpoint.location(),
+                        breakpoint.message().unwrap_or("unknown reason")
+                    ),
+                }
 
                 Ok(CliState::Continue)
             },
@@ -167,13 +260,66 @@ impl DebugCli {
 
         cli.add_command(Command {
             name: "clear_break",
-            help_text: "Clear a breakpoint",
+            help_text: "Clear a breakpoint, by its number or its address (0x...)",
 
             function: |cli_data, args| {
-                let address_str = args.get(0).ok_or(CliError::MissingArgument)?;
-                let address = parse_u32_hex(address_str);
+                let arg = args.get(0).ok_or(CliError::MissingArgument)?;
+
+                let ids: Vec<usize> = match arg.strip_prefix("0x") {
+                    Some(address) => {
+                        let address = parse_u32_hex(address);
+                        cli_data
+                            .breakpoints
+                            .breakpoints()
+                            .iter()
+                            .filter(|bp| bp.address() == Some(address))
+                            .map(|bp| bp.id())
+                            .collect()
+                    }
+                    None => arg.parse().into_iter().collect(),
+                };
+
+                let mut cleared = false;
+                for id in ids {
+                    cleared |= cli_data.breakpoints.remove(cli_data.session, id)?;
+                }
+
+                if !cleared {
+                    println!("No breakpoint {} was found", arg);
+                }
+
+                Ok(CliState::Continue)
+            },
+        });
+
+        cli.add_command(Command {
+            name: "breakpoints",
+            help_text: "List the breakpoints",
+
+            function: |cli_data, _args| {
+                for breakpoint in cli_data.breakpoints.breakpoints() {
+                    let address = breakpoint
+                        .address()
+                        .map(|address| format!("{:#010x}", address))
+                        .unwrap_or_else(|| "-".to_owned());
+                    let state = match (breakpoint.verified(), breakpoint.kind()) {
+                        (true, Some(BreakpointKind::Software)) => "software".to_owned(),
+                        (true, Some(BreakpointKind::Flash)) => "in flash".to_owned(),
+                        (true, _) => "hardware".to_owned(),
+                        (false, _) => format!(
+                            "not verified: {}",
+                            breakpoint.message().unwrap_or("unknown reason")
+                        ),
+                    };
 
-                cli_data.core.clear_hw_breakpoint(address)?;
+                    println!(
+                        "{:>3} {:<12} {} ({})",
+                        breakpoint.id(),
+                        address,
+                        breakpoint.location(),
+                        state
+                    );
+                }
 
                 Ok(CliState::Continue)
             },
@@ -184,18 +330,30 @@ impl DebugCli {
             help_text: "Show backtrace",
 
             function: |cli_data, _args| {
-                let status = cli_data.core.status()?;
+                let memory_map = cli_data.session.target().memory_map.clone();
+                let mut core = cli_data.session.core(0)?;
+
+                let status = core.status()?;
 
                 if status.is_halted() {
-                    let regs = cli_data.core.registers();
-                    let program_counter = cli_data.core.read_core_reg(regs.program_counter())?;
+                    let regs = core.registers();
+                    let program_counter = core.read_core_reg(regs.program_counter())?;
 
                     if let Some(di) = &cli_data.debug_info {
-                        let frames = di.try_unwind(&mut cli_data.core, u64::from(program_counter));
+                        let mut frames = di
+                            .try_unwind(&mut core, u64::from(program_counter))
+                            .with_memory_map(&memory_map);
 
-                        for frame in frames {
+                        for frame in &mut frames {
                             println!("{}", frame);
                         }
+
+                        if let Some(address) = frames.unreadable_stack() {
+                            println!(
+                                "The stack at 0x{:08x} is not readable, the backtrace is incomplete.",
+                                address
+                            );
+                        }
                     } else {
                         println!("No debug information present!");
                     }
@@ -212,10 +370,12 @@ impl DebugCli {
             help_text: "Show CPU register values",
 
             function: |cli_data, _args| {
-                let register_file = cli_data.core.registers();
+                let mut core = cli_data.session.core(0)?;
+
+                let register_file = core.registers();
 
                 for register in register_file.registers() {
-                    let value = cli_data.core.read_core_reg(register)?;
+                    let value = core.read_core_reg(register)?;
 
                     println!("{}: {:#010x}", register.name(), value)
                 }
@@ -229,32 +389,40 @@ impl DebugCli {
             help_text: "Store a dump of the current CPU state",
 
             function: |cli_data, _args| {
+                let mut core = cli_data.session.core(0)?;
+
                 // dump all relevant data, stack and regs for now..
                 //
                 // stack beginning -> assume beginning to be hardcoded
 
                 let stack_top: u32 = 0x2000_0000 + 0x4000;
 
-                let regs = cli_data.core.registers();
+                let regs = core.registers();
 
-                let stack_bot: u32 = cli_data.core.read_core_reg(regs.stack_pointer())?;
-                let pc: u32 = cli_data.core.read_core_reg(regs.program_counter())?;
+                let stack_bot: u32 = core.read_core_reg(regs.stack_pointer())?;
+                let pc: u32 = core.read_core_reg(regs.program_counter())?;
 
                 let mut stack = vec![0u8; (stack_top - stack_bot) as usize];
 
-                cli_data.core.read_8(stack_bot, &mut stack[..])?;
+                let read = core.read_partial(stack_bot, &mut stack[..])?;
+                if read < stack.len() {
+                    println!(
+                        "Only 0x{:08x}..0x{:08x} of the stack is readable.",
+                        stack_bot,
+                        stack_bot + read as u32
+                    );
+                    stack.truncate(read);
+                }
 
                 let mut dump = CortexDump::new(stack_bot, stack);
 
                 for i in 0..12 {
                     dump.regs[i as usize] =
-                        cli_data
-                            .core
-                            .read_core_reg(Into::<CoreRegisterAddress>::into(i))?;
+                        core.read_core_reg(Into::<CoreRegisterAddress>::into(i))?;
                 }
 
                 dump.regs[13] = stack_bot;
-                dump.regs[14] = cli_data.core.read_core_reg(regs.return_address())?;
+                dump.regs[14] = core.read_core_reg(regs.return_address())?;
                 dump.regs[15] = pc;
 
                 let serialized = ron::ser::to_string(&dump).expect("Failed to serialize dump");
@@ -275,8 +443,13 @@ impl DebugCli {
             help_text: "Reset the CPU",
 
             function: |cli_data, _args| {
-                cli_data.core.halt(Duration::from_millis(100))?;
-                cli_data.core.reset_and_halt(Duration::from_millis(100))?;
+                let mut core = cli_data.session.core(0)?;
+
+                core.halt(Duration::from_millis(100))?;
+                core.reset_and_halt(Duration::from_millis(100))?;
+                drop(core);
+                discard_recording(cli_data);
+                cli_data.breakpoints.reinstall(cli_data.session)?;
 
                 Ok(CliState::Continue)
             },
@@ -324,9 +497,23 @@ impl DebugCli {
 }
 
 pub struct CliData<'p> {
-    pub core: Core<'p>,
+    pub session: &'p mut Session,
     pub debug_info: Option<DebugInfo>,
     pub capstone: Capstone,
+    pub breakpoints: BreakpointManager,
+    /// The steps which can be undone with `step_back`.
+    pub recording: StepRecorder,
+}
+
+/// Discards the recorded steps, which are invalid once the core ran freely.
+fn discard_recording(cli_data: &mut CliData) {
+    if !cli_data.recording.is_empty() {
+        println!(
+            "Discarding the recording of {} steps",
+            cli_data.recording.len()
+        );
+        cli_data.recording.clear();
+    }
 }
 
 pub enum CliState {
@@ -0,0 +1,101 @@
+use crate::{
+    common::{open_configured_probe, session_config, with_device},
+    output::OutputFormat,
+    progress::progress_reporter,
+    SharedOptions,
+};
+
+use probe_rs::{
+    config::get_target_by_name,
+    flashing::{erase_all, unlock},
+    Error, Permissions,
+};
+
+use anyhow::{anyhow, Result};
+
+pub(crate) fn erase_flash(
+    shared_options: &SharedOptions,
+    allow_erase_all: bool,
+    recover: bool,
+    progress_format: OutputFormat,
+) -> Result<()> {
+    if recover {
+        return recover_locked_chip(shared_options);
+    }
+
+    if allow_erase_all {
+        // A locked chip can not be identified, so it has to be given explicitly.
+        let chip = session_config(shared_options)?.chip.ok_or_else(|| {
+            anyhow!("--allow-erase-all requires the chip to be given with --chip or a profile")
+        })?;
+
+        let target = get_target_by_name(&chip)?;
+
+        match target.unlock_sequence {
+            Some(sequence) => {
+                let probe = open_configured_probe(shared_options)?;
+                unlock(probe, sequence, &Permissions::new().allow_erase_all())?;
+
+                println!("Erased the complete chip with {:?}.", sequence);
+                return Ok(());
+            }
+            None => log::warn!(
+                "No unlock sequence is known for {}, only the flash is erased.",
+                target.name
+            ),
+        }
+    }
+
+    let progress = progress_reporter(progress_format);
+
+    with_device(shared_options, |mut session| {
+        erase_all(&mut session, Some(&progress))?;
+
+        Ok(())
+    })
+}
+
+/// Unlocks the chip if it is locked, and attaches to it again.
+fn recover_locked_chip(shared_options: &SharedOptions) -> Result<()> {
+    let chip = session_config(shared_options)?.chip.ok_or_else(|| {
+        anyhow!("--recover requires the chip to be given with --chip or a profile")
+    })?;
+
+    let target = get_target_by_name(&chip)?;
+    let sequence = target
+        .unlock_sequence
+        .ok_or_else(|| anyhow!("No unlock sequence is known for {}", target.name))?;
+
+    match with_device(shared_options, |_| Ok(())) {
+        Err(e) if is_locked(&e) => {
+            log::warn!(
+                "The chip is locked, it is erased completely with {:?}.",
+                sequence
+            );
+
+            let probe = open_configured_probe(shared_options)?;
+            unlock(probe, sequence, &Permissions::new().allow_erase_all())?;
+        }
+        result => return result,
+    }
+
+    with_device(shared_options, |_| Ok(()))?;
+
+    // Some chips lock themselves again on their next reset until new firmware is flashed,
+    // e.g. a Kinetis with an erased flash configuration field.
+    println!(
+        "Recovered the chip with {:?}. Flash new firmware before the chip is reset.",
+        sequence
+    );
+
+    Ok(())
+}
+
+fn is_locked(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        matches!(
+            cause.downcast_ref::<Error>(),
+            Some(Error::DeviceLocked { .. })
+        )
+    })
+}
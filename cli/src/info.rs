@@ -1,19 +1,37 @@
-use crate::{common::open_probe, SharedOptions};
+use crate::{
+    common::{open_configured_probe, session_config, with_device},
+    output::OutputFormat,
+    power::print_power_information,
+    SharedOptions,
+};
 
 use probe_rs::{
     architecture::arm::{
         ap::{GenericAP, MemoryAP},
+        lock::{self, DeviceLock},
         m0::Demcr,
-        memory::Component,
-        ApInformation, MemoryApInformation,
+        memory::{discover_components, CoresightComponent},
+        ApInformation,
     },
+    config::{detect_chips, get_target_by_name, ChipInfo, UnlockSequence},
+    report::Record,
     CoreRegister,
 };
 
 use anyhow::Result;
 
-pub(crate) fn show_info_of_device(shared_options: &SharedOptions) -> Result<()> {
-    let mut probe = open_probe(shared_options.n)?;
+pub(crate) fn show_info_of_device(
+    shared_options: &SharedOptions,
+    format: OutputFormat,
+    measure_clock: bool,
+) -> Result<()> {
+    let mut probe = open_configured_probe(shared_options)?;
+
+    format.text(format!("Probe: {}", probe.get_name()));
+    if format == OutputFormat::Text {
+        print_power_information(&mut probe);
+    }
+
     probe.attach_to_unspecified()?;
 
     /*
@@ -41,7 +59,23 @@ pub(crate) fn show_info_of_device(shared_options: &SharedOptions) -> Result<()>
     let mut interface = probe.into_arm_interface()?;
 
     if let Some(interface) = &mut interface {
-        println!("\nAvailable Access Ports:");
+        // Without a chip, the access ports of all known protections are checked.
+        let target = shared_options
+            .chip
+            .as_ref()
+            .and_then(|chip| get_target_by_name(chip).ok());
+        let (core_ap, unlock_sequences) = match &target {
+            Some(target) => (
+                target.cores.first().map_or(0, |core| core.access_port),
+                target.unlock_sequence.into_iter().collect(),
+            ),
+            None => (0, UnlockSequence::ALL.to_vec()),
+        };
+
+        let lock = lock::detect(&mut **interface, core_ap, unlock_sequences)?;
+        print_lock_state(lock.as_ref(), format);
+
+        format.text("\nAvailable Access Ports:");
 
         let num_access_ports = interface.num_access_ports();
 
@@ -53,14 +87,20 @@ pub(crate) fn show_info_of_device(shared_options: &SharedOptions) -> Result<()>
             //let idr = interface.read_ap_register(access_port, IDR::default())?;
             //println!("{:#x?}", idr);
 
+            format.record(&Record::AccessPort {
+                index: ap_index as u8,
+                memory_ap: matches!(ap_information, ApInformation::MemoryAp(_)),
+                debug_base_address: match ap_information {
+                    ApInformation::MemoryAp(information) => Some(information.debug_base_address),
+                    ApInformation::Other { .. } => None,
+                },
+            });
+
             match ap_information {
-                ApInformation::MemoryAp(MemoryApInformation {
-                    debug_base_address, ..
-                }) => {
+                // The memory of a locked chip can not be accessed.
+                ApInformation::MemoryAp(_) if lock.is_some() => (),
+                ApInformation::MemoryAp(_) => {
                     let access_port: MemoryAP = access_port.into();
-
-                    let base_address = *debug_base_address;
-
                     let mut memory = interface.memory_interface(access_port)?;
 
                     // Enable
@@ -71,29 +111,178 @@ pub(crate) fn show_info_of_device(shared_options: &SharedOptions) -> Result<()>
                     let mut demcr = Demcr(memory.read_word_32(Demcr::ADDRESS)?);
                     demcr.set_dwtena(true);
                     memory.write_word_32(Demcr::ADDRESS, demcr.into())?;
+                }
+                ApInformation::Other { .. } => format.text("Unknown Type of access port"),
+            }
+        }
 
-                    let component_table = Component::try_parse(&mut memory, base_address);
-
-                    component_table
-                        .iter()
-                        .for_each(|entry| println!("{:#08x?}", entry));
-
-                    // let mut reader = crate::memory::romtable::RomTableReader::new(&link_ref, baseaddr as u64);
-
-                    // for e in reader.entries() {
-                    //     if let Ok(e) = e {
-                    //         println!("ROM Table Entry: Component @ 0x{:08x}", e.component_addr());
-                    //     }
-                    // }
+        match discover_components(&mut **interface) {
+            Ok(components) => {
+                format.text("\nCoreSight components:");
+                for component in &components {
+                    format.text(describe_component(component));
+                    format.record(&Record::from(component));
                 }
-                ApInformation::Other { .. } => println!("Unknown Type of access port"),
             }
+            Err(e) => format.text(format!(
+                "\nError while discovering the CoreSight components: {}",
+                e
+            )),
+        }
+
+        match interface.read_from_rom_table() {
+            Ok(Some(chip_info)) => print_detected_chip(ChipInfo::from(chip_info), format),
+            Ok(None) => format.text("\nThe connected chip could not be identified."),
+            Err(e) => format.text(format!(
+                "\nError while identifying the connected chip: {}",
+                e
+            )),
         }
     } else {
-        println!(
-            "No DAP interface was found on the connected probe. Thus, ARM info cannot be printed."
+        format.text(
+            "No DAP interface was found on the connected probe. Thus, ARM info cannot be printed.",
         )
     }
 
+    if let Some(chip) = session_config(shared_options)?.chip {
+        print_debug_freeze(&chip, format);
+    }
+
+    if measure_clock {
+        // The probe is opened again to attach to the target.
+        drop(interface);
+        print_core_clock(shared_options, format);
+    }
+
     Ok(())
 }
+
+fn print_lock_state(lock: Option<&DeviceLock>, format: OutputFormat) {
+    match lock {
+        Some(lock) => {
+            format.text(format!(
+                "\nLock state: locked ({})",
+                lock.family_hint.as_deref().unwrap_or("unknown protection")
+            ));
+            format.text("It can be recovered with:");
+            for option in &lock.recovery_options {
+                format.text(format!("\t{}", option));
+            }
+        }
+        None => format.text("\nLock state: unlocked"),
+    }
+
+    format.record(&Record::LockState {
+        locked: lock.is_some(),
+        family: lock.and_then(|lock| lock.family_hint.clone()),
+        recovery_options: lock
+            .map(|lock| {
+                lock.recovery_options
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect()
+            })
+            .unwrap_or_default(),
+    });
+}
+
+fn print_core_clock(shared_options: &SharedOptions, format: OutputFormat) {
+    let result = with_device(shared_options, |mut session| {
+        let measurement = session.measure_core_clock()?;
+
+        format.text(format!("\nCore clock: {}", measurement));
+        format.record(&Record::from(&measurement));
+
+        Ok(())
+    });
+
+    if let Err(e) = result {
+        format.text(format!("\nError while measuring the core clock: {}", e));
+    }
+}
+
+/// Describes the component on one line, indented by the depth of its ROM table.
+fn describe_component(component: &CoresightComponent) -> String {
+    let indent = "  ".repeat(component.depth + 1);
+    let peripheral_id = match &component.peripheral_id {
+        Some(peripheral_id) => peripheral_id,
+        None => {
+            return format!(
+                "{}AP {} {:#010x}: not accessible",
+                indent, component.access_port, component.address
+            )
+        }
+    };
+
+    let mut description = format!(
+        "{}AP {} {:#010x}: {} part {:#05x} rev {}",
+        indent,
+        component.access_port,
+        component.address,
+        if component.is_rom_table() {
+            "rom_table"
+        } else {
+            component.class.map_or("unknown", |class| class.name())
+        },
+        peripheral_id.part(),
+        peripheral_id.revision(),
+    );
+    if let Some(designer) = component.designer() {
+        description += &format!(" by {}", designer);
+    }
+    if let Some(architecture) = component.device_architecture {
+        description += &format!(" (architecture {:#06x})", architecture.archid);
+    }
+
+    description
+}
+
+fn print_debug_freeze(chip: &str, format: OutputFormat) {
+    let target = match get_target_by_name(chip) {
+        Ok(target) => target,
+        Err(e) => {
+            format.text(format!("\nError while looking up the chip {}: {}", chip, e));
+            return;
+        }
+    };
+
+    match &target.debug_freeze {
+        Some(debug_freeze) => {
+            format.text("\nPeripherals which can be frozen with --freeze:");
+            for bit in debug_freeze.bits.iter() {
+                format.text(format!("\t{}", bit.name));
+            }
+        }
+        None => format.text(format!(
+            "\nNo debug freeze bits are known for {}.",
+            target.name
+        )),
+    }
+}
+
+fn print_detected_chip(chip_info: ChipInfo, format: OutputFormat) {
+    format.text(format!("\nDetected chip: {}", chip_info));
+
+    let candidates = match detect_chips(&chip_info) {
+        Ok(candidates) if candidates.is_empty() => {
+            format.text("No matching target was found in the registry.");
+            candidates
+        }
+        Ok(candidates) => {
+            format.text("Matching targets:");
+            for candidate in &candidates {
+                format.text(format!("\t{}", candidate));
+            }
+            candidates
+        }
+        Err(e) => {
+            format.text(format!("Error while searching the registry: {}", e));
+            Vec::new()
+        }
+    };
+
+    format.record(&Record::DetectedChip {
+        chip_info: chip_info.to_string(),
+        candidates,
+    });
+}
@@ -0,0 +1,1304 @@
+//! The probe-rs CLI, which is also usable as a library.
+//!
+//! Binaries which link in further probe drivers register them and then run the CLI,
+//! so that their probes show up in `probe-rs-cli list` and can be selected with `--probe`:
+//!
+//! ```no_run
+//! # #[derive(Debug)]
+//! # struct MyProbeFactory;
+//! # impl probe_rs::ProbeFactory for MyProbeFactory {
+//! #     fn list_probes(&self) -> Vec<probe_rs::DebugProbeInfo> { vec![] }
+//! #     fn open(
+//! #         &self,
+//! #         _selector: &probe_rs::DebugProbeSelector,
+//! #     ) -> Result<Box<dyn probe_rs::DebugProbe>, probe_rs::DebugProbeError> {
+//! #         unimplemented!()
+//! #     }
+//! # }
+//! fn main() -> anyhow::Result<()> {
+//!     probe_rs::register_probe_factory(MyProbeFactory);
+//!     probe_rs_cli::run()
+//! }
+//! ```
+
+mod benchmark;
+mod common;
+mod config;
+mod debugger;
+mod erase;
+mod info;
+mod options;
+mod output;
+mod power;
+mod progress;
+mod read;
+mod reg;
+mod trace;
+mod verify;
+
+use common::with_device;
+use debugger::CliState;
+
+use probe_rs::{
+    asynchronous::Cancellation,
+    config::{get_target_by_name, RawFlashAlgorithm},
+    debug::{BreakpointManager, DebugInfo, DebugInfoOptions, StepRecorder},
+    flashing::{
+        download_files_with_options, layout_files, BinOptions, DownloadOptions, FileDownloadError,
+        FillPolicy, FlashAlgorithmProperties, FlashError, FlashFailure, Format, Image,
+        PreservePriority, Uf2Options,
+    },
+    report::{ProbeCapabilities, ProbeRecord, Record},
+    EventReceiver, MemoryInterface, Permissions, Probe, ProbeServer, ResetType, Session,
+    SessionEvent,
+};
+
+use capstone::{arch::arm::ArchMode, prelude::*, Capstone, Endian};
+use rustyline::Editor;
+use structopt::StructOpt;
+
+use anyhow::{anyhow, Result};
+
+use std::net::TcpListener;
+use std::num::ParseIntError;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// The number of steps the debugger can undo.
+const RECORDED_STEPS: usize = 1000;
+
+fn parse_hex(src: &str) -> Result<u32, ParseIntError> {
+    u32::from_str_radix(src, 16)
+}
+
+/// Parses an address which is either decimal or hexadecimal with a `0x` prefix.
+fn parse_address(src: &str) -> Result<u32, ParseIntError> {
+    if let Some(hex) = src.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16)
+    } else {
+        src.parse()
+    }
+}
+
+/// Parses an address range in the form `start..end`.
+fn parse_range(src: &str) -> Result<Range<u32>, String> {
+    let mut parts = src.splitn(2, "..");
+
+    let (start, end) = match (parts.next(), parts.next()) {
+        (Some(start), Some(end)) => (start, end),
+        _ => return Err(format!("'{}' is not a range of the form start..end", src)),
+    };
+
+    let start = parse_address(start).map_err(|e| e.to_string())?;
+    let end = parse_address(end).map_err(|e| e.to_string())?;
+
+    if start >= end {
+        return Err(format!("The range '{}' is empty", src));
+    }
+
+    Ok(start..end)
+}
+
+/// Parses a duration with a `ms`, `s` or `m` suffix, or in seconds without a suffix.
+fn parse_duration(src: &str) -> Result<Duration, String> {
+    let (number, millis_per_unit) = if let Some(number) = src.strip_suffix("ms") {
+        (number, 1)
+    } else if let Some(number) = src.strip_suffix('s') {
+        (number, 1_000)
+    } else if let Some(number) = src.strip_suffix('m') {
+        (number, 60_000)
+    } else {
+        (src, 1_000)
+    };
+
+    let value: u64 = number
+        .trim()
+        .parse()
+        .map_err(|_| format!("'{}' is not a valid duration", src))?;
+
+    Ok(Duration::from_millis(value * millis_per_unit))
+}
+
+fn parse_preserve_priority(src: &str) -> Result<PreservePriority, String> {
+    match src {
+        "existing" => Ok(PreservePriority::Existing),
+        "image" => Ok(PreservePriority::Image),
+        _ => Err(format!("Preserve priority '{}' is unknown.", src)),
+    }
+}
+
+fn parse_fill_policy(src: &str) -> Result<FillPolicy, String> {
+    match src {
+        "erase-value" => Ok(FillPolicy::EraseValue),
+        "read-back" => Ok(FillPolicy::ReadBack),
+        "error" => Ok(FillPolicy::Error),
+        _ => Err(format!("Fill policy '{}' is unknown.", src)),
+    }
+}
+
+/// The file formats which can be downloaded with the CLI.
+#[derive(Clone, Copy)]
+enum FileFormat {
+    Elf,
+    Hex,
+    Uf2,
+}
+
+impl FromStr for FileFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_lowercase()[..] {
+            "elf" => Ok(FileFormat::Elf),
+            "hex" | "ihex" => Ok(FileFormat::Hex),
+            "uf2" => Ok(FileFormat::Uf2),
+            _ => Err(format!("Format '{}' is unknown.", s)),
+        }
+    }
+}
+
+impl FileFormat {
+    fn into_format(self, allow_family_mismatch: bool) -> Format {
+        match self {
+            FileFormat::Elf => Format::Elf,
+            FileFormat::Hex => Format::Hex,
+            FileFormat::Uf2 => Format::Uf2(Uf2Options {
+                allow_family_mismatch,
+            }),
+        }
+    }
+}
+
+/// A file which is downloaded with `--image`, in the form `path[@address]`.
+///
+/// The format is derived from the extension of the file. Binary files (`.bin`) require the
+/// address at which they are programmed.
+#[derive(Clone)]
+struct ImageArgument {
+    path: PathBuf,
+    format: Option<FileFormat>,
+    base_address: Option<u32>,
+}
+
+impl FromStr for ImageArgument {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (path, base_address) = match s.rsplitn(2, '@').collect::<Vec<_>>()[..] {
+            [address, path] => (
+                path,
+                Some(parse_address(address).map_err(|e| {
+                    format!(
+                        "'{}' is not a valid base address of {}: {}",
+                        address, path, e
+                    )
+                })?),
+            ),
+            _ => (s, None),
+        };
+
+        let extension = Path::new(path)
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+        let format = match extension.as_deref() {
+            Some("bin") => None,
+            Some("hex") | Some("ihex") => Some(FileFormat::Hex),
+            Some("uf2") => Some(FileFormat::Uf2),
+            _ => Some(FileFormat::Elf),
+        };
+
+        match (format, base_address) {
+            (None, None) => Err(format!(
+                "The binary file {} requires a base address, e.g. {}@0x08000000.",
+                path, path
+            )),
+            (Some(_), Some(_)) => Err(format!(
+                "Only binary files can be given a base address, {} contains its addresses.",
+                path
+            )),
+            _ => Ok(ImageArgument {
+                path: PathBuf::from(path),
+                format,
+                base_address,
+            }),
+        }
+    }
+}
+
+/// Collects the file given as argument with its `format`, followed by the ones given with `--image`.
+fn images(
+    path: &Option<String>,
+    format: FileFormat,
+    images: &[ImageArgument],
+    allow_family_mismatch: bool,
+) -> Vec<Image> {
+    path.iter()
+        .map(|path| Image {
+            path: PathBuf::from(path),
+            format: format.into_format(allow_family_mismatch),
+        })
+        .chain(
+            images
+                .iter()
+                .cloned()
+                .map(|image| image.into_image(allow_family_mismatch)),
+        )
+        .collect()
+}
+
+impl ImageArgument {
+    fn into_image(self, allow_family_mismatch: bool) -> Image {
+        let format = match self.format {
+            Some(format) => format.into_format(allow_family_mismatch),
+            None => Format::Bin(BinOptions {
+                base_address: self.base_address,
+                skip: 0,
+            }),
+        };
+
+        Image {
+            path: self.path,
+            format,
+        }
+    }
+}
+
+#[derive(StructOpt)]
+#[structopt(
+    name = "Probe-rs CLI",
+    about = "A CLI for on top of the debug probe capabilities provided by probe-rs",
+    author = "Noah Hüsser <yatekii@yatekii.ch> / Dominik Böhi <dominik.boehi@gmail.ch>"
+)]
+enum CLI {
+    /// List all connected debug probes
+    #[structopt(name = "list")]
+    List {
+        /// Also show the USB port path of each probe, which can be used to select it with --probe usb:<port path>
+        #[structopt(long, short)]
+        verbose: bool,
+
+        /// The format of the output (text or json). The JSON output also contains the
+        /// capabilities of the probes, which are opened to query them
+        #[structopt(long, default_value = "text")]
+        format: output::OutputFormat,
+    },
+    /// Gets infos about the selected debug probe and connected target
+    #[structopt(name = "info")]
+    Info {
+        #[structopt(flatten)]
+        shared: SharedOptions,
+
+        /// The format of the output (text or json)
+        #[structopt(long, default_value = "text")]
+        format: output::OutputFormat,
+
+        /// Measure the clock frequency of the first core. The firmware is stopped for
+        /// a short time while it is measured
+        #[structopt(long)]
+        measure_clock: bool,
+    },
+    /// Resets the target attached to the selected debug probe
+    #[structopt(name = "reset")]
+    Reset {
+        #[structopt(flatten)]
+        shared: SharedOptions,
+
+        /// Whether the reset pin should be asserted or deasserted. If left open, just pulse it
+        assert: Option<bool>,
+    },
+    #[structopt(name = "debug")]
+    Debug {
+        #[structopt(flatten)]
+        shared: SharedOptions,
+
+        #[structopt(long, parse(from_os_str))]
+        /// Binary to debug
+        exe: Option<PathBuf>,
+
+        /// A directory in which the separate debug file of a stripped binary is looked up,
+        /// by build ID (`.build-id/ab/cdef.debug`) or by the name in its `.gnu_debuglink`
+        #[structopt(long, parse(from_os_str), number_of_values = 1)]
+        debug_file_directory: Vec<PathBuf>,
+
+        /// Program breakpoints into the flash when all hardware breakpoint units are in use,
+        /// erasing and programming each flash sector at most this many times
+        #[structopt(long, value_name = "max rewrites per sector")]
+        flash_breakpoints: Option<u32>,
+    },
+    /// Dump memory from attached target, or write a core dump of it with --output
+    #[structopt(name = "dump")]
+    Dump {
+        #[structopt(flatten)]
+        shared: SharedOptions,
+
+        /// The address of the memory to dump from the target (in hexadecimal without 0x prefix)
+        #[structopt(parse(try_from_str = parse_hex), required_unless = "output")]
+        loc: Option<u32>,
+        /// The amount of memory (in words) to dump
+        #[structopt(required_unless = "output")]
+        words: Option<u32>,
+
+        /// Write the registers of all cores and the memory into this ELF core file,
+        /// which can be opened with `gdb app.elf <file>`. Use --no-halt to take the
+        /// dump without resetting the target
+        #[structopt(long, parse(from_os_str), conflicts_with_all = &["loc", "words"])]
+        output: Option<PathBuf>,
+
+        /// Address range to include in the core dump, e.g. 0x20000000..0x20010000.
+        /// Can be given multiple times. If not given, all RAM regions are included.
+        #[structopt(long, parse(try_from_str = parse_range), number_of_values = 1, requires = "output")]
+        range: Vec<Range<u32>>,
+    },
+    /// Download memory to attached target
+    #[structopt(name = "download")]
+    Download {
+        #[structopt(flatten)]
+        shared: SharedOptions,
+
+        /// The path to the file to be downloaded to the flash
+        #[structopt(required_unless = "image")]
+        path: Option<String>,
+
+        /// The format of the file (elf, hex or uf2)
+        #[structopt(long, default_value = "elf")]
+        format: FileFormat,
+
+        /// Another file which is downloaded, in the form path[@address], e.g. fs.bin@0x08060000.
+        /// The format is derived from the extension, binary files require an address.
+        /// Can be given multiple times, all files are programmed together
+        #[structopt(long, number_of_values = 1)]
+        image: Vec<ImageArgument>,
+
+        /// Reset the target after all files are downloaded, such that it runs the new firmware
+        #[structopt(long)]
+        reset: bool,
+
+        /// Only warn instead of failing if the family ID of a UF2 file does not match the target
+        #[structopt(long)]
+        allow_family_mismatch: bool,
+
+        /// Skip the sectors which already contain the data to be written, instead of erasing and programming them
+        #[structopt(long)]
+        skip_unchanged: bool,
+
+        /// Verify the flash contents after programming
+        #[structopt(long)]
+        verify: bool,
+
+        /// Transfer each page only after the previous one has been programmed
+        #[structopt(long)]
+        disable_double_buffering: bool,
+
+        /// The format of the progress output (text or json). The JSON output ends with
+        /// a `download_finished` record
+        #[structopt(long, default_value = "text")]
+        progress_format: output::OutputFormat,
+
+        /// Address range whose current flash contents are kept, e.g. 0x0807F800..0x08080000.
+        /// Can be given multiple times.
+        #[structopt(long, parse(try_from_str = parse_range), number_of_values = 1)]
+        preserve: Vec<Range<u32>>,
+
+        /// Which data wins if a preserved range overlaps with the image (existing or image).
+        /// If not given, an overlap is an error.
+        #[structopt(long, parse(try_from_str = parse_preserve_priority))]
+        preserve_priority: Option<PreservePriority>,
+
+        /// What is written to erased bytes which are not part of the image
+        /// (erase-value, read-back or error)
+        #[structopt(long, default_value = "erase-value", parse(try_from_str = parse_fill_policy))]
+        fill_policy: FillPolicy,
+
+        /// Log the registers and the stack of the flash algorithm if one of its routines fails,
+        /// and leave the core halted at the failure
+        #[structopt(long)]
+        flash_algo_debug: bool,
+
+        /// Halt the core at the entry of the given flash algorithm routine (e.g. program_page)
+        /// instead of running it, such that it can be stepped through with a debugger
+        #[structopt(long)]
+        flash_algo_break: Option<String>,
+
+        /// Retry programming this many times after a communication failure.
+        /// Sectors which were completely programmed are not programmed again
+        #[structopt(long, default_value = "0")]
+        retries: u32,
+
+        /// Allow writing to one-time programmable regions, like eFuses, OTP areas or the UICR of nRF chips.
+        /// Bits programmed in these regions can not be cleared again, or only with a full chip erase
+        #[structopt(long)]
+        allow_otp: bool,
+
+        /// Lay out the flash contents without connecting to a probe. Requires --chip
+        #[structopt(long)]
+        dry_run: bool,
+
+        /// Write the laid out flash contents to this file (.hex for Intel HEX, otherwise binary).
+        /// A report of the erased and restored ranges is written next to it. Requires --chip
+        #[structopt(long, parse(from_os_str))]
+        emit_layout: Option<PathBuf>,
+
+        /// Program with the flash algorithm in this ELF or FLM file instead of the algorithms of the target.
+        /// The algorithm is only used for this download
+        #[structopt(long, parse(from_os_str))]
+        flash_algorithm: Option<PathBuf>,
+
+        /// The address range which is programmed with --flash-algorithm, e.g. 0x90000000..0x91000000.
+        /// Defaults to the range of the FlashDevice description in the file
+        #[structopt(long = "range", parse(try_from_str = parse_range), requires = "flash-algorithm")]
+        flash_algorithm_range: Option<Range<u32>>,
+    },
+    /// Erase the complete flash of the attached target
+    #[structopt(name = "erase")]
+    Erase {
+        #[structopt(flatten)]
+        shared: SharedOptions,
+
+        /// Unlock a read protected chip with the unlock sequence of its target description.
+        /// This erases the complete chip, including its configuration and protection settings.
+        /// Requires --chip
+        #[structopt(long)]
+        allow_erase_all: bool,
+
+        /// Only unlock the chip if it refuses the debug access because it is locked,
+        /// and check that it can be attached to afterwards. Requires --allow-erase-all
+        #[structopt(long, requires = "allow-erase-all")]
+        recover: bool,
+
+        /// The format of the progress output (text or json)
+        #[structopt(long, default_value = "text")]
+        progress_format: output::OutputFormat,
+    },
+    /// Compare the flash contents of the attached target with files, without programming anything
+    #[structopt(name = "verify")]
+    Verify {
+        #[structopt(flatten)]
+        shared: SharedOptions,
+
+        /// The path to the file to be compared with the flash
+        #[structopt(required_unless = "image")]
+        path: Option<String>,
+
+        /// The format of the file (elf, hex or uf2)
+        #[structopt(long, default_value = "elf")]
+        format: FileFormat,
+
+        /// Another file which is compared, in the form path[@address], like for download.
+        /// Can be given multiple times
+        #[structopt(long, number_of_values = 1)]
+        image: Vec<ImageArgument>,
+
+        /// Only warn instead of failing if the family ID of a UF2 file does not match the target
+        #[structopt(long)]
+        allow_family_mismatch: bool,
+
+        /// The number of differing ranges which are listed
+        #[structopt(long, default_value = "10")]
+        max_ranges: usize,
+
+        /// The format of the progress output (text or json)
+        #[structopt(long, default_value = "text")]
+        progress_format: output::OutputFormat,
+    },
+    /// Check that the flash of the attached target is erased
+    #[structopt(name = "blank-check")]
+    BlankCheck {
+        #[structopt(flatten)]
+        shared: SharedOptions,
+
+        /// The address range which is checked, e.g. 0x08000000..0x08010000.
+        /// Can be given multiple times. If no range is given, all NVM regions are checked
+        #[structopt(long, parse(try_from_str = parse_range), number_of_values = 1)]
+        range: Vec<Range<u32>>,
+
+        /// The number of ranges which are not erased that are listed
+        #[structopt(long, default_value = "10")]
+        max_ranges: usize,
+
+        /// The format of the progress output (text or json)
+        #[structopt(long, default_value = "text")]
+        progress_format: output::OutputFormat,
+    },
+    /// Read back the flash contents of the attached target into a file
+    #[structopt(name = "read")]
+    Read {
+        #[structopt(flatten)]
+        shared: SharedOptions,
+
+        /// The start address of the memory to read (in hexadecimal without 0x prefix).
+        /// If no address is given, all NVM regions of the target are read.
+        #[structopt(long, parse(try_from_str = parse_hex))]
+        address: Option<u32>,
+
+        /// The amount of bytes to read (in hexadecimal without 0x prefix)
+        #[structopt(long, parse(try_from_str = parse_hex))]
+        size: Option<u32>,
+
+        /// The format of the output file (bin, hex or elf)
+        #[structopt(long, default_value = "bin")]
+        format: read::OutputFormat,
+
+        /// The path of the file the contents are written to
+        #[structopt(long, short, parse(from_os_str))]
+        output: PathBuf,
+
+        /// Read a sample of the memory a second time to verify the result
+        #[structopt(long)]
+        verify: bool,
+
+        /// The format of the progress output (text or json)
+        #[structopt(long, default_value = "text")]
+        progress_format: output::OutputFormat,
+    },
+    /// Read or modify the option bytes of the attached target
+    #[structopt(name = "options")]
+    Options {
+        #[structopt(subcommand)]
+        command: options::OptionsCommand,
+    },
+    /// Read or write the registers of the peripherals of the attached target by name,
+    /// with the descriptions in an SVD file
+    #[structopt(name = "reg")]
+    Reg {
+        #[structopt(subcommand)]
+        command: reg::RegCommand,
+    },
+    /// Manage the attach profiles of the project, which hold the flags of the other commands
+    #[structopt(name = "config")]
+    Config {
+        #[structopt(subcommand)]
+        command: config::ConfigCommand,
+    },
+    /// Switch the power supply of the target, for probes which are able to power it
+    #[structopt(name = "power")]
+    Power {
+        #[structopt(subcommand)]
+        command: power::PowerCommand,
+    },
+    /// Trace a word of memory, or capture the SWO output with `trace swo`
+    #[structopt(name = "trace")]
+    Trace {
+        #[structopt(flatten)]
+        shared: SharedOptions,
+
+        /// The address of the memory to dump from the target (in hexadecimal without 0x prefix)
+        #[structopt(parse(try_from_str = parse_hex))]
+        loc: Option<u32>,
+
+        #[structopt(subcommand)]
+        command: Option<trace::TraceCommand>,
+    },
+    /// Measure the memory throughput and the RTT polling rate of the attached target.
+    /// The contents of the RAM used for the measurements are overwritten
+    #[structopt(name = "benchmark")]
+    Benchmark {
+        #[structopt(flatten)]
+        shared: SharedOptions,
+
+        /// The address of the RAM to use (in hexadecimal without 0x prefix).
+        /// If not given, the start of the first RAM region of the target is used.
+        #[structopt(long, parse(try_from_str = parse_hex))]
+        address: Option<u32>,
+
+        /// How long each block size is read and written, in milliseconds
+        #[structopt(long, default_value = "250")]
+        duration_ms: u64,
+
+        /// Only measure with the core halted
+        #[structopt(long)]
+        halted_only: bool,
+
+        /// The size of the block which is read repeatedly to measure the streaming rate,
+        /// in bytes. 0 skips the measurement
+        #[structopt(long, default_value = "4096")]
+        stream_block: u32,
+
+        /// The format of the results (table or json)
+        #[structopt(long, default_value = "table")]
+        format: benchmark::BenchmarkFormat,
+    },
+    /// Make a debug probe available to other hosts, which select it with --probe tcp://token@host:port
+    #[structopt(name = "server")]
+    Server {
+        /// The number associated with the debug probe to serve
+        #[structopt(long = "probe-index")]
+        n: Option<usize>,
+
+        /// The loopback address to listen on. Other hosts connect through a tunnel, e.g. SSH port forwarding
+        #[structopt(long, default_value = "127.0.0.1:1338")]
+        listen: String,
+
+        /// The token clients have to send to use the probe
+        #[structopt(long)]
+        token: String,
+    },
+}
+
+/// Shared options for all commands which use a specific probe
+#[derive(StructOpt)]
+struct SharedOptions {
+    /// The number associated with the debug probe to use
+    #[structopt(long = "probe-index")]
+    n: Option<usize>,
+
+    /// The debug probe to use, as VID:PID[:serial], as usb:<port path> (see list --verbose),
+    /// as tcp://token@host:port for a probe which is made available by the server command
+    /// on another host, or as an alias from ~/.config/probe-rs/probes.toml
+    #[structopt(long, conflicts_with = "n")]
+    probe: Option<String>,
+
+    /// The target to be selected. With `auto`, the connected chip is detected and
+    /// all matching targets are listed if it can not be determined unambiguously.
+    #[structopt(short, long)]
+    chip: Option<String>,
+
+    /// The attach profile of the project to use, from the .probe-rs.toml file in the current
+    /// directory or its parents. Without it, the `default` profile is used if there is one.
+    /// The other flags override the values of the profile
+    #[structopt(long)]
+    profile: Option<String>,
+
+    /// Protocol to use for target connection
+    #[structopt(short, long)]
+    protocol: Option<String>,
+
+    #[structopt(long)]
+    connect_under_reset: bool,
+
+    /// How long the reset line is asserted before connecting under reset, in milliseconds
+    #[structopt(long, requires = "connect-under-reset")]
+    reset_assert_time: Option<u64>,
+
+    /// How long to wait after releasing the reset line when connecting under reset, in milliseconds
+    #[structopt(long, requires = "connect-under-reset")]
+    reset_settle_time: Option<u64>,
+
+    /// Reset the target through the debug port after connecting, without using the reset line
+    #[structopt(long, conflicts_with = "connect-under-reset")]
+    software_reset: bool,
+
+    /// Attach to the target without halting or resetting it
+    #[structopt(long, conflicts_with_all = &["connect-under-reset", "software-reset"])]
+    no_halt: bool,
+
+    /// Wait until a target which sleeps most of the time wakes up, keep its debug port
+    /// powered in the low power modes and halt it before it can go back to sleep
+    #[structopt(long, conflicts_with_all = &["connect-under-reset", "software-reset", "no-halt"])]
+    attach_on_wakeup: bool,
+
+    /// How long to wait for the target to wake up, e.g. `60s` or `500ms`
+    #[structopt(long, parse(try_from_str = parse_duration), requires = "attach-on-wakeup")]
+    attach_timeout: Option<Duration>,
+
+    /// Let other probe-rs processes on this host use the probe while this command runs,
+    /// e.g. to read memory or RTT. Only this process may halt or reset the target.
+    #[structopt(long)]
+    share: bool,
+
+    /// The protocol speed in kHz. Without it, the speed is lowered automatically
+    /// if the communication with the target fails
+    #[structopt(long)]
+    speed: Option<u32>,
+
+    /// Raise the protocol speed after connecting, as long as the communication stays reliable
+    #[structopt(long, conflicts_with = "speed")]
+    increase_speed: bool,
+
+    /// How the target is reset: hardware_pin, system_reset, core_only or the name of a debug
+    /// sequence of the target. Defaults to the reset type of the target, which is also used
+    /// while connecting with --software-reset
+    #[structopt(long)]
+    reset_type: Option<ResetType>,
+
+    /// Whether the watchdogs of the target are stopped while it is halted (true or false).
+    /// Defaults to true, unless --no-halt is used
+    #[structopt(long)]
+    freeze_watchdogs: Option<bool>,
+
+    /// Peripherals which are stopped while the target is halted, e.g. `--freeze TIM2,CAN1`.
+    /// `probe-rs-cli info --chip <chip>` lists the available names
+    #[structopt(long, use_delimiter = true)]
+    freeze: Vec<String>,
+}
+
+/// Runs the CLI with the arguments of the process.
+///
+/// Probe drivers which are registered with [probe_rs::register_probe_factory] before
+/// are used like the built-in ones, see the crate documentation.
+pub fn run() -> Result<()> {
+    // Initialize the logging backend.
+    pretty_env_logger::init();
+
+    let matches = CLI::from_args();
+
+    match matches {
+        CLI::List { verbose, format } => list_connected_devices(verbose, format),
+        CLI::Info {
+            shared,
+            format,
+            measure_clock,
+        } => crate::info::show_info_of_device(&shared, format, measure_clock),
+        CLI::Reset { shared, assert } => reset_target_of_device(&shared, assert),
+        CLI::Debug {
+            shared,
+            exe,
+            debug_file_directory,
+            flash_breakpoints,
+        } => debug(&shared, exe, debug_file_directory, flash_breakpoints),
+        CLI::Dump {
+            shared,
+            loc,
+            words,
+            output,
+            range,
+        } => match (output, loc, words) {
+            (Some(output), _, _) => write_core_dump(&shared, &range, &output),
+            (None, Some(loc), Some(words)) => dump_memory(&shared, loc, words),
+            _ => Err(anyhow!(
+                "Either an address and a number of words, or --output have to be given."
+            )),
+        },
+        CLI::Download {
+            shared,
+            path,
+            format,
+            image,
+            reset,
+            allow_family_mismatch,
+            skip_unchanged,
+            verify,
+            disable_double_buffering,
+            progress_format,
+            preserve,
+            preserve_priority,
+            fill_policy,
+            flash_algo_debug,
+            flash_algo_break,
+            retries,
+            allow_otp,
+            dry_run,
+            emit_layout,
+            flash_algorithm,
+            flash_algorithm_range,
+        } => {
+            let flash_algorithms = match flash_algorithm {
+                Some(path) => {
+                    let properties = FlashAlgorithmProperties {
+                        address_range: flash_algorithm_range,
+                        ..Default::default()
+                    };
+                    vec![RawFlashAlgorithm::from_elf(
+                        &std::fs::read(path)?,
+                        &properties,
+                    )?]
+                }
+                None => vec![],
+            };
+
+            let options = DownloadOptions {
+                skip_unchanged_sectors: skip_unchanged,
+                verify,
+                disable_double_buffering,
+                preserved_ranges: preserve,
+                preserve_priority,
+                fill_policy,
+                flash_algo_debug,
+                flash_algo_break,
+                retries,
+                permissions: if allow_otp {
+                    Permissions::new().allow_otp_write()
+                } else {
+                    Permissions::new()
+                },
+                flash_algorithms,
+                ..Default::default()
+            };
+
+            if dry_run || emit_layout.is_some() {
+                layout_program(
+                    &shared,
+                    images(&path, format, &image, allow_family_mismatch),
+                    &options,
+                    emit_layout.as_deref(),
+                )?;
+            }
+
+            if dry_run {
+                Ok(())
+            } else {
+                download_program_fast(
+                    &shared,
+                    images(&path, format, &image, allow_family_mismatch),
+                    options,
+                    progress_format,
+                    reset,
+                )
+            }
+        }
+        CLI::Erase {
+            shared,
+            allow_erase_all,
+            recover,
+            progress_format,
+        } => erase::erase_flash(&shared, allow_erase_all, recover, progress_format),
+        CLI::Verify {
+            shared,
+            path,
+            format,
+            image,
+            allow_family_mismatch,
+            max_ranges,
+            progress_format,
+        } => verify::verify_images(
+            &shared,
+            images(&path, format, &image, allow_family_mismatch),
+            max_ranges,
+            progress_format,
+        ),
+        CLI::BlankCheck {
+            shared,
+            range,
+            max_ranges,
+            progress_format,
+        } => verify::blank_check_ranges(&shared, &range, max_ranges, progress_format),
+        CLI::Read {
+            shared,
+            address,
+            size,
+            format,
+            output,
+            verify,
+            progress_format,
+        } => read::read_flash_to_file(
+            &shared,
+            address,
+            size,
+            format,
+            &output,
+            verify,
+            progress_format,
+        ),
+        CLI::Options { command } => options::run(command),
+        CLI::Reg { command } => reg::run(command),
+        CLI::Config { command } => config::run(command),
+        CLI::Power { command } => power::run(command),
+        CLI::Trace {
+            command: Some(command),
+            ..
+        } => trace::run(command),
+        CLI::Trace {
+            shared,
+            loc: Some(loc),
+            ..
+        } => trace_u32_on_target(&shared, loc),
+        CLI::Trace { .. } => Err(anyhow!(
+            "Either the address of the memory to trace or the `swo` subcommand is required."
+        )),
+        CLI::Benchmark {
+            shared,
+            address,
+            duration_ms,
+            halted_only,
+            stream_block,
+            format,
+        } => benchmark::run_benchmark(
+            &shared,
+            address,
+            duration_ms,
+            halted_only,
+            stream_block,
+            format,
+        ),
+        CLI::Server { n, listen, token } => serve_probe(n, &listen, &token),
+    }
+}
+
+fn list_connected_devices(verbose: bool, format: output::OutputFormat) -> Result<()> {
+    let links = Probe::list_all();
+
+    if format == output::OutputFormat::Json {
+        for link in &links {
+            let mut record = ProbeRecord::from(link);
+            record.capabilities = Probe::open(link)
+                .map(|probe| ProbeCapabilities::from(&probe))
+                .ok();
+            output::print_record(&Record::Probe(record));
+        }
+    } else if !links.is_empty() {
+        println!("The following devices were found:");
+        for (num, link) in links.iter().enumerate() {
+            println!("[{}]: {:?}", num, link);
+
+            if verbose {
+                match &link.port_path {
+                    Some(port_path) => println!("     USB port: usb:{}", port_path),
+                    None => println!("     USB port: unknown"),
+                }
+            }
+        }
+    } else {
+        println!("No devices were found.");
+    }
+
+    Ok(())
+}
+
+fn serve_probe(n: Option<usize>, listen: &str, token: &str) -> Result<()> {
+    let probe = common::open_probe(n, false)?;
+    let listener = TcpListener::bind(listen)?;
+
+    println!("Serving {} on {}", probe.get_name(), listen);
+    ProbeServer::new(probe, token).run(listener)?;
+
+    Ok(())
+}
+
+fn dump_memory(shared_options: &SharedOptions, loc: u32, words: u32) -> Result<()> {
+    with_device(shared_options, |mut session| {
+        let mut data = vec![0_u32; words as usize];
+
+        // Start timer.
+        let instant = Instant::now();
+
+        // let loc = 220 * 1024;
+
+        let mut core = session.core(0)?;
+
+        core.read_32(loc, &mut data.as_mut_slice())?;
+        // Stop timer.
+        let elapsed = instant.elapsed();
+
+        // Print read values.
+        for word in 0..words {
+            println!(
+                "Addr 0x{:08x?}: 0x{:08x}",
+                loc + 4 * word,
+                data[word as usize]
+            );
+        }
+        // Print stats.
+        println!("Read {:?} words in {:?}", words, elapsed);
+
+        Ok(())
+    })
+}
+
+fn write_core_dump(
+    shared_options: &SharedOptions,
+    ranges: &[Range<u32>],
+    output: &Path,
+) -> Result<()> {
+    with_device(shared_options, |mut session| {
+        let dump = session.core_dump(ranges)?;
+        dump.store(output)?;
+
+        let bytes: usize = dump.memory.iter().map(|memory| memory.data.len()).sum();
+
+        println!(
+            "Wrote the registers of {} cores and {} bytes of memory to {}",
+            dump.cores.len(),
+            bytes,
+            output.display()
+        );
+
+        Ok(())
+    })
+}
+
+/// Downloads the `images` to the flash, and resets the target afterwards if `reset` is `true`.
+fn download_program_fast(
+    shared_options: &SharedOptions,
+    images: Vec<Image>,
+    options: DownloadOptions<'_>,
+    progress_format: output::OutputFormat,
+    reset: bool,
+) -> Result<()> {
+    let progress = progress::progress_reporter(progress_format);
+
+    // Ctrl+C stops the download at the next sector boundary, instead of in the middle of a sector.
+    let cancellation = Cancellation::new();
+    {
+        let cancellation = cancellation.clone();
+        ctrlc::set_handler(move || cancellation.cancel())?;
+    }
+
+    let options = DownloadOptions {
+        progress: Some(&progress),
+        cancellation: Some(cancellation),
+        ..options
+    };
+
+    let path = images
+        .iter()
+        .map(|image| image.path.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    let instant = Instant::now();
+
+    with_device(shared_options, |mut session| {
+        let result = download_files_with_options(&mut session, images, options);
+
+        if let Err(FileDownloadError::Flash(FlashError::OperationFailed { failure, .. })) = &result
+        {
+            print_flash_failure(failure);
+        }
+
+        progress_format.record(&Record::DownloadFinished {
+            path,
+            success: result.is_ok(),
+            error: result.as_ref().err().map(ToString::to_string),
+            total_time_us: instant.elapsed().as_micros() as u64,
+        });
+        result?;
+
+        if reset {
+            session.core(0)?.reset()?;
+        }
+
+        Ok(())
+    })
+}
+
+/// Prints where flashing failed, and which parts of the flash are already programmed.
+fn print_flash_failure(failure: &FlashFailure) {
+    eprintln!("{} failed.", failure.operation);
+    eprintln!("  Address:          {:#010x}", failure.address);
+    if let Some(sector) = &failure.sector {
+        eprintln!(
+            "  Sector:           {:#010x}..{:#010x}",
+            sector.start, sector.end
+        );
+    }
+    match failure.return_code {
+        Some(return_code) => eprintln!("  Return code:      {:#x}", return_code),
+        None => {
+            eprintln!("  Return code:      none, the flash algorithm did not report the failure")
+        }
+    }
+    eprintln!("  Bytes programmed: {}", failure.bytes_programmed);
+    if failure.probe_reconnected {
+        eprintln!("  The probe was reconnected during the operation, flashing was aborted.");
+    }
+    if let Some(resume_address) = failure.resume_address {
+        eprintln!(
+            "  All sectors below {:#010x} are completely programmed.",
+            resume_address
+        );
+    }
+}
+
+/// Lays out the files for the flash of the chip given in `shared_options`, without connecting to a probe.
+///
+/// The report of the layout is printed, and if `output` is given, the flash contents are written
+/// to it together with the report.
+fn layout_program(
+    shared_options: &SharedOptions,
+    images: Vec<Image>,
+    options: &DownloadOptions<'_>,
+    output: Option<&Path>,
+) -> Result<()> {
+    let chip = shared_options
+        .chip
+        .as_ref()
+        .ok_or_else(|| anyhow!("A chip has to be given with --chip to lay out the flash."))?;
+    let target = get_target_by_name(chip)?;
+
+    let image = layout_files(&target, images, options)?;
+    let report = image.report();
+    print!("{}", report);
+
+    if let Some(output) = output {
+        let is_hex = output
+            .extension()
+            .map_or(false, |extension| extension == "hex" || extension == "ihex");
+
+        let data = if is_hex {
+            image.to_hex()?.into_bytes()
+        } else {
+            let base_address = image.chunks.first().map_or(0, |(address, _)| *address);
+            image.to_bin(base_address)
+        };
+        std::fs::write(output, data)?;
+
+        let mut report_path = output.as_os_str().to_owned();
+        report_path.push(".txt");
+        std::fs::write(report_path, report)?;
+    }
+
+    Ok(())
+}
+
+fn reset_target_of_device(shared_options: &SharedOptions, _assert: Option<bool>) -> Result<()> {
+    with_device(shared_options, |mut session| {
+        session.core(0)?.reset()?;
+
+        Ok(())
+    })
+}
+
+fn trace_u32_on_target(shared_options: &SharedOptions, loc: u32) -> Result<()> {
+    use scroll::{Pwrite, LE};
+    use std::io::prelude::*;
+    use std::thread::sleep;
+
+    let mut xs = vec![];
+    let mut ys = vec![];
+
+    let start = Instant::now();
+
+    with_device(shared_options, |mut session| {
+        let mut core = session.core(0)?;
+
+        loop {
+            // Prepare read.
+            let elapsed = start.elapsed();
+            let instant = elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis());
+
+            // Read data.
+            let value: u32 = core.read_word_32(loc)?;
+
+            xs.push(instant);
+            ys.push(value);
+
+            // Send value to plot.py.
+            let mut buf = [0_u8; 8];
+            // Unwrap is safe!
+            buf.pwrite_with(instant, 0, LE).unwrap();
+            buf.pwrite_with(value, 4, LE).unwrap();
+            std::io::stdout().write_all(&buf)?;
+
+            std::io::stdout().flush()?;
+
+            // Schedule next read.
+            let elapsed = start.elapsed();
+            let instant = elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis());
+            let poll_every_ms = 50;
+            let time_to_wait = poll_every_ms - instant % poll_every_ms;
+            sleep(Duration::from_millis(time_to_wait));
+        }
+    })
+}
+
+fn debug(
+    shared_options: &SharedOptions,
+    exe: Option<PathBuf>,
+    debug_file_directories: Vec<PathBuf>,
+    flash_breakpoints: Option<u32>,
+) -> Result<()> {
+    let debug_info_options = DebugInfoOptions {
+        cache_dir: debug_info_cache_dir(),
+        debug_file_directories,
+    };
+
+    let runner = |mut session: Session| {
+        let cs = Capstone::new()
+            .arm()
+            .mode(ArchMode::Thumb)
+            .endian(Endian::Little)
+            .build()
+            .map_err(|err| anyhow!("Error creating capstone: {:?}", err))?;
+
+        let di = exe.as_ref().and_then(|path| {
+            match DebugInfo::from_file_with_options(path, &debug_info_options) {
+                Ok(di) => Some(di),
+                Err(e) => {
+                    // The symbols of the binary itself are still used.
+                    eprintln!("Warning: {}", e);
+                    DebugInfo::from_file(path).ok()
+                }
+            }
+        });
+
+        let cli = debugger::DebugCli::new();
+
+        let mut breakpoints = BreakpointManager::new(&session, 0);
+        if let Some(max_rewrites) = flash_breakpoints {
+            breakpoints.enable_flash_breakpoints(max_rewrites);
+        }
+
+        let mut cli_data = debugger::CliData {
+            session: &mut session,
+            debug_info: di,
+            capstone: cs,
+            breakpoints,
+            recording: StepRecorder::new(RECORDED_STEPS),
+        };
+
+        let result = run_debugger(&cli, &mut cli_data);
+
+        // The instructions replaced by breakpoints are restored, also if the debugger failed.
+        let restored = cli_data.breakpoints.uninstall(cli_data.session);
+        result?;
+        restored?;
+
+        Ok(())
+    };
+
+    with_device(shared_options, runner)
+}
+
+/// The directory in which the index of the debug information of ELF files is cached.
+fn debug_info_cache_dir() -> Option<PathBuf> {
+    let cache_dir = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("LOCALAPPDATA").map(PathBuf::from))
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))?;
+
+    Some(cache_dir.join("probe-rs").join("debug-info"))
+}
+
+fn run_debugger(cli: &debugger::DebugCli, cli_data: &mut debugger::CliData) -> Result<()> {
+    let mut rl = Editor::<()>::new();
+    let events = cli_data.session.subscribe();
+
+    loop {
+        // The changes of the core status are shown before each prompt.
+        if let Err(e) = cli_data.session.poll_events() {
+            log::warn!("Unable to poll the status of the target: {}", e);
+        }
+        print_session_events(&events);
+
+        let readline = rl.readline(">> ");
+        match readline {
+            Ok(line) => {
+                let history_entry: &str = line.as_ref();
+                rl.add_history_entry(history_entry);
+                let cli_state = cli.handle_line(&line, cli_data)?;
+
+                match cli_state {
+                    CliState::Continue => (),
+                    CliState::Stop => return Ok(()),
+                }
+            }
+            Err(e) => {
+                use rustyline::error::ReadlineError;
+
+                match e {
+                    // For end of file and ctrl-c, we just quit
+                    ReadlineError::Eof | ReadlineError::Interrupted => return Ok(()),
+                    actual_error => {
+                        // Show error message and quit
+                        println!("Error handling input: {:?}", actual_error);
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn print_session_events(events: &EventReceiver) {
+    for event in events.try_iter() {
+        match event {
+            SessionEvent::Halted { core, reason } => {
+                println!("Core {} halted ({:?})", core, reason)
+            }
+            SessionEvent::Running { core } => println!("Core {} is running", core),
+            SessionEvent::ResetDetected { core } => println!("Core {} was reset", core),
+            SessionEvent::ProbeError(message) => println!("Probe error: {}", message),
+            _ => (),
+        }
+    }
+}
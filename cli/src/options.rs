@@ -0,0 +1,65 @@
+use crate::{common::with_device, parse_address, SharedOptions};
+
+use structopt::StructOpt;
+
+use anyhow::Result;
+
+/// Parses an option value in the form `NAME=VALUE`.
+fn parse_option_value(src: &str) -> Result<(String, u32), String> {
+    let mut parts = src.splitn(2, '=');
+
+    match (parts.next(), parts.next()) {
+        (Some(name), Some(value)) if !name.is_empty() => {
+            let value = parse_address(value).map_err(|e| e.to_string())?;
+            Ok((name.to_string(), value))
+        }
+        _ => Err(format!("'{}' is not an option of the form NAME=VALUE", src)),
+    }
+}
+
+#[derive(StructOpt)]
+pub(crate) enum OptionsCommand {
+    /// Print the current values of all option fields
+    #[structopt(name = "read")]
+    Read {
+        #[structopt(flatten)]
+        shared: SharedOptions,
+    },
+    /// Modify option fields and load the new option bytes. This usually resets the target
+    #[structopt(name = "write")]
+    Write {
+        #[structopt(flatten)]
+        shared: SharedOptions,
+
+        /// The option fields to modify, in the form NAME=VALUE (decimal or hexadecimal with a 0x prefix)
+        #[structopt(required = true, parse(try_from_str = parse_option_value))]
+        values: Vec<(String, u32)>,
+    },
+}
+
+pub(crate) fn run(command: OptionsCommand) -> Result<()> {
+    match command {
+        OptionsCommand::Read { shared } => with_device(&shared, |mut session| {
+            for (field, value) in session.target_options().read_all()? {
+                println!(
+                    "{:<12} {:#x} (at {:#010x}, mask {:#010x})",
+                    field.name, value, field.address, field.mask
+                );
+            }
+
+            Ok(())
+        }),
+        OptionsCommand::Write { shared, values } => with_device(&shared, |mut session| {
+            let values = values
+                .iter()
+                .map(|(name, value)| (name.as_str(), *value))
+                .collect::<Vec<_>>();
+
+            session.target_options().set_all(&values)?;
+
+            println!("Option bytes written.");
+
+            Ok(())
+        }),
+    }
+}
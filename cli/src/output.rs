@@ -0,0 +1,50 @@
+use probe_rs::report::Record;
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// The format of the output of a command.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum OutputFormat {
+    /// Human readable text.
+    Text,
+    /// One JSON [Record] per line on stdout, while the text is written to stderr.
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_lowercase()[..] {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!("Output format '{}' is unknown.", s)),
+        }
+    }
+}
+
+impl OutputFormat {
+    /// Prints a human readable line, to stderr if the output is JSON.
+    pub(crate) fn text(self, line: impl Display) {
+        match self {
+            OutputFormat::Text => println!("{}", line),
+            OutputFormat::Json => eprintln!("{}", line),
+        }
+    }
+
+    /// Prints the record if the output is JSON.
+    pub(crate) fn record(self, record: &Record) {
+        if self == OutputFormat::Json {
+            print_record(record);
+        }
+    }
+}
+
+/// Prints the record as a single line of JSON.
+pub(crate) fn print_record(record: &Record) {
+    match serde_json::to_string(record) {
+        Ok(json) => println!("{}", json),
+        Err(e) => log::error!("Could not serialize {:?}: {}", record, e),
+    }
+}
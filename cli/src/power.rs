@@ -0,0 +1,100 @@
+use crate::{common::open_configured_probe, SharedOptions};
+
+use probe_rs::Probe;
+use structopt::StructOpt;
+
+use anyhow::{anyhow, Result};
+use std::thread::sleep;
+use std::time::Duration;
+
+#[derive(StructOpt)]
+pub(crate) enum PowerCommand {
+    /// Switch the power supply of the target on
+    #[structopt(name = "on")]
+    On {
+        #[structopt(flatten)]
+        shared: SharedOptions,
+    },
+    /// Switch the power supply of the target off
+    #[structopt(name = "off")]
+    Off {
+        #[structopt(flatten)]
+        shared: SharedOptions,
+    },
+    /// Switch the power supply of the target off and on again
+    #[structopt(name = "cycle")]
+    Cycle {
+        #[structopt(flatten)]
+        shared: SharedOptions,
+
+        /// How long the power stays off, in milliseconds
+        #[structopt(long, default_value = "200")]
+        settle_ms: u64,
+    },
+}
+
+pub(crate) fn run(command: PowerCommand) -> Result<()> {
+    match command {
+        PowerCommand::On { shared } => {
+            let mut probe = open_power_switch(&shared)?;
+            probe.set_target_power(true)?;
+            println!("Target power on.");
+        }
+        PowerCommand::Off { shared } => {
+            let mut probe = open_power_switch(&shared)?;
+            probe.set_target_power(false)?;
+            println!("Target power off.");
+        }
+        PowerCommand::Cycle { shared, settle_ms } => {
+            let mut probe = open_power_switch(&shared)?;
+            probe.set_target_power(false)?;
+            sleep(Duration::from_millis(settle_ms));
+            probe.set_target_power(true)?;
+            println!("Target power cycled.");
+        }
+    }
+
+    Ok(())
+}
+
+/// Opens the probe, and checks that it can switch the power supply of the target.
+fn open_power_switch(shared_options: &SharedOptions) -> Result<Probe> {
+    let probe = open_configured_probe(shared_options)?;
+
+    if !probe.power_capabilities().target_power {
+        return Err(anyhow!(
+            "The {} can not switch the power supply of the target.",
+            probe.get_name()
+        ));
+    }
+
+    Ok(probe)
+}
+
+/// Prints the target voltage and current, if the probe measures them.
+pub(crate) fn print_power_information(probe: &mut Probe) {
+    let capabilities = probe.power_capabilities();
+
+    if capabilities.target_voltage {
+        match probe.target_voltage() {
+            Ok(voltage) => println!("Target voltage: {:.2} V", voltage),
+            Err(e) => println!("Target voltage: unknown ({})", e),
+        }
+    }
+
+    if capabilities.target_current {
+        match probe.target_current() {
+            Ok(current) => println!("Target current: {:.1} mA", current * 1000.0),
+            Err(e) => println!("Target current: unknown ({})", e),
+        }
+    }
+
+    println!(
+        "Target power switch: {}",
+        if capabilities.target_power {
+            "available"
+        } else {
+            "not available"
+        }
+    );
+}
@@ -0,0 +1,66 @@
+use crate::output::{print_record, OutputFormat};
+
+use probe_rs::flashing::{FlashProgress, ProgressEvent};
+use probe_rs::report::{ProgressRecord, Record};
+
+use std::time::Duration;
+
+/// Creates a progress reporter which prints the events in the given format.
+pub(crate) fn progress_reporter(format: OutputFormat) -> FlashProgress {
+    match format {
+        OutputFormat::Text => FlashProgress::new(|event| {
+            if let Some(line) = event_to_text(&event) {
+                println!("{}", line);
+            }
+        }),
+        OutputFormat::Json => FlashProgress::new(|event| {
+            print_record(&Record::Progress(ProgressRecord::from(&event)))
+        }),
+    }
+}
+
+fn event_to_text(event: &ProgressEvent) -> Option<String> {
+    use ProgressEvent::*;
+
+    let summary = |phase: &str, total_bytes: &u32, total_time: &Duration| {
+        format!(
+            "{} {} bytes in {:.2}s",
+            phase,
+            total_bytes,
+            total_time.as_secs_f32()
+        )
+    };
+
+    match event {
+        SectorsSkipped { count, size } => Some(format!(
+            "Skipped {} sectors ({} bytes) which already contain the data.",
+            count, size
+        )),
+        FinishedFilling {
+            total_bytes,
+            total_time,
+        } if *total_bytes > 0 => Some(summary("Filled", total_bytes, total_time)),
+        FinishedErasing {
+            total_bytes,
+            total_time,
+        } => Some(summary("Erased", total_bytes, total_time)),
+        FinishedProgramming {
+            total_bytes,
+            total_time,
+        } => Some(summary("Programmed", total_bytes, total_time)),
+        FinishedVerifying {
+            total_bytes,
+            total_time,
+        } => Some(summary("Verified", total_bytes, total_time)),
+        FinishedReading {
+            total_bytes,
+            total_time,
+        } => Some(summary("Read", total_bytes, total_time)),
+        FailedFilling => Some("Filling failed.".to_owned()),
+        FailedErasing => Some("Erasing failed.".to_owned()),
+        FailedProgramming => Some("Programming failed.".to_owned()),
+        FailedVerifying => Some("Verification failed.".to_owned()),
+        FailedReading => Some("Reading failed.".to_owned()),
+        _ => None,
+    }
+}
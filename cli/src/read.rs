@@ -0,0 +1,297 @@
+use crate::{
+    common::with_device, output::OutputFormat as ProgressFormat, progress::progress_reporter,
+    SharedOptions,
+};
+
+use probe_rs::{
+    flashing::{read_flash_with_options, ReadOptions},
+    Architecture,
+};
+
+use anyhow::{anyhow, Result};
+
+use std::{fs::File, io::Write, path::Path, str::FromStr};
+
+/// The file formats which can be written when reading back the flash.
+pub(crate) enum OutputFormat {
+    Bin,
+    Hex,
+    Elf,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_lowercase()[..] {
+            "bin" => Ok(OutputFormat::Bin),
+            "hex" | "ihex" => Ok(OutputFormat::Hex),
+            "elf" => Ok(OutputFormat::Elf),
+            _ => Err(format!("Format '{}' is unknown.", s)),
+        }
+    }
+}
+
+pub(crate) fn read_flash_to_file(
+    shared_options: &SharedOptions,
+    address: Option<u32>,
+    size: Option<u32>,
+    format: OutputFormat,
+    output: &Path,
+    verify: bool,
+    progress_format: ProgressFormat,
+) -> Result<()> {
+    let ranges = match (address, size) {
+        (Some(address), Some(size)) => match address.checked_add(size) {
+            Some(end) => vec![address..end],
+            None => {
+                return Err(anyhow!(
+                    "The range of {:#x} bytes at {:#010x} exceeds the address space.",
+                    size,
+                    address
+                ))
+            }
+        },
+        (None, None) => vec![],
+        _ => return Err(anyhow!("Both an address and a size have to be given.")),
+    };
+
+    let progress = progress_reporter(progress_format);
+
+    with_device(shared_options, |mut session| {
+        let architecture = session.architecture();
+
+        let options = ReadOptions {
+            progress: Some(&progress),
+            verify,
+        };
+
+        let contents = read_flash_with_options(&mut session, &ranges, options)?;
+
+        let data = match format {
+            OutputFormat::Bin => to_bin(&contents),
+            OutputFormat::Hex => to_hex(&contents)?.into_bytes(),
+            OutputFormat::Elf => to_elf(&contents, architecture),
+        };
+
+        File::create(output)?.write_all(&data)?;
+
+        Ok(())
+    })
+}
+
+/// Concatenates all regions into a single binary, filling gaps with `0xFF`.
+fn to_bin(contents: &[(u32, Vec<u8>)]) -> Vec<u8> {
+    let start = contents.iter().map(|(address, _)| *address).min();
+    let end = contents
+        .iter()
+        .map(|(address, data)| *address + data.len() as u32)
+        .max();
+
+    let (start, end) = match (start, end) {
+        (Some(start), Some(end)) => (start, end),
+        _ => return vec![],
+    };
+
+    let mut binary = vec![0xFF; (end - start) as usize];
+
+    for (address, data) in contents {
+        let offset = (address - start) as usize;
+        binary[offset..offset + data.len()].copy_from_slice(data);
+    }
+
+    binary
+}
+
+/// Creates Intel HEX records with up to 16 bytes each.
+///
+/// Records do not cross 64 KiB boundaries, as their offset is only 16 bit wide.
+/// Each 64 KiB segment starts with an extended linear address record.
+fn to_hex(contents: &[(u32, Vec<u8>)]) -> Result<String> {
+    let mut records = vec![];
+
+    for (address, data) in contents {
+        let mut upper_address = None;
+        let mut offset = 0;
+
+        while offset < data.len() {
+            let record_address = address + offset as u32;
+            let to_boundary = 0x1_0000 - (record_address & 0xffff) as usize;
+            let length = 16.min(data.len() - offset).min(to_boundary);
+
+            if upper_address != Some(record_address >> 16) {
+                upper_address = Some(record_address >> 16);
+                records.push(ihex::Record::ExtendedLinearAddress(
+                    (record_address >> 16) as u16,
+                ));
+            }
+
+            records.push(ihex::Record::Data {
+                offset: record_address as u16,
+                value: data[offset..offset + length].to_vec(),
+            });
+            offset += length;
+        }
+    }
+
+    records.push(ihex::Record::EndOfFile);
+
+    Ok(ihex::create_object_file_representation(&records)?)
+}
+
+/// Creates a minimal ELF file which contains one loadable segment and section per region.
+fn to_elf(contents: &[(u32, Vec<u8>)], architecture: Architecture) -> Vec<u8> {
+    const HEADER_SIZE: u32 = 52;
+    const PROGRAM_HEADER_SIZE: u32 = 32;
+    const SECTION_HEADER_SIZE: u32 = 40;
+
+    let machine: u16 = match architecture {
+        Architecture::Arm => 40,
+        Architecture::Riscv => 243,
+    };
+
+    // Section names, the first byte is the empty name of the null section.
+    let mut string_table = vec![0u8];
+    let mut section_names = vec![];
+    for index in 0..contents.len() {
+        section_names.push(string_table.len() as u32);
+        string_table.extend_from_slice(format!(".flash{}\0", index).as_bytes());
+    }
+    let string_table_name = string_table.len() as u32;
+    string_table.extend_from_slice(b".shstrtab\0");
+
+    let data_offset = HEADER_SIZE + PROGRAM_HEADER_SIZE * contents.len() as u32;
+    let data_size: u32 = contents.iter().map(|(_, data)| data.len() as u32).sum();
+    let string_table_offset = data_offset + data_size;
+    let section_header_offset = string_table_offset + string_table.len() as u32;
+
+    let mut elf = vec![];
+
+    // ELF header
+    elf.extend_from_slice(&[0x7f, b'E', b'L', b'F', 1, 1, 1, 0]);
+    elf.extend_from_slice(&[0; 8]);
+    elf.extend_from_slice(&2u16.to_le_bytes()); // e_type: ET_EXEC
+    elf.extend_from_slice(&machine.to_le_bytes());
+    elf.extend_from_slice(&1u32.to_le_bytes()); // e_version
+    elf.extend_from_slice(&0u32.to_le_bytes()); // e_entry
+    elf.extend_from_slice(&HEADER_SIZE.to_le_bytes()); // e_phoff
+    elf.extend_from_slice(&section_header_offset.to_le_bytes());
+    elf.extend_from_slice(&0u32.to_le_bytes()); // e_flags
+    elf.extend_from_slice(&(HEADER_SIZE as u16).to_le_bytes());
+    elf.extend_from_slice(&(PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+    elf.extend_from_slice(&(contents.len() as u16).to_le_bytes());
+    elf.extend_from_slice(&(SECTION_HEADER_SIZE as u16).to_le_bytes());
+    elf.extend_from_slice(&(contents.len() as u16 + 2).to_le_bytes());
+    elf.extend_from_slice(&(contents.len() as u16 + 1).to_le_bytes()); // e_shstrndx
+
+    // Program headers
+    let mut offset = data_offset;
+    for (address, data) in contents {
+        elf.extend_from_slice(&1u32.to_le_bytes()); // p_type: PT_LOAD
+        elf.extend_from_slice(&offset.to_le_bytes());
+        elf.extend_from_slice(&address.to_le_bytes()); // p_vaddr
+        elf.extend_from_slice(&address.to_le_bytes()); // p_paddr
+        elf.extend_from_slice(&(data.len() as u32).to_le_bytes()); // p_filesz
+        elf.extend_from_slice(&(data.len() as u32).to_le_bytes()); // p_memsz
+        elf.extend_from_slice(&5u32.to_le_bytes()); // p_flags: R + X
+        elf.extend_from_slice(&1u32.to_le_bytes()); // p_align
+        offset += data.len() as u32;
+    }
+
+    for (_, data) in contents {
+        elf.extend_from_slice(data);
+    }
+
+    elf.extend_from_slice(&string_table);
+
+    // Section headers, starting with the null section.
+    elf.extend_from_slice(&[0; SECTION_HEADER_SIZE as usize]);
+
+    let mut offset = data_offset;
+    for ((address, data), name) in contents.iter().zip(section_names) {
+        elf.extend_from_slice(&name.to_le_bytes());
+        elf.extend_from_slice(&1u32.to_le_bytes()); // sh_type: SHT_PROGBITS
+        elf.extend_from_slice(&6u32.to_le_bytes()); // sh_flags: SHF_ALLOC + SHF_EXECINSTR
+        elf.extend_from_slice(&address.to_le_bytes());
+        elf.extend_from_slice(&offset.to_le_bytes());
+        elf.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        elf.extend_from_slice(&[0; 8]); // sh_link, sh_info
+        elf.extend_from_slice(&1u32.to_le_bytes()); // sh_addralign
+        elf.extend_from_slice(&0u32.to_le_bytes()); // sh_entsize
+        offset += data.len() as u32;
+    }
+
+    elf.extend_from_slice(&string_table_name.to_le_bytes());
+    elf.extend_from_slice(&3u32.to_le_bytes()); // sh_type: SHT_STRTAB
+    elf.extend_from_slice(&0u32.to_le_bytes());
+    elf.extend_from_slice(&0u32.to_le_bytes());
+    elf.extend_from_slice(&string_table_offset.to_le_bytes());
+    elf.extend_from_slice(&(string_table.len() as u32).to_le_bytes());
+    elf.extend_from_slice(&[0; 8]);
+    elf.extend_from_slice(&1u32.to_le_bytes());
+    elf.extend_from_slice(&0u32.to_le_bytes());
+
+    elf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{to_bin, to_elf, to_hex};
+    use probe_rs::Architecture;
+
+    #[test]
+    fn binary_gaps_are_filled() {
+        let contents = [(0x100, vec![1, 2]), (0x104, vec![3])];
+
+        assert_eq!(to_bin(&contents), vec![1, 2, 0xff, 0xff, 3]);
+        assert_eq!(to_bin(&[]), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn hex_records_do_not_cross_64k_boundaries() {
+        let data: Vec<u8> = (0..0x20).collect();
+        let hex = to_hex(&[(0x0800_fff8, data.clone())]).unwrap();
+
+        let records: Vec<_> = ihex::Reader::new(&hex).map(Result::unwrap).collect();
+        assert_eq!(
+            records,
+            vec![
+                ihex::Record::ExtendedLinearAddress(0x0800),
+                ihex::Record::Data {
+                    offset: 0xfff8,
+                    value: data[..8].to_vec(),
+                },
+                ihex::Record::ExtendedLinearAddress(0x0801),
+                ihex::Record::Data {
+                    offset: 0x0000,
+                    value: data[8..24].to_vec(),
+                },
+                ihex::Record::Data {
+                    offset: 0x0010,
+                    value: data[24..].to_vec(),
+                },
+                ihex::Record::EndOfFile,
+            ]
+        );
+    }
+
+    #[test]
+    fn elf_contains_a_segment_per_region() {
+        use object::{Object, ObjectSegment};
+
+        let contents = [(0x0800_0000, vec![1, 2, 3, 4]), (0x0810_0000, vec![5, 6])];
+        let elf = to_elf(&contents, Architecture::Arm);
+
+        let file = object::File::parse(&elf).unwrap();
+        assert_eq!(file.architecture(), object::Architecture::Arm);
+
+        let segments: Vec<_> = file
+            .segments()
+            .map(|segment| (segment.address(), segment.data().unwrap().to_vec()))
+            .collect();
+        assert_eq!(
+            segments,
+            vec![(0x0800_0000, vec![1, 2, 3, 4]), (0x0810_0000, vec![5, 6])]
+        );
+    }
+}
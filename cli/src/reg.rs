@@ -0,0 +1,164 @@
+use crate::{common::with_device, parse_address, SharedOptions};
+
+use probe_rs::svd::{Device, RegisterPath};
+use structopt::StructOpt;
+
+use anyhow::{anyhow, Result};
+
+use std::path::PathBuf;
+
+#[derive(StructOpt)]
+pub(crate) enum RegCommand {
+    /// Print a register with its decoded fields, or a single field
+    #[structopt(name = "read")]
+    Read {
+        #[structopt(flatten)]
+        shared: SharedOptions,
+
+        /// The SVD file which describes the peripherals of the target
+        #[structopt(long, parse(from_os_str))]
+        svd: PathBuf,
+
+        /// The register or field, in the form PERIPHERAL.REGISTER[.FIELD]
+        #[structopt(required_unless = "all")]
+        path: Option<String>,
+
+        /// Print all registers of this peripheral
+        #[structopt(long, conflicts_with = "path")]
+        all: Option<String>,
+    },
+    /// Write a register, or a single field of it. The other fields keep their values
+    #[structopt(name = "write")]
+    Write {
+        #[structopt(flatten)]
+        shared: SharedOptions,
+
+        /// The SVD file which describes the peripherals of the target
+        #[structopt(long, parse(from_os_str))]
+        svd: PathBuf,
+
+        /// The register or field, in the form PERIPHERAL.REGISTER[.FIELD]
+        path: String,
+
+        /// The value to write (decimal or hexadecimal with a 0x prefix),
+        /// or the name of an enumerated value of the field
+        value: String,
+    },
+}
+
+pub(crate) fn run(command: RegCommand) -> Result<()> {
+    match command {
+        RegCommand::Read {
+            shared,
+            svd,
+            path,
+            all,
+        } => {
+            let device = Device::from_file(&svd)?;
+
+            with_device(&shared, |mut session| {
+                let mut core = session.core(0)?;
+
+                if let Some(peripheral) = all {
+                    let peripheral = device.peripheral(&peripheral)?;
+                    for register in &peripheral.registers {
+                        let path = RegisterPath {
+                            peripheral,
+                            register,
+                            field: None,
+                        };
+                        if register.access.is_readable() {
+                            print_register(&path, path.read(&mut core)?);
+                        } else {
+                            println!("{} @ {:#010x} is {}", path, path.address(), register.access);
+                        }
+                    }
+                    return Ok(());
+                }
+
+                let path = device.resolve(path.as_deref().unwrap_or_default())?;
+                let value = path.read(&mut core)?;
+                match path.field {
+                    Some(field) => {
+                        println!("{} = {}", path, describe(value, field.value_name(value)))
+                    }
+                    None => print_register(&path, value),
+                }
+
+                Ok(())
+            })
+        }
+        RegCommand::Write {
+            shared,
+            svd,
+            path,
+            value,
+        } => {
+            let device = Device::from_file(&svd)?;
+            let path = device.resolve(&path)?;
+
+            let value = match path.field.and_then(|field| field.value_by_name(&value)) {
+                Some(value) => value,
+                None => parse_address(&value).map_err(|_| {
+                    anyhow!("'{}' is neither a number nor a value of {}", value, path)
+                })?,
+            };
+
+            with_device(&shared, |mut session| {
+                let mut core = session.core(0)?;
+                path.write(&mut core, value)?;
+
+                println!(
+                    "{} = {}",
+                    path,
+                    describe(value, path.field.and_then(|field| field.value_name(value)))
+                );
+
+                Ok(())
+            })
+        }
+    }
+}
+
+/// Prints the `value` of the register, and the values of its fields.
+fn print_register(path: &RegisterPath, value: u32) {
+    println!(
+        "{} @ {:#010x} = {:#0width$x}",
+        path,
+        path.address(),
+        value,
+        width = path.register.size as usize / 4 + 2
+    );
+
+    for field in path.register.decode(value) {
+        let bits = if field.field.width == 1 {
+            format!("[{}]", field.field.offset)
+        } else {
+            format!(
+                "[{}:{}]",
+                field.field.offset + field.field.width - 1,
+                field.field.offset
+            )
+        };
+        if field.field.access.is_readable() {
+            println!(
+                "  {:<12} {:<8} = {}",
+                field.field.name,
+                bits,
+                describe(field.value, field.name())
+            );
+        } else {
+            println!(
+                "  {:<12} {:<8}   {}",
+                field.field.name, bits, field.field.access
+            );
+        }
+    }
+}
+
+fn describe(value: u32, name: Option<&str>) -> String {
+    match name {
+        Some(name) => format!("{:#x} ({})", value, name),
+        None => format!("{:#x}", value),
+    }
+}
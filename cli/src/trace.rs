@@ -0,0 +1,254 @@
+use crate::{common::with_device, SharedOptions};
+
+use probe_rs::architecture::arm::swo::{
+    Decoder, ExceptionSummary, ExceptionTracer, SwvWriter, TraceEventWriter,
+};
+use probe_rs::architecture::arm::SwoConfig;
+use structopt::StructOpt;
+
+use anyhow::{anyhow, Context, Result};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[derive(StructOpt)]
+pub(crate) enum TraceCommand {
+    /// Capture the SWO output of the target and write it to a file
+    #[structopt(name = "swo")]
+    Swo {
+        #[structopt(flatten)]
+        shared: SharedOptions,
+
+        /// The file the trace is written to
+        #[structopt(long, parse(from_os_str))]
+        output: PathBuf,
+
+        /// The format of the file: 'swv' for the raw SWO bytes, or 'perfetto' for the
+        /// trace_event JSON format of Perfetto and chrome://tracing
+        #[structopt(long, default_value = "perfetto")]
+        format: TraceFormat,
+
+        /// The clock of the core, which drives the TPIU and the timestamps, e.g. '168mhz'
+        #[structopt(long, parse(try_from_str = parse_frequency))]
+        core_clock: u32,
+
+        /// The baud rate of the SWO output, e.g. '2mhz'
+        #[structopt(long, parse(try_from_str = parse_frequency), default_value = "1mhz")]
+        baud: u32,
+
+        /// Stop the capture after this many seconds, instead of running until it is interrupted
+        #[structopt(long)]
+        duration: Option<u64>,
+
+        /// Trace the exceptions, and print statistics of their handlers when the capture stops
+        #[structopt(long)]
+        exceptions: bool,
+    },
+}
+
+/// The file format of a captured trace.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum TraceFormat {
+    Swv,
+    Perfetto,
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_lowercase()[..] {
+            "swv" | "raw" => Ok(TraceFormat::Swv),
+            "perfetto" | "chrome" => Ok(TraceFormat::Perfetto),
+            _ => Err(format!("Trace format '{}' is unknown.", s)),
+        }
+    }
+}
+
+/// Parses a frequency in Hz, with an optional `hz`, `khz` or `mhz` suffix.
+fn parse_frequency(src: &str) -> Result<u32> {
+    let lower = src.trim().to_lowercase();
+    let (number, factor) = if let Some(number) = lower.strip_suffix("mhz") {
+        (number, 1_000_000.0)
+    } else if let Some(number) = lower.strip_suffix("khz") {
+        (number, 1_000.0)
+    } else {
+        (lower.strip_suffix("hz").unwrap_or(&lower), 1.0)
+    };
+
+    let value: f64 = number
+        .trim()
+        .parse()
+        .with_context(|| format!("'{}' is not a valid frequency", src))?;
+
+    Ok((value * factor).round() as u32)
+}
+
+pub(crate) fn run(command: TraceCommand) -> Result<()> {
+    match command {
+        TraceCommand::Swo {
+            shared,
+            output,
+            format,
+            core_clock,
+            baud,
+            duration,
+            exceptions,
+        } => capture_swo(
+            &shared,
+            &output,
+            format,
+            core_clock,
+            baud,
+            duration.map(Duration::from_secs),
+            exceptions,
+        ),
+    }
+}
+
+fn capture_swo(
+    shared_options: &SharedOptions,
+    output: &Path,
+    format: TraceFormat,
+    core_clock: u32,
+    baud: u32,
+    duration: Option<Duration>,
+    exceptions: bool,
+) -> Result<()> {
+    if core_clock < baud {
+        return Err(anyhow!(
+            "The core clock must be at least the SWO baud rate of {} Hz.",
+            baud
+        ));
+    }
+
+    let file = BufWriter::new(
+        File::create(output).with_context(|| format!("Failed to create {}", output.display()))?,
+    );
+
+    // The capture is stopped with Ctrl+C, after which the files are completed.
+    let interrupted = Arc::new(AtomicBool::new(false));
+    {
+        let interrupted = interrupted.clone();
+        ctrlc::set_handler(move || interrupted.store(true, Ordering::SeqCst))?;
+    }
+
+    with_device(shared_options, |mut session| {
+        let config = SwoConfig::new(core_clock).set_baud(baud);
+        session.setup_swv(&config)?;
+        if format == TraceFormat::Perfetto || exceptions {
+            session.enable_swv_exception_trace()?;
+        }
+
+        let (mut swv, mut events) = match format {
+            TraceFormat::Swv => (Some(SwvWriter::new(file)), None),
+            TraceFormat::Perfetto => (None, Some(TraceEventWriter::new(file, core_clock)?)),
+        };
+        let mut tracer = if exceptions {
+            Some(ExceptionTracer::new())
+        } else {
+            None
+        };
+        let mut decoder = Decoder::new();
+
+        println!(
+            "Writing the SWO trace to {}. Press Ctrl+C to stop.",
+            output.display()
+        );
+
+        let start = Instant::now();
+        let running = || {
+            !interrupted.load(Ordering::SeqCst)
+                && duration.map_or(true, |duration| start.elapsed() < duration)
+        };
+
+        while running() {
+            let data = session.read_swo()?;
+            if let Some(writer) = &mut swv {
+                writer.write(&data)?;
+            }
+
+            if events.is_none() && tracer.is_none() {
+                continue;
+            }
+
+            decoder.feed(data);
+            while let Some(packet) = decoder.pull() {
+                if let Some(writer) = &mut events {
+                    writer.write_packet(&packet)?;
+                }
+                if let Some(tracer) = &mut tracer {
+                    tracer.process(&packet);
+                }
+            }
+            if let Some(writer) = &mut events {
+                writer.flush()?;
+            }
+        }
+
+        if let Some(writer) = events {
+            writer.finish()?;
+        }
+        session.disable_swv()?;
+
+        if let Some(tracer) = tracer {
+            print_exception_summary(&tracer.summary(), core_clock);
+        }
+
+        Ok(())
+    })
+}
+
+/// Prints the statistics of the exception handlers as a table, with the times in microseconds.
+fn print_exception_summary(summary: &ExceptionSummary, core_clock: u32) {
+    let micros = |cycles: f64| cycles * 1_000_000.0 / f64::from(core_clock);
+    let format_micros = |cycles: Option<f64>| match cycles {
+        Some(cycles) => format!("{:.2}", micros(cycles)),
+        None => "-".to_owned(),
+    };
+
+    println!();
+    println!(
+        "{:<20} {:>10} {:>12} {:>12} {:>12}",
+        "Exception", "Count", "Min (µs)", "Mean (µs)", "Max (µs)"
+    );
+    for statistics in summary.exceptions.values() {
+        println!(
+            "{:<20} {:>10} {:>12} {:>12} {:>12}",
+            statistics.name,
+            statistics.count,
+            format_micros(statistics.min_cycles.map(|cycles| cycles as f64)),
+            format_micros(statistics.mean_cycles()),
+            format_micros(statistics.max_cycles.map(|cycles| cycles as f64)),
+        );
+    }
+
+    let share = |cycles: u64| {
+        if summary.total_cycles == 0 {
+            0.0
+        } else {
+            cycles as f64 * 100.0 / summary.total_cycles as f64
+        }
+    };
+
+    println!();
+    println!(
+        "Handler mode: {:.2} µs ({:.1}%)",
+        micros(summary.handler_cycles as f64),
+        share(summary.handler_cycles)
+    );
+    println!(
+        "Thread mode:  {:.2} µs ({:.1}%)",
+        micros(summary.thread_cycles() as f64),
+        share(summary.thread_cycles())
+    );
+
+    if let Some(note) = summary.confidence_note() {
+        println!();
+        println!("Note: {}", note);
+    }
+}
@@ -0,0 +1,85 @@
+use crate::{
+    common::with_device, output::OutputFormat, progress::progress_reporter, SharedOptions,
+};
+
+use probe_rs::flashing::{blank_check, verify_files_with_options, DownloadOptions, Image};
+
+use anyhow::{anyhow, Result};
+
+use std::ops::Range;
+
+/// Compares the flash contents with the `images`, and fails if they differ.
+pub(crate) fn verify_images(
+    shared_options: &SharedOptions,
+    images: Vec<Image>,
+    max_ranges: usize,
+    progress_format: OutputFormat,
+) -> Result<()> {
+    let progress = progress_reporter(progress_format);
+
+    with_device(shared_options, |mut session| {
+        let options = DownloadOptions {
+            progress: Some(&progress),
+            ..Default::default()
+        };
+
+        let differing = verify_files_with_options(&mut session, images, options)?;
+
+        report(
+            &differing,
+            max_ranges,
+            "The flash contents match the files.",
+            "differ from the files",
+        )
+    })
+}
+
+/// Checks that the `ranges` of the flash are erased, and fails if they are not.
+pub(crate) fn blank_check_ranges(
+    shared_options: &SharedOptions,
+    ranges: &[Range<u32>],
+    max_ranges: usize,
+    progress_format: OutputFormat,
+) -> Result<()> {
+    let progress = progress_reporter(progress_format);
+
+    with_device(shared_options, |mut session| {
+        let not_erased = blank_check(&mut session, ranges, Some(&progress))?;
+
+        report(
+            &not_erased,
+            max_ranges,
+            "The flash is erased.",
+            "are not erased",
+        )
+    })
+}
+
+/// Prints the first `max_ranges` of the `ranges` with the `problem` they have, and fails if there are any.
+fn report(ranges: &[Range<u32>], max_ranges: usize, success: &str, problem: &str) -> Result<()> {
+    if ranges.is_empty() {
+        println!("{}", success);
+        return Ok(());
+    }
+
+    for range in ranges.iter().take(max_ranges) {
+        eprintln!(
+            "  {:#010x}..{:#010x} ({} bytes)",
+            range.start,
+            range.end,
+            range.end - range.start
+        );
+    }
+    if ranges.len() > max_ranges {
+        eprintln!("  ... and {} more ranges", ranges.len() - max_ranges);
+    }
+
+    let bytes: u32 = ranges.iter().map(|range| range.end - range.start).sum();
+
+    Err(anyhow!(
+        "{} bytes in {} ranges {}.",
+        bytes,
+        ranges.len(),
+        problem
+    ))
+}
@@ -1,4 +1,8 @@
-use probe_rs::{config::MemoryRegion, Core, CoreRegisterAddress, CoreType};
+use probe_rs::{
+    config::{FlashProperties, MemoryRegion, NvmRegion, RawFlashAlgorithm},
+    Core, CoreRegisterAddress, CoreType,
+};
+use std::ops::Range;
 
 /// Extension trait for probe_rs::Core, which adds some GDB -> probe-rs internal translation functions.
 ///
@@ -59,6 +63,8 @@ impl<'probe> GdbArchitectureExt for Core<'probe> {
                     let addr: CoreRegisterAddress = self.registers().program_counter().into();
                     (addr.0, 8)
                 }
+                // CSRs, numbered starting at 65 (see riscv-csr.xml in GDB)
+                x @ 65..=4160 => ((x - 65) as u16, 8),
                 other => {
                     log::warn!("Request for unsupported register with number {}", other);
                     return None;
@@ -110,14 +116,26 @@ impl GdbTargetExt for probe_rs::Target {
                     region.range.start,
                     region.range.end - region.range.start
                 ),
-                MemoryRegion::Nvm(region) => {
-                    // TODO: Use flash with block size
-                    format!(
+                MemoryRegion::Nvm(region) => match flash_algorithm(&self.flash_algorithms, region)
+                {
+                    // GDB erases and programs flash with the vFlash packets, which requires the block size.
+                    Some(algorithm) => flash_blocks(&region.range, &algorithm.flash_properties)
+                        .into_iter()
+                        .map(|(range, block_size)| {
+                            format!(
+                                r#"<memory type="flash" start="{:#x}" length="{:#x}"><property name="blocksize">{:#x}</property></memory>\n"#,
+                                range.start,
+                                range.end - range.start,
+                                block_size
+                            )
+                        })
+                        .collect(),
+                    None => format!(
                         r#"<memory type="rom" start="{:#x}" length="{:#x}"/>\n"#,
                         region.range.start,
                         region.range.end - region.range.start
-                    )
-                }
+                    ),
+                },
             };
 
             xml_map.push_str(&region_entry);
@@ -161,3 +179,87 @@ impl GdbTargetExt for probe_rs::Target {
         target_description
     }
 }
+
+/// Returns the flash algorithm which is used to program `region`, like the flash loader selects it.
+fn flash_algorithm<'a>(
+    algorithms: &'a [RawFlashAlgorithm],
+    region: &NvmRegion,
+) -> Option<&'a RawFlashAlgorithm> {
+    let algorithms = algorithms
+        .iter()
+        .filter(|algorithm| {
+            let range = &algorithm.flash_properties.address_range;
+            range.start <= region.range.start && region.range.end <= range.end
+        })
+        .collect::<Vec<_>>();
+
+    match algorithms.len() {
+        1 => Some(algorithms[0]),
+        _ => algorithms.into_iter().find(|algorithm| algorithm.default),
+    }
+}
+
+/// Splits `range` into parts with a uniform sector size and returns them together with the sector size.
+fn flash_blocks(range: &Range<u32>, properties: &FlashProperties) -> Vec<(Range<u32>, u32)> {
+    let flash_start = properties.address_range.start;
+    let mut sectors = properties.sectors.to_vec();
+    sectors.sort_by_key(|sector| sector.address);
+
+    let mut blocks = vec![];
+
+    for (index, sector) in sectors.iter().enumerate() {
+        let start = flash_start + sector.address;
+        let end = sectors
+            .get(index + 1)
+            .map_or(properties.address_range.end, |next| {
+                flash_start + next.address
+            });
+
+        let start = start.max(range.start);
+        let end = end.min(range.end);
+
+        if start < end {
+            blocks.push((start..end, sector.size));
+        }
+    }
+
+    blocks
+}
+
+#[cfg(test)]
+mod test {
+    use super::flash_blocks;
+    use probe_rs::config::{FlashProperties, SectorDescription};
+    use std::borrow::Cow;
+
+    #[test]
+    fn flash_blocks_follow_the_sector_sizes() {
+        let properties = FlashProperties {
+            address_range: 0x0800_0000..0x0810_0000,
+            sectors: Cow::Owned(vec![
+                SectorDescription {
+                    size: 0x4000,
+                    address: 0,
+                },
+                SectorDescription {
+                    size: 0x1_0000,
+                    address: 0x1_0000,
+                },
+                SectorDescription {
+                    size: 0x2_0000,
+                    address: 0x2_0000,
+                },
+            ]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            flash_blocks(&(0x0800_0000..0x0808_0000), &properties),
+            vec![
+                (0x0800_0000..0x0801_0000, 0x4000),
+                (0x0801_0000..0x0802_0000, 0x1_0000),
+                (0x0802_0000..0x0808_0000, 0x2_0000),
+            ]
+        );
+    }
+}
@@ -1,13 +1,18 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use colored::*;
-use std::sync::Mutex;
+use std::path::PathBuf;
 use std::{
     process::{self},
     time::Duration,
 };
 use structopt::StructOpt;
 
-use probe_rs::{config::TargetSelector, DebugProbeInfo, DebugProbeSelector, Probe};
+use probe_rs::{
+    debug::detect_rtos,
+    flashing::{download_file, Format},
+    DebugProbeInfo, DebugProbeSelector, Probe, SessionConfig, SharedSession,
+};
+use probe_rs_gdb_server::ServerOptions;
 
 #[derive(Debug, StructOpt)]
 struct Opt {
@@ -25,6 +30,13 @@ struct Opt {
         help = "Use this flag to reset and halt (instead of just a halt) the attached core after attaching to the target."
     )]
     reset_halt: bool,
+    #[structopt(
+        name = "no-halt",
+        long = "no-halt",
+        conflicts_with = "reset-halt",
+        help = "Use this flag to attach to the target without halting or resetting it. GDB has to connect in non-stop mode (`set non-stop on`) then."
+    )]
+    no_halt: bool,
     #[structopt(
         name = "gdb-connection-string",
         long = "gdb-connection-string",
@@ -48,9 +60,44 @@ struct Opt {
         long = "probe",
         help = "Use this flag to select a specific probe in the list by vendor and product id.\n\
         Use '--probe VID:PID' or '--probe VID:PID:Serial' if you have more than one probe with the same VID:PID.\n\
+        Use '--probe usb:PORT_PATH', e.g. '--probe usb:1-3.2', to select a probe by the USB port it is connected to.\n\
         If there are multiple probes with the same VID:PID:Serial, you have to specify it with '--probe-index'."
     )]
     probe_selector: Option<DebugProbeSelector>,
+    #[structopt(
+        long = "elf",
+        parse(from_os_str),
+        help = "The ELF file the target is running. If it uses a supported RTOS, its threads are shown in GDB."
+    )]
+    elf: Option<PathBuf>,
+    #[structopt(
+        long = "flash",
+        requires = "elf",
+        conflicts_with = "no-halt",
+        help = "Program the ELF file before GDB connects. It is programmed again when the program is restarted with `run` in GDB's extended-remote mode."
+    )]
+    flash: bool,
+    #[structopt(
+        long = "semihosting-root",
+        parse(from_os_str),
+        help = "Execute the semihosting file operations of the target in this directory, instead of forwarding them to GDB. Absolute paths and paths outside of it are rejected."
+    )]
+    semihosting_root: Option<PathBuf>,
+    #[structopt(
+        long = "share",
+        help = "Let other probe-rs processes on this host use the probe while GDB is connected, e.g. to read RTT. Only the GDB server may halt or reset the target."
+    )]
+    share: bool,
+    #[structopt(
+        long = "speed",
+        help = "The protocol speed in kHz. Without it, the speed is lowered automatically if the communication with the target fails."
+    )]
+    speed: Option<u32>,
+    #[structopt(
+        long = "profile",
+        help = "The attach profile of the project to use, from the .probe-rs.toml file in the current directory or its parents. Without it, the `default` profile is used if there is one. The other flags override the values of the profile."
+    )]
+    profile: Option<String>,
 }
 
 fn main() {
@@ -64,7 +111,11 @@ fn main() {
     }
 }
 
-pub fn open_probe(index: Option<usize>, available_probes: &[DebugProbeInfo]) -> Result<Probe> {
+pub fn open_probe(
+    index: Option<usize>,
+    available_probes: &[DebugProbeInfo],
+    share: bool,
+) -> Result<Probe> {
     let device = match index {
         Some(index) => available_probes
             .get(index)
@@ -79,7 +130,11 @@ pub fn open_probe(index: Option<usize>, available_probes: &[DebugProbeInfo]) ->
         }
     };
 
-    let probe = Probe::open(device)?;
+    let probe = if share {
+        Probe::open_shared(device)?
+    } else {
+        Probe::open(device)?
+    };
 
     Ok(probe)
 }
@@ -88,19 +143,26 @@ fn main_try() -> Result<()> {
     // Get commandline options.
     let opt = Opt::from_iter(std::env::args());
 
+    let explicit = SessionConfig {
+        chip: opt.chip.clone(),
+        speed: opt.speed,
+        ..Default::default()
+    };
+    let config =
+        SessionConfig::resolve(explicit, &std::env::current_dir()?, opt.profile.as_deref())?;
+
+    // A probe which is selected with the flags wins over the probe of the profile.
+    let probe_selector = match (opt.probe_selector, &config.probe) {
+        (Some(selector), _) => Some(selector),
+        (None, Some(probe)) if opt.probe_index.is_none() => Some(probe.parse()?),
+        (None, _) => None,
+    };
+
     let mut available_probes = Probe::list_all();
 
     // Only retain probes with matching probe selector
-    if let Some(selector) = opt.probe_selector {
-        available_probes.retain(|probe| {
-            probe.vendor_id == selector.vendor_id
-                && probe.product_id == selector.product_id
-                && if let Some(serial) = &selector.serial_number {
-                    probe.serial_number.as_ref() == Some(serial)
-                } else {
-                    true
-                }
-        });
+    if let Some(selector) = probe_selector {
+        available_probes.retain(|probe| selector.matches(probe));
     }
 
     if opt.list {
@@ -111,19 +173,32 @@ fn main_try() -> Result<()> {
         return Ok(());
     }
 
-    let probe = open_probe(opt.probe_index, &available_probes)?;
+    let mut probe = open_probe(opt.probe_index, &available_probes, opt.share)?;
 
-    let target_selector = match opt.chip {
-        Some(identifier) => identifier.into(),
-        None => TargetSelector::Auto,
+    config.configure_probe(&mut probe)?;
+
+    let target_selector = config.target_selector();
+
+    let mut session = if opt.no_halt {
+        probe.attach_running(target_selector)?
+    } else {
+        probe.attach(target_selector)?
     };
+    config.configure_session(&mut session)?;
+
+    if opt.flash {
+        // Checked by structopt, `--flash` requires `--elf`.
+        let path = opt.elf.as_ref().unwrap();
+        download_file(&mut session, path, Format::Elf)
+            .with_context(|| format!("Failed to program {}", path.display()))?;
+        println!("Programmed {}", path.display());
+    }
 
-    let session = Mutex::new(probe.attach(target_selector)?);
+    let session = SharedSession::new(session);
 
-    if opt.reset_halt {
+    if opt.reset_halt || opt.flash {
         session
             .lock()
-            .unwrap()
             .core(0)?
             .reset_and_halt(Duration::from_millis(100))?;
     }
@@ -136,7 +211,35 @@ fn main_try() -> Result<()> {
         "Firing up GDB stub at {}",
         gdb_connection_string.as_ref().unwrap()
     );
-    if let Err(e) = probe_rs_gdb_server::run(gdb_connection_string, &session) {
+    let rtos = match &opt.elf {
+        Some(path) => {
+            let data = std::fs::read(path)
+                .with_context(|| format!("Failed to read the ELF file {}", path.display()))?;
+            detect_rtos(&data)?
+        }
+        None => None,
+    };
+
+    if let Some(rtos) = &rtos {
+        println!("Found {}, its threads are shown in GDB.", rtos.name());
+    }
+
+    let semihosting_root =
+        match &opt.semihosting_root {
+            Some(root) => Some(root.canonicalize().with_context(|| {
+                format!("The semihosting root {} does not exist", root.display())
+            })?),
+            None => None,
+        };
+
+    let options = ServerOptions {
+        rtos: rtos.as_deref(),
+        restart_image: opt.elf.as_deref().filter(|_| opt.flash),
+        semihosting_root: semihosting_root.as_deref(),
+    };
+
+    if let Err(e) = probe_rs_gdb_server::run_with_options(gdb_connection_string, &session, options)
+    {
         eprintln!("During the execution of GDB an error was encountered:");
         eprintln!("{:?}", e);
     }
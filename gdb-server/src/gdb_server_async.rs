@@ -1,4 +1,4 @@
-use std::sync::Mutex;
+use std::path::Path;
 
 use async_std::{
     net::{TcpListener, TcpStream, ToSocketAddrs},
@@ -7,7 +7,7 @@ use async_std::{
 };
 use futures::channel::mpsc;
 use gdb_protocol::packet::CheckedPacket;
-use probe_rs::Session;
+use probe_rs::{debug::RtosProvider, SharedSession};
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
 type Sender<T> = mpsc::UnboundedSender<T>;
@@ -15,24 +15,76 @@ type Receiver<T> = mpsc::UnboundedReceiver<T>;
 
 const CONNECTION_STRING: &str = "127.0.0.1:1337";
 
+/// The options of the GDB stub, see [run_with_options].
+#[derive(Default, Clone, Copy)]
+pub struct ServerOptions<'a> {
+    /// The provider for the RTOS of the firmware, whose threads are presented to GDB.
+    ///
+    /// It can be found with [probe_rs::debug::detect_rtos].
+    pub rtos: Option<&'a dyn RtosProvider>,
+    /// The ELF file which is programmed again when GDB restarts the program
+    /// in extended-remote mode, e.g. with `run` or `start`.
+    ///
+    /// Without it, the target is only reset.
+    pub restart_image: Option<&'a Path>,
+    /// The directory in which the file operations of ARM semihosting are executed by the server.
+    ///
+    /// Paths outside of it are rejected. Without it, semihosting calls are forwarded to GDB.
+    pub semihosting_root: Option<&'a Path>,
+}
+
 /// This is the main entrypoint which we will call to start the GDB stub.
 /// This function is blocking. If you would like to use it concurently to other users of the session,
-/// please use a thread.
-pub fn run(connection_string: Option<impl Into<String>>, session: &Mutex<Session>) -> Result<()> {
+/// please use a thread, and give the other threads a clone of the [SharedSession].
+pub fn run(connection_string: Option<impl Into<String>>, session: &SharedSession) -> Result<()> {
+    run_with_rtos(connection_string, session, None)
+}
+
+/// Starts the GDB stub like [run], and presents the threads found by `rtos` to GDB.
+///
+/// The provider for the firmware of the target can be found with [probe_rs::debug::detect_rtos].
+pub fn run_with_rtos(
+    connection_string: Option<impl Into<String>>,
+    session: &SharedSession,
+    rtos: Option<&dyn RtosProvider>,
+) -> Result<()> {
+    run_with_options(
+        connection_string,
+        session,
+        ServerOptions {
+            rtos,
+            ..Default::default()
+        },
+    )
+}
+
+/// Starts the GDB stub like [run], with the given [ServerOptions].
+///
+/// The stub keeps running when GDB disconnects, and accepts the next connection
+/// without attaching to the target again.
+pub fn run_with_options(
+    connection_string: Option<impl Into<String>>,
+    session: &SharedSession,
+    options: ServerOptions<'_>,
+) -> Result<()> {
     let connection_string = connection_string
         .map(|cs| cs.into())
         .unwrap_or_else(|| CONNECTION_STRING.to_owned());
     println!("GDB stub listening on {}", connection_string);
-    task::block_on(accept_loop(connection_string, session))
+    task::block_on(accept_loop(connection_string, session, options))
 }
 
 /// This function accepts any incomming connection.
-async fn accept_loop(addr: impl ToSocketAddrs, session: &Mutex<Session>) -> Result<()> {
+async fn accept_loop(
+    addr: impl ToSocketAddrs,
+    session: &SharedSession,
+    options: ServerOptions<'_>,
+) -> Result<()> {
     let listener = TcpListener::bind(addr).await?;
 
     let mut incoming = listener.incoming();
     while let Some(stream) = incoming.next().await {
-        if let Err(e) = handle_connection(stream?, session).await {
+        if let Err(e) = handle_connection(stream?, session, options).await {
             eprintln!(
                 "An error with the current connection has been encountered. It has been closed."
             );
@@ -43,7 +95,11 @@ async fn accept_loop(addr: impl ToSocketAddrs, session: &Mutex<Session>) -> Resu
 }
 
 /// Handle a single connection of a client
-async fn handle_connection(stream: TcpStream, session: &Mutex<Session>) -> Result<()> {
+async fn handle_connection(
+    stream: TcpStream,
+    session: &SharedSession,
+    options: ServerOptions<'_>,
+) -> Result<()> {
     let (packet_stream_sender, packet_stream_receiver) = mpsc::unbounded();
     let (tbd_sender, tbd_receiver) = mpsc::unbounded();
 
@@ -55,7 +111,7 @@ async fn handle_connection(stream: TcpStream, session: &Mutex<Session>) -> Resul
         packet_stream_receiver,
     ));
 
-    super::worker::worker(tbd_receiver, packet_stream_sender, session).await?;
+    super::worker::worker(tbd_receiver, packet_stream_sender, session, options).await?;
 
     inbound_broker_handle.await?;
 
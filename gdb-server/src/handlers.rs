@@ -1,10 +1,22 @@
 use crate::architecture::{GdbArchitectureExt, GdbTargetExt};
-use probe_rs::{Core, CoreStatus, MemoryInterface, Session};
+use crate::memory;
+use crate::non_stop::NonStop;
+use crate::parser::v_packet::Action;
+use crate::parser::{ThreadId, ThreadOperation};
+use crate::semihosting::Semihosting;
+use crate::threads::Threads;
+use probe_rs::debug::{RangeStep, RangeStepStatus};
+use probe_rs::flashing::{
+    download_data, download_file_with_options, DownloadOptions, FlashProgress, Format,
+    ProgressEvent,
+};
+use probe_rs::{Core, CoreRegisterAddress, CoreStatus, MemoryInterface, Session};
+use std::path::Path;
 use std::time::Duration;
 
 pub(crate) fn q_supported() -> Option<String> {
     Some(
-        "PacketSize=2048;swbreak-;hwbreak+;vContSupported+;qXfer:features:read+;qXfer:memory-map:read+"
+        "PacketSize=2048;swbreak-;hwbreak+;vContSupported+;qXfer:features:read+;qXfer:memory-map:read+;qXfer:threads:read+;QNonStop+"
             .into(),
     )
 }
@@ -17,11 +29,90 @@ pub(crate) fn q_attached() -> Option<String> {
     Some("1".into())
 }
 
-pub(crate) fn halt_reason() -> Option<String> {
-    Some("S05".into())
+/// Halts all cores of the session, as GDB expects in all-stop mode once one of them stopped.
+fn halt_all(session: &mut Session) {
+    for n in 0..session.list_cores().len() {
+        if let Err(e) = session
+            .core(n)
+            .and_then(|mut core| core.halt(Duration::from_millis(100)))
+        {
+            log::warn!("Unable to halt core {}: {}", n, e);
+        }
+    }
+}
+
+pub(crate) fn halt_reason(threads: &Threads) -> Option<String> {
+    Some(format!("T05thread:{:x};", threads.current_id()))
+}
+
+/// Replies to `?` in all-stop mode, if the target was attached without halting it.
+///
+/// GDB can only connect to a running target in non-stop mode, the target is not halted for it.
+pub(crate) fn hot_halt_reason(session: &mut Session, threads: &Threads) -> Option<String> {
+    match session
+        .core(threads.selected_core())
+        .and_then(|mut core| core.core_halted())
+    {
+        Ok(true) => halt_reason(threads),
+        Ok(false) => {
+            log::error!(
+                "The target is running and was attached without halting it. \
+                Use `set non-stop on` in GDB before connecting, to debug it while it runs."
+            );
+            Some("E01".into())
+        }
+        Err(e) => {
+            log::warn!("Unable to read the status of the core: {}", e);
+            Some("E01".into())
+        }
+    }
+}
+
+pub(crate) fn current_thread(threads: &Threads) -> Option<String> {
+    Some(format!("QC{:x}", threads.current_id()))
+}
+
+pub(crate) fn select_thread(
+    session: &Session,
+    threads: &mut Threads,
+    operation: ThreadOperation,
+    thread: ThreadId,
+) -> Option<String> {
+    if threads.select(session, operation, thread) {
+        Some("OK".into())
+    } else {
+        Some("E01".into())
+    }
+}
+
+pub(crate) fn thread_alive(
+    session: &Session,
+    threads: &Threads,
+    thread: ThreadId,
+) -> Option<String> {
+    if threads.is_alive(session, thread) {
+        Some("OK".into())
+    } else {
+        Some("E01".into())
+    }
+}
+
+pub(crate) fn read_threads(
+    session: &Session,
+    threads: &Threads,
+    offset: u32,
+    length: u32,
+) -> Option<String> {
+    let threads = threads.xml(session);
+
+    Some(String::from_utf8(gdb_sanitize_file(threads.as_bytes(), offset, length)).unwrap())
 }
 
-pub(crate) fn read_general_registers(mut core: Core) -> Option<String> {
+/// Reads the general registers of `core`, or takes them from `saved` if the registers of a suspended thread are read.
+pub(crate) fn read_general_registers(
+    mut core: Core,
+    saved: Option<&[(CoreRegisterAddress, u32)]>,
+) -> Option<String> {
     // First we check the core status.
     // If the core is not properly halted it does not make much sense to try and read registers.
     // On some cores this even leads to a fault!
@@ -46,24 +137,39 @@ pub(crate) fn read_general_registers(mut core: Core) -> Option<String> {
         }
     }
 
-    let mut general_registers_value = String::new();
-
-    for reg in 0..core.num_general_registers() {
-        let (probe_rs_number, bytesize) = core.translate_gdb_register_number(reg as u32)?;
+    let registers = (0..core.num_general_registers())
+        .map(|reg| core.translate_gdb_register_number(reg as u32))
+        .collect::<Option<Vec<_>>>()?;
+
+    let values: Vec<_> = match saved {
+        Some(saved) => registers
+            .iter()
+            .map(|&(probe_rs_number, _)| saved_register(saved, probe_rs_number))
+            .collect(),
+        None => {
+            let addresses: Vec<_> = registers.iter().map(|&(address, _)| address).collect();
+            core.read_core_regs(&addresses)
+                .into_iter()
+                .map(|value| Some(value.unwrap()))
+                .collect()
+        }
+    };
 
-        let mut value = core.read_core_reg(probe_rs_number).unwrap();
+    let mut general_registers_value = String::new();
 
-        for _ in 0..bytesize {
-            let byte = value as u8;
-            general_registers_value.push_str(&format!("{:02x}", byte));
-            value >>= 8;
-        }
+    for (value, &(_, bytesize)) in values.into_iter().zip(&registers) {
+        general_registers_value.push_str(&encode_register(value, bytesize));
     }
 
     Some(general_registers_value)
 }
 
-pub(crate) fn read_register(register: u32, mut core: Core) -> Option<String> {
+/// Reads a register of `core`, or takes it from `saved` if the registers of a suspended thread are read.
+pub(crate) fn read_register(
+    register: u32,
+    mut core: Core,
+    saved: Option<&[(CoreRegisterAddress, u32)]>,
+) -> Option<String> {
     // First we check the core status.
     // If the core is not properly halted it does not make much sense to try and read registers.
     // On some cores this even leads to a fault!
@@ -93,7 +199,42 @@ pub(crate) fn read_register(register: u32, mut core: Core) -> Option<String> {
 
     let (probe_rs_number, bytesize) = core.translate_gdb_register_number(register)?;
 
-    let mut value = core.read_core_reg(probe_rs_number).unwrap();
+    if let Some(saved) = saved {
+        return Some(encode_register(
+            saved_register(saved, probe_rs_number),
+            bytesize,
+        ));
+    }
+
+    let value = match core.read_core_reg(probe_rs_number) {
+        Ok(value) => value,
+        Err(e) => {
+            // This happens for example when GDB tries to read a CSR which is not implemented.
+            log::warn!("Unable to read register {}: {}", register, e);
+            return Some("E14".to_string());
+        }
+    };
+
+    Some(encode_register(Some(value), bytesize))
+}
+
+/// Returns the value of `register` in the registers of a suspended thread.
+fn saved_register(
+    saved: &[(CoreRegisterAddress, u32)],
+    register: CoreRegisterAddress,
+) -> Option<u32> {
+    saved
+        .iter()
+        .find(|(address, _)| *address == register)
+        .map(|(_, value)| *value)
+}
+
+/// Encodes a register value in target byte order, or marks it as unavailable if it is `None`.
+fn encode_register(value: Option<u32>, bytesize: u32) -> String {
+    let mut value = match value {
+        Some(value) => value,
+        None => return "xx".repeat(bytesize as usize),
+    };
 
     let mut register_value = String::new();
 
@@ -103,10 +244,50 @@ pub(crate) fn read_register(register: u32, mut core: Core) -> Option<String> {
         value >>= 8;
     }
 
-    Some(register_value)
+    register_value
+}
+
+pub(crate) fn write_register(register: u32, value: &[u8], mut core: Core) -> Option<String> {
+    let (probe_rs_number, bytesize) = core.translate_gdb_register_number(register)?;
+
+    // The value is transferred in target byte order, i.e. little endian.
+    let value = value
+        .iter()
+        .take(bytesize.min(4) as usize)
+        .rev()
+        .fold(0u32, |acc, byte| (acc << 8) | *byte as u32);
+
+    match core.write_core_reg(probe_rs_number, value) {
+        Ok(_) => Some("OK".into()),
+        Err(e) => {
+            log::warn!("Unable to write register {}: {}", register, e);
+            // Same as for reading, report an EFAULT to GDB.
+            Some("E14".to_string())
+        }
+    }
 }
 
-pub(crate) fn read_memory(address: u32, length: u32, mut core: Core) -> Option<String> {
+/// Reads memory for GDB, unless it is in a region with side effects on reads.
+///
+/// GDB reads the memory around the variables and the stack on its own, which must not
+/// change the state of peripherals. Such regions are only read with `read_side_effects`.
+pub(crate) fn read_memory(
+    address: u32,
+    length: u32,
+    mut core: Core,
+    read_side_effects: bool,
+) -> Option<String> {
+    if !read_side_effects {
+        if let Some(region) = core.read_side_effects(address, length as usize) {
+            log::warn!(
+                "Not reading the memory region {}, which has side effects on reads. \
+                Use 'monitor read-side-effects on' to read it anyway.",
+                region
+            );
+            return Some("E01".into());
+        }
+    }
+
     let mut readback_data = vec![0u8; length as usize];
     match core.read_8(address, &mut readback_data) {
         Ok(_) => Some(
@@ -119,7 +300,56 @@ pub(crate) fn read_memory(address: u32, length: u32, mut core: Core) -> Option<S
         // We have no clue if this is the right error code since GDB doesn't feel like docs.
         // We just assume Linux ERRNOs and pick a fitting one: https://gist.github.com/greggyNapalm/2413028#file-gistfile1-txt-L138
         // This seems to work in practice and seems to be the way to do stuff around GDB.
-        Err(_e) => Some("E79".to_string()),
+        Err(e) => {
+            log::warn!("Unable to read memory: {}", e);
+            Some("E79".to_string())
+        }
+    }
+}
+
+pub(crate) fn memory_crc(address: u32, length: u32, mut core: Core) -> Option<String> {
+    if address.checked_add(length).is_none() {
+        return Some("E01".into());
+    }
+
+    match memory::memory_crc(address, length, |address, data| core.read_8(address, data)) {
+        Ok(crc) => Some(format!("C{:x}", crc)),
+        Err(e) => {
+            log::warn!(
+                "Unable to read {:#010x}..{:#010x}: {}",
+                address,
+                address + length,
+                e
+            );
+            Some("E01".into())
+        }
+    }
+}
+
+pub(crate) fn search_memory(
+    address: u32,
+    length: u32,
+    pattern: &[u8],
+    mut core: Core,
+) -> Option<String> {
+    if address.checked_add(length).is_none() {
+        return Some("E01".into());
+    }
+
+    match memory::search_memory(address, length, pattern, |address, data| {
+        core.read_8(address, data)
+    }) {
+        Ok(Some(found)) => Some(format!("1,{:x}", found)),
+        Ok(None) => Some("0".into()),
+        Err(e) => {
+            log::warn!(
+                "Unable to read {:#010x}..{:#010x}: {}",
+                address,
+                address + length,
+                e
+            );
+            Some("E01".into())
+        }
     }
 }
 
@@ -128,7 +358,7 @@ pub(crate) fn vcont_supported() -> Option<String> {
     // the variants with and without signal support,
     // i.e. both c and C, otherwise GDB will not use
     // the command.
-    Some("vCont;c;C;t;s;S".into())
+    Some("vCont;c;C;t;s;S;r".into())
 }
 
 pub(crate) fn host_info() -> Option<String> {
@@ -138,31 +368,212 @@ pub(crate) fn host_info() -> Option<String> {
     Some("cputype:12;cpusubtype:14;triple:armv6m--none-eabi;endian:litte;ptrsize:4".to_string())
 }
 
-pub(crate) fn run(mut core: Core, awaits_halt: &mut bool) -> Option<String> {
-    core.run().unwrap();
-    *awaits_halt = true;
-    None
+/// Applies the `actions` of a `vCont` packet to the cores.
+///
+/// The first action which applies to a core is used for it, cores without an action are not touched.
+/// A range step which does not finish right away is kept in `range_step`, with the index of its core.
+pub(crate) fn resume(
+    session: &mut Session,
+    actions: &[(Action, Option<ThreadId>)],
+    threads: &mut Threads,
+    awaits_halt: &mut bool,
+    range_step: &mut Option<(usize, RangeStep)>,
+) -> Option<String> {
+    let core_actions = core_actions(session, actions, threads);
+
+    let mut stepped_core = None;
+    let mut at_breakpoint = false;
+
+    for (n, action) in core_actions.iter().enumerate() {
+        let result = session.core(n).and_then(|mut core| match action {
+            Some(Action::Continue) => core.run(),
+            Some(Action::Step) => {
+                stepped_core = Some(n);
+                core.step().map(|_| ())
+            }
+            Some(Action::RangeStep { start, end }) => {
+                let (step, status) = RangeStep::start(&mut core, *start..*end)?;
+                match status {
+                    RangeStepStatus::Running => *range_step = Some((n, step)),
+                    status => {
+                        stepped_core = Some(n);
+                        at_breakpoint = status == RangeStepStatus::Breakpoint;
+                    }
+                }
+                Ok(())
+            }
+            Some(Action::Stop) => core.halt(Duration::from_millis(100)).map(|_| ()),
+            Some(other) => {
+                log::warn!("vCont with action {:?} not supported", other);
+                Ok(())
+            }
+            None => Ok(()),
+        });
+
+        if let Err(e) = result {
+            log::warn!("Unable to resume core {}: {}", n, e);
+            return Some("E01".into());
+        }
+    }
+
+    if let Some(n) = stepped_core {
+        // The step is done, so all other cores are stopped as well.
+        cancel_range_step(session, range_step);
+        halt_all(session);
+        threads.halted(session, n);
+        *awaits_halt = false;
+        if at_breakpoint {
+            Some(format!("T05hwbreak:;thread:{:x};", threads.current_id()))
+        } else {
+            Some(format!("T05thread:{:x};", threads.current_id()))
+        }
+    } else if core_actions.contains(&Some(Action::Continue)) || range_step.is_some() {
+        *awaits_halt = true;
+        None
+    } else {
+        *awaits_halt = false;
+        Some("OK".into())
+    }
+}
+
+/// Removes the temporary breakpoint of a range step which did not finish.
+pub(crate) fn cancel_range_step(
+    session: &mut Session,
+    range_step: &mut Option<(usize, RangeStep)>,
+) {
+    if let Some((n, mut step)) = range_step.take() {
+        if let Err(e) = session.core(n).and_then(|mut core| step.cancel(&mut core)) {
+            log::warn!("Unable to cancel the range step of core {}: {}", n, e);
+        }
+    }
 }
 
-pub(crate) fn stop(mut core: Core, awaits_halt: &mut bool) -> Option<String> {
-    core.halt(Duration::from_millis(100)).unwrap();
-    *awaits_halt = false;
+/// Applies the `actions` of a `vCont` packet to the cores in non-stop mode.
+///
+/// The packet is acknowledged right away, the stops of the cores are reported with notifications.
+pub(crate) fn resume_non_stop(
+    session: &mut Session,
+    actions: &[(Action, Option<ThreadId>)],
+    threads: &Threads,
+    non_stop: &mut NonStop,
+) -> Option<String> {
+    let core_actions = core_actions(session, actions, threads);
+
+    for (n, action) in core_actions.iter().enumerate() {
+        let result = session.core(n).and_then(|mut core| {
+            match action {
+                Some(Action::Continue) => {
+                    core.run()?;
+                    non_stop.resumed(n);
+                }
+                // A single step is a valid range step, GDB steps again while it is in the range.
+                Some(Action::Step) | Some(Action::RangeStep { .. }) => {
+                    core.step()?;
+                    non_stop.report_stop(format!("T05thread:{:x};", threads.thread_of(n)));
+                }
+                // Threads which are stopped on request are reported with signal 0.
+                Some(Action::Stop) if non_stop.running_cores().contains(&n) => {
+                    core.halt(Duration::from_millis(100))?;
+                    non_stop.stopped(n);
+                    non_stop.report_stop(format!("T00thread:{:x};", threads.thread_of(n)));
+                }
+                Some(Action::Stop) | None => (),
+                Some(other) => log::warn!("vCont with action {:?} not supported", other),
+            }
+            Ok(())
+        });
+
+        if let Err(e) = result {
+            log::warn!("Unable to resume core {}: {}", n, e);
+            return Some("E01".into());
+        }
+    }
+
     Some("OK".into())
 }
 
-pub(crate) fn step(mut core: Core, awaits_halt: &mut bool) -> Option<String> {
-    core.step().unwrap();
-    *awaits_halt = false;
-    Some("S05".into())
+/// Returns the action of a `vCont` packet for each core.
+///
+/// The first action which applies to a core is used for it.
+fn core_actions(
+    session: &Session,
+    actions: &[(Action, Option<ThreadId>)],
+    threads: &Threads,
+) -> Vec<Option<Action>> {
+    (0..session.list_cores().len())
+        .map(|n| {
+            actions
+                .iter()
+                .find(|(_, thread)| match thread {
+                    Some(ThreadId::Id(id)) => threads.core_of(session, *id) == Some(n),
+                    _ => true,
+                })
+                .map(|(action, _)| action.clone())
+        })
+        .collect()
+}
+
+pub(crate) fn set_non_stop(non_stop: &mut NonStop, enabled: bool) -> Option<String> {
+    log::info!("Non-stop mode enabled: {}", enabled);
+    non_stop.set_enabled(enabled);
+    Some("OK".into())
+}
+
+pub(crate) fn next_stop(non_stop: &mut NonStop) -> Option<String> {
+    Some(non_stop.next_stop())
+}
+
+/// Reports all stopped threads in non-stop mode, as reply to `?`.
+pub(crate) fn stop_replies(
+    session: &mut Session,
+    threads: &Threads,
+    non_stop: &mut NonStop,
+) -> Option<String> {
+    let mut replies = vec![];
+
+    for n in 0..session.list_cores().len() {
+        match session.core(n).and_then(|mut core| core.core_halted()) {
+            Ok(true) => replies.push(format!("T05thread:{:x};", threads.thread_of(n))),
+            Ok(false) => non_stop.resumed(n),
+            Err(e) => log::warn!("Unable to read the status of core {}: {}", n, e),
+        }
+    }
+
+    Some(non_stop.report_all(replies))
 }
 
-pub(crate) fn insert_hardware_break(address: u32, _kind: u32, mut core: Core) -> Option<String> {
-    core.set_hw_breakpoint(address).unwrap();
+pub(crate) fn insert_hardware_break(
+    address: u32,
+    _kind: u32,
+    session: &mut Session,
+) -> Option<String> {
+    // GDB breakpoints apply to all threads, so they are set on all cores.
+    for n in 0..session.list_cores().len() {
+        if let Err(e) = session
+            .core(n)
+            .and_then(|mut core| core.set_hw_breakpoint(address))
+        {
+            log::warn!("Unable to set breakpoint on core {}: {}", n, e);
+            return Some("E01".into());
+        }
+    }
     Some("OK".into())
 }
 
-pub(crate) fn remove_hardware_break(address: u32, _kind: u32, mut core: Core) -> Option<String> {
-    core.clear_hw_breakpoint(address).unwrap();
+pub(crate) fn remove_hardware_break(
+    address: u32,
+    _kind: u32,
+    session: &mut Session,
+) -> Option<String> {
+    for n in 0..session.list_cores().len() {
+        if let Err(e) = session
+            .core(n)
+            .and_then(|mut core| core.clear_hw_breakpoint(address))
+        {
+            log::warn!("Unable to clear breakpoint on core {}: {}", n, e);
+            return Some("E01".into());
+        }
+    }
     Some("OK".into())
 }
 
@@ -172,16 +583,214 @@ pub(crate) fn write_memory(address: u32, data: &[u8], mut core: Core) -> Option<
     Some("OK".into())
 }
 
-pub(crate) fn get_memory_map(session: &Session) -> Option<String> {
+pub(crate) fn get_memory_map(session: &Session, offset: u32, length: u32) -> Option<String> {
     let memory_map = session.target().gdb_memory_map();
 
-    Some(String::from_utf8(gdb_sanitize_file(memory_map.as_bytes(), 0, 1000)).unwrap())
+    Some(String::from_utf8(gdb_sanitize_file(memory_map.as_bytes(), offset, length)).unwrap())
+}
+
+pub(crate) fn flash_erase(address: u32, length: u32) -> Option<String> {
+    // The flash loader erases all sectors which are written, so nothing has to be done here.
+    log::debug!(
+        "GDB requested to erase {:#010x}..{:#010x}",
+        address,
+        address + length
+    );
+    Some("OK".into())
+}
+
+pub(crate) fn flash_write(
+    address: u32,
+    data: Vec<u8>,
+    flash_data: &mut Vec<(u32, Vec<u8>)>,
+) -> Option<String> {
+    // GDB sends the data in packet sized pieces, contiguous pieces are merged into one chunk.
+    if let Some((last_address, last_data)) = flash_data.last_mut() {
+        if *last_address + last_data.len() as u32 == address {
+            last_data.extend_from_slice(&data);
+            return Some("OK".into());
+        }
+    }
+
+    flash_data.push((address, data));
+    Some("OK".into())
+}
+
+pub(crate) fn flash_done(
+    session: &mut Session,
+    flash_data: &mut Vec<(u32, Vec<u8>)>,
+) -> Option<String> {
+    let chunks = std::mem::take(flash_data);
+
+    let progress = FlashProgress::new(log_progress);
+    let options = DownloadOptions {
+        progress: Some(&progress),
+        ..Default::default()
+    };
+
+    match download_data(session, &chunks, options) {
+        Ok(()) => Some("OK".into()),
+        Err(error) => {
+            log::error!("Flashing failed: {}", error);
+            Some("E01".into())
+        }
+    }
+}
+
+/// GDB does not accept console output while it waits for the reply to a flash packet,
+/// so the progress is logged instead.
+fn log_progress(event: ProgressEvent) {
+    match event {
+        ProgressEvent::FinishedErasing {
+            total_bytes,
+            total_time,
+        } => log::info!("Erased {} bytes in {:?}", total_bytes, total_time),
+        ProgressEvent::FinishedProgramming {
+            total_bytes,
+            total_time,
+        } => log::info!("Programmed {} bytes in {:?}", total_bytes, total_time),
+        _ => {}
+    }
+}
+
+pub(crate) fn enable_extended_mode(extended: &mut bool) -> Option<String> {
+    *extended = true;
+    Some("OK".into())
+}
+
+/// Restarts the program for `R` and `vRun`, and leaves all cores halted at the reset vector.
+///
+/// The image is programmed again before the reset, if the server was started with one.
+pub(crate) fn restart(
+    session: &mut Session,
+    threads: &mut Threads,
+    image: Option<&Path>,
+    awaits_halt: &mut bool,
+) -> bool {
+    if let Some(path) = image {
+        let progress = FlashProgress::new(log_progress);
+        let options = DownloadOptions {
+            progress: Some(&progress),
+            ..Default::default()
+        };
+
+        if let Err(e) = download_file_with_options(session, path, Format::Elf, options) {
+            log::error!("Unable to program {}: {}", path.display(), e);
+            return false;
+        }
+    }
+
+    if let Err(e) = session
+        .core(0)
+        .and_then(|mut core| core.reset_and_halt(Duration::from_millis(400)))
+    {
+        log::error!("Unable to reset the target: {}", e);
+        return false;
+    }
+
+    halt_all(session);
+    threads.halted(session, 0);
+    *awaits_halt = false;
+    true
+}
+
+/// Replies to `vRun` with the stop reply of the restarted program.
+pub(crate) fn run_program(
+    session: &mut Session,
+    threads: &mut Threads,
+    image: Option<&Path>,
+    awaits_halt: &mut bool,
+) -> Option<String> {
+    if restart(session, threads, image, awaits_halt) {
+        halt_reason(threads)
+    } else {
+        Some("E01".into())
+    }
+}
+
+/// Kills the program for `k` and `vKill` in extended mode.
+///
+/// A target cannot be killed, it is reset and halted instead, until GDB starts the program again.
+pub(crate) fn kill(
+    session: &mut Session,
+    threads: &mut Threads,
+    awaits_halt: &mut bool,
+) -> Option<String> {
+    if restart(session, threads, None, awaits_halt) {
+        Some("OK".into())
+    } else {
+        Some("E01".into())
+    }
+}
+
+/// Resets the target for `monitor reset run`, and lets it run.
+///
+/// GDB still considers the target halted, so it has to be interrupted before it is debugged again.
+pub(crate) fn reset_run(mut core: Core) -> Option<String> {
+    match core.reset() {
+        Ok(()) => Some("OK".into()),
+        Err(e) => {
+            log::warn!("Unable to reset the target: {}", e);
+            Some("E01".into())
+        }
+    }
 }
 
-pub(crate) fn user_halt(mut core: Core, awaits_halt: &mut bool) -> Option<String> {
-    let _ = core.halt(Duration::from_millis(100));
+pub(crate) fn user_halt(
+    session: &mut Session,
+    threads: &mut Threads,
+    awaits_halt: &mut bool,
+) -> Option<String> {
+    halt_all(session);
+    threads.halted(session, threads.selected_core());
     *awaits_halt = false;
-    Some("T02".into())
+    Some(format!("T02thread:{:x};", threads.current_id()))
+}
+
+/// Completes the pending semihosting call with the reply of GDB to the `F` packet, and resumes the core.
+pub(crate) fn file_io_reply(
+    session: &mut Session,
+    semihosting: &mut Semihosting,
+    threads: &mut Threads,
+    result: i64,
+    errno: Option<u32>,
+    interrupted: bool,
+    awaits_halt: &mut bool,
+) -> Option<String> {
+    let n = match semihosting.pending_core() {
+        Some(n) => n,
+        None => {
+            log::warn!("Received a File-I/O reply without a pending call");
+            return reply_empty();
+        }
+    };
+
+    let resumed = session.core(n).and_then(|mut core| {
+        semihosting.reply(&mut core, result, errno)?;
+        if !interrupted {
+            core.run()?;
+        }
+        Ok(())
+    });
+
+    match resumed {
+        Ok(()) if !interrupted => {
+            *awaits_halt = true;
+            None
+        }
+        // GDB expects a stop reply if the user interrupted the call.
+        Ok(()) => {
+            halt_all(session);
+            threads.halted(session, n);
+            Some(format!("T02thread:{:x};", threads.current_id()))
+        }
+        Err(e) => {
+            log::warn!("Unable to complete the semihosting call: {}", e);
+            halt_all(session);
+            threads.halted(session, n);
+            Some(format!("T05thread:{:x};", threads.current_id()))
+        }
+    }
 }
 
 pub(crate) fn detach(break_due: &mut bool) -> Option<String> {
@@ -0,0 +1,565 @@
+//! Execution of the file operations of ARM semihosting on the host, without GDB.
+//!
+//! The files are restricted to a root directory: absolute paths and paths which leave
+//! the root with `..` or through symbolic links are rejected. The special file `:tt` is
+//! the console of the server, as the semihosting specification requires.
+
+use crate::semihosting::{
+    MAX_STRING_LENGTH, SYS_CLOSE, SYS_ERRNO, SYS_FLEN, SYS_ISTTY, SYS_OPEN, SYS_READ, SYS_REMOVE,
+    SYS_RENAME, SYS_SEEK, SYS_WRITE, SYS_WRITE0, SYS_WRITEC,
+};
+use probe_rs::{Core, Error, MemoryInterface};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+
+/// The handles 0, 1 and 2 are stdin, stdout and stderr, opened with `:tt`.
+const FIRST_FILE_HANDLE: u32 = 3;
+
+/// The value of `R0` for failed calls.
+const FAILED: u32 = -1i32 as u32;
+
+/// Paths are limited to this length, like on most hosts.
+const MAX_PATH_LENGTH: u32 = 4096;
+
+/// `SYS_WRITE` copies the data from the target in chunks of this size.
+const WRITE_CHUNK_SIZE: u32 = 4096;
+
+/// `SYS_READ` of the console returns at most this many bytes at once.
+const CONSOLE_READ_SIZE: u32 = 4096;
+
+const EBADF: u32 = 9;
+const EACCES: u32 = 13;
+const EINVAL: u32 = 22;
+
+/// The memory of the core which made a semihosting call.
+pub(crate) trait TargetMemory {
+    fn read(&mut self, address: u32, data: &mut [u8]) -> Result<(), Error>;
+
+    fn write(&mut self, address: u32, data: &[u8]) -> Result<(), Error>;
+}
+
+impl TargetMemory for Core<'_> {
+    fn read(&mut self, address: u32, data: &mut [u8]) -> Result<(), Error> {
+        self.read_8(address, data)
+    }
+
+    fn write(&mut self, address: u32, data: &[u8]) -> Result<(), Error> {
+        self.write_8(address, data)
+    }
+}
+
+/// The files the target opened on the host.
+#[derive(Debug)]
+pub(crate) struct HostFiles {
+    root: PathBuf,
+    files: HashMap<u32, File>,
+    /// Handles are never reused, so a handle from before a reset of the target stays invalid.
+    next_handle: u32,
+    /// The error of the last call, as returned by `SYS_ERRNO`.
+    errno: u32,
+}
+
+impl HostFiles {
+    /// Creates the files of a target, which are opened relative to `root`.
+    pub(crate) fn new(root: PathBuf) -> Self {
+        Self {
+            root,
+            files: HashMap::new(),
+            next_handle: FIRST_FILE_HANDLE,
+            errno: 0,
+        }
+    }
+
+    /// Closes all files when the target is reset, their handles are invalid afterwards.
+    pub(crate) fn reset(&mut self) {
+        if !self.files.is_empty() {
+            log::debug!(
+                "Closing {} semihosting files after a reset",
+                self.files.len()
+            );
+        }
+        self.files.clear();
+    }
+
+    /// Executes the semihosting `operation` with the parameter block at `parameters`.
+    ///
+    /// Returns the value of `R0`, or `None` if the operation is not executed on the host.
+    pub(crate) fn call(
+        &mut self,
+        operation: u32,
+        parameters: u32,
+        memory: &mut impl TargetMemory,
+    ) -> Result<Option<u32>, Error> {
+        let result = match operation {
+            SYS_OPEN => {
+                let (name, mode, length) = (
+                    parameter(memory, parameters, 0)?,
+                    parameter(memory, parameters, 1)?,
+                    parameter(memory, parameters, 2)?,
+                );
+                let name = read_path(memory, name, length)?;
+                self.open(name.as_deref(), mode)
+            }
+            SYS_CLOSE => {
+                let handle = parameter(memory, parameters, 0)?;
+                if handle < FIRST_FILE_HANDLE || self.files.remove(&handle).is_some() {
+                    Ok(0)
+                } else {
+                    Err(EBADF)
+                }
+            }
+            SYS_WRITEC => {
+                let mut byte = [0];
+                memory.read(parameters, &mut byte)?;
+                console_write(1, &byte);
+                Ok(0)
+            }
+            SYS_WRITE0 => {
+                let mut text = vec![];
+                let mut byte = [0];
+                while text.len() < MAX_STRING_LENGTH as usize {
+                    memory.read(parameters + text.len() as u32, &mut byte)?;
+                    if byte[0] == 0 {
+                        break;
+                    }
+                    text.push(byte[0]);
+                }
+                console_write(1, &text);
+                Ok(0)
+            }
+            SYS_WRITE => {
+                let (handle, buffer, length) = (
+                    parameter(memory, parameters, 0)?,
+                    parameter(memory, parameters, 1)?,
+                    parameter(memory, parameters, 2)?,
+                );
+                let mut chunk = vec![0; length.min(WRITE_CHUNK_SIZE) as usize];
+                let mut result = Ok(0);
+                for offset in (0..length).step_by(WRITE_CHUNK_SIZE as usize) {
+                    let chunk = &mut chunk[..(length - offset).min(WRITE_CHUNK_SIZE) as usize];
+                    memory.read(buffer.wrapping_add(offset), chunk)?;
+                    if let Err(errno) = self.write(handle, chunk) {
+                        result = Err(errno);
+                        break;
+                    }
+                }
+                result
+            }
+            SYS_READ => {
+                let (handle, buffer, length) = (
+                    parameter(memory, parameters, 0)?,
+                    parameter(memory, parameters, 1)?,
+                    parameter(memory, parameters, 2)?,
+                );
+                match self.read(handle, length) {
+                    Ok(data) => {
+                        memory.write(buffer, &data)?;
+                        // The number of bytes which were not read.
+                        Ok(length - data.len() as u32)
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            SYS_ISTTY => {
+                let handle = parameter(memory, parameters, 0)?;
+                if handle < FIRST_FILE_HANDLE {
+                    Ok(1)
+                } else if self.files.contains_key(&handle) {
+                    Ok(0)
+                } else {
+                    Err(EBADF)
+                }
+            }
+            SYS_SEEK => {
+                let (handle, position) = (
+                    parameter(memory, parameters, 0)?,
+                    parameter(memory, parameters, 1)?,
+                );
+                self.file(handle).and_then(|file| {
+                    file.seek(SeekFrom::Start(position.into()))
+                        .map(|_| 0)
+                        .map_err(|e| errno(&e))
+                })
+            }
+            SYS_FLEN => {
+                let handle = parameter(memory, parameters, 0)?;
+                self.file(handle).and_then(|file| {
+                    file.metadata()
+                        .map(|metadata| metadata.len() as u32)
+                        .map_err(|e| errno(&e))
+                })
+            }
+            SYS_REMOVE => {
+                let (name, length) = (
+                    parameter(memory, parameters, 0)?,
+                    parameter(memory, parameters, 1)?,
+                );
+                let name = read_path(memory, name, length)?;
+                self.path(name.as_deref())
+                    .and_then(|path| fs::remove_file(path).map_err(|e| errno(&e)))
+                    .map(|()| 0)
+            }
+            SYS_RENAME => {
+                let (old, old_length) = (
+                    parameter(memory, parameters, 0)?,
+                    parameter(memory, parameters, 1)?,
+                );
+                let (new, new_length) = (
+                    parameter(memory, parameters, 2)?,
+                    parameter(memory, parameters, 3)?,
+                );
+                let old = read_path(memory, old, old_length)?;
+                let new = read_path(memory, new, new_length)?;
+                self.path(old.as_deref())
+                    .and_then(|old| Ok((old, self.path(new.as_deref())?)))
+                    .and_then(|(old, new)| fs::rename(old, new).map_err(|e| errno(&e)))
+                    .map(|()| 0)
+            }
+            SYS_ERRNO => Ok(self.errno),
+            _ => return Ok(None),
+        };
+
+        Ok(Some(result.unwrap_or_else(|errno| {
+            self.errno = errno;
+            FAILED
+        })))
+    }
+
+    /// Opens `name` with the `fopen` mode of `SYS_OPEN`, and returns its handle.
+    fn open(&mut self, name: Option<&str>, mode: u32) -> Result<u32, u32> {
+        // The special file ":tt" is the console: stdin, stdout or stderr depending on the mode.
+        if name == Some(":tt") {
+            return Ok(mode / 4);
+        }
+
+        let path = self.path(name)?;
+
+        let mut options = OpenOptions::new();
+        // The modes come in pairs, with and without the binary flag.
+        match mode / 2 {
+            0 => options.read(true),
+            1 => options.read(true).write(true),
+            2 => options.write(true).create(true).truncate(true),
+            3 => options.read(true).write(true).create(true).truncate(true),
+            4 => options.append(true).create(true),
+            5 => options.read(true).append(true).create(true),
+            _ => return Err(EINVAL),
+        };
+
+        let file = options.open(&path).map_err(|e| errno(&e))?;
+        log::debug!("Opened {} for semihosting", path.display());
+
+        let handle = self.next_handle;
+        self.next_handle += 1;
+        self.files.insert(handle, file);
+        Ok(handle)
+    }
+
+    fn write(&mut self, handle: u32, data: &[u8]) -> Result<(), u32> {
+        match handle {
+            0 => Err(EBADF),
+            1 | 2 => {
+                console_write(handle, data);
+                Ok(())
+            }
+            _ => self.file(handle)?.write_all(data).map_err(|e| errno(&e)),
+        }
+    }
+
+    fn read(&mut self, handle: u32, length: u32) -> Result<Vec<u8>, u32> {
+        let mut data = vec![];
+        let result = match handle {
+            // The console returns what is available, e.g. one line, instead of
+            // waiting until `length` bytes were typed.
+            0 => {
+                data.resize(length.min(CONSOLE_READ_SIZE) as usize, 0);
+                io::stdin().read(&mut data).map(|read| {
+                    data.truncate(read);
+                    read
+                })
+            }
+            1 | 2 => return Err(EBADF),
+            _ => self
+                .file(handle)?
+                .take(length.into())
+                .read_to_end(&mut data),
+        };
+
+        result.map(|_| data).map_err(|e| errno(&e))
+    }
+
+    fn file(&mut self, handle: u32) -> Result<&mut File, u32> {
+        self.files.get_mut(&handle).ok_or(EBADF)
+    }
+
+    /// Returns the path of `name` in the root.
+    fn path(&self, name: Option<&str>) -> Result<PathBuf, u32> {
+        let name = name.ok_or(EINVAL)?;
+        let path = sandboxed(&self.root, name).and_then(|path| resolved(&self.root, &path));
+        path.ok_or_else(|| {
+            log::warn!(
+                "Semihosting access to '{}' outside of the root was denied",
+                name
+            );
+            EACCES
+        })
+    }
+}
+
+/// Returns `name` in `root`, or `None` if it is absolute or leaves the root.
+fn sandboxed(root: &Path, name: &str) -> Option<PathBuf> {
+    let name = Path::new(name);
+    let relative = name
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+
+    if relative && name.components().next().is_some() {
+        Some(root.join(name))
+    } else {
+        None
+    }
+}
+
+/// Returns `path` with its symbolic links resolved, or `None` if it is not in `root`.
+///
+/// A path which does not exist yet is resolved by its directory, unless it is a
+/// symbolic link, which would create a file wherever it points to.
+fn resolved(root: &Path, path: &Path) -> Option<PathBuf> {
+    let root = root.canonicalize().ok()?;
+
+    let resolved = match path.canonicalize() {
+        Ok(resolved) => resolved,
+        Err(e) if e.kind() == io::ErrorKind::NotFound && fs::symlink_metadata(path).is_err() => {
+            path.parent()?.canonicalize().ok()?.join(path.file_name()?)
+        }
+        Err(_) => return None,
+    };
+
+    if resolved.starts_with(&root) {
+        Some(resolved)
+    } else {
+        None
+    }
+}
+
+/// Reads the parameter with the `index` from the parameter block.
+fn parameter(memory: &mut impl TargetMemory, parameters: u32, index: u32) -> Result<u32, Error> {
+    let mut word = [0; 4];
+    memory.read(parameters + index * 4, &mut word)?;
+    Ok(u32::from_le_bytes(word))
+}
+
+/// Reads a path with `length` bytes, which is `None` if it is not valid UTF-8
+/// or longer than [MAX_PATH_LENGTH].
+fn read_path(
+    memory: &mut impl TargetMemory,
+    address: u32,
+    length: u32,
+) -> Result<Option<String>, Error> {
+    if length > MAX_PATH_LENGTH {
+        log::warn!(
+            "Semihosting path of {} bytes is longer than {} bytes",
+            length,
+            MAX_PATH_LENGTH
+        );
+        return Ok(None);
+    }
+
+    let mut name = vec![0; length as usize];
+    memory.read(address, &mut name)?;
+    Ok(String::from_utf8(name).ok())
+}
+
+fn console_write(handle: u32, data: &[u8]) {
+    let result = if handle == 2 {
+        io::stderr().write_all(data)
+    } else {
+        let mut stdout = io::stdout();
+        stdout.write_all(data).and_then(|()| stdout.flush())
+    };
+
+    if let Err(e) = result {
+        log::warn!("Unable to write semihosting output: {}", e);
+    }
+}
+
+/// Returns the errno of the target for an error of the host.
+fn errno(error: &io::Error) -> u32 {
+    match error.kind() {
+        io::ErrorKind::NotFound => 2,
+        io::ErrorKind::PermissionDenied => EACCES,
+        io::ErrorKind::AlreadyExists => 17,
+        io::ErrorKind::InvalidInput => EINVAL,
+        _ => 5,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sandboxed, HostFiles, TargetMemory, FAILED};
+    use probe_rs::Error;
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    /// The memory of a target, whose parameter block is at 0x2000_0000.
+    #[derive(Default)]
+    struct Memory(HashMap<u32, u8>);
+
+    impl Memory {
+        fn with_parameters(parameters: &[u32]) -> Self {
+            let mut memory = Memory::default();
+            for (n, parameter) in parameters.iter().enumerate() {
+                memory
+                    .write(0x2000_0000 + n as u32 * 4, &parameter.to_le_bytes())
+                    .unwrap();
+            }
+            memory
+        }
+    }
+
+    impl TargetMemory for Memory {
+        fn read(&mut self, address: u32, data: &mut [u8]) -> Result<(), Error> {
+            for (i, byte) in data.iter_mut().enumerate() {
+                *byte = self.0[&(address + i as u32)];
+            }
+            Ok(())
+        }
+
+        fn write(&mut self, address: u32, data: &[u8]) -> Result<(), Error> {
+            for (i, byte) in data.iter().enumerate() {
+                self.0.insert(address + i as u32, *byte);
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn paths_outside_of_the_root_are_rejected() {
+        let root = Path::new("/srv/vectors");
+
+        assert_eq!(
+            sandboxed(root, "input/a.bin"),
+            Some(root.join("input/a.bin"))
+        );
+        assert_eq!(sandboxed(root, "./a.bin"), Some(root.join("a.bin")));
+        assert_eq!(sandboxed(root, "/etc/passwd"), None);
+        assert_eq!(sandboxed(root, "../secret"), None);
+        assert_eq!(sandboxed(root, "input/../../secret"), None);
+        assert_eq!(sandboxed(root, ""), None);
+    }
+
+    #[test]
+    fn files_are_written_and_read_in_the_root() {
+        let root =
+            std::env::temp_dir().join(format!("probe-rs-semihosting-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let mut files = HostFiles::new(root.clone());
+
+        // SYS_OPEN "test.bin" with mode "wb+"
+        let mut memory = Memory::with_parameters(&[0x2000_0100, 7, 8]);
+        memory.write(0x2000_0100, b"test.bin").unwrap();
+        let handle = files.call(0x01, 0x2000_0000, &mut memory).unwrap().unwrap();
+        assert_eq!(handle, 3);
+
+        // SYS_WRITE of 4 bytes
+        let mut memory = Memory::with_parameters(&[handle, 0x2000_0100, 4]);
+        memory.write(0x2000_0100, b"data").unwrap();
+        assert_eq!(files.call(0x05, 0x2000_0000, &mut memory).unwrap(), Some(0));
+
+        // SYS_FLEN, SYS_SEEK to the start, and SYS_READ of 8 bytes
+        let mut memory = Memory::with_parameters(&[handle, 0]);
+        assert_eq!(files.call(0x0C, 0x2000_0000, &mut memory).unwrap(), Some(4));
+        assert_eq!(files.call(0x0A, 0x2000_0000, &mut memory).unwrap(), Some(0));
+        let mut memory = Memory::with_parameters(&[handle, 0x2000_0100, 8]);
+        assert_eq!(files.call(0x06, 0x2000_0000, &mut memory).unwrap(), Some(4));
+        let mut data = [0; 4];
+        memory.read(0x2000_0100, &mut data).unwrap();
+        assert_eq!(&data, b"data");
+
+        // After a reset, the handle is invalid.
+        files.reset();
+        let mut memory = Memory::with_parameters(&[handle]);
+        assert_eq!(
+            files.call(0x02, 0x2000_0000, &mut memory).unwrap(),
+            Some(FAILED)
+        );
+        assert_eq!(files.call(0x13, 0x2000_0000, &mut memory).unwrap(), Some(9));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn long_writes_are_copied_in_chunks() {
+        let root = std::env::temp_dir().join(format!(
+            "probe-rs-semihosting-chunks-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&root).unwrap();
+        let mut files = HostFiles::new(root.clone());
+
+        // SYS_OPEN "long.bin" with mode "wb"
+        let mut memory = Memory::with_parameters(&[0x2000_0100, 5, 8]);
+        memory.write(0x2000_0100, b"long.bin").unwrap();
+        let handle = files.call(0x01, 0x2000_0000, &mut memory).unwrap().unwrap();
+
+        // SYS_WRITE of two and a half chunks
+        let data: Vec<u8> = (0..10_000).map(|i| i as u8).collect();
+        let mut memory = Memory::with_parameters(&[handle, 0x2000_1000, data.len() as u32]);
+        memory.write(0x2000_1000, &data).unwrap();
+        assert_eq!(files.call(0x05, 0x2000_0000, &mut memory).unwrap(), Some(0));
+
+        files.reset();
+        assert_eq!(std::fs::read(root.join("long.bin")).unwrap(), data);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symbolic_links_out_of_the_root_are_rejected() {
+        let base =
+            std::env::temp_dir().join(format!("probe-rs-semihosting-links-{}", std::process::id()));
+        let (root, outside) = (base.join("root"), base.join("outside"));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::create_dir_all(&outside).unwrap();
+        std::fs::write(outside.join("secret"), b"secret").unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("escape")).unwrap();
+        std::os::unix::fs::symlink(outside.join("new"), root.join("dangling")).unwrap();
+        let mut files = HostFiles::new(root);
+
+        // SYS_OPEN with mode "rb", and with "wb" through a link to a file which does not exist
+        for (name, mode) in &[(&b"escape/secret"[..], 1), (&b"dangling"[..], 5)] {
+            let mut memory = Memory::with_parameters(&[0x2000_0100, *mode, name.len() as u32]);
+            memory.write(0x2000_0100, name).unwrap();
+            assert_eq!(
+                files.call(0x01, 0x2000_0000, &mut memory).unwrap(),
+                Some(FAILED)
+            );
+            assert_eq!(
+                files.call(0x13, 0x2000_0000, &mut memory).unwrap(),
+                Some(13)
+            );
+        }
+        assert!(!outside.join("new").exists());
+
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn long_paths_are_rejected() {
+        let mut files = HostFiles::new(Path::new("/srv/vectors").to_owned());
+
+        // SYS_OPEN with a path length from uninitialized memory, which is not read.
+        let mut memory = Memory::with_parameters(&[0x2000_0100, 0, 0xdead_beef]);
+        assert_eq!(
+            files.call(0x01, 0x2000_0000, &mut memory).unwrap(),
+            Some(FAILED)
+        );
+        assert_eq!(
+            files.call(0x13, 0x2000_0000, &mut memory).unwrap(),
+            Some(22)
+        );
+    }
+}
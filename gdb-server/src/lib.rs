@@ -3,9 +3,14 @@
 mod architecture;
 mod gdb_server_async;
 mod handlers;
+mod host_files;
+mod memory;
+mod non_stop;
 mod parser;
 mod reader;
+mod semihosting;
+mod threads;
 mod worker;
 mod writer;
 
-pub use gdb_server_async::run;
+pub use gdb_server_async::{run, run_with_options, run_with_rtos, ServerOptions};
@@ -0,0 +1,166 @@
+//! Operations on large memory ranges, which are read from the target in chunks.
+
+use probe_rs::Error;
+
+/// The size of the chunks in which memory ranges are read.
+const CHUNK_SIZE: u32 = 1024;
+
+/// The polynomial of the CRC-32 which GDB uses for `qCRC`.
+const CRC_POLYNOMIAL: u32 = 0x04C1_1DB7;
+
+/// Reads `address..address + length` chunk by chunk and passes each chunk to `process`,
+/// until it returns `false`.
+fn for_each_chunk(
+    address: u32,
+    length: u32,
+    mut read: impl FnMut(u32, &mut [u8]) -> Result<(), Error>,
+    mut process: impl FnMut(&[u8]) -> bool,
+) -> Result<(), Error> {
+    let mut buffer = vec![0; CHUNK_SIZE as usize];
+    let mut offset = 0;
+
+    while offset < length {
+        let size = (length - offset).min(CHUNK_SIZE);
+        let chunk = &mut buffer[..size as usize];
+
+        read(address + offset, chunk)?;
+        if !process(chunk) {
+            break;
+        }
+
+        offset += size;
+    }
+
+    Ok(())
+}
+
+/// Continues the CRC-32 `crc` over `data`, the way GDB computes it for `qCRC`.
+///
+/// The CRC starts with `0xFFFF_FFFF`, is not reflected and not inverted at the end.
+fn crc32(mut crc: u32, data: &[u8]) -> u32 {
+    for byte in data {
+        crc ^= u32::from(*byte) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ CRC_POLYNOMIAL
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Computes the CRC-32 of `address..address + length` for `qCRC`.
+///
+/// The memory is read in chunks, so the range does not have to fit into memory.
+pub(crate) fn memory_crc(
+    address: u32,
+    length: u32,
+    read: impl FnMut(u32, &mut [u8]) -> Result<(), Error>,
+) -> Result<u32, Error> {
+    let mut crc = 0xFFFF_FFFF;
+
+    for_each_chunk(address, length, read, |chunk| {
+        crc = crc32(crc, chunk);
+        true
+    })?;
+
+    Ok(crc)
+}
+
+/// Returns the address of the first occurence of `pattern` in `address..address + length`.
+///
+/// The memory is read in chunks, a match which crosses chunks is found as well.
+pub(crate) fn search_memory(
+    address: u32,
+    length: u32,
+    pattern: &[u8],
+    read: impl FnMut(u32, &mut [u8]) -> Result<(), Error>,
+) -> Result<Option<u32>, Error> {
+    if pattern.is_empty() {
+        return Ok(Some(address));
+    }
+
+    // The end of the previous chunks, which could be the start of a match.
+    let mut window = Vec::new();
+    let mut window_address = address;
+    let mut found = None;
+
+    for_each_chunk(address, length, read, |chunk| {
+        window.extend_from_slice(chunk);
+
+        if let Some(position) = window
+            .windows(pattern.len())
+            .position(|candidate| candidate == pattern)
+        {
+            found = Some(window_address + position as u32);
+            return false;
+        }
+
+        let dropped = window.len().saturating_sub(pattern.len() - 1);
+        window.drain(..dropped);
+        window_address += dropped as u32;
+        true
+    })?;
+
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{memory_crc, search_memory, CHUNK_SIZE};
+    use probe_rs::Error;
+
+    fn read_from(memory: &[u8]) -> impl FnMut(u32, &mut [u8]) -> Result<(), Error> + '_ {
+        move |address, data| {
+            let start = address as usize - 0x1000;
+            data.copy_from_slice(&memory[start..start + data.len()]);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn crc_matches_gdb() {
+        // The check value of the CRC-32/MPEG-2, which GDB uses.
+        assert_eq!(
+            memory_crc(0x1000, 9, read_from(b"123456789")).unwrap(),
+            0x0376_E6E7
+        );
+    }
+
+    #[test]
+    fn crc_is_continued_across_chunks() {
+        let memory = (0..3 * CHUNK_SIZE).map(|n| n as u8).collect::<Vec<_>>();
+
+        let mut chunk_sizes = vec![];
+        let crc = memory_crc(0x1000, memory.len() as u32, |address, data| {
+            chunk_sizes.push(data.len());
+            read_from(&memory)(address, data)
+        })
+        .unwrap();
+
+        assert_eq!(chunk_sizes, vec![CHUNK_SIZE as usize; 3]);
+        assert_eq!(crc, super::crc32(0xFFFF_FFFF, &memory));
+    }
+
+    #[test]
+    fn patterns_are_found_across_chunks() {
+        let mut memory = vec![0; 2 * CHUNK_SIZE as usize];
+        memory[CHUNK_SIZE as usize - 2..CHUNK_SIZE as usize + 2].copy_from_slice(b"abcd");
+
+        let found = search_memory(0x1000, memory.len() as u32, b"abcd", read_from(&memory));
+        assert_eq!(found.unwrap(), Some(0x1000 + CHUNK_SIZE - 2));
+
+        let missing = search_memory(0x1000, memory.len() as u32, b"abce", read_from(&memory));
+        assert_eq!(missing.unwrap(), None);
+    }
+
+    #[test]
+    fn read_errors_are_returned() {
+        let result = search_memory(0x1000, 16, b"a", |_, _| {
+            Err(Error::Other(anyhow::anyhow!("Unreadable")))
+        });
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::VecDeque;
+
+/// The state of GDB's non-stop mode, in which each thread is stopped and resumed on its own.
+///
+/// Stops are reported asynchronously: the first stop reply is sent as a `%Stop` notification,
+/// GDB then fetches all queued stop replies with `vStopped` until the reply is `OK`.
+#[derive(Debug, Default)]
+pub(crate) struct NonStop {
+    enabled: bool,
+    /// The cores which were resumed and did not stop yet.
+    running: Vec<usize>,
+    /// The stop replies which GDB did not acknowledge yet.
+    stops: VecDeque<String>,
+    /// The notification which has to be sent to GDB.
+    notification: Option<String>,
+}
+
+impl NonStop {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Switches between non-stop and all-stop mode.
+    pub(crate) fn set_enabled(&mut self, enabled: bool) {
+        *self = Self {
+            enabled,
+            ..Self::default()
+        };
+    }
+
+    /// The cores which were resumed and did not stop yet.
+    pub(crate) fn running_cores(&self) -> Vec<usize> {
+        self.running.clone()
+    }
+
+    /// Marks the core `core` as running.
+    pub(crate) fn resumed(&mut self, core: usize) {
+        if !self.running.contains(&core) {
+            self.running.push(core);
+        }
+    }
+
+    /// Marks the core `core` as stopped, returns `false` if it was not running.
+    pub(crate) fn stopped(&mut self, core: usize) -> bool {
+        let running = self.running.len();
+        self.running.retain(|n| *n != core);
+        self.running.len() != running
+    }
+
+    /// Queues a stop `reply`, which is sent as notification if no other stop is waiting for GDB.
+    pub(crate) fn report_stop(&mut self, reply: String) {
+        self.stops.push_back(reply);
+
+        if self.stops.len() == 1 {
+            self.notification = Some(format!("Stop:{}", self.stops[0]));
+        }
+    }
+
+    /// Returns the notification which has to be sent to GDB, if there is one.
+    pub(crate) fn take_notification(&mut self) -> Option<String> {
+        self.notification.take()
+    }
+
+    /// Handles `vStopped`: the oldest stop reply was received, the next one is returned.
+    pub(crate) fn next_stop(&mut self) -> String {
+        self.stops.pop_front();
+        self.stops.front().cloned().unwrap_or_else(|| "OK".into())
+    }
+
+    /// Handles `?`: all stopped threads are reported with `replies`, instead of the queued stops.
+    pub(crate) fn report_all(&mut self, replies: Vec<String>) -> String {
+        self.stops = replies.into();
+        self.notification = None;
+        self.stops.front().cloned().unwrap_or_else(|| "OK".into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NonStop;
+
+    #[test]
+    fn only_the_first_stop_is_notified() {
+        let mut non_stop = NonStop::new();
+        non_stop.set_enabled(true);
+
+        non_stop.report_stop("T05thread:1;".into());
+        non_stop.report_stop("T05thread:2;".into());
+
+        assert_eq!(
+            non_stop.take_notification(),
+            Some("Stop:T05thread:1;".into())
+        );
+        assert_eq!(non_stop.take_notification(), None);
+
+        assert_eq!(non_stop.next_stop(), "T05thread:2;");
+        assert_eq!(non_stop.next_stop(), "OK");
+
+        non_stop.report_stop("T00thread:1;".into());
+        assert_eq!(
+            non_stop.take_notification(),
+            Some("Stop:T00thread:1;".into())
+        );
+    }
+
+    #[test]
+    fn running_cores_are_tracked() {
+        let mut non_stop = NonStop::new();
+        non_stop.resumed(1);
+        non_stop.resumed(1);
+        non_stop.resumed(0);
+
+        assert_eq!(non_stop.running_cores(), vec![1, 0]);
+        assert!(non_stop.stopped(1));
+        assert!(!non_stop.stopped(1));
+        assert_eq!(non_stop.running_cores(), vec![0]);
+    }
+}
@@ -10,9 +10,10 @@ use nom::{
     branch::alt,
     bytes::complete::{tag, take},
     character::complete::char,
-    combinator::value,
+    combinator::{opt, value},
     map, named,
     number::complete::hex_u32,
+    sequence::preceded,
     IResult,
 };
 
@@ -21,7 +22,7 @@ use query::query_packet;
 use v_packet::v_packet;
 
 pub use query::{Pid, QueryPacket};
-use util::hex_u64;
+use util::{hex_bytes, hex_u64};
 pub use v_packet::VPacket;
 
 #[allow(dead_code)]
@@ -53,14 +54,24 @@ pub enum Packet {
     Debug,
     /// Packet `D`
     Detach,
-    /// Packet `F`
-    FileIO,
+    /// Packet `F`, the reply to a File-I/O request
+    FileIO {
+        /// The return value of the system call.
+        result: i64,
+        /// The error of the system call, if it failed.
+        errno: Option<u32>,
+        /// `true` if the user interrupted the call with Ctrl-C.
+        interrupted: bool,
+    },
     /// Packet `g`
     ReadGeneralRegister,
     /// Packet `G`
     WriteGeneralRegister,
     /// Packet `H`
-    SelectThread,
+    SelectThread {
+        operation: ThreadOperation,
+        thread: ThreadId,
+    },
     /// Packet `i`
     StepClockCycle,
     /// Packet `I`
@@ -77,14 +88,19 @@ pub enum Packet {
     /// Packet 'p'
     ReadRegisterHex(u32),
     /// Packet 'P'
-    WriteRegisterHex,
+    WriteRegisterHex {
+        register: u32,
+        value: Vec<u8>,
+    },
     // Packet 'q'
     Query(QueryPacket),
     // Packet 'Q'
     QuerySet,
+    /// Packet `QNonStop`, which enables or disables non-stop mode.
+    NonStop(bool),
     // Packet 'r'
     Reset,
-    // Packet 'R'
+    /// Packet `R`, which restarts the program in extended mode.
     Restart,
     // Packet 's'
     SingleStep,
@@ -93,7 +109,7 @@ pub enum Packet {
     // Packet 't'
     SearchBackwards,
     // Packet 'T'
-    ThreadInfo,
+    ThreadAlive(ThreadId),
     // Packet 'v'
     V(VPacket),
     // Packet 'X'
@@ -117,6 +133,26 @@ pub enum Packet {
     Interrupt,
 }
 
+/// The operations for which a thread is selected with the `H` packet.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ThreadOperation {
+    /// Packet `Hg`, used by all operations except continuing and stepping.
+    General,
+    /// Packet `Hc`, used by continuing and stepping.
+    Continue,
+}
+
+/// A thread id, as used in the `H` and `vCont` packets.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ThreadId {
+    /// All threads, given as `-1`.
+    All,
+    /// Any thread, given as `0`.
+    Any,
+    /// A specific thread.
+    Id(u32),
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum BreakpointType {
     Software,
@@ -130,10 +166,17 @@ pub fn parse_packet(input: &[u8]) -> Result<Packet> {
     let parse_result = alt((
         extended_mode,
         detach,
+        kill_request,
+        restart,
         halt_reason,
         read_register,
         read_register_hex,
+        write_register_hex,
         read_memory,
+        select_thread,
+        thread_alive,
+        file_io,
+        non_stop,
         query,
         v,
         insert_breakpoint,
@@ -153,6 +196,61 @@ named!(extended_mode<&[u8], Packet>, map!(char('!'), |_| Packet::EnableExtendedM
 
 named!(halt_reason<&[u8], Packet>, map!(char('?'), |_| Packet::HaltReason));
 
+fn thread_id(input: &[u8]) -> IResult<&[u8], ThreadId> {
+    alt((
+        value(ThreadId::All, tag("-1")),
+        nom::combinator::map(hex_u32, |id| match id {
+            0 => ThreadId::Any,
+            id => ThreadId::Id(id),
+        }),
+    ))(input)
+}
+
+fn select_thread(input: &[u8]) -> IResult<&[u8], Packet> {
+    let (input, _) = char('H')(input)?;
+
+    let (input, operation) = alt((
+        value(ThreadOperation::General, char('g')),
+        value(ThreadOperation::Continue, char('c')),
+    ))(input)?;
+
+    let (input, thread) = thread_id(input)?;
+
+    Ok((input, Packet::SelectThread { operation, thread }))
+}
+
+fn file_io(input: &[u8]) -> IResult<&[u8], Packet> {
+    let (input, _) = char('F')(input)?;
+
+    let (input, negative) = opt(char('-'))(input)?;
+    let (input, result) = hex_u64(input)?;
+    let (input, errno) = opt(preceded(char(','), hex_u32))(input)?;
+    let (input, interrupted) = opt(tag(",C"))(input)?;
+
+    let result = if negative.is_some() {
+        -(result as i64)
+    } else {
+        result as i64
+    };
+
+    Ok((
+        input,
+        Packet::FileIO {
+            result,
+            errno,
+            interrupted: interrupted.is_some(),
+        },
+    ))
+}
+
+fn thread_alive(input: &[u8]) -> IResult<&[u8], Packet> {
+    let (input, _) = char('T')(input)?;
+
+    let (input, thread) = thread_id(input)?;
+
+    Ok((input, Packet::ThreadAlive(thread)))
+}
+
 fn continue_packet(input: &[u8]) -> IResult<&[u8], Packet> {
     let (input, _) = char('c')(input)?;
 
@@ -163,6 +261,18 @@ fn detach(input: &[u8]) -> IResult<&[u8], Packet> {
     value(Packet::Detach, char('D'))(input)
 }
 
+fn kill_request(input: &[u8]) -> IResult<&[u8], Packet> {
+    value(Packet::KillRequest, char('k'))(input)
+}
+
+fn restart(input: &[u8]) -> IResult<&[u8], Packet> {
+    // The argument of the packet is ignored by GDB, and has no meaning for us either.
+    let (input, _) = char('R')(input)?;
+    let (input, _) = opt(hex_u32)(input)?;
+
+    Ok((input, Packet::Restart))
+}
+
 fn read_register(input: &[u8]) -> IResult<&[u8], Packet> {
     let (input, _) = char('g')(input)?;
 
@@ -177,6 +287,24 @@ fn read_register_hex(input: &[u8]) -> IResult<&[u8], Packet> {
     Ok((input, Packet::ReadRegisterHex(value)))
 }
 
+fn write_register_hex(input: &[u8]) -> IResult<&[u8], Packet> {
+    let (input, _) = char('P')(input)?;
+
+    let (input, register) = hex_u32(input)?;
+    let (input, _) = char('=')(input)?;
+    let (input, value) = hex_bytes(input)?;
+
+    Ok((input, Packet::WriteRegisterHex { register, value }))
+}
+
+fn non_stop(input: &[u8]) -> IResult<&[u8], Packet> {
+    let (input, _) = tag("QNonStop:")(input)?;
+
+    let (input, enabled) = alt((value(false, char('0')), value(true, char('1'))))(input)?;
+
+    Ok((input, Packet::NonStop(enabled)))
+}
+
 fn query(input: &[u8]) -> IResult<&[u8], Packet> {
     let (input, _) = char('q')(input)?;
     let (input, packet) = query_packet(input)?;
@@ -296,6 +424,8 @@ mod test {
             ("c", Packet::Continue),
             ("g", Packet::ReadGeneralRegister),
             ("D", Packet::Detach),
+            ("k", Packet::KillRequest),
+            ("R00", Packet::Restart),
             ("qSupported", Packet::Query(QueryPacket::Supported(vec![]))),
             ("qHostInfo", Packet::Query(QueryPacket::HostInfo)),
             ("vCont?", Packet::V(VPacket::QueryContSupport)),
@@ -319,6 +449,17 @@ mod test {
         assert_eq!(parse_packet(b"p03").unwrap(), Packet::ReadRegisterHex(3));
     }
 
+    #[test]
+    fn parse_packet_write_register_hex() {
+        assert_eq!(
+            parse_packet(b"P86=78563412").unwrap(),
+            Packet::WriteRegisterHex {
+                register: 0x86,
+                value: vec![0x78, 0x56, 0x34, 0x12],
+            }
+        );
+    }
+
     #[test]
     fn parse_query_attached() {
         assert_eq!(
@@ -411,6 +552,39 @@ mod test {
         );
     }
 
+    #[test]
+    fn parse_select_thread() {
+        assert_eq!(
+            parse_packet(b"Hg2").unwrap(),
+            Packet::SelectThread {
+                operation: ThreadOperation::General,
+                thread: ThreadId::Id(2),
+            }
+        );
+        assert_eq!(
+            parse_packet(b"Hc-1").unwrap(),
+            Packet::SelectThread {
+                operation: ThreadOperation::Continue,
+                thread: ThreadId::All,
+            }
+        );
+        assert_eq!(
+            parse_packet(b"Hg0").unwrap(),
+            Packet::SelectThread {
+                operation: ThreadOperation::General,
+                thread: ThreadId::Any,
+            }
+        );
+    }
+
+    #[test]
+    fn parse_thread_alive() {
+        assert_eq!(
+            parse_packet(b"T1").unwrap(),
+            Packet::ThreadAlive(ThreadId::Id(1))
+        );
+    }
+
     #[test]
     fn parse_interrupt() {
         assert_eq!(parse_packet(&[0x03]).unwrap(), Packet::Interrupt);
@@ -441,4 +615,42 @@ mod test {
             })
         );
     }
+
+    #[test]
+    fn parse_file_io_reply() {
+        assert_eq!(
+            parse_packet(b"F1a").unwrap(),
+            Packet::FileIO {
+                result: 0x1a,
+                errno: None,
+                interrupted: false,
+            }
+        );
+        assert_eq!(
+            parse_packet(b"F-1,4,C").unwrap(),
+            Packet::FileIO {
+                result: -1,
+                errno: Some(4),
+                interrupted: true,
+            }
+        );
+    }
+
+    #[test]
+    fn parse_non_stop() {
+        assert_eq!(parse_packet(b"QNonStop:1").unwrap(), Packet::NonStop(true));
+        assert_eq!(parse_packet(b"QNonStop:0").unwrap(), Packet::NonStop(false));
+    }
+
+    #[test]
+    fn parse_query_search_packet() {
+        assert_eq!(
+            parse_packet(b"qSearch:memory:20000000;1000;\x12;4").unwrap(),
+            Packet::Query(QueryPacket::Search {
+                address: 0x2000_0000,
+                length: 0x1000,
+                pattern: b"\x12;4".to_vec(),
+            })
+        );
+    }
 }
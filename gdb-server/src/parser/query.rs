@@ -27,6 +27,12 @@ pub enum QueryPacket {
         address: u32,
         length: u32,
     },
+    /// Packet `qSearch:memory`
+    Search {
+        address: u32,
+        length: u32,
+        pattern: Vec<u8>,
+    },
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -56,6 +62,7 @@ pub fn query_packet(input: &[u8]) -> IResult<&[u8], QueryPacket> {
         query_attached,
         query_command,
         query_crc,
+        query_search,
         query_supported,
         query_transfer,
         query_hostinfo,
@@ -185,6 +192,24 @@ fn transfer_operation_write(input: &[u8]) -> IResult<&[u8], TransferOperation> {
     ))
 }
 
+fn query_search(input: &[u8]) -> IResult<&[u8], QueryPacket> {
+    let (input, _) = tag("Search:memory:")(input)?;
+
+    let (input, address) = hex_u32(input)?;
+    let (input, _) = char(';')(input)?;
+    let (input, length) = hex_u32(input)?;
+    let (input, _) = char(';')(input)?;
+
+    Ok((
+        &[],
+        QueryPacket::Search {
+            address,
+            length,
+            pattern: input.to_owned(),
+        },
+    ))
+}
+
 fn query_crc(input: &[u8]) -> IResult<&[u8], QueryPacket> {
     let (input, _) = tag("CRC")(input)?;
 
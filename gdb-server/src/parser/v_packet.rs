@@ -1,14 +1,41 @@
-use super::{query::pid, Pid};
+use super::{query::pid, thread_id, Pid, ThreadId};
 use nom::{
-    branch::alt, bytes::complete::tag, character::complete::char, combinator::value, IResult,
+    branch::alt,
+    bytes::complete::tag,
+    character::complete::char,
+    combinator::{opt, rest, value},
+    multi::many1,
+    number::complete::hex_u32,
+    sequence::preceded,
+    IResult,
 };
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum VPacket {
     Attach(Pid),
-    Continue(Action),
+    /// Packet `vCont`, with the actions and the threads they apply to.
+    ///
+    /// Actions without a thread apply to all threads which have no other action.
+    Continue(Vec<(Action, Option<ThreadId>)>),
     Unknown(Vec<u8>),
     QueryContSupport,
+    FlashErase {
+        address: u32,
+        length: u32,
+    },
+    FlashWrite {
+        address: u32,
+        data: Vec<u8>,
+    },
+    FlashDone,
+    /// Packet `vStopped`, which acknowledges a stop notification in non-stop mode.
+    Stopped,
+    /// Packet `vKill`, which kills the process in extended mode.
+    Kill(Pid),
+    /// Packet `vRun`, which starts the program in extended mode.
+    ///
+    /// The file name and the arguments are ignored, the firmware on the target is restarted.
+    Run,
 }
 
 #[allow(dead_code)]
@@ -23,7 +50,17 @@ pub enum Action {
 }
 
 pub fn v_packet(input: &[u8]) -> IResult<&[u8], VPacket> {
-    let parse_result = alt((v_attach, v_cont_support, v_cont))(input);
+    let parse_result = alt((
+        v_attach,
+        v_cont_support,
+        v_cont,
+        v_flash_erase,
+        v_flash_write,
+        v_flash_done,
+        v_stopped,
+        v_kill,
+        v_run,
+    ))(input);
 
     match parse_result {
         Ok((input, packet)) => Ok((input, packet)),
@@ -51,11 +88,68 @@ fn v_cont_support(input: &[u8]) -> IResult<&[u8], VPacket> {
 }
 
 fn v_cont(input: &[u8]) -> IResult<&[u8], VPacket> {
-    let (input, _) = tag("Cont;")(input)?;
+    let (input, _) = tag("Cont")(input)?;
 
+    let (input, actions) = many1(preceded(char(';'), v_cont_thread_action))(input)?;
+
+    Ok((input, VPacket::Continue(actions)))
+}
+
+fn v_cont_thread_action(input: &[u8]) -> IResult<&[u8], (Action, Option<ThreadId>)> {
     let (input, action) = v_cont_action(input)?;
 
-    Ok((input, VPacket::Continue(action)))
+    let (input, thread) = opt(preceded(char(':'), thread_id))(input)?;
+
+    Ok((input, (action, thread)))
+}
+
+fn v_flash_erase(input: &[u8]) -> IResult<&[u8], VPacket> {
+    let (input, _) = tag("FlashErase:")(input)?;
+
+    let (input, address) = hex_u32(input)?;
+    let (input, _) = char(',')(input)?;
+    let (input, length) = hex_u32(input)?;
+
+    Ok((input, VPacket::FlashErase { address, length }))
+}
+
+fn v_flash_write(input: &[u8]) -> IResult<&[u8], VPacket> {
+    let (input, _) = tag("FlashWrite:")(input)?;
+
+    let (input, address) = hex_u32(input)?;
+    let (input, _) = char(':')(input)?;
+    let (input, data) = rest(input)?;
+
+    Ok((
+        input,
+        VPacket::FlashWrite {
+            address,
+            data: data.to_owned(),
+        },
+    ))
+}
+
+fn v_flash_done(input: &[u8]) -> IResult<&[u8], VPacket> {
+    value(VPacket::FlashDone, tag("FlashDone"))(input)
+}
+
+fn v_stopped(input: &[u8]) -> IResult<&[u8], VPacket> {
+    value(VPacket::Stopped, tag("Stopped"))(input)
+}
+
+fn v_kill(input: &[u8]) -> IResult<&[u8], VPacket> {
+    let (input, _) = tag("Kill;")(input)?;
+
+    let (input, pid) = pid(input)?;
+
+    Ok((input, VPacket::Kill(pid)))
+}
+
+fn v_run(input: &[u8]) -> IResult<&[u8], VPacket> {
+    let (input, _) = tag("Run")(input)?;
+    let (input, _) = rest(input)?;
+
+    Ok((input, VPacket::Run))
 }
 
 fn v_cont_action(input: &[u8]) -> IResult<&[u8], Action> {
@@ -63,9 +157,19 @@ fn v_cont_action(input: &[u8]) -> IResult<&[u8], Action> {
         value(Action::Continue, char('c')),
         value(Action::Step, char('s')),
         value(Action::Stop, char('t')),
+        v_cont_range_step,
     ))(input)
 }
 
+fn v_cont_range_step(input: &[u8]) -> IResult<&[u8], Action> {
+    let (input, _) = char('r')(input)?;
+    let (input, start) = hex_u32(input)?;
+    let (input, _) = char(',')(input)?;
+    let (input, end) = hex_u32(input)?;
+
+    Ok((input, Action::RangeStep { start, end }))
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -77,6 +181,20 @@ mod test {
         assert_eq!(v_packet(b"Attach;7").unwrap(), (EMPTY, VPacket::Attach(7)));
     }
 
+    #[test]
+    fn parse_v_kill() {
+        assert_eq!(v_packet(b"Kill;2a").unwrap(), (EMPTY, VPacket::Kill(0x2a)));
+    }
+
+    #[test]
+    fn parse_v_run() {
+        assert_eq!(
+            v_packet(b"Run;6669726d77617265;").unwrap(),
+            (EMPTY, VPacket::Run)
+        );
+        assert_eq!(v_packet(b"Run;").unwrap(), (EMPTY, VPacket::Run));
+    }
+
     #[test]
     fn parse_v_cont_support() {
         assert_eq!(
@@ -89,7 +207,7 @@ mod test {
     fn parse_v_cont_cont() {
         assert_eq!(
             v_packet(b"Cont;c").unwrap(),
-            (EMPTY, VPacket::Continue(Action::Continue))
+            (EMPTY, VPacket::Continue(vec![(Action::Continue, None)]))
         );
     }
 
@@ -97,15 +215,87 @@ mod test {
     fn parse_v_cont_step() {
         assert_eq!(
             v_packet(b"Cont;s").unwrap(),
-            (EMPTY, VPacket::Continue(Action::Step))
+            (EMPTY, VPacket::Continue(vec![(Action::Step, None)]))
+        );
+    }
+
+    #[test]
+    fn parse_v_cont_range_step() {
+        assert_eq!(
+            v_packet(b"Cont;r8000100,8000110:1;c").unwrap(),
+            (
+                EMPTY,
+                VPacket::Continue(vec![
+                    (
+                        Action::RangeStep {
+                            start: 0x0800_0100,
+                            end: 0x0800_0110
+                        },
+                        Some(ThreadId::Id(1))
+                    ),
+                    (Action::Continue, None)
+                ])
+            )
+        );
+    }
+
+    #[test]
+    fn parse_v_flash_erase() {
+        assert_eq!(
+            v_packet(b"FlashErase:08000000,4000").unwrap(),
+            (
+                EMPTY,
+                VPacket::FlashErase {
+                    address: 0x0800_0000,
+                    length: 0x4000
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn parse_v_flash_write() {
+        assert_eq!(
+            v_packet(b"FlashWrite:8000100:\x00:#").unwrap(),
+            (
+                EMPTY,
+                VPacket::FlashWrite {
+                    address: 0x0800_0100,
+                    data: b"\x00:#".to_vec()
+                }
+            )
         );
     }
 
+    #[test]
+    fn parse_v_flash_done() {
+        assert_eq!(v_packet(b"FlashDone").unwrap(), (EMPTY, VPacket::FlashDone));
+    }
+
+    #[test]
+    fn parse_v_stopped() {
+        assert_eq!(v_packet(b"Stopped").unwrap(), (EMPTY, VPacket::Stopped));
+    }
+
     #[test]
     fn parse_v_cont_stop() {
         assert_eq!(
             v_packet(b"Cont;t").unwrap(),
-            (EMPTY, VPacket::Continue(Action::Stop))
+            (EMPTY, VPacket::Continue(vec![(Action::Stop, None)]))
+        );
+    }
+
+    #[test]
+    fn parse_v_cont_with_threads() {
+        assert_eq!(
+            v_packet(b"Cont;s:2;c").unwrap(),
+            (
+                EMPTY,
+                VPacket::Continue(vec![
+                    (Action::Step, Some(ThreadId::Id(2))),
+                    (Action::Continue, None)
+                ])
+            )
         );
     }
 }
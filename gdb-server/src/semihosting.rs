@@ -0,0 +1,441 @@
+//! Forwarding of ARM semihosting calls to GDB with the File-I/O extension.
+//!
+//! When a core halts on a `BKPT 0xAB` instruction, the semihosting operation in `R0` is
+//! translated into an `F` packet. GDB executes the system call on the host and replies
+//! with an `F` packet, whose result is written back to `R0` before the core is resumed.
+//!
+//! Only GDB implements the File-I/O extension, so the forwarding is only enabled if the
+//! client identifies as GDB in its `qSupported` packet.
+//!
+//! If the server was started with a root directory for semihosting, the file operations
+//! are executed on the host instead, see [HostFiles], with any client.
+
+use crate::host_files::HostFiles;
+use probe_rs::{Architecture, Core, CoreRegisterAddress, Error, MemoryInterface};
+use std::path::Path;
+
+/// The `BKPT 0xAB` instruction, as it is stored in memory.
+const SEMIHOSTING_BREAKPOINT: [u8; 2] = [0xAB, 0xBE];
+
+pub(crate) const SYS_OPEN: u32 = 0x01;
+pub(crate) const SYS_CLOSE: u32 = 0x02;
+pub(crate) const SYS_WRITEC: u32 = 0x03;
+pub(crate) const SYS_WRITE0: u32 = 0x04;
+pub(crate) const SYS_WRITE: u32 = 0x05;
+pub(crate) const SYS_READ: u32 = 0x06;
+pub(crate) const SYS_ISTTY: u32 = 0x09;
+pub(crate) const SYS_SEEK: u32 = 0x0A;
+pub(crate) const SYS_FLEN: u32 = 0x0C;
+pub(crate) const SYS_REMOVE: u32 = 0x0E;
+pub(crate) const SYS_RENAME: u32 = 0x0F;
+pub(crate) const SYS_SYSTEM: u32 = 0x12;
+pub(crate) const SYS_ERRNO: u32 = 0x13;
+
+/// The permissions of files created with `SYS_OPEN`, `0644`.
+const OPEN_MODE: u32 = 0o644;
+
+/// The longest string which is written with `SYS_WRITE0`.
+pub(crate) const MAX_STRING_LENGTH: u32 = 4096;
+
+/// The File-I/O state of a GDB connection.
+#[derive(Debug, Default)]
+pub(crate) struct Semihosting {
+    /// `true` if the client supports the File-I/O extension.
+    enabled: bool,
+    /// The call GDB is executing, and the core which made it.
+    pending: Option<(usize, Operation)>,
+    /// The error of the last call, as returned by `SYS_ERRNO`.
+    errno: u32,
+    /// The files which are opened on the host, if there is a root directory for them.
+    host: Option<HostFiles>,
+}
+
+/// What happened to a halt of a core.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Halt {
+    /// The core did not halt for a semihosting call, or the call is not forwarded.
+    Stopped,
+    /// The call was completed without GDB and the core was resumed.
+    Resumed,
+    /// The call has to be sent to GDB with this `F` packet.
+    Forward(String),
+}
+
+/// A semihosting operation which is executed by GDB.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operation {
+    /// An operation which returns the result of the system call.
+    Call,
+    /// `SYS_WRITE` and `SYS_READ` of `length` bytes, which return the number of bytes not transferred.
+    Transfer { length: u32 },
+    /// `SYS_SEEK`, which returns 0 on success.
+    Seek,
+    /// `SYS_WRITEC` and `SYS_WRITE0`, which do not return anything.
+    Output,
+}
+
+/// How a semihosting call is handled.
+#[derive(Debug, PartialEq)]
+enum Call {
+    /// The call is completed with this value in `R0`.
+    Local(u32),
+    /// The call is sent to GDB with the `packet`.
+    Forward {
+        operation: Operation,
+        packet: String,
+    },
+}
+
+impl Semihosting {
+    /// Creates the semihosting state, with the file operations executed in `root` if it is given.
+    pub(crate) fn new(root: Option<&Path>) -> Self {
+        Self {
+            host: root.map(|root| HostFiles::new(root.to_owned())),
+            ..Self::default()
+        }
+    }
+
+    /// Invalidates the files the target opened on the host, when the target is reset.
+    pub(crate) fn target_reset(&mut self) {
+        if let Some(host) = &mut self.host {
+            host.reset();
+        }
+    }
+
+    /// Enables the forwarding if the client which sent the `qSupported` `features` supports File-I/O.
+    pub(crate) fn set_client_features(&mut self, features: &[String]) {
+        // GDB always announces qRelocInsn, which no other client implements.
+        self.enabled = features.iter().any(|feature| feature == "qRelocInsn+");
+        log::debug!("Semihosting over File-I/O enabled: {}", self.enabled);
+    }
+
+    /// The index of the core which waits for the result of a call.
+    pub(crate) fn pending_core(&self) -> Option<usize> {
+        self.pending.map(|(core, _)| core)
+    }
+
+    /// Handles a halt of the core `core`, which has the index `index`.
+    pub(crate) fn halted(&mut self, core: &mut Core, index: usize) -> Result<Halt, Error> {
+        if !(self.enabled || self.host.is_some()) || core.architecture() != Architecture::Arm {
+            return Ok(Halt::Stopped);
+        }
+
+        let pc = core.read_core_reg(core.registers().program_counter())?;
+        let mut instruction = [0; 2];
+        core.read_8(pc, &mut instruction)?;
+        if instruction != SEMIHOSTING_BREAKPOINT {
+            return Ok(Halt::Stopped);
+        }
+
+        let operation = core.read_core_reg(CoreRegisterAddress(0))?;
+        let parameters = core.read_core_reg(CoreRegisterAddress(1))?;
+
+        if let Some(host) = &mut self.host {
+            if let Some(result) = host.call(operation, parameters, &mut *core)? {
+                complete(core, result)?;
+                core.run()?;
+                return Ok(Halt::Resumed);
+            }
+        }
+
+        if !self.enabled {
+            log::debug!("Semihosting operation {:#x} is not supported", operation);
+            return Ok(Halt::Stopped);
+        }
+
+        let call = decode(operation, parameters, self.errno, |address, data| {
+            core.read_8(address, data)
+        })?;
+
+        match call {
+            None => {
+                log::debug!("Semihosting operation {:#x} is not supported", operation);
+                Ok(Halt::Stopped)
+            }
+            Some(Call::Local(result)) => {
+                complete(core, result)?;
+                core.run()?;
+                Ok(Halt::Resumed)
+            }
+            Some(Call::Forward { operation, packet }) => {
+                self.pending = Some((index, operation));
+                Ok(Halt::Forward(packet))
+            }
+        }
+    }
+
+    /// Completes the pending call on `core` with the reply of GDB.
+    pub(crate) fn reply(
+        &mut self,
+        core: &mut Core,
+        result: i64,
+        errno: Option<u32>,
+    ) -> Result<(), Error> {
+        let operation = match self.pending.take() {
+            Some((_, operation)) => operation,
+            None => {
+                log::warn!("Received a File-I/O reply without a pending call");
+                return Ok(());
+            }
+        };
+
+        if let Some(errno) = errno {
+            self.errno = errno;
+        }
+
+        complete(core, return_value(operation, result))
+    }
+}
+
+/// Writes the `result` of a call to `R0` and moves the PC behind the `BKPT` instruction.
+fn complete(core: &mut Core, result: u32) -> Result<(), Error> {
+    let pc_register: CoreRegisterAddress = core.registers().program_counter().into();
+    let pc = core.read_core_reg(pc_register)?;
+
+    core.write_core_reg(CoreRegisterAddress(0), result)?;
+    core.write_core_reg(pc_register, pc + 2)
+}
+
+/// Returns the value of `R0` for the `result` GDB returned.
+fn return_value(operation: Operation, result: i64) -> u32 {
+    match operation {
+        Operation::Call | Operation::Output => result as u32,
+        Operation::Transfer { length } if result < 0 => length,
+        Operation::Transfer { length } => length.saturating_sub(result as u32),
+        Operation::Seek if result < 0 => -1i32 as u32,
+        Operation::Seek => 0,
+    }
+}
+
+/// Returns the GDB File-I/O flags for the `fopen` mode of `SYS_OPEN`.
+fn open_flags(mode: u32) -> Option<u32> {
+    // The modes come in pairs, with and without the binary flag.
+    match mode / 2 {
+        // r: O_RDONLY
+        0 => Some(0x000),
+        // r+: O_RDWR
+        1 => Some(0x002),
+        // w: O_WRONLY | O_CREAT | O_TRUNC
+        2 => Some(0x601),
+        // w+: O_RDWR | O_CREAT | O_TRUNC
+        3 => Some(0x602),
+        // a: O_WRONLY | O_CREAT | O_APPEND
+        4 => Some(0x209),
+        // a+: O_RDWR | O_CREAT | O_APPEND
+        5 => Some(0x20A),
+        _ => None,
+    }
+}
+
+/// Translates the semihosting `operation` with the parameter block at `parameters` into a call.
+///
+/// Returns `None` for operations which can not be forwarded.
+fn decode(
+    operation: u32,
+    parameters: u32,
+    errno: u32,
+    mut read: impl FnMut(u32, &mut [u8]) -> Result<(), Error>,
+) -> Result<Option<Call>, Error> {
+    let mut parameter = |index: u32| -> Result<u32, Error> {
+        let mut word = [0; 4];
+        read(parameters + index * 4, &mut word)?;
+        Ok(u32::from_le_bytes(word))
+    };
+
+    let forward = |operation: Operation, packet: String| -> Result<Option<Call>, Error> {
+        Ok(Some(Call::Forward { operation, packet }))
+    };
+
+    match operation {
+        SYS_OPEN => {
+            let (name, mode, length) = (parameter(0)?, parameter(1)?, parameter(2)?);
+
+            // The special file ":tt" is the console: stdin, stdout or stderr depending on the mode.
+            let mut special = [0; 3];
+            if length == 3 {
+                read(name, &mut special)?;
+            }
+            if &special == b":tt" {
+                return Ok(Some(Call::Local(mode / 4)));
+            }
+
+            let flags = match open_flags(mode) {
+                Some(flags) => flags,
+                None => return Ok(Some(Call::Local(-1i32 as u32))),
+            };
+
+            forward(
+                Operation::Call,
+                format!(
+                    "Fopen,{:x}/{:x},{:x},{:x}",
+                    name,
+                    length + 1,
+                    flags,
+                    OPEN_MODE
+                ),
+            )
+        }
+        SYS_CLOSE => {
+            let handle = parameter(0)?;
+
+            // The console is not closed.
+            if handle <= 2 {
+                return Ok(Some(Call::Local(0)));
+            }
+
+            forward(Operation::Call, format!("Fclose,{:x}", handle))
+        }
+        SYS_WRITEC => forward(Operation::Output, format!("Fwrite,1,{:x},1", parameters)),
+        SYS_WRITE0 => {
+            let mut length = 0;
+            let mut byte = [0];
+            while length < MAX_STRING_LENGTH {
+                read(parameters + length, &mut byte)?;
+                if byte[0] == 0 {
+                    break;
+                }
+                length += 1;
+            }
+
+            forward(
+                Operation::Output,
+                format!("Fwrite,1,{:x},{:x}", parameters, length),
+            )
+        }
+        SYS_WRITE | SYS_READ => {
+            let (handle, buffer, length) = (parameter(0)?, parameter(1)?, parameter(2)?);
+            let call = if operation == SYS_WRITE {
+                "Fwrite"
+            } else {
+                "Fread"
+            };
+
+            forward(
+                Operation::Transfer { length },
+                format!("{},{:x},{:x},{:x}", call, handle, buffer, length),
+            )
+        }
+        SYS_ISTTY => forward(Operation::Call, format!("Fisatty,{:x}", parameter(0)?)),
+        SYS_SEEK => {
+            let (handle, position) = (parameter(0)?, parameter(1)?);
+            forward(
+                Operation::Seek,
+                format!("Flseek,{:x},{:x},0", handle, position),
+            )
+        }
+        SYS_REMOVE => {
+            let (name, length) = (parameter(0)?, parameter(1)?);
+            forward(
+                Operation::Call,
+                format!("Funlink,{:x}/{:x}", name, length + 1),
+            )
+        }
+        SYS_RENAME => {
+            let (old, old_length) = (parameter(0)?, parameter(1)?);
+            let (new, new_length) = (parameter(2)?, parameter(3)?);
+            forward(
+                Operation::Call,
+                format!(
+                    "Frename,{:x}/{:x},{:x}/{:x}",
+                    old,
+                    old_length + 1,
+                    new,
+                    new_length + 1
+                ),
+            )
+        }
+        SYS_SYSTEM => {
+            let (command, length) = (parameter(0)?, parameter(1)?);
+            forward(
+                Operation::Call,
+                format!("Fsystem,{:x}/{:x}", command, length + 1),
+            )
+        }
+        SYS_ERRNO => Ok(Some(Call::Local(errno))),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, return_value, Call, Operation};
+    use std::collections::HashMap;
+
+    /// Decodes `operation` with the parameter block at 0x2000_0000, which contains `parameters`.
+    fn decode_with(operation: u32, parameters: &[u32], memory: &[(u32, &[u8])]) -> Option<Call> {
+        let mut bytes = HashMap::new();
+        for (n, parameter) in parameters.iter().enumerate() {
+            for (i, byte) in parameter.to_le_bytes().iter().enumerate() {
+                bytes.insert(0x2000_0000 + (n * 4 + i) as u32, *byte);
+            }
+        }
+        for (address, data) in memory {
+            for (i, byte) in data.iter().enumerate() {
+                bytes.insert(address + i as u32, *byte);
+            }
+        }
+
+        decode(operation, 0x2000_0000, 2, |address, data| {
+            for (i, byte) in data.iter_mut().enumerate() {
+                *byte = bytes[&(address + i as u32)];
+            }
+            Ok(())
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn files_are_opened_with_gdb_flags() {
+        assert_eq!(
+            decode_with(0x01, &[0x2000_0100, 5, 8], &[(0x2000_0100, b"test.txt")]),
+            Some(Call::Forward {
+                operation: Operation::Call,
+                packet: "Fopen,20000100/9,601,1a4".into()
+            })
+        );
+    }
+
+    #[test]
+    fn console_is_opened_locally() {
+        assert_eq!(
+            decode_with(0x01, &[0x2000_0100, 4, 3], &[(0x2000_0100, b":tt")]),
+            Some(Call::Local(1))
+        );
+        assert_eq!(
+            decode_with(0x01, &[0x2000_0100, 8, 3], &[(0x2000_0100, b":tt")]),
+            Some(Call::Local(2))
+        );
+    }
+
+    #[test]
+    fn write0_writes_to_the_console() {
+        assert_eq!(
+            decode_with(0x04, &[], &[(0x2000_0000, b"hello\0")]),
+            Some(Call::Forward {
+                operation: Operation::Output,
+                packet: "Fwrite,1,20000000,5".into()
+            })
+        );
+    }
+
+    #[test]
+    fn transfers_return_the_remaining_bytes() {
+        assert_eq!(
+            decode_with(0x06, &[3, 0x2000_0100, 16], &[]),
+            Some(Call::Forward {
+                operation: Operation::Transfer { length: 16 },
+                packet: "Fread,3,20000100,10".into()
+            })
+        );
+
+        assert_eq!(return_value(Operation::Transfer { length: 16 }, 10), 6);
+        assert_eq!(return_value(Operation::Transfer { length: 16 }, -1), 16);
+        assert_eq!(return_value(Operation::Seek, 100), 0);
+    }
+
+    #[test]
+    fn errno_is_returned_locally() {
+        assert_eq!(decode_with(0x13, &[], &[]), Some(Call::Local(2)));
+        assert_eq!(decode_with(0x18, &[], &[]), None);
+    }
+}
@@ -0,0 +1,192 @@
+use crate::parser::{ThreadId, ThreadOperation};
+use probe_rs::debug::{RtosError, RtosProvider, RtosThread};
+use probe_rs::{CoreRegisterAddress, Session};
+
+/// The threads which are presented to GDB.
+///
+/// Each core of the session is a thread. If an RTOS provider is set and the RTOS is running,
+/// the threads of the RTOS on the first core are presented instead, identified by their RTOS ids.
+pub(crate) struct Threads<'a> {
+    rtos: Option<&'a dyn RtosProvider>,
+    /// The core which is used for register and memory accesses.
+    selected_core: usize,
+    /// The RTOS threads, as they were found when the target halted the last time.
+    rtos_threads: Vec<RtosThread>,
+    /// The RTOS thread GDB selected, if it is not the running one.
+    selected_rtos_thread: Option<u32>,
+}
+
+impl<'a> Threads<'a> {
+    pub(crate) fn new(rtos: Option<&'a dyn RtosProvider>) -> Self {
+        Self {
+            rtos,
+            selected_core: 0,
+            rtos_threads: vec![],
+            selected_rtos_thread: None,
+        }
+    }
+
+    /// The core which is used for register and memory accesses.
+    pub(crate) fn selected_core(&self) -> usize {
+        self.selected_core
+    }
+
+    /// Updates the threads after the target halted, because of the core `core`.
+    pub(crate) fn halted(&mut self, session: &mut Session, core: usize) {
+        self.selected_core = core;
+        self.selected_rtos_thread = None;
+        self.refresh(session);
+    }
+
+    /// Reads the RTOS threads again, without changing the selected thread.
+    ///
+    /// In non-stop mode, GDB keeps its selection when a thread stops.
+    pub(crate) fn refresh(&mut self, session: &mut Session) {
+        self.rtos_threads.clear();
+
+        if let Some(rtos) = self.rtos {
+            let threads = session
+                .core(0)
+                .map_err(RtosError::from)
+                .and_then(|mut core| rtos.threads(&mut core));
+
+            match threads {
+                Ok(threads) => self.rtos_threads = threads,
+                Err(e) => log::warn!("Unable to read the {} threads: {}", rtos.name(), e),
+            }
+        }
+    }
+
+    /// Returns the GDB thread id of the thread which is running on the selected core.
+    pub(crate) fn current_id(&self) -> u32 {
+        self.thread_of(self.selected_core)
+    }
+
+    /// Returns the GDB thread id of the thread which is running on the core `core`.
+    pub(crate) fn thread_of(&self, core: usize) -> u32 {
+        self.rtos_threads
+            .iter()
+            .find(|thread| thread.is_current && core == 0)
+            .map(|thread| thread.id)
+            .unwrap_or_else(|| core_thread_id(core))
+    }
+
+    /// Returns the index of the core the thread `id` runs on.
+    pub(crate) fn core_of(&self, session: &Session, id: u32) -> Option<usize> {
+        if self.rtos_threads.iter().any(|thread| thread.id == id) {
+            return Some(0);
+        }
+
+        let index = id.checked_sub(1)? as usize;
+        if self.rtos_threads.is_empty() && index < session.list_cores().len() {
+            Some(index)
+        } else {
+            None
+        }
+    }
+
+    /// Selects the thread `thread` for the `operation`, returns `false` if it does not exist.
+    pub(crate) fn select(
+        &mut self,
+        session: &Session,
+        operation: ThreadOperation,
+        thread: ThreadId,
+    ) -> bool {
+        let id = match thread {
+            ThreadId::All | ThreadId::Any => return true,
+            ThreadId::Id(id) => id,
+        };
+
+        let core = match self.core_of(session, id) {
+            Some(core) => core,
+            None => return false,
+        };
+
+        // All threads are resumed together in all-stop mode,
+        // so only the thread for the other operations is relevant.
+        if operation == ThreadOperation::General {
+            self.selected_core = core;
+            self.selected_rtos_thread = self
+                .rtos_threads
+                .iter()
+                .find(|thread| thread.id == id && !thread.is_current)
+                .map(|thread| thread.id);
+        }
+
+        true
+    }
+
+    /// Returns `true` if the thread `thread` exists.
+    pub(crate) fn is_alive(&self, session: &Session, thread: ThreadId) -> bool {
+        match thread {
+            ThreadId::All | ThreadId::Any => true,
+            ThreadId::Id(id) => self.core_of(session, id).is_some(),
+        }
+    }
+
+    /// Returns the registers of the selected thread, if it is an RTOS thread which is not running.
+    pub(crate) fn saved_registers(&self) -> Option<&[(CoreRegisterAddress, u32)]> {
+        let id = self.selected_rtos_thread?;
+
+        self.rtos_threads
+            .iter()
+            .find(|thread| thread.id == id)
+            .map(|thread| thread.registers.as_slice())
+    }
+
+    /// Returns the thread list in the format of `qXfer:threads:read`.
+    pub(crate) fn xml(&self, session: &Session) -> String {
+        let mut threads = r#"<?xml version="1.0"?>
+<threads>
+"#
+        .to_owned();
+
+        if self.rtos_threads.is_empty() {
+            for (n, core_type) in session.list_cores() {
+                threads.push_str(&format!(
+                    "<thread id=\"{:x}\" core=\"{}\" name=\"Core {} ({:?})\"></thread>\n",
+                    core_thread_id(n),
+                    n,
+                    n,
+                    core_type
+                ));
+            }
+        } else {
+            for thread in &self.rtos_threads {
+                threads.push_str(&format!(
+                    "<thread id=\"{:x}\" core=\"0\" name=\"{}\"></thread>\n",
+                    thread.id,
+                    escape_xml(&thread.name)
+                ));
+            }
+        }
+
+        threads.push_str("</threads>");
+        threads
+    }
+}
+
+/// Returns the GDB thread id of the core with the index `core`.
+///
+/// Thread ids start at 1, as 0 means any thread.
+fn core_thread_id(core: usize) -> u32 {
+    core as u32 + 1
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::escape_xml;
+
+    #[test]
+    fn thread_names_are_escaped() {
+        assert_eq!(escape_xml("Tmr Svc"), "Tmr Svc");
+        assert_eq!(escape_xml("<a&\"b\">"), "&lt;a&amp;&quot;b&quot;&gt;");
+    }
+}
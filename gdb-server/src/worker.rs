@@ -4,57 +4,111 @@ use futures::channel::mpsc;
 use futures::future::FutureExt;
 use futures::select;
 use gdb_protocol::packet::{CheckedPacket, Kind as PacketKind};
-use probe_rs::Session;
+use probe_rs::debug::{RangeStep, RangeStepStatus};
+use probe_rs::SharedSession;
 use std::convert::TryFrom;
-use std::{sync::Mutex, time::Duration};
+use std::path::Path;
+use std::time::Duration;
 
 use crate::parser::parse_packet;
 
 use crate::handlers;
+use crate::non_stop::NonStop;
+use crate::semihosting::{Halt, Semihosting};
+use crate::threads::Threads;
+use crate::ServerOptions;
 
 type ServerResult<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
 type Sender<T> = mpsc::UnboundedSender<T>;
 type Receiver<T> = mpsc::UnboundedReceiver<T>;
 
-pub async fn worker(
+/// The state of a GDB connection.
+pub(crate) struct State<'a> {
+    /// `true` while the target runs in all-stop mode, until it halts.
+    awaits_halt: bool,
+    /// The data GDB sent with `vFlashWrite`, which is programmed on `vFlashDone`.
+    flash_data: Vec<(u32, Vec<u8>)>,
+    threads: Threads<'a>,
+    semihosting: Semihosting,
+    non_stop: NonStop,
+    /// `true` after GDB enabled the extended mode with `!`, e.g. with `target extended-remote`.
+    extended: bool,
+    /// The image which is programmed again when the program is restarted in extended mode.
+    restart_image: Option<&'a Path>,
+    /// The range step of `vCont;r` which is in progress, with the index of its core.
+    range_step: Option<(usize, RangeStep)>,
+    /// `true` after `monitor read-side-effects on`, which lets GDB read regions with side effects on reads.
+    read_side_effects: bool,
+}
+
+pub(crate) async fn worker(
     mut input_stream: Receiver<CheckedPacket>,
     output_stream: Sender<CheckedPacket>,
-    session: &Mutex<Session>,
+    session: &SharedSession,
+    options: ServerOptions<'_>,
 ) -> ServerResult<()> {
     // When we first attach to the core, GDB expects us to halt the core, so we do this here when a new client connects.
     // If the core is already halted, nothing happens if we issue a halt command again, so we always do this no matter of core state.
-    session
-        .lock()
-        .unwrap()
-        .core(0)?
-        .halt(Duration::from_millis(100))?;
+    // A target which was attached without halting it keeps running, until GDB is used to halt it.
+    let mut threads = Threads::new(options.rtos);
+    {
+        let mut session = session.lock();
+        if !session.is_hot_attached() {
+            session.core(0)?.halt(Duration::from_millis(100))?;
+        }
+        if session.core(0)?.core_halted()? {
+            threads.halted(&mut session, 0);
+        }
+    }
 
-    let mut awaits_halt = false;
+    let mut state = State {
+        awaits_halt: false,
+        flash_data: vec![],
+        threads,
+        semihosting: Semihosting::new(options.semihosting_root),
+        non_stop: NonStop::new(),
+        extended: false,
+        restart_image: options.restart_image,
+        range_step: None,
+        read_side_effects: false,
+    };
 
     loop {
         select! {
             potential_packet = input_stream.next().fuse() => {
                 if let Some(packet) = potential_packet {
                     log::warn!("WORKING {}", String::from_utf8_lossy(&packet.data));
-                    if handler(&session, &output_stream, &mut awaits_halt, packet).await? {
+                    if handler(&session, &output_stream, &mut state, packet).await? {
                         break;
                     }
                 } else {
                     break
                 }
             },
-            _ = await_halt(session, &output_stream, &mut awaits_halt).fuse() => {}
+            _ = await_halt(session, &output_stream, &mut state).fuse() => {}
         }
     }
     Ok(())
 }
 
-pub async fn handler(
-    session: &Mutex<Session>,
+pub(crate) async fn handler(
+    session: &SharedSession,
     output_stream: &Sender<CheckedPacket>,
-    awaits_halt: &mut bool,
+    state: &mut State<'_>,
     packet: CheckedPacket,
 ) -> ServerResult<bool> {
+    let State {
+        awaits_halt,
+        flash_data,
+        threads,
+        semihosting,
+        non_stop,
+        extended,
+        restart_image,
+        range_step,
+        read_side_effects,
+    } = state;
+
     let parsed_packet = parse_packet(&packet.data);
     let mut break_due = false;
 
@@ -67,55 +121,166 @@ pub async fn handler(
     let response: Option<String> = match parsed_packet {
         Ok(parsed_packet) => {
             log::debug!("Parsed packet: {:?}", parsed_packet);
-            let mut session = session.lock().expect("Poisoned Mutex");
+            let mut session = session.lock();
             match parsed_packet {
-                HaltReason => handlers::halt_reason(),
-                Continue => handlers::run(session.core(0)?, awaits_halt),
+                HaltReason if non_stop.enabled() => {
+                    handlers::stop_replies(&mut session, threads, non_stop)
+                }
+                HaltReason if session.is_hot_attached() => {
+                    handlers::hot_halt_reason(&mut session, threads)
+                }
+                HaltReason => handlers::halt_reason(threads),
+                NonStop(enabled) => handlers::set_non_stop(non_stop, enabled),
+                EnableExtendedMode => handlers::enable_extended_mode(extended),
+                // GDB does not expect a reply to `R`.
+                Restart if *extended => {
+                    semihosting.target_reset();
+                    handlers::restart(&mut session, threads, *restart_image, awaits_halt);
+                    None
+                }
+                V(VPacket::Run) if *extended => {
+                    semihosting.target_reset();
+                    handlers::run_program(&mut session, threads, *restart_image, awaits_halt)
+                }
+                V(VPacket::Kill(_)) if *extended => {
+                    semihosting.target_reset();
+                    handlers::kill(&mut session, threads, awaits_halt)
+                }
+                // In extended mode, the connection stays open after `k`, and the program can be started again.
+                KillRequest if *extended => {
+                    semihosting.target_reset();
+                    handlers::kill(&mut session, threads, awaits_halt);
+                    None
+                }
+                KillRequest => {
+                    break_due = true;
+                    None
+                }
+                V(VPacket::Stopped) => handlers::next_stop(non_stop),
+                // In all-stop mode, all cores are resumed.
+                Continue => handlers::resume(
+                    &mut session,
+                    &[(Action::Continue, None)],
+                    threads,
+                    awaits_halt,
+                    range_step,
+                ),
+                SelectThread { operation, thread } => {
+                    handlers::select_thread(&session, threads, operation, thread)
+                }
+                ThreadAlive(thread) => handlers::thread_alive(&session, threads, thread),
+                Query(QueryPacket::ThreadId) => handlers::current_thread(threads),
                 V(VPacket::QueryContSupport) => handlers::vcont_supported(),
-                Query(QueryPacket::Supported { .. }) => handlers::q_supported(),
+                V(VPacket::FlashErase { address, length }) => {
+                    handlers::flash_erase(address, length)
+                }
+                V(VPacket::FlashWrite { address, data }) => {
+                    handlers::flash_write(address, data, flash_data)
+                }
+                V(VPacket::FlashDone) => handlers::flash_done(&mut session, flash_data),
+                Query(QueryPacket::Supported(features)) => {
+                    semihosting.set_client_features(&features);
+                    handlers::q_supported()
+                }
+                FileIO {
+                    result,
+                    errno,
+                    interrupted,
+                } => handlers::file_io_reply(
+                    &mut session,
+                    semihosting,
+                    threads,
+                    result,
+                    errno,
+                    interrupted,
+                    awaits_halt,
+                ),
                 Query(QueryPacket::Attached { .. }) => handlers::q_attached(),
-                Query(QueryPacket::Command(cmd)) => {
-                    if cmd == b"reset" {
-                        handlers::reset_halt(session.core(0)?)
-                    } else {
+                Query(QueryPacket::Command(cmd)) => match cmd.as_slice() {
+                    b"reset" | b"reset halt" => {
+                        semihosting.target_reset();
+                        handlers::reset_halt(session.core(threads.selected_core())?)
+                    }
+                    b"reset run" => {
+                        semihosting.target_reset();
+                        handlers::reset_run(session.core(threads.selected_core())?)
+                    }
+                    b"read-side-effects on" | b"read-side-effects off" => {
+                        *read_side_effects = cmd.ends_with(b"on");
+                        Some("OK".into())
+                    }
+                    _ => {
                         log::debug!("Unknown monitor command: '{:?}'", cmd);
                         Some(hex::encode(
-                            "Unknown monitor command\nOnly 'reset' is currently supported\n"
+                            "Unknown monitor command\n\
+                            Only 'reset', 'reset halt', 'reset run' and \
+                            'read-side-effects on|off' are currently supported\n"
                                 .as_bytes(),
                         ))
                     }
-                }
+                },
                 Query(QueryPacket::HostInfo) => handlers::host_info(),
-                ReadGeneralRegister => handlers::read_general_registers(session.core(0)?),
-                ReadRegisterHex(register) => handlers::read_register(register, session.core(0)?),
+                Query(QueryPacket::Crc { address, length }) => {
+                    handlers::memory_crc(address, length, session.core(threads.selected_core())?)
+                }
+                Query(QueryPacket::Search {
+                    address,
+                    length,
+                    pattern,
+                }) => handlers::search_memory(
+                    address,
+                    length,
+                    &pattern,
+                    session.core(threads.selected_core())?,
+                ),
+                ReadGeneralRegister => handlers::read_general_registers(
+                    session.core(threads.selected_core())?,
+                    threads.saved_registers(),
+                ),
+                ReadRegisterHex(register) => handlers::read_register(
+                    register,
+                    session.core(threads.selected_core())?,
+                    threads.saved_registers(),
+                ),
+                WriteRegisterHex { .. } if threads.saved_registers().is_some() => {
+                    log::warn!("The registers of a suspended RTOS thread can not be modified.");
+                    Some("E01".into())
+                }
+                WriteRegisterHex { register, value } => handlers::write_register(
+                    register,
+                    &value,
+                    session.core(threads.selected_core())?,
+                ),
                 ReadMemory { address, length } => {
                     // LLDB will send 64 bit addresses, which are not supported by probe-rs
                     // yet.
 
                     if let Ok(address) = u32::try_from(address) {
-                        handlers::read_memory(address, length, session.core(0)?)
+                        handlers::read_memory(
+                            address,
+                            length,
+                            session.core(threads.selected_core())?,
+                            *read_side_effects,
+                        )
                     } else {
                         //
                         handlers::reply_empty()
                     }
                 }
                 Detach => handlers::detach(&mut break_due),
-                V(VPacket::Continue(action)) => match action {
-                    Action::Continue => handlers::run(session.core(0)?, awaits_halt),
-                    Action::Stop => handlers::stop(session.core(0)?, awaits_halt),
-                    Action::Step => handlers::step(session.core(0)?, awaits_halt),
-                    other => {
-                        log::warn!("vCont with action {:?} not supported", other);
-                        handlers::reply_empty()
-                    }
-                },
+                V(VPacket::Continue(actions)) if non_stop.enabled() => {
+                    handlers::resume_non_stop(&mut session, &actions, threads, non_stop)
+                }
+                V(VPacket::Continue(actions)) => {
+                    handlers::resume(&mut session, &actions, threads, awaits_halt, range_step)
+                }
                 InsertBreakpoint {
                     breakpoint_type,
                     address,
                     kind,
                 } => match breakpoint_type {
                     BreakpointType::Hardware => {
-                        handlers::insert_hardware_break(address, kind, session.core(0)?)
+                        handlers::insert_hardware_break(address, kind, &mut session)
                     }
                     other => {
                         log::warn!("Breakpoint type {:?} is not supported.", other);
@@ -128,7 +293,7 @@ pub async fn handler(
                     kind,
                 } => match breakpoint_type {
                     BreakpointType::Hardware => {
-                        handlers::remove_hardware_break(address, kind, session.core(0)?)
+                        handlers::remove_hardware_break(address, kind, &mut session)
                     }
                     other => {
                         log::warn!("Breakpoint type {:?} is not supported.", other);
@@ -136,7 +301,7 @@ pub async fn handler(
                     }
                 },
                 WriteMemoryBinary { address, data } => {
-                    handlers::write_memory(address, &data, session.core(0)?)
+                    handlers::write_memory(address, &data, session.core(threads.selected_core())?)
                 }
                 Query(QueryPacket::Transfer { object, operation }) => {
                     use crate::parser::query::TransferOperation;
@@ -144,8 +309,8 @@ pub async fn handler(
                     match object.as_slice() {
                         b"memory-map" => {
                             match operation {
-                                TransferOperation::Read { .. } => {
-                                    handlers::get_memory_map(&session)
+                                TransferOperation::Read { offset, length, .. } => {
+                                    handlers::get_memory_map(&session, offset, length)
                                 }
                                 TransferOperation::Write { .. } => {
                                     // not supported
@@ -164,13 +329,25 @@ pub async fn handler(
                                 }
                             }
                         }
+                        b"threads" => match operation {
+                            TransferOperation::Read { offset, length, .. } => {
+                                handlers::read_threads(&session, threads, offset, length)
+                            }
+                            TransferOperation::Write { .. } => {
+                                // not supported
+                                handlers::reply_empty()
+                            }
+                        },
                         object => {
                             log::warn!("Object '{:?}' not supported for qXfer command", object);
                             handlers::reply_empty()
                         }
                     }
                 }
-                Interrupt => handlers::user_halt(session.core(0)?, awaits_halt),
+                Interrupt => {
+                    handlers::cancel_range_step(&mut session, range_step);
+                    handlers::user_halt(&mut session, threads, awaits_halt)
+                }
                 other => {
                     log::warn!("Unknown command: '{:?}'", other);
 
@@ -202,25 +379,110 @@ pub async fn handler(
         output_stream.unbounded_send(response)?;
     };
 
+    send_notification(output_stream, non_stop)?;
+
     Ok(break_due)
 }
 
-pub async fn await_halt(
-    session: &Mutex<Session>,
+/// Sends the pending stop notification of non-stop mode, if there is one.
+fn send_notification(
+    output_stream: &Sender<CheckedPacket>,
+    non_stop: &mut NonStop,
+) -> ServerResult<()> {
+    if let Some(notification) = non_stop.take_notification() {
+        log::debug!("Notification: '{}'", notification);
+        let notification =
+            CheckedPacket::from_data(PacketKind::Notification, notification.into_bytes());
+        output_stream.unbounded_send(notification)?;
+    }
+
+    Ok(())
+}
+
+pub(crate) async fn await_halt(
+    session: &SharedSession,
     output_stream: &Sender<CheckedPacket>,
-    await_halt: &mut bool,
+    state: &mut State<'_>,
 ) -> ServerResult<()> {
+    let State {
+        awaits_halt: await_halt,
+        threads,
+        semihosting,
+        non_stop,
+        range_step,
+        ..
+    } = state;
+
     task::sleep(Duration::from_millis(10)).await;
-    if *await_halt {
-        let mut session = session.lock().expect("Poisoned Mutex");
-        if session.core(0)?.core_halted().unwrap() {
-            let response = CheckedPacket::from_data(PacketKind::Packet, b"T05hwbreak:;".to_vec());
+    if non_stop.enabled() {
+        // Each core which stops on its own is reported, the others keep running.
+        let mut session = session.lock();
+        for n in non_stop.running_cores() {
+            if !session.core(n)?.core_halted()? {
+                continue;
+            }
+
+            non_stop.stopped(n);
+            if n == 0 {
+                threads.refresh(&mut session);
+            }
+            non_stop.report_stop(format!("T05hwbreak:;thread:{:x};", threads.thread_of(n)));
+        }
+
+        send_notification(output_stream, non_stop)?;
+    } else if *await_halt {
+        let mut session = session.lock();
+        for n in 0..session.list_cores().len() {
+            let mut core = session.core(n)?;
+            if !core.core_halted()? {
+                continue;
+            }
+
+            match semihosting.halted(&mut core, n)? {
+                Halt::Stopped => (),
+                Halt::Resumed => continue,
+                Halt::Forward(request) => {
+                    // The core stays halted until GDB replies with the result of the call.
+                    *await_halt = false;
+                    let request =
+                        CheckedPacket::from_data(PacketKind::Packet, request.into_bytes());
+                    let _ = output_stream.unbounded_send(request);
+                    break;
+                }
+            }
+
+            // A range step goes on until it leaves its range.
+            let mut reason = "hwbreak:;";
+            if let Some((_, step)) = range_step.as_mut().filter(|(core, _)| *core == n) {
+                match step.halted(&mut core)? {
+                    RangeStepStatus::Running => continue,
+                    RangeStepStatus::Breakpoint => {}
+                    RangeStepStatus::Finished => reason = "",
+                }
+                *range_step = None;
+            }
+
+            // Release this core, the session is needed for the other cores below.
+            drop(core);
+
+            // In all-stop mode, the other cores are stopped as soon as one core halts.
+            for other in (0..session.list_cores().len()).filter(|other| *other != n) {
+                session.core(other)?.halt(Duration::from_millis(100))?;
+            }
+
+            threads.halted(&mut session, n);
+
+            let response = CheckedPacket::from_data(
+                PacketKind::Packet,
+                format!("T05{}thread:{:x};", reason, threads.current_id()).into_bytes(),
+            );
 
             let mut bytes = Vec::new();
             response.encode(&mut bytes).unwrap();
             *await_halt = false;
 
             let _ = output_stream.unbounded_send(response);
+            break;
         }
     }
 
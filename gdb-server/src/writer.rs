@@ -17,6 +17,11 @@ pub async fn writer(
     encode(&packet, stream).await?;
     stream.flush().await?;
 
+    // Notifications are not acknowledged.
+    if let PacketKind::Notification = packet.kind {
+        return super::reader::reader(stream, packet_stream, buffer).await;
+    }
+
     log::debug!("Request ACK for {}", String::from_utf8_lossy(&packet.data));
     'ack: loop {
         log::debug!("Reading");
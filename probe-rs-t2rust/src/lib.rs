@@ -40,7 +40,10 @@ pub fn run(input_dir: impl AsRef<Path>, output_file: impl AsRef<Path>) {
         quote::quote! {
             #[allow(unused_imports)]
             use jep106::JEP106Code;
-            use crate::config::{Chip, RawFlashAlgorithm, NvmRegion, MemoryRegion, RamRegion, SectorDescription, FlashProperties};
+            use crate::config::{BankSwap, Chip, ChipCore, ClockFactor, CoreClock, RawFlashAlgorithm, NvmRegion, EraseMode, MemoryRegion, RamRegion, RegisterWrite, SectorDescription, FlashProperties, UnlockSequence, OptionBytes, OptionField, StatusBit};
+            // Only used by some of the targets.
+            #[allow(unused_imports)]
+            use crate::{config::{DebugSequence, SequenceStep, MemoryMappedAccess, GenericRegion, MemoryAccess, WatchdogFreeze, DebugFreeze, FreezeBit, LowPowerDebug}, AccessSize, ResetType};
 
             use std::borrow::Cow;
         }
@@ -143,11 +146,29 @@ fn extract_algorithms(
                     .as_u64()
                     .map(|v| v as u32),
             );
+            let pc_verify = quote_option(
+                algorithm
+                    .get("pc_verify")
+                    .and_then(|v| v.as_u64())
+                    .map(|v| v as u32),
+            );
+            let pc_blank_check = quote_option(
+                algorithm
+                    .get("pc_blank_check")
+                    .and_then(|v| v.as_u64())
+                    .map(|v| v as u32),
+            );
             let data_section_offset = algorithm
                 .get("data_section_offset")
                 .unwrap()
                 .as_u64()
                 .unwrap() as u32;
+            let core_index = algorithm
+                .get("core_index")
+                .and_then(|v| v.as_u64())
+                .unwrap_or(0) as usize;
+            let pre_flash_sequence = extract_register_writes(algorithm, "pre_flash_sequence");
+            let post_flash_sequence = extract_register_writes(algorithm, "post_flash_sequence");
 
             let flash_properties = algorithm.get("flash_properties").unwrap();
 
@@ -196,7 +217,16 @@ fn extract_algorithms(
                     pc_program_page: #pc_program_page,
                     pc_erase_sector: #pc_erase_sector,
                     pc_erase_all: #pc_erase_all,
+                    pc_verify: #pc_verify,
+                    pc_blank_check: #pc_blank_check,
                     data_section_offset: #data_section_offset,
+                    core_index: #core_index,
+                    pre_flash_sequence: Cow::Borrowed(&[
+                        #(#pre_flash_sequence,)*
+                    ]),
+                    post_flash_sequence: Cow::Borrowed(&[
+                        #(#post_flash_sequence,)*
+                    ]),
                     flash_properties: FlashProperties {
                         address_range: #start..#end,
                         page_size: #page_size,
@@ -239,6 +269,37 @@ fn extract_sectors(region: &serde_yaml::Value) -> Vec<proc_macro2::TokenStream>
     }
 }
 
+/// Extracts a list of register writes from the sequence with the given name.
+fn extract_register_writes(
+    algorithm: &serde_yaml::Value,
+    sequence: &str,
+) -> Vec<proc_macro2::TokenStream> {
+    match algorithm.get(sequence) {
+        Some(writes) => writes
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .map(|write| {
+                let address = write.get("address").unwrap().as_u64().unwrap() as u32;
+                let value = write.get("value").unwrap().as_u64().unwrap() as u32;
+                let mask = write
+                    .get("mask")
+                    .and_then(|v| v.as_u64())
+                    .unwrap_or(0xFFFF_FFFF) as u32;
+
+                quote::quote! {
+                    RegisterWrite {
+                        address: #address,
+                        value: #value,
+                        mask: #mask,
+                    }
+                }
+            })
+            .collect(),
+        None => vec![],
+    }
+}
+
 /// Extracts a list of algorithm token streams from a yaml value.
 fn extract_memory_map(chip: &serde_yaml::Value) -> Vec<proc_macro2::TokenStream> {
     // Get an iterator over all the algorithms contained in the chip value obtained from the yaml file.
@@ -259,11 +320,15 @@ fn extract_memory_map(chip: &serde_yaml::Value) -> Vec<proc_macro2::TokenStream>
                     let start = range.get("start").unwrap().as_u64().unwrap() as u32;
                     let end = range.get("end").unwrap().as_u64().unwrap() as u32;
                     let is_boot_memory = region.get("is_boot_memory").unwrap().as_bool().unwrap();
+                    let core_index = region
+                        .get("core_index")
+                        .map_or(0, |core_index| core_index.as_u64().unwrap() as usize);
 
                     quote::quote! {
                         MemoryRegion::Ram(RamRegion {
                             range: #start..#end,
                             is_boot_memory: #is_boot_memory,
+                            core_index: #core_index,
                         })
                     }
                 })
@@ -274,11 +339,75 @@ fn extract_memory_map(chip: &serde_yaml::Value) -> Vec<proc_macro2::TokenStream>
                         let end = range.get("end").unwrap().as_u64().unwrap() as u32;
                         let is_boot_memory =
                             region.get("is_boot_memory").unwrap().as_bool().unwrap();
+                        let is_otp = region
+                            .get("is_otp")
+                            .map_or(false, |is_otp| is_otp.as_bool().unwrap());
+                        let erase_mode = match region
+                            .get("erase_mode")
+                            .map_or("sector", |mode| mode.as_str().unwrap())
+                        {
+                            "sector" => quote::quote! { EraseMode::Sector },
+                            "chip_only" => quote::quote! { EraseMode::ChipOnly },
+                            "none" => quote::quote! { EraseMode::None },
+                            unknown => panic!("Unknown erase mode: {}", unknown),
+                        };
+                        let sectors = extract_sectors(region);
+                        let memory_mapped = match region.get("memory_mapped") {
+                            Some(memory_mapped) => {
+                                let pre_algorithm_sequence = extract_register_writes(
+                                    memory_mapped,
+                                    "pre_algorithm_sequence",
+                                );
+                                let restore_sequence =
+                                    extract_register_writes(memory_mapped, "restore_sequence");
+
+                                quote::quote! {
+                                    Some(MemoryMappedAccess {
+                                        pre_algorithm_sequence: Cow::Borrowed(&[
+                                            #(#pre_algorithm_sequence,)*
+                                        ]),
+                                        restore_sequence: Cow::Borrowed(&[
+                                            #(#restore_sequence,)*
+                                        ]),
+                                    })
+                                }
+                            }
+                            None => quote::quote! { None },
+                        };
 
                         quote::quote! {
                             MemoryRegion::Nvm(NvmRegion {
                                 range: #start..#end,
                                 is_boot_memory: #is_boot_memory,
+                                is_otp: #is_otp,
+                                erase_mode: #erase_mode,
+                                sectors: Cow::Borrowed(&[
+                                    #(#sectors,)*
+                                ]),
+                                memory_mapped: #memory_mapped,
+                            })
+                        }
+                    })
+                })
+                .or_else(|| {
+                    memory_region.get("Generic").map(|region| {
+                        let range = region.get("range").unwrap();
+                        let start = range.get("start").unwrap().as_u64().unwrap() as u32;
+                        let end = range.get("end").unwrap().as_u64().unwrap() as u32;
+                        let name = match region.get("name") {
+                            Some(name) => {
+                                let name = name.as_str().unwrap();
+                                quote::quote! { Some(Cow::Borrowed(#name)) }
+                            }
+                            None => quote::quote! { None },
+                        };
+                        let access = extract_memory_access(region);
+
+                        quote::quote! {
+                            MemoryRegion::Generic(GenericRegion {
+                                range: #start..#end,
+                                name: #name,
+                                access: #access,
                             })
                         }
                     })
@@ -287,6 +416,49 @@ fn extract_memory_map(chip: &serde_yaml::Value) -> Vec<proc_macro2::TokenStream>
         .collect()
 }
 
+/// Extracts the access attributes of a generic memory region.
+fn extract_memory_access(region: &serde_yaml::Value) -> proc_macro2::TokenStream {
+    let access = match region.get("access") {
+        Some(access) => access,
+        None => return quote::quote! { MemoryAccess::default() },
+    };
+
+    let sizes = access.get("sizes").map_or(vec![], |sizes| {
+        sizes
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .map(|size| match size.as_str().unwrap() {
+                "u8" => quote::quote! { AccessSize::U8 },
+                "u16" => quote::quote! { AccessSize::U16 },
+                "u32" => quote::quote! { AccessSize::U32 },
+                "u64" => quote::quote! { AccessSize::U64 },
+                unknown => panic!("Unknown access size: {}", unknown),
+            })
+            .collect()
+    });
+    let read_side_effects = access
+        .get("read_side_effects")
+        .map_or(false, |value| value.as_bool().unwrap());
+    let write_only = access
+        .get("write_only")
+        .map_or(false, |value| value.as_bool().unwrap());
+    let not_readable_while_halted = access
+        .get("not_readable_while_halted")
+        .map_or(false, |value| value.as_bool().unwrap());
+
+    quote::quote! {
+        MemoryAccess {
+            sizes: Cow::Borrowed(&[
+                #(#sizes,)*
+            ]),
+            read_side_effects: #read_side_effects,
+            write_only: #write_only,
+            not_readable_while_halted: #not_readable_while_halted,
+        }
+    }
+}
+
 /// Extracts a list of algorithm token streams from a yaml value.
 fn extract_variants(chip_family: &serde_yaml::Value) -> Vec<proc_macro2::TokenStream> {
     // Get an iterator over all the algorithms contained in the chip value obtained from the yaml file.
@@ -315,6 +487,28 @@ fn extract_variants(chip_family: &serde_yaml::Value) -> Vec<proc_macro2::TokenSt
                 .flat_map(|f| f.as_sequence().into_iter().flat_map(|f2| f2.iter()));
 
             let flash_algorithm_names = flash_algorithms.map(|a| a.as_str().unwrap());
+
+            let cores = variant
+                .get("cores")
+                .and_then(|cores| cores.as_sequence())
+                .into_iter()
+                .flatten()
+                .map(|core| {
+                    let name = core.get("name").unwrap().as_str().unwrap();
+                    let access_port = core.get("access_port").unwrap().as_u64().unwrap() as u8;
+                    let enable_sequence = extract_register_writes(core, "enable_sequence");
+
+                    quote::quote! {
+                        ChipCore {
+                            name: Cow::Borrowed(#name),
+                            access_port: #access_port,
+                            enable_sequence: Cow::Borrowed(&[
+                                #(#enable_sequence,)*
+                            ]),
+                        }
+                    }
+                });
+
             quote::quote! {
                 Chip {
                     name: Cow::Borrowed(#name),
@@ -325,6 +519,9 @@ fn extract_variants(chip_family: &serde_yaml::Value) -> Vec<proc_macro2::TokenSt
                     flash_algorithms: Cow::Borrowed(&[
                         #(Cow::Borrowed(#flash_algorithm_names),)*
                     ]),
+                    cores: Cow::Borrowed(&[
+                        #(#cores,)*
+                    ]),
                 }
             }
         })
@@ -355,6 +552,37 @@ fn extract_chip_family(
         .unwrap()
         .to_ascii_lowercase();
     let manufacturer = quote_option(extract_manufacturer(&chip_family));
+    let unlock_sequence = quote_option(extract_unlock_sequence(&chip_family));
+    let option_bytes = quote_option(extract_option_bytes(&chip_family));
+    let recommended_speed_khz = quote_option(
+        chip_family
+            .get("recommended_speed_khz")
+            .map(|speed| speed.as_u64().unwrap() as u32),
+    );
+    let debug_vars = quote_option(
+        chip_family
+            .get("debug_vars")
+            .map(|vars| vars.as_str().unwrap())
+            .map(|vars| quote::quote! { Cow::Borrowed(#vars) }),
+    );
+    let debug_sequences = extract_debug_sequences(&chip_family);
+    let reset_types: Vec<_> = chip_family
+        .get("reset_types")
+        .and_then(|reset_types| reset_types.as_sequence())
+        .into_iter()
+        .flatten()
+        .map(extract_reset_type)
+        .collect();
+    let default_reset_type = quote_option(
+        chip_family
+            .get("default_reset_type")
+            .map(extract_reset_type),
+    );
+    let watchdog_freeze = quote_option(extract_watchdog_freeze(&chip_family));
+    let debug_freeze = quote_option(extract_debug_freeze(&chip_family));
+    let low_power_debug = quote_option(extract_low_power_debug(&chip_family));
+    let bank_swap = quote_option(extract_bank_swap(&chip_family));
+    let core_clock = quote_option(extract_core_clock(&chip_family));
 
     // Quote the chip.
     let chip_family = quote::quote! {
@@ -368,12 +596,341 @@ fn extract_chip_family(
                 #(#variants,)*
             ]),
             core: Cow::Borrowed(#core),
+            unlock_sequence: #unlock_sequence,
+            option_bytes: #option_bytes,
+            recommended_speed_khz: #recommended_speed_khz,
+            debug_vars: #debug_vars,
+            debug_sequences: Cow::Borrowed(&[
+                #(#debug_sequences,)*
+            ]),
+            reset_types: Cow::Borrowed(&[
+                #(#reset_types,)*
+            ]),
+            default_reset_type: #default_reset_type,
+            watchdog_freeze: #watchdog_freeze,
+            debug_freeze: #debug_freeze,
+            low_power_debug: #low_power_debug,
+            bank_swap: #bank_swap,
+            core_clock: #core_clock,
         }
     };
 
     chip_family
 }
 
+/// Extracts the unlock sequence token stream from a yaml value.
+fn extract_unlock_sequence(chip: &serde_yaml::Value) -> Option<proc_macro2::TokenStream> {
+    chip.get("unlock_sequence")
+        .map(|sequence| match sequence.as_str().unwrap() {
+            "nrf_ctrl_ap_erase_all" => quote::quote! { UnlockSequence::NrfCtrlApEraseAll },
+            "nrf53_ctrl_ap_erase_all" => quote::quote! { UnlockSequence::Nrf53CtrlApEraseAll },
+            "nrf91_ctrl_ap_erase_all" => quote::quote! { UnlockSequence::Nrf91CtrlApEraseAll },
+            "kinetis_mdm_ap_mass_erase" => quote::quote! { UnlockSequence::KinetisMdmApMassErase },
+            "stm32f4_rdp_regression" => quote::quote! { UnlockSequence::Stm32f4RdpRegression },
+            "wch_link_unprotect" => quote::quote! { UnlockSequence::WchLinkUnprotect },
+            unknown => panic!("Unknown unlock sequence: {}", unknown),
+        })
+}
+
+/// Extracts a reset type token stream from a yaml value.
+fn extract_reset_type(reset_type: &serde_yaml::Value) -> proc_macro2::TokenStream {
+    if let Some(name) = reset_type.get("custom") {
+        let name = name.as_str().unwrap();
+        return quote::quote! { ResetType::Custom(Cow::Borrowed(#name)) };
+    }
+
+    match reset_type.as_str().unwrap() {
+        "hardware_pin" => quote::quote! { ResetType::HardwarePin },
+        "system_reset" => quote::quote! { ResetType::SystemReset },
+        "core_only" => quote::quote! { ResetType::CoreOnly },
+        unknown => panic!("Unknown reset type: {}", unknown),
+    }
+}
+
+/// Extracts a list of debug sequence token streams from a yaml value.
+fn extract_debug_sequences(chip: &serde_yaml::Value) -> Vec<proc_macro2::TokenStream> {
+    match chip.get("debug_sequences") {
+        Some(sequences) => sequences
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .map(|sequence| {
+                let name = sequence.get("name").unwrap().as_str().unwrap();
+                let steps = extract_sequence_steps(sequence);
+
+                quote::quote! {
+                    DebugSequence {
+                        name: Cow::Borrowed(#name),
+                        steps: Cow::Borrowed(&[
+                            #(#steps,)*
+                        ]),
+                    }
+                }
+            })
+            .collect(),
+        None => vec![],
+    }
+}
+
+/// Extracts the steps of a debug sequence or of a control step.
+fn extract_sequence_steps(value: &serde_yaml::Value) -> Vec<proc_macro2::TokenStream> {
+    let steps = match value.get("steps") {
+        Some(steps) => steps.as_sequence().unwrap(),
+        None => return vec![],
+    };
+
+    steps
+        .iter()
+        .map(|step| {
+            if let Some(block) = step.get("block") {
+                let code = block.get("code").unwrap().as_str().unwrap();
+                let atomic = block
+                    .get("atomic")
+                    .map_or(false, |atomic| atomic.as_bool().unwrap());
+
+                quote::quote! {
+                    SequenceStep::Block {
+                        code: Cow::Borrowed(#code),
+                        atomic: #atomic,
+                    }
+                }
+            } else if let Some(control) = step.get("control") {
+                let condition = quote_option(
+                    control
+                        .get("if")
+                        .map(|condition| condition.as_str().unwrap())
+                        .map(|condition| quote::quote! { Cow::Borrowed(#condition) }),
+                );
+                let repeat = quote_option(
+                    control
+                        .get("while")
+                        .map(|repeat| repeat.as_str().unwrap())
+                        .map(|repeat| quote::quote! { Cow::Borrowed(#repeat) }),
+                );
+                let timeout = control
+                    .get("timeout")
+                    .map_or(0, |timeout| timeout.as_u64().unwrap());
+                let steps = extract_sequence_steps(control);
+
+                quote::quote! {
+                    SequenceStep::Control {
+                        condition: #condition,
+                        repeat: #repeat,
+                        timeout: #timeout,
+                        steps: Cow::Borrowed(&[
+                            #(#steps,)*
+                        ]),
+                    }
+                }
+            } else {
+                panic!("Unknown debug sequence step: {:?}", step);
+            }
+        })
+        .collect()
+}
+
+/// Extracts the option bytes token stream from a yaml value.
+fn extract_option_bytes(chip: &serde_yaml::Value) -> Option<proc_macro2::TokenStream> {
+    chip.get("option_bytes").map(|option_bytes| {
+        let fields = option_bytes
+            .get("fields")
+            .unwrap()
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .map(|field| {
+                let name = field.get("name").unwrap().as_str().unwrap();
+                let address = field.get("address").unwrap().as_u64().unwrap() as u32;
+                let mask = field.get("mask").unwrap().as_u64().unwrap() as u32;
+
+                quote::quote! {
+                    OptionField {
+                        name: Cow::Borrowed(#name),
+                        address: #address,
+                        mask: #mask,
+                    }
+                }
+            });
+        let lock = quote_option(extract_status_bit(option_bytes, "lock"));
+        let unlock = extract_register_writes(option_bytes, "unlock");
+        let program = extract_register_writes(option_bytes, "program");
+        let busy = quote_option(extract_status_bit(option_bytes, "busy"));
+        let launch = extract_register_writes(option_bytes, "launch");
+
+        quote::quote! {
+            OptionBytes {
+                fields: Cow::Borrowed(&[
+                    #(#fields,)*
+                ]),
+                lock: #lock,
+                unlock: Cow::Borrowed(&[
+                    #(#unlock,)*
+                ]),
+                program: Cow::Borrowed(&[
+                    #(#program,)*
+                ]),
+                busy: #busy,
+                launch: Cow::Borrowed(&[
+                    #(#launch,)*
+                ]),
+            }
+        }
+    })
+}
+
+/// Extracts the watchdog freeze token stream from a yaml value.
+fn extract_watchdog_freeze(chip: &serde_yaml::Value) -> Option<proc_macro2::TokenStream> {
+    chip.get("watchdog_freeze").map(|watchdog_freeze| {
+        let unlock = extract_register_writes(watchdog_freeze, "unlock");
+        let freeze = extract_register_writes(watchdog_freeze, "freeze");
+
+        quote::quote! {
+            WatchdogFreeze {
+                unlock: Cow::Borrowed(&[
+                    #(#unlock,)*
+                ]),
+                freeze: Cow::Borrowed(&[
+                    #(#freeze,)*
+                ]),
+            }
+        }
+    })
+}
+
+/// Extracts the debug freeze token stream from a yaml value.
+fn extract_debug_freeze(chip: &serde_yaml::Value) -> Option<proc_macro2::TokenStream> {
+    chip.get("debug_freeze").map(|debug_freeze| {
+        let unlock = extract_register_writes(debug_freeze, "unlock");
+        let bits = debug_freeze
+            .get("bits")
+            .unwrap()
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .map(|bit| {
+                let name = bit.get("name").unwrap().as_str().unwrap();
+                let address = bit.get("address").unwrap().as_u64().unwrap() as u32;
+                let mask = bit.get("mask").unwrap().as_u64().unwrap() as u32;
+
+                quote::quote! {
+                    FreezeBit {
+                        name: Cow::Borrowed(#name),
+                        address: #address,
+                        mask: #mask,
+                    }
+                }
+            });
+
+        quote::quote! {
+            DebugFreeze {
+                unlock: Cow::Borrowed(&[
+                    #(#unlock,)*
+                ]),
+                bits: Cow::Borrowed(&[
+                    #(#bits,)*
+                ]),
+            }
+        }
+    })
+}
+
+/// Extracts the low power debug token stream from a yaml value.
+fn extract_low_power_debug(chip: &serde_yaml::Value) -> Option<proc_macro2::TokenStream> {
+    chip.get("low_power_debug").map(|low_power_debug| {
+        let enable = extract_register_writes(low_power_debug, "enable");
+
+        quote::quote! {
+            LowPowerDebug {
+                enable: Cow::Borrowed(&[
+                    #(#enable,)*
+                ]),
+            }
+        }
+    })
+}
+
+/// Extracts the bank swap token stream from a yaml value.
+fn extract_bank_swap(chip: &serde_yaml::Value) -> Option<proc_macro2::TokenStream> {
+    chip.get("bank_swap").map(|bank_swap| {
+        let swapped = extract_status_bit(bank_swap, "swapped").unwrap();
+        let unswap = extract_register_writes(bank_swap, "unswap");
+
+        quote::quote! {
+            BankSwap {
+                swapped: #swapped,
+                unswap: Cow::Borrowed(&[
+                    #(#unswap,)*
+                ]),
+            }
+        }
+    })
+}
+
+/// Extracts the core clock token stream from a yaml value.
+fn extract_core_clock(chip: &serde_yaml::Value) -> Option<proc_macro2::TokenStream> {
+    chip.get("core_clock").map(|core_clock| {
+        let input_frequency = core_clock.get("input_frequency").unwrap().as_u64().unwrap() as u32;
+        let tolerance_ppm = core_clock
+            .get("tolerance_ppm")
+            .map_or(10_000, |tolerance| tolerance.as_u64().unwrap() as u32);
+        let factors = core_clock
+            .get("factors")
+            .and_then(|factors| factors.as_sequence())
+            .into_iter()
+            .flatten()
+            .map(|factor| {
+                let name = factor.get("name").unwrap().as_str().unwrap();
+                let address = factor.get("address").unwrap().as_u64().unwrap() as u32;
+                let mask = factor.get("mask").unwrap().as_u64().unwrap() as u32;
+                let offset = factor
+                    .get("offset")
+                    .map_or(0, |offset| offset.as_u64().unwrap() as u32);
+                let scale = factor
+                    .get("scale")
+                    .map_or(1, |scale| scale.as_u64().unwrap() as u32);
+                let divider = factor
+                    .get("divider")
+                    .map_or(false, |divider| divider.as_bool().unwrap());
+
+                quote::quote! {
+                    ClockFactor {
+                        name: Cow::Borrowed(#name),
+                        address: #address,
+                        mask: #mask,
+                        offset: #offset,
+                        scale: #scale,
+                        divider: #divider,
+                    }
+                }
+            });
+
+        quote::quote! {
+            CoreClock {
+                input_frequency: #input_frequency,
+                tolerance_ppm: #tolerance_ppm,
+                factors: Cow::Borrowed(&[
+                    #(#factors,)*
+                ]),
+            }
+        }
+    })
+}
+
+/// Extracts the status bit with the given name from a yaml value.
+fn extract_status_bit(value: &serde_yaml::Value, name: &str) -> Option<proc_macro2::TokenStream> {
+    value.get(name).map(|bit| {
+        let address = bit.get("address").unwrap().as_u64().unwrap() as u32;
+        let mask = bit.get("mask").unwrap().as_u64().unwrap() as u32;
+
+        quote::quote! {
+            StatusBit {
+                address: #address,
+                mask: #mask,
+            }
+        }
+    })
+}
+
 /// Extracts the jep code token stream from a yaml value.
 fn extract_manufacturer(chip: &serde_yaml::Value) -> Option<proc_macro2::TokenStream> {
     chip.get("manufacturer").and_then(|manufacturer| {
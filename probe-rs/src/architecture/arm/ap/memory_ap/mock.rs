@@ -1,4 +1,4 @@
-use super::super::{APAccess, Register};
+use super::super::{APAccess, APBatchAccess, Register};
 use super::{APRegister, AddressIncrement, DataSize, MemoryAP, CSW, DRW, TAR};
 use crate::{
     architecture::arm::dp::{DPAccess, DPRegister, DebugPortError},
@@ -43,6 +43,10 @@ impl CommunicationInterface for MockMemoryAP {
     fn flush(&mut self) -> Result<(), DebugProbeError> {
         Ok(())
     }
+
+    fn target_reset(&mut self) -> Result<(), DebugProbeError> {
+        Err(DebugProbeError::CommandNotSupportedByProbe)
+    }
 }
 
 impl<R> APAccess<MemoryAP, R> for MockMemoryAP
@@ -213,6 +217,9 @@ where
     }
 }
 
+/// The mock has no banked registers, so batches are not supported.
+impl APBatchAccess<MemoryAP> for MockMemoryAP {}
+
 impl DPAccess for MockMemoryAP {
     fn read_dp_register<R: DPRegister>(&mut self) -> Result<R, DebugPortError> {
         // Ignore for Tests
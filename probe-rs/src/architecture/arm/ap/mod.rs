@@ -8,7 +8,7 @@ use crate::DebugProbeError;
 
 pub use generic_ap::{APClass, APType, GenericAP, IDR};
 pub use memory_ap::{
-    AddressIncrement, BaseaddrFormat, DataSize, MemoryAP, BASE, BASE2, CSW, DRW, TAR,
+    AddressIncrement, BaseaddrFormat, DataSize, MemoryAP, BASE, BASE2, BD0, BD1, BD2, CSW, DRW, TAR,
 };
 
 use super::Register;
@@ -137,6 +137,31 @@ where
     }
 }
 
+/// A read or a write of an AP register in a batch, see [APBatchAccess].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum APBatchCommand {
+    /// Read the register with the given address.
+    Read(u8),
+    /// Write the value to the register with the given address.
+    Write(u8, u32),
+}
+
+/// Accesses to several registers of an AP, which are sent to the probe together.
+pub trait APBatchAccess<PORT: AccessPort> {
+    /// Performs the `commands` on the registers in the bank `bank` of the AP,
+    /// in their order, and returns the values which were read.
+    ///
+    /// Returns [DebugProbeError::CommandNotSupportedByProbe] if the accesses can not be batched.
+    fn batch_ap_registers(
+        &mut self,
+        _port: impl Into<PORT>,
+        _bank: u8,
+        _commands: &[APBatchCommand],
+    ) -> Result<Vec<u32>, DebugProbeError> {
+        Err(DebugProbeError::CommandNotSupportedByProbe)
+    }
+}
+
 /// Determine if an AP exists with the given AP number.
 /// Can fail silently under the hood testing an ap that doesnt exist and would require cleanup.
 pub fn access_port_is_valid<AP>(debug_port: &mut AP, access_port: GenericAP) -> bool
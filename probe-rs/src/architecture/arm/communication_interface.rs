@@ -1,17 +1,19 @@
 use super::{
     ap::{
-        valid_access_ports, APAccess, APClass, APRegister, AccessPort, BaseaddrFormat, DataSize,
-        GenericAP, MemoryAP, BASE, BASE2, CSW, IDR,
+        valid_access_ports, APAccess, APBatchAccess, APBatchCommand, APClass, APRegister,
+        AccessPort, BaseaddrFormat, DataSize, GenericAP, MemoryAP, BASE, BASE2, CSW, IDR,
     },
     dp::{
         Abort, Ctrl, DPAccess, DPBankSel, DPRegister, DebugPortError, DebugPortId,
-        DebugPortVersion, Select, DPIDR,
+        DebugPortVersion, Select, DPIDR, TARGETID,
     },
+    fault::{FaultPolicy, FaultRecovery, Resume, StickyErrors, TransferFault},
     memory::{adi_v5_memory_interface::ADIMemoryInterface, Component},
     SwoAccess, SwoConfig,
 };
 use crate::{
-    CommunicationInterface, DebugProbe, DebugProbeError, Error as ProbeRsError, Memory, Probe,
+    probe::BatchCommand, CommunicationInterface, DebugProbe, DebugProbeError,
+    Error as ProbeRsError, Memory, Probe,
 };
 use anyhow::anyhow;
 use jep106::JEP106Code;
@@ -116,6 +118,24 @@ pub trait DAPAccess: DebugProbe + AsRef<dyn DebugProbe> + AsMut<dyn DebugProbe>
         Ok(())
     }
 
+    /// Performs the `commands` in their order, and returns the values which were read.
+    ///
+    /// Probes which queue their transfers send all commands in as few requests as possible,
+    /// also when there are several reads. Otherwise, this falls back to the `read_register`
+    /// and `write_register` functions.
+    fn batch(&mut self, commands: &[BatchCommand]) -> Result<Vec<u32>, DebugProbeError> {
+        let mut values = vec![];
+
+        for command in commands {
+            match *command {
+                BatchCommand::Read(port, addr) => values.push(self.read_register(port, addr)?),
+                BatchCommand::Write(port, addr, value) => self.write_register(port, addr, value)?,
+            }
+        }
+
+        Ok(values)
+    }
+
     /// Flush any outstanding writes.
     ///
     /// By default, this does nothing -- but in probes that implement write
@@ -138,6 +158,36 @@ pub trait ArmProbeInterface:
 
     fn read_from_rom_table(&mut self) -> Result<Option<ArmChipInfo>, ProbeRsError>;
 
+    /// Reads the register at `address` of the AP `port`, for APs which are not
+    /// known to probe-rs, such as vendor specific control APs.
+    fn read_raw_ap_register(&mut self, port: u8, address: u8) -> Result<u32, DebugProbeError>;
+
+    /// Writes `value` to the register at `address` of the AP `port`.
+    fn write_raw_ap_register(
+        &mut self,
+        port: u8,
+        address: u8,
+        value: u32,
+    ) -> Result<(), DebugProbeError>;
+
+    /// Reads the register at `address` of the debug port.
+    ///
+    /// The DP bank of banked registers has to be selected by writing the SELECT register.
+    fn read_raw_dp_register(&mut self, address: u8) -> Result<u32, DebugProbeError>;
+
+    /// Writes `value` to the register at `address` of the debug port.
+    fn write_raw_dp_register(&mut self, address: u8, value: u32) -> Result<(), DebugProbeError>;
+
+    /// Sets how transfers which the target answers with FAULT are handled.
+    ///
+    /// The sticky error flags are always cleared after a FAULT, so the following transfers work again.
+    fn set_fault_policy(&mut self, policy: FaultPolicy);
+
+    /// Sets up the connection to the target again.
+    ///
+    /// This is required after the target was reset in a way which also resets its debug port.
+    fn reinitialize(&mut self) -> Result<(), ProbeRsError>;
+
     fn close(self: Box<Self>) -> Probe;
 }
 
@@ -169,6 +219,13 @@ pub enum ApInformation {
 }
 
 impl ArmCommunicationInterfaceState {
+    /// Updates the cached selection of the AP and the banks after SELECT was written directly.
+    pub(crate) fn update_selection(&mut self, select: Select) {
+        self.current_apsel = select.ap_sel();
+        self.current_apbanksel = select.ap_bank_sel();
+        self.current_dpbanksel = select.dp_bank_sel();
+    }
+
     pub fn new() -> Self {
         Self {
             debug_port_version: DebugPortVersion::Unsupported(0xFF),
@@ -188,6 +245,11 @@ pub struct MemoryApInformation {
     /// also support other widths. Based on this, 8 bit data access can either
     /// be performed directly, or has to be done as a 32 bit access.
     pub only_32bit_data_size: bool,
+    /// Indicates if the Memory AP supports 16 bit wide access to data.
+    pub supports_16bit_data_size: bool,
+    /// Indicates if the Memory AP supports 64 bit wide access to data,
+    /// which is part of the Large Data Extension.
+    pub supports_64bit_data_size: bool,
     /// The Debug Base Address points to either the start of a set of debug register,
     /// or a ROM table which describes the connected debug components.
     ///
@@ -207,6 +269,8 @@ pub struct MemoryApInformation {
 pub struct ArmCommunicationInterface {
     probe: Box<dyn DAPAccess>,
     state: ArmCommunicationInterfaceState,
+    use_overrun_detect: bool,
+    fault_policy: FaultPolicy,
 }
 
 impl ArmProbeInterface for ArmCommunicationInterface {
@@ -226,6 +290,57 @@ impl ArmProbeInterface for ArmCommunicationInterface {
         self.state.ap_information.len()
     }
 
+    fn read_raw_ap_register(&mut self, port: u8, address: u8) -> Result<u32, DebugProbeError> {
+        self.select_ap_and_ap_bank(port, address >> 4)?;
+
+        self.dap_read(PortType::AccessPort(u16::from(port)), u16::from(address))
+    }
+
+    fn write_raw_ap_register(
+        &mut self,
+        port: u8,
+        address: u8,
+        value: u32,
+    ) -> Result<(), DebugProbeError> {
+        self.select_ap_and_ap_bank(port, address >> 4)?;
+
+        self.dap_write(
+            PortType::AccessPort(u16::from(port)),
+            u16::from(address),
+            value,
+        )
+    }
+
+    fn read_raw_dp_register(&mut self, address: u8) -> Result<u32, DebugProbeError> {
+        self.dap_read(PortType::DebugPort, u16::from(address))
+    }
+
+    fn write_raw_dp_register(&mut self, address: u8, value: u32) -> Result<(), DebugProbeError> {
+        self.dap_write(PortType::DebugPort, u16::from(address), value)?;
+
+        // Keep the cached selection in sync with the SELECT register.
+        if address == Select::ADDRESS {
+            self.state.update_selection(Select(value));
+        }
+
+        Ok(())
+    }
+
+    fn set_fault_policy(&mut self, policy: FaultPolicy) {
+        self.fault_policy = policy;
+    }
+
+    fn reinitialize(&mut self) -> Result<(), ProbeRsError> {
+        let probe: &mut dyn DebugProbe = self.as_mut();
+        probe.attach()?;
+
+        self.state = ArmCommunicationInterfaceState::new();
+        self.enter_debug_mode(self.use_overrun_detect)?;
+        self.read_all_ap_information()?;
+
+        Ok(())
+    }
+
     fn close(self: Box<Self>) -> Probe {
         Probe::from_attached_probe(self.probe.into_probe())
     }
@@ -250,22 +365,32 @@ impl<'interface> ArmCommunicationInterface {
     ) -> Result<Self, DebugProbeError> {
         let state = ArmCommunicationInterfaceState::new();
 
-        let mut interface = Self { probe, state };
+        let mut interface = Self {
+            probe,
+            state,
+            use_overrun_detect,
+            fault_policy: FaultPolicy::default(),
+        };
 
         interface.enter_debug_mode(use_overrun_detect)?;
+        interface.read_all_ap_information()?;
+
+        Ok(interface)
+    }
 
-        /* determine the number and type of available APs */
+    /// Determines the number and type of available APs.
+    fn read_all_ap_information(&mut self) -> Result<(), DebugProbeError> {
         log::trace!("Searching valid APs");
 
-        for ap in valid_access_ports(&mut interface) {
-            let ap_state = interface.read_ap_information(ap)?;
+        for ap in valid_access_ports(self) {
+            let ap_state = self.read_ap_information(ap)?;
 
             log::debug!("AP {}: {:?}", ap.port_number(), ap_state);
 
-            interface.state.ap_information.push(ap_state);
+            self.state.ap_information.push(ap_state);
         }
 
-        Ok(interface)
+        Ok(())
     }
 
     pub fn memory_interface(
@@ -415,7 +540,7 @@ impl<'interface> ArmCommunicationInterface {
 
         self.select_ap_and_ap_bank(port.into().port_number(), R::APBANKSEL)?;
 
-        self.probe.write_register(
+        self.dap_write(
             PortType::AccessPort(u16::from(self.state.current_apsel)),
             u16::from(R::ADDRESS),
             register_value,
@@ -445,7 +570,7 @@ impl<'interface> ArmCommunicationInterface {
 
         self.select_ap_and_ap_bank(port.into().port_number(), R::APBANKSEL)?;
 
-        self.probe.write_block(
+        self.dap_write_block(
             PortType::AccessPort(u16::from(self.state.current_apsel)),
             u16::from(R::ADDRESS),
             values,
@@ -468,8 +593,7 @@ impl<'interface> ArmCommunicationInterface {
         self.select_ap_and_ap_bank(port.into().port_number(), R::APBANKSEL)?;
 
         let result: R = self
-            .probe
-            .read_register(
+            .dap_read(
                 PortType::AccessPort(u16::from(self.state.current_apsel)),
                 u16::from(R::ADDRESS),
             )?
@@ -502,7 +626,7 @@ impl<'interface> ArmCommunicationInterface {
 
         self.select_ap_and_ap_bank(port.into().port_number(), R::APBANKSEL)?;
 
-        self.probe.read_block(
+        self.dap_read_block(
             PortType::AccessPort(u16::from(self.state.current_apsel)),
             u16::from(R::ADDRESS),
             values,
@@ -555,9 +679,20 @@ impl<'interface> ArmCommunicationInterface {
 
             log::debug!("HNONSEC supported: {}", supports_hnonsec);
 
+            // The SIZE field only keeps values of supported access widths.
+            self.write_ap_register(access_port, CSW::new(DataSize::U16))?;
+            let supports_16bit_data_size =
+                self.read_ap_register(access_port, CSW::default())?.SIZE == DataSize::U16;
+
+            self.write_ap_register(access_port, CSW::new(DataSize::U64))?;
+            let supports_64bit_data_size =
+                self.read_ap_register(access_port, CSW::default())?.SIZE == DataSize::U64;
+
             Ok(ApInformation::MemoryAp(MemoryApInformation {
                 port_number: access_port.port_number(),
                 only_32bit_data_size,
+                supports_16bit_data_size,
+                supports_64bit_data_size,
                 debug_base_address: base_address,
                 supports_hnonsec,
             }))
@@ -568,17 +703,139 @@ impl<'interface> ArmCommunicationInterface {
         }
     }
 
+    /// The fault policy, which memory APs apply to their block transfers.
+    pub(crate) fn fault_policy(&self) -> FaultPolicy {
+        self.fault_policy
+    }
+
+    /// Reads a register of the DP or an AP, and handles a FAULT response with the fault policy.
+    fn dap_read(&mut self, port: PortType, address: u16) -> Result<u32, DebugProbeError> {
+        let mut recovery = FaultRecovery::new(self.fault_policy);
+
+        loop {
+            match self.probe.read_register(port, address) {
+                Err(error) if is_fault_response(&error) => self.recover(&mut recovery)?,
+                result => return result,
+            }
+        }
+    }
+
+    /// Writes a register of the DP or an AP, and handles a FAULT response with the fault policy.
+    fn dap_write(
+        &mut self,
+        port: PortType,
+        address: u16,
+        value: u32,
+    ) -> Result<(), DebugProbeError> {
+        let mut recovery = FaultRecovery::new(self.fault_policy);
+
+        loop {
+            match self.probe.write_register(port, address, value) {
+                Err(error) if is_fault_response(&error) => self.recover(&mut recovery)?,
+                result => return result,
+            }
+        }
+    }
+
+    /// Reads a register repeatedly.
+    ///
+    /// The position of a transfer which is answered with FAULT is not known here,
+    /// so the block fails after the sticky flags were cleared. Memory APs find the
+    /// position from TAR, and continue the block as the fault policy says.
+    fn dap_read_block(
+        &mut self,
+        port: PortType,
+        address: u16,
+        values: &mut [u32],
+    ) -> Result<(), DebugProbeError> {
+        match self.probe.read_block(port, address, values) {
+            Err(error) if is_fault_response(&error) => Err(self.block_fault()?.into()),
+            result => result,
+        }
+    }
+
+    /// Writes a register repeatedly, see [ArmCommunicationInterface::dap_read_block].
+    fn dap_write_block(
+        &mut self,
+        port: PortType,
+        address: u16,
+        values: &[u32],
+    ) -> Result<(), DebugProbeError> {
+        match self.probe.write_block(port, address, values) {
+            Err(error) if is_fault_response(&error) => Err(self.block_fault()?.into()),
+            result => result,
+        }
+    }
+
+    /// Clears the sticky flags after a FAULT of a single transfer,
+    /// and returns an error unless the transfer is repeated.
+    fn recover(&mut self, recovery: &mut FaultRecovery) -> Result<(), DebugProbeError> {
+        let errors = self.clear_sticky_errors()?;
+
+        match recovery.fault(0, errors) {
+            Ok(Resume::Replay) => Ok(()),
+            // A single transfer which is skipped fails.
+            Ok(Resume::Skip) | Err(_) => Err(TransferFault {
+                index: None,
+                errors,
+            }
+            .into()),
+        }
+    }
+
+    fn block_fault(&mut self) -> Result<TransferFault, DebugProbeError> {
+        let errors = self.clear_sticky_errors()?;
+
+        Ok(TransferFault {
+            index: None,
+            errors,
+        })
+    }
+
+    /// Reads the sticky error flags of CTRL/STAT after a FAULT, and clears them through ABORT.
+    fn clear_sticky_errors(&mut self) -> Result<StickyErrors, DebugProbeError> {
+        self.select_dp_bank(Ctrl::DP_BANK)?;
+
+        let ctrl = Ctrl::from(
+            self.probe
+                .read_register(PortType::DebugPort, u16::from(Ctrl::ADDRESS))?,
+        );
+        let errors = StickyErrors::from_ctrl(&ctrl);
+        log::debug!("FAULT response, CTRL/STAT: {:?}", ctrl);
+
+        self.probe.write_register(
+            PortType::DebugPort,
+            u16::from(Abort::ADDRESS),
+            errors.abort().into(),
+        )?;
+
+        Ok(errors)
+    }
+
     fn get_debug_port_version(&mut self) -> Result<DebugPortVersion, DebugProbeError> {
-        let dpidr = DPIDR(self.probe.read_register(PortType::DebugPort, 0)?);
+        let dpidr = DPIDR(self.dap_read(PortType::DebugPort, 0)?);
 
         Ok(DebugPortVersion::from(dpidr.version()))
     }
 }
 
+fn is_fault_response(error: &DebugProbeError) -> bool {
+    match error {
+        DebugProbeError::ArchitectureSpecific(error) => {
+            matches!(error.downcast_ref(), Some(DapError::FaultResponse))
+        }
+        _ => false,
+    }
+}
+
 impl CommunicationInterface for ArmCommunicationInterface {
     fn flush(&mut self) -> Result<(), DebugProbeError> {
         self.probe.flush()
     }
+
+    fn target_reset(&mut self) -> Result<(), DebugProbeError> {
+        self.probe.target_reset()
+    }
 }
 
 impl DPAccess for ArmCommunicationInterface {
@@ -594,8 +851,7 @@ impl DPAccess for ArmCommunicationInterface {
 
         log::debug!("Reading DP register {}", R::NAME);
         let result = self
-            .probe
-            .read_register(PortType::DebugPort, u16::from(R::ADDRESS))?
+            .dap_read(PortType::DebugPort, u16::from(R::ADDRESS))?
             .into();
 
         log::debug!("Read    DP register {}, value=0x{:x?}", R::NAME, result);
@@ -614,8 +870,7 @@ impl DPAccess for ArmCommunicationInterface {
         self.select_dp_bank(R::DP_BANK)?;
 
         log::debug!("Writing DP register {}, value=0x{:x?}", R::NAME, register);
-        self.probe
-            .write_register(PortType::DebugPort, R::ADDRESS as u16, register.into())?;
+        self.dap_write(PortType::DebugPort, R::ADDRESS as u16, register.into())?;
 
         Ok(())
     }
@@ -685,6 +940,34 @@ where
     }
 }
 
+impl APBatchAccess<MemoryAP> for ArmCommunicationInterface {
+    fn batch_ap_registers(
+        &mut self,
+        port: impl Into<MemoryAP>,
+        bank: u8,
+        commands: &[APBatchCommand],
+    ) -> Result<Vec<u32>, DebugProbeError> {
+        self.select_ap_and_ap_bank(port.into().port_number(), bank)?;
+
+        let port = PortType::AccessPort(u16::from(self.state.current_apsel));
+        let commands: Vec<_> = commands
+            .iter()
+            .map(|command| match *command {
+                APBatchCommand::Read(address) => BatchCommand::Read(port, u16::from(address)),
+                APBatchCommand::Write(address, value) => {
+                    BatchCommand::Write(port, u16::from(address), value)
+                }
+            })
+            .collect();
+
+        // As for block transfers, the position of a FAULT is not known.
+        match self.probe.batch(&commands) {
+            Err(error) if is_fault_response(&error) => Err(self.block_fault()?.into()),
+            result => result,
+        }
+    }
+}
+
 impl<R> APAccess<GenericAP, R> for ArmCommunicationInterface
 where
     R: APRegister<GenericAP>,
@@ -730,10 +1013,71 @@ where
 pub struct ArmChipInfo {
     pub manufacturer: JEP106Code,
     pub part: u16,
+    /// The value of the DPIDR register of the debug port.
+    pub dpidr: Option<u32>,
+    /// The value of the TARGETID register, only present on DPv2 debug ports.
+    pub target_id: Option<u32>,
+}
+
+impl ArmChipInfo {
+    /// Creates the chip information from the debug port identification registers alone,
+    /// for chips which have no ROM table with a manufacturer code.
+    pub(crate) fn from_debug_port(dpidr: Option<u32>, target_id: Option<u32>) -> Option<Self> {
+        let (manufacturer, part) = target_id_designer_and_part(target_id?)?;
+
+        Some(ArmChipInfo {
+            manufacturer,
+            part,
+            dpidr,
+            target_id,
+        })
+    }
+
+    /// The designer and the part number contained in the TARGETID register, if it was read.
+    pub fn target_id_designer_and_part(&self) -> Option<(JEP106Code, u16)> {
+        self.target_id.and_then(target_id_designer_and_part)
+    }
+}
+
+fn target_id_designer_and_part(target_id: u32) -> Option<(JEP106Code, u16)> {
+    let target_id = TARGETID::from(target_id);
+    let designer = target_id.tdesigner();
+
+    // A designer of zero means that the TARGETID register is not implemented.
+    if designer == 0 {
+        return None;
+    }
+
+    Some((
+        JEP106Code::new((designer >> 7) as u8, (designer & 0x7f) as u8),
+        target_id.tpartno(),
+    ))
+}
+
+/// Reads the DPIDR and the TARGETID register of the debug port, which are used
+/// in addition to the ROM table to identify the connected chip.
+///
+/// The TARGETID register is only read for DPv2 debug ports.
+pub(crate) fn read_debug_port_ids(dap: &mut impl DPAccess) -> (Option<u32>, Option<u32>) {
+    let dpidr = dap
+        .read_dp_register::<DPIDR>()
+        .map(u32::from)
+        .map_err(|e| log::debug!("Failed to read DPIDR: {}", e))
+        .ok();
+
+    let target_id = dap
+        .read_dp_register::<TARGETID>()
+        .map(u32::from)
+        .map_err(|e| log::debug!("Failed to read TARGETID: {}", e))
+        .ok();
+
+    (dpidr, target_id)
 }
 
 impl ArmCommunicationInterface {
     pub fn read_from_rom_table(&mut self) -> Result<Option<ArmChipInfo>, ProbeRsError> {
+        let (dpidr, target_id) = read_debug_port_ids(self);
+
         // faults on some chips need to be cleaned up.
         let aps = valid_access_ports(self);
 
@@ -772,6 +1116,8 @@ impl ArmCommunicationInterface {
                         return Ok(Some(ArmChipInfo {
                             manufacturer: jep106,
                             part: component_id.peripheral_id().part(),
+                            dpidr,
+                            target_id,
                         }));
                     }
                 }
@@ -785,7 +1131,7 @@ impl ArmCommunicationInterface {
         //     "flash and UICR area of the device, in addition to the entire RAM".yellow()
         // );
 
-        Ok(None)
+        Ok(ArmChipInfo::from_debug_port(dpidr, target_id))
     }
 }
 
@@ -798,6 +1144,282 @@ impl std::fmt::Display for ArmChipInfo {
                 self.manufacturer.cc, self.manufacturer.id
             ),
         };
-        write!(f, "{} 0x{:04x}", manu, self.part)
+        write!(f, "{} 0x{:04x}", manu, self.part)?;
+
+        if let Some(target_id) = self.target_id {
+            write!(f, " (TARGETID 0x{:08x})", target_id)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ArmCommunicationInterface, ArmProbeInterface, DapError, PortType};
+    use crate::architecture::arm::ap::MemoryAP;
+    use crate::architecture::arm::fault::find_transfer_fault;
+    use crate::architecture::arm::FaultPolicy;
+    use crate::probe::{DebugProbeError, FakeDap};
+    use crate::CoreRegisterAddress;
+
+    use std::collections::HashMap;
+    use std::sync::atomic::Ordering;
+
+    const RAM: u32 = 0x2000_0000;
+    const STICKYERR: u32 = 1 << 5;
+
+    const DHCSR: u32 = 0xE000_EDF0;
+    const DCRSR: u32 = 0xE000_EDF4;
+    const DCRDR: u32 = 0xE000_EDF8;
+
+    /// A target with a memory AP in front of 16 words of RAM, which answers
+    /// the accesses to chosen addresses with FAULT. It is run by a [FakeDap].
+    ///
+    /// While STICKYERR is set, all AP transfers are answered with FAULT, and TAR is only
+    /// incremented by successful transfers, like on a real debug port.
+    ///
+    /// The debug registers of a halted core are at their usual addresses, and the
+    /// register transfers through them complete immediately.
+    #[derive(Debug)]
+    struct FaultyTarget {
+        sticky: u32,
+        select: u32,
+        csw: u32,
+        tar: u32,
+        ram: Vec<u32>,
+        /// How many of the next accesses to an address fail.
+        faults: HashMap<u32, usize>,
+        core_registers: Vec<u32>,
+        dcrdr: u32,
+    }
+
+    impl FaultyTarget {
+        fn new(faults: &[(u32, usize)]) -> Self {
+            FaultyTarget {
+                sticky: 0,
+                select: 0,
+                csw: 0,
+                tar: 0,
+                ram: (0..16).map(|i| 0xa5a5_0000 | i).collect(),
+                faults: faults.iter().cloned().collect(),
+                core_registers: (0..32).map(|i| 0xc0de_0000 | i).collect(),
+                dcrdr: 0,
+            }
+        }
+
+        fn access_port(
+            &mut self,
+            ap: u16,
+            addr: u16,
+            value: Option<u32>,
+        ) -> Result<u32, DebugProbeError> {
+            if self.sticky & STICKYERR != 0 {
+                return Err(DapError::FaultResponse.into());
+            }
+            if ap != 0 {
+                return Ok(0);
+            }
+
+            match ((self.select & 0xf0) as u16 | (addr & 0xc), value) {
+                (0x00, Some(value)) => self.csw = value,
+                (0x00, None) => return Ok(self.csw),
+                (0x04, Some(value)) => self.tar = value,
+                (0x04, None) => return Ok(self.tar),
+                (0x0c, value) => return self.data(value),
+                // The banked data registers BD0 to BD3.
+                (0x10..=0x1c, value) => {
+                    return Ok(self.debug_register(self.tar + u32::from(addr & 0xc), value))
+                }
+                (0xf8, None) => return Ok(0xe00f_f003),
+                (0xfc, None) => return Ok(0x2477_0011),
+                _ => {}
+            }
+
+            Ok(0)
+        }
+
+        fn data(&mut self, value: Option<u32>) -> Result<u32, DebugProbeError> {
+            if let Some(remaining) = self.faults.get_mut(&self.tar) {
+                if *remaining > 0 {
+                    *remaining -= 1;
+                    self.sticky |= STICKYERR;
+                    return Err(DapError::FaultResponse.into());
+                }
+            }
+
+            if self.tar >= DHCSR {
+                return Ok(self.debug_register(self.tar, value));
+            }
+
+            let index = ((self.tar - RAM) / 4) as usize;
+            if let Some(value) = value {
+                self.ram[index] = value;
+            }
+            self.tar += 4;
+
+            Ok(self.ram[index])
+        }
+
+        fn debug_register(&mut self, address: u32, value: Option<u32>) -> u32 {
+            match (address, value) {
+                // S_REGRDY
+                (DHCSR, None) => return 1 << 16,
+                (DCRSR, Some(dcrsr)) => {
+                    let register = (dcrsr & 0x1f) as usize;
+                    // REGWnR
+                    if dcrsr & (1 << 16) != 0 {
+                        self.core_registers[register] = self.dcrdr;
+                    } else {
+                        self.dcrdr = self.core_registers[register];
+                    }
+                }
+                (DCRDR, Some(value)) => self.dcrdr = value,
+                (DCRDR, None) => return self.dcrdr,
+                _ => {}
+            }
+
+            0
+        }
+
+        fn transfer(
+            &mut self,
+            port: PortType,
+            addr: u16,
+            value: Option<u32>,
+        ) -> Result<u32, DebugProbeError> {
+            match (port, addr, value) {
+                (PortType::DebugPort, 0x0, None) => Ok(0x2ba0_1477),
+                (PortType::DebugPort, 0x0, Some(value)) => {
+                    // STKERRCLR, WDERRCLR and ORUNERRCLR
+                    let cleared = [(1 << 2, STICKYERR), (1 << 3, 1 << 7), (1 << 4, 1 << 1)];
+                    for (clear, flag) in cleared.iter() {
+                        if value & clear != 0 {
+                            self.sticky &= !flag;
+                        }
+                    }
+                    Ok(0)
+                }
+                // The power requests are acknowledged.
+                (PortType::DebugPort, 0x4, None) => Ok(0xf000_0000 | self.sticky),
+                (PortType::DebugPort, 0x8, Some(value)) => {
+                    self.select = value;
+                    Ok(0)
+                }
+                (PortType::DebugPort, _, _) => Ok(0),
+                (PortType::AccessPort(ap), addr, value) => self.access_port(ap, addr, value),
+            }
+        }
+    }
+
+    fn faulty_target(faults: &[(u32, usize)]) -> FakeDap {
+        let mut target = FaultyTarget::new(faults);
+
+        FakeDap::new("Faulty target")
+            .with_target(move |_, port, addr, value| Some(target.transfer(port, addr, value)))
+    }
+
+    fn interface(policy: FaultPolicy, faults: &[(u32, usize)]) -> ArmCommunicationInterface {
+        let mut interface =
+            ArmCommunicationInterface::new(Box::new(faulty_target(faults)), false).unwrap();
+        interface.set_fault_policy(policy);
+        interface
+    }
+
+    #[test]
+    fn faulted_transfers_are_replayed() {
+        let mut interface = interface(
+            FaultPolicy::Replay { attempts: 2 },
+            &[(RAM + 8, 2), (RAM + 20, 3)],
+        );
+        let mut memory = interface.memory_interface(MemoryAP::new(0)).unwrap();
+
+        let mut data = [0; 4];
+        memory.read_32(RAM, &mut data).unwrap();
+        assert_eq!(data, [0xa5a5_0000, 0xa5a5_0001, 0xa5a5_0002, 0xa5a5_0003]);
+
+        // The third fault of the same transfer fails it.
+        let error = memory.write_32(RAM + 16, &[1, 2, 3, 4]).unwrap_err();
+        assert_eq!(find_transfer_fault(&error).unwrap().index, Some(1));
+
+        // All faults were used up, so the same write works now.
+        memory.write_32(RAM + 16, &[1, 2, 3, 4]).unwrap();
+        let mut data = [0; 4];
+        memory.read_32(RAM + 16, &mut data).unwrap();
+        assert_eq!(data, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn failed_transfers_are_skipped() {
+        let mut interface = interface(FaultPolicy::FailTransfer, &[(RAM + 8, 1), (RAM + 16, 1)]);
+        let mut memory = interface.memory_interface(MemoryAP::new(0)).unwrap();
+
+        let mut data = [0; 6];
+        let error = memory.read_32(RAM, &mut data).unwrap_err();
+        assert_eq!(find_transfer_fault(&error).unwrap().index, Some(2));
+        assert_eq!(
+            data,
+            [0xa5a5_0000, 0xa5a5_0001, 0, 0xa5a5_0003, 0, 0xa5a5_0005]
+        );
+    }
+
+    #[test]
+    fn faulted_batches_are_aborted() {
+        let mut interface = interface(FaultPolicy::AbortBatch, &[(RAM + 8, 1), (RAM + 32, 1)]);
+        let mut memory = interface.memory_interface(MemoryAP::new(0)).unwrap();
+
+        // The writes after the failed one are posted, but do not reach the memory.
+        let error = memory.write_32(RAM, &[1, 2, 3, 4]).unwrap_err();
+        let fault = find_transfer_fault(&error).unwrap();
+        assert_eq!(fault.index, Some(2));
+        assert!(fault.errors.sticky_error);
+
+        // The sticky flag was cleared, so the next transfers work again.
+        let mut data = [0; 4];
+        memory.read_32(RAM, &mut data).unwrap();
+        assert_eq!(data, [1, 2, 0xa5a5_0002, 0xa5a5_0003]);
+
+        // Single transfers fail without a position.
+        let error = memory.read_word_32(RAM + 32).unwrap_err();
+        assert_eq!(find_transfer_fault(&error).unwrap().index, None);
+        assert_eq!(memory.read_word_32(RAM + 32).unwrap(), 0xa5a5_0008);
+    }
+
+    #[test]
+    fn core_registers_are_batched() {
+        let target = faulty_target(&[]);
+        let transactions = target.transactions.clone();
+        let mut interface = ArmCommunicationInterface::new(Box::new(target), false).unwrap();
+        let mut memory = interface.memory_interface(MemoryAP::new(0)).unwrap();
+
+        let addresses: Vec<_> = (0..16).map(CoreRegisterAddress).collect();
+
+        transactions.store(0, Ordering::SeqCst);
+        let single: Vec<_> = addresses
+            .iter()
+            .map(|&address| memory.read_core_reg(address).unwrap())
+            .collect();
+        assert_eq!(transactions.swap(0, Ordering::SeqCst), 32);
+
+        let batched: Vec<_> = memory
+            .read_core_regs(&addresses)
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(transactions.swap(0, Ordering::SeqCst), 1);
+        assert_eq!(batched, single);
+
+        let values: Vec<_> = addresses
+            .iter()
+            .map(|&address| (address, 0x1000 + u32::from(address.0)))
+            .collect();
+        for result in memory.write_core_regs(&values) {
+            result.unwrap();
+        }
+        assert_eq!(transactions.swap(0, Ordering::SeqCst), 1);
+        assert_eq!(
+            memory.read_core_reg(CoreRegisterAddress(15)).unwrap(),
+            0x100f
+        );
     }
 }
@@ -139,6 +139,12 @@ pub fn remove_swv_data_trace(
     dwt.disable_data_trace(unit)
 }
 
+/// Configures the DWT to trace the entry and exit of exceptions.
+pub fn enable_swv_exception_trace(core: &mut Core, component: &Component) -> Result<(), Error> {
+    let mut dwt = component.dwt(core).map_err(Error::architecture_specific)?;
+    dwt.enable_exception_trace()
+}
+
 /// Sets TRCENA in DEMCR to begin trace generation.
 pub fn enable_tracing(core: &mut Core) -> Result<(), Error> {
     let mut demcr = Demcr(core.read_word_32(Demcr::ADDRESS)?);
@@ -4,9 +4,9 @@ use crate::core::{
     RegisterDescription, RegisterFile, RegisterKind,
 };
 use crate::error::Error;
-use crate::memory::Memory;
+use crate::memory::{AccessSize, Memory};
 use crate::{CoreStatus, DebugProbeError, HaltReason, MemoryInterface};
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use bitfield::bitfield;
 use log::debug;
 use std::{
@@ -411,6 +411,23 @@ impl<'probe> CoreInterface for M0<'probe> {
         Ok(CoreInformation { pc: pc_value })
     }
 
+    fn reset_core(&mut self) -> Result<(), Error> {
+        // VECTRESET is reserved in the ARMv6-M architecture.
+        Err(anyhow!("Resetting only the core is not supported by Cortex-M0 cores.").into())
+    }
+
+    fn reset_hardware_pin(&mut self) -> Result<(), Error> {
+        self.memory.target_reset()
+    }
+
+    fn reset_catch_set(&mut self) -> Result<(), Error> {
+        reset_catch_set(self)
+    }
+
+    fn reset_catch_clear(&mut self) -> Result<(), Error> {
+        reset_catch_clear(self)
+    }
+
     fn get_available_breakpoint_units(&mut self) -> Result<u32, Error> {
         let result = self.memory.read_word_32(BpCtrl::ADDRESS)?;
 
@@ -539,6 +556,14 @@ impl<'probe> CoreInterface for M0<'probe> {
         self.memory.write_core_reg(address, value)?;
         Ok(())
     }
+
+    fn read_core_regs(&mut self, addresses: &[CoreRegisterAddress]) -> Vec<Result<u32, Error>> {
+        self.memory.read_core_regs(addresses)
+    }
+
+    fn write_core_regs(&mut self, values: &[(CoreRegisterAddress, u32)]) -> Vec<Result<(), Error>> {
+        self.memory.write_core_regs(values)
+    }
 }
 
 impl<'probe> MemoryInterface for M0<'probe> {
@@ -548,24 +573,49 @@ impl<'probe> MemoryInterface for M0<'probe> {
     fn read_word_8(&mut self, address: u32) -> Result<u8, Error> {
         self.memory.read_word_8(address)
     }
+    fn read_word_64(&mut self, address: u32) -> Result<u64, Error> {
+        self.memory.read_word_64(address)
+    }
     fn read_32(&mut self, address: u32, data: &mut [u32]) -> Result<(), Error> {
         self.memory.read_32(address, data)
     }
     fn read_8(&mut self, address: u32, data: &mut [u8]) -> Result<(), Error> {
         self.memory.read_8(address, data)
     }
+    fn read_stream(&mut self, address: u32, data: &mut [u8], restart: bool) -> Result<(), Error> {
+        self.memory.read_stream(address, data, restart)
+    }
     fn write_word_32(&mut self, address: u32, data: u32) -> Result<(), Error> {
         self.memory.write_word_32(address, data)
     }
     fn write_word_8(&mut self, address: u32, data: u8) -> Result<(), Error> {
         self.memory.write_word_8(address, data)
     }
+    fn write_word_64(&mut self, address: u32, data: u64) -> Result<(), Error> {
+        self.memory.write_word_64(address, data)
+    }
     fn write_32(&mut self, address: u32, data: &[u32]) -> Result<(), Error> {
         self.memory.write_32(address, data)
     }
     fn write_8(&mut self, address: u32, data: &[u8]) -> Result<(), Error> {
         self.memory.write_8(address, data)
     }
+    fn read_mem_with_size(
+        &mut self,
+        address: u32,
+        data: &mut [u8],
+        size: AccessSize,
+    ) -> Result<(), Error> {
+        self.memory.read_mem_with_size(address, data, size)
+    }
+    fn write_mem_with_size(
+        &mut self,
+        address: u32,
+        data: &[u8],
+        size: AccessSize,
+    ) -> Result<(), Error> {
+        self.memory.write_mem_with_size(address, data, size)
+    }
     fn flush(&mut self) -> Result<(), Error> {
         self.memory.flush()
     }
@@ -0,0 +1,295 @@
+//! Recovery from transfers which the target answers with a FAULT response.
+//!
+//! A FAULT response leaves a sticky flag set in the CTRL/STAT register of the debug port,
+//! and the debug port does not start any AP transfer until the flag is cleared through the
+//! ABORT register. The sticky flags are read and cleared after every FAULT, and the transfer
+//! is then handled as the [FaultPolicy] of the interface says.
+//!
+//! While STICKYERR is set, the transfers after the failed one in a block do not reach the
+//! memory AP, and TAR is only incremented by successful transfers. So after a FAULT in a
+//! block transfer of a memory AP, TAR points to the transfer which failed, even if the FAULT
+//! was reported for a later, posted transfer.
+
+use super::dp::{Abort, Ctrl};
+use crate::DebugProbeError;
+
+use std::fmt;
+
+/// What happens with a transfer which was answered with FAULT, after the sticky flags were cleared.
+///
+/// The policy is set with [ArmProbeInterface::set_fault_policy](super::communication_interface::ArmProbeInterface::set_fault_policy).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultPolicy {
+    /// Repeat the failed transfer up to `attempts` times, and fail it with a [TransferFault]
+    /// if it is still answered with FAULT. A block transfer continues after the repeated transfer.
+    ///
+    /// This helps with faults which go away on their own, e.g. while the firmware switches clocks.
+    Replay {
+        /// How often a transfer is repeated.
+        attempts: u8,
+    },
+    /// Fail the transfer with a [TransferFault], but continue a block transfer with the
+    /// transfer after it. A block of memory is transferred except for the words which faulted,
+    /// and the error names the first one.
+    FailTransfer,
+    /// Fail the transfer with a [TransferFault], and skip the rest of a block transfer.
+    AbortBatch,
+}
+
+impl Default for FaultPolicy {
+    /// Repeats a failed transfer up to five times, like the probe drivers did before the
+    /// policy could be set.
+    fn default() -> Self {
+        FaultPolicy::Replay { attempts: 5 }
+    }
+}
+
+/// The sticky error flags of CTRL/STAT, which tell why a transfer was answered with FAULT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StickyErrors {
+    /// WDATAERR: The data of a write was corrupted, or not sent after a WAIT response.
+    pub write_error: bool,
+    /// STICKYERR: The AP transfer failed, e.g. because the bus of a memory AP returned an error.
+    pub sticky_error: bool,
+    /// STICKYORUN: A transfer was sent with overrun detection enabled, while an earlier one
+    /// was not finished yet.
+    pub overrun: bool,
+}
+
+impl StickyErrors {
+    pub(crate) fn from_ctrl(ctrl: &Ctrl) -> Self {
+        StickyErrors {
+            write_error: ctrl.w_data_err(),
+            sticky_error: ctrl.sticky_err(),
+            overrun: ctrl.sticky_orun(),
+        }
+    }
+
+    /// Returns `true` if no flag is set.
+    pub fn is_empty(&self) -> bool {
+        !(self.write_error || self.sticky_error || self.overrun)
+    }
+
+    /// The value of the ABORT register which clears these flags.
+    pub(crate) fn abort(&self) -> Abort {
+        let mut abort = Abort(0);
+        abort.set_wderrclr(self.write_error);
+        abort.set_stkerrclr(self.sticky_error);
+        abort.set_orunerrclr(self.overrun);
+        abort
+    }
+}
+
+impl fmt::Display for StickyErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "no sticky flag set");
+        }
+
+        let flags = [
+            (self.write_error, "WDATAERR"),
+            (self.sticky_error, "STICKYERR"),
+            (self.overrun, "STICKYORUN"),
+        ];
+        let names: Vec<_> = flags
+            .iter()
+            .filter(|(set, _)| *set)
+            .map(|(_, name)| *name)
+            .collect();
+
+        write!(f, "{}", names.join(", "))
+    }
+}
+
+/// A transfer which was answered with FAULT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransferFault {
+    /// The position of the failed transfer in its block, if it is known.
+    pub index: Option<usize>,
+    /// The sticky flags which were set by the fault. They were cleared again.
+    pub errors: StickyErrors,
+}
+
+impl fmt::Display for TransferFault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.index {
+            Some(index) => write!(
+                f,
+                "Transfer {} of the block was answered with FAULT ({})",
+                index, self.errors
+            ),
+            None => write!(f, "The target answered with FAULT ({})", self.errors),
+        }
+    }
+}
+
+impl std::error::Error for TransferFault {}
+
+impl From<TransferFault> for DebugProbeError {
+    fn from(fault: TransferFault) -> Self {
+        DebugProbeError::ArchitectureSpecific(Box::new(fault))
+    }
+}
+
+/// Finds the [TransferFault] in the sources of `error`.
+pub(crate) fn find_transfer_fault(
+    error: &(dyn std::error::Error + 'static),
+) -> Option<TransferFault> {
+    let mut source = Some(error);
+
+    while let Some(error) = source {
+        if let Some(fault) = error.downcast_ref::<TransferFault>() {
+            return Some(*fault);
+        }
+        if let Some(DebugProbeError::ArchitectureSpecific(inner)) =
+            error.downcast_ref::<DebugProbeError>()
+        {
+            if let Some(fault) = inner.downcast_ref::<TransferFault>() {
+                return Some(*fault);
+            }
+        }
+        source = error.source();
+    }
+
+    None
+}
+
+/// How a transfer continues after it was answered with FAULT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Resume {
+    /// Repeat the failed transfer.
+    Replay,
+    /// Continue with the transfer after the failed one.
+    Skip,
+}
+
+/// Applies a [FaultPolicy] to the faults of one transfer or block transfer.
+#[derive(Debug)]
+pub(crate) struct FaultRecovery {
+    policy: FaultPolicy,
+    /// The transfer which was replayed last, and how often.
+    replayed: Option<(usize, u8)>,
+    /// The first transfer which was skipped.
+    skipped: Option<TransferFault>,
+}
+
+impl FaultRecovery {
+    pub(crate) fn new(policy: FaultPolicy) -> Self {
+        FaultRecovery {
+            policy,
+            replayed: None,
+            skipped: None,
+        }
+    }
+
+    /// Decides how to continue after the transfer at `index` was answered with FAULT.
+    ///
+    /// Returns the fault as error if the transfer, or the whole block, fails.
+    pub(crate) fn fault(
+        &mut self,
+        index: usize,
+        errors: StickyErrors,
+    ) -> Result<Resume, TransferFault> {
+        let fault = TransferFault {
+            index: Some(index),
+            errors,
+        };
+
+        match self.policy {
+            FaultPolicy::Replay { attempts } => {
+                let replays = match self.replayed {
+                    Some((replayed, replays)) if replayed == index => replays,
+                    _ => 0,
+                };
+
+                if replays >= attempts {
+                    return Err(fault);
+                }
+
+                log::debug!("{}, repeating it", fault);
+                self.replayed = Some((index, replays + 1));
+                Ok(Resume::Replay)
+            }
+            FaultPolicy::FailTransfer => {
+                log::warn!("{}, skipping it", fault);
+                self.skipped.get_or_insert(fault);
+                Ok(Resume::Skip)
+            }
+            FaultPolicy::AbortBatch => Err(fault),
+        }
+    }
+
+    /// Returns the first skipped transfer as error, once all transfers are done.
+    pub(crate) fn finish(self) -> Result<(), TransferFault> {
+        match self.skipped {
+            Some(fault) => Err(fault),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FaultPolicy, FaultRecovery, Resume, StickyErrors, TransferFault};
+    use crate::architecture::arm::dp::Ctrl;
+    use crate::DebugProbeError;
+
+    const STICKY: StickyErrors = StickyErrors {
+        write_error: false,
+        sticky_error: true,
+        overrun: false,
+    };
+
+    #[test]
+    fn sticky_flags_are_read_and_cleared() {
+        // CTRL/STAT after a bus error with overrun detection enabled.
+        let errors = StickyErrors::from_ctrl(&Ctrl::from(0xf000_0023));
+        assert_eq!(
+            errors,
+            StickyErrors {
+                write_error: false,
+                sticky_error: true,
+                overrun: true,
+            }
+        );
+        assert_eq!(errors.to_string(), "STICKYERR, STICKYORUN");
+        assert_eq!(u32::from(errors.abort()), 0b1_0100);
+    }
+
+    #[test]
+    fn replays_are_limited_per_transfer() {
+        let mut recovery = FaultRecovery::new(FaultPolicy::Replay { attempts: 2 });
+
+        assert_eq!(recovery.fault(3, STICKY), Ok(Resume::Replay));
+        assert_eq!(recovery.fault(3, STICKY), Ok(Resume::Replay));
+        assert_eq!(recovery.fault(5, STICKY), Ok(Resume::Replay));
+        assert_eq!(recovery.fault(5, STICKY), Ok(Resume::Replay));
+        assert_eq!(
+            recovery.fault(5, STICKY),
+            Err(TransferFault {
+                index: Some(5),
+                errors: STICKY,
+            })
+        );
+    }
+
+    #[test]
+    fn failed_transfers_are_reported_at_the_end() {
+        let mut recovery = FaultRecovery::new(FaultPolicy::FailTransfer);
+
+        assert_eq!(recovery.fault(1, STICKY), Ok(Resume::Skip));
+        assert_eq!(recovery.fault(4, STICKY), Ok(Resume::Skip));
+        assert_eq!(recovery.finish().unwrap_err().index, Some(1));
+    }
+
+    #[test]
+    fn faults_are_found_in_probe_errors() {
+        let fault = TransferFault {
+            index: Some(2),
+            errors: STICKY,
+        };
+        let error = DebugProbeError::from(fault);
+
+        assert_eq!(super::find_transfer_fault(&error), Some(fault));
+    }
+}
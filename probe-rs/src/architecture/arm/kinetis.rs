@@ -0,0 +1,158 @@
+//! The MDM-AP of NXP Kinetis and S32K chips.
+//!
+//! The MDM-AP stays accessible while the chip is secured or held in reset,
+//! when the AHB-AP can not be used.
+
+use super::communication_interface::ArmProbeInterface;
+use crate::Error;
+
+/// The index of the MDM-AP.
+pub(crate) const MDM_AP: u8 = 1;
+pub(crate) const MDM_AP_IDR: u32 = 0x001C_0000;
+pub(crate) const STATUS: u8 = 0x00;
+pub(crate) const CONTROL: u8 = 0x04;
+pub(crate) const STATUS_FLASH_READY: u32 = 1 << 1;
+pub(crate) const STATUS_SYSTEM_SECURITY: u32 = 1 << 2;
+/// Cleared while the system is in reset.
+pub(crate) const STATUS_SYSTEM_RESET: u32 = 1 << 3;
+pub(crate) const STATUS_MASS_ERASE_ENABLE: u32 = 1 << 5;
+pub(crate) const CONTROL_MASS_ERASE: u32 = 1 << 0;
+pub(crate) const CONTROL_SYSTEM_RESET: u32 = 1 << 3;
+pub(crate) const CONTROL_CORE_HOLD_RESET: u32 = 1 << 4;
+
+/// Returns `true` if the MDM-AP is present, and reports the chip as secured.
+pub(crate) fn is_secured(interface: &mut dyn ArmProbeInterface) -> Result<bool, Error> {
+    let idr = interface.read_raw_ap_register(MDM_AP, 0xFC)?;
+    if idr != MDM_AP_IDR {
+        log::debug!(
+            "Access port {} has the IDR {:#010x}, it is not a MDM-AP.",
+            MDM_AP,
+            idr
+        );
+        return Ok(false);
+    }
+
+    let status = interface.read_raw_ap_register(MDM_AP, STATUS)?;
+    log::debug!("MDM-AP status: {:#010x}", status);
+
+    Ok(status & STATUS_SYSTEM_SECURITY != 0)
+}
+
+/// Holds the core in reset while the rest of the system runs, or releases it again.
+pub(crate) fn hold_core_in_reset(
+    interface: &mut dyn ArmProbeInterface,
+    hold: bool,
+) -> Result<(), Error> {
+    let control = if hold { CONTROL_CORE_HOLD_RESET } else { 0 };
+
+    interface.write_raw_ap_register(MDM_AP, CONTROL, control)?;
+
+    Ok(())
+}
+
+/// The DPIDR of the debug port of the fake chips, a DPv1.
+#[cfg(test)]
+const DPIDR: u32 = 0x2ba0_1477;
+
+/// A chip with a MDM-AP for tests, which is secured if `secured` is set.
+///
+/// A mass erase unsecures the chip, if the chip allows it. The erase completes
+/// once the CONTROL register was read after requesting it.
+#[cfg(test)]
+pub(crate) fn fake_chip(secured: bool, mass_erase_enabled: bool) -> crate::probe::FakeDap {
+    use crate::architecture::arm::PortType;
+
+    let mdm_ap = PortType::AccessPort(u16::from(MDM_AP));
+    let mut secured = secured;
+    let mut control = 0;
+
+    crate::probe::FakeDap::new("Kinetis")
+        .with_register(PortType::DebugPort, 0x0, DPIDR)
+        .with_target(move |_, port, address, value| {
+            if port != mdm_ap {
+                return None;
+            }
+
+            match (address as u8, value) {
+                (0xFC, None) => Some(Ok(MDM_AP_IDR)),
+                (STATUS, None) => {
+                    let mut status = STATUS_FLASH_READY;
+                    if secured {
+                        status |= STATUS_SYSTEM_SECURITY;
+                    }
+                    if control & CONTROL_SYSTEM_RESET == 0 {
+                        status |= STATUS_SYSTEM_RESET;
+                    }
+                    if mass_erase_enabled {
+                        status |= STATUS_MASS_ERASE_ENABLE;
+                    }
+                    Some(Ok(status))
+                }
+                (CONTROL, None) => {
+                    let value = control;
+                    if control & CONTROL_MASS_ERASE != 0 {
+                        secured = false;
+                        control &= !CONTROL_MASS_ERASE;
+                    }
+                    Some(Ok(value))
+                }
+                (CONTROL, Some(value)) => {
+                    control = value;
+                    if !mass_erase_enabled {
+                        control &= !CONTROL_MASS_ERASE;
+                    }
+                    Some(Ok(0))
+                }
+                _ => None,
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::architecture::arm::{ArmCommunicationInterface, PortType};
+    use crate::probe::FakeDap;
+
+    fn interface(probe: FakeDap) -> ArmCommunicationInterface {
+        ArmCommunicationInterface::new(Box::new(probe), false).unwrap()
+    }
+
+    #[test]
+    fn secured_chip_is_detected() {
+        assert!(is_secured(&mut interface(fake_chip(true, true))).unwrap());
+        assert!(!is_secured(&mut interface(fake_chip(false, true))).unwrap());
+    }
+
+    #[test]
+    fn other_access_port_is_not_secured() {
+        // The security bit of a different AP at the same index is ignored.
+        let probe = FakeDap::new("Other chip")
+            .with_register(PortType::DebugPort, 0x0, DPIDR)
+            .with_register(PortType::AccessPort(1), 0xFC, 0x2477_0011)
+            .with_register(PortType::AccessPort(1), 0x00, STATUS_SYSTEM_SECURITY);
+
+        assert!(!is_secured(&mut interface(probe)).unwrap());
+    }
+
+    #[test]
+    fn core_is_held_through_the_mdm_ap() {
+        let probe = fake_chip(false, true);
+        let writes = probe.writes.clone();
+        let mut interface = interface(probe);
+
+        hold_core_in_reset(&mut interface, true).unwrap();
+        hold_core_in_reset(&mut interface, false).unwrap();
+
+        let control_writes: Vec<_> = writes
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|&&(port, address, _)| {
+                port == PortType::AccessPort(u16::from(MDM_AP)) && address == u16::from(CONTROL)
+            })
+            .map(|&(_, _, value)| value)
+            .collect();
+        assert_eq!(control_writes, vec![CONTROL_CORE_HOLD_RESET, 0]);
+    }
+}
@@ -0,0 +1,162 @@
+//! Detection of chips whose debug access is locked.
+//!
+//! A locked chip still answers on its debug port, but the access port of its cores
+//! can not be used. Vendor specific access ports, like the Nordic CTRL-AP, the Kinetis
+//! MDM-AP or the LPC55 debug mailbox, stay accessible and tell why, so that the attach
+//! fails with an [Error::DeviceLocked] which lists how the chip can be recovered,
+//! instead of a failed memory access.
+
+use super::communication_interface::ArmProbeInterface;
+use super::{kinetis, nordic};
+use crate::config::UnlockSequence;
+use crate::Error;
+
+use std::fmt;
+
+/// The index of the debug mailbox AP (DM-AP) of the LPC55.
+const LPC55_DM_AP: u8 = 2;
+const LPC55_DM_AP_IDR: u32 = 0x002A_0000;
+
+/// A way to recover a locked chip.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecoveryOption {
+    /// A mass erase with the unlock sequence, see [unlock](crate::flashing::unlock).
+    Unlock(UnlockSequence),
+    /// A debug authentication with a signed debug credential, through the debug mailbox.
+    DebugAuthentication,
+    /// A regression of the protection level or the product state with the tools of the vendor.
+    VendorTool,
+}
+
+impl fmt::Display for RecoveryOption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecoveryOption::Unlock(sequence) => {
+                write!(f, "a mass erase with the {:?} unlock sequence", sequence)
+            }
+            RecoveryOption::DebugAuthentication => {
+                write!(f, "a debug authentication with a signed debug credential")
+            }
+            RecoveryOption::VendorTool => write!(
+                f,
+                "a regression of the protection level with the tools of the vendor"
+            ),
+        }
+    }
+}
+
+/// The lock of a chip, which was found by [detect].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceLock {
+    /// The chip family whose protection was found, e.g. `nRF52`,
+    /// or the name of the target if only the access port of its core is disabled.
+    pub family_hint: Option<String>,
+    /// The ways to recover the chip, from the simplest one.
+    pub recovery_options: Vec<RecoveryOption>,
+}
+
+impl From<DeviceLock> for Error {
+    fn from(lock: DeviceLock) -> Self {
+        Error::DeviceLocked {
+            family_hint: lock.family_hint,
+            recovery_options: lock.recovery_options,
+        }
+    }
+}
+
+/// Checks whether the debug access of the chip is locked.
+///
+/// The vendor specific access ports of the `unlock_sequences` are asked whether their
+/// protection is enabled. Otherwise, the chip is considered locked if the access port
+/// `core_ap` of its first core has an IDR of zero.
+///
+/// Returns `None` if the chip is not locked.
+pub fn detect(
+    interface: &mut dyn ArmProbeInterface,
+    core_ap: u8,
+    unlock_sequences: impl IntoIterator<Item = UnlockSequence>,
+) -> Result<Option<DeviceLock>, Error> {
+    let unlock_sequences: Vec<_> = unlock_sequences.into_iter().collect();
+
+    for &sequence in &unlock_sequences {
+        let (family, locked) = match sequence {
+            UnlockSequence::NrfCtrlApEraseAll => (
+                "nRF52",
+                nordic::is_protected(interface, nordic::CTRL_AP, nordic::CTRL_AP_IDR)?,
+            ),
+            UnlockSequence::Nrf53CtrlApEraseAll => (
+                "nRF53",
+                nordic::is_protected(
+                    interface,
+                    nordic::NRF53_APPLICATION_CTRL_AP,
+                    nordic::CTRL_AP_V2_IDR,
+                )?,
+            ),
+            UnlockSequence::Nrf91CtrlApEraseAll => (
+                "nRF91",
+                nordic::is_protected(interface, nordic::NRF91_CTRL_AP, nordic::CTRL_AP_V2_IDR)?,
+            ),
+            UnlockSequence::KinetisMdmApMassErase => ("Kinetis", kinetis::is_secured(interface)?),
+            // The read protection of these chips does not disable the access port.
+            UnlockSequence::Stm32f4RdpRegression | UnlockSequence::WchLinkUnprotect => continue,
+        };
+
+        if locked {
+            return Ok(Some(DeviceLock {
+                family_hint: Some(family.to_owned()),
+                recovery_options: vec![RecoveryOption::Unlock(sequence)],
+            }));
+        }
+    }
+
+    let idr = interface.read_raw_ap_register(core_ap, 0xFC)?;
+    if idr != 0 {
+        return Ok(None);
+    }
+
+    log::debug!(
+        "Access port {} has an IDR of zero, but the debug port answers.",
+        core_ap
+    );
+
+    let mut recovery_options: Vec<_> = unlock_sequences
+        .into_iter()
+        .map(RecoveryOption::Unlock)
+        .collect();
+
+    let family_hint = if interface.read_raw_ap_register(LPC55_DM_AP, 0xFC)? == LPC55_DM_AP_IDR {
+        recovery_options.push(RecoveryOption::DebugAuthentication);
+        Some("LPC55".to_owned())
+    } else {
+        recovery_options.push(RecoveryOption::VendorTool);
+        None
+    };
+
+    Ok(Some(DeviceLock {
+        family_hint,
+        recovery_options,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_lists_recovery_options() {
+        let error = Error::from(DeviceLock {
+            family_hint: Some("LPC55".to_owned()),
+            recovery_options: vec![
+                RecoveryOption::Unlock(UnlockSequence::NrfCtrlApEraseAll),
+                RecoveryOption::DebugAuthentication,
+            ],
+        });
+
+        assert_eq!(
+            error.to_string(),
+            "The debug access of the target (LPC55) is locked. It can be recovered with \
+             a mass erase with the NrfCtrlApEraseAll unlock sequence, \
+             or a debug authentication with a signed debug credential"
+        );
+    }
+}
@@ -1,8 +1,16 @@
 use super::super::ap::{
-    APAccess, APRegister, AccessPortError, AddressIncrement, DataSize, MemoryAP, CSW, DRW, TAR,
+    APAccess, APBatchAccess, APBatchCommand, APRegister, AccessPortError, AddressIncrement,
+    DataSize, MemoryAP, BD0, BD1, BD2, CSW, DRW, TAR,
+};
+use super::super::Register;
+use crate::architecture::arm::{
+    dp::DPAccess,
+    fault::{find_transfer_fault, FaultPolicy, FaultRecovery, Resume, TransferFault},
+    ArmCommunicationInterface, MemoryApInformation,
+};
+use crate::{
+    AccessSize, CommunicationInterface, CoreRegister, CoreRegisterAddress, DebugProbeError, Error,
 };
-use crate::architecture::arm::{dp::DPAccess, ArmCommunicationInterface, MemoryApInformation};
-use crate::{CommunicationInterface, CoreRegister, CoreRegisterAddress, DebugProbeError, Error};
 use scroll::{Pread, Pwrite, LE};
 use std::convert::TryInto;
 use std::{
@@ -21,13 +29,77 @@ pub trait ArmProbe {
         value: u32,
     ) -> Result<(), Error>;
 
+    /// Read several core registers, see [Core::read_core_regs](crate::Core::read_core_regs).
+    fn read_core_regs(
+        &mut self,
+        ap: MemoryAP,
+        addrs: &[CoreRegisterAddress],
+    ) -> Vec<Result<u32, Error>> {
+        addrs
+            .iter()
+            .map(|&addr| self.read_core_reg(ap, addr))
+            .collect()
+    }
+
+    /// Write several core registers, see [Core::write_core_regs](crate::Core::write_core_regs).
+    fn write_core_regs(
+        &mut self,
+        ap: MemoryAP,
+        values: &[(CoreRegisterAddress, u32)],
+    ) -> Vec<Result<(), Error>> {
+        values
+            .iter()
+            .map(|&(addr, value)| self.write_core_reg(ap, addr, value))
+            .collect()
+    }
+
     fn read_8(&mut self, ap: MemoryAP, address: u32, data: &mut [u8]) -> Result<(), Error>;
     fn read_32(&mut self, ap: MemoryAP, address: u32, data: &mut [u32]) -> Result<(), Error>;
 
     fn write_8(&mut self, ap: MemoryAP, address: u32, data: &[u8]) -> Result<(), Error>;
     fn write_32(&mut self, ap: MemoryAP, address: u32, data: &[u32]) -> Result<(), Error>;
 
+    /// Read a block of memory as one of the repeated reads of a stream,
+    /// see [MemoryInterface::read_stream](crate::MemoryInterface::read_stream).
+    fn read_stream(
+        &mut self,
+        ap: MemoryAP,
+        address: u32,
+        data: &mut [u8],
+        _restart: bool,
+    ) -> Result<(), Error> {
+        self.read_8(ap, address, data)
+    }
+
+    /// Returns true if the memory can be accessed with the given size.
+    fn supports_access_size(&self, size: AccessSize) -> bool;
+
+    /// Read a block of memory, using only accesses of the given size.
+    ///
+    /// Returns [Error::AccessSizeNotSupported] if accesses of this size are not possible.
+    fn read_with_size(
+        &mut self,
+        ap: MemoryAP,
+        address: u32,
+        data: &mut [u8],
+        size: AccessSize,
+    ) -> Result<(), Error>;
+
+    /// Write a block of memory, using only accesses of the given size.
+    ///
+    /// Returns [Error::AccessSizeNotSupported] if accesses of this size are not possible.
+    fn write_with_size(
+        &mut self,
+        ap: MemoryAP,
+        address: u32,
+        data: &[u8],
+        size: AccessSize,
+    ) -> Result<(), Error>;
+
     fn flush(&mut self) -> Result<(), Error>;
+
+    /// Resets the target with the reset pin of the debug probe.
+    fn target_reset(&mut self) -> Result<(), Error>;
 }
 
 /// A struct to give access to a targets memory using a certain DAP.
@@ -41,11 +113,16 @@ where
 {
     interface: &'interface mut AP,
     only_32bit_data_size: bool,
+    supports_16bit_data_size: bool,
+    supports_64bit_data_size: bool,
 
     // Does the connected memory AP support the HNONSEC bit?
     // If it doesn't support it, bit 30 in the CSW register has
     // to be set to 1 at all times.
     supports_hnonsec: bool,
+
+    /// How block transfers continue after a transfer was answered with FAULT.
+    fault_policy: FaultPolicy,
 }
 
 impl<'interface> ADIMemoryInterface<'interface, ArmCommunicationInterface> {
@@ -55,8 +132,11 @@ impl<'interface> ADIMemoryInterface<'interface, ArmCommunicationInterface> {
         ap_information: &MemoryApInformation,
     ) -> Result<ADIMemoryInterface<'interface, ArmCommunicationInterface>, AccessPortError> {
         Ok(Self {
+            fault_policy: interface.fault_policy(),
             interface,
             only_32bit_data_size: ap_information.only_32bit_data_size,
+            supports_16bit_data_size: ap_information.supports_16bit_data_size,
+            supports_64bit_data_size: ap_information.supports_64bit_data_size,
             supports_hnonsec: ap_information.supports_hnonsec,
         })
     }
@@ -120,6 +200,93 @@ where
         Err(Error::Probe(DebugProbeError::Timeout))
     }
 
+    /// Performs the debug register accesses of `commands` through the banked data registers.
+    ///
+    /// With TAR set to DHCSR, the registers BD0, BD1 and BD2 access DHCSR, DCRSR and DCRDR,
+    /// so that all core register transfers can be sent to the probe together, instead of
+    /// setting TAR and waiting for each of them.
+    fn batch_debug_registers(
+        &mut self,
+        access_port: MemoryAP,
+        commands: &[APBatchCommand],
+    ) -> Result<Vec<u32>, Error>
+    where
+        AP: APBatchAccess<MemoryAP>,
+    {
+        let csw = self.build_csw_register(DataSize::U32);
+        let tar = TAR {
+            address: Dhcsr::ADDRESS,
+        };
+        self.write_ap_register(access_port, csw)?;
+        self.write_ap_register(access_port, tar)?;
+
+        Ok(self
+            .interface
+            .batch_ap_registers(access_port, BD0::APBANKSEL, commands)?)
+    }
+
+    /// Reads the core registers with one batch, see [ADIMemoryInterface::batch_debug_registers].
+    ///
+    /// DHCSR is read after each transfer, and the values are only returned up to the first
+    /// register whose transfer was not complete.
+    fn read_core_regs_batched(
+        &mut self,
+        access_port: MemoryAP,
+        addrs: &[CoreRegisterAddress],
+    ) -> Result<Vec<u32>, Error>
+    where
+        AP: APBatchAccess<MemoryAP>,
+    {
+        let mut commands = Vec::with_capacity(addrs.len() * 3);
+        for &addr in addrs {
+            let mut dcrsr_val = Dcrsr(0);
+            dcrsr_val.set_regwnr(false);
+            dcrsr_val.set_regsel(addr.into());
+
+            commands.push(APBatchCommand::Write(BD1::ADDRESS, dcrsr_val.into()));
+            commands.push(APBatchCommand::Read(BD0::ADDRESS));
+            commands.push(APBatchCommand::Read(BD2::ADDRESS));
+        }
+
+        let values = self.batch_debug_registers(access_port, &commands)?;
+
+        Ok(values
+            .chunks_exact(2)
+            .take_while(|values| Dhcsr(values[0]).s_regrdy())
+            .map(|values| values[1])
+            .collect())
+    }
+
+    /// Writes the core registers with one batch, see [ADIMemoryInterface::batch_debug_registers].
+    ///
+    /// Returns how many of the transfers were complete, up to the first one which was not.
+    fn write_core_regs_batched(
+        &mut self,
+        access_port: MemoryAP,
+        values: &[(CoreRegisterAddress, u32)],
+    ) -> Result<usize, Error>
+    where
+        AP: APBatchAccess<MemoryAP>,
+    {
+        let mut commands = Vec::with_capacity(values.len() * 3);
+        for &(addr, value) in values {
+            let mut dcrsr_val = Dcrsr(0);
+            dcrsr_val.set_regwnr(true);
+            dcrsr_val.set_regsel(addr.into());
+
+            commands.push(APBatchCommand::Write(BD2::ADDRESS, value));
+            commands.push(APBatchCommand::Write(BD1::ADDRESS, dcrsr_val.into()));
+            commands.push(APBatchCommand::Read(BD0::ADDRESS));
+        }
+
+        let dhcsr_values = self.batch_debug_registers(access_port, &commands)?;
+
+        Ok(dhcsr_values
+            .iter()
+            .take_while(|&&dhcsr| Dhcsr(dhcsr).s_regrdy())
+            .count())
+    }
+
     /// Read a 32 bit register on the given AP.
     fn read_ap_register<R>(
         &mut self,
@@ -184,6 +351,92 @@ where
             .map_err(AccessPortError::register_write_error::<R, _>)
     }
 
+    /// Reads `values` from DRW, with TAR starting at the address of the `block`.
+    ///
+    /// A transfer which is answered with FAULT is found from TAR,
+    /// and the block continues as the `recovery` says.
+    fn read_drw_block(
+        &mut self,
+        access_port: MemoryAP,
+        block: DrwBlock,
+        values: &mut [u32],
+        recovery: &mut FaultRecovery,
+    ) -> Result<(), AccessPortError> {
+        let mut done = 0;
+
+        while done < values.len() {
+            match self.read_ap_register_repeated(access_port, DRW { data: 0 }, &mut values[done..])
+            {
+                Ok(()) => break,
+                Err(error) => {
+                    done = self.resume_block(access_port, block, values.len(), error, recovery)?
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes `values` to DRW, see [ADIMemoryInterface::read_drw_block].
+    fn write_drw_block(
+        &mut self,
+        access_port: MemoryAP,
+        block: DrwBlock,
+        values: &[u32],
+        recovery: &mut FaultRecovery,
+    ) -> Result<(), AccessPortError> {
+        let mut done = 0;
+
+        while done < values.len() {
+            match self.write_ap_register_repeated(access_port, DRW { data: 0 }, &values[done..]) {
+                Ok(()) => break,
+                Err(error) => {
+                    done = self.resume_block(access_port, block, values.len(), error, recovery)?
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Finds the transfer of the `block` which was answered with FAULT, and sets TAR to the
+    /// transfer the block continues with. Returns the index of its first DRW access.
+    ///
+    /// `error` is returned if it is not a FAULT, or if the failed transfer can not be found.
+    fn resume_block(
+        &mut self,
+        access_port: MemoryAP,
+        block: DrwBlock,
+        accesses: usize,
+        error: AccessPortError,
+        recovery: &mut FaultRecovery,
+    ) -> Result<usize, AccessPortError> {
+        let fault = match find_transfer_fault(&error) {
+            Some(fault) => fault,
+            None => return Err(error),
+        };
+
+        let accesses_per_transfer = drw_accesses(block.size.bytes(), block.size);
+        let tar = self.read_ap_register(access_port, TAR::default())?.address;
+        let failed = (tar.wrapping_sub(block.address) / block.size.bytes() as u32) as usize;
+
+        if failed >= accesses / accesses_per_transfer {
+            log::debug!("TAR {:#010x} is outside of the failed block", tar);
+            return Err(error);
+        }
+
+        let next = match recovery.fault(block.first + failed, fault.errors) {
+            Ok(Resume::Replay) => failed,
+            Ok(Resume::Skip) => failed + 1,
+            Err(fault) => return Err(block_fault_error(&error, fault)),
+        };
+
+        let address = block.address + (next * block.size.bytes()) as u32;
+        self.write_ap_register(access_port, TAR { address })?;
+
+        Ok(next * accesses_per_transfer)
+    }
+
     /// Read a 32bit word at `addr`.
     ///
     /// The address where the read should be performed at has to be word aligned.
@@ -286,10 +539,16 @@ where
 
         let first_chunk_size_words = first_chunk_size_bytes / 4;
 
-        self.read_ap_register_repeated(
+        let mut recovery = FaultRecovery::new(self.fault_policy);
+        self.read_drw_block(
             access_port,
-            DRW { data: 0 },
+            DrwBlock {
+                address,
+                size: AccessSize::U32,
+                first: data_offset,
+            },
             &mut data[data_offset..first_chunk_size_words],
+            &mut recovery,
         )?;
 
         remaining_data_len -= first_chunk_size_words;
@@ -312,10 +571,15 @@ where
 
             let next_chunk_size_words = next_chunk_size_bytes / 4;
 
-            self.read_ap_register_repeated(
+            self.read_drw_block(
                 access_port,
-                DRW { data: 0 },
+                DrwBlock {
+                    address,
+                    size: AccessSize::U32,
+                    first: data_offset,
+                },
                 &mut data[data_offset..(data_offset + next_chunk_size_words)],
+                &mut recovery,
             )?;
 
             remaining_data_len -= next_chunk_size_words;
@@ -325,7 +589,62 @@ where
 
         log::debug!("Finished reading block");
 
-        Ok(())
+        recovery
+            .finish()
+            .map_err(AccessPortError::register_read_error::<DRW, _>)
+    }
+
+    /// Reads a block of 32 bit words like [ADIMemoryInterface::read_32], as one of the
+    /// repeated reads of a stream.
+    ///
+    /// CSW is only written if `restart` is set, the other reads of the stream use the CSW
+    /// of the first one. TAR is written for every block of the automatic address increment.
+    pub fn read_stream_32(
+        &mut self,
+        access_port: MemoryAP,
+        start_address: u32,
+        data: &mut [u32],
+        restart: bool,
+    ) -> Result<(), AccessPortError> {
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        if (start_address % 4) != 0 {
+            return Err(AccessPortError::alignment_error(start_address, 4));
+        }
+
+        if restart {
+            let csw = self.build_csw_register(DataSize::U32);
+            self.write_ap_register(access_port, csw)?;
+        }
+
+        let mut recovery = FaultRecovery::new(self.fault_policy);
+        let mut address = start_address;
+        let mut data_offset = 0;
+
+        while data_offset < data.len() {
+            let chunk_words = autoincrement_chunk_len(address, (data.len() - data_offset) * 4) / 4;
+
+            self.write_ap_register(access_port, TAR { address })?;
+            self.read_drw_block(
+                access_port,
+                DrwBlock {
+                    address,
+                    size: AccessSize::U32,
+                    first: data_offset,
+                },
+                &mut data[data_offset..data_offset + chunk_words],
+                &mut recovery,
+            )?;
+
+            address += (4 * chunk_words) as u32;
+            data_offset += chunk_words;
+        }
+
+        recovery
+            .finish()
+            .map_err(AccessPortError::register_read_error::<DRW, _>)
     }
 
     pub fn read_8(
@@ -474,10 +793,16 @@ where
 
         let first_chunk_size_words = first_chunk_size_bytes / 4;
 
-        self.write_ap_register_repeated(
+        let mut recovery = FaultRecovery::new(self.fault_policy);
+        self.write_drw_block(
             access_port,
-            DRW { data: 0 },
+            DrwBlock {
+                address,
+                size: AccessSize::U32,
+                first: data_offset,
+            },
             &data[data_offset..first_chunk_size_words],
+            &mut recovery,
         )?;
 
         remaining_data_len -= first_chunk_size_words;
@@ -500,10 +825,15 @@ where
 
             let next_chunk_size_words = next_chunk_size_bytes / 4;
 
-            self.write_ap_register_repeated(
+            self.write_drw_block(
                 access_port,
-                DRW { data: 0 },
+                DrwBlock {
+                    address,
+                    size: AccessSize::U32,
+                    first: data_offset,
+                },
                 &data[data_offset..(data_offset + next_chunk_size_words)],
+                &mut recovery,
             )?;
 
             remaining_data_len -= next_chunk_size_words;
@@ -516,7 +846,9 @@ where
 
         log::debug!("Finished writing block");
 
-        Ok(())
+        recovery
+            .finish()
+            .map_err(AccessPortError::register_write_error::<DRW, _>)
     }
 
     /// Write a block of 8bit words at `addr`.
@@ -568,6 +900,122 @@ where
 
         Ok(())
     }
+
+    /// Read a block of memory with accesses of the given size.
+    ///
+    /// The address is incremented by single transfers, so every access to DRW is exactly one
+    /// bus transaction of this size. A 64bit transfer takes two accesses to DRW, the lower
+    /// word first. `address` and the length of `data` have to be aligned to the size.
+    pub fn read_with_size(
+        &mut self,
+        access_port: MemoryAP,
+        address: u32,
+        data: &mut [u8],
+        size: AccessSize,
+    ) -> Result<(), AccessPortError> {
+        if !size.is_aligned(address, data.len()) {
+            return Err(AccessPortError::alignment_error(address, size.bytes()));
+        }
+
+        let csw = self.build_csw_register(data_size(size));
+        self.write_ap_register(access_port, csw)?;
+
+        let mut recovery = FaultRecovery::new(self.fault_policy);
+        let mut offset = 0;
+
+        while offset < data.len() {
+            let chunk_address = address + offset as u32;
+            let chunk_len = autoincrement_chunk_len(chunk_address, data.len() - offset);
+
+            self.write_ap_register(
+                access_port,
+                TAR {
+                    address: chunk_address,
+                },
+            )?;
+
+            let block = DrwBlock {
+                address: chunk_address,
+                size,
+                first: offset / size.bytes(),
+            };
+            let mut values = vec![0u32; drw_accesses(chunk_len, size)];
+            self.read_drw_block(access_port, block, &mut values, &mut recovery)?;
+
+            drw_values_to_bytes(
+                chunk_address,
+                &values,
+                &mut data[offset..offset + chunk_len],
+                size,
+            );
+
+            offset += chunk_len;
+        }
+
+        recovery
+            .finish()
+            .map_err(AccessPortError::register_read_error::<DRW, _>)
+    }
+
+    /// Write a block of memory with accesses of the given size.
+    ///
+    /// See [ADIMemoryInterface::read_with_size] for the requirements.
+    pub fn write_with_size(
+        &mut self,
+        access_port: MemoryAP,
+        address: u32,
+        data: &[u8],
+        size: AccessSize,
+    ) -> Result<(), AccessPortError> {
+        if !size.is_aligned(address, data.len()) {
+            return Err(AccessPortError::alignment_error(address, size.bytes()));
+        }
+
+        let csw = self.build_csw_register(data_size(size));
+        self.write_ap_register(access_port, csw)?;
+
+        let mut recovery = FaultRecovery::new(self.fault_policy);
+        let mut offset = 0;
+
+        while offset < data.len() {
+            let chunk_address = address + offset as u32;
+            let chunk_len = autoincrement_chunk_len(chunk_address, data.len() - offset);
+
+            self.write_ap_register(
+                access_port,
+                TAR {
+                    address: chunk_address,
+                },
+            )?;
+
+            let block = DrwBlock {
+                address: chunk_address,
+                size,
+                first: offset / size.bytes(),
+            };
+            let values =
+                bytes_to_drw_values(chunk_address, &data[offset..offset + chunk_len], size);
+            self.write_drw_block(access_port, block, &values, &mut recovery)?;
+
+            offset += chunk_len;
+        }
+
+        // Ensure the last write is actually performed
+        self.write_ap_register(access_port, csw)?;
+
+        recovery
+            .finish()
+            .map_err(AccessPortError::register_write_error::<DRW, _>)
+    }
+
+    fn supports_data_size(&self, size: AccessSize) -> bool {
+        match size {
+            AccessSize::U8 => !self.only_32bit_data_size,
+            AccessSize::U16 => self.supports_16bit_data_size,
+            AccessSize::U32 => true,
+            AccessSize::U64 => self.supports_64bit_data_size,
+        }
+    }
 }
 
 impl<AP> ArmProbe for ADIMemoryInterface<'_, AP>
@@ -576,6 +1024,7 @@ where
         + APAccess<MemoryAP, CSW>
         + APAccess<MemoryAP, TAR>
         + APAccess<MemoryAP, DRW>
+        + APBatchAccess<MemoryAP>
         + DPAccess,
 {
     fn read_core_reg(&mut self, ap: MemoryAP, addr: CoreRegisterAddress) -> Result<u32, Error> {
@@ -613,6 +1062,48 @@ where
         Ok(())
     }
 
+    fn read_core_regs(
+        &mut self,
+        ap: MemoryAP,
+        addrs: &[CoreRegisterAddress],
+    ) -> Vec<Result<u32, Error>> {
+        let mut results: Vec<_> = match self.read_core_regs_batched(ap, addrs) {
+            Ok(values) => values.into_iter().map(Ok).collect(),
+            Err(error) => {
+                log::debug!("Reading the core registers in a batch failed: {}", error);
+                Vec::new()
+            }
+        };
+
+        // The registers after a transfer which was not complete are read one by one.
+        for &addr in &addrs[results.len()..] {
+            results.push(ArmProbe::read_core_reg(self, ap, addr));
+        }
+
+        results
+    }
+
+    fn write_core_regs(
+        &mut self,
+        ap: MemoryAP,
+        values: &[(CoreRegisterAddress, u32)],
+    ) -> Vec<Result<(), Error>> {
+        let written = match self.write_core_regs_batched(ap, values) {
+            Ok(written) => written,
+            Err(error) => {
+                log::debug!("Writing the core registers in a batch failed: {}", error);
+                0
+            }
+        };
+
+        let mut results: Vec<_> = (0..written).map(|_| Ok(())).collect();
+        for &(addr, value) in &values[written..] {
+            results.push(ArmProbe::write_core_reg(self, ap, addr, value));
+        }
+
+        results
+    }
+
     fn read_8(&mut self, ap: MemoryAP, address: u32, data: &mut [u8]) -> Result<(), Error> {
         if data.len() == 1 {
             data[0] = self.read_word_8(ap, address)?;
@@ -633,6 +1124,26 @@ where
         Ok(())
     }
 
+    fn read_stream(
+        &mut self,
+        ap: MemoryAP,
+        address: u32,
+        data: &mut [u8],
+        restart: bool,
+    ) -> Result<(), Error> {
+        if address % 4 != 0 || data.len() % 4 != 0 {
+            return ArmProbe::read_8(self, ap, address, data);
+        }
+
+        let mut words = vec![0u32; data.len() / 4];
+        self.read_stream_32(ap, address, &mut words, restart)?;
+        for (bytes, word) in data.chunks_exact_mut(4).zip(&words) {
+            bytes.copy_from_slice(&word.to_le_bytes());
+        }
+
+        Ok(())
+    }
+
     fn write_8(&mut self, ap: MemoryAP, address: u32, data: &[u8]) -> Result<(), Error> {
         if data.len() == 1 {
             self.write_word_8(ap, address, data[0])?;
@@ -653,11 +1164,53 @@ where
         Ok(())
     }
 
+    fn supports_access_size(&self, size: AccessSize) -> bool {
+        self.supports_data_size(size)
+    }
+
+    fn read_with_size(
+        &mut self,
+        ap: MemoryAP,
+        address: u32,
+        data: &mut [u8],
+        size: AccessSize,
+    ) -> Result<(), Error> {
+        if !self.supports_data_size(size) {
+            return Err(Error::AccessSizeNotSupported(size));
+        }
+
+        self.read_with_size(ap, address, data, size)?;
+
+        Ok(())
+    }
+
+    fn write_with_size(
+        &mut self,
+        ap: MemoryAP,
+        address: u32,
+        data: &[u8],
+        size: AccessSize,
+    ) -> Result<(), Error> {
+        if !self.supports_data_size(size) {
+            return Err(Error::AccessSizeNotSupported(size));
+        }
+
+        self.write_with_size(ap, address, data, size)?;
+
+        Ok(())
+    }
+
     fn flush(&mut self) -> Result<(), Error> {
         self.interface.flush()?;
 
         Ok(())
     }
+
+    fn target_reset(&mut self) -> Result<(), Error> {
+        self.interface.target_reset()?;
+
+        Ok(())
+    }
 }
 
 bitfield! {
@@ -751,6 +1304,97 @@ impl CoreRegister for Dcrdr {
     const NAME: &'static str = "DCRDR";
 }
 
+/// A block transfer through DRW, with TAR incremented after every transfer.
+#[derive(Debug, Clone, Copy)]
+struct DrwBlock {
+    /// The address of the first transfer.
+    address: u32,
+    size: AccessSize,
+    /// The index of the first transfer in the whole access, which is reported in a [TransferFault].
+    first: usize,
+}
+
+/// Reports the `fault` of a block transfer like the `error` which was returned for it.
+fn block_fault_error(error: &AccessPortError, fault: TransferFault) -> AccessPortError {
+    match error {
+        AccessPortError::RegisterWriteError { .. } => {
+            AccessPortError::register_write_error::<DRW, _>(fault)
+        }
+        _ => AccessPortError::register_read_error::<DRW, _>(fault),
+    }
+}
+
+fn data_size(size: AccessSize) -> DataSize {
+    match size {
+        AccessSize::U8 => DataSize::U8,
+        AccessSize::U16 => DataSize::U16,
+        AccessSize::U32 => DataSize::U32,
+        AccessSize::U64 => DataSize::U64,
+    }
+}
+
+/// Returns how many of the `remaining` bytes starting at `address` can be transferred before
+/// the automatic increment of TAR wraps around, which it only does in the lowest 10 bits.
+fn autoincrement_chunk_len(address: u32, remaining: usize) -> usize {
+    const AUTOINCREMENT_BLOCK: usize = 0x400;
+
+    std::cmp::min(
+        AUTOINCREMENT_BLOCK - (address as usize % AUTOINCREMENT_BLOCK),
+        remaining,
+    )
+}
+
+/// The number of DRW accesses needed to transfer `len` bytes with accesses of the given size.
+fn drw_accesses(len: usize, size: AccessSize) -> usize {
+    match size {
+        AccessSize::U8 | AccessSize::U16 => len / size.bytes(),
+        AccessSize::U32 | AccessSize::U64 => len / 4,
+    }
+}
+
+/// Extracts the data of narrow accesses from the byte lanes of DRW.
+///
+/// See "Arm Debug Interface Architecture Specification ADIv5.0 to ADIv5.2", C2.2.6
+fn drw_values_to_bytes(address: u32, values: &[u32], data: &mut [u8], size: AccessSize) {
+    match size {
+        AccessSize::U8 | AccessSize::U16 => {
+            for (index, (value, chunk)) in values
+                .iter()
+                .zip(data.chunks_exact_mut(size.bytes()))
+                .enumerate()
+            {
+                let lane = (address as usize + index * size.bytes()) % 4;
+                chunk.copy_from_slice(&value.to_le_bytes()[lane..lane + size.bytes()]);
+            }
+        }
+        AccessSize::U32 | AccessSize::U64 => {
+            for (value, chunk) in values.iter().zip(data.chunks_exact_mut(4)) {
+                chunk.copy_from_slice(&value.to_le_bytes());
+            }
+        }
+    }
+}
+
+/// Places the data of narrow accesses in the byte lanes of DRW.
+fn bytes_to_drw_values(address: u32, data: &[u8], size: AccessSize) -> Vec<u32> {
+    match size {
+        AccessSize::U8 | AccessSize::U16 => data
+            .chunks_exact(size.bytes())
+            .enumerate()
+            .map(|(index, chunk)| {
+                let lane = (address as usize + index * size.bytes()) % 4;
+                let mut value = [0u8; 4];
+                value[lane..lane + size.bytes()].copy_from_slice(chunk);
+                u32::from_le_bytes(value)
+            })
+            .collect(),
+        AccessSize::U32 | AccessSize::U64 => data
+            .chunks_exact(4)
+            .map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap()))
+            .collect(),
+    }
+}
+
 /// Calculates a 32-bit word aligned range from an address/length pair.
 fn aligned_range(address: u32, len: usize) -> Result<Range<u32>, AccessPortError> {
     // Round start address down to the nearest multiple of 4
@@ -773,7 +1417,9 @@ fn aligned_range(address: u32, len: usize) -> Result<Range<u32>, AccessPortError
 #[cfg(test)]
 mod tests {
     use super::super::super::ap::memory_ap::mock::MockMemoryAP;
-    use super::ADIMemoryInterface;
+    use super::{bytes_to_drw_values, drw_values_to_bytes, ADIMemoryInterface, ArmProbe};
+    use crate::architecture::arm::FaultPolicy;
+    use crate::{AccessSize, Error};
 
     impl<'interface> ADIMemoryInterface<'interface, MockMemoryAP> {
         /// Creates a new MemoryInterface for given AccessPort.
@@ -781,7 +1427,10 @@ mod tests {
             Self {
                 interface: mock,
                 only_32bit_data_size: false,
+                supports_16bit_data_size: true,
+                supports_64bit_data_size: false,
                 supports_hnonsec: false,
+                fault_policy: FaultPolicy::default(),
             }
         }
 
@@ -901,6 +1550,23 @@ mod tests {
         }
     }
 
+    #[test]
+    fn read_stream_keeps_csw() {
+        let mut mock = MockMemoryAP::with_pattern();
+        mock.memory[..16].copy_from_slice(DATA8);
+        let mut mi = ADIMemoryInterface::<MockMemoryAP>::new(&mut mock);
+
+        let mut data = [0u8; 16];
+        ArmProbe::read_stream(&mut mi, 0.into(), 0, &mut data, true).expect("read_stream failed");
+        assert_eq!(data, DATA8);
+
+        // The continued stream uses the CSW of the first read.
+        mi.interface.memory[0] = 0xaa;
+        ArmProbe::read_stream(&mut mi, 0.into(), 0, &mut data, false).expect("read_stream failed");
+        assert_eq!(data[0], 0xaa);
+        assert_eq!(&data[1..], &DATA8[1..]);
+    }
+
     #[test]
     fn read_8() {
         let mut mock = MockMemoryAP::with_pattern();
@@ -1002,4 +1668,89 @@ mod tests {
 
         let _ = aligned_range(0xfffffff9, 4);
     }
+
+    #[test]
+    fn read_with_size_16() {
+        let mut mock = MockMemoryAP::with_pattern();
+        mock.memory[..DATA8.len()].copy_from_slice(DATA8);
+        let mut mi = ADIMemoryInterface::<MockMemoryAP>::new(&mut mock);
+
+        for &address in &[0, 2, 6] {
+            let mut data = [0u8; 6];
+            mi.read_with_size(0.into(), address, &mut data, AccessSize::U16)
+                .unwrap_or_else(|_| panic!("read_with_size failed, address = {}", address));
+
+            assert_eq!(
+                &data[..],
+                &DATA8[address as usize..address as usize + 6],
+                "address = {}",
+                address
+            );
+        }
+    }
+
+    #[test]
+    fn write_with_size_8_and_16() {
+        for &(address, size) in &[
+            (1, AccessSize::U8),
+            (3, AccessSize::U8),
+            (2, AccessSize::U16),
+        ] {
+            let mut mock = MockMemoryAP::with_pattern();
+            let mut mi = ADIMemoryInterface::<MockMemoryAP>::new(&mut mock);
+
+            let mut expected = Vec::from(mi.mock_memory());
+            expected[address as usize..address as usize + 6].copy_from_slice(&DATA8[..6]);
+
+            mi.write_with_size(0.into(), address, &DATA8[..6], size)
+                .unwrap_or_else(|_| panic!("write_with_size failed, address = {}", address));
+            assert_eq!(
+                mi.mock_memory(),
+                expected.as_slice(),
+                "address = {}",
+                address
+            );
+        }
+    }
+
+    #[test]
+    fn unaligned_sized_access_should_error() {
+        let mut mock = MockMemoryAP::with_pattern();
+        let mut mi = ADIMemoryInterface::<MockMemoryAP>::new(&mut mock);
+
+        assert!(mi
+            .read_with_size(0.into(), 1, &mut [0u8; 2], AccessSize::U16)
+            .is_err());
+        assert!(mi
+            .write_with_size(0.into(), 4, &[0u8; 6], AccessSize::U32)
+            .is_err());
+    }
+
+    #[test]
+    fn unsupported_size_is_not_split() {
+        let mut mock = MockMemoryAP::with_pattern();
+        let mut mi = ADIMemoryInterface::<MockMemoryAP>::new(&mut mock);
+        let expected = Vec::from(mi.mock_memory());
+
+        let result = ArmProbe::write_with_size(&mut mi, 0.into(), 0, &[0u8; 8], AccessSize::U64);
+
+        assert!(matches!(
+            result,
+            Err(Error::AccessSizeNotSupported(AccessSize::U64))
+        ));
+        assert_eq!(mi.mock_memory(), expected.as_slice());
+    }
+
+    #[test]
+    fn drw_lanes_of_64bit_accesses() {
+        let data = &DATA8[..8];
+        let values = bytes_to_drw_values(0, data, AccessSize::U64);
+
+        assert_eq!(values, &DATA32[..2]);
+
+        let mut read = [0u8; 8];
+        drw_values_to_bytes(0, &values, &mut read, AccessSize::U64);
+
+        assert_eq!(&read[..], data);
+    }
 }
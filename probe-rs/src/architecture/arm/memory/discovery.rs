@@ -0,0 +1,321 @@
+//! Discovery of all CoreSight components which are reachable through the memory APs.
+//!
+//! The ROM tables are walked recursively, including class 9 ROM tables, and each component is
+//! reported with its identification registers. A component whose registers can not be read,
+//! e.g. because the access to it has not been granted yet like on PSoC 6 devices, is still
+//! reported, without its identification.
+
+use super::romtable::{ComponentInformationReader, PeripheralID, RawComponent};
+use crate::architecture::arm::ap::{GenericAP, MemoryAP};
+use crate::architecture::arm::communication_interface::{ApInformation, ArmProbeInterface};
+use crate::{Error, Memory};
+
+use std::collections::HashSet;
+
+/// The deepest nesting of ROM tables which is followed, to end a scan of inconsistent tables.
+const MAX_DEPTH: usize = 8;
+
+/// The offset of the DEVARCH register of a CoreSight component.
+const DEVARCH: u32 = 0xFBC;
+/// The offset of the DEVID register of a CoreSight component.
+const DEVID: u32 = 0xFC8;
+
+/// The number of entries of a class 1 ROM table.
+const CLASS1_ENTRIES: u32 = 960;
+/// The number of entries of a class 9 ROM table with 32 bit entries.
+const CLASS9_ENTRIES: u32 = 512;
+
+/// The class of a CoreSight component, from its component ID.
+///
+/// Described in table D1-2 in the ADIv5.2 spec.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComponentClass {
+    GenericVerification,
+    /// A class 1 ROM table.
+    RomTable,
+    /// A CoreSight component, which can be a class 9 ROM table too, see [DeviceArchitecture::is_rom_table].
+    CoreSight,
+    PeripheralTestBlock,
+    GenericIP,
+    CoreLinkOrPrimeCellOrSystem,
+}
+
+impl ComponentClass {
+    /// The name of the class, e.g. `class1_rom_table`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ComponentClass::GenericVerification => "generic_verification_component",
+            ComponentClass::RomTable => "class1_rom_table",
+            ComponentClass::CoreSight => "coresight_component",
+            ComponentClass::PeripheralTestBlock => "peripheral_test_block",
+            ComponentClass::GenericIP => "generic_ip_component",
+            ComponentClass::CoreLinkOrPrimeCellOrSystem => "corelink_or_primecell_or_system",
+        }
+    }
+}
+
+impl From<RawComponent> for ComponentClass {
+    fn from(class: RawComponent) -> Self {
+        match class {
+            RawComponent::GenericVerificationComponent => ComponentClass::GenericVerification,
+            RawComponent::RomTable => ComponentClass::RomTable,
+            RawComponent::CoreSightComponent => ComponentClass::CoreSight,
+            RawComponent::PeripheralTestBlock => ComponentClass::PeripheralTestBlock,
+            RawComponent::GenericIPComponent => ComponentClass::GenericIP,
+            RawComponent::CoreLinkOrPrimeCellOrSystemComponent => {
+                ComponentClass::CoreLinkOrPrimeCellOrSystem
+            }
+        }
+    }
+}
+
+/// The contents of the device architecture register (DEVARCH) of a CoreSight component.
+///
+/// Described in section D1.2.2 of the ADIv5.2 spec.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceArchitecture {
+    /// The designer of the architecture.
+    pub architect: jep106::JEP106Code,
+    /// The revision of the architecture.
+    pub revision: u8,
+    /// The ID of the architecture, e.g. `0x0af7` for a ROM table.
+    pub archid: u16,
+}
+
+impl DeviceArchitecture {
+    /// Decodes the DEVARCH register, which is only valid if its PRESENT bit is set.
+    fn from_raw(devarch: u32) -> Option<Self> {
+        if devarch & (1 << 20) == 0 {
+            return None;
+        }
+
+        Some(DeviceArchitecture {
+            architect: jep106::JEP106Code::new(
+                ((devarch >> 28) & 0xf) as u8,
+                ((devarch >> 21) & 0x7f) as u8,
+            ),
+            revision: ((devarch >> 16) & 0xf) as u8,
+            archid: (devarch & 0xffff) as u16,
+        })
+    }
+
+    /// Returns `true` if the component is a class 9 ROM table.
+    pub fn is_rom_table(&self) -> bool {
+        self.architect == jep106::JEP106Code::new(4, 0x3b) && self.archid == 0x0af7
+    }
+}
+
+/// A CoreSight component which was found by [discover_components].
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoresightComponent {
+    /// The number of the memory AP through which the component is accessed.
+    pub access_port: u8,
+    /// The base address of the component.
+    pub address: u64,
+    /// The number of ROM tables the component is nested in, `0` for the ROM table of the AP.
+    pub depth: usize,
+    /// The power domain of the component, if the ROM table entry names one.
+    pub power_domain: Option<u8>,
+    /// The class of the component, or `None` if its identification registers could not be read.
+    pub class: Option<ComponentClass>,
+    /// The peripheral ID of the component, or `None` if its identification registers could not be read.
+    pub peripheral_id: Option<PeripheralID>,
+    /// The device architecture of CoreSight components which implement the DEVARCH register.
+    pub device_architecture: Option<DeviceArchitecture>,
+}
+
+impl CoresightComponent {
+    /// Returns `true` if the component is a class 1 or class 9 ROM table.
+    pub fn is_rom_table(&self) -> bool {
+        self.class == Some(ComponentClass::RomTable)
+            || self
+                .device_architecture
+                .map_or(false, |architecture| architecture.is_rom_table())
+    }
+
+    /// The name of the designer of the component, if it is known.
+    pub fn designer(&self) -> Option<&'static str> {
+        self.peripheral_id
+            .as_ref()
+            .and_then(PeripheralID::jep106)
+            .and_then(|code| code.get())
+    }
+}
+
+/// Finds the CoreSight components in the ROM tables of all memory APs.
+///
+/// The components are listed in the order of the ROM tables, each ROM table is followed by
+/// the components it contains.
+pub fn discover_components(
+    interface: &mut dyn ArmProbeInterface,
+) -> Result<Vec<CoresightComponent>, Error> {
+    let mut components = vec![];
+
+    for ap_index in 0..interface.num_access_ports() {
+        let access_port = GenericAP::from(ap_index as u8);
+        let base_address = match interface.ap_information(access_port) {
+            Some(ApInformation::MemoryAp(information)) => information.debug_base_address,
+            _ => continue,
+        };
+
+        let access_port: MemoryAP = access_port.into();
+        let mut memory = interface.memory_interface(access_port)?;
+
+        let mut scan = Scan {
+            memory: &mut memory,
+            access_port: ap_index as u8,
+            visited: HashSet::new(),
+            components: &mut components,
+        };
+        scan.component(base_address, 0, None);
+    }
+
+    Ok(components)
+}
+
+/// The state of the scan of the ROM tables of one AP.
+struct Scan<'probe, 'memory, 'components> {
+    memory: &'memory mut Memory<'probe>,
+    access_port: u8,
+    /// The ROM tables which were scanned, to not follow a loop.
+    visited: HashSet<u64>,
+    components: &'components mut Vec<CoresightComponent>,
+}
+
+impl Scan<'_, '_, '_> {
+    /// Adds the component at `address`, and the components it contains if it is a ROM table.
+    fn component(&mut self, address: u64, depth: usize, power_domain: Option<u8>) {
+        let mut component = CoresightComponent {
+            access_port: self.access_port,
+            address,
+            depth,
+            power_domain,
+            class: None,
+            peripheral_id: None,
+            device_architecture: None,
+        };
+
+        let mut reader = ComponentInformationReader::new(address, self.memory);
+        match (reader.component_class(), reader.peripheral_id()) {
+            (Ok(class), Ok(peripheral_id)) => {
+                component.class = Some(class.into());
+                component.peripheral_id = Some(peripheral_id);
+            }
+            (Err(e), _) | (_, Err(e)) => {
+                log::warn!(
+                    "The component at {:#010x} of AP {} can not be identified: {}",
+                    address,
+                    self.access_port,
+                    e
+                );
+                self.components.push(component);
+                return;
+            }
+        }
+
+        if component.class == Some(ComponentClass::CoreSight) {
+            component.device_architecture = self
+                .read(address, DEVARCH)
+                .ok()
+                .and_then(DeviceArchitecture::from_raw);
+        }
+
+        let is_rom_table = component.is_rom_table();
+        let class = component.class;
+        self.components.push(component);
+
+        if !is_rom_table || depth >= MAX_DEPTH || !self.visited.insert(address) {
+            return;
+        }
+
+        let result = if class == Some(ComponentClass::RomTable) {
+            self.class1_rom_table(address, depth)
+        } else {
+            self.class9_rom_table(address, depth)
+        };
+        if let Err(e) = result {
+            log::warn!(
+                "The ROM table at {:#010x} of AP {} could not be read completely: {}",
+                address,
+                self.access_port,
+                e
+            );
+        }
+    }
+
+    fn class1_rom_table(&mut self, address: u64, depth: usize) -> Result<(), Error> {
+        for index in 0..CLASS1_ENTRIES {
+            let entry = self.read(address, index * 4)?;
+            if entry == 0 {
+                break;
+            }
+
+            if entry & 1 != 0 {
+                self.entry(address, entry, depth);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn class9_rom_table(&mut self, address: u64, depth: usize) -> Result<(), Error> {
+        // Only tables with 32 bit entries are supported.
+        if self.read(address, DEVID)? & 0xf != 0 {
+            log::warn!(
+                "The ROM table at {:#010x} has 64 bit entries, which are not supported.",
+                address
+            );
+            return Ok(());
+        }
+
+        for index in 0..CLASS9_ENTRIES {
+            let entry = self.read(address, index * 4)?;
+            match entry & 0b11 {
+                0b00 => break,
+                0b11 => self.entry(address, entry, depth),
+                // The entry is not present, but the table goes on.
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Adds the component of the ROM table `entry`, which is given relative to the table at `address`.
+    fn entry(&mut self, address: u64, entry: u32, depth: usize) {
+        let component_address = (address as u32).wrapping_add(entry & 0xffff_f000);
+        let power_domain = if entry & 0b100 != 0 {
+            Some(((entry >> 4) & 0x1f) as u8)
+        } else {
+            None
+        };
+
+        self.component(u64::from(component_address), depth + 1, power_domain);
+    }
+
+    fn read(&mut self, address: u64, offset: u32) -> Result<u32, Error> {
+        self.memory.read_word_32(address as u32 + offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DeviceArchitecture;
+
+    #[test]
+    fn device_architecture_is_decoded() {
+        // The ROM table of a Cortex-M33.
+        let architecture = DeviceArchitecture::from_raw(0x4770_0af7).unwrap();
+        assert_eq!(architecture.architect, jep106::JEP106Code::new(4, 0x3b));
+        assert_eq!(architecture.revision, 0);
+        assert_eq!(architecture.archid, 0x0af7);
+        assert!(architecture.is_rom_table());
+
+        // The DWT of a Cortex-M33.
+        let architecture = DeviceArchitecture::from_raw(0x4771_1a02).unwrap();
+        assert_eq!(architecture.revision, 1);
+        assert!(!architecture.is_rom_table());
+
+        assert_eq!(DeviceArchitecture::from_raw(0x4760_0af7), None);
+    }
+}
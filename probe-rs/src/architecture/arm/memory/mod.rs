@@ -1,8 +1,10 @@
 pub(crate) mod adi_v5_memory_interface;
+mod discovery;
 pub(crate) mod romtable;
 
 use super::ap::AccessPortError;
-pub use romtable::Component;
+pub use discovery::{discover_components, ComponentClass, CoresightComponent, DeviceArchitecture};
+pub use romtable::{Component, PeripheralID};
 
 pub trait ToMemoryReadSize: Into<u32> + Copy {
     /// The alignment mask that is required to test for properly aligned memory.
@@ -284,7 +284,7 @@ impl<'probe: 'memory, 'memory> ComponentInformationReader<'probe, 'memory> {
     /// Reads the component class from a component information table.
     ///
     /// This function does a direct memory access and is meant for internal use only.
-    fn component_class(&mut self) -> Result<RawComponent, RomTableError> {
+    pub(super) fn component_class(&mut self) -> Result<RawComponent, RomTableError> {
         #![allow(clippy::verbose_bit_mask)]
         let mut cidr = [0u32; 4];
 
@@ -322,7 +322,7 @@ impl<'probe: 'memory, 'memory> ComponentInformationReader<'probe, 'memory> {
     /// Reads the peripheral ID from a component information table.
     ///
     /// This function does a direct memory access and is meant for internal use only.
-    fn peripheral_id(&mut self) -> Result<PeripheralID, RomTableError> {
+    pub(super) fn peripheral_id(&mut self) -> Result<PeripheralID, RomTableError> {
         let mut data = [0u32; 8];
 
         let peripheral_id_address = self.base_address + 0xFD0;
@@ -364,7 +364,7 @@ impl<'probe: 'memory, 'memory> ComponentInformationReader<'probe, 'memory> {
 ///
 /// Described in table D1-2 in the ADIv5.2 spec.
 #[derive(Primitive, Debug, PartialEq)]
-enum RawComponent {
+pub(super) enum RawComponent {
     GenericVerificationComponent = 0,
     RomTable = 1,
     CoreSightComponent = 9,
@@ -548,7 +548,7 @@ impl<'a> Iterator for ComponentIter<'a> {
 }
 
 /// Indicates component modifications by the implementor of a CoreSight component.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 enum ComponentModification {
     /// Indicates that no specific modification was made.
     No,
@@ -560,7 +560,7 @@ enum ComponentModification {
 ///
 /// Described in section D1.2.2 of the ADIv5.2 spec.
 #[allow(non_snake_case)]
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct PeripheralID {
     /// Indicates minor errata fixes by the component `designer`.
     REVAND: u8,
@@ -626,4 +626,27 @@ impl PeripheralID {
     pub fn part(&self) -> u16 {
         self.PART
     }
+
+    /// Returns the major REVISION of the component.
+    pub fn revision(&self) -> u8 {
+        self.REVISION
+    }
+
+    /// Returns the REVAND of the component, which indicates minor errata fixes.
+    pub fn revand(&self) -> u8 {
+        self.REVAND
+    }
+
+    /// Returns the customer modification number of the component, if it was modified by its implementor.
+    pub fn modification(&self) -> Option<u8> {
+        match self.CMOD {
+            ComponentModification::No => None,
+            ComponentModification::Yes(modification) => Some(modification),
+        }
+    }
+
+    /// Returns the number of 4 KB blocks the component occupies.
+    pub fn size(&self) -> u8 {
+        self.SIZE
+    }
 }
@@ -3,13 +3,20 @@ pub(crate) mod communication_interface;
 pub mod component;
 pub(crate) mod core;
 pub mod dp;
+pub(crate) mod fault;
+pub(crate) mod kinetis;
+pub mod lock;
 pub mod memory;
+pub(crate) mod nordic;
+pub(crate) mod sequences;
 pub mod swo;
 
 pub use communication_interface::{
     ApInformation, ArmChipInfo, ArmCommunicationInterface, DAPAccess, DapError, MemoryApInformation,
 };
 pub use communication_interface::{PortType, Register};
+pub use fault::{FaultPolicy, StickyErrors, TransferFault};
+pub use sequences::SequenceError;
 pub use swo::{SwoAccess, SwoConfig, SwoMode};
 
 pub use self::core::m0;
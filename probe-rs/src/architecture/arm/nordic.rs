@@ -0,0 +1,47 @@
+//! The CTRL-AP of Nordic nRF chips.
+//!
+//! The CTRL-AP stays accessible while the access port protection (APPROTECT) of the chip
+//! is enabled, and can erase the chip to remove the protection.
+
+use super::communication_interface::ArmProbeInterface;
+use crate::Error;
+
+/// The index of the CTRL-AP of the nRF52.
+pub(crate) const CTRL_AP: u8 = 1;
+pub(crate) const CTRL_AP_IDR: u32 = 0x0288_0000;
+/// The CTRL-APs of the application and the network core of the nRF53.
+pub(crate) const NRF53_APPLICATION_CTRL_AP: u8 = 2;
+pub(crate) const NRF53_NETWORK_CTRL_AP: u8 = 3;
+/// The CTRL-AP of the nRF91.
+pub(crate) const NRF91_CTRL_AP: u8 = 4;
+/// The IDR of the CTRL-APs of the nRF53 and the nRF91.
+pub(crate) const CTRL_AP_V2_IDR: u32 = 0x1288_0000;
+pub(crate) const RESET: u8 = 0x00;
+pub(crate) const ERASEALL: u8 = 0x04;
+pub(crate) const ERASEALLSTATUS: u8 = 0x08;
+pub(crate) const APPROTECTSTATUS: u8 = 0x0C;
+/// Cleared while the access port protection is enabled.
+pub(crate) const APPROTECTSTATUS_DISABLED: u32 = 1 << 0;
+
+/// Returns `true` if the CTRL-AP `port` with the IDR `idr` is present,
+/// and reports the access port protection as enabled.
+pub(crate) fn is_protected(
+    interface: &mut dyn ArmProbeInterface,
+    port: u8,
+    idr: u32,
+) -> Result<bool, Error> {
+    let found = interface.read_raw_ap_register(port, 0xFC)?;
+    if found != idr {
+        log::debug!(
+            "Access port {} has the IDR {:#010x}, it is not a CTRL-AP.",
+            port,
+            found
+        );
+        return Ok(false);
+    }
+
+    let status = interface.read_raw_ap_register(port, APPROTECTSTATUS)?;
+    log::debug!("CTRL-AP {} APPROTECTSTATUS: {:#010x}", port, status);
+
+    Ok(status & APPROTECTSTATUS_DISABLED == 0)
+}
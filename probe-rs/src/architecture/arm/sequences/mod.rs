@@ -0,0 +1,1180 @@
+//! Interpreter for the debug sequences of a [CMSIS-Pack debug description].
+//!
+//! Debug sequences are stored in the target description, see [DebugSequence].
+//! When a target has a sequence with one of the names probe-rs knows, e.g. `DebugCoreStart`,
+//! the sequence is executed instead of the built-in implementation.
+//!
+//! [CMSIS-Pack debug description]: https://open-cmsis-pack.github.io/Open-CMSIS-Pack-Spec/main/html/debug_description.html
+
+mod parser;
+
+use self::parser::{BinaryOp, Expr, Statement, UnaryOp};
+use super::{ap::MemoryAP, communication_interface::ArmProbeInterface};
+use crate::config::{DebugSequence, SequenceStep};
+use crate::{AccessSize, CoreInterface, Error};
+
+use anyhow::anyhow;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// Enables debugging of the core, replaces the built-in `debug_core_start`.
+pub(crate) const DEBUG_CORE_START: &str = "DebugCoreStart";
+/// Unlocks the debug access to the device after connecting.
+pub(crate) const DEBUG_DEVICE_UNLOCK: &str = "DebugDeviceUnlock";
+/// Makes the core halt after the next reset, replaces the built-in `reset_catch_set`.
+pub(crate) const RESET_CATCH_SET: &str = "ResetCatchSet";
+/// Undoes the settings of [RESET_CATCH_SET], replaces the built-in `reset_catch_clear`.
+pub(crate) const RESET_CATCH_CLEAR: &str = "ResetCatchClear";
+
+/// The functions which can be called from a debug sequence, with their number of arguments.
+///
+/// `Message` takes a variable number of arguments, the number is the minimum.
+const FUNCTIONS: [(&str, usize); 15] = [
+    ("Read8", 1),
+    ("Read16", 1),
+    ("Read32", 1),
+    ("Read64", 1),
+    ("Write8", 2),
+    ("Write16", 2),
+    ("Write32", 2),
+    ("Write64", 2),
+    ("ReadAP", 1),
+    ("WriteAP", 2),
+    ("ReadDP", 1),
+    ("WriteDP", 2),
+    ("DAP_Delay", 1),
+    ("Sequence", 1),
+    ("Message", 2),
+];
+
+/// An error which occured while checking or executing a debug sequence.
+#[derive(Debug, Error)]
+pub enum SequenceError {
+    /// The code of a sequence is not valid.
+    #[error("The sequence '{sequence}' could not be parsed: {message}")]
+    Parse {
+        /// The name of the sequence.
+        sequence: String,
+        /// A description of the error.
+        message: String,
+    },
+    /// A sequence calls a function which probe-rs does not implement,
+    /// e.g. `DAP_SWJ_Sequence` or `Query`.
+    #[error("The sequence '{sequence}' calls the function '{function}', which is not supported")]
+    UnsupportedFunction {
+        /// The name of the sequence.
+        sequence: String,
+        /// The name of the function.
+        function: String,
+    },
+    /// A function was called with the wrong number of arguments.
+    #[error("The function '{function}' expects {expected} arguments")]
+    ArgumentCount {
+        /// The name of the function.
+        function: String,
+        /// The number of arguments the function expects.
+        expected: usize,
+    },
+    /// A string was used as a value, which is only allowed as argument of `Sequence` and `Message`.
+    #[error("The sequence '{0}' uses a string as value")]
+    UnexpectedString(String),
+    /// A variable was used before it was declared.
+    #[error("The variable '{0}' is not declared")]
+    UnknownVariable(String),
+    /// A sequence which the target does not have was called.
+    #[error("The sequence '{0}' does not exist")]
+    UnknownSequence(String),
+    /// A value was divided by zero.
+    #[error("Division by zero")]
+    DivisionByZero,
+    /// An address does not fit into 32 bits.
+    #[error("The address {0:#x} is out of range")]
+    AddressOutOfRange(u64),
+    /// A `while` loop did not finish before its timeout.
+    #[error("A loop of the sequence '{0}' did not finish before its timeout")]
+    Timeout(String),
+    /// The sequence reported an error with `Message`.
+    #[error("The sequence '{sequence}' reported an error: {message}")]
+    Message {
+        /// The name of the sequence.
+        sequence: String,
+        /// The formatted message.
+        message: String,
+    },
+    /// The sequence finished with a value of `__Result` which is not zero.
+    #[error("The sequence '{sequence}' failed with the result {result:#x}")]
+    Failed {
+        /// The name of the sequence.
+        sequence: String,
+        /// The value of `__Result`.
+        result: u64,
+    },
+    /// An access to the target failed.
+    #[error("An access to the target failed")]
+    Access(#[source] Error),
+}
+
+impl From<SequenceError> for Error {
+    fn from(error: SequenceError) -> Self {
+        Error::architecture_specific(error)
+    }
+}
+
+/// The accesses a debug sequence can make.
+pub(crate) trait SequenceInterface {
+    fn read_memory(&mut self, ap: u8, address: u32, size: AccessSize) -> Result<u64, Error>;
+
+    fn write_memory(
+        &mut self,
+        ap: u8,
+        address: u32,
+        size: AccessSize,
+        value: u64,
+    ) -> Result<(), Error>;
+
+    fn read_ap(&mut self, ap: u8, address: u8) -> Result<u32, Error>;
+
+    fn write_ap(&mut self, ap: u8, address: u8, value: u32) -> Result<(), Error>;
+
+    fn read_dp(&mut self, address: u8) -> Result<u32, Error>;
+
+    fn write_dp(&mut self, address: u8, value: u32) -> Result<(), Error>;
+}
+
+impl SequenceInterface for Box<dyn ArmProbeInterface> {
+    fn read_memory(&mut self, ap: u8, address: u32, size: AccessSize) -> Result<u64, Error> {
+        let mut memory = self.memory_interface(MemoryAP::from(ap))?;
+
+        match size {
+            AccessSize::U8 => memory.read_word_8(address).map(u64::from),
+            AccessSize::U16 => {
+                let mut data = [0; 2];
+                memory.read_mem_with_size(address, &mut data, AccessSize::U16)?;
+                Ok(u64::from(u16::from_le_bytes(data)))
+            }
+            AccessSize::U32 => memory.read_word_32(address).map(u64::from),
+            AccessSize::U64 => memory.read_word_64(address),
+        }
+    }
+
+    fn write_memory(
+        &mut self,
+        ap: u8,
+        address: u32,
+        size: AccessSize,
+        value: u64,
+    ) -> Result<(), Error> {
+        let mut memory = self.memory_interface(MemoryAP::from(ap))?;
+
+        match size {
+            AccessSize::U8 => memory.write_word_8(address, value as u8),
+            AccessSize::U16 => {
+                memory.write_mem_with_size(address, &(value as u16).to_le_bytes(), AccessSize::U16)
+            }
+            AccessSize::U32 => memory.write_word_32(address, value as u32),
+            AccessSize::U64 => memory.write_word_64(address, value),
+        }?;
+
+        memory.flush()
+    }
+
+    fn read_ap(&mut self, ap: u8, address: u8) -> Result<u32, Error> {
+        Ok(self.read_raw_ap_register(ap, address)?)
+    }
+
+    fn write_ap(&mut self, ap: u8, address: u8, value: u32) -> Result<(), Error> {
+        Ok(self.write_raw_ap_register(ap, address, value)?)
+    }
+
+    fn read_dp(&mut self, address: u8) -> Result<u32, Error> {
+        Ok(self.read_raw_dp_register(address)?)
+    }
+
+    fn write_dp(&mut self, address: u8, value: u32) -> Result<(), Error> {
+        Ok(self.write_raw_dp_register(address, value)?)
+    }
+}
+
+/// Executes sequences with the memory interface of a core,
+/// for the reset sequences which are run by [Core::reset](crate::Core::reset).
+///
+/// Only the memory behind the access port of the core can be accessed.
+pub(crate) struct CoreSequenceInterface<'a, 'probe>(
+    pub(crate) &'a mut (dyn CoreInterface + 'probe),
+);
+
+impl CoreSequenceInterface<'_, '_> {
+    fn check_ap(ap: u8) -> Result<(), Error> {
+        if ap == 0 {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "Reset sequences can only access the memory of the core, not AP {}",
+                ap
+            )
+            .into())
+        }
+    }
+}
+
+impl SequenceInterface for CoreSequenceInterface<'_, '_> {
+    fn read_memory(&mut self, ap: u8, address: u32, size: AccessSize) -> Result<u64, Error> {
+        Self::check_ap(ap)?;
+
+        match size {
+            AccessSize::U8 => self.0.read_word_8(address).map(u64::from),
+            AccessSize::U16 => {
+                let mut data = [0; 2];
+                self.0
+                    .read_mem_with_size(address, &mut data, AccessSize::U16)?;
+                Ok(u64::from(u16::from_le_bytes(data)))
+            }
+            AccessSize::U32 => self.0.read_word_32(address).map(u64::from),
+            AccessSize::U64 => self.0.read_word_64(address),
+        }
+    }
+
+    fn write_memory(
+        &mut self,
+        ap: u8,
+        address: u32,
+        size: AccessSize,
+        value: u64,
+    ) -> Result<(), Error> {
+        Self::check_ap(ap)?;
+
+        match size {
+            AccessSize::U8 => self.0.write_word_8(address, value as u8),
+            AccessSize::U16 => {
+                self.0
+                    .write_mem_with_size(address, &(value as u16).to_le_bytes(), AccessSize::U16)
+            }
+            AccessSize::U32 => self.0.write_word_32(address, value as u32),
+            AccessSize::U64 => self.0.write_word_64(address, value),
+        }?;
+
+        self.0.flush()
+    }
+
+    fn read_ap(&mut self, _ap: u8, _address: u8) -> Result<u32, Error> {
+        Err(anyhow!("Reset sequences can not access AP registers").into())
+    }
+
+    fn write_ap(&mut self, _ap: u8, _address: u8, _value: u32) -> Result<(), Error> {
+        Err(anyhow!("Reset sequences can not access AP registers").into())
+    }
+
+    fn read_dp(&mut self, _address: u8) -> Result<u32, Error> {
+        Err(anyhow!("Reset sequences can not access DP registers").into())
+    }
+
+    fn write_dp(&mut self, _address: u8, _value: u32) -> Result<(), Error> {
+        Err(anyhow!("Reset sequences can not access DP registers").into())
+    }
+}
+
+/// Checks that all sequences and the debug variables can be parsed, and that they
+/// only call functions which are supported.
+///
+/// Returns all errors which were found, so unsupported sequences can be reported
+/// when they are imported instead of failing when the target is used.
+pub(crate) fn validate(
+    sequences: &[DebugSequence],
+    debug_vars: Option<&str>,
+) -> Vec<SequenceError> {
+    let mut errors = vec![];
+
+    if let Some(debug_vars) = debug_vars {
+        match parser::parse_block(debug_vars) {
+            Ok(statements) => {
+                for statement in &statements {
+                    validate_statement("debugvars", statement, sequences, &mut errors);
+                }
+            }
+            Err(e) => errors.push(SequenceError::Parse {
+                sequence: "debugvars".to_string(),
+                message: e.to_string(),
+            }),
+        }
+    }
+
+    for sequence in sequences {
+        validate_steps(sequence, &sequence.steps, sequences, &mut errors);
+    }
+
+    errors
+}
+
+fn validate_steps(
+    sequence: &DebugSequence,
+    steps: &[SequenceStep],
+    sequences: &[DebugSequence],
+    errors: &mut Vec<SequenceError>,
+) {
+    let parse_error = |e: parser::ParseError| SequenceError::Parse {
+        sequence: sequence.name.to_string(),
+        message: e.to_string(),
+    };
+
+    for step in steps {
+        match step {
+            SequenceStep::Block { code, .. } => match parser::parse_block(code) {
+                Ok(statements) => {
+                    for statement in &statements {
+                        validate_statement(&sequence.name, statement, sequences, errors);
+                    }
+                }
+                Err(e) => errors.push(parse_error(e)),
+            },
+            SequenceStep::Control {
+                condition,
+                repeat,
+                steps,
+                ..
+            } => {
+                for expression in condition.iter().chain(repeat.iter()) {
+                    match parser::parse_expression(expression) {
+                        Ok(expr) => validate_expr(&sequence.name, &expr, sequences, errors),
+                        Err(e) => errors.push(parse_error(e)),
+                    }
+                }
+
+                validate_steps(sequence, steps, sequences, errors);
+            }
+        }
+    }
+}
+
+fn validate_statement(
+    sequence: &str,
+    statement: &Statement,
+    sequences: &[DebugSequence],
+    errors: &mut Vec<SequenceError>,
+) {
+    match statement {
+        Statement::Declare(_, expr) | Statement::Assign(_, _, expr) | Statement::Expr(expr) => {
+            validate_expr(sequence, expr, sequences, errors)
+        }
+    }
+}
+
+fn validate_expr(
+    sequence: &str,
+    expr: &Expr,
+    sequences: &[DebugSequence],
+    errors: &mut Vec<SequenceError>,
+) {
+    match expr {
+        Expr::Number(_) | Expr::Variable(_) => (),
+        Expr::String(_) => errors.push(SequenceError::UnexpectedString(sequence.to_string())),
+        Expr::Unary(_, operand) => validate_expr(sequence, operand, sequences, errors),
+        Expr::Binary(_, lhs, rhs) => {
+            validate_expr(sequence, lhs, sequences, errors);
+            validate_expr(sequence, rhs, sequences, errors);
+        }
+        Expr::Call(name, arguments) => {
+            let expected = match FUNCTIONS.iter().find(|(function, _)| function == name) {
+                Some((_, expected)) => *expected,
+                None => {
+                    errors.push(SequenceError::UnsupportedFunction {
+                        sequence: sequence.to_string(),
+                        function: name.clone(),
+                    });
+                    return;
+                }
+            };
+
+            let count_matches = if name == "Message" {
+                arguments.len() >= expected
+            } else {
+                arguments.len() == expected
+            };
+            if !count_matches {
+                errors.push(SequenceError::ArgumentCount {
+                    function: name.clone(),
+                    expected,
+                });
+            }
+
+            // The name of the called sequence and the format of a message are strings.
+            let string_argument = match name.as_str() {
+                "Sequence" => Some(0),
+                "Message" => Some(1),
+                _ => None,
+            };
+
+            for (i, argument) in arguments.iter().enumerate() {
+                match argument {
+                    Expr::String(called) if string_argument == Some(i) => {
+                        if name == "Sequence" && !sequences.iter().any(|s| s.name == *called) {
+                            errors.push(SequenceError::UnknownSequence(called.clone()));
+                        }
+                    }
+                    argument => validate_expr(sequence, argument, sequences, errors),
+                }
+            }
+        }
+    }
+}
+
+/// Executes the debug sequences of a target.
+pub(crate) struct SequenceRunner<'a> {
+    sequences: &'a [DebugSequence],
+    debug_vars: Option<&'a str>,
+}
+
+/// The variables of a running sequence.
+struct Scope {
+    /// The predefined variables and the debug variables, which are shared by all sequences.
+    globals: HashMap<String, u64>,
+    /// The variables declared in the sequences which are currently executed, innermost last.
+    locals: Vec<HashMap<String, u64>>,
+}
+
+impl Scope {
+    fn get(&self, name: &str) -> Result<u64, SequenceError> {
+        self.locals
+            .last()
+            .and_then(|locals| locals.get(name))
+            .or_else(|| self.globals.get(name))
+            .copied()
+            .ok_or_else(|| SequenceError::UnknownVariable(name.to_string()))
+    }
+
+    fn set(&mut self, name: &str, value: u64) -> Result<(), SequenceError> {
+        let variable = match self
+            .locals
+            .last_mut()
+            .and_then(|locals| locals.get_mut(name))
+        {
+            Some(variable) => variable,
+            None => self
+                .globals
+                .get_mut(name)
+                .ok_or_else(|| SequenceError::UnknownVariable(name.to_string()))?,
+        };
+
+        *variable = value;
+        Ok(())
+    }
+
+    fn declare(&mut self, name: &str, value: u64) {
+        match self.locals.last_mut() {
+            Some(locals) => locals.insert(name.to_string(), value),
+            None => self.globals.insert(name.to_string(), value),
+        };
+    }
+}
+
+impl<'a> SequenceRunner<'a> {
+    pub(crate) fn new(sequences: &'a [DebugSequence], debug_vars: Option<&'a str>) -> Self {
+        Self {
+            sequences,
+            debug_vars,
+        }
+    }
+
+    /// Returns true if the target has a sequence with the given name.
+    pub(crate) fn has_sequence(&self, name: &str) -> bool {
+        self.sequences.iter().any(|sequence| sequence.name == name)
+    }
+
+    /// Executes the sequence with the given name.
+    pub(crate) fn run(
+        &self,
+        interface: &mut dyn SequenceInterface,
+        name: &str,
+    ) -> Result<(), SequenceError> {
+        log::debug!("Executing debug sequence {}", name);
+
+        let mut scope = Scope {
+            globals: HashMap::new(),
+            locals: vec![],
+        };
+
+        // The predefined variables. Only SWD and a single debug port are supported.
+        for (variable, value) in &[
+            ("__protocol", 2),
+            ("__connection", 1),
+            ("__dp", 0),
+            ("__ap", 0),
+            ("__traceout", 0),
+            ("__errorcontrol", 0),
+            ("__FlashOp", 0),
+            ("__Result", 0),
+        ] {
+            scope.declare(variable, *value);
+        }
+
+        if let Some(debug_vars) = self.debug_vars {
+            let statements = parser::parse_block(debug_vars).map_err(|e| SequenceError::Parse {
+                sequence: "debugvars".to_string(),
+                message: e.to_string(),
+            })?;
+
+            let mut execution = Execution {
+                runner: self,
+                interface,
+                scope: &mut scope,
+                sequence: "debugvars",
+            };
+            for statement in &statements {
+                execution.statement(statement)?;
+            }
+        }
+
+        Execution {
+            runner: self,
+            interface,
+            scope: &mut scope,
+            sequence: name,
+        }
+        .call_sequence(name)
+    }
+}
+
+/// The state while a sequence is executed.
+struct Execution<'r, 'a> {
+    runner: &'r SequenceRunner<'a>,
+    interface: &'r mut dyn SequenceInterface,
+    scope: &'r mut Scope,
+    /// The name of the sequence which is currently executed.
+    sequence: &'r str,
+}
+
+impl Execution<'_, '_> {
+    fn call_sequence(&mut self, name: &str) -> Result<(), SequenceError> {
+        let sequence = self
+            .runner
+            .sequences
+            .iter()
+            .find(|sequence| sequence.name == name)
+            .ok_or_else(|| SequenceError::UnknownSequence(name.to_string()))?;
+
+        self.scope.locals.push(HashMap::new());
+        self.scope.set("__Result", 0)?;
+
+        let mut execution = Execution {
+            runner: self.runner,
+            interface: &mut *self.interface,
+            scope: &mut *self.scope,
+            sequence: &sequence.name,
+        };
+        let result = execution.steps(&sequence.steps);
+
+        self.scope.locals.pop();
+        result?;
+
+        match self.scope.get("__Result")? {
+            0 => Ok(()),
+            result => Err(SequenceError::Failed {
+                sequence: name.to_string(),
+                result,
+            }),
+        }
+    }
+
+    fn parse_error(&self, error: parser::ParseError) -> SequenceError {
+        SequenceError::Parse {
+            sequence: self.sequence.to_string(),
+            message: error.to_string(),
+        }
+    }
+
+    fn steps(&mut self, steps: &[SequenceStep]) -> Result<(), SequenceError> {
+        for step in steps {
+            match step {
+                SequenceStep::Block { code, .. } => {
+                    let statements = parser::parse_block(code).map_err(|e| self.parse_error(e))?;
+
+                    for statement in &statements {
+                        self.statement(statement)?;
+                    }
+                }
+                SequenceStep::Control {
+                    condition,
+                    repeat,
+                    timeout,
+                    steps,
+                } => {
+                    if let Some(condition) = condition {
+                        let condition =
+                            parser::parse_expression(condition).map_err(|e| self.parse_error(e))?;
+                        if self.evaluate(&condition)? == 0 {
+                            continue;
+                        }
+                    }
+
+                    let repeat = match repeat {
+                        Some(repeat) => {
+                            parser::parse_expression(repeat).map_err(|e| self.parse_error(e))?
+                        }
+                        None => {
+                            self.steps(steps)?;
+                            continue;
+                        }
+                    };
+
+                    let start = Instant::now();
+                    while self.evaluate(&repeat)? != 0 {
+                        if *timeout != 0 && start.elapsed() > Duration::from_micros(*timeout) {
+                            return Err(SequenceError::Timeout(self.sequence.to_string()));
+                        }
+
+                        self.steps(steps)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn statement(&mut self, statement: &Statement) -> Result<(), SequenceError> {
+        match statement {
+            Statement::Declare(name, expr) => {
+                let value = self.evaluate(expr)?;
+                self.scope.declare(name, value);
+            }
+            Statement::Assign(name, operator, expr) => {
+                let mut value = self.evaluate(expr)?;
+                if let Some(operator) = operator {
+                    value = binary(*operator, self.scope.get(name)?, value)?;
+                }
+                self.scope.set(name, value)?;
+            }
+            Statement::Expr(expr) => {
+                self.evaluate(expr)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn evaluate(&mut self, expr: &Expr) -> Result<u64, SequenceError> {
+        match expr {
+            Expr::Number(value) => Ok(*value),
+            Expr::String(_) => Err(SequenceError::UnexpectedString(self.sequence.to_string())),
+            Expr::Variable(name) => self.scope.get(name),
+            Expr::Unary(op, operand) => {
+                let value = self.evaluate(operand)?;
+
+                Ok(match op {
+                    UnaryOp::Not => (value == 0) as u64,
+                    UnaryOp::BitNot => !value,
+                    UnaryOp::Negate => value.wrapping_neg(),
+                })
+            }
+            // The logical operators only evaluate the right side if necessary.
+            Expr::Binary(BinaryOp::And, lhs, rhs) => {
+                Ok((self.evaluate(lhs)? != 0 && self.evaluate(rhs)? != 0) as u64)
+            }
+            Expr::Binary(BinaryOp::Or, lhs, rhs) => {
+                Ok((self.evaluate(lhs)? != 0 || self.evaluate(rhs)? != 0) as u64)
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs = self.evaluate(lhs)?;
+                let rhs = self.evaluate(rhs)?;
+                binary(*op, lhs, rhs)
+            }
+            Expr::Call(name, arguments) => self.call(name, arguments),
+        }
+    }
+
+    fn call(&mut self, name: &str, arguments: &[Expr]) -> Result<u64, SequenceError> {
+        let expected = FUNCTIONS
+            .iter()
+            .find(|(function, _)| *function == name)
+            .map(|(_, expected)| *expected)
+            .ok_or_else(|| SequenceError::UnsupportedFunction {
+                sequence: self.sequence.to_string(),
+                function: name.to_string(),
+            })?;
+
+        if arguments.len() != expected && !(name == "Message" && arguments.len() > expected) {
+            return Err(SequenceError::ArgumentCount {
+                function: name.to_string(),
+                expected,
+            });
+        }
+
+        match name {
+            "Sequence" => {
+                let called = match &arguments[0] {
+                    Expr::String(called) => called,
+                    _ => return Err(SequenceError::UnexpectedString(self.sequence.to_string())),
+                };
+
+                self.call_sequence(called)?;
+                Ok(0)
+            }
+            "Message" => {
+                let format = match &arguments[1] {
+                    Expr::String(format) => format,
+                    _ => return Err(SequenceError::UnexpectedString(self.sequence.to_string())),
+                };
+
+                let kind = self.evaluate(&arguments[0])?;
+                let values = arguments[2..]
+                    .iter()
+                    .map(|argument| self.evaluate(argument))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let message = format_message(format, &values);
+
+                match kind {
+                    0 => log::info!("{}: {}", self.sequence, message),
+                    1 => log::warn!("{}: {}", self.sequence, message),
+                    _ => {
+                        return Err(SequenceError::Message {
+                            sequence: self.sequence.to_string(),
+                            message,
+                        })
+                    }
+                }
+
+                Ok(0)
+            }
+            "DAP_Delay" => {
+                let delay = self.evaluate(&arguments[0])?;
+                std::thread::sleep(Duration::from_micros(delay));
+                Ok(0)
+            }
+            _ => {
+                let values = arguments
+                    .iter()
+                    .map(|argument| self.evaluate(argument))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                self.access(name, &values)
+            }
+        }
+    }
+
+    /// Calls one of the functions which access the target.
+    fn access(&mut self, name: &str, arguments: &[u64]) -> Result<u64, SequenceError> {
+        let ap = self.scope.get("__ap")? as u8;
+
+        let address = || {
+            u32::try_from(arguments[0]).map_err(|_| SequenceError::AddressOutOfRange(arguments[0]))
+        };
+
+        let result = match name {
+            "Read8" => self.interface.read_memory(ap, address()?, AccessSize::U8),
+            "Read16" => self.interface.read_memory(ap, address()?, AccessSize::U16),
+            "Read32" => self.interface.read_memory(ap, address()?, AccessSize::U32),
+            "Read64" => self.interface.read_memory(ap, address()?, AccessSize::U64),
+            "Write8" => self
+                .interface
+                .write_memory(ap, address()?, AccessSize::U8, arguments[1])
+                .map(|_| 0),
+            "Write16" => self
+                .interface
+                .write_memory(ap, address()?, AccessSize::U16, arguments[1])
+                .map(|_| 0),
+            "Write32" => self
+                .interface
+                .write_memory(ap, address()?, AccessSize::U32, arguments[1])
+                .map(|_| 0),
+            "Write64" => self
+                .interface
+                .write_memory(ap, address()?, AccessSize::U64, arguments[1])
+                .map(|_| 0),
+            "ReadAP" => self
+                .interface
+                .read_ap(ap, arguments[0] as u8)
+                .map(u64::from),
+            "WriteAP" => self
+                .interface
+                .write_ap(ap, arguments[0] as u8, arguments[1] as u32)
+                .map(|_| 0),
+            "ReadDP" => self.interface.read_dp(arguments[0] as u8).map(u64::from),
+            "WriteDP" => self
+                .interface
+                .write_dp(arguments[0] as u8, arguments[1] as u32)
+                .map(|_| 0),
+            _ => unreachable!("All functions are handled"),
+        };
+
+        match result {
+            Ok(value) => Ok(value),
+            // Bit 0 of `__errorcontrol` ignores failed accesses.
+            Err(e) if self.scope.get("__errorcontrol")? & 1 != 0 => {
+                log::debug!("Ignoring failed access of {}: {}", name, e);
+                Ok(0)
+            }
+            Err(e) => Err(SequenceError::Access(e)),
+        }
+    }
+}
+
+fn binary(op: BinaryOp, lhs: u64, rhs: u64) -> Result<u64, SequenceError> {
+    use BinaryOp::*;
+
+    let value = match op {
+        Mul => lhs.wrapping_mul(rhs),
+        Div => lhs.checked_div(rhs).ok_or(SequenceError::DivisionByZero)?,
+        Rem => lhs.checked_rem(rhs).ok_or(SequenceError::DivisionByZero)?,
+        Add => lhs.wrapping_add(rhs),
+        Sub => lhs.wrapping_sub(rhs),
+        Shl => lhs.checked_shl(rhs as u32).unwrap_or(0),
+        Shr => lhs.checked_shr(rhs as u32).unwrap_or(0),
+        Lt => (lhs < rhs) as u64,
+        Le => (lhs <= rhs) as u64,
+        Gt => (lhs > rhs) as u64,
+        Ge => (lhs >= rhs) as u64,
+        Eq => (lhs == rhs) as u64,
+        Ne => (lhs != rhs) as u64,
+        BitAnd => lhs & rhs,
+        BitXor => lhs ^ rhs,
+        BitOr => lhs | rhs,
+        And => (lhs != 0 && rhs != 0) as u64,
+        Or => (lhs != 0 || rhs != 0) as u64,
+    };
+
+    Ok(value)
+}
+
+/// Formats the message of a `Message` call, replacing the `%x`, `%d` and `%u` conversions
+/// with the values. Flags and widths like in `%08X` are supported.
+fn format_message(format: &str, values: &[u64]) -> String {
+    let mut message = String::new();
+    let mut values = values.iter();
+    let mut chars = format.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            message.push(c);
+            continue;
+        }
+
+        let mut spec = String::new();
+        while let Some(&c) = chars.peek() {
+            if c.is_ascii_digit() {
+                spec.push(c);
+                chars.next();
+            } else {
+                break;
+            }
+        }
+
+        let zero_padded = spec.starts_with('0');
+        let width = spec.parse::<usize>().unwrap_or(0);
+        let mut value = || values.next().copied().unwrap_or(0);
+
+        let formatted = match chars.next() {
+            Some('%') => "%".to_string(),
+            Some('x') => format!("{:x}", value()),
+            Some('X') => format!("{:X}", value()),
+            Some('d') | Some('u') | Some('i') => format!("{}", value()),
+            Some(other) => format!("%{}{}", spec, other),
+            None => format!("%{}", spec),
+        };
+
+        let padding = width.saturating_sub(formatted.len());
+        let pad = if zero_padded { '0' } else { ' ' };
+        message.extend(std::iter::repeat(pad).take(padding));
+        message.push_str(&formatted);
+    }
+
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+
+    /// A target with a flat memory, which records all accesses.
+    #[derive(Default)]
+    struct MockInterface {
+        memory: HashMap<u32, u64>,
+        dp: HashMap<u8, u32>,
+        accesses: Vec<String>,
+        /// Reads of this address return an error.
+        failing_address: Option<u32>,
+    }
+
+    impl SequenceInterface for MockInterface {
+        fn read_memory(&mut self, ap: u8, address: u32, size: AccessSize) -> Result<u64, Error> {
+            self.accesses
+                .push(format!("read {} {:#x} {}", ap, address, size));
+
+            if Some(address) == self.failing_address {
+                return Err(Error::Other(anyhow::anyhow!("Failed")));
+            }
+
+            Ok(self.memory.get(&address).copied().unwrap_or(0))
+        }
+
+        fn write_memory(
+            &mut self,
+            ap: u8,
+            address: u32,
+            size: AccessSize,
+            value: u64,
+        ) -> Result<(), Error> {
+            self.accesses
+                .push(format!("write {} {:#x} {} {:#x}", ap, address, size, value));
+            self.memory.insert(address, value);
+            Ok(())
+        }
+
+        fn read_ap(&mut self, ap: u8, address: u8) -> Result<u32, Error> {
+            self.accesses.push(format!("read ap {} {:#x}", ap, address));
+            Ok(0x2477_0011)
+        }
+
+        fn write_ap(&mut self, ap: u8, address: u8, value: u32) -> Result<(), Error> {
+            self.accesses
+                .push(format!("write ap {} {:#x} {:#x}", ap, address, value));
+            Ok(())
+        }
+
+        fn read_dp(&mut self, address: u8) -> Result<u32, Error> {
+            Ok(self.dp.get(&address).copied().unwrap_or(0))
+        }
+
+        fn write_dp(&mut self, address: u8, value: u32) -> Result<(), Error> {
+            self.dp.insert(address, value);
+            Ok(())
+        }
+    }
+
+    fn block(code: &'static str) -> SequenceStep {
+        SequenceStep::Block {
+            code: Cow::Borrowed(code),
+            atomic: false,
+        }
+    }
+
+    fn sequence(name: &'static str, steps: Vec<SequenceStep>) -> DebugSequence {
+        DebugSequence {
+            name: Cow::Borrowed(name),
+            steps: Cow::Owned(steps),
+        }
+    }
+
+    /// Written after the `ResetCatchSet` sequence of the LPC55S6x pack, which uses the
+    /// breakpoint unit to halt at the reset vector read from the flash.
+    fn lpc55_reset_catch_set() -> DebugSequence {
+        sequence(
+            "ResetCatchSet",
+            vec![
+                block(
+                    r#"
+                    __var SCS_Addr   = 0xE000E000;
+                    __var DHCSR_Addr = SCS_Addr + 0xDF0;
+                    __var DEMCR_Addr = SCS_Addr + 0xDFC;
+                    __var reset_vector = 0;
+
+                    // Disable the reset vector catch
+                    Write32(DEMCR_Addr, Read32(DEMCR_Addr) & (~0x00000001));
+
+                    reset_vector = Read32(0x00000004);
+                    "#,
+                ),
+                SequenceStep::Control {
+                    condition: Some(Cow::Borrowed("reset_vector != 0xFFFFFFFF")),
+                    repeat: None,
+                    timeout: 0,
+                    steps: Cow::Owned(vec![block(
+                        r#"
+                        Write32(0xE0002008, (reset_vector & 0xFFFFFFFE) | 1);
+                        Write32(0xE0002000, 0x00000003);
+                        "#,
+                    )]),
+                },
+                block("Read32(DHCSR_Addr);"),
+            ],
+        )
+    }
+
+    #[test]
+    fn run_reset_catch_set() {
+        let sequences = [lpc55_reset_catch_set()];
+        let runner = SequenceRunner::new(&sequences, None);
+
+        let mut interface = MockInterface::default();
+        interface.memory.insert(0xE000_EDFC, 0x0100_0001);
+        interface.memory.insert(0x4, 0x1301);
+
+        runner.run(&mut interface, "ResetCatchSet").unwrap();
+
+        assert_eq!(interface.memory[&0xE000_EDFC], 0x0100_0000);
+        assert_eq!(interface.memory[&0xE000_2008], 0x1301);
+        assert_eq!(interface.memory[&0xE000_2000], 0x3);
+        assert_eq!(
+            interface.accesses.last().unwrap(),
+            "read 0 0xe000edf0 32 bit"
+        );
+    }
+
+    #[test]
+    fn control_condition_skips_steps() {
+        let sequences = [lpc55_reset_catch_set()];
+        let runner = SequenceRunner::new(&sequences, None);
+
+        let mut interface = MockInterface::default();
+        interface.memory.insert(0x4, 0xFFFF_FFFF);
+
+        runner.run(&mut interface, "ResetCatchSet").unwrap();
+
+        assert!(!interface.memory.contains_key(&0xE000_2000));
+    }
+
+    /// Written after the `DebugPortStart` of the PSoC 6 pack, which powers up the
+    /// debug port and selects the AP with a debug variable.
+    #[test]
+    fn debug_vars_and_nested_sequences() {
+        let sequences = [
+            sequence(
+                "DebugPortStart",
+                vec![
+                    block(
+                        r#"
+                        __var powered_down = 0;
+                        WriteDP(0x4, 0x50000000);
+                        powered_down = ((ReadDP(0x4) & 0xA0000000) != 0xA0000000);
+                        __ap = ap_index;
+                        Sequence("EnableCore");
+                        "#,
+                    ),
+                    SequenceStep::Control {
+                        condition: None,
+                        repeat: Some(Cow::Borrowed("(Read32(0x40200000) & 0x1) == 0")),
+                        timeout: 100_000,
+                        steps: Cow::Owned(vec![block("Write32(0x40200000, 1);")]),
+                    },
+                ],
+            ),
+            sequence("EnableCore", vec![block("WriteAP(0x0, 0x23000052);")]),
+        ];
+
+        let runner = SequenceRunner::new(&sequences, Some("__var ap_index = 2;"));
+        let mut interface = MockInterface::default();
+
+        runner.run(&mut interface, "DebugPortStart").unwrap();
+
+        assert_eq!(interface.dp[&0x4], 0x5000_0000);
+        assert!(interface
+            .accesses
+            .contains(&"write ap 2 0x0 0x23000052".to_string()));
+        assert_eq!(interface.memory[&0x4020_0000], 1);
+    }
+
+    #[test]
+    fn loop_timeout() {
+        let sequences = [sequence(
+            "Wait",
+            vec![SequenceStep::Control {
+                condition: None,
+                repeat: Some(Cow::Borrowed("Read32(0x1000) == 0")),
+                timeout: 1000,
+                steps: Cow::Borrowed(&[]),
+            }],
+        )];
+
+        let runner = SequenceRunner::new(&sequences, None);
+        let result = runner.run(&mut MockInterface::default(), "Wait");
+
+        assert!(matches!(result, Err(SequenceError::Timeout(_))));
+    }
+
+    #[test]
+    fn error_control_ignores_failed_accesses() {
+        let sequences = [sequence(
+            "Probe",
+            vec![block(
+                r#"
+                __errorcontrol = 1;
+                __var value = Read32(0x2000);
+                __errorcontrol = 0;
+                Write32(0x3000, value + 1);
+                "#,
+            )],
+        )];
+
+        let runner = SequenceRunner::new(&sequences, None);
+        let mut interface = MockInterface {
+            failing_address: Some(0x2000),
+            ..Default::default()
+        };
+
+        runner.run(&mut interface, "Probe").unwrap();
+        assert_eq!(interface.memory[&0x3000], 1);
+    }
+
+    #[test]
+    fn result_and_error_messages() {
+        let sequences = [
+            sequence("Failing", vec![block("__Result = 0x5;")]),
+            sequence(
+                "Reporting",
+                vec![block(
+                    r#"Message(2, "Device is locked, status %08X", 0xAB);"#,
+                )],
+            ),
+        ];
+        let runner = SequenceRunner::new(&sequences, None);
+
+        match runner.run(&mut MockInterface::default(), "Failing") {
+            Err(SequenceError::Failed { result, .. }) => assert_eq!(result, 5),
+            other => panic!("Unexpected result {:?}", other),
+        }
+
+        match runner.run(&mut MockInterface::default(), "Reporting") {
+            Err(SequenceError::Message { message, .. }) => {
+                assert_eq!(message, "Device is locked, status 000000AB")
+            }
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn validate_reports_unsupported_functions() {
+        let sequences = [
+            lpc55_reset_catch_set(),
+            sequence(
+                "DebugPortSetup",
+                vec![block(
+                    r#"
+                    DAP_SWJ_Sequence(51, 0x0007FFFFFFFFFFFF);
+                    Sequence("DebugPortStop");
+                    Write32(0x1000);
+                    "#,
+                )],
+            ),
+        ];
+
+        let errors = validate(&sequences, Some("__var x = Query(0, \"?\", 1);"));
+
+        assert_eq!(errors.len(), 4, "{:?}", errors);
+        assert!(matches!(
+            &errors[0],
+            SequenceError::UnsupportedFunction { function, .. } if function == "Query"
+        ));
+        assert!(matches!(
+            &errors[1],
+            SequenceError::UnsupportedFunction { function, .. } if function == "DAP_SWJ_Sequence"
+        ));
+        assert!(
+            matches!(&errors[2], SequenceError::UnknownSequence(name) if name == "DebugPortStop")
+        );
+        assert!(matches!(
+            &errors[3],
+            SequenceError::ArgumentCount { function, .. } if function == "Write32"
+        ));
+
+        assert!(validate(&[lpc55_reset_catch_set()], None).is_empty());
+    }
+
+    #[test]
+    fn message_format() {
+        assert_eq!(
+            format_message("%x %X %d %08x %4u%%", &[0xab, 0xab, 12, 0x1f, 7]),
+            "ab AB 12 0000001f    7%"
+        );
+    }
+}
@@ -0,0 +1,538 @@
+//! Parser for the statements and expressions used in the debug sequences
+//! of a [CMSIS-Pack debug description].
+//!
+//! The language is a small subset of C. All values are unsigned 64 bit integers,
+//! strings are only used as arguments of functions.
+//!
+//! [CMSIS-Pack debug description]: https://open-cmsis-pack.github.io/Open-CMSIS-Pack-Spec/main/html/debug_description.html
+
+use std::fmt;
+
+/// An expression, which evaluates to a value.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Expr {
+    Number(u64),
+    String(String),
+    Variable(String),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum UnaryOp {
+    /// `!`
+    Not,
+    /// `~`
+    BitNot,
+    /// `-`
+    Negate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum BinaryOp {
+    Mul,
+    Div,
+    Rem,
+    Add,
+    Sub,
+    Shl,
+    Shr,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    BitAnd,
+    BitXor,
+    BitOr,
+    And,
+    Or,
+}
+
+impl BinaryOp {
+    fn from_punct(punct: &str) -> Option<Self> {
+        use BinaryOp::*;
+
+        let op = match punct {
+            "*" => Mul,
+            "/" => Div,
+            "%" => Rem,
+            "+" => Add,
+            "-" => Sub,
+            "<<" => Shl,
+            ">>" => Shr,
+            "<" => Lt,
+            "<=" => Le,
+            ">" => Gt,
+            ">=" => Ge,
+            "==" => Eq,
+            "!=" => Ne,
+            "&" => BitAnd,
+            "^" => BitXor,
+            "|" => BitOr,
+            "&&" => And,
+            "||" => Or,
+            _ => return None,
+        };
+
+        Some(op)
+    }
+
+    /// The precedence of the operator, as in C. Higher values bind stronger.
+    fn precedence(self) -> u8 {
+        use BinaryOp::*;
+
+        match self {
+            Mul | Div | Rem => 10,
+            Add | Sub => 9,
+            Shl | Shr => 8,
+            Lt | Le | Gt | Ge => 7,
+            Eq | Ne => 6,
+            BitAnd => 5,
+            BitXor => 4,
+            BitOr => 3,
+            And => 2,
+            Or => 1,
+        }
+    }
+}
+
+/// A statement of a `<block>` element.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Statement {
+    /// `__var name = value;`
+    Declare(String, Expr),
+    /// `name = value;`, or a compound assignment like `name |= value;`
+    Assign(String, Option<BinaryOp>, Expr),
+    /// An expression whose value is not used, usually a function call.
+    Expr(Expr),
+}
+
+/// An error in the syntax of a block or an expression.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ParseError(String);
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(u64),
+    Ident(String),
+    String(String),
+    Punct(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(value) => write!(f, "{:#x}", value),
+            Token::Ident(name) => write!(f, "'{}'", name),
+            Token::String(string) => write!(f, "\"{}\"", string),
+            Token::Punct(punct) => write!(f, "'{}'", punct),
+        }
+    }
+}
+
+/// All punctuation, longer ones first so they are matched before their prefixes.
+const PUNCTUATION: [&str; 35] = [
+    "<<=", ">>=", "&&", "||", "==", "!=", "<=", ">=", "<<", ">>", "+=", "-=", "*=", "/=", "%=",
+    "&=", "|=", "^=", "(", ")", ",", ";", "=", "<", ">", "+", "-", "*", "/", "%", "&", "|", "^",
+    "!", "~",
+];
+
+fn tokenize(code: &str) -> Result<Vec<Token>, ParseError> {
+    let mut tokens = vec![];
+    let mut rest = code;
+
+    loop {
+        rest = rest.trim_start();
+
+        if rest.is_empty() {
+            return Ok(tokens);
+        }
+
+        if let Some(comment) = rest.strip_prefix("//") {
+            rest = comment.find('\n').map_or("", |end| &comment[end..]);
+            continue;
+        }
+
+        if let Some(comment) = rest.strip_prefix("/*") {
+            let end = comment
+                .find("*/")
+                .ok_or_else(|| ParseError("Unterminated comment".to_string()))?;
+            rest = &comment[end + 2..];
+            continue;
+        }
+
+        let c = rest.chars().next().unwrap();
+
+        if c.is_ascii_digit() {
+            let end = rest
+                .find(|c: char| !c.is_ascii_alphanumeric())
+                .unwrap_or(rest.len());
+            let literal = &rest[..end];
+
+            let value = if let Some(hex) = literal
+                .strip_prefix("0x")
+                .or_else(|| literal.strip_prefix("0X"))
+            {
+                u64::from_str_radix(hex, 16)
+            } else {
+                literal.parse()
+            }
+            .map_err(|_| ParseError(format!("Invalid number '{}'", literal)))?;
+
+            tokens.push(Token::Number(value));
+            rest = &rest[end..];
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+
+            tokens.push(Token::Ident(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else if c == '"' {
+            let mut string = String::new();
+            let mut chars = rest[1..].char_indices();
+
+            let end = loop {
+                match chars.next() {
+                    Some((i, '"')) => break i + 2,
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, 'n')) => string.push('\n'),
+                        Some((_, 't')) => string.push('\t'),
+                        Some((_, escaped)) => string.push(escaped),
+                        None => return Err(ParseError("Unterminated string".to_string())),
+                    },
+                    Some((_, c)) => string.push(c),
+                    None => return Err(ParseError("Unterminated string".to_string())),
+                }
+            };
+
+            tokens.push(Token::String(string));
+            rest = &rest[end..];
+        } else {
+            let punct = PUNCTUATION
+                .iter()
+                .find(|punct| rest.starts_with(*punct))
+                .ok_or_else(|| ParseError(format!("Unexpected character '{}'", c)))?;
+
+            tokens.push(Token::Punct(punct));
+            rest = &rest[punct.len()..];
+        }
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn new(code: &str) -> Result<Self, ParseError> {
+        Ok(Parser {
+            tokens: tokenize(code)?,
+            position: 0,
+        })
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn peek_punct(&self) -> Option<&'static str> {
+        match self.peek() {
+            Some(Token::Punct(punct)) => Some(*punct),
+            _ => None,
+        }
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn is_at_end(&self) -> bool {
+        self.position >= self.tokens.len()
+    }
+
+    fn expect_punct(&mut self, expected: &str) -> Result<(), ParseError> {
+        match self.advance() {
+            Some(Token::Punct(punct)) if punct == expected => Ok(()),
+            Some(token) => Err(ParseError(format!(
+                "Expected '{}', found {}",
+                expected, token
+            ))),
+            None => Err(ParseError(format!(
+                "Expected '{}', found the end of the code",
+                expected
+            ))),
+        }
+    }
+
+    fn statements(&mut self) -> Result<Vec<Statement>, ParseError> {
+        let mut statements = vec![];
+
+        while !self.is_at_end() {
+            // Empty statements
+            if self.peek_punct() == Some(";") {
+                self.position += 1;
+                continue;
+            }
+
+            statements.push(self.statement()?);
+
+            // The semicolon after the last statement is optional.
+            if !self.is_at_end() {
+                self.expect_punct(";")?;
+            }
+        }
+
+        Ok(statements)
+    }
+
+    fn statement(&mut self) -> Result<Statement, ParseError> {
+        if let Some(Token::Ident(keyword)) = self.peek() {
+            if keyword == "__var" {
+                self.position += 1;
+
+                let name = match self.advance() {
+                    Some(Token::Ident(name)) => name,
+                    _ => return Err(ParseError("Expected a variable name after '__var'".into())),
+                };
+
+                let value = if self.peek_punct() == Some("=") {
+                    self.position += 1;
+                    self.expression(0)?
+                } else {
+                    Expr::Number(0)
+                };
+
+                return Ok(Statement::Declare(name, value));
+            }
+        }
+
+        if let (Some(Token::Ident(name)), Some(Token::Punct(punct))) = (
+            self.tokens.get(self.position),
+            self.tokens.get(self.position + 1),
+        ) {
+            let operator = match *punct {
+                "=" => Some(None),
+                compound if compound.len() > 1 && compound.ends_with('=') => {
+                    BinaryOp::from_punct(&compound[..compound.len() - 1])
+                        .filter(|op| !matches!(op, BinaryOp::Lt | BinaryOp::Gt))
+                        .map(Some)
+                }
+                _ => None,
+            };
+
+            if let Some(operator) = operator {
+                let name = name.clone();
+                self.position += 2;
+
+                return Ok(Statement::Assign(name, operator, self.expression(0)?));
+            }
+        }
+
+        Ok(Statement::Expr(self.expression(0)?))
+    }
+
+    /// Parses an expression whose binary operators have at least the precedence `min_precedence`.
+    fn expression(&mut self, min_precedence: u8) -> Result<Expr, ParseError> {
+        let mut lhs = self.unary()?;
+
+        while let Some(op) = self.peek_punct().and_then(BinaryOp::from_punct) {
+            if op.precedence() < min_precedence {
+                break;
+            }
+            self.position += 1;
+
+            let rhs = self.expression(op.precedence() + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ParseError> {
+        let op = match self.peek_punct() {
+            Some("!") => UnaryOp::Not,
+            Some("~") => UnaryOp::BitNot,
+            Some("-") => UnaryOp::Negate,
+            Some("+") => {
+                self.position += 1;
+                return self.unary();
+            }
+            _ => return self.primary(),
+        };
+        self.position += 1;
+
+        Ok(Expr::Unary(op, Box::new(self.unary()?)))
+    }
+
+    fn primary(&mut self) -> Result<Expr, ParseError> {
+        match self.advance() {
+            Some(Token::Number(value)) => Ok(Expr::Number(value)),
+            Some(Token::String(string)) => Ok(Expr::String(string)),
+            Some(Token::Ident(name)) => {
+                if self.peek_punct() != Some("(") {
+                    return Ok(Expr::Variable(name));
+                }
+                self.position += 1;
+
+                let mut arguments = vec![];
+                if self.peek_punct() == Some(")") {
+                    self.position += 1;
+                } else {
+                    loop {
+                        arguments.push(self.expression(0)?);
+
+                        match self.advance() {
+                            Some(Token::Punct(",")) => continue,
+                            Some(Token::Punct(")")) => break,
+                            _ => {
+                                return Err(ParseError(format!(
+                                    "Expected ',' or ')' in the arguments of '{}'",
+                                    name
+                                )))
+                            }
+                        }
+                    }
+                }
+
+                Ok(Expr::Call(name, arguments))
+            }
+            Some(Token::Punct("(")) => {
+                let expr = self.expression(0)?;
+                self.expect_punct(")")?;
+                Ok(expr)
+            }
+            Some(token) => Err(ParseError(format!("Unexpected {}", token))),
+            None => Err(ParseError("Unexpected end of the code".to_string())),
+        }
+    }
+}
+
+/// Parses the statements of a `<block>` element.
+pub(crate) fn parse_block(code: &str) -> Result<Vec<Statement>, ParseError> {
+    Parser::new(code)?.statements()
+}
+
+/// Parses the expression of an `if` or `while` attribute of a `<control>` element.
+pub(crate) fn parse_expression(code: &str) -> Result<Expr, ParseError> {
+    let mut parser = Parser::new(code)?;
+    let expr = parser.expression(0)?;
+
+    match parser.advance() {
+        None => Ok(expr),
+        Some(token) => Err(ParseError(format!(
+            "Unexpected {} after the expression",
+            token
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn number(value: u64) -> Box<Expr> {
+        Box::new(Expr::Number(value))
+    }
+
+    fn variable(name: &str) -> Box<Expr> {
+        Box::new(Expr::Variable(name.to_string()))
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(
+            parse_expression("1 + 2 * 3 == 7 && a").unwrap(),
+            Expr::Binary(
+                BinaryOp::And,
+                Box::new(Expr::Binary(
+                    BinaryOp::Eq,
+                    Box::new(Expr::Binary(
+                        BinaryOp::Add,
+                        number(1),
+                        Box::new(Expr::Binary(BinaryOp::Mul, number(2), number(3)))
+                    )),
+                    number(7)
+                )),
+                variable("a")
+            )
+        );
+    }
+
+    #[test]
+    fn left_associative() {
+        assert_eq!(
+            parse_expression("8 - 4 - 2").unwrap(),
+            Expr::Binary(
+                BinaryOp::Sub,
+                Box::new(Expr::Binary(BinaryOp::Sub, number(8), number(4))),
+                number(2)
+            )
+        );
+    }
+
+    #[test]
+    fn statements() {
+        let code = r#"
+            // Enable the debug clock
+            __var value = Read32(0x40000000) | (1 << 3);
+            value &= ~0x10; /* keep the reset value */
+            Write32(0x40000000, value);
+            Message(0, "done");
+        "#;
+
+        assert_eq!(
+            parse_block(code).unwrap(),
+            vec![
+                Statement::Declare(
+                    "value".to_string(),
+                    Expr::Binary(
+                        BinaryOp::BitOr,
+                        Box::new(Expr::Call(
+                            "Read32".to_string(),
+                            vec![Expr::Number(0x4000_0000)]
+                        )),
+                        Box::new(Expr::Binary(BinaryOp::Shl, number(1), number(3)))
+                    )
+                ),
+                Statement::Assign(
+                    "value".to_string(),
+                    Some(BinaryOp::BitAnd),
+                    Expr::Unary(UnaryOp::BitNot, number(0x10))
+                ),
+                Statement::Expr(Expr::Call(
+                    "Write32".to_string(),
+                    vec![
+                        Expr::Number(0x4000_0000),
+                        Expr::Variable("value".to_string())
+                    ]
+                )),
+                Statement::Expr(Expr::Call(
+                    "Message".to_string(),
+                    vec![Expr::Number(0), Expr::String("done".to_string())]
+                )),
+            ]
+        );
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert!(parse_block("Write32(0x1000, 1").is_err());
+        assert!(parse_block("__var = 1;").is_err());
+        assert!(parse_block("a = 1 b = 2;").is_err());
+        assert!(parse_expression("1 +").is_err());
+        assert!(parse_expression("0xZZ").is_err());
+    }
+}
@@ -10,6 +10,7 @@
 //! https://github.com/arduino/OpenOCD/blob/master/contrib/itmdump.c
 
 use std::collections::VecDeque;
+use std::fmt;
 
 use scroll::Pread;
 
@@ -102,6 +103,36 @@ pub enum ExceptionType {
     ExternalInterrupt(usize),
 }
 
+impl ExceptionType {
+    /// Returns the exception number, as it is stored in the IPSR register.
+    ///
+    /// External interrupt `n` has the exception number `n + 16`.
+    pub fn number(&self) -> usize {
+        match self {
+            ExceptionType::Reset => 1,
+            ExceptionType::Nmi => 2,
+            ExceptionType::HardFault => 3,
+            ExceptionType::MemManage => 4,
+            ExceptionType::BusFault => 5,
+            ExceptionType::UsageFault => 6,
+            ExceptionType::SVCall => 11,
+            ExceptionType::DebugMonitor => 12,
+            ExceptionType::PendSV => 14,
+            ExceptionType::SysTick => 15,
+            ExceptionType::ExternalInterrupt(n) => *n,
+        }
+    }
+}
+
+impl fmt::Display for ExceptionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExceptionType::ExternalInterrupt(n) => write!(f, "IRQ {}", n.saturating_sub(16)),
+            other => write!(f, "{:?}", other),
+        }
+    }
+}
+
 /// This enum denotes the type of memory access.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum MemoryAccessType {
@@ -0,0 +1,304 @@
+//! Statistics of the exceptions of a Cortex-M core, from the DWT exception trace.
+//!
+//! The DWT emits a packet when an exception is entered, when it exits, and when the core
+//! returns to a preempted exception. [ExceptionTracer] follows these packets, and measures
+//! how long the handlers run and how much time the core spends in handler and thread mode.
+//!
+//! The times are counted in cycles of the core clock, with the ITM local timestamps. Events
+//! get the time of the latest timestamp packet.
+
+use super::{ExceptionAction, ExceptionType, TracePacket};
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// An exception trace packet, with the time it was received.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExceptionEvent {
+    /// The exception the packet is about.
+    pub exception: ExceptionType,
+    /// What happened to the exception.
+    pub action: ExceptionAction,
+    /// The core clock cycles since the start of the trace.
+    pub cycles: u64,
+}
+
+/// The statistics of a single exception.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExceptionStatistics {
+    /// The name of the exception, e.g. `SysTick` or `IRQ 5`.
+    pub name: String,
+    /// How often the exception was entered.
+    pub count: u64,
+    /// How many runs of the handler were measured, from the entry to the exit of the exception.
+    pub measured: u64,
+    /// The shortest run of the handler, in cycles.
+    pub min_cycles: Option<u64>,
+    /// The longest run of the handler, in cycles.
+    pub max_cycles: Option<u64>,
+    /// The sum of all measured runs of the handler, in cycles.
+    pub total_cycles: u64,
+}
+
+impl ExceptionStatistics {
+    fn new(name: String) -> Self {
+        Self {
+            name,
+            count: 0,
+            measured: 0,
+            min_cycles: None,
+            max_cycles: None,
+            total_cycles: 0,
+        }
+    }
+
+    /// The mean duration of the handler, in cycles.
+    pub fn mean_cycles(&self) -> Option<f64> {
+        if self.measured == 0 {
+            None
+        } else {
+            Some(self.total_cycles as f64 / self.measured as f64)
+        }
+    }
+
+    fn add_run(&mut self, cycles: u64) {
+        self.measured += 1;
+        self.total_cycles += cycles;
+        self.min_cycles = Some(self.min_cycles.map_or(cycles, |min| min.min(cycles)));
+        self.max_cycles = Some(self.max_cycles.map_or(cycles, |max| max.max(cycles)));
+    }
+}
+
+/// The aggregated statistics of all exceptions of a trace.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ExceptionSummary {
+    /// The statistics of every exception which occurred, by exception number.
+    pub exceptions: BTreeMap<usize, ExceptionStatistics>,
+    /// The cycles since the start of the trace.
+    pub total_cycles: u64,
+    /// The cycles the core spent in exception handlers.
+    pub handler_cycles: u64,
+    /// The number of overflow packets, each of which means that trace packets were dropped.
+    pub overflows: u64,
+    /// The number of exception packets which did not match the exceptions which were active.
+    pub unmatched: u64,
+}
+
+impl ExceptionSummary {
+    /// The cycles the core spent outside of exception handlers.
+    pub fn thread_cycles(&self) -> u64 {
+        self.total_cycles.saturating_sub(self.handler_cycles)
+    }
+
+    /// Returns a note if packets were lost, in which case the statistics are incomplete.
+    pub fn confidence_note(&self) -> Option<String> {
+        if self.overflows == 0 && self.unmatched == 0 {
+            return None;
+        }
+
+        Some(format!(
+            "The trace overflowed {} times and {} exception packets did not match, \
+            so exceptions are missing from these statistics.",
+            self.overflows, self.unmatched
+        ))
+    }
+}
+
+/// Follows the exceptions of a core through the decoded trace packets.
+#[derive(Debug, Default)]
+pub struct ExceptionTracer {
+    /// The names of external interrupts, by exception number.
+    names: HashMap<usize, String>,
+    /// The core clock cycles since the start of the trace.
+    cycles: u64,
+    /// The active exceptions, with the cycle they were entered in, the innermost one last.
+    active: Vec<(usize, u64)>,
+    /// The cycle the core entered handler mode in, while an exception is active.
+    handler_since: Option<u64>,
+    summary: ExceptionSummary,
+}
+
+impl ExceptionTracer {
+    /// Creates a tracer, which names the exceptions with the built-in Cortex-M names.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Names the external interrupts, by their interrupt number, e.g. with the names of the SVD
+    /// file of the chip. The names are used for the statistics of exceptions which occur later.
+    pub fn set_interrupt_names(&mut self, names: impl IntoIterator<Item = (usize, String)>) {
+        self.names = names.into_iter().map(|(n, name)| (n + 16, name)).collect();
+    }
+
+    /// Returns the name of the exception.
+    pub fn name(&self, exception: &ExceptionType) -> String {
+        self.names
+            .get(&exception.number())
+            .cloned()
+            .unwrap_or_else(|| exception.to_string())
+    }
+
+    /// Processes the next packet of the trace.
+    ///
+    /// Returns the event for exception trace packets.
+    pub fn process(&mut self, packet: &TracePacket) -> Option<ExceptionEvent> {
+        match packet {
+            TracePacket::TimeStamp { ts, .. } => {
+                self.cycles += *ts as u64;
+                self.summary.total_cycles = self.cycles;
+                None
+            }
+            TracePacket::Overflow => {
+                // Exits may have been dropped, so the active exceptions are unknown.
+                self.summary.overflows += 1;
+                self.active.clear();
+                self.handler_since = None;
+                None
+            }
+            TracePacket::ExceptionTrace { exception, action } => {
+                self.exception(exception, action);
+                Some(ExceptionEvent {
+                    exception: exception.clone(),
+                    action: action.clone(),
+                    cycles: self.cycles,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    fn exception(&mut self, exception: &ExceptionType, action: &ExceptionAction) {
+        let number = exception.number();
+
+        match action {
+            ExceptionAction::Entered => {
+                let name = self.name(exception);
+                self.summary
+                    .exceptions
+                    .entry(number)
+                    .or_insert_with(|| ExceptionStatistics::new(name))
+                    .count += 1;
+
+                if self.active.is_empty() {
+                    self.handler_since = Some(self.cycles);
+                }
+                self.active.push((number, self.cycles));
+            }
+            ExceptionAction::Exited => {
+                let position = match self.active.iter().rposition(|(n, _)| *n == number) {
+                    Some(position) => position,
+                    None => {
+                        self.summary.unmatched += 1;
+                        return;
+                    }
+                };
+
+                // Exceptions which were entered later, but did not exit.
+                self.summary.unmatched += (self.active.len() - position - 1) as u64;
+
+                let (_, entered) = self.active[position];
+                self.active.truncate(position);
+
+                if let Some(statistics) = self.summary.exceptions.get_mut(&number) {
+                    statistics.add_run(self.cycles - entered);
+                }
+
+                if self.active.is_empty() {
+                    if let Some(since) = self.handler_since.take() {
+                        self.summary.handler_cycles += self.cycles - since;
+                    }
+                }
+            }
+            // The core continues with a preempted exception, which is still active.
+            ExceptionAction::Returned => {}
+        }
+    }
+
+    /// Returns the statistics of the trace so far.
+    pub fn summary(&self) -> ExceptionSummary {
+        let mut summary = self.summary.clone();
+        if let Some(since) = self.handler_since {
+            summary.handler_cycles += self.cycles - since;
+        }
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{ExceptionAction, ExceptionType, TracePacket};
+    use super::ExceptionTracer;
+
+    fn exception(n: usize, action: ExceptionAction) -> TracePacket {
+        TracePacket::ExceptionTrace {
+            exception: ExceptionType::ExternalInterrupt(n),
+            action,
+        }
+    }
+
+    fn time(ts: usize) -> TracePacket {
+        TracePacket::TimeStamp { tc: 0, ts }
+    }
+
+    #[test]
+    fn nested_exceptions_are_measured() {
+        let mut tracer = ExceptionTracer::new();
+        tracer.set_interrupt_names(vec![(5, "USART1".to_owned())]);
+
+        let packets = [
+            time(100),
+            exception(21, ExceptionAction::Entered),
+            time(10),
+            exception(22, ExceptionAction::Entered),
+            time(5),
+            exception(22, ExceptionAction::Exited),
+            exception(21, ExceptionAction::Returned),
+            time(20),
+            exception(21, ExceptionAction::Exited),
+            time(65),
+            exception(21, ExceptionAction::Entered),
+            time(15),
+            exception(21, ExceptionAction::Exited),
+        ];
+        for packet in &packets {
+            tracer.process(packet);
+        }
+
+        let summary = tracer.summary();
+        assert_eq!(summary.total_cycles, 215);
+        assert_eq!(summary.handler_cycles, 50);
+        assert_eq!(summary.thread_cycles(), 165);
+        assert_eq!(summary.confidence_note(), None);
+
+        let usart = &summary.exceptions[&21];
+        assert_eq!(usart.name, "USART1");
+        assert_eq!(usart.count, 2);
+        assert_eq!(usart.min_cycles, Some(15));
+        assert_eq!(usart.max_cycles, Some(35));
+        assert_eq!(usart.mean_cycles(), Some(25.0));
+
+        let other = &summary.exceptions[&22];
+        assert_eq!(other.name, "IRQ 6");
+        assert_eq!(other.total_cycles, 5);
+    }
+
+    #[test]
+    fn lost_packets_are_reported() {
+        let mut tracer = ExceptionTracer::new();
+
+        let packets = [
+            exception(21, ExceptionAction::Entered),
+            TracePacket::Overflow,
+            exception(21, ExceptionAction::Exited),
+        ];
+        for packet in &packets {
+            tracer.process(packet);
+        }
+
+        let summary = tracer.summary();
+        assert_eq!(summary.overflows, 1);
+        assert_eq!(summary.unmatched, 1);
+        assert_eq!(summary.exceptions[&21].measured, 0);
+        assert!(summary.confidence_note().is_some());
+    }
+}
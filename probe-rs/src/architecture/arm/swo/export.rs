@@ -0,0 +1,258 @@
+//! Writers for SWO trace data in file formats which can be opened with other tools.
+//!
+//! [SwvWriter] stores the raw SWO bytes, as they are read by viewers like itmdump or orbuculum.
+//! [TraceEventWriter] converts the decoded [TracePacket]s into the JSON `trace_event` format,
+//! which can be opened in [Perfetto](https://ui.perfetto.dev) and `chrome://tracing`.
+//!
+//! Both write the data as it arrives, so a file can be written while the target runs.
+
+use super::{ExceptionAction, TracePacket};
+
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+
+/// The thread ID of the track with the exceptions, after the 32 ITM stimulus ports.
+const EXCEPTION_TRACK: u32 = 32;
+
+/// The process ID of all events.
+const PROCESS: u32 = 1;
+
+/// Writes the raw SWO bytes, without decoding them.
+pub struct SwvWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> SwvWriter<W> {
+    /// Creates a writer which writes the SWO data to `writer`.
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    /// Writes the bytes read from the SWO interface.
+    pub fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.writer.write_all(data)?;
+        self.writer.flush()
+    }
+
+    /// Returns the inner writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// A single event of the `trace_event` format.
+#[derive(Serialize)]
+struct TraceEvent {
+    name: String,
+    /// The phase of the event, e.g. `B` for the begin of a slice.
+    ph: &'static str,
+    /// The timestamp in microseconds.
+    ts: f64,
+    pid: u32,
+    tid: u32,
+    /// The scope of instant events.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    s: Option<&'static str>,
+    #[serde(skip_serializing_if = "Value::is_null")]
+    args: Value,
+}
+
+/// Writes the decoded trace packets in the JSON `trace_event` format of Chrome and Perfetto.
+///
+/// - Every ITM stimulus port becomes a named track, on which the written values are instant events.
+/// - DWT exception trace packets become slices on the `Exceptions` track, from the entry to the exit of the exception.
+/// - Overflows are global instant events.
+///
+/// The local timestamps of the ITM are counted in cycles of the core clock, and converted
+/// to microseconds. Events get the time of the latest timestamp packet.
+///
+/// The closing bracket of the JSON array is optional in this format, so the file can be
+/// opened even if [TraceEventWriter::finish] was not called.
+pub struct TraceEventWriter<W: Write> {
+    writer: W,
+    core_clock: u32,
+    /// The core clock cycles since the start of the trace.
+    cycles: u64,
+    /// The tracks which already have a name.
+    named_tracks: BTreeSet<u32>,
+    /// `true` until the first event was written.
+    first: bool,
+}
+
+impl<W: Write> TraceEventWriter<W> {
+    /// Creates a writer which converts the timestamps with the core clock `core_clock`, in Hz.
+    pub fn new(mut writer: W, core_clock: u32) -> io::Result<Self> {
+        writer.write_all(b"[\n")?;
+
+        let mut trace_writer = Self {
+            writer,
+            core_clock,
+            cycles: 0,
+            named_tracks: BTreeSet::new(),
+            first: true,
+        };
+
+        trace_writer.write_event(&TraceEvent {
+            name: "process_name".into(),
+            ph: "M",
+            ts: 0.0,
+            pid: PROCESS,
+            tid: 0,
+            s: None,
+            args: json!({ "name": "SWO trace" }),
+        })?;
+
+        Ok(trace_writer)
+    }
+
+    /// The time of the events, in microseconds since the start of the trace.
+    fn timestamp(&self) -> f64 {
+        self.cycles as f64 * 1_000_000.0 / f64::from(self.core_clock)
+    }
+
+    /// Writes the event of the `packet`, if it has one.
+    pub fn write_packet(&mut self, packet: &TracePacket) -> io::Result<()> {
+        match packet {
+            TracePacket::TimeStamp { ts, .. } => {
+                self.cycles += *ts as u64;
+            }
+            TracePacket::ItmData { id, payload } => {
+                let track = *id as u32;
+                self.name_track(track, format!("ITM port {}", id))?;
+
+                let value = payload
+                    .iter()
+                    .rev()
+                    .fold(0u32, |value, byte| value << 8 | u32::from(*byte));
+
+                self.write_event(&TraceEvent {
+                    name: format!("{:#x}", value),
+                    ph: "i",
+                    ts: self.timestamp(),
+                    pid: PROCESS,
+                    tid: track,
+                    s: Some("t"),
+                    args: json!({ "value": value, "size": payload.len() }),
+                })?;
+            }
+            TracePacket::ExceptionTrace { exception, action } => {
+                let ph = match action {
+                    ExceptionAction::Entered => "B",
+                    ExceptionAction::Exited => "E",
+                    // The preempted exception continues, its slice is still open.
+                    ExceptionAction::Returned => return Ok(()),
+                };
+
+                self.name_track(EXCEPTION_TRACK, "Exceptions".into())?;
+                self.write_event(&TraceEvent {
+                    name: exception.to_string(),
+                    ph,
+                    ts: self.timestamp(),
+                    pid: PROCESS,
+                    tid: EXCEPTION_TRACK,
+                    s: None,
+                    args: Value::Null,
+                })?;
+            }
+            TracePacket::Overflow => {
+                self.write_event(&TraceEvent {
+                    name: "Overflow".into(),
+                    ph: "i",
+                    ts: self.timestamp(),
+                    pid: PROCESS,
+                    tid: 0,
+                    s: Some("g"),
+                    args: Value::Null,
+                })?;
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Writes the buffered events.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Closes the JSON array, and returns the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.write_all(b"\n]\n")?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    /// Names the `track` when it is used for the first time.
+    fn name_track(&mut self, track: u32, name: String) -> io::Result<()> {
+        if !self.named_tracks.insert(track) {
+            return Ok(());
+        }
+
+        self.write_event(&TraceEvent {
+            name: "thread_name".into(),
+            ph: "M",
+            ts: 0.0,
+            pid: PROCESS,
+            tid: track,
+            s: None,
+            args: json!({ "name": name }),
+        })
+    }
+
+    fn write_event(&mut self, event: &TraceEvent) -> io::Result<()> {
+        if !self.first {
+            self.writer.write_all(b",\n")?;
+        }
+        self.first = false;
+
+        serde_json::to_writer(&mut self.writer, event)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{ExceptionAction, ExceptionType, TracePacket};
+    use super::TraceEventWriter;
+
+    #[test]
+    fn packets_are_written_as_trace_events() {
+        let mut writer = TraceEventWriter::new(vec![], 2_000_000).unwrap();
+
+        let packets = [
+            TracePacket::ItmData {
+                id: 1,
+                payload: vec![0x34, 0x12],
+            },
+            TracePacket::TimeStamp { tc: 0, ts: 1000 },
+            TracePacket::ExceptionTrace {
+                exception: ExceptionType::ExternalInterrupt(21),
+                action: ExceptionAction::Entered,
+            },
+            TracePacket::TimeStamp { tc: 0, ts: 3 },
+            TracePacket::ExceptionTrace {
+                exception: ExceptionType::ExternalInterrupt(21),
+                action: ExceptionAction::Exited,
+            },
+        ];
+        for packet in &packets {
+            writer.write_packet(packet).unwrap();
+        }
+
+        let json = String::from_utf8(writer.finish().unwrap()).unwrap();
+        let events: serde_json::Value = serde_json::from_str(&json).unwrap();
+
+        let expected = serde_json::json!([
+            {"name": "process_name", "ph": "M", "ts": 0.0, "pid": 1, "tid": 0, "args": {"name": "SWO trace"}},
+            {"name": "thread_name", "ph": "M", "ts": 0.0, "pid": 1, "tid": 1, "args": {"name": "ITM port 1"}},
+            {"name": "0x1234", "ph": "i", "ts": 0.0, "pid": 1, "tid": 1, "s": "t", "args": {"value": 0x1234, "size": 2}},
+            {"name": "thread_name", "ph": "M", "ts": 0.0, "pid": 1, "tid": 32, "args": {"name": "Exceptions"}},
+            {"name": "IRQ 5", "ph": "B", "ts": 500.0, "pid": 1, "tid": 32},
+            {"name": "IRQ 5", "ph": "E", "ts": 501.5, "pid": 1, "tid": 32},
+        ]);
+        assert_eq!(events, expected);
+    }
+}
@@ -1,7 +1,11 @@
 mod decoder;
+mod exceptions;
+mod export;
 mod publisher;
 
 pub use decoder::{Decoder, ExceptionAction, ExceptionType, TracePacket};
+pub use exceptions::{ExceptionEvent, ExceptionStatistics, ExceptionSummary, ExceptionTracer};
+pub use export::{SwvWriter, TraceEventWriter};
 pub use publisher::{SwoPublisher, UpdaterChannel};
 
 use crate::Error;
@@ -7,11 +7,12 @@
 use super::{register, Dmcontrol, Dmstatus};
 use crate::architecture::riscv::*;
 use crate::DebugProbeError;
-use crate::{MemoryInterface, Probe};
+use crate::{AccessSize, MemoryInterface, Probe};
 
 use crate::{probe::JTAGAccess, CoreRegisterAddress, DebugProbe, Error as ProbeRsError};
 
 use bitfield::bitfield;
+use jep106::JEP106Code;
 use std::{
     collections::HashMap,
     convert::TryInto,
@@ -39,8 +40,14 @@ pub(crate) enum RiscvError {
     ProgramBufferTooSmall,
     #[error("Memory width larger than 32 bits is not supported yet.")]
     UnsupportedBusAccessWidth(RiscvBusAccess),
+    #[error("Failed to access address {address:#010x} as it is not aligned to {alignment} bytes.")]
+    UnalignedAccess { address: u32, alignment: usize },
     #[error("Unexpected trigger type {0} for address breakpoint.")]
     UnexpectedTriggerType(u32),
+    #[error("The CSR {0:#05x} is not implemented by the core.")]
+    CsrNotImplemented(u16),
+    #[error("The debug module does not support {0}.")]
+    ResetNotSupported(&'static str),
 }
 
 impl From<RiscvError> for ProbeRsError {
@@ -175,6 +182,59 @@ impl Default for RiscvCommunicationInterfaceState {
     }
 }
 
+/// Information read from a RISC-V target, which is used
+/// for the automatic detection of the connected chip.
+#[derive(Debug)]
+pub struct RiscvChipInfo {
+    /// The JTAG IDCODE of the debug transport module.
+    pub idcode: u32,
+    /// The `marchid` CSR of the first hart, if it could be read.
+    pub marchid: Option<u32>,
+    /// The `mimpid` CSR of the first hart, if it could be read.
+    pub mimpid: Option<u32>,
+}
+
+impl RiscvChipInfo {
+    /// The manufacturer encoded in the JTAG IDCODE.
+    pub fn manufacturer(&self) -> JEP106Code {
+        JEP106Code::new(
+            ((self.idcode >> 8) & 0xf) as u8,
+            ((self.idcode >> 1) & 0x7f) as u8,
+        )
+    }
+
+    /// The part number encoded in the JTAG IDCODE.
+    pub fn part(&self) -> u16 {
+        (self.idcode >> 12) as u16
+    }
+}
+
+impl std::fmt::Display for RiscvChipInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let manufacturer = self.manufacturer();
+        let manu = match manufacturer.get() {
+            Some(name) => name.to_string(),
+            None => format!(
+                "<unknown manufacturer (cc={:2x}, id={:2x})>",
+                manufacturer.cc, manufacturer.id
+            ),
+        };
+        write!(
+            f,
+            "{} 0x{:04x} (IDCODE 0x{:08x})",
+            manu,
+            self.part(),
+            self.idcode
+        )?;
+
+        if let (Some(marchid), Some(mimpid)) = (self.marchid, self.mimpid) {
+            write!(f, ", marchid 0x{:08x}, mimpid 0x{:08x}", marchid, mimpid)?;
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub struct RiscvCommunicationInterface {
     probe: Box<dyn JTAGAccess>,
@@ -288,6 +348,31 @@ impl<'probe> RiscvCommunicationInterface {
         Ok(u32::from_le_bytes((&value[..]).try_into().unwrap()))
     }
 
+    /// Read the information used to identify the connected chip.
+    ///
+    /// The `marchid` and `mimpid` CSRs can usually only be read while the hart
+    /// is halted, so they are left empty if reading them fails.
+    pub(crate) fn read_chip_info(&mut self) -> Result<RiscvChipInfo, DebugProbeError> {
+        let idcode = self.read_idcode()?;
+
+        let mut read_csr = |address: u16| match self.abstract_cmd_register_read(address) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                log::debug!("Failed to read CSR {:#05x}: {}", address, e);
+                None
+            }
+        };
+
+        let marchid = read_csr(0xf12);
+        let mimpid = read_csr(0xf13);
+
+        Ok(RiscvChipInfo {
+            idcode,
+            marchid,
+            mimpid,
+        })
+    }
+
     /// Perform an access to the dmi register of the JTAG Transport module.
     ///
     /// Every access both writes and reads from the register, which means a value is always
@@ -574,7 +659,9 @@ impl<'probe> RiscvCommunicationInterface {
         Ok(())
     }
 
-    pub(crate) fn execute_abstract_command(&mut self, command: u32) -> Result<(), RiscvError> {
+    /// Ensures that the preconditions for abstract commands are fulfilled,
+    /// and clears the error of a previous command.
+    fn prepare_abstract_commands(&mut self) -> Result<(), RiscvError> {
         // ensure that preconditions are fullfileld
         // haltreq      = 0
         // resumereq    = 0
@@ -600,6 +687,12 @@ impl<'probe> RiscvCommunicationInterface {
             self.write_dm_register(abstractcs_clear)?;
         }
 
+        Ok(())
+    }
+
+    pub(crate) fn execute_abstract_command(&mut self, command: u32) -> Result<(), RiscvError> {
+        self.prepare_abstract_commands()?;
+
         self.write_dm_register(Command(command))?;
 
         // poll busy flag in abstractcs
@@ -741,6 +834,92 @@ impl<'probe> RiscvCommunicationInterface {
         }
     }
 
+    /// Read several core registers using abstract commands.
+    ///
+    /// The preconditions are only checked once, and like for memory reads, the commands
+    /// are not polled. The command error is checked after the last register, and cleared
+    /// if one of the commands failed.
+    pub(crate) fn abstract_cmd_register_read_batch(
+        &mut self,
+        regnos: &[CoreRegisterAddress],
+    ) -> Result<Vec<u32>, RiscvError> {
+        if !regnos.iter().all(|&regno| {
+            self.check_abstract_cmd_register_support(regno, CoreRegisterAbstractCmdSupport::READ)
+        }) {
+            return Err(RiscvError::AbstractCommand(
+                AbstractCommandErrorKind::NotSupported,
+            ));
+        }
+
+        self.prepare_abstract_commands()?;
+
+        let mut values = Vec::with_capacity(regnos.len());
+        for regno in regnos {
+            let mut command = AccessRegisterCommand(0);
+            command.set_cmd_type(0);
+            command.set_transfer(true);
+            command.set_aarsize(RiscvBusAccess::A32);
+            command.set_regno(regno.0 as u32);
+
+            self.write_dm_register(command)?;
+
+            let value: Data0 = self.read_dm_register()?;
+            values.push(value.into());
+        }
+
+        self.check_abstract_cmd_batch()?;
+
+        Ok(values)
+    }
+
+    /// Write several core registers using abstract commands,
+    /// see [RiscvCommunicationInterface::abstract_cmd_register_read_batch].
+    pub(crate) fn abstract_cmd_register_write_batch(
+        &mut self,
+        values: &[(CoreRegisterAddress, u32)],
+    ) -> Result<(), RiscvError> {
+        if !values.iter().all(|&(regno, _)| {
+            self.check_abstract_cmd_register_support(regno, CoreRegisterAbstractCmdSupport::WRITE)
+        }) {
+            return Err(RiscvError::AbstractCommand(
+                AbstractCommandErrorKind::NotSupported,
+            ));
+        }
+
+        self.prepare_abstract_commands()?;
+
+        for &(regno, value) in values {
+            let mut command = AccessRegisterCommand(0);
+            command.set_cmd_type(0);
+            command.set_transfer(true);
+            command.set_write(true);
+            command.set_aarsize(RiscvBusAccess::A32);
+            command.set_regno(regno.0 as u32);
+
+            self.write_dm_register(Data0(value))?;
+            self.write_dm_register(command)?;
+        }
+
+        self.check_abstract_cmd_batch()
+    }
+
+    /// Checks the command error after a batch of abstract commands, and clears it.
+    fn check_abstract_cmd_batch(&mut self) -> Result<(), RiscvError> {
+        let status: Abstractcs = self.read_dm_register()?;
+
+        if status.cmderr() != 0 {
+            let mut abstractcs_clear = Abstractcs(0);
+            abstractcs_clear.set_cmderr(0x7);
+            self.write_dm_register(abstractcs_clear)?;
+
+            return Err(RiscvError::AbstractCommand(
+                AbstractCommandErrorKind::parse(status.cmderr() as u8),
+            ));
+        }
+
+        Ok(())
+    }
+
     pub fn close(self) -> Probe {
         Probe::from_attached_probe(self.probe.into_probe())
     }
@@ -1036,11 +1215,81 @@ impl MemoryInterface for RiscvCommunicationInterface {
         Ok(())
     }
 
+    fn read_mem_with_size(
+        &mut self,
+        address: u32,
+        data: &mut [u8],
+        size: AccessSize,
+    ) -> Result<(), crate::Error> {
+        let width = bus_access_width(size)?;
+
+        if !size.is_aligned(address, data.len()) {
+            return Err(RiscvError::UnalignedAccess {
+                address,
+                alignment: size.bytes(),
+            }
+            .into());
+        }
+
+        // Every value is loaded with a single instruction of the requested width.
+        for (address, chunk) in (address..)
+            .step_by(size.bytes())
+            .zip(data.chunks_exact_mut(size.bytes()))
+        {
+            let value = self.perform_memory_read(address, width)?;
+
+            chunk.copy_from_slice(&value.to_le_bytes()[..size.bytes()]);
+        }
+
+        Ok(())
+    }
+
+    fn write_mem_with_size(
+        &mut self,
+        address: u32,
+        data: &[u8],
+        size: AccessSize,
+    ) -> Result<(), crate::Error> {
+        let width = bus_access_width(size)?;
+
+        if !size.is_aligned(address, data.len()) {
+            return Err(RiscvError::UnalignedAccess {
+                address,
+                alignment: size.bytes(),
+            }
+            .into());
+        }
+
+        // Every value is stored with a single instruction of the requested width.
+        for (address, chunk) in (address..)
+            .step_by(size.bytes())
+            .zip(data.chunks_exact(size.bytes()))
+        {
+            let mut value = [0u8; 4];
+            value[..size.bytes()].copy_from_slice(chunk);
+
+            self.perform_memory_write(address, width, u32::from_le_bytes(value))?;
+        }
+
+        Ok(())
+    }
+
     fn flush(&mut self) -> Result<(), crate::Error> {
         Ok(())
     }
 }
 
+/// Returns the bus access width for an access size, if the program buffer
+/// has load and store instructions of this size.
+fn bus_access_width(size: AccessSize) -> Result<RiscvBusAccess, crate::Error> {
+    match size {
+        AccessSize::U8 => Ok(RiscvBusAccess::A8),
+        AccessSize::U16 => Ok(RiscvBusAccess::A16),
+        AccessSize::U32 => Ok(RiscvBusAccess::A32),
+        AccessSize::U64 => Err(crate::Error::AccessSizeNotSupported(size)),
+    }
+}
+
 /// Access width for bus access.
 /// This is used both for system bus access (`sbcs` register),
 /// as well for abstract commands.
@@ -11,7 +11,7 @@ use communication_interface::{
 };
 
 use crate::core::{CoreInformation, RegisterFile};
-use crate::{CoreRegisterAddress, CoreStatus, Error, HaltReason, MemoryInterface};
+use crate::{AccessSize, CoreRegisterAddress, CoreStatus, Error, HaltReason, MemoryInterface};
 use bitfield::bitfield;
 use register::RISCV_REGISTERS;
 use std::time::{Duration, Instant};
@@ -23,6 +23,27 @@ pub(crate) mod assembly;
 
 pub mod communication_interface;
 
+/// Highest register number which refers to a CSR.
+///
+/// In the register number space used by abstract commands, the numbers
+/// `0x0000` to `0x0fff` are mapped directly to the CSRs, followed by the
+/// GPRs starting at `0x1000`.
+const CSR_MAX_ADDRESS: u16 = 0x0fff;
+
+/// Accessing a CSR which is not implemented raises an illegal instruction exception,
+/// which is reported as an exception of the abstract command. Turn this into a more
+/// helpful error.
+fn map_csr_error(address: u16, error: RiscvError) -> RiscvError {
+    match error {
+        RiscvError::AbstractCommand(AbstractCommandErrorKind::Exception)
+            if address <= CSR_MAX_ADDRESS =>
+        {
+            RiscvError::CsrNotImplemented(address)
+        }
+        other => other,
+    }
+}
+
 pub struct Riscv32<'probe> {
     interface: &'probe mut RiscvCommunicationInterface,
 }
@@ -45,7 +66,9 @@ impl<'probe> Riscv32<'probe> {
         // if not supported
         match self.interface.abstract_cmd_register_read(address) {
             Ok(v) => Ok(v),
-            Err(RiscvError::AbstractCommand(AbstractCommandErrorKind::NotSupported)) => {
+            Err(RiscvError::AbstractCommand(AbstractCommandErrorKind::NotSupported))
+                if address <= CSR_MAX_ADDRESS =>
+            {
                 log::debug!("Could not read core register {:#x} with abstract command, falling back to program buffer", address);
                 let reg_value = self.read_csr_progbuf(address)?;
                 Ok(reg_value)
@@ -78,16 +101,19 @@ impl<'probe> Riscv32<'probe> {
         let mut postexec_cmd = AccessRegisterCommand(0);
         postexec_cmd.set_postexec(true);
 
-        self.interface.execute_abstract_command(postexec_cmd.0)?;
-
-        // read the s0 value
-        let reg_value = self.interface.abstract_cmd_register_read(&register::S0)?;
+        // If the CSR does not exist, the csrr instruction raises an illegal instruction
+        // exception. s0 has to be restored in any case, so the result is only checked
+        // afterwards.
+        let result = self
+            .interface
+            .execute_abstract_command(postexec_cmd.0)
+            .and_then(|_| self.interface.abstract_cmd_register_read(&register::S0));
 
         // restore original value in s0
         self.interface
             .abstract_cmd_register_write(&register::S0, s0)?;
 
-        Ok(reg_value)
+        result
     }
 
     fn write_csr(&mut self, address: u16, value: u32) -> Result<(), RiscvError> {
@@ -95,7 +121,9 @@ impl<'probe> Riscv32<'probe> {
 
         match self.interface.abstract_cmd_register_write(address, value) {
             Ok(_) => Ok(()),
-            Err(RiscvError::AbstractCommand(AbstractCommandErrorKind::NotSupported)) => {
+            Err(RiscvError::AbstractCommand(AbstractCommandErrorKind::NotSupported))
+                if address <= CSR_MAX_ADDRESS =>
+            {
                 log::debug!("Could not write core register {:#x} with abstract command, falling back to program buffer", address);
                 self.write_csr_progbuf(address, value)?;
                 Ok(())
@@ -134,14 +162,16 @@ impl<'probe> Riscv32<'probe> {
         let mut postexec_cmd = AccessRegisterCommand(0);
         postexec_cmd.set_postexec(true);
 
-        self.interface.execute_abstract_command(postexec_cmd.0)?;
+        // s0 has already been overwritten at this point, so it has to be restored
+        // even if the csrw instruction raised an exception.
+        let result = self.interface.execute_abstract_command(postexec_cmd.0);
 
         // command: transfer, regno = 0x1008
         // restore original value in s0
         self.interface
             .abstract_cmd_register_write(&register::S0, s0)?;
 
-        Ok(())
+        result
     }
 }
 
@@ -346,6 +376,78 @@ impl<'probe> CoreInterface for Riscv32<'probe> {
         Ok(CoreInformation { pc })
     }
 
+    fn reset_core(&mut self) -> Result<(), crate::Error> {
+        log::debug!("Resetting hart, setting hartreset bit");
+
+        let mut dmcontrol = Dmcontrol(0);
+        dmcontrol.set_dmactive(true);
+        dmcontrol.set_hartreset(true);
+
+        self.interface.write_dm_register(dmcontrol)?;
+
+        // The bit is hardwired to zero if resetting a single hart is not supported
+        let readback: Dmcontrol = self.interface.read_dm_register()?;
+
+        let mut dmcontrol = Dmcontrol(0);
+        dmcontrol.set_dmactive(true);
+        dmcontrol.set_hartreset(false);
+
+        self.interface.write_dm_register(dmcontrol)?;
+
+        if !readback.hartreset() {
+            return Err(RiscvError::ResetNotSupported("resetting only the hart").into());
+        }
+
+        let readback: Dmstatus = self.interface.read_dm_register()?;
+
+        if !readback.allhavereset() {
+            log::warn!("Dmstatus: {:?}", readback);
+            return Err(RiscvError::RequestNotAcknowledged.into());
+        }
+
+        // acknowledge the reset
+        let mut dmcontrol = Dmcontrol(0);
+        dmcontrol.set_dmactive(true);
+        dmcontrol.set_ackhavereset(true);
+
+        self.interface.write_dm_register(dmcontrol)?;
+
+        Ok(())
+    }
+
+    fn reset_hardware_pin(&mut self) -> Result<(), crate::Error> {
+        let probe: &mut dyn crate::DebugProbe = self.interface.as_mut();
+        probe.target_reset()?;
+
+        Ok(())
+    }
+
+    fn reset_catch_set(&mut self) -> Result<(), crate::Error> {
+        let status: Dmstatus = self.interface.read_dm_register()?;
+
+        if !status.hasresethaltreq() {
+            return Err(RiscvError::ResetNotSupported("halting after a reset").into());
+        }
+
+        let mut dmcontrol = Dmcontrol(0);
+        dmcontrol.set_dmactive(true);
+        dmcontrol.set_resethaltreq(true);
+
+        self.interface.write_dm_register(dmcontrol)?;
+
+        Ok(())
+    }
+
+    fn reset_catch_clear(&mut self) -> Result<(), crate::Error> {
+        let mut dmcontrol = Dmcontrol(0);
+        dmcontrol.set_dmactive(true);
+        dmcontrol.set_clrresethaltreq(true);
+
+        self.interface.write_dm_register(dmcontrol)?;
+
+        Ok(())
+    }
+
     fn step(&mut self) -> Result<crate::core::CoreInformation, crate::Error> {
         let mut dcsr = Dcsr(self.read_core_reg(CoreRegisterAddress(0x7b0))?);
 
@@ -370,11 +472,41 @@ impl<'probe> CoreInterface for Riscv32<'probe> {
     }
 
     fn read_core_reg(&mut self, address: crate::CoreRegisterAddress) -> Result<u32, crate::Error> {
-        self.read_csr(address.0).map_err(|e| e.into())
+        self.read_csr(address.0)
+            .map_err(|e| map_csr_error(address.0, e).into())
     }
 
     fn write_core_reg(&mut self, address: crate::CoreRegisterAddress, value: u32) -> Result<()> {
-        self.write_csr(address.0, value).map_err(|e| e.into())
+        self.write_csr(address.0, value)
+            .map_err(|e| map_csr_error(address.0, e).into())
+    }
+
+    fn read_core_regs(&mut self, addresses: &[CoreRegisterAddress]) -> Vec<Result<u32, Error>> {
+        match self.interface.abstract_cmd_register_read_batch(addresses) {
+            Ok(values) => values.into_iter().map(Ok).collect(),
+            Err(e) => {
+                // The failed register is found, and possibly read with the program buffer,
+                // when reading them one by one.
+                log::debug!("Reading the core registers in a batch failed: {}", e);
+                addresses
+                    .iter()
+                    .map(|&address| self.read_core_reg(address))
+                    .collect()
+            }
+        }
+    }
+
+    fn write_core_regs(&mut self, values: &[(CoreRegisterAddress, u32)]) -> Vec<Result<(), Error>> {
+        match self.interface.abstract_cmd_register_write_batch(values) {
+            Ok(()) => values.iter().map(|_| Ok(())).collect(),
+            Err(e) => {
+                log::debug!("Writing the core registers in a batch failed: {}", e);
+                values
+                    .iter()
+                    .map(|&(address, value)| Ok(self.write_core_reg(address, value)?))
+                    .collect()
+            }
+        }
     }
 
     fn get_available_breakpoint_units(&mut self) -> Result<u32, crate::Error> {
@@ -560,6 +692,9 @@ impl<'probe> MemoryInterface for Riscv32<'probe> {
     fn read_word_8(&mut self, address: u32) -> Result<u8, Error> {
         self.interface.read_word_8(address)
     }
+    fn read_word_64(&mut self, address: u32) -> Result<u64, Error> {
+        self.interface.read_word_64(address)
+    }
     fn read_32(&mut self, address: u32, data: &mut [u32]) -> Result<(), Error> {
         self.interface.read_32(address, data)
     }
@@ -572,12 +707,31 @@ impl<'probe> MemoryInterface for Riscv32<'probe> {
     fn write_word_8(&mut self, address: u32, data: u8) -> Result<(), Error> {
         self.interface.write_word_8(address, data)
     }
+    fn write_word_64(&mut self, address: u32, data: u64) -> Result<(), Error> {
+        self.interface.write_word_64(address, data)
+    }
     fn write_32(&mut self, address: u32, data: &[u32]) -> Result<(), Error> {
         self.interface.write_32(address, data)
     }
     fn write_8(&mut self, address: u32, data: &[u8]) -> Result<(), Error> {
         self.interface.write_8(address, data)
     }
+    fn read_mem_with_size(
+        &mut self,
+        address: u32,
+        data: &mut [u8],
+        size: AccessSize,
+    ) -> Result<(), Error> {
+        self.interface.read_mem_with_size(address, data, size)
+    }
+    fn write_mem_with_size(
+        &mut self,
+        address: u32,
+        data: &[u8],
+        size: AccessSize,
+    ) -> Result<(), Error> {
+        self.interface.write_mem_with_size(address, data, size)
+    }
     fn flush(&mut self) -> Result<(), Error> {
         self.interface.flush()
     }
@@ -788,3 +942,319 @@ bitfield! {
     store, set_store: 1;
     load, set_load: 0;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probe::{
+        DebugProbe, DebugProbeError, DebugProbeSelector, JTAGAccess, ProbeCreationError,
+        WireProtocol,
+    };
+    use std::collections::HashMap;
+
+    /// `cmderr` of an abstract command which is not supported.
+    const NOT_SUPPORTED: u32 = 2;
+    /// `cmderr` of an abstract command which raised an exception.
+    const EXCEPTION: u32 = 3;
+
+    const MSCRATCH: u16 = 0x340;
+    /// A custom CSR, which the fake hart does not implement.
+    const UNIMPLEMENTED_CSR: u16 = 0x7c0;
+
+    /// A halted hart behind a debug module, which is accessed through the DMI of a JTAG probe.
+    ///
+    /// Only the CSRs in `csrs` are implemented, accessing any other CSR raises an exception.
+    /// The program buffer executes `csrrw` and `csrrs` instructions.
+    #[derive(Debug)]
+    struct FakeDebugModule {
+        /// If CSRs can be accessed with abstract commands, or only with the program buffer.
+        abstract_csr_access: bool,
+        csrs: HashMap<u16, u32>,
+        gprs: [u32; 32],
+        data0: u32,
+        progbuf: [u32; 2],
+        cmderr: u32,
+        /// The result of a DMI read, which is shifted out by the next DMI access.
+        dmi_result: u32,
+    }
+
+    impl FakeDebugModule {
+        fn new(abstract_csr_access: bool) -> Self {
+            let mut gprs = [0; 32];
+            gprs[8] = 0x5050_5050;
+
+            FakeDebugModule {
+                abstract_csr_access,
+                csrs: vec![(MSCRATCH, 0x1234_5678)].into_iter().collect(),
+                gprs,
+                data0: 0,
+                progbuf: [0; 2],
+                cmderr: 0,
+                dmi_result: 0,
+            }
+        }
+
+        fn interface(self) -> RiscvCommunicationInterface {
+            RiscvCommunicationInterface::new(Box::new(self)).unwrap()
+        }
+
+        fn read_dm_register(&self, address: u8) -> u32 {
+            match address {
+                0x04 => self.data0,
+                // dmstatus: version 0.13, all harts halted
+                0x11 => 0x302,
+                // abstractcs: two program buffer words, one data register
+                0x16 => 2 << 24 | self.cmderr << 8 | 1,
+                0x20 | 0x21 => self.progbuf[usize::from(address - 0x20)],
+                _ => 0,
+            }
+        }
+
+        fn write_dm_register(&mut self, address: u8, value: u32) {
+            match address {
+                0x04 => self.data0 = value,
+                // cmderr is cleared by writing ones.
+                0x16 => self.cmderr &= !(value >> 8 & 0x7),
+                0x17 if self.cmderr == 0 => {
+                    if let Err(cmderr) = self.execute_command(AccessRegisterCommand(value)) {
+                        self.cmderr = cmderr;
+                    }
+                }
+                0x20 | 0x21 => self.progbuf[usize::from(address - 0x20)] = value,
+                _ => (),
+            }
+        }
+
+        fn execute_command(&mut self, command: AccessRegisterCommand) -> Result<(), u32> {
+            let command = command.0;
+            if command >> 24 != 0 {
+                return Err(NOT_SUPPORTED);
+            }
+
+            if command & 1 << 17 != 0 {
+                let write = command & 1 << 16 != 0;
+                let register = match command as u16 {
+                    regno @ 0x1000..=0x101f => &mut self.gprs[usize::from(regno - 0x1000)],
+                    regno @ 0..=0xfff if self.abstract_csr_access => {
+                        self.csrs.get_mut(&regno).ok_or(EXCEPTION)?
+                    }
+                    _ => return Err(NOT_SUPPORTED),
+                };
+
+                if write {
+                    *register = self.data0;
+                } else {
+                    self.data0 = *register;
+                }
+            }
+
+            if command & 1 << 18 != 0 {
+                self.execute_progbuf()?;
+            }
+
+            Ok(())
+        }
+
+        fn execute_progbuf(&mut self) -> Result<(), u32> {
+            for &instruction in self.progbuf.iter() {
+                if instruction == assembly::EBREAK {
+                    break;
+                }
+
+                let rd = (instruction >> 7 & 0x1f) as usize;
+                let rs1 = (instruction >> 15 & 0x1f) as usize;
+                let csr = self
+                    .csrs
+                    .get_mut(&((instruction >> 20) as u16))
+                    .ok_or(EXCEPTION)?;
+
+                let value = *csr;
+                match (instruction & 0x7f, instruction >> 12 & 0x7) {
+                    // csrrw
+                    (0x73, 1) => *csr = self.gprs[rs1],
+                    // csrrs
+                    (0x73, 2) => *csr |= self.gprs[rs1],
+                    _ => return Err(EXCEPTION),
+                }
+                if rd != 0 {
+                    self.gprs[rd] = value;
+                }
+            }
+
+            Ok(())
+        }
+    }
+
+    impl DebugProbe for FakeDebugModule {
+        fn new_from_selector(
+            _selector: impl Into<DebugProbeSelector>,
+        ) -> Result<Box<Self>, DebugProbeError>
+        where
+            Self: Sized,
+        {
+            Err(DebugProbeError::ProbeCouldNotBeCreated(
+                ProbeCreationError::Other("This is a fake probe."),
+            ))
+        }
+
+        fn get_name(&self) -> &str {
+            "Fake debug module"
+        }
+
+        fn speed(&self) -> u32 {
+            1000
+        }
+
+        fn set_speed(&mut self, speed_khz: u32) -> Result<u32, DebugProbeError> {
+            Ok(speed_khz)
+        }
+
+        fn attach(&mut self) -> Result<(), DebugProbeError> {
+            Ok(())
+        }
+
+        fn detach(&mut self) -> Result<(), DebugProbeError> {
+            Ok(())
+        }
+
+        fn target_reset(&mut self) -> Result<(), DebugProbeError> {
+            Ok(())
+        }
+
+        fn target_reset_assert(&mut self) -> Result<(), DebugProbeError> {
+            Err(DebugProbeError::CommandNotSupportedByProbe)
+        }
+
+        fn target_reset_deassert(&mut self) -> Result<(), DebugProbeError> {
+            Err(DebugProbeError::CommandNotSupportedByProbe)
+        }
+
+        fn select_protocol(&mut self, _protocol: WireProtocol) -> Result<(), DebugProbeError> {
+            Ok(())
+        }
+    }
+
+    impl JTAGAccess for FakeDebugModule {
+        fn read_register(&mut self, address: u32, len: u32) -> Result<Vec<u8>, DebugProbeError> {
+            assert_eq!(len, 32);
+            let value: u32 = match address {
+                // dtmcs: version 0.13, 7 address bits
+                0x10 => 7 << 4 | 1,
+                _ => 0,
+            };
+            Ok(value.to_le_bytes().to_vec())
+        }
+
+        fn set_idle_cycles(&mut self, _idle_cycles: u8) {}
+
+        fn write_register(
+            &mut self,
+            address: u32,
+            data: &[u8],
+            len: u32,
+        ) -> Result<Vec<u8>, DebugProbeError> {
+            let byte_len = (len as usize + 7) / 8;
+            if address != 0x11 {
+                return Ok(vec![0; byte_len]);
+            }
+
+            let mut request = [0; 16];
+            request[..data.len()].copy_from_slice(data);
+            let request = u128::from_le_bytes(request);
+
+            let response = u128::from(self.dmi_result) << 2;
+
+            let dm_address = (request >> 34) as u8;
+            let value = (request >> 2) as u32;
+            match request & 0x3 {
+                1 => self.dmi_result = self.read_dm_register(dm_address),
+                2 => self.write_dm_register(dm_address, value),
+                _ => (),
+            }
+
+            Ok(response.to_le_bytes()[..byte_len].to_vec())
+        }
+
+        fn into_probe(self: Box<Self>) -> Box<dyn DebugProbe> {
+            self
+        }
+    }
+
+    impl<'a> AsRef<dyn DebugProbe + 'a> for FakeDebugModule {
+        fn as_ref(&self) -> &(dyn DebugProbe + 'a) {
+            self
+        }
+    }
+
+    impl<'a> AsMut<dyn DebugProbe + 'a> for FakeDebugModule {
+        fn as_mut(&mut self) -> &mut (dyn DebugProbe + 'a) {
+            self
+        }
+    }
+
+    fn assert_csr_not_implemented(error: Option<&RiscvError>) {
+        assert!(
+            matches!(
+                error,
+                Some(RiscvError::CsrNotImplemented(UNIMPLEMENTED_CSR))
+            ),
+            "{:?}",
+            error
+        );
+    }
+
+    #[test]
+    fn csrs_are_accessed_with_abstract_commands() {
+        let mut interface = FakeDebugModule::new(true).interface();
+        let mut core = Riscv32::new(&mut interface);
+
+        let mscratch = CoreRegisterAddress(MSCRATCH);
+        assert_eq!(core.read_core_reg(mscratch).unwrap(), 0x1234_5678);
+        core.write_core_reg(mscratch, 0xcafe_f00d).unwrap();
+        assert_eq!(core.read_core_reg(mscratch).unwrap(), 0xcafe_f00d);
+    }
+
+    #[test]
+    fn csrs_are_accessed_with_the_program_buffer() {
+        let mut interface = FakeDebugModule::new(false).interface();
+        let mut core = Riscv32::new(&mut interface);
+
+        let mscratch = CoreRegisterAddress(MSCRATCH);
+        assert_eq!(core.read_core_reg(mscratch).unwrap(), 0x1234_5678);
+        core.write_core_reg(mscratch, 0xcafe_f00d).unwrap();
+        assert_eq!(core.read_core_reg(mscratch).unwrap(), 0xcafe_f00d);
+
+        // s0 is used by the program, and restored afterwards.
+        assert_eq!(
+            core.read_core_reg((&register::S0).into()).unwrap(),
+            0x5050_5050
+        );
+    }
+
+    #[test]
+    fn unimplemented_csr_is_an_error() {
+        for &abstract_csr_access in &[true, false] {
+            let mut interface = FakeDebugModule::new(abstract_csr_access).interface();
+            let mut core = Riscv32::new(&mut interface);
+
+            let csr = CoreRegisterAddress(UNIMPLEMENTED_CSR);
+            match core.read_core_reg(csr) {
+                Err(Error::ArchitectureSpecific(error)) => {
+                    assert_csr_not_implemented(error.downcast_ref())
+                }
+                result => panic!("Unexpected result {:?}", result),
+            }
+            assert_csr_not_implemented(core.write_core_reg(csr, 1).unwrap_err().downcast_ref());
+
+            // The command error was cleared, so other registers can still be accessed.
+            assert_eq!(
+                core.read_core_reg(CoreRegisterAddress(MSCRATCH)).unwrap(),
+                0x1234_5678
+            );
+            assert_eq!(
+                core.read_core_reg((&register::S0).into()).unwrap(),
+                0x5050_5050
+            );
+        }
+    }
+}
@@ -0,0 +1,316 @@
+//! Using a session from asynchronous code.
+//!
+//! All probe accesses block until the probe answers. An [AsyncSession] runs each operation on
+//! a thread of its own, and returns an [Operation], a future which completes with its result,
+//! so the operations can be awaited on any executor, e.g. tokio, without blocking it.
+//!
+//! # Cancellation
+//!
+//! Dropping an [Operation] cancels it. Operations can not be interrupted while the probe is
+//! accessed, so they stop at the next cancellation point instead:
+//!
+//! - Memory accesses stop between two batches of [SharedSession::read_8] and
+//!   [SharedSession::write_8], and fail with [Error::Cancelled].
+//! - Flash downloads stop at the next sector boundary, after the flash algorithm finished the
+//!   sector, and fail with [FlashError::Cancelled](crate::flashing::FlashError::Cancelled).
+//!   Sectors which were erased but not yet programmed stay erased.
+//!
+//! Single register accesses and halting or resuming a core are short, and always run to the end.
+//!
+//! ```no_run
+//! # use probe_rs::{asynchronous::AsyncSession, flashing::Format, Session, SharedSession};
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! let session = Session::auto_attach("nrf52")?;
+//! let session = AsyncSession::new(SharedSession::new(session));
+//!
+//! let data = session.read_8(0, 0x2000_0000, 1024).await?;
+//!
+//! // If this future is dropped, e.g. because a timeout elapsed first,
+//! // the download stops at the next sector boundary.
+//! session
+//!     .download_file("firmware.elf".into(), Format::Elf)
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::flashing::{download_file_with_options, DownloadOptions, FileDownloadError, Format};
+use crate::{CoreInformation, CoreRegisterAddress, CoreStatus, Error, SharedSession};
+
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::Duration;
+
+/// A flag which asks a running operation to stop at its next cancellation point.
+///
+/// Clones share the flag.
+#[derive(Debug, Clone, Default)]
+pub struct Cancellation(Arc<AtomicBool>);
+
+impl Cancellation {
+    /// Creates a cancellation which is not cancelled yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Asks the operations which use this cancellation to stop.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns `true` if [Cancellation::cancel] was called.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// A [SharedSession] whose operations are awaited instead of blocking the calling thread.
+#[derive(Debug, Clone)]
+pub struct AsyncSession {
+    session: SharedSession,
+}
+
+impl AsyncSession {
+    /// Uses the `session` from asynchronous code.
+    ///
+    /// Other clones of the shared session can still be used by other threads at the same time.
+    pub fn new(session: SharedSession) -> Self {
+        Self { session }
+    }
+
+    /// The shared session the operations run on.
+    pub fn shared(&self) -> &SharedSession {
+        &self.session
+    }
+
+    /// Runs `f` on a thread of its own, and returns a future which completes with its result.
+    ///
+    /// `f` should check the [Cancellation] between its steps, which is cancelled
+    /// when the returned [Operation] is dropped.
+    pub fn spawn<T, F>(&self, f: F) -> Operation<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&SharedSession, &Cancellation) -> T + Send + 'static,
+    {
+        let session = self.session.clone();
+        Operation::spawn(move |cancellation| f(&session, cancellation))
+    }
+
+    /// Reads `length` bytes from `address` with the core `core`.
+    pub fn read_8(
+        &self,
+        core: usize,
+        address: u32,
+        length: usize,
+    ) -> Operation<Result<Vec<u8>, Error>> {
+        self.spawn(move |session, cancellation| {
+            let mut data = vec![0; length];
+            session.read_8_until(core, address, &mut data, Some(cancellation))?;
+            Ok(data)
+        })
+    }
+
+    /// Writes `data` to `address` with the core `core`.
+    pub fn write_8(
+        &self,
+        core: usize,
+        address: u32,
+        data: Vec<u8>,
+    ) -> Operation<Result<(), Error>> {
+        self.spawn(move |session, cancellation| {
+            session.write_8_until(core, address, &data, Some(cancellation))
+        })
+    }
+
+    /// Reads the register at `address` of the core `core`.
+    pub fn read_core_reg(
+        &self,
+        core: usize,
+        address: CoreRegisterAddress,
+    ) -> Operation<Result<u32, Error>> {
+        self.spawn(move |session, _| session.with_core(core, |core| core.read_core_reg(address)))
+    }
+
+    /// Halts the core `core`, and waits at most for `timeout` until it is halted.
+    pub fn halt(
+        &self,
+        core: usize,
+        timeout: Duration,
+    ) -> Operation<Result<CoreInformation, Error>> {
+        self.spawn(move |session, _| session.with_core(core, |core| core.halt(timeout)))
+    }
+
+    /// Resumes the core `core`.
+    pub fn run(&self, core: usize) -> Operation<Result<(), Error>> {
+        self.spawn(move |session, _| session.with_core(core, |core| core.run()))
+    }
+
+    /// Returns the status of the core `core`.
+    pub fn status(&self, core: usize) -> Operation<Result<CoreStatus, Error>> {
+        self.spawn(move |session, _| session.with_core(core, |core| core.status()))
+    }
+
+    /// Downloads the file of the given `format` at `path` to the flash of the target.
+    ///
+    /// The session is locked for the whole download.
+    pub fn download_file(
+        &self,
+        path: PathBuf,
+        format: Format,
+    ) -> Operation<Result<(), FileDownloadError>> {
+        self.spawn(move |session, cancellation| {
+            let options = DownloadOptions {
+                cancellation: Some(cancellation.clone()),
+                ..Default::default()
+            };
+            download_file_with_options(&mut session.lock(), &path, format, options)
+        })
+    }
+}
+
+/// The result of an operation which runs on another thread, and the waker of the task which awaits it.
+#[derive(Debug)]
+struct Completion<T> {
+    result: Option<thread::Result<T>>,
+    waker: Option<Waker>,
+}
+
+/// An operation of an [AsyncSession], which completes with the result of the operation.
+///
+/// Dropping the operation before it completed cancels it.
+#[derive(Debug)]
+pub struct Operation<T> {
+    completion: Arc<(Mutex<Completion<T>>, Condvar)>,
+    cancellation: Cancellation,
+}
+
+impl<T: Send + 'static> Operation<T> {
+    fn spawn(f: impl FnOnce(&Cancellation) -> T + Send + 'static) -> Self {
+        let completion = Arc::new((
+            Mutex::new(Completion {
+                result: None,
+                waker: None,
+            }),
+            Condvar::new(),
+        ));
+        let cancellation = Cancellation::new();
+
+        {
+            let completion = completion.clone();
+            let cancellation = cancellation.clone();
+            thread::spawn(move || {
+                // A panic is raised again in the task which awaits the operation.
+                let result = panic::catch_unwind(AssertUnwindSafe(|| f(&cancellation)));
+
+                let (state, done) = &*completion;
+                let mut state = state.lock().unwrap_or_else(PoisonError::into_inner);
+                state.result = Some(result);
+                done.notify_all();
+                if let Some(waker) = state.waker.take() {
+                    waker.wake();
+                }
+            });
+        }
+
+        Self {
+            completion,
+            cancellation,
+        }
+    }
+}
+
+impl<T> Operation<T> {
+    /// Asks the operation to stop at its next cancellation point.
+    ///
+    /// The operation still completes, usually with a cancellation error.
+    pub fn cancel(&self) {
+        self.cancellation.cancel();
+    }
+
+    /// Blocks the calling thread until the operation completed, for callers which are not asynchronous.
+    pub fn wait(self) -> T {
+        let (state, done) = &*self.completion;
+        let mut state = state.lock().unwrap_or_else(PoisonError::into_inner);
+        loop {
+            if let Some(result) = state.result.take() {
+                return unwrap_result(result);
+            }
+            state = done.wait(state).unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+}
+
+impl<T> Future for Operation<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut state = self
+            .completion
+            .0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        match state.result.take() {
+            Some(result) => Poll::Ready(unwrap_result(result)),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T> Drop for Operation<T> {
+    fn drop(&mut self) {
+        // A completed operation ignores the cancellation.
+        self.cancellation.cancel();
+    }
+}
+
+fn unwrap_result<T>(result: thread::Result<T>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(payload) => panic::resume_unwind(payload),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cancellation, Operation};
+
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn operations_complete_with_their_result() {
+        assert_eq!(Operation::spawn(|_| 42).wait(), 42);
+    }
+
+    #[test]
+    fn dropped_operations_are_cancelled() {
+        let (finish, finished) = channel();
+        let (sender, receiver) = channel::<Cancellation>();
+
+        let operation = Operation::spawn(move |cancellation| {
+            sender.send(cancellation.clone()).unwrap();
+            finished.recv().unwrap();
+        });
+
+        let cancellation = receiver.recv().unwrap();
+        assert!(!cancellation.is_cancelled());
+        drop(operation);
+        assert!(cancellation.is_cancelled());
+        finish.send(()).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "in the operation")]
+    fn panics_are_raised_in_the_caller() {
+        Operation::<()>::spawn(|_| panic!("in the operation")).wait();
+    }
+}
@@ -0,0 +1,593 @@
+//! Measures how fast the memory of a target can be accessed through the debug probe.
+//!
+//! [run] reads and writes a block of RAM with every combination of access width,
+//! block size and direction, once with the core halted and, where the architecture
+//! allows it, once with the core running. It also measures how often an RTT channel
+//! can be polled, using a dummy RTT control block, and how fast a block of memory can
+//! be read repeatedly with a [MemoryStream](crate::MemoryStream).
+//!
+//! The RAM used for the measurements is overwritten and not restored.
+
+use crate::config::MemoryRegion;
+use crate::{Architecture, Core, Error, MemoryInterface, Session};
+
+use std::fmt;
+use std::ops::Range;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// The ID at the start of an RTT control block.
+const RTT_ID: &[u8; 16] = b"SEGGER RTT\0\0\0\0\0\0";
+
+/// The size of the buffer of the dummy RTT up channel.
+const RTT_BUFFER_SIZE: u32 = 64;
+
+/// The offset of the up channel descriptor in the RTT control block.
+const RTT_UP_CHANNEL_OFFSET: u32 = 24;
+
+/// The size of the dummy RTT control block, including its up channel and buffer.
+const RTT_AREA_SIZE: u32 = RTT_UP_CHANNEL_OFFSET + 24 + RTT_BUFFER_SIZE;
+
+/// The width of the single accesses to the memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessWidth {
+    Bits8,
+    Bits16,
+    Bits32,
+}
+
+impl AccessWidth {
+    /// All access widths, from the narrowest to the widest.
+    pub const ALL: [AccessWidth; 3] =
+        [AccessWidth::Bits8, AccessWidth::Bits16, AccessWidth::Bits32];
+
+    /// The width in bits.
+    pub fn bits(self) -> u32 {
+        match self {
+            AccessWidth::Bits8 => 8,
+            AccessWidth::Bits16 => 16,
+            AccessWidth::Bits32 => 32,
+        }
+    }
+
+    fn bytes(self) -> u32 {
+        self.bits() / 8
+    }
+}
+
+impl fmt::Display for AccessWidth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} bit", self.bits())
+    }
+}
+
+/// Whether the memory is read or written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Read,
+    Write,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Direction::Read => write!(f, "read"),
+            Direction::Write => write!(f, "write"),
+        }
+    }
+}
+
+/// Whether the core was halted or running during a measurement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoreCondition {
+    Halted,
+    Running,
+}
+
+impl fmt::Display for CoreCondition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CoreCondition::Halted => write!(f, "halted"),
+            CoreCondition::Running => write!(f, "running"),
+        }
+    }
+}
+
+/// Configures which measurements [run] makes.
+#[derive(Debug, Clone)]
+pub struct BenchmarkOptions {
+    /// The address of the RAM used for the measurements.
+    /// If not given, the start of the first RAM region of the target is used.
+    pub address: Option<u32>,
+    /// The sizes of the blocks which are read and written, in bytes.
+    /// Sizes which do not fit into the RAM region are skipped.
+    pub block_sizes: Vec<u32>,
+    /// The access widths to measure.
+    pub widths: Vec<AccessWidth>,
+    /// How long each block size is read or written repeatedly.
+    /// Every block is accessed at least once.
+    pub duration: Duration,
+    /// Repeat the measurements with the core running. This is only done for ARM targets,
+    /// other architectures can not access the memory while the core runs.
+    pub running: bool,
+    /// Measure how often an RTT channel can be polled.
+    pub rtt: bool,
+    /// The size of the block which is read repeatedly to measure the streaming rate,
+    /// in bytes. It is reduced to the available RAM. `None` skips the measurement.
+    pub stream_block_size: Option<u32>,
+}
+
+impl Default for BenchmarkOptions {
+    fn default() -> Self {
+        Self {
+            address: None,
+            block_sizes: vec![4, 64, 1024, 16 * 1024, 64 * 1024],
+            widths: AccessWidth::ALL.to_vec(),
+            duration: Duration::from_millis(250),
+            running: true,
+            rtt: true,
+            stream_block_size: Some(4096),
+        }
+    }
+}
+
+/// The throughput of one combination of access width, block size and direction.
+#[derive(Debug, Clone)]
+pub struct Measurement {
+    pub core: CoreCondition,
+    pub direction: Direction,
+    pub width: AccessWidth,
+    /// The size of the block which was accessed at once, in bytes.
+    pub block_size: u32,
+    /// The total amount of bytes which were transferred.
+    pub bytes: u64,
+    /// The time the transfers took.
+    pub duration: Duration,
+}
+
+impl Measurement {
+    /// The throughput in bytes per second.
+    pub fn bytes_per_second(&self) -> f64 {
+        self.bytes as f64 / self.duration.as_secs_f64()
+    }
+}
+
+/// How often an RTT channel without new data could be polled.
+#[derive(Debug, Clone)]
+pub struct RttMeasurement {
+    pub core: CoreCondition,
+    /// The number of times the channel was polled.
+    pub polls: u32,
+    /// The time the polls took.
+    pub duration: Duration,
+}
+
+impl RttMeasurement {
+    /// The number of polls per second.
+    pub fn polls_per_second(&self) -> f64 {
+        f64::from(self.polls) / self.duration.as_secs_f64()
+    }
+}
+
+/// How fast a block of memory could be read repeatedly with a [MemoryStream](crate::MemoryStream).
+#[derive(Debug, Clone)]
+pub struct StreamMeasurement {
+    pub core: CoreCondition,
+    /// The size of the block which was read, in bytes.
+    pub block_size: u32,
+    /// The number of times the block was read.
+    pub reads: u32,
+    /// The time the reads took.
+    pub duration: Duration,
+}
+
+impl StreamMeasurement {
+    /// The sustained throughput in bytes per second.
+    pub fn bytes_per_second(&self) -> f64 {
+        f64::from(self.block_size) * f64::from(self.reads) / self.duration.as_secs_f64()
+    }
+
+    /// The number of reads per second.
+    pub fn reads_per_second(&self) -> f64 {
+        f64::from(self.reads) / self.duration.as_secs_f64()
+    }
+}
+
+/// The results of [run].
+#[derive(Debug, Clone)]
+pub struct BenchmarkReport {
+    /// The protocol speed during the measurements, in kHz.
+    pub speed_khz: u32,
+    /// The address of the RAM used for the measurements.
+    pub address: u32,
+    pub measurements: Vec<Measurement>,
+    pub rtt: Vec<RttMeasurement>,
+    pub stream: Vec<StreamMeasurement>,
+}
+
+#[derive(Debug, Error)]
+pub enum BenchmarkError {
+    #[error("The target has no RAM region which can be used for the benchmark.")]
+    NoRam,
+    #[error("Address {0:#010x} is not in a RAM region of the target.")]
+    AddressNotInRam(u32),
+    #[error("The data read back from {address:#010x} does not match the written data.")]
+    DataMismatch { address: u32 },
+    #[error("Something during the interaction with the core went wrong")]
+    Core(#[source] Error),
+}
+
+impl From<Error> for BenchmarkError {
+    fn from(error: Error) -> Self {
+        BenchmarkError::Core(error)
+    }
+}
+
+/// Measures the memory throughput of the first core of the session.
+///
+/// The core is left in the state it was in before the benchmark.
+pub fn run(
+    session: &mut Session,
+    options: &BenchmarkOptions,
+) -> Result<BenchmarkReport, BenchmarkError> {
+    let area = ram_area(&session.target().memory_map, options.address)?;
+    let available = area.end - area.start;
+
+    let block_sizes = usable_block_sizes(&options.block_sizes, available);
+    for skipped in options
+        .block_sizes
+        .iter()
+        .filter(|s| !block_sizes.contains(s))
+    {
+        log::warn!(
+            "Skipping block size {}, only {} bytes of RAM are available.",
+            skipped,
+            available
+        );
+    }
+
+    let mut conditions = vec![CoreCondition::Halted];
+    if options.running {
+        if session.architecture() == Architecture::Arm {
+            conditions.push(CoreCondition::Running);
+        } else {
+            log::warn!("The memory can only be accessed with the core halted, skipping the measurements with a running core.");
+        }
+    }
+
+    let speed_khz = session.current_speed();
+    let mut core = session.core(0)?;
+    let was_halted = core.core_halted()?;
+
+    let mut report = BenchmarkReport {
+        speed_khz,
+        address: area.start,
+        measurements: vec![],
+        rtt: vec![],
+        stream: vec![],
+    };
+
+    for &condition in &conditions {
+        match condition {
+            CoreCondition::Halted => {
+                if !core.core_halted()? {
+                    core.halt(Duration::from_millis(100))?;
+                }
+            }
+            CoreCondition::Running => core.run()?,
+        }
+
+        for &width in &options.widths {
+            for &block_size in block_sizes.iter().filter(|&&s| s % width.bytes() == 0) {
+                for &direction in &[Direction::Write, Direction::Read] {
+                    let measurement = measure(
+                        &mut core,
+                        area.start,
+                        condition,
+                        direction,
+                        width,
+                        block_size,
+                        options.duration,
+                    )?;
+                    report.measurements.push(measurement);
+                }
+            }
+        }
+
+        if options.rtt && available >= RTT_AREA_SIZE {
+            report.rtt.push(measure_rtt(
+                &mut core,
+                area.start,
+                condition,
+                options.duration,
+            )?);
+        }
+
+        if let Some(block_size) = options.stream_block_size.map(|s| s.min(available) & !3) {
+            if block_size > 0 {
+                report.stream.push(measure_stream(
+                    &mut core,
+                    area.start,
+                    condition,
+                    block_size,
+                    options.duration,
+                )?);
+            }
+        }
+    }
+
+    if was_halted {
+        if !core.core_halted()? {
+            core.halt(Duration::from_millis(100))?;
+        }
+    } else if !conditions.contains(&CoreCondition::Running) {
+        core.run()?;
+    }
+
+    Ok(report)
+}
+
+/// Finds the RAM used for the measurements, from `address` up to the end of its region.
+fn ram_area(
+    memory_map: &[MemoryRegion],
+    address: Option<u32>,
+) -> Result<Range<u32>, BenchmarkError> {
+    let mut ram = memory_map.iter().filter_map(|region| match region {
+        MemoryRegion::Ram(ram) => Some(&ram.range),
+        _ => None,
+    });
+
+    match address {
+        Some(address) => ram
+            .find(|range| range.contains(&address))
+            .map(|range| address..range.end)
+            .ok_or(BenchmarkError::AddressNotInRam(address)),
+        None => ram.next().cloned().ok_or(BenchmarkError::NoRam),
+    }
+}
+
+/// Returns the block sizes which fit into `available` bytes.
+fn usable_block_sizes(block_sizes: &[u32], available: u32) -> Vec<u32> {
+    block_sizes
+        .iter()
+        .copied()
+        .filter(|&size| size > 0 && size <= available)
+        .collect()
+}
+
+/// The data written in the measurements, which is different for every byte of a block.
+fn test_pattern(size: u32) -> Vec<u8> {
+    (0..size)
+        .map(|i| (i.wrapping_mul(7) ^ 0xa5) as u8)
+        .collect()
+}
+
+fn measure(
+    core: &mut Core,
+    address: u32,
+    condition: CoreCondition,
+    direction: Direction,
+    width: AccessWidth,
+    block_size: u32,
+    duration: Duration,
+) -> Result<Measurement, BenchmarkError> {
+    let pattern = test_pattern(block_size);
+
+    let halfwords: Vec<u16> = pattern
+        .chunks_exact(2)
+        .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+        .collect();
+    let words: Vec<u32> = pattern
+        .chunks_exact(4)
+        .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .collect();
+
+    let mut read_bytes = vec![0u8; pattern.len()];
+    let mut read_halfwords = vec![0u16; halfwords.len()];
+    let mut read_words = vec![0u32; words.len()];
+
+    let mut access = |core: &mut Core| -> Result<(), Error> {
+        match (direction, width) {
+            (Direction::Write, AccessWidth::Bits8) => core.write_8(address, &pattern),
+            (Direction::Write, AccessWidth::Bits16) => core.write_16(address, &halfwords),
+            (Direction::Write, AccessWidth::Bits32) => core.write_32(address, &words),
+            (Direction::Read, AccessWidth::Bits8) => core.read_8(address, &mut read_bytes),
+            (Direction::Read, AccessWidth::Bits16) => core.read_16(address, &mut read_halfwords),
+            (Direction::Read, AccessWidth::Bits32) => core.read_32(address, &mut read_words),
+        }
+    };
+
+    let start = Instant::now();
+    let mut bytes = 0;
+
+    loop {
+        access(core)?;
+        bytes += u64::from(block_size);
+
+        if start.elapsed() >= duration {
+            break;
+        }
+    }
+
+    let elapsed = start.elapsed();
+
+    // The firmware may change the RAM while the core runs, so the data is only checked with the core halted.
+    if direction == Direction::Write && condition == CoreCondition::Halted {
+        let mut written = vec![0u8; pattern.len()];
+        core.read_8(address, &mut written)?;
+
+        if written != pattern {
+            return Err(BenchmarkError::DataMismatch { address });
+        }
+    }
+
+    Ok(Measurement {
+        core: condition,
+        direction,
+        width,
+        block_size,
+        bytes,
+        duration: elapsed,
+    })
+}
+
+/// Creates an RTT control block with a single, empty up channel at `address`.
+fn rtt_control_block(address: u32) -> Vec<u8> {
+    let buffer_address = address + RTT_AREA_SIZE - RTT_BUFFER_SIZE;
+
+    let mut block = RTT_ID.to_vec();
+
+    // Number of up and down channels
+    block.extend_from_slice(&1u32.to_le_bytes());
+    block.extend_from_slice(&0u32.to_le_bytes());
+
+    // The up channel: name, buffer, size, write offset, read offset and flags
+    for value in &[0, buffer_address, RTT_BUFFER_SIZE, 0, 0, 0] {
+        block.extend_from_slice(&value.to_le_bytes());
+    }
+
+    block.resize(RTT_AREA_SIZE as usize, 0);
+
+    block
+}
+
+/// Polls the up channel of a dummy RTT control block for `duration`, the way an RTT host does
+/// when the target sends no data: by reading the write and read offset of the channel.
+fn measure_rtt(
+    core: &mut Core,
+    address: u32,
+    condition: CoreCondition,
+    duration: Duration,
+) -> Result<RttMeasurement, BenchmarkError> {
+    core.write_8(address, &rtt_control_block(address))?;
+
+    let offsets_address = address + RTT_UP_CHANNEL_OFFSET + 12;
+    let mut offsets = [0u32; 2];
+
+    let start = Instant::now();
+    let mut polls = 0;
+
+    let result = loop {
+        if let Err(e) = core.read_32(offsets_address, &mut offsets) {
+            break Err(e);
+        }
+        polls += 1;
+
+        if start.elapsed() >= duration {
+            break Ok(());
+        }
+    };
+
+    let elapsed = start.elapsed();
+
+    // Remove the ID, so that no RTT host finds the dummy control block later on.
+    core.write_8(address, &[0; 16])?;
+    result?;
+
+    Ok(RttMeasurement {
+        core: condition,
+        polls,
+        duration: elapsed,
+    })
+}
+
+/// Reads a block of `block_size` bytes at `address` as fast as possible for `duration`,
+/// with a [MemoryStream](crate::MemoryStream).
+fn measure_stream(
+    core: &mut Core,
+    address: u32,
+    condition: CoreCondition,
+    block_size: u32,
+    duration: Duration,
+) -> Result<StreamMeasurement, BenchmarkError> {
+    let start = Instant::now();
+    let mut reads = 0;
+
+    for data in core.stream(address, block_size as usize, Duration::from_secs(0)) {
+        data?;
+        reads += 1;
+
+        if start.elapsed() >= duration {
+            break;
+        }
+    }
+
+    Ok(StreamMeasurement {
+        core: condition,
+        block_size,
+        reads,
+        duration: start.elapsed(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{EraseMode, NvmRegion, RamRegion};
+    use std::borrow::Cow;
+
+    fn memory_map() -> Vec<MemoryRegion> {
+        vec![
+            MemoryRegion::Nvm(NvmRegion {
+                range: 0x0800_0000..0x0810_0000,
+                is_boot_memory: true,
+                is_otp: false,
+                erase_mode: EraseMode::Sector,
+                sectors: Cow::Borrowed(&[]),
+                memory_mapped: None,
+            }),
+            MemoryRegion::Ram(RamRegion {
+                range: 0x2000_0000..0x2000_8000,
+                is_boot_memory: false,
+                core_index: 0,
+            }),
+        ]
+    }
+
+    #[test]
+    fn ram_area_defaults_to_first_ram_region() {
+        assert_eq!(
+            ram_area(&memory_map(), None).unwrap(),
+            0x2000_0000..0x2000_8000
+        );
+    }
+
+    #[test]
+    fn ram_area_starts_at_address() {
+        assert_eq!(
+            ram_area(&memory_map(), Some(0x2000_7000)).unwrap(),
+            0x2000_7000..0x2000_8000
+        );
+        assert!(matches!(
+            ram_area(&memory_map(), Some(0x0800_0000)),
+            Err(BenchmarkError::AddressNotInRam(0x0800_0000))
+        ));
+    }
+
+    #[test]
+    fn large_block_sizes_are_skipped() {
+        let sizes = BenchmarkOptions::default().block_sizes;
+
+        assert_eq!(usable_block_sizes(&sizes, 0x8000), vec![4, 64, 1024, 16384]);
+    }
+
+    #[test]
+    fn rtt_control_block_layout() {
+        let block = rtt_control_block(0x2000_0000);
+        let word = |offset: usize| {
+            u32::from_le_bytes([
+                block[offset],
+                block[offset + 1],
+                block[offset + 2],
+                block[offset + 3],
+            ])
+        };
+
+        assert_eq!(block.len(), RTT_AREA_SIZE as usize);
+        assert_eq!(&block[..16], RTT_ID);
+        assert_eq!(word(16), 1);
+        assert_eq!(word(20), 0);
+        assert_eq!(word(28), 0x2000_0000 + RTT_AREA_SIZE - RTT_BUFFER_SIZE);
+        assert_eq!(word(32), RTT_BUFFER_SIZE);
+    }
+}
@@ -0,0 +1,604 @@
+//! Measures the clock frequency of the first core of a target.
+//!
+//! [measure] counts the clock cycles of the core over an interval which is timed by the host.
+//! The core runs a small loop from the RAM while it is measured, so the firmware can not put
+//! it to sleep, which stops the counters:
+//!
+//! - Cortex-M cores with a cycle counter use `CYCCNT` of the DWT.
+//! - On RISC-V cores, the `mcycle` CSR is read while the core is halted before and after
+//!   the interval.
+//! - Other Cortex-M cores use the [CoreClock](crate::config::CoreClock) description of the
+//!   target if it has one, otherwise the SysTick timer with the core clock as its source.
+//! - Cores without SysTick time a delay loop with a known number of cycles per iteration.
+//!
+//! The core is halted while the counters are set up. Its state, the RAM used for the loop,
+//! and the registers of the counters are restored afterwards, and the core is resumed if it
+//! was running.
+
+use crate::architecture::arm::core::{m0::Demcr, register};
+use crate::config::{CoreClock, MemoryRegion};
+use crate::{Core, CoreRegister, CoreRegisterAddress, CoreType, Error, MemoryInterface, Session};
+
+use std::fmt;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// How long the cycles are counted.
+pub const MEASUREMENT_DURATION: Duration = Duration::from_millis(100);
+
+/// The DWT control register.
+const DWT_CTRL: u32 = 0xE000_1000;
+/// The cycle counter of the DWT.
+const DWT_CYCCNT: u32 = 0xE000_1004;
+/// Set in `DWT_CTRL` if the DWT has no cycle counter.
+const DWT_CTRL_NOCYCCNT: u32 = 1 << 25;
+/// Enables the cycle counter in `DWT_CTRL`.
+const DWT_CTRL_CYCCNTENA: u32 = 1;
+
+/// The SysTick control and status register.
+const SYST_CSR: u32 = 0xE000_E010;
+/// The SysTick reload value register.
+const SYST_RVR: u32 = 0xE000_E014;
+/// The SysTick current value register.
+const SYST_CVR: u32 = 0xE000_E018;
+/// Enables SysTick with the core clock as its source, without its interrupt.
+const SYST_CSR_ENABLE_CORE_CLOCK: u32 = 0b101;
+/// The largest reload value of SysTick.
+const SYST_RELOAD_MAX: u32 = 0x00FF_FFFF;
+
+/// The register with CONTROL, FAULTMASK, BASEPRI and PRIMASK of a Cortex-M core.
+const ARM_SPECIAL_REGISTERS: CoreRegisterAddress = CoreRegisterAddress(0b1_0100);
+/// Masks the interrupts in the special registers.
+const ARM_PRIMASK: u32 = 1;
+/// The Thumb bit of XPSR.
+const XPSR_THUMB: u32 = 1 << 24;
+
+/// `b .`
+const ARM_SPIN_LOOP: [u16; 2] = [0xE7FE, 0xE7FE];
+/// `subs r0, #1; bne .-2; bkpt #0`
+const ARM_DELAY_LOOP: [u16; 4] = [0x3801, 0xD1FD, 0xBE00, 0xBE00];
+/// The cycles of an iteration of the delay loop: three on the Cortex-M0+, four on the Cortex-M0.
+const ARM_DELAY_LOOP_CYCLES: (f64, f64) = (3.5, 0.5);
+
+/// The RISC-V cycle counter.
+const RISCV_MCYCLE: CoreRegisterAddress = CoreRegisterAddress(0xB00);
+/// The RISC-V debug control and status register.
+const RISCV_DCSR: CoreRegisterAddress = CoreRegisterAddress(0x7B0);
+/// Stops the counters in `dcsr` while the core is halted.
+const RISCV_DCSR_STOPCOUNT: u32 = 1 << 10;
+/// `j .`
+const RISCV_SPIN_LOOP: u32 = 0x0000_006F;
+
+/// How the clock frequency was measured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockMethod {
+    /// With the cycle counter of the DWT.
+    CycleCounter,
+    /// With the `mcycle` CSR of a RISC-V core.
+    CycleCsr,
+    /// With the SysTick timer.
+    SysTick,
+    /// By timing a delay loop.
+    DelayLoop,
+    /// From the clock configuration registers, with the
+    /// [CoreClock](crate::config::CoreClock) description of the target.
+    ClockTree,
+}
+
+impl fmt::Display for ClockMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClockMethod::CycleCounter => write!(f, "DWT cycle counter"),
+            ClockMethod::CycleCsr => write!(f, "mcycle CSR"),
+            ClockMethod::SysTick => write!(f, "SysTick"),
+            ClockMethod::DelayLoop => write!(f, "delay loop"),
+            ClockMethod::ClockTree => write!(f, "clock configuration"),
+        }
+    }
+}
+
+/// The measured clock frequency of a core.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClockMeasurement {
+    /// The frequency in Hz.
+    pub frequency: f64,
+    /// The relative uncertainty of the frequency, e.g. `0.01` if it is accurate to 1 %.
+    pub accuracy: f64,
+    pub method: ClockMethod,
+}
+
+impl fmt::Display for ClockMeasurement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.3} MHz ± {:.2} % ({})",
+            self.frequency / 1e6,
+            self.accuracy * 100.0,
+            self.method
+        )
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ClockError {
+    #[error("The target has no RAM region for the measurement loop.")]
+    NoRam,
+    #[error("The clock of the core did not advance during the measurement.")]
+    Stopped,
+    #[error("The clock configuration of the target has a factor of zero.")]
+    InvalidClockTree,
+    #[error("Something during the interaction with the core went wrong")]
+    Core(#[source] Error),
+}
+
+impl From<Error> for ClockError {
+    fn from(error: Error) -> Self {
+        ClockError::Core(error)
+    }
+}
+
+/// A reading of a counter, with the time before and after the read, since the start of the measurement.
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    before: Duration,
+    after: Duration,
+    value: u32,
+}
+
+impl Sample {
+    fn take(start: Instant, read: impl FnOnce() -> Result<u32, Error>) -> Result<Sample, Error> {
+        let before = start.elapsed();
+        let value = read()?;
+        let after = start.elapsed();
+
+        Ok(Sample {
+            before,
+            after,
+            value,
+        })
+    }
+
+    /// The time at which the value was most likely read.
+    fn time(&self) -> f64 {
+        (self.before + self.after).as_secs_f64() / 2.0
+    }
+
+    /// How far the time of the read can be off from [Sample::time].
+    fn uncertainty(&self) -> f64 {
+        (self.after - self.before).as_secs_f64() / 2.0
+    }
+}
+
+/// A hardware counter which is driven by the core clock.
+#[derive(Debug, Clone, Copy)]
+struct Counter {
+    /// The bits of the counter.
+    mask: u32,
+    counts_down: bool,
+}
+
+impl Counter {
+    const CYCCNT: Counter = Counter {
+        mask: u32::MAX,
+        counts_down: false,
+    };
+
+    const SYSTICK: Counter = Counter {
+        mask: SYST_RELOAD_MAX,
+        counts_down: true,
+    };
+
+    /// Returns the number of cycles between the `samples`.
+    ///
+    /// The counter must not wrap around more than once between two samples.
+    fn cycles(&self, samples: &[Sample]) -> u64 {
+        samples
+            .windows(2)
+            .map(|pair| {
+                let (first, second) = (pair[0].value, pair[1].value);
+                let delta = if self.counts_down {
+                    first.wrapping_sub(second)
+                } else {
+                    second.wrapping_sub(first)
+                };
+                u64::from(delta & self.mask)
+            })
+            .sum()
+    }
+}
+
+/// Returns the frequency and its relative uncertainty, for `cycles` which were counted
+/// between the `first` and the `last` sample.
+fn frequency(cycles: f64, first: &Sample, last: &Sample) -> Option<(f64, f64)> {
+    let elapsed = last.time() - first.time();
+    if cycles <= 0.0 || elapsed <= 0.0 {
+        return None;
+    }
+
+    let uncertainty = (first.uncertainty() + last.uncertainty()) / elapsed;
+
+    Some((cycles / elapsed, uncertainty))
+}
+
+/// Measures the clock frequency of the first core of the `session`, over the `duration`.
+///
+/// See the [module documentation](self) for the methods which are used.
+pub fn measure(session: &mut Session, duration: Duration) -> Result<ClockMeasurement, ClockError> {
+    let core_type = session.list_cores()[0].1;
+    let ram = session
+        .target()
+        .memory_map
+        .iter()
+        .find_map(|region| match region {
+            MemoryRegion::Ram(ram) => Some(ram.range.start),
+            _ => None,
+        });
+    let core_clock = session.target().core_clock.clone();
+
+    let mut core = session.core(0)?;
+    let was_halted = core.core_halted()?;
+    if !was_halted {
+        core.halt(Duration::from_millis(100))?;
+    }
+
+    let result = measure_halted(&mut core, core_type, ram, core_clock, duration);
+
+    if !was_halted {
+        core.run()?;
+    }
+
+    result
+}
+
+fn measure_halted(
+    core: &mut Core,
+    core_type: CoreType,
+    ram: Option<u32>,
+    core_clock: Option<CoreClock>,
+    duration: Duration,
+) -> Result<ClockMeasurement, ClockError> {
+    if matches!(core_type, CoreType::Riscv) {
+        return measure_riscv(core, ram.ok_or(ClockError::NoRam)?, duration);
+    }
+
+    if !matches!(core_type, CoreType::M0) && has_cycle_counter(core)? {
+        let ram = ram.ok_or(ClockError::NoRam)?;
+        return measure_arm_counter(core, ram, duration, ClockMethod::CycleCounter);
+    }
+
+    if let Some(core_clock) = core_clock {
+        return read_clock_tree(core, &core_clock);
+    }
+
+    let ram = ram.ok_or(ClockError::NoRam)?;
+    if has_systick(core)? {
+        measure_arm_counter(core, ram, duration, ClockMethod::SysTick)
+    } else {
+        measure_arm_delay_loop(core, ram, duration)
+    }
+}
+
+/// Returns `true` if the DWT of the core has a cycle counter.
+fn has_cycle_counter(core: &mut Core) -> Result<bool, Error> {
+    enable_trace(core)?;
+
+    Ok(core.read_word_32(DWT_CTRL)? & DWT_CTRL_NOCYCCNT == 0)
+}
+
+/// Returns `true` if the core has a SysTick timer.
+///
+/// The reload value register of a missing SysTick reads as zero.
+fn has_systick(core: &mut Core) -> Result<bool, Error> {
+    let reload = core.read_word_32(SYST_RVR)?;
+    core.write_word_32(SYST_RVR, SYST_RELOAD_MAX)?;
+    let implemented = core.read_word_32(SYST_RVR)? != 0;
+    core.write_word_32(SYST_RVR, reload)?;
+
+    Ok(implemented)
+}
+
+fn enable_trace(core: &mut Core) -> Result<(), Error> {
+    let mut demcr = Demcr(core.read_word_32(Demcr::ADDRESS)?);
+    demcr.set_dwtena(true);
+    core.write_word_32(Demcr::ADDRESS, demcr.into())
+}
+
+/// The state of a halted core which is changed by a measurement.
+struct SavedState {
+    registers: Vec<(CoreRegisterAddress, u32)>,
+    ram_address: u32,
+    ram: Vec<u8>,
+}
+
+impl SavedState {
+    /// Saves the `registers` and `ram_size` bytes of RAM at `ram_address`.
+    fn save(
+        core: &mut Core,
+        registers: &[CoreRegisterAddress],
+        ram_address: u32,
+        ram_size: usize,
+    ) -> Result<Self, Error> {
+        let registers = registers
+            .iter()
+            .map(|&address| Ok((address, core.read_core_reg(address)?)))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let mut ram = vec![0; ram_size];
+        core.read_8(ram_address, &mut ram)?;
+
+        Ok(Self {
+            registers,
+            ram_address,
+            ram,
+        })
+    }
+
+    /// Halts the core, and restores the saved state.
+    fn restore(self, core: &mut Core) -> Result<(), Error> {
+        if !core.core_halted()? {
+            core.halt(Duration::from_millis(100))?;
+        }
+
+        core.write_8(self.ram_address, &self.ram)?;
+        for (address, value) in self.registers {
+            core.write_core_reg(address, value)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Runs `measurement` with the core running `code` from `ram`, with the interrupts masked
+/// and `r0` set to `argument`. The state of the core is restored afterwards.
+fn with_arm_loop<T>(
+    core: &mut Core,
+    ram: u32,
+    code: &[u16],
+    argument: u32,
+    measurement: impl FnOnce(&mut Core) -> Result<T, ClockError>,
+) -> Result<T, ClockError> {
+    let r0 = core.registers().argument_register(0).address;
+    let pc = core.registers().program_counter().address;
+    let saved = SavedState::save(
+        core,
+        &[r0, pc, register::XPSR.address, ARM_SPECIAL_REGISTERS],
+        ram,
+        code.len() * 2,
+    )?;
+
+    let result = (|| -> Result<T, ClockError> {
+        core.write_16(ram, code)?;
+        core.write_core_reg(r0, argument)?;
+        core.write_core_reg(pc, ram)?;
+        core.write_core_reg(register::XPSR.address, XPSR_THUMB)?;
+        let special = core.read_core_reg(ARM_SPECIAL_REGISTERS)?;
+        core.write_core_reg(ARM_SPECIAL_REGISTERS, special | ARM_PRIMASK)?;
+
+        measurement(core)
+    })();
+
+    saved.restore(core)?;
+
+    result
+}
+
+/// Samples the counter of the `method` while the core spins in a loop.
+fn measure_arm_counter(
+    core: &mut Core,
+    ram: u32,
+    duration: Duration,
+    method: ClockMethod,
+) -> Result<ClockMeasurement, ClockError> {
+    let (address, counter, control, enable, restore) = match method {
+        ClockMethod::CycleCounter => {
+            let ctrl = core.read_word_32(DWT_CTRL)?;
+            (
+                DWT_CYCCNT,
+                Counter::CYCCNT,
+                DWT_CTRL,
+                ctrl | DWT_CTRL_CYCCNTENA,
+                vec![(DWT_CTRL, ctrl)],
+            )
+        }
+        _ => {
+            let restore = vec![
+                (SYST_CSR, core.read_word_32(SYST_CSR)?),
+                (SYST_RVR, core.read_word_32(SYST_RVR)?),
+            ];
+            core.write_word_32(SYST_CSR, 0)?;
+            core.write_word_32(SYST_RVR, SYST_RELOAD_MAX)?;
+            core.write_word_32(SYST_CVR, 0)?;
+            (
+                SYST_CVR,
+                Counter::SYSTICK,
+                SYST_CSR,
+                SYST_CSR_ENABLE_CORE_CLOCK,
+                restore,
+            )
+        }
+    };
+    core.write_word_32(control, enable)?;
+
+    let result = with_arm_loop(core, ram, &ARM_SPIN_LOOP, 0, |core| {
+        core.run()?;
+
+        let start = Instant::now();
+        let mut samples = vec![];
+        loop {
+            samples.push(Sample::take(start, || core.read_word_32(address))?);
+            if start.elapsed() >= duration {
+                break;
+            }
+        }
+
+        let cycles = counter.cycles(&samples) as f64;
+        let (frequency, accuracy) = frequency(cycles, &samples[0], &samples[samples.len() - 1])
+            .ok_or(ClockError::Stopped)?;
+
+        Ok(ClockMeasurement {
+            frequency,
+            accuracy,
+            method,
+        })
+    });
+
+    for (address, value) in restore {
+        core.write_word_32(address, value)?;
+    }
+
+    result
+}
+
+/// Times a delay loop with a number of iterations which runs for at least the `duration`.
+fn measure_arm_delay_loop(
+    core: &mut Core,
+    ram: u32,
+    duration: Duration,
+) -> Result<ClockMeasurement, ClockError> {
+    let mut iterations: u32 = 0x1_0000;
+
+    loop {
+        let (first, last) = with_arm_loop(core, ram, &ARM_DELAY_LOOP, iterations, |core| {
+            let start = Instant::now();
+            let first = Sample::take(start, || core.run().map(|_| 0))?;
+
+            let mut previous = first;
+            loop {
+                let poll = Sample::take(start, || core.core_halted().map(u32::from))?;
+                if poll.value != 0 {
+                    let last = Sample {
+                        before: previous.before,
+                        ..poll
+                    };
+                    return Ok((first, last));
+                }
+                if start.elapsed() > 100 * duration {
+                    return Err(ClockError::Stopped);
+                }
+                previous = poll;
+            }
+        })?;
+
+        let elapsed = last.time() - first.time();
+        if elapsed < duration.as_secs_f64() && iterations < u32::MAX / 4 {
+            // Scale the iterations to the duration, with a margin for the inaccurate first guess.
+            let scale = (2.0 * duration.as_secs_f64() / elapsed.max(1e-3)).min(16.0);
+            iterations = (f64::from(iterations) * scale).min(f64::from(u32::MAX / 4)) as u32;
+            continue;
+        }
+
+        let (cycles_per_iteration, cycles_uncertainty) = ARM_DELAY_LOOP_CYCLES;
+        let (frequency, accuracy) =
+            frequency(f64::from(iterations) * cycles_per_iteration, &first, &last)
+                .ok_or(ClockError::Stopped)?;
+
+        return Ok(ClockMeasurement {
+            frequency,
+            accuracy: accuracy + cycles_uncertainty / cycles_per_iteration,
+            method: ClockMethod::DelayLoop,
+        });
+    }
+}
+
+/// Reads `mcycle` before and after the core spins in a loop, with the counters stopped while it is halted.
+fn measure_riscv(
+    core: &mut Core,
+    ram: u32,
+    duration: Duration,
+) -> Result<ClockMeasurement, ClockError> {
+    let pc = core.registers().program_counter().address;
+    let saved = SavedState::save(core, &[pc, RISCV_DCSR], ram, 4)?;
+
+    let result = (|| -> Result<ClockMeasurement, ClockError> {
+        core.write_word_32(ram, RISCV_SPIN_LOOP)?;
+        core.write_core_reg(pc, ram)?;
+        let dcsr = core.read_core_reg(RISCV_DCSR)?;
+        core.write_core_reg(RISCV_DCSR, dcsr | RISCV_DCSR_STOPCOUNT)?;
+        if core.read_core_reg(RISCV_DCSR)? & RISCV_DCSR_STOPCOUNT == 0 {
+            log::warn!("The counters of the core keep running while it is halted, the measured frequency might be too high.");
+        }
+
+        let cycles_before = core.read_core_reg(RISCV_MCYCLE)?;
+
+        let start = Instant::now();
+        let first = Sample::take(start, || core.run().map(|_| 0))?;
+        std::thread::sleep(duration);
+        let last = Sample::take(start, || core.halt(Duration::from_millis(100)).map(|_| 0))?;
+
+        let cycles = core
+            .read_core_reg(RISCV_MCYCLE)?
+            .wrapping_sub(cycles_before);
+        let (frequency, accuracy) =
+            frequency(f64::from(cycles), &first, &last).ok_or(ClockError::Stopped)?;
+
+        Ok(ClockMeasurement {
+            frequency,
+            accuracy,
+            method: ClockMethod::CycleCsr,
+        })
+    })();
+
+    saved.restore(core)?;
+
+    result
+}
+
+/// Calculates the frequency from the clock configuration registers.
+fn read_clock_tree(
+    core: &mut Core,
+    core_clock: &CoreClock,
+) -> Result<ClockMeasurement, ClockError> {
+    let frequency = core_clock
+        .frequency(|address| core.read_word_32(address))?
+        .ok_or(ClockError::InvalidClockTree)?;
+
+    Ok(ClockMeasurement {
+        frequency,
+        accuracy: f64::from(core_clock.tolerance_ppm) / 1e6,
+        method: ClockMethod::ClockTree,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{frequency, Counter, Sample};
+    use std::time::Duration;
+
+    fn sample(before_ms: u64, after_ms: u64, value: u32) -> Sample {
+        Sample {
+            before: Duration::from_millis(before_ms),
+            after: Duration::from_millis(after_ms),
+            value,
+        }
+    }
+
+    #[test]
+    fn counted_cycles_wrap_around() {
+        let samples = [
+            sample(0, 1, 0xFFFF_FF00),
+            sample(1, 2, 0x0000_0100),
+            sample(2, 3, 0x0000_0200),
+        ];
+        assert_eq!(Counter::CYCCNT.cycles(&samples), 0x300);
+
+        let samples = [
+            sample(0, 1, 0x0000_0100),
+            sample(1, 2, 0x00FF_FF00),
+            sample(2, 3, 0x00FF_FE00),
+        ];
+        assert_eq!(Counter::SYSTICK.cycles(&samples), 0x300);
+    }
+
+    #[test]
+    fn frequency_with_uncertainty_of_reads() {
+        // 48 MHz over 100 ms, with reads taking 2 ms
+        let first = sample(0, 2, 0);
+        let last = sample(100, 102, 4_800_000);
+
+        let (frequency, accuracy) = frequency(4_800_000.0, &first, &last).unwrap();
+
+        assert!((frequency - 48e6).abs() < 1.0);
+        assert!((accuracy - 0.02).abs() < 1e-9);
+        assert_eq!(super::frequency(0.0, &first, &last), None);
+    }
+}
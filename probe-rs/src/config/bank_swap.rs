@@ -0,0 +1,98 @@
+use super::flash_algorithm::RegisterWrite;
+use super::option_bytes::StatusBit;
+use std::borrow::Cow;
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+
+/// Describes how the two banks of the flash of a chip family are swapped,
+/// e.g. with the `BFB2` option bit of a STM32L4 or STM32G4.
+///
+/// The banks are the two halves of the boot memory region. While the banks are swapped,
+/// the second bank is mapped at the start of the region, and the first one behind it.
+///
+/// The flash algorithms work on the unswapped flash, so the `unswap` sequence maps
+/// the banks to their own addresses while an algorithm runs, and the data of an image
+/// is moved to the bank which appears at its address while the banks are swapped.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BankSwap {
+    /// The bit which is set while the banks are swapped.
+    pub swapped: StatusBit,
+    /// The register writes which map the banks to their own addresses.
+    ///
+    /// The original register values are restored when the flash algorithm is uninitialized.
+    #[serde(default)]
+    pub unswap: Cow<'static, [RegisterWrite]>,
+}
+
+impl BankSwap {
+    /// Returns `true` if the `value` of the register of the `swapped` bit shows swapped banks.
+    pub fn is_swapped(&self, value: u32) -> bool {
+        value & self.swapped.mask != 0
+    }
+
+    /// Returns the address in the unswapped `flash` which appears at `address`
+    /// while the banks are swapped.
+    ///
+    /// Addresses outside of the flash are returned unchanged.
+    pub fn unswapped_address(flash: &Range<u32>, address: u32) -> u32 {
+        if !flash.contains(&address) {
+            return address;
+        }
+
+        let bank_size = (flash.end - flash.start) / 2;
+        if address < flash.start + bank_size {
+            address + bank_size
+        } else {
+            address - bank_size
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BankSwap;
+
+    /// The `BFB2` bit in the `FLASH_OPTR` of a STM32L4.
+    const STM32L4_BFB2: &str = "
+        swapped:
+          address: 0x40022020
+          mask: 0x100000
+        unswap:
+          - address: 0x40010000
+            value: 0
+            mask: 0x100
+    ";
+
+    #[test]
+    fn swap_state_is_read_from_the_option_register() {
+        let bank_swap: BankSwap = serde_yaml::from_str(STM32L4_BFB2).unwrap();
+
+        // FLASH_OPTR of a STM32L476 with the factory option bytes, and with BFB2 set.
+        assert!(!bank_swap.is_swapped(0xffef_f8aa));
+        assert!(bank_swap.is_swapped(0xffff_f8aa));
+        assert_eq!(bank_swap.unswap.len(), 1);
+    }
+
+    #[test]
+    fn addresses_are_moved_to_the_other_bank() {
+        let flash = 0x0800_0000..0x0810_0000;
+
+        assert_eq!(
+            BankSwap::unswapped_address(&flash, 0x0800_0000),
+            0x0808_0000
+        );
+        assert_eq!(
+            BankSwap::unswapped_address(&flash, 0x0807_fffc),
+            0x080f_fffc
+        );
+        assert_eq!(
+            BankSwap::unswapped_address(&flash, 0x0808_0000),
+            0x0800_0000
+        );
+        assert_eq!(
+            BankSwap::unswapped_address(&flash, 0x0810_0000),
+            0x0810_0000
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use super::flash_algorithm::RegisterWrite;
 use super::memory::MemoryRegion;
 use std::borrow::Cow;
 
@@ -24,4 +25,23 @@ pub struct Chip {
     ///
     /// [`ChipFamily::flash_algorithms`]: crate::config::ChipFamily::flash_algorithms
     pub flash_algorithms: Cow<'static, [Cow<'static, str>]>,
+    /// The cores of the chip, if it has more than one.
+    ///
+    /// A chip without any cores listed has a single core, which is debugged through AP 0.
+    #[serde(default, skip_serializing_if = "<[ChipCore]>::is_empty")]
+    pub cores: Cow<'static, [ChipCore]>,
+}
+
+/// A core of a chip with several cores.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChipCore {
+    /// The name of the core, e.g. `network`.
+    pub name: Cow<'static, str>,
+    /// The memory AP through which the core is debugged.
+    pub access_port: u8,
+    /// The register writes which power up the core, e.g. by releasing it from a forced off state.
+    ///
+    /// They are written through the first core, before the core is attached the first time.
+    #[serde(default, skip_serializing_if = "<[RegisterWrite]>::is_empty")]
+    pub enable_sequence: Cow<'static, [RegisterWrite]>,
 }
@@ -1,11 +1,56 @@
+use super::bank_swap::BankSwap;
 use super::chip::Chip;
+use super::core_clock::CoreClock;
+use super::debug_sequence::DebugSequence;
 use super::flash_algorithm::RawFlashAlgorithm;
+use super::freeze::{DebugFreeze, WatchdogFreeze};
+use super::low_power_debug::LowPowerDebug;
+use super::option_bytes::OptionBytes;
+use crate::architecture::arm::{sequences, SequenceError};
 use crate::config::TargetParseError;
+use crate::core::ResetType;
 use jep106::JEP106Code;
 use std::borrow::Cow;
 
 use serde::{Deserialize, Serialize};
 
+/// A vendor specific sequence which unlocks a read protected chip.
+///
+/// All of these sequences erase the complete flash of the chip,
+/// including any configuration and protection settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnlockSequence {
+    /// Erase the chip with the ERASEALL register of the Nordic CTRL-AP.
+    NrfCtrlApEraseAll,
+    /// Erase the network core and then the application core of a nRF53 chip,
+    /// with the ERASEALL registers of their CTRL-APs.
+    Nrf53CtrlApEraseAll,
+    /// Erase a nRF91 chip with the ERASEALL register of its CTRL-AP,
+    /// which also removes the protection of the secure domain.
+    Nrf91CtrlApEraseAll,
+    /// Erase the chip with a mass erase request to the Kinetis MDM-AP.
+    KinetisMdmApMassErase,
+    /// Regress the read protection level of a STM32F4 from level 1 to level 0,
+    /// which mass erases the flash.
+    Stm32f4RdpRegression,
+    /// Remove the read protection of a WCH chip with a command of the WCH-Link probe,
+    /// which erases the flash.
+    WchLinkUnprotect,
+}
+
+impl UnlockSequence {
+    /// All unlock sequences.
+    pub const ALL: [UnlockSequence; 6] = [
+        UnlockSequence::NrfCtrlApEraseAll,
+        UnlockSequence::Nrf53CtrlApEraseAll,
+        UnlockSequence::Nrf91CtrlApEraseAll,
+        UnlockSequence::KinetisMdmApMassErase,
+        UnlockSequence::Stm32f4RdpRegression,
+        UnlockSequence::WchLinkUnprotect,
+    ];
+}
+
 /// This describes a chip family with all its variants.
 ///
 /// This struct is usually read from a target description
@@ -27,6 +72,60 @@ pub struct ChipFamily {
     /// The name of the core type.
     /// E.g. `M0` or `M4`.
     pub core: Cow<'static, str>,
+    /// The sequence which can be used to unlock a read protected chip of this family.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unlock_sequence: Option<UnlockSequence>,
+    /// The option bytes of this family, if they can be configured.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub option_bytes: Option<OptionBytes>,
+    /// The SWD/JTAG speed in kHz at which the communication with chips of this family
+    /// starts, if it differs from the speed configured for the probe.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recommended_speed_khz: Option<u32>,
+    /// The `<debugvars>` of the CMSIS-Pack debug description, which declare
+    /// the variables that are shared by the debug sequences.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debug_vars: Option<Cow<'static, str>>,
+    /// The debug sequences which replace the built-in steps to connect to and reset the chips of this family.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "<[DebugSequence]>::is_empty")]
+    pub debug_sequences: Cow<'static, [DebugSequence]>,
+    /// The reset types which can be used with chips of this family.
+    /// If the list is empty, all reset types the core supports can be used.
+    ///
+    /// A [ResetType::Custom] reset type executes the debug sequence with its name.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "<[ResetType]>::is_empty")]
+    pub reset_types: Cow<'static, [ResetType]>,
+    /// The reset type which is used unless another one is selected, e.g. because
+    /// a system reset would lock the debug access of the chips again.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_reset_type: Option<ResetType>,
+    /// The register writes which stop the watchdogs of this family while a core is halted.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub watchdog_freeze: Option<WatchdogFreeze>,
+    /// The debug freeze bits of the peripherals of this family, which can be selected by name.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debug_freeze: Option<DebugFreeze>,
+    /// The register writes which keep the debug port of this family powered in its low power modes.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub low_power_debug: Option<LowPowerDebug>,
+    /// How the flash banks of this family are swapped, if they can be.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bank_swap: Option<BankSwap>,
+    /// How the core clock of this family is derived from its input clock.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub core_clock: Option<CoreClock>,
 }
 
 pub fn serialize<S>(raw_algorithms: &[RawFlashAlgorithm], serializer: S) -> Result<S::Ok, S::Error>
@@ -96,6 +195,51 @@ impl ChipFamily {
         let name = name.as_ref();
         self.flash_algorithms.iter().find(|elem| elem.name == name)
     }
+
+    /// Checks that the debug sequences of this family only use functions which
+    /// probe-rs supports.
+    ///
+    /// Sequences are executed when a chip of the family is attached, so this should
+    /// be used when a family is imported to report unsupported sequences early.
+    pub fn validate_debug_sequences(&self) -> Result<(), Vec<SequenceError>> {
+        let errors = sequences::validate(&self.debug_sequences, self.debug_vars.as_deref());
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Checks that the default reset type is one of the reset types of this family,
+    /// and that there is a debug sequence for every custom reset type.
+    pub fn validate_reset_types(&self) -> Result<(), String> {
+        if let Some(default) = &self.default_reset_type {
+            if !self.reset_types.is_empty() && !self.reset_types.contains(default) {
+                return Err(format!(
+                    "The default reset type {} is not one of the reset types of the family",
+                    default
+                ));
+            }
+        }
+
+        for reset_type in self.reset_types.iter().chain(&self.default_reset_type) {
+            if let ResetType::Custom(name) = reset_type {
+                if !self
+                    .debug_sequences
+                    .iter()
+                    .any(|sequence| sequence.name == *name)
+                {
+                    return Err(format!(
+                        "The reset type {} has no debug sequence with the same name",
+                        name
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
 }
 
 #[test]
@@ -107,3 +251,24 @@ fn map_to_list_deserialize() {
     let chip_family = result.unwrap();
     assert_eq!(chip_family.algorithms().len(), 18);
 }
+
+#[test]
+fn validate_reset_types() {
+    let mut chip_family: ChipFamily =
+        serde_yaml::from_str(include_str!("../../targets/STM32F4 Series.yaml")).unwrap();
+    assert!(chip_family.validate_reset_types().is_ok());
+
+    chip_family.reset_types = Cow::Borrowed(&[ResetType::SystemReset]);
+    chip_family.default_reset_type = Some(ResetType::HardwarePin);
+    assert!(chip_family.validate_reset_types().is_err());
+
+    chip_family.default_reset_type = Some(ResetType::Custom(Cow::Borrowed("ResetSystem")));
+    chip_family.reset_types = Cow::Borrowed(&[]);
+    assert!(chip_family.validate_reset_types().is_err());
+
+    chip_family.debug_sequences = Cow::Owned(vec![DebugSequence {
+        name: Cow::Borrowed("ResetSystem"),
+        steps: Cow::Borrowed(&[]),
+    }]);
+    assert!(chip_family.validate_reset_types().is_ok());
+}
@@ -1,17 +1,23 @@
-use crate::architecture::arm::ArmChipInfo;
+use crate::architecture::{arm::ArmChipInfo, riscv::communication_interface::RiscvChipInfo};
 
 /// Information about a chip which is used
 /// for automatic detection of the connected chip.
 ///
 /// For ARM-based chips, the function [ArmProbeInterface::read_from_rom_table] is
-/// used to read the information from the target.
+/// used to read the information from the target. It contains the peripheral IDs of the
+/// ROM table and, if available, the TARGETID register of the debug port.
+///
+/// For RISC-V based chips, the JTAG IDCODE is used.
 ///
 /// [ArmProbeInterface::read_from_rom_table]: crate::architecture::arm::communication_interface::ArmProbeInterface::read_from_rom_table
 #[derive(Debug)]
-pub(crate) enum ChipInfo {
+pub enum ChipInfo {
     /// ARM specific information for chip
     /// auto-detection. See [ArmChipInfo].
     Arm(ArmChipInfo),
+    /// RISC-V specific information for chip
+    /// auto-detection. See [RiscvChipInfo].
+    Riscv(RiscvChipInfo),
 }
 
 impl From<ArmChipInfo> for ChipInfo {
@@ -19,3 +25,30 @@ impl From<ArmChipInfo> for ChipInfo {
         ChipInfo::Arm(info)
     }
 }
+
+impl From<RiscvChipInfo> for ChipInfo {
+    fn from(info: RiscvChipInfo) -> Self {
+        ChipInfo::Riscv(info)
+    }
+}
+
+impl std::fmt::Display for ChipInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChipInfo::Arm(info) => info.fmt(f),
+            ChipInfo::Riscv(info) => info.fmt(f),
+        }
+    }
+}
+
+/// The result of the automatic detection of the connected chip.
+#[derive(Debug)]
+pub struct TargetDetection {
+    /// The identification information read from the chip.
+    pub chip_info: ChipInfo,
+    /// The names of all chips in the registry which match the identification.
+    ///
+    /// If there is more than one candidate, the chip can not be determined
+    /// unambiguously and has to be selected by the user.
+    pub candidates: Vec<String>,
+}
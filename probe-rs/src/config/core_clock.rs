@@ -0,0 +1,128 @@
+use std::borrow::Cow;
+
+use serde::{Deserialize, Serialize};
+
+/// Describes how the core clock of a chip family is derived from a fixed input clock,
+/// e.g. from the HSE oscillator through the PLL of a STM32.
+///
+/// The frequency is the `input_frequency`, multiplied and divided by the `factors`,
+/// which are read from the clock configuration registers of the chip. It is used by
+/// [Session::measure_core_clock](crate::Session::measure_core_clock) for cores which
+/// have no cycle counter.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CoreClock {
+    /// The frequency of the input clock, in Hz.
+    pub input_frequency: u32,
+    /// The tolerance of the input clock, in parts per million.
+    #[serde(default = "default_tolerance_ppm")]
+    pub tolerance_ppm: u32,
+    /// The multipliers and dividers between the input clock and the core clock.
+    #[serde(default)]
+    pub factors: Cow<'static, [ClockFactor]>,
+}
+
+fn default_tolerance_ppm() -> u32 {
+    // The typical tolerance of an internal RC oscillator.
+    10_000
+}
+
+impl CoreClock {
+    /// Returns the core clock frequency in Hz, with the `read` values of the registers of the factors.
+    ///
+    /// Returns `None` if a factor is zero.
+    pub fn frequency(
+        &self,
+        mut read: impl FnMut(u32) -> Result<u32, crate::Error>,
+    ) -> Result<Option<f64>, crate::Error> {
+        let mut frequency = f64::from(self.input_frequency);
+
+        for factor in self.factors.iter() {
+            let value = factor.value(read(factor.address)?);
+            if value == 0 {
+                log::warn!("The clock factor {} is zero", factor.name);
+                return Ok(None);
+            }
+
+            if factor.divider {
+                frequency /= f64::from(value);
+            } else {
+                frequency *= f64::from(value);
+            }
+        }
+
+        Ok(Some(frequency))
+    }
+}
+
+/// A multiplier or divider of the core clock, which is stored in a field of a register.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClockFactor {
+    /// The name of the field, e.g. `PLLN`.
+    pub name: Cow<'static, str>,
+    /// The address of the register.
+    pub address: u32,
+    /// The bits of the field in the register.
+    pub mask: u32,
+    /// The value which is added to the field.
+    #[serde(default)]
+    pub offset: u32,
+    /// The value by which the field is multiplied, after the offset was added.
+    #[serde(default = "default_scale")]
+    pub scale: u32,
+    /// Whether the clock is divided by the factor, instead of multiplied.
+    #[serde(default)]
+    pub divider: bool,
+}
+
+fn default_scale() -> u32 {
+    1
+}
+
+impl ClockFactor {
+    /// Returns the factor, with the `register` value which contains its field.
+    pub fn value(&self, register: u32) -> u32 {
+        let field = (register & self.mask)
+            .checked_shr(self.mask.trailing_zeros())
+            .unwrap_or(0);
+
+        (field + self.offset) * self.scale
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CoreClock;
+
+    #[test]
+    fn frequency_from_pll_configuration() {
+        // RCC_PLLCFGR of a STM32F4: 8 MHz HSE, PLLM = 8, PLLN = 336, PLLP = 4
+        let yaml = "
+input_frequency: 8000000
+tolerance_ppm: 50
+factors:
+  - name: PLLM
+    address: 0x40023804
+    mask: 0x3f
+    divider: true
+  - name: PLLN
+    address: 0x40023804
+    mask: 0x7fc0
+  - name: PLLP
+    address: 0x40023804
+    mask: 0x30000
+    offset: 1
+    scale: 2
+    divider: true
+";
+
+        let core_clock: CoreClock = serde_yaml::from_str(yaml).unwrap();
+        let pllcfgr = 8 | 336 << 6 | 1 << 16;
+
+        assert_eq!(core_clock.tolerance_ppm, 50);
+        assert_eq!(
+            core_clock.frequency(|_| Ok(pllcfgr)).unwrap(),
+            Some(84_000_000.0)
+        );
+        assert_eq!(core_clock.frequency(|_| Ok(0)).unwrap(), None);
+    }
+}
@@ -0,0 +1,46 @@
+use std::borrow::Cow;
+
+/// A debug sequence from the debug description of a CMSIS-Pack.
+///
+/// Debug sequences contain the vendor specific steps which are required to connect to
+/// and reset a chip, e.g. enabling clocks or unlocking the debug access. The known
+/// sequences are executed instead of the built-in steps of probe-rs, see
+/// [the debug description of CMSIS-Packs] for their names and their syntax.
+///
+/// [the debug description of CMSIS-Packs]: https://open-cmsis-pack.github.io/Open-CMSIS-Pack-Spec/main/html/debug_description.html#sequences
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DebugSequence {
+    /// The name of the sequence, e.g. `DebugCoreStart` or `ResetCatchSet`.
+    pub name: Cow<'static, str>,
+    /// The `<block>` and `<control>` elements of the sequence.
+    pub steps: Cow<'static, [SequenceStep]>,
+}
+
+/// A step of a [DebugSequence].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SequenceStep {
+    /// A `<block>` element with statements which are executed in order.
+    Block {
+        /// The statements of the block.
+        code: Cow<'static, str>,
+        /// True if the statements should be executed without interruption.
+        #[serde(default)]
+        atomic: bool,
+    },
+    /// A `<control>` element, which executes its steps conditionally or repeatedly.
+    Control {
+        /// The steps are only executed if this expression is not zero.
+        #[serde(default, rename = "if", skip_serializing_if = "Option::is_none")]
+        condition: Option<Cow<'static, str>>,
+        /// The steps are executed repeatedly as long as this expression is not zero.
+        #[serde(default, rename = "while", skip_serializing_if = "Option::is_none")]
+        repeat: Option<Cow<'static, str>>,
+        /// The time in microseconds after which a `while` loop fails. Zero disables the timeout.
+        #[serde(default)]
+        timeout: u64,
+        /// The nested steps.
+        #[serde(default)]
+        steps: Cow<'static, [SequenceStep]>,
+    },
+}
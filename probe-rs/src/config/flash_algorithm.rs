@@ -1,5 +1,5 @@
 use super::flash_properties::FlashProperties;
-use super::memory::{PageInfo, RamRegion, SectorInfo};
+use super::memory::{NvmRegion, PageInfo, RamRegion, SectorDescription, SectorInfo};
 use crate::architecture::riscv;
 use crate::core::Architecture;
 use crate::flashing::FlashError;
@@ -32,6 +32,10 @@ pub struct FlashAlgorithm {
     pub pc_erase_sector: u32,
     /// Address of the `EraseAll()` entry point. Optional.
     pub pc_erase_all: Option<u32>,
+    /// Address of the `Verify()` entry point. Optional.
+    pub pc_verify: Option<u32>,
+    /// Address of the `BlankCheck()` entry point. Optional.
+    pub pc_blank_check: Option<u32>,
     /// Initial value of the R9 register for calling flash algo entry points, which
     /// determines where the position-independent data resides.
     pub static_base: u32,
@@ -43,6 +47,14 @@ pub struct FlashAlgorithm {
     /// least as large as the region's `page_size` attribute. If at least 2 buffers are included in
     /// the list, then double buffered programming will be enabled.
     pub page_buffers: Vec<u32>,
+    /// The index of the core which runs the flash algorithm.
+    pub core_index: usize,
+    /// Register writes which are executed before the flash algorithm is run.
+    pub pre_flash_sequence: Vec<RegisterWrite>,
+    /// Register writes which are executed after the flash algorithm has finished.
+    ///
+    /// If this is empty, the registers modified by the `pre_flash_sequence` are restored instead.
+    pub post_flash_sequence: Vec<RegisterWrite>,
 
     /// The properties of the flash on the device.
     pub flash_properties: FlashProperties,
@@ -80,6 +92,28 @@ impl FlashAlgorithm {
         })
     }
 
+    /// Replaces the sectors of the flash properties with the sectors of `region`,
+    /// if the region describes its own sectors.
+    ///
+    /// The region has to be contained in the address range of the algorithm.
+    pub(crate) fn use_sectors_of_region(&mut self, region: &NvmRegion) {
+        if region.sectors.is_empty() {
+            return;
+        }
+
+        let offset = region.range.start - self.flash_properties.address_range.start;
+
+        self.flash_properties.sectors = region
+            .sectors
+            .iter()
+            .map(|sector| SectorDescription {
+                size: sector.size,
+                address: offset + sector.address,
+            })
+            .collect::<Vec<_>>()
+            .into();
+    }
+
     /// Returns the necessary information about the page which `address` resides in
     /// if the address is inside the flash region.
     pub fn page_info(&self, address: u32) -> Option<PageInfo> {
@@ -102,6 +136,36 @@ impl FlashAlgorithm {
         }
         true
     }
+
+    /// Returns the name of the routine which contains `address`,
+    /// together with the offset of `address` from the entry point of the routine.
+    ///
+    /// The routine is determined from the entry points of the algorithm,
+    /// e.g. `program_page+0x1a`. Addresses outside of the algorithm code return `None`.
+    pub fn routine_at(&self, address: u32) -> Option<(&'static str, u32)> {
+        let code_end = self.load_address + (self.instructions.len() * 4) as u32;
+        if !(self.load_address..code_end).contains(&address) {
+            return None;
+        }
+
+        let entry_points = [
+            ("init", self.pc_init),
+            ("uninit", self.pc_uninit),
+            ("program_page", Some(self.pc_program_page)),
+            ("erase_sector", Some(self.pc_erase_sector)),
+            ("erase_all", self.pc_erase_all),
+            ("verify", self.pc_verify),
+            ("blank_check", self.pc_blank_check),
+        ];
+
+        entry_points
+            .iter()
+            // Entry points on ARM have the Thumb bit set.
+            .filter_map(|(name, entry)| entry.map(|entry| (*name, entry & !1)))
+            .filter(|(_, entry)| *entry <= address)
+            .max_by_key(|(_, entry)| *entry)
+            .map(|(name, entry)| (name, address - entry))
+    }
 }
 
 /// The raw flash algorithm is the description of a flash algorithm,
@@ -132,12 +196,57 @@ pub struct RawFlashAlgorithm {
     pub pc_erase_sector: u32,
     /// Address of the `EraseAll()` entry point. Optional.
     pub pc_erase_all: Option<u32>,
+    /// Address of the `Verify()` entry point. Optional.
+    #[serde(default)]
+    pub pc_verify: Option<u32>,
+    /// Address of the `BlankCheck()` entry point. Optional.
+    #[serde(default)]
+    pub pc_blank_check: Option<u32>,
     /// The offset from the start of RAM to the data section.
     pub data_section_offset: u32,
+    /// The index of the core which has to run the flash algorithm.
+    ///
+    /// This is required on multi-core chips where only one of the cores can access the flash.
+    #[serde(default)]
+    pub core_index: usize,
+    /// Register writes which prepare the target before the flash algorithm is run,
+    /// e.g. to disable caches or to enable a specific clock.
+    #[serde(default)]
+    pub pre_flash_sequence: Cow<'static, [RegisterWrite]>,
+    /// Register writes which are executed after the flash algorithm has finished.
+    ///
+    /// If this is empty, the registers modified by the `pre_flash_sequence` are
+    /// restored to their original values instead.
+    #[serde(default)]
+    pub post_flash_sequence: Cow<'static, [RegisterWrite]>,
     /// The properties of the flash on the device.
     pub flash_properties: FlashProperties,
 }
 
+/// A single write to a 32 bit register of the target, used to prepare
+/// the target for running a flash algorithm.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct RegisterWrite {
+    /// The address of the register.
+    pub address: u32,
+    /// The value which is written to the register.
+    pub value: u32,
+    /// Only the bits set in the mask are modified, all other bits keep their current value.
+    #[serde(default = "RegisterWrite::default_mask")]
+    pub mask: u32,
+}
+
+impl RegisterWrite {
+    fn default_mask() -> u32 {
+        0xFFFF_FFFF
+    }
+
+    /// Returns the new value of the register, given its `current` value.
+    pub fn apply(&self, current: u32) -> u32 {
+        (current & !self.mask) | (self.value & self.mask)
+    }
+}
+
 pub fn serialize<S>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error>
 where
     S: serde::Serializer,
@@ -196,6 +305,16 @@ impl RawFlashAlgorithm {
         }
     }
 
+    /// Returns the number of bytes of RAM the algorithm requires at least,
+    /// for its code and data, the smallest stack and a single page buffer.
+    pub(crate) fn required_ram(&self, architecture: Architecture) -> u32 {
+        let header = self.get_algorithm_header(architecture);
+
+        (std::mem::size_of_val(header) + self.instructions.len()) as u32
+            + Self::FLASH_ALGO_STACK_DECREMENT
+            + self.flash_properties.page_size
+    }
+
     /// Constructs a complete flash algorithm, tailored to the flash and RAM sizes given.
     pub fn assemble(
         &self,
@@ -267,15 +386,85 @@ impl RawFlashAlgorithm {
             pc_program_page: code_start + self.pc_program_page,
             pc_erase_sector: code_start + self.pc_erase_sector,
             pc_erase_all: self.pc_erase_all.map(|v| code_start + v),
+            pc_verify: self.pc_verify.map(|v| code_start + v),
+            pc_blank_check: self.pc_blank_check.map(|v| code_start + v),
             static_base: code_start + self.data_section_offset,
             begin_stack: addr_stack,
             begin_data: page_buffers[0],
             page_buffers: page_buffers.clone(),
+            core_index: self.core_index,
+            pre_flash_sequence: self.pre_flash_sequence.to_vec(),
+            post_flash_sequence: self.post_flash_sequence.to_vec(),
             flash_properties: self.flash_properties.clone(),
         })
     }
 }
 
+#[test]
+fn flash_sector_of_region() {
+    use crate::config::{EraseMode, NvmRegion, SectorDescription};
+    let mut config = FlashAlgorithm {
+        flash_properties: FlashProperties {
+            sectors: Cow::Borrowed(&[SectorDescription {
+                size: 0x2_0000,
+                address: 0x0,
+            }]),
+            address_range: 0x800_0000..0x800_0000 + 0x20_0000,
+            page_size: 0x100,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    // The second bank has smaller sectors at its start.
+    let region = NvmRegion {
+        range: 0x810_0000..0x820_0000,
+        is_boot_memory: false,
+        is_otp: false,
+        erase_mode: EraseMode::Sector,
+        sectors: Cow::Borrowed(&[
+            SectorDescription {
+                size: 0x4000,
+                address: 0x0,
+            },
+            SectorDescription {
+                size: 0x1_0000,
+                address: 0x1_0000,
+            },
+            SectorDescription {
+                size: 0x2_0000,
+                address: 0x2_0000,
+            },
+        ]),
+        memory_mapped: None,
+    };
+
+    config.use_sectors_of_region(&region);
+
+    assert_eq!(None, config.sector_info(0x800_0000));
+    assert_eq!(
+        Some(SectorInfo {
+            base_address: 0x810_4000,
+            size: 0x4000,
+        }),
+        config.sector_info(0x810_7fff)
+    );
+    assert_eq!(
+        Some(SectorInfo {
+            base_address: 0x811_0000,
+            size: 0x1_0000,
+        }),
+        config.sector_info(0x811_0000)
+    );
+    assert_eq!(
+        Some(SectorInfo {
+            base_address: 0x81E_0000,
+            size: 0x2_0000,
+        }),
+        config.sector_info(0x81F_FFFF)
+    );
+}
+
 #[test]
 fn flash_sector_single_size() {
     use crate::config::SectorDescription;
@@ -381,3 +570,43 @@ fn flash_sector_multiple_sizes() {
     assert_eq!(Some(expected_b), config.sector_info(0x801_0000));
     assert_eq!(Some(expected_c), config.sector_info(0x80A_0000));
 }
+
+#[test]
+fn register_write_only_modifies_masked_bits() {
+    let write = RegisterWrite {
+        address: 0xE000_ED14,
+        value: 0x0000_0000,
+        mask: 0x0003_0000,
+    };
+
+    assert_eq!(write.apply(0x0003_0200), 0x0000_0200);
+
+    let write = RegisterWrite {
+        address: 0x5802_4400,
+        value: 0x0000_0001,
+        mask: RegisterWrite::default_mask(),
+    };
+
+    assert_eq!(write.apply(0xFFFF_FFFF), 0x0000_0001);
+}
+
+#[test]
+fn routine_at_address() {
+    let algorithm = FlashAlgorithm {
+        load_address: 0x2000_0000,
+        instructions: vec![0; 0x100],
+        pc_init: Some(0x2000_0021),
+        pc_program_page: 0x2000_0081,
+        pc_erase_sector: 0x2000_0041,
+        ..Default::default()
+    };
+
+    assert_eq!(algorithm.routine_at(0x2000_0010), None);
+    assert_eq!(algorithm.routine_at(0x2000_0024), Some(("init", 4)));
+    assert_eq!(algorithm.routine_at(0x2000_0040), Some(("erase_sector", 0)));
+    assert_eq!(
+        algorithm.routine_at(0x2000_009a),
+        Some(("program_page", 0x1a))
+    );
+    assert_eq!(algorithm.routine_at(0x2000_0400), None);
+}
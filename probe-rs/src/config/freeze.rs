@@ -0,0 +1,114 @@
+use super::flash_algorithm::RegisterWrite;
+use std::borrow::Cow;
+
+use serde::{Deserialize, Serialize};
+
+/// Describes how the watchdogs of a chip family are stopped while a core is halted.
+///
+/// Most chips have debug freeze bits, e.g. in the `DBGMCU` of a STM32, which stop
+/// the watchdog counters while the core is halted. If they are not set, the watchdog
+/// resets the chip shortly after halting it.
+///
+/// The `unlock` sequence is run before the freeze bits are modified, both when they are
+/// set and when their original values are restored, e.g. to enable the clock of the debug
+/// unit or to write the key of a write protected watchdog.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WatchdogFreeze {
+    /// The register writes which make the freeze bits writable.
+    #[serde(default)]
+    pub unlock: Cow<'static, [RegisterWrite]>,
+    /// The register writes which set the freeze bits.
+    pub freeze: Cow<'static, [RegisterWrite]>,
+}
+
+/// A named debug freeze bit, which stops a peripheral while a core is halted,
+/// e.g. `DBG_TIM2_STOP` in the `DBGMCU_APB1_FZ` register of a STM32F4.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FreezeBit {
+    /// The name of the peripheral which is stopped, e.g. `TIM2` or `CAN1`.
+    pub name: Cow<'static, str>,
+    /// The address of the register which contains the bit.
+    pub address: u32,
+    /// The bit, or bits, which are set to stop the peripheral.
+    pub mask: u32,
+}
+
+impl FreezeBit {
+    /// Returns the register write which sets the bit.
+    pub fn write(&self) -> RegisterWrite {
+        RegisterWrite {
+            address: self.address,
+            value: self.mask,
+            mask: self.mask,
+        }
+    }
+}
+
+/// Describes the debug freeze bits of the peripherals of a chip family.
+///
+/// Unlike the watchdogs, the peripherals are only stopped if they are selected by name,
+/// see [Session::freeze_peripherals](crate::Session::freeze_peripherals).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DebugFreeze {
+    /// The register writes which make the freeze bits writable.
+    #[serde(default)]
+    pub unlock: Cow<'static, [RegisterWrite]>,
+    /// The freeze bits of the peripherals.
+    pub bits: Cow<'static, [FreezeBit]>,
+}
+
+impl DebugFreeze {
+    /// Returns the freeze bit of the peripheral `name`, ignoring the case of the name.
+    pub fn bit(&self, name: &str) -> Option<&FreezeBit> {
+        self.bits
+            .iter()
+            .find(|bit| bit.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Returns the names of all freeze bits.
+    pub fn names(&self) -> Vec<String> {
+        self.bits.iter().map(|bit| bit.name.to_string()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DebugFreeze, WatchdogFreeze};
+
+    #[test]
+    fn deserialize_watchdog_freeze() {
+        let yaml = "
+freeze:
+  - address: 0xE0042008
+    value: 0x1800
+    mask: 0x1800
+";
+
+        let watchdog_freeze: WatchdogFreeze = serde_yaml::from_str(yaml).unwrap();
+
+        assert!(watchdog_freeze.unlock.is_empty());
+        assert_eq!(watchdog_freeze.freeze.len(), 1);
+        assert_eq!(watchdog_freeze.freeze[0].address, 0xE004_2008);
+        assert_eq!(watchdog_freeze.freeze[0].apply(0x0000_0001), 0x0000_1801);
+    }
+
+    #[test]
+    fn freeze_bits_are_found_by_name() {
+        let yaml = "
+bits:
+  - name: TIM2
+    address: 0xE0042008
+    mask: 0x1
+  - name: CAN1
+    address: 0xE0042008
+    mask: 0x2000000
+";
+
+        let debug_freeze: DebugFreeze = serde_yaml::from_str(yaml).unwrap();
+
+        let can = debug_freeze.bit("can1").unwrap();
+        assert_eq!(can.write().apply(0x0000_0001), 0x0200_0001);
+        assert!(debug_freeze.bit("TIM3").is_none());
+        assert_eq!(debug_freeze.names(), vec!["TIM2", "CAN1"]);
+    }
+}
@@ -0,0 +1,40 @@
+use super::flash_algorithm::RegisterWrite;
+use std::borrow::Cow;
+
+use serde::{Deserialize, Serialize};
+
+/// Describes how the debug port of a chip family stays powered in its low power modes.
+///
+/// Most chips power down the debug port in their deep sleep modes, e.g. in the STOP and
+/// STANDBY modes of a STM32, unless debug bits like `DBG_STOP` and `DBG_STANDBY` in the
+/// `DBGMCU_CR` are set. The bits are set as soon as the target is reachable when attaching
+/// with [AttachMethod::OnWakeup](crate::AttachMethod::OnWakeup).
+///
+/// The bits are not restored when the session ends, the chip keeps its debug port powered
+/// until it is power cycled.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LowPowerDebug {
+    /// The register writes which keep the debug port powered in the low power modes.
+    pub enable: Cow<'static, [RegisterWrite]>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LowPowerDebug;
+
+    #[test]
+    fn deserialize_low_power_debug() {
+        // DBGMCU_CR of a STM32F4: DBG_SLEEP, DBG_STOP, DBG_STANDBY
+        let yaml = "
+enable:
+  - address: 0xE0042004
+    value: 0x7
+    mask: 0x7
+";
+
+        let low_power_debug: LowPowerDebug = serde_yaml::from_str(yaml).unwrap();
+
+        assert_eq!(low_power_debug.enable.len(), 1);
+        assert_eq!(low_power_debug.enable[0].apply(0x0000_0020), 0x0000_0027);
+    }
+}
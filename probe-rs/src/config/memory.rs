@@ -1,4 +1,8 @@
+use super::flash_algorithm::RegisterWrite;
+use crate::AccessSize;
 use core::ops::Range;
+use std::borrow::Cow;
+use std::fmt;
 
 /// Represents a region in non-volatile memory (e.g. flash or EEPROM).
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -7,9 +11,111 @@ pub struct NvmRegion {
     pub range: Range<u32>,
     /// True if the chip boots from this memory
     pub is_boot_memory: bool,
+    /// True if the region is one-time programmable, or can only be erased together
+    /// with the complete chip, e.g. eFuses, OTP areas or the UICR of nRF chips.
+    ///
+    /// Writing to such a region requires the `otp_write` permission.
+    #[serde(default)]
+    pub is_otp: bool,
+    /// How the region can be erased.
+    #[serde(default)]
+    pub erase_mode: EraseMode,
+    /// The sectors of the region, if they differ from the sectors described by the flash algorithm,
+    /// e.g. because the region is one bank of a flash with several banks.
+    ///
+    /// The addresses are relative to the start of the region. Like in [`FlashProperties`],
+    /// every entry is valid until the next one, so both a list of single sectors and
+    /// groups of sectors with the same size can be described.
+    ///
+    /// [`FlashProperties`]: crate::config::FlashProperties
+    #[serde(default, skip_serializing_if = "<[SectorDescription]>::is_empty")]
+    pub sectors: Cow<'static, [SectorDescription]>,
+    /// Set if the region is an external flash which is read through the memory bus
+    /// (execute in place), but programmed through its controller by the flash algorithm.
+    ///
+    /// The contents of such a region can only be read while the controller is in its
+    /// memory mapped mode, not while the flash algorithm uses it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_mapped: Option<MemoryMappedAccess>,
+}
+
+/// Describes how the controller of a memory mapped external flash is switched
+/// between the memory mapped mode and the mode used by the flash algorithm.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub struct MemoryMappedAccess {
+    /// Register writes which are executed before the flash algorithm is loaded,
+    /// e.g. to abort the memory mapped mode of the controller.
+    #[serde(default)]
+    pub pre_algorithm_sequence: Cow<'static, [RegisterWrite]>,
+    /// Register writes which enable the memory mapped mode again
+    /// after the flash algorithm has finished.
+    #[serde(default)]
+    pub restore_sequence: Cow<'static, [RegisterWrite]>,
+}
+
+/// Describes how a region of non-volatile memory can be erased.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EraseMode {
+    /// Each sector can be erased on its own.
+    Sector,
+    /// The region is only erased together with the complete chip.
+    ///
+    /// It is programmed without erasing it first.
+    ChipOnly,
+    /// The region can not be erased at all.
+    ///
+    /// It is programmed without erasing it first.
+    None,
+}
+
+impl Default for EraseMode {
+    fn default() -> Self {
+        EraseMode::Sector
+    }
 }
 
 impl NvmRegion {
+    /// Returns true if the sectors of this region are erased before programming them.
+    pub fn is_sector_erasable(&self) -> bool {
+        self.erase_mode == EraseMode::Sector
+    }
+
+    /// Checks that the sectors of the region start at the beginning of the region,
+    /// have a size and are sorted by their address.
+    pub fn validate_sectors(&self) -> Result<(), &'static str> {
+        let first = match self.sectors.first() {
+            Some(first) => first,
+            None => return Ok(()),
+        };
+
+        if first.address != 0 {
+            return Err("the first sector does not start at the beginning of the region");
+        }
+
+        if self.sectors.iter().any(|sector| sector.size == 0) {
+            return Err("a sector has a size of zero");
+        }
+
+        if self
+            .sectors
+            .windows(2)
+            .any(|pair| pair[0].address >= pair[1].address)
+        {
+            return Err("the sectors are not sorted by their address");
+        }
+
+        if self
+            .sectors
+            .iter()
+            .any(|sector| sector.address >= self.range.end - self.range.start)
+        {
+            return Err("a sector starts outside of the region");
+        }
+
+        Ok(())
+    }
+
     /// Returns the necessary information about the NVM.
     pub fn nvm_info(&self) -> NvmInfo {
         NvmInfo {
@@ -25,6 +131,9 @@ pub struct RamRegion {
     pub range: Range<u32>,
     /// True if the chip boots from this memory
     pub is_boot_memory: bool,
+    /// The index of the core which accesses the region, e.g. to run a flash algorithm in it.
+    #[serde(default)]
+    pub core_index: usize,
 }
 
 /// Represents a generic region.
@@ -32,6 +141,113 @@ pub struct RamRegion {
 pub struct GenericRegion {
     /// Address range of the region
     pub range: Range<u32>,
+    /// The name of the region, e.g. the peripheral which it contains.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<Cow<'static, str>>,
+    /// How the region can be accessed, e.g. if it contains peripherals which
+    /// do not behave like memory.
+    #[serde(default)]
+    pub access: MemoryAccess,
+}
+
+impl fmt::Display for GenericRegion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(name) = &self.name {
+            write!(f, "{} ", name)?;
+        }
+
+        write!(f, "({:#010x}..{:#010x})", self.range.start, self.range.end)
+    }
+}
+
+/// The access attributes of a memory region.
+///
+/// By default, a region can be accessed like memory: probe-rs splits and merges
+/// the accesses as it likes, and the debugger reads it whenever it needs to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub struct MemoryAccess {
+    /// The sizes of the accesses which the region allows, all sizes if it is empty.
+    ///
+    /// Accesses of other sizes are made with one of these sizes, and fail if this
+    /// is not possible, e.g. a byte write to a region which only allows 32 bit accesses.
+    #[serde(default, skip_serializing_if = "<[AccessSize]>::is_empty")]
+    pub sizes: Cow<'static, [AccessSize]>,
+    /// True if reading the region changes the state of the target, e.g. pops a FIFO
+    /// or clears status flags.
+    ///
+    /// The debugger does not read such a region unless it is explicitly requested.
+    #[serde(default)]
+    pub read_side_effects: bool,
+    /// True if the region can not be read at all.
+    #[serde(default)]
+    pub write_only: bool,
+    /// True if the region can not be read while the core is halted, e.g. because
+    /// its clock is stopped in debug state.
+    #[serde(default)]
+    pub not_readable_while_halted: bool,
+}
+
+impl MemoryAccess {
+    /// Returns true if the region can be accessed like memory.
+    pub fn is_unrestricted(&self) -> bool {
+        self == &MemoryAccess::default()
+    }
+
+    /// Returns the allowed size of an access of `len` bytes at `address`, to replace
+    /// an access with the `requested` size, or `None` if the requested access is allowed.
+    ///
+    /// The largest allowed size which `address` and `len` are aligned to is used. Reads
+    /// which are not aligned to any of them are widened, unaligned writes are not possible.
+    pub(crate) fn allowed_size(
+        &self,
+        address: u32,
+        len: usize,
+        requested: Option<AccessSize>,
+        write: bool,
+    ) -> Result<Option<AccessSize>, AccessAttribute> {
+        if !write && self.write_only {
+            return Err(AccessAttribute::WriteOnly);
+        }
+
+        if self.sizes.is_empty() || requested.map_or(false, |size| self.sizes.contains(&size)) {
+            return Ok(None);
+        }
+
+        let mut sizes = self.sizes.to_vec();
+        sizes.sort_by_key(|size| std::cmp::Reverse(size.bytes()));
+
+        if let Some(size) = sizes.iter().find(|size| size.is_aligned(address, len)) {
+            Ok(Some(*size))
+        } else if !write {
+            Ok(sizes.last().copied())
+        } else {
+            Err(AccessAttribute::Sizes(sizes))
+        }
+    }
+}
+
+/// An access attribute of a memory region, which an access violated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccessAttribute {
+    /// The region only allows accesses of these sizes.
+    Sizes(Vec<AccessSize>),
+    /// The region can not be read.
+    WriteOnly,
+    /// Reading the region changes the state of the target.
+    ReadSideEffects,
+}
+
+impl fmt::Display for AccessAttribute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccessAttribute::Sizes(sizes) => {
+                let sizes: Vec<_> = sizes.iter().map(ToString::to_string).collect();
+                write!(f, "only allows {} accesses", sizes.join(" or "))
+            }
+            AccessAttribute::WriteOnly => write!(f, "is write-only"),
+            AccessAttribute::ReadSideEffects => write!(f, "has side effects on reads"),
+        }
+    }
 }
 
 /// Holds information about a specific, individual flash
@@ -121,10 +337,213 @@ pub enum MemoryRegion {
     Nvm(NvmRegion),
 }
 
+/// Returns the generic regions of `memory_map` which intersect `range`.
+fn generic_regions(
+    memory_map: &[MemoryRegion],
+    range: Range<u32>,
+) -> impl Iterator<Item = &GenericRegion> {
+    memory_map.iter().filter_map(move |region| match region {
+        MemoryRegion::Generic(region) if region.range.intersects_range(&range) => Some(region),
+        _ => None,
+    })
+}
+
+/// Returns the size of the accesses for `len` bytes at `address`, if a region of
+/// `memory_map` restricts the access sizes, see [MemoryAccess::allowed_size].
+///
+/// Returns [Error::MemoryAccessViolation](crate::Error::MemoryAccessViolation) if the
+/// access is not possible in one of the regions.
+pub(crate) fn restricted_access_size(
+    memory_map: &[MemoryRegion],
+    address: u32,
+    len: usize,
+    requested: Option<AccessSize>,
+    write: bool,
+) -> Result<Option<AccessSize>, crate::Error> {
+    if len == 0 {
+        return Ok(None);
+    }
+
+    let range = address..address.saturating_add(len as u32);
+    let mut restricted = None;
+    for region in generic_regions(memory_map, range) {
+        let size = region
+            .access
+            .allowed_size(address, len, requested, write)
+            .map_err(|attribute| crate::Error::MemoryAccessViolation {
+                address,
+                region: region.to_string(),
+                attribute,
+            })?;
+        restricted = restricted.or(size);
+    }
+
+    Ok(restricted)
+}
+
+/// Checks that the regions of `memory_map` allow accesses of exactly `size`
+/// for `len` bytes at `address`.
+pub(crate) fn check_access_size(
+    memory_map: &[MemoryRegion],
+    address: u32,
+    len: usize,
+    size: AccessSize,
+    write: bool,
+) -> Result<(), crate::Error> {
+    if len == 0 {
+        return Ok(());
+    }
+
+    let range = address..address.saturating_add(len as u32);
+    for region in generic_regions(memory_map, range) {
+        let access = &region.access;
+        let attribute = if !write && access.write_only {
+            AccessAttribute::WriteOnly
+        } else if !access.sizes.is_empty() && !access.sizes.contains(&size) {
+            AccessAttribute::Sizes(access.sizes.to_vec())
+        } else {
+            continue;
+        };
+
+        return Err(crate::Error::MemoryAccessViolation {
+            address,
+            region: region.to_string(),
+            attribute,
+        });
+    }
+
+    Ok(())
+}
+
+/// Returns the first region of `memory_map` with side effects on reads which
+/// intersects the `len` bytes at `address`.
+pub(crate) fn read_side_effect_region(
+    memory_map: &[MemoryRegion],
+    address: u32,
+    len: usize,
+) -> Option<&GenericRegion> {
+    if len == 0 {
+        return None;
+    }
+
+    let range = address..address.saturating_add(len as u32);
+    generic_regions(memory_map, range).find(|region| region.access.read_side_effects)
+}
+
+/// Returns the first region of `memory_map` which intersects the `len` bytes at `address`
+/// and can not be read, either not at all or not while the core is `halted`.
+pub(crate) fn unreadable_region(
+    memory_map: &[MemoryRegion],
+    address: u32,
+    len: usize,
+    halted: bool,
+) -> Option<&GenericRegion> {
+    if len == 0 {
+        return None;
+    }
+
+    let range = address..address.saturating_add(len as u32);
+    generic_regions(memory_map, range).find(|region| {
+        region.access.write_only || (halted && region.access.not_readable_while_halted)
+    })
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    fn region_with_sectors(sectors: &'static [SectorDescription]) -> NvmRegion {
+        NvmRegion {
+            range: 0x0810_0000..0x0820_0000,
+            is_boot_memory: false,
+            is_otp: false,
+            erase_mode: EraseMode::Sector,
+            sectors: Cow::Borrowed(sectors),
+            memory_mapped: None,
+        }
+    }
+
+    #[test]
+    fn validate_sectors() {
+        assert!(region_with_sectors(&[]).validate_sectors().is_ok());
+        assert!(region_with_sectors(&[
+            SectorDescription {
+                size: 0x4000,
+                address: 0,
+            },
+            SectorDescription {
+                size: 0x2_0000,
+                address: 0x2_0000,
+            },
+        ])
+        .validate_sectors()
+        .is_ok());
+
+        // Does not start at the beginning of the region
+        assert!(region_with_sectors(&[SectorDescription {
+            size: 0x4000,
+            address: 0x4000,
+        }])
+        .validate_sectors()
+        .is_err());
+
+        // Not sorted
+        assert!(region_with_sectors(&[
+            SectorDescription {
+                size: 0x4000,
+                address: 0,
+            },
+            SectorDescription {
+                size: 0x2_0000,
+                address: 0x2_0000,
+            },
+            SectorDescription {
+                size: 0x1_0000,
+                address: 0x1_0000,
+            },
+        ])
+        .validate_sectors()
+        .is_err());
+    }
+
+    #[test]
+    fn deserialize_memory_mapped_region() {
+        let region: NvmRegion = serde_yaml::from_str(
+            "
+range:
+  start: 0x90000000
+  end: 0x91000000
+is_boot_memory: false
+memory_mapped:
+  pre_algorithm_sequence:
+    - address: 0xA0001000
+      value: 0x2
+      mask: 0x2
+  restore_sequence:
+    - address: 0xA0001014
+      value: 0x0C000000
+",
+        )
+        .unwrap();
+
+        assert_eq!(
+            region.memory_mapped,
+            Some(MemoryMappedAccess {
+                pre_algorithm_sequence: Cow::Owned(vec![RegisterWrite {
+                    address: 0xA000_1000,
+                    value: 0x2,
+                    mask: 0x2,
+                }]),
+                restore_sequence: Cow::Owned(vec![RegisterWrite {
+                    address: 0xA000_1014,
+                    value: 0x0C00_0000,
+                    mask: 0xFFFF_FFFF,
+                }]),
+            })
+        );
+        assert!(region_with_sectors(&[]).memory_mapped.is_none());
+    }
+
     #[test]
     fn contains_range1() {
         let range1 = 0..1;
@@ -229,4 +648,158 @@ mod test {
         let range2 = 6..8;
         assert!(!range1.intersects_range(&range2));
     }
+
+    fn peripheral_map(access: MemoryAccess) -> Vec<MemoryRegion> {
+        vec![
+            MemoryRegion::Ram(RamRegion {
+                range: 0x2000_0000..0x2001_0000,
+                is_boot_memory: false,
+                core_index: 0,
+            }),
+            MemoryRegion::Generic(GenericRegion {
+                range: 0x4000_0000..0x4000_1000,
+                name: Some("UART0".into()),
+                access,
+            }),
+        ]
+    }
+
+    #[test]
+    fn deserialize_access_attributes() {
+        let region: GenericRegion = serde_yaml::from_str(
+            "
+range:
+  start: 0x40000000
+  end: 0x40001000
+name: UART0
+access:
+  sizes: [u32, u16]
+  read_side_effects: true
+",
+        )
+        .unwrap();
+
+        assert_eq!(region.name.as_deref(), Some("UART0"));
+        assert_eq!(
+            region.access,
+            MemoryAccess {
+                sizes: Cow::Owned(vec![AccessSize::U32, AccessSize::U16]),
+                read_side_effects: true,
+                write_only: false,
+                not_readable_while_halted: false,
+            }
+        );
+
+        let region: GenericRegion =
+            serde_yaml::from_str("range:\n  start: 0x40000000\n  end: 0x40001000\n").unwrap();
+        assert!(region.access.is_unrestricted());
+    }
+
+    #[test]
+    fn restricted_access_size_outside_of_regions() {
+        let map = peripheral_map(MemoryAccess {
+            sizes: Cow::Borrowed(&[AccessSize::U32]),
+            ..Default::default()
+        });
+
+        assert_eq!(
+            restricted_access_size(&map, 0x2000_0001, 3, None, true).unwrap(),
+            None
+        );
+        assert_eq!(
+            restricted_access_size(&map, 0x3fff_fffc, 4, None, false).unwrap(),
+            None
+        );
+        assert_eq!(
+            restricted_access_size(&map, 0x3fff_fffc, 8, None, false).unwrap(),
+            Some(AccessSize::U32)
+        );
+    }
+
+    #[test]
+    fn restricted_access_size_picks_largest_aligned_size() {
+        let map = peripheral_map(MemoryAccess {
+            sizes: Cow::Borrowed(&[AccessSize::U16, AccessSize::U32]),
+            ..Default::default()
+        });
+
+        // A byte write can not be made with any of the allowed sizes.
+        assert!(restricted_access_size(&map, 0x4000_0000, 1, Some(AccessSize::U8), true).is_err());
+        assert_eq!(
+            restricted_access_size(&map, 0x4000_0000, 8, None, true).unwrap(),
+            Some(AccessSize::U32)
+        );
+        assert_eq!(
+            restricted_access_size(&map, 0x4000_0002, 6, None, true).unwrap(),
+            Some(AccessSize::U16)
+        );
+        assert_eq!(
+            restricted_access_size(&map, 0x4000_0004, 4, Some(AccessSize::U32), true).unwrap(),
+            None
+        );
+        // Reads are widened to the smallest allowed size.
+        assert_eq!(
+            restricted_access_size(&map, 0x4000_0001, 1, Some(AccessSize::U8), false).unwrap(),
+            Some(AccessSize::U16)
+        );
+    }
+
+    #[test]
+    fn access_violations_name_region_and_attribute() {
+        let map = peripheral_map(MemoryAccess {
+            sizes: Cow::Borrowed(&[AccessSize::U32]),
+            write_only: true,
+            ..Default::default()
+        });
+
+        let error = restricted_access_size(&map, 0x4000_0010, 4, None, false).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The access at 0x40000010 is not possible, the memory region UART0 (0x40000000..0x40001000) is write-only"
+        );
+
+        let error = restricted_access_size(&map, 0x4000_0011, 1, None, true).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The access at 0x40000011 is not possible, the memory region UART0 (0x40000000..0x40001000) only allows 32 bit accesses"
+        );
+
+        assert!(check_access_size(&map, 0x4000_0010, 4, AccessSize::U32, true).is_ok());
+        assert!(check_access_size(&map, 0x4000_0010, 4, AccessSize::U8, true).is_err());
+        assert!(check_access_size(&map, 0x4000_0010, 4, AccessSize::U32, false).is_err());
+    }
+
+    #[test]
+    fn read_side_effect_regions() {
+        let map = peripheral_map(MemoryAccess {
+            read_side_effects: true,
+            ..Default::default()
+        });
+
+        assert!(read_side_effect_region(&map, 0x2000_0000, 0x100).is_none());
+        assert!(read_side_effect_region(&map, 0x4000_1000, 4).is_none());
+        assert_eq!(
+            read_side_effect_region(&map, 0x3fff_fff0, 0x20)
+                .and_then(|region| region.name.as_deref()),
+            Some("UART0")
+        );
+    }
+
+    #[test]
+    fn unreadable_regions() {
+        let map = peripheral_map(MemoryAccess {
+            not_readable_while_halted: true,
+            ..Default::default()
+        });
+
+        assert!(unreadable_region(&map, 0x4000_0000, 4, false).is_none());
+        assert!(unreadable_region(&map, 0x2000_0000, 0x100, true).is_none());
+        assert!(unreadable_region(&map, 0x4000_0ffc, 4, true).is_some());
+
+        let map = peripheral_map(MemoryAccess {
+            write_only: true,
+            ..Default::default()
+        });
+        assert!(unreadable_region(&map, 0x4000_0000, 4, false).is_some());
+    }
 }
@@ -22,24 +22,44 @@
 //! be used to read targets from a YAML file.
 //!
 
+mod bank_swap;
 mod chip;
 mod chip_family;
 mod chip_info;
+mod core_clock;
+mod debug_sequence;
 mod flash_algorithm;
 mod flash_properties;
+mod freeze;
+mod low_power_debug;
 mod memory;
+mod option_bytes;
 mod registry;
 mod target;
 
-pub use chip::Chip;
-pub use chip_family::ChipFamily;
-pub use flash_algorithm::{FlashAlgorithm, RawFlashAlgorithm};
+pub use bank_swap::BankSwap;
+pub use chip::{Chip, ChipCore};
+pub use chip_family::{ChipFamily, UnlockSequence};
+pub use chip_info::{ChipInfo, TargetDetection};
+pub use core_clock::{ClockFactor, CoreClock};
+pub use debug_sequence::{DebugSequence, SequenceStep};
+pub use flash_algorithm::{FlashAlgorithm, RawFlashAlgorithm, RegisterWrite};
 pub use flash_properties::FlashProperties;
-pub use memory::{MemoryRegion, NvmRegion, PageInfo, RamRegion, SectorDescription, SectorInfo};
-pub use registry::{add_target_from_yaml, families, get_target_by_name, RegistryError};
+pub use freeze::{DebugFreeze, FreezeBit, WatchdogFreeze};
+pub use low_power_debug::LowPowerDebug;
+pub use memory::{
+    AccessAttribute, EraseMode, GenericRegion, MemoryAccess, MemoryMappedAccess, MemoryRegion,
+    NvmRegion, PageInfo, RamRegion, SectorDescription, SectorInfo,
+};
+pub use option_bytes::{OptionBytes, OptionField, StatusBit};
+pub use registry::{
+    add_target_from_yaml, detect_chips, families, get_target_by_name, RegistryError,
+};
 pub use target::{Target, TargetParseError, TargetSelector};
 
 // Crate-internal API
-pub(crate) use chip_info::ChipInfo;
-pub(crate) use memory::MemoryRange;
+pub(crate) use memory::{
+    check_access_size, read_side_effect_region, restricted_access_size, unreadable_region,
+    MemoryRange,
+};
 pub(crate) use registry::get_target_by_chip_info;
@@ -0,0 +1,100 @@
+use super::flash_algorithm::RegisterWrite;
+use std::borrow::Cow;
+
+use serde::{Deserialize, Serialize};
+
+/// A named field of the option bytes, e.g. the read protection level `RDP`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OptionField {
+    /// The name of the field, as used in the reference manual of the chip.
+    pub name: Cow<'static, str>,
+    /// The address of the register which contains the field.
+    pub address: u32,
+    /// The bits of the register which belong to the field.
+    pub mask: u32,
+}
+
+impl OptionField {
+    /// Extracts the value of the field from the `register` value.
+    pub fn extract(&self, register: u32) -> u32 {
+        (register & self.mask) >> self.mask.trailing_zeros()
+    }
+
+    /// Returns the register value with the field set to `value`,
+    /// or `None` if `value` does not fit into the field.
+    pub fn insert(&self, register: u32, value: u32) -> Option<u32> {
+        let shifted = value.checked_shl(self.mask.trailing_zeros())?;
+
+        if shifted & !self.mask != 0 || shifted >> self.mask.trailing_zeros() != value {
+            return None;
+        }
+
+        Some((register & !self.mask) | shifted)
+    }
+}
+
+/// A single bit of a status register.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StatusBit {
+    /// The address of the register.
+    pub address: u32,
+    /// The bit, or bits, which are checked.
+    pub mask: u32,
+}
+
+/// Describes the option bytes of a chip family, and how they are programmed.
+///
+/// The option bytes are programmed by first running the `unlock` sequence,
+/// if the `lock` bit is set, then modifying the fields, running the `program`
+/// sequence and waiting until the `busy` bit is cleared. The `launch` sequence then
+/// loads the new option bytes, which usually resets the chip.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OptionBytes {
+    /// The fields of the option bytes.
+    pub fields: Cow<'static, [OptionField]>,
+    /// The bit which is set while the option bytes can not be modified.
+    #[serde(default)]
+    pub lock: Option<StatusBit>,
+    /// The register writes which unlock the option bytes, e.g. writing the keys to `FLASH_OPTKEYR`.
+    #[serde(default)]
+    pub unlock: Cow<'static, [RegisterWrite]>,
+    /// The register writes which start programming the modified option bytes.
+    #[serde(default)]
+    pub program: Cow<'static, [RegisterWrite]>,
+    /// The bit which is set while the option bytes are being programmed.
+    #[serde(default)]
+    pub busy: Option<StatusBit>,
+    /// The register writes which load the programmed option bytes.
+    ///
+    /// This usually resets the chip, so the debug connection is set up again afterwards.
+    #[serde(default)]
+    pub launch: Cow<'static, [RegisterWrite]>,
+}
+
+impl OptionBytes {
+    /// Returns the field with the given name, ignoring the case.
+    pub fn field(&self, name: &str) -> Option<&OptionField> {
+        self.fields
+            .iter()
+            .find(|field| field.name.eq_ignore_ascii_case(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OptionField;
+    use std::borrow::Cow;
+
+    #[test]
+    fn fields_are_shifted_by_their_mask() {
+        let rdp = OptionField {
+            name: Cow::Borrowed("RDP"),
+            address: 0x4002_3C14,
+            mask: 0x0000_FF00,
+        };
+
+        assert_eq!(rdp.extract(0x0FFF_AAED), 0xAA);
+        assert_eq!(rdp.insert(0x0FFF_55ED, 0xAA), Some(0x0FFF_AAED));
+        assert_eq!(rdp.insert(0x0FFF_55ED, 0x1AA), None);
+    }
+}
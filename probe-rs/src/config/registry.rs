@@ -2,7 +2,8 @@
 
 use super::target::Target;
 use crate::config::{Chip, ChipFamily, ChipInfo};
-use crate::core::CoreType;
+use crate::core::{CoreType, ResetType};
+use jep106::JEP106Code;
 use lazy_static::lazy_static;
 use std::fs::File;
 use std::path::Path;
@@ -28,6 +29,18 @@ pub enum RegistryError {
     /// no matching chip was found in the registry.
     #[error("The connected chip could not automatically be determined.")]
     ChipAutodetectFailed,
+    /// When searching for a chip based on information read from the target,
+    /// multiple chips in the registry matched. One of them has to be selected by name.
+    #[error(
+        "The connected chip ({chip}) matches several known targets, select one of them: {}",
+        .candidates.join(", ")
+    )]
+    AmbiguousChip {
+        /// The identification of the connected chip.
+        chip: String,
+        /// The names of all matching chips.
+        candidates: Vec<String>,
+    },
     /// A core type contained in a target description is not supported
     /// in probe-rs.
     #[error("The core type '{0}' is not supported in probe-rs.")]
@@ -58,9 +71,22 @@ const GENERIC_TARGETS: [ChipFamily; 6] = [
             part: None,
             memory_map: Cow::Borrowed(&[]),
             flash_algorithms: Cow::Borrowed(&[]),
+            cores: Cow::Borrowed(&[]),
         }]),
         flash_algorithms: Cow::Borrowed(&[]),
         core: Cow::Borrowed("M0"),
+        unlock_sequence: None,
+        option_bytes: None,
+        recommended_speed_khz: None,
+        debug_vars: None,
+        debug_sequences: Cow::Borrowed(&[]),
+        reset_types: Cow::Borrowed(&[]),
+        default_reset_type: None,
+        watchdog_freeze: None,
+        debug_freeze: None,
+        low_power_debug: None,
+        bank_swap: None,
+        core_clock: None,
     },
     ChipFamily {
         name: Cow::Borrowed("Generic Cortex-M4"),
@@ -70,9 +96,22 @@ const GENERIC_TARGETS: [ChipFamily; 6] = [
             part: None,
             memory_map: Cow::Borrowed(&[]),
             flash_algorithms: Cow::Borrowed(&[]),
+            cores: Cow::Borrowed(&[]),
         }]),
         flash_algorithms: Cow::Borrowed(&[]),
         core: Cow::Borrowed("M4"),
+        unlock_sequence: None,
+        option_bytes: None,
+        recommended_speed_khz: None,
+        debug_vars: None,
+        debug_sequences: Cow::Borrowed(&[]),
+        reset_types: Cow::Borrowed(&[]),
+        default_reset_type: None,
+        watchdog_freeze: None,
+        debug_freeze: None,
+        low_power_debug: None,
+        bank_swap: None,
+        core_clock: None,
     },
     ChipFamily {
         name: Cow::Borrowed("Generic Cortex-M3"),
@@ -82,9 +121,22 @@ const GENERIC_TARGETS: [ChipFamily; 6] = [
             part: None,
             memory_map: Cow::Borrowed(&[]),
             flash_algorithms: Cow::Borrowed(&[]),
+            cores: Cow::Borrowed(&[]),
         }]),
         flash_algorithms: Cow::Borrowed(&[]),
         core: Cow::Borrowed("M3"),
+        unlock_sequence: None,
+        option_bytes: None,
+        recommended_speed_khz: None,
+        debug_vars: None,
+        debug_sequences: Cow::Borrowed(&[]),
+        reset_types: Cow::Borrowed(&[]),
+        default_reset_type: None,
+        watchdog_freeze: None,
+        debug_freeze: None,
+        low_power_debug: None,
+        bank_swap: None,
+        core_clock: None,
     },
     ChipFamily {
         name: Cow::Borrowed("Generic Cortex-M33"),
@@ -94,9 +146,22 @@ const GENERIC_TARGETS: [ChipFamily; 6] = [
             part: None,
             memory_map: Cow::Borrowed(&[]),
             flash_algorithms: Cow::Borrowed(&[]),
+            cores: Cow::Borrowed(&[]),
         }]),
         flash_algorithms: Cow::Borrowed(&[]),
         core: Cow::Borrowed("M33"),
+        unlock_sequence: None,
+        option_bytes: None,
+        recommended_speed_khz: None,
+        debug_vars: None,
+        debug_sequences: Cow::Borrowed(&[]),
+        reset_types: Cow::Borrowed(&[]),
+        default_reset_type: None,
+        watchdog_freeze: None,
+        debug_freeze: None,
+        low_power_debug: None,
+        bank_swap: None,
+        core_clock: None,
     },
     ChipFamily {
         name: Cow::Borrowed("Generic Cortex-M7"),
@@ -106,9 +171,22 @@ const GENERIC_TARGETS: [ChipFamily; 6] = [
             part: None,
             memory_map: Cow::Borrowed(&[]),
             flash_algorithms: Cow::Borrowed(&[]),
+            cores: Cow::Borrowed(&[]),
         }]),
         flash_algorithms: Cow::Borrowed(&[]),
         core: Cow::Borrowed("M7"),
+        unlock_sequence: None,
+        option_bytes: None,
+        recommended_speed_khz: None,
+        debug_vars: None,
+        debug_sequences: Cow::Borrowed(&[]),
+        reset_types: Cow::Borrowed(&[]),
+        default_reset_type: None,
+        watchdog_freeze: None,
+        debug_freeze: None,
+        low_power_debug: None,
+        bank_swap: None,
+        core_clock: None,
     },
     ChipFamily {
         name: Cow::Borrowed("Generic Riscv"),
@@ -118,9 +196,22 @@ const GENERIC_TARGETS: [ChipFamily; 6] = [
             part: None,
             memory_map: Cow::Borrowed(&[]),
             flash_algorithms: Cow::Borrowed(&[]),
+            cores: Cow::Borrowed(&[]),
         }]),
         flash_algorithms: Cow::Borrowed(&[]),
         core: Cow::Borrowed("riscv"),
+        unlock_sequence: None,
+        option_bytes: None,
+        recommended_speed_khz: None,
+        debug_vars: None,
+        debug_sequences: Cow::Borrowed(&[]),
+        reset_types: Cow::Borrowed(&[]),
+        default_reset_type: None,
+        watchdog_freeze: None,
+        debug_freeze: None,
+        low_power_debug: None,
+        bank_swap: None,
+        core_clock: None,
     },
 ];
 
@@ -192,45 +283,69 @@ impl Registry {
     }
 
     fn get_target_by_chip_info(&self, chip_info: ChipInfo) -> Result<Target, RegistryError> {
-        let (family, chip) = {
-            match chip_info {
-                ChipInfo::Arm(chip_info) => {
-                    // Try get the corresponding chip.
+        let mut identified_chips = self.detect_chips(&chip_info);
 
-                    let families = self.families.iter().filter(|f| {
-                        f.manufacturer
-                            .map(|m| m == chip_info.manufacturer)
-                            .unwrap_or(false)
-                    });
+        let (family, chip) = match identified_chips.len() {
+            0 => {
+                log::debug!("Found no matching chip for information {:?}", chip_info);
+                return Err(RegistryError::ChipAutodetectFailed);
+            }
+            1 => identified_chips.pop().unwrap(),
+            _ => {
+                return Err(RegistryError::AmbiguousChip {
+                    chip: chip_info.to_string(),
+                    candidates: identified_chips
+                        .iter()
+                        .map(|(_, chip)| chip.name.to_string())
+                        .collect(),
+                })
+            }
+        };
+        self.get_target(family, chip)
+    }
 
-                    let mut identified_chips = Vec::new();
+    /// Find all chips matching the information read from a target.
+    ///
+    /// For ARM chips, the peripheral IDs of the ROM table are used. The TARGETID register
+    /// is only used if no chip matches the ROM table, because the part numbers in the
+    /// target descriptions are taken from the ROM table.
+    fn detect_chips(&self, chip_info: &ChipInfo) -> Vec<(&ChipFamily, &Chip)> {
+        match chip_info {
+            ChipInfo::Arm(info) => {
+                let chips = self.chips_with_part(info.manufacturer, info.part);
+
+                match info.target_id_designer_and_part() {
+                    Some((designer, part)) if chips.is_empty() => {
+                        self.chips_with_part(designer, part)
+                    }
+                    _ => chips,
+                }
+            }
+            ChipInfo::Riscv(info) => self.chips_with_part(info.manufacturer(), info.part()),
+        }
+    }
 
-                    for family in families {
-                        log::debug!("Checking family {}", family.name);
+    fn chips_with_part(&self, manufacturer: JEP106Code, part: u16) -> Vec<(&ChipFamily, &Chip)> {
+        let families = self
+            .families
+            .iter()
+            .filter(|f| f.manufacturer.map(|m| m == manufacturer).unwrap_or(false));
 
-                        let chips = family
-                            .variants()
-                            .iter()
-                            .filter(|v| v.part.map(|p| p == chip_info.part).unwrap_or(false))
-                            .map(|c| (family, c));
+        let mut identified_chips = Vec::new();
 
-                        identified_chips.extend(chips)
-                    }
+        for family in families {
+            log::debug!("Checking family {}", family.name);
 
-                    if identified_chips.len() == 1 {
-                        identified_chips.pop().unwrap()
-                    } else {
-                        log::debug!(
-                        "Found {} matching chips for information {:?}, unable to determine chip",
-                        identified_chips.len(),
-                        chip_info
-                    );
-                        return Err(RegistryError::ChipAutodetectFailed);
-                    }
-                }
-            }
-        };
-        self.get_target(family, chip)
+            let chips = family
+                .variants()
+                .iter()
+                .filter(|v| v.part.map(|p| p == part).unwrap_or(false))
+                .map(|c| (family, c));
+
+            identified_chips.extend(chips)
+        }
+
+        identified_chips
     }
 
     fn get_target(&self, family: &ChipFamily, chip: &Chip) -> Result<Target, RegistryError> {
@@ -251,13 +366,44 @@ impl Registry {
             .cloned()
             .collect();
 
-        Ok(Target::new(chip, chip_algorithms, core))
+        let mut target = Target::new(chip, chip_algorithms, core);
+        target.unlock_sequence = family.unlock_sequence;
+        target.option_bytes = family.option_bytes.clone();
+        target.recommended_speed_khz = family.recommended_speed_khz;
+        target.debug_vars = family.debug_vars.as_ref().map(|vars| vars.to_string());
+        target.debug_sequences = family.debug_sequences.to_vec();
+        target.watchdog_freeze = family.watchdog_freeze.clone();
+        target.debug_freeze = family.debug_freeze.clone();
+        target.low_power_debug = family.low_power_debug.clone();
+        target.bank_swap = family.bank_swap.clone();
+        target.core_clock = family.core_clock.clone();
+
+        if !family.reset_types.is_empty() {
+            target.reset_types = family.reset_types.to_vec();
+        }
+        target.default_reset_type = match &family.default_reset_type {
+            Some(reset_type) => reset_type.clone(),
+            None if target.reset_types.contains(&ResetType::SystemReset) => ResetType::SystemReset,
+            None => target.reset_types.first().cloned().unwrap_or_default(),
+        };
+
+        Ok(target)
     }
 
     fn add_target_from_yaml(&mut self, path_to_yaml: &Path) -> Result<(), RegistryError> {
         let file = File::open(path_to_yaml)?;
         let chip = ChipFamily::from_yaml_reader(file)?;
 
+        if let Err(errors) = chip.validate_debug_sequences() {
+            for error in errors {
+                log::warn!("{}: {}", chip.name, error);
+            }
+        }
+
+        if let Err(error) = chip.validate_reset_types() {
+            log::warn!("{}: {}", chip.name, error);
+        }
+
         let index = self
             .families
             .iter()
@@ -281,6 +427,17 @@ pub(crate) fn get_target_by_chip_info(chip_info: ChipInfo) -> Result<Target, Reg
     REGISTRY.try_lock()?.get_target_by_chip_info(chip_info)
 }
 
+/// Find the names of all chips in the internal registry which match
+/// the [ChipInfo] read from a target.
+pub fn detect_chips(chip_info: &ChipInfo) -> Result<Vec<String>, RegistryError> {
+    Ok(REGISTRY
+        .try_lock()?
+        .detect_chips(chip_info)
+        .iter()
+        .map(|(_, chip)| chip.name.to_string())
+        .collect())
+}
+
 /// Parse a target description file and add the contained targets
 /// to the internal target registry.
 pub fn add_target_from_yaml(path_to_yaml: &Path) -> Result<(), RegistryError> {
@@ -296,6 +453,7 @@ pub fn families() -> Result<Vec<ChipFamily>, RegistryError> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::architecture::{arm::ArmChipInfo, riscv::communication_interface::RiscvChipInfo};
 
     #[test]
     fn try_fetch1() {
@@ -320,4 +478,131 @@ mod tests {
         let registry = Registry::from_builtin_families();
         assert!(registry.get_target_by_name("nrf51822_Xxaa").is_ok());
     }
+
+    fn family_with_chips(name: &'static str, chips: Vec<Chip>) -> ChipFamily {
+        ChipFamily {
+            name: Cow::Borrowed(name),
+            manufacturer: Some(JEP106Code::new(2, 0x44)),
+            variants: Cow::Owned(chips),
+            flash_algorithms: Cow::Borrowed(&[]),
+            core: Cow::Borrowed("M4"),
+            unlock_sequence: None,
+            option_bytes: None,
+            recommended_speed_khz: None,
+            debug_vars: None,
+            debug_sequences: Cow::Borrowed(&[]),
+            reset_types: Cow::Borrowed(&[]),
+            default_reset_type: None,
+            watchdog_freeze: None,
+            debug_freeze: None,
+            low_power_debug: None,
+            bank_swap: None,
+            core_clock: None,
+        }
+    }
+
+    fn chip(name: &'static str, part: u16) -> Chip {
+        Chip {
+            name: Cow::Borrowed(name),
+            part: Some(part),
+            memory_map: Cow::Borrowed(&[]),
+            flash_algorithms: Cow::Borrowed(&[]),
+            cores: Cow::Borrowed(&[]),
+        }
+    }
+
+    fn test_registry() -> Registry {
+        Registry {
+            families: vec![
+                family_with_chips("First", vec![chip("first_a", 0x10), chip("first_b", 0x20)]),
+                family_with_chips("Second", vec![chip("second", 0x20)]),
+            ],
+        }
+    }
+
+    fn arm_info(part: u16, target_id: Option<u32>) -> ChipInfo {
+        ChipInfo::Arm(ArmChipInfo {
+            manufacturer: JEP106Code::new(2, 0x44),
+            part,
+            dpidr: None,
+            target_id,
+        })
+    }
+
+    #[test]
+    fn detect_unique_chip() {
+        let registry = test_registry();
+
+        let target = registry
+            .get_target_by_chip_info(arm_info(0x10, None))
+            .unwrap();
+
+        assert_eq!(target.name, "first_a");
+    }
+
+    #[test]
+    fn detect_ambiguous_chip_lists_candidates() {
+        let registry = test_registry();
+
+        match registry.get_target_by_chip_info(arm_info(0x20, None)) {
+            Err(RegistryError::AmbiguousChip { candidates, .. }) => {
+                assert_eq!(candidates, vec!["first_b", "second"])
+            }
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn detect_chip_from_target_id() {
+        let registry = test_registry();
+
+        // Designer 0x144, part 0x10, revision 1
+        let target_id = (1 << 28) | (0x10 << 12) | (0x144 << 1) | 1;
+
+        let chips = registry.detect_chips(&arm_info(0x99, Some(target_id)));
+
+        assert_eq!(chips.len(), 1);
+        assert_eq!(chips[0].1.name, "first_a");
+    }
+
+    #[test]
+    fn detect_riscv_chip_from_idcode() {
+        let registry = test_registry();
+
+        let info = ChipInfo::Riscv(RiscvChipInfo {
+            idcode: (0x10 << 12) | (0x144 << 1) | 1,
+            marchid: None,
+            mimpid: None,
+        });
+
+        let chips = registry.detect_chips(&info);
+
+        assert_eq!(chips.len(), 1);
+        assert_eq!(chips[0].1.name, "first_a");
+    }
+
+    #[test]
+    fn reset_types_of_target() {
+        let mut family = family_with_chips("First", vec![chip("first_a", 0x10)]);
+        let registry = Registry {
+            families: vec![family.clone()],
+        };
+
+        let target = registry.get_target_by_name("first_a").unwrap();
+        assert_eq!(target.reset_types, ResetType::defaults_for(CoreType::M4));
+        assert_eq!(target.default_reset_type, ResetType::SystemReset);
+
+        // Without a default, the first reset type of the family is used
+        family.reset_types = Cow::Borrowed(&[ResetType::CoreOnly, ResetType::HardwarePin]);
+        let registry = Registry {
+            families: vec![family],
+        };
+
+        let target = registry.get_target_by_name("first_a").unwrap();
+        assert_eq!(
+            target.reset_types,
+            vec![ResetType::CoreOnly, ResetType::HardwarePin]
+        );
+        assert_eq!(target.default_reset_type, ResetType::CoreOnly);
+    }
 }
@@ -1,7 +1,14 @@
-use super::chip::Chip;
+use super::bank_swap::BankSwap;
+use super::chip::{Chip, ChipCore};
+use super::chip_family::UnlockSequence;
+use super::core_clock::CoreClock;
+use super::debug_sequence::DebugSequence;
 use super::flash_algorithm::RawFlashAlgorithm;
+use super::freeze::{DebugFreeze, WatchdogFreeze};
+use super::low_power_debug::LowPowerDebug;
 use super::memory::MemoryRegion;
-use crate::core::{Architecture, CoreType};
+use super::option_bytes::OptionBytes;
+use crate::core::{Architecture, CoreType, ResetType};
 
 /// This describes a complete target with a fixed chip model and variant.
 #[derive(Clone)]
@@ -14,6 +21,35 @@ pub struct Target {
     pub core_type: CoreType,
     /// The memory map of the target.
     pub memory_map: Vec<MemoryRegion>,
+    /// The cores of the target, or an empty list for a target with a single core on AP 0.
+    pub cores: Vec<ChipCore>,
+    /// The sequence which unlocks a read protected target, if one is known.
+    pub unlock_sequence: Option<UnlockSequence>,
+    /// The option bytes of the target, if they can be configured.
+    pub option_bytes: Option<OptionBytes>,
+    /// The SWD/JTAG speed in kHz recommended for the target, if there is one.
+    pub recommended_speed_khz: Option<u32>,
+    /// The declarations of the variables shared by the debug sequences.
+    pub debug_vars: Option<String>,
+    /// The debug sequences of the target.
+    pub debug_sequences: Vec<DebugSequence>,
+    /// The reset types which can be used with the target.
+    pub reset_types: Vec<ResetType>,
+    /// The reset type which is used unless another one is selected
+    /// with [Session::set_reset_type](crate::Session::set_reset_type).
+    pub default_reset_type: ResetType,
+    /// The register writes which stop the watchdogs of the target while a core is halted,
+    /// if they are known.
+    pub watchdog_freeze: Option<WatchdogFreeze>,
+    /// The debug freeze bits of the peripherals of the target, if they are known.
+    pub debug_freeze: Option<DebugFreeze>,
+    /// The register writes which keep the debug port of the target powered in its low power modes,
+    /// if they are known.
+    pub low_power_debug: Option<LowPowerDebug>,
+    /// How the flash banks of the target are swapped, if they can be.
+    pub bank_swap: Option<BankSwap>,
+    /// How the core clock of the target is derived from its input clock, if it is known.
+    pub core_clock: Option<CoreClock>,
 }
 
 impl std::fmt::Debug for Target {
@@ -45,6 +81,19 @@ impl Target {
             flash_algorithms,
             core_type,
             memory_map: chip.memory_map.clone().into_owned(),
+            cores: chip.cores.to_vec(),
+            unlock_sequence: None,
+            option_bytes: None,
+            recommended_speed_khz: None,
+            debug_vars: None,
+            debug_sequences: Vec::new(),
+            reset_types: ResetType::defaults_for(core_type),
+            default_reset_type: ResetType::SystemReset,
+            watchdog_freeze: None,
+            debug_freeze: None,
+            low_power_debug: None,
+            bank_swap: None,
+            core_clock: None,
         }
     }
 
@@ -2,4 +2,7 @@ use crate::DebugProbeError;
 
 pub trait CommunicationInterface {
     fn flush(&mut self) -> Result<(), DebugProbeError>;
+
+    /// Resets the target with the reset pin of the debug probe.
+    fn target_reset(&mut self) -> Result<(), DebugProbeError>;
 }
@@ -2,15 +2,20 @@ pub(crate) mod communication_interface;
 
 pub use communication_interface::CommunicationInterface;
 
+use crate::config::{
+    check_access_size, read_side_effect_region, restricted_access_size, GenericRegion, MemoryRegion,
+};
 use crate::error;
 use crate::DebugProbeError;
 use crate::{
     architecture::{
         arm::core::CortexState, riscv::communication_interface::RiscvCommunicationInterface,
     },
-    Error, Memory, MemoryInterface,
+    AccessSize, Error, Memory, MemoryInterface,
 };
 use anyhow::{anyhow, Result};
+use std::borrow::Cow;
+use std::convert::TryInto;
 use std::time::Duration;
 
 pub trait CoreRegister: Clone + From<u32> + Into<u32> + Sized + std::fmt::Debug {
@@ -155,6 +160,21 @@ pub trait CoreInterface: MemoryInterface {
     /// [`reset`]: Core::reset
     fn reset_and_halt(&mut self, timeout: Duration) -> Result<CoreInformation, error::Error>;
 
+    /// Reset only the core, without the rest of the system, and then continue
+    /// to execute instructions.
+    fn reset_core(&mut self) -> Result<(), error::Error>;
+
+    /// Reset the target with the reset pin of the debug probe.
+    fn reset_hardware_pin(&mut self) -> Result<(), error::Error>;
+
+    /// Request the core to halt directly after the next reset.
+    fn reset_catch_set(&mut self) -> Result<(), error::Error>;
+
+    /// Undo the settings of [`reset_catch_set`].
+    ///
+    /// [`reset_catch_set`]: CoreInterface::reset_catch_set
+    fn reset_catch_clear(&mut self) -> Result<(), error::Error>;
+
     /// Steps one instruction and then enters halted state again.
     fn step(&mut self) -> Result<CoreInformation, error::Error>;
 
@@ -162,6 +182,32 @@ pub trait CoreInterface: MemoryInterface {
 
     fn write_core_reg(&mut self, address: CoreRegisterAddress, value: u32) -> Result<()>;
 
+    /// Read several core registers, see [Core::read_core_regs].
+    ///
+    /// By default, the registers are read one after the other.
+    fn read_core_regs(
+        &mut self,
+        addresses: &[CoreRegisterAddress],
+    ) -> Vec<Result<u32, error::Error>> {
+        addresses
+            .iter()
+            .map(|&address| self.read_core_reg(address))
+            .collect()
+    }
+
+    /// Write several core registers, see [Core::write_core_regs].
+    ///
+    /// By default, the registers are written one after the other.
+    fn write_core_regs(
+        &mut self,
+        values: &[(CoreRegisterAddress, u32)],
+    ) -> Vec<Result<(), error::Error>> {
+        values
+            .iter()
+            .map(|&(address, value)| Ok(self.write_core_reg(address, value)?))
+            .collect()
+    }
+
     fn get_available_breakpoint_units(&mut self) -> Result<u32, error::Error>;
 
     fn enable_breakpoints(&mut self, state: bool) -> Result<(), error::Error>;
@@ -180,35 +226,160 @@ pub trait CoreInterface: MemoryInterface {
 
 impl<'probe> MemoryInterface for Core<'probe> {
     fn read_word_32(&mut self, address: u32) -> Result<u32, Error> {
-        self.inner.read_word_32(address)
+        match self.restricted_size(address, 4, Some(AccessSize::U32), false)? {
+            Some(size) => {
+                let mut bytes = [0; 4];
+                self.read_widened(address, &mut bytes, size)?;
+                Ok(u32::from_le_bytes(bytes))
+            }
+            None => self.inner.read_word_32(address),
+        }
     }
 
     fn read_word_8(&mut self, address: u32) -> Result<u8, Error> {
-        self.inner.read_word_8(address)
+        match self.restricted_size(address, 1, Some(AccessSize::U8), false)? {
+            Some(size) => {
+                let mut bytes = [0; 1];
+                self.read_widened(address, &mut bytes, size)?;
+                Ok(bytes[0])
+            }
+            None => self.inner.read_word_8(address),
+        }
+    }
+
+    fn read_word_64(&mut self, address: u32) -> Result<u64, Error> {
+        match self.restricted_size(address, 8, Some(AccessSize::U64), false)? {
+            Some(size) => {
+                let mut bytes = [0; 8];
+                self.read_widened(address, &mut bytes, size)?;
+                Ok(u64::from_le_bytes(bytes))
+            }
+            None => self.inner.read_word_64(address),
+        }
     }
 
     fn read_32(&mut self, address: u32, data: &mut [u32]) -> Result<(), Error> {
-        self.inner.read_32(address, data)
+        match self.restricted_size(address, data.len() * 4, Some(AccessSize::U32), false)? {
+            Some(size) => {
+                let mut bytes = vec![0; data.len() * 4];
+                self.read_widened(address, &mut bytes, size)?;
+
+                for (word, bytes) in data.iter_mut().zip(bytes.chunks_exact(4)) {
+                    *word = u32::from_le_bytes(bytes.try_into().unwrap());
+                }
+
+                Ok(())
+            }
+            None => self.inner.read_32(address, data),
+        }
     }
 
     fn read_8(&mut self, address: u32, data: &mut [u8]) -> Result<(), Error> {
-        self.inner.read_8(address, data)
+        match self.restricted_size(address, data.len(), None, false)? {
+            Some(size) => self.read_widened(address, data, size),
+            None => self.inner.read_8(address, data),
+        }
+    }
+
+    fn read_16(&mut self, address: u32, data: &mut [u16]) -> Result<(), Error> {
+        match self.restricted_size(address, data.len() * 2, Some(AccessSize::U16), false)? {
+            Some(size) => {
+                let mut bytes = vec![0; data.len() * 2];
+                self.read_widened(address, &mut bytes, size)?;
+
+                for (word, bytes) in data.iter_mut().zip(bytes.chunks_exact(2)) {
+                    *word = u16::from_le_bytes([bytes[0], bytes[1]]);
+                }
+
+                Ok(())
+            }
+            None => self.inner.read_16(address, data),
+        }
+    }
+
+    fn read_stream(&mut self, address: u32, data: &mut [u8], restart: bool) -> Result<(), Error> {
+        match self.restricted_size(address, data.len(), None, false)? {
+            Some(size) => self.read_widened(address, data, size),
+            None => self.inner.read_stream(address, data, restart),
+        }
     }
 
     fn write_word_32(&mut self, addr: u32, data: u32) -> Result<(), Error> {
-        self.inner.write_word_32(addr, data)
+        match self.restricted_size(addr, 4, Some(AccessSize::U32), true)? {
+            Some(size) => self
+                .inner
+                .write_mem_with_size(addr, &data.to_le_bytes(), size),
+            None => self.inner.write_word_32(addr, data),
+        }
     }
 
     fn write_word_8(&mut self, addr: u32, data: u8) -> Result<(), Error> {
-        self.inner.write_word_8(addr, data)
+        match self.restricted_size(addr, 1, Some(AccessSize::U8), true)? {
+            Some(size) => self.inner.write_mem_with_size(addr, &[data], size),
+            None => self.inner.write_word_8(addr, data),
+        }
+    }
+
+    fn write_word_64(&mut self, addr: u32, data: u64) -> Result<(), Error> {
+        match self.restricted_size(addr, 8, Some(AccessSize::U64), true)? {
+            Some(size) => self
+                .inner
+                .write_mem_with_size(addr, &data.to_le_bytes(), size),
+            None => self.inner.write_word_64(addr, data),
+        }
     }
 
     fn write_32(&mut self, addr: u32, data: &[u32]) -> Result<(), Error> {
-        self.inner.write_32(addr, data)
+        match self.restricted_size(addr, data.len() * 4, Some(AccessSize::U32), true)? {
+            Some(size) => {
+                let bytes: Vec<u8> = data
+                    .iter()
+                    .flat_map(|word| word.to_le_bytes().to_vec())
+                    .collect();
+                self.inner.write_mem_with_size(addr, &bytes, size)
+            }
+            None => self.inner.write_32(addr, data),
+        }
     }
 
     fn write_8(&mut self, addr: u32, data: &[u8]) -> Result<(), Error> {
-        self.inner.write_8(addr, data)
+        match self.restricted_size(addr, data.len(), None, true)? {
+            Some(size) => self.inner.write_mem_with_size(addr, data, size),
+            None => self.inner.write_8(addr, data),
+        }
+    }
+
+    fn write_16(&mut self, addr: u32, data: &[u16]) -> Result<(), Error> {
+        match self.restricted_size(addr, data.len() * 2, Some(AccessSize::U16), true)? {
+            Some(size) => {
+                let bytes: Vec<u8> = data
+                    .iter()
+                    .flat_map(|word| word.to_le_bytes().to_vec())
+                    .collect();
+                self.inner.write_mem_with_size(addr, &bytes, size)
+            }
+            None => self.inner.write_16(addr, data),
+        }
+    }
+
+    fn read_mem_with_size(
+        &mut self,
+        address: u32,
+        data: &mut [u8],
+        size: AccessSize,
+    ) -> Result<(), Error> {
+        check_access_size(self.memory_map, address, data.len(), size, false)?;
+        self.inner.read_mem_with_size(address, data, size)
+    }
+
+    fn write_mem_with_size(
+        &mut self,
+        addr: u32,
+        data: &[u8],
+        size: AccessSize,
+    ) -> Result<(), Error> {
+        check_access_size(self.memory_map, addr, data.len(), size, true)?;
+        self.inner.write_mem_with_size(addr, data, size)
     }
 
     fn flush(&mut self) -> Result<(), Error> {
@@ -216,7 +387,7 @@ impl<'probe> MemoryInterface for Core<'probe> {
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug)]
 pub enum CoreType {
     M3,
     M4,
@@ -251,6 +422,72 @@ impl CoreType {
     }
 }
 
+/// The method used to reset a target.
+///
+/// Which methods can be used depends on the target, see [Target::reset_types](crate::Target::reset_types).
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResetType {
+    /// Pulses the reset pin of the debug probe. This resets the complete target,
+    /// on most chips including the debug port.
+    HardwarePin,
+    /// Requests a reset of the complete system from the core, with `SYSRESETREQ`
+    /// on ARM and `ndmreset` on RISC-V, if the hart can not be reset on its own.
+    SystemReset,
+    /// Resets only the core, the peripherals keep their state.
+    /// This uses `VECTRESET` on ARMv7-M and `hartreset` on RISC-V.
+    CoreOnly,
+    /// Executes the debug sequence with the given name.
+    Custom(Cow<'static, str>),
+}
+
+impl ResetType {
+    /// The reset types which every core of the given type supports.
+    pub(crate) fn defaults_for(core_type: CoreType) -> Vec<ResetType> {
+        match core_type {
+            CoreType::M3 | CoreType::M4 | CoreType::M7 | CoreType::Riscv => vec![
+                ResetType::SystemReset,
+                ResetType::HardwarePin,
+                ResetType::CoreOnly,
+            ],
+            CoreType::M0 | CoreType::M33 => vec![ResetType::SystemReset, ResetType::HardwarePin],
+        }
+    }
+}
+
+impl Default for ResetType {
+    fn default() -> Self {
+        ResetType::SystemReset
+    }
+}
+
+impl std::fmt::Display for ResetType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ResetType::HardwarePin => write!(f, "hardware_pin"),
+            ResetType::SystemReset => write!(f, "system_reset"),
+            ResetType::CoreOnly => write!(f, "core_only"),
+            ResetType::Custom(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+impl std::str::FromStr for ResetType {
+    type Err = String;
+
+    /// Parses the names printed by the `Display` implementation. All other
+    /// names are treated as the name of a custom debug sequence.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_ascii_lowercase().replace('-', "_")[..] {
+            "" => Err("The reset type must not be empty.".to_string()),
+            "hardware_pin" => Ok(ResetType::HardwarePin),
+            "system_reset" => Ok(ResetType::SystemReset),
+            "core_only" => Ok(ResetType::CoreOnly),
+            _ => Ok(ResetType::Custom(Cow::Owned(s.to_string()))),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct CoreState {
     id: usize,
@@ -332,9 +569,17 @@ impl SpecificCoreState {
     }
 }
 
+/// Executes a custom reset sequence with the interface of a core.
+pub(crate) type ResetSequence<'probe> =
+    Box<dyn FnMut(&mut dyn CoreInterface) -> Result<(), error::Error> + 'probe>;
+
 pub struct Core<'probe> {
     inner: Box<dyn CoreInterface + 'probe>,
     state: &'probe mut CoreState,
+    reset_type: ResetType,
+    reset_sequence: Option<ResetSequence<'probe>>,
+    /// The memory map of the target, whose access attributes restrict the memory accesses.
+    memory_map: &'probe [MemoryRegion],
 }
 
 impl<'probe> Core<'probe> {
@@ -342,9 +587,83 @@ impl<'probe> Core<'probe> {
         Self {
             inner: Box::new(core),
             state,
+            reset_type: ResetType::default(),
+            reset_sequence: None,
+            memory_map: &[],
         }
     }
 
+    /// Sets the memory map whose access attributes the memory accesses of the core follow.
+    pub(crate) fn set_memory_map(&mut self, memory_map: &'probe [MemoryRegion]) {
+        self.memory_map = memory_map;
+    }
+
+    /// Returns the first region with side effects on reads which intersects
+    /// the `len` bytes at `address`.
+    ///
+    /// Debuggers should not read such a region unless the user explicitly requests it.
+    pub fn read_side_effects(&self, address: u32, len: usize) -> Option<&GenericRegion> {
+        read_side_effect_region(self.memory_map, address, len)
+    }
+
+    /// Returns the access size which the memory map requires for `len` bytes at `address`,
+    /// or `None` if the `requested` access is possible.
+    fn restricted_size(
+        &self,
+        address: u32,
+        len: usize,
+        requested: Option<AccessSize>,
+        write: bool,
+    ) -> Result<Option<AccessSize>, Error> {
+        restricted_access_size(self.memory_map, address, len, requested, write)
+    }
+
+    /// Reads `data` at `address` with accesses of `size`, widening the read
+    /// to the aligned range around it.
+    fn read_widened(
+        &mut self,
+        address: u32,
+        data: &mut [u8],
+        size: AccessSize,
+    ) -> Result<(), Error> {
+        let mask = size.bytes() as u32 - 1;
+        let start = address & !mask;
+        let end = (address + data.len() as u32 + mask) & !mask;
+
+        let mut bytes = vec![0; (end - start) as usize];
+        self.inner.read_mem_with_size(start, &mut bytes, size)?;
+
+        let offset = (address - start) as usize;
+        data.copy_from_slice(&bytes[offset..offset + data.len()]);
+
+        Ok(())
+    }
+
+    /// Selects how the core is reset by [`reset`] and [`reset_and_halt`].
+    ///
+    /// `reset_sequence` executes the debug sequence of a [ResetType::Custom] reset type.
+    ///
+    /// [`reset`]: Core::reset
+    /// [`reset_and_halt`]: Core::reset_and_halt
+    pub(crate) fn set_reset_type(
+        &mut self,
+        reset_type: ResetType,
+        reset_sequence: Option<ResetSequence<'probe>>,
+    ) {
+        self.reset_type = reset_type;
+        self.reset_sequence = reset_sequence;
+    }
+
+    /// Returns how the core is reset by [`reset`] and [`reset_and_halt`].
+    ///
+    /// For cores of a [Session](crate::Session), this is the reset type of the session.
+    ///
+    /// [`reset`]: Core::reset
+    /// [`reset_and_halt`]: Core::reset_and_halt
+    pub fn reset_type(&self) -> &ResetType {
+        &self.reset_type
+    }
+
     pub fn create_state(id: usize) -> CoreState {
         CoreState::new(id)
     }
@@ -378,17 +697,64 @@ impl<'probe> Core<'probe> {
     /// Reset the core, and then continue to execute instructions. If the core
     /// should be halted after reset, use the [`reset_and_halt`] function.
     ///
+    /// The core is reset with its [`reset_type`].
+    ///
     /// [`reset_and_halt`]: Core::reset_and_halt
+    /// [`reset_type`]: Core::reset_type
     pub fn reset(&mut self) -> Result<(), error::Error> {
-        self.inner.reset()
+        log::info!(
+            "Resetting core {} with reset type {}",
+            self.id(),
+            self.reset_type
+        );
+
+        self.request_reset()
     }
 
     /// Reset the core, and then immediately halt. To continue execution after
     /// reset, use the [`reset`] function.
     ///
+    /// The core is reset with its [`reset_type`].
+    ///
     /// [`reset`]: Core::reset
+    /// [`reset_type`]: Core::reset_type
     pub fn reset_and_halt(&mut self, timeout: Duration) -> Result<CoreInformation, error::Error> {
-        self.inner.reset_and_halt(timeout)
+        log::info!(
+            "Resetting and halting core {} with reset type {}",
+            self.id(),
+            self.reset_type
+        );
+
+        if self.reset_type == ResetType::SystemReset {
+            return self.inner.reset_and_halt(timeout);
+        }
+
+        self.inner.reset_catch_set()?;
+        self.request_reset()?;
+        self.inner.wait_for_core_halted(timeout)?;
+        self.inner.reset_catch_clear()?;
+
+        let pc = self
+            .inner
+            .read_core_reg(self.inner.registers().program_counter().address)?;
+
+        Ok(CoreInformation { pc })
+    }
+
+    fn request_reset(&mut self) -> Result<(), error::Error> {
+        match &self.reset_type {
+            ResetType::HardwarePin => self.inner.reset_hardware_pin(),
+            ResetType::SystemReset => self.inner.reset(),
+            ResetType::CoreOnly => self.inner.reset_core(),
+            ResetType::Custom(name) => match &mut self.reset_sequence {
+                Some(reset_sequence) => reset_sequence(self.inner.as_mut()),
+                None => Err(anyhow!(
+                    "The reset sequence '{}' can only be executed for the cores of a session",
+                    name
+                )
+                .into()),
+            },
+        }
     }
 
     /// Steps one instruction and then enters halted state again.
@@ -415,6 +781,26 @@ impl<'probe> Core<'probe> {
         Ok(self.inner.write_core_reg(address, value)?)
     }
 
+    /// Read several core registers at once.
+    ///
+    /// The accesses are sent to the probe together where the architecture allows it,
+    /// which is a lot faster than reading the registers one by one. There is one result
+    /// for each of the `addresses`, in their order.
+    pub fn read_core_regs(
+        &mut self,
+        addresses: &[CoreRegisterAddress],
+    ) -> Vec<Result<u32, error::Error>> {
+        self.inner.read_core_regs(addresses)
+    }
+
+    /// Write several core registers at once, see [Core::read_core_regs].
+    pub fn write_core_regs(
+        &mut self,
+        values: &[(CoreRegisterAddress, u32)],
+    ) -> Vec<Result<(), error::Error>> {
+        self.inner.write_core_regs(values)
+    }
+
     pub fn get_available_breakpoint_units(&mut self) -> Result<u32, error::Error> {
         self.inner.get_available_breakpoint_units()
     }
@@ -490,6 +876,11 @@ impl<'probe> Core<'probe> {
         }
     }
 
+    /// Returns the addresses of the hardware breakpoints which were set by probe-rs.
+    pub fn hw_breakpoints(&self) -> Vec<u32> {
+        self.state.breakpoints.iter().map(|bp| bp.address).collect()
+    }
+
     /// Clear all hardware breakpoints
     ///
     /// This function will clear all HW breakpoints which are configured on the target,
@@ -0,0 +1,662 @@
+//! Snapshots of the registers and the memory of a target, stored as ELF core files.
+//!
+//! A [CoreDump] is taken with [Session::core_dump]. Its ELF representation contains a
+//! `NT_PRSTATUS` note with the registers of every core and a loadable segment for every
+//! captured memory range, so it can be opened with `gdb app.elf crash.core`. The name of
+//! the target and the fault status registers are stored in additional `PROBE-RS` notes.
+//!
+//! A stored dump can be loaded again with [CoreDump::load], and read through
+//! [MemoryInterface] like a halted target.
+
+use crate::config::MemoryRegion;
+use crate::{Architecture, CoreRegisterAddress, CoreType, Error, MemoryInterface, Session};
+
+use scroll::{Pread, LE};
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+use std::time::Duration;
+use thiserror::Error;
+
+const HEADER_SIZE: u32 = 52;
+const PROGRAM_HEADER_SIZE: u32 = 32;
+
+const ET_CORE: u16 = 4;
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+const NT_PRSTATUS: u32 = 1;
+
+const EM_ARM: u16 = 40;
+const EM_RISCV: u16 = 243;
+
+/// The offset of `pr_reg` in the 32 bit `elf_prstatus` structure.
+const PRSTATUS_REGISTERS_OFFSET: usize = 72;
+
+/// Type of the `PROBE-RS` note with the name of the target.
+const NT_PROBE_RS_TARGET: u32 = 1;
+/// Type of the `PROBE-RS` note with the fault status registers of a core.
+const NT_PROBE_RS_FAULT_STATUS: u32 = 2;
+
+/// SIGTRAP, reported as the signal which stopped every core.
+const SIGTRAP: u16 = 5;
+
+/// The fault status registers of ARMv7-M and ARMv8-M cores.
+const ARM_FAULT_STATUS_REGISTERS: &[(&str, u32)] = &[
+    ("CFSR", 0xE000_ED28),
+    ("HFSR", 0xE000_ED2C),
+    ("DFSR", 0xE000_ED30),
+    ("MMFAR", 0xE000_ED34),
+    ("BFAR", 0xE000_ED38),
+];
+
+/// ARMv6-M cores only have the debug fault status register.
+const ARMV6M_FAULT_STATUS_REGISTERS: &[(&str, u32)] = &[("DFSR", 0xE000_ED30)];
+
+/// The trap CSRs of RISC-V cores.
+const RISCV_FAULT_STATUS_REGISTERS: &[(&str, u16)] =
+    &[("mcause", 0x342), ("mtval", 0x343), ("mepc", 0x341)];
+
+#[derive(Debug, Error)]
+pub enum CoreDumpError {
+    #[error("The file is not a 32 bit little endian ELF core file.")]
+    NotACoreFile,
+    #[error("Core files for the ELF machine type {0} are not supported.")]
+    UnsupportedMachine(u16),
+    #[error("The core file is malformed")]
+    Malformed(#[from] scroll::Error),
+    #[error("Address {0:#010x} is not contained in the core dump.")]
+    AddressNotDumped(u32),
+    #[error("The memory of a core dump can not be written.")]
+    ReadOnly,
+    #[error("The core dump could not be read or written")]
+    Io(#[from] io::Error),
+}
+
+impl From<CoreDumpError> for Error {
+    fn from(error: CoreDumpError) -> Self {
+        Error::Other(error.into())
+    }
+}
+
+/// The registers of one core at the time of the dump.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DumpedCore {
+    /// The registers in the order of the `pr_reg` field of an ELF core file:
+    /// R0 to R15 and xPSR for ARM, the PC and x1 to x31 for RISC-V.
+    pub registers: Vec<u32>,
+    /// The fault status registers of the core, by name.
+    pub fault_status: Vec<(String, u32)>,
+}
+
+/// A captured range of memory.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DumpedMemory {
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+impl DumpedMemory {
+    fn range(&self) -> Range<u32> {
+        self.address..self.address + self.data.len() as u32
+    }
+}
+
+/// A snapshot of the registers and the memory of a target.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoreDump {
+    /// The name of the target the dump was taken from.
+    pub target_name: String,
+    pub architecture: Architecture,
+    pub cores: Vec<DumpedCore>,
+    pub memory: Vec<DumpedMemory>,
+}
+
+/// Takes a core dump of all cores of the session. See [Session::core_dump].
+pub(crate) fn capture(session: &mut Session, ranges: &[Range<u32>]) -> Result<CoreDump, Error> {
+    let ranges: Vec<Range<u32>> = if ranges.is_empty() {
+        session
+            .target()
+            .memory_map
+            .iter()
+            .filter_map(|region| match region {
+                MemoryRegion::Ram(ram) => Some(ram.range.clone()),
+                _ => None,
+            })
+            .collect()
+    } else {
+        ranges.to_vec()
+    };
+
+    let architecture = session.architecture();
+    let target_name = session.target().name.clone();
+    let core_types: Vec<CoreType> = session.list_cores().into_iter().map(|(_, t)| t).collect();
+
+    let mut cores = vec![];
+    let mut running = vec![];
+
+    // Halt all cores first, so that the memory does not change while it is read.
+    for (index, core_type) in core_types.iter().enumerate() {
+        let mut core = session.core(index)?;
+
+        let was_running = !core.core_halted()?;
+        if was_running {
+            core.halt(Duration::from_millis(100))?;
+        }
+        running.push(was_running);
+
+        let registers = match architecture {
+            Architecture::Arm => (0..=16)
+                .map(|register| core.read_core_reg(CoreRegisterAddress(register)))
+                .collect::<Result<Vec<_>, _>>()?,
+            Architecture::Riscv => {
+                let mut registers = vec![core.read_core_reg(core.registers().program_counter())?];
+                for register in 1..32 {
+                    registers.push(core.read_core_reg(CoreRegisterAddress(0x1000 + register))?);
+                }
+                registers
+            }
+        };
+
+        let mut fault_status = vec![];
+        match (architecture, core_type) {
+            (Architecture::Arm, CoreType::M0) => {
+                for (name, address) in ARMV6M_FAULT_STATUS_REGISTERS {
+                    fault_status.push((name.to_string(), core.read_word_32(*address)?));
+                }
+            }
+            (Architecture::Arm, _) => {
+                for (name, address) in ARM_FAULT_STATUS_REGISTERS {
+                    fault_status.push((name.to_string(), core.read_word_32(*address)?));
+                }
+            }
+            (Architecture::Riscv, _) => {
+                for (name, csr) in RISCV_FAULT_STATUS_REGISTERS {
+                    // Not every core implements all trap CSRs.
+                    match core.read_core_reg(CoreRegisterAddress(*csr)) {
+                        Ok(value) => fault_status.push((name.to_string(), value)),
+                        Err(e) => log::debug!("Failed to read {}: {}", name, e),
+                    }
+                }
+            }
+        }
+
+        cores.push(DumpedCore {
+            registers,
+            fault_status,
+        });
+    }
+
+    let mut memory = vec![];
+    {
+        let mut core = session.core(0)?;
+
+        for range in ranges {
+            log::info!("Dumping memory {:#010x}..{:#010x}", range.start, range.end);
+
+            let mut data = vec![0u8; (range.end - range.start) as usize];
+            core.read_8(range.start, &mut data)?;
+
+            memory.push(DumpedMemory {
+                address: range.start,
+                data,
+            });
+        }
+    }
+
+    for (index, was_running) in running.into_iter().enumerate() {
+        if was_running {
+            session.core(index)?.run()?;
+        }
+    }
+
+    Ok(CoreDump {
+        target_name,
+        architecture,
+        cores,
+        memory,
+    })
+}
+
+impl CoreDump {
+    /// Loads a core dump which was stored with [CoreDump::store].
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, CoreDumpError> {
+        Self::from_elf(&fs::read(path)?)
+    }
+
+    /// Stores the core dump as an ELF core file.
+    pub fn store(&self, path: impl AsRef<Path>) -> Result<(), CoreDumpError> {
+        fs::write(path, self.to_elf())?;
+
+        Ok(())
+    }
+
+    /// The program counter of the given core.
+    pub fn program_counter(&self, core: usize) -> Option<u32> {
+        let index = match self.architecture {
+            Architecture::Arm => 15,
+            Architecture::Riscv => 0,
+        };
+
+        self.cores.get(core)?.registers.get(index).copied()
+    }
+
+    /// The stack pointer of the given core.
+    pub fn stack_pointer(&self, core: usize) -> Option<u32> {
+        let index = match self.architecture {
+            Architecture::Arm => 13,
+            Architecture::Riscv => 2,
+        };
+
+        self.cores.get(core)?.registers.get(index).copied()
+    }
+
+    /// Creates the ELF core file of the dump.
+    pub fn to_elf(&self) -> Vec<u8> {
+        let machine = match self.architecture {
+            Architecture::Arm => EM_ARM,
+            Architecture::Riscv => EM_RISCV,
+        };
+
+        let notes = self.notes();
+
+        let segment_count = 1 + self.memory.len() as u32;
+        let notes_offset = HEADER_SIZE + PROGRAM_HEADER_SIZE * segment_count;
+        let data_offset = notes_offset + notes.len() as u32;
+
+        let mut elf = vec![];
+
+        // ELF header
+        elf.extend_from_slice(&[0x7f, b'E', b'L', b'F', 1, 1, 1, 0]);
+        elf.extend_from_slice(&[0; 8]);
+        elf.extend_from_slice(&ET_CORE.to_le_bytes());
+        elf.extend_from_slice(&machine.to_le_bytes());
+        elf.extend_from_slice(&1u32.to_le_bytes()); // e_version
+        elf.extend_from_slice(&0u32.to_le_bytes()); // e_entry
+        elf.extend_from_slice(&HEADER_SIZE.to_le_bytes()); // e_phoff
+        elf.extend_from_slice(&0u32.to_le_bytes()); // e_shoff
+        elf.extend_from_slice(&0u32.to_le_bytes()); // e_flags
+        elf.extend_from_slice(&(HEADER_SIZE as u16).to_le_bytes());
+        elf.extend_from_slice(&(PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+        elf.extend_from_slice(&(segment_count as u16).to_le_bytes());
+        elf.extend_from_slice(&0u16.to_le_bytes()); // e_shentsize
+        elf.extend_from_slice(&0u16.to_le_bytes()); // e_shnum
+        elf.extend_from_slice(&0u16.to_le_bytes()); // e_shstrndx
+
+        // Program headers, the notes first.
+        elf.extend_from_slice(&PT_NOTE.to_le_bytes());
+        elf.extend_from_slice(&notes_offset.to_le_bytes());
+        elf.extend_from_slice(&0u32.to_le_bytes()); // p_vaddr
+        elf.extend_from_slice(&0u32.to_le_bytes()); // p_paddr
+        elf.extend_from_slice(&(notes.len() as u32).to_le_bytes()); // p_filesz
+        elf.extend_from_slice(&0u32.to_le_bytes()); // p_memsz
+        elf.extend_from_slice(&0u32.to_le_bytes()); // p_flags
+        elf.extend_from_slice(&4u32.to_le_bytes()); // p_align
+
+        let mut offset = data_offset;
+        for memory in &self.memory {
+            elf.extend_from_slice(&PT_LOAD.to_le_bytes());
+            elf.extend_from_slice(&offset.to_le_bytes());
+            elf.extend_from_slice(&memory.address.to_le_bytes()); // p_vaddr
+            elf.extend_from_slice(&memory.address.to_le_bytes()); // p_paddr
+            elf.extend_from_slice(&(memory.data.len() as u32).to_le_bytes()); // p_filesz
+            elf.extend_from_slice(&(memory.data.len() as u32).to_le_bytes()); // p_memsz
+            elf.extend_from_slice(&7u32.to_le_bytes()); // p_flags: R + W + X
+            elf.extend_from_slice(&1u32.to_le_bytes()); // p_align
+            offset += memory.data.len() as u32;
+        }
+
+        elf.extend_from_slice(&notes);
+
+        for memory in &self.memory {
+            elf.extend_from_slice(&memory.data);
+        }
+
+        elf
+    }
+
+    /// Creates the contents of the note segment.
+    fn notes(&self) -> Vec<u8> {
+        let mut notes = vec![];
+
+        push_note(
+            &mut notes,
+            b"PROBE-RS",
+            NT_PROBE_RS_TARGET,
+            self.target_name.as_bytes(),
+        );
+
+        for (index, core) in self.cores.iter().enumerate() {
+            let mut prstatus = vec![0u8; PRSTATUS_REGISTERS_OFFSET];
+            prstatus[12..14].copy_from_slice(&SIGTRAP.to_le_bytes()); // pr_cursig
+            prstatus[24..28].copy_from_slice(&(index as u32 + 1).to_le_bytes()); // pr_pid
+
+            for register in &core.registers {
+                prstatus.extend_from_slice(&register.to_le_bytes());
+            }
+
+            if self.architecture == Architecture::Arm {
+                // orig_r0, which only has a meaning for Linux system calls
+                prstatus.extend_from_slice(&0u32.to_le_bytes());
+            }
+
+            // pr_fpvalid
+            prstatus.extend_from_slice(&0u32.to_le_bytes());
+
+            push_note(&mut notes, b"CORE", NT_PRSTATUS, &prstatus);
+
+            let fault_status: String = core
+                .fault_status
+                .iter()
+                .map(|(name, value)| format!("{} {:#010x}\n", name, value))
+                .collect();
+
+            push_note(
+                &mut notes,
+                b"PROBE-RS",
+                NT_PROBE_RS_FAULT_STATUS,
+                fault_status.as_bytes(),
+            );
+        }
+
+        notes
+    }
+
+    /// Parses an ELF core file created by [CoreDump::to_elf].
+    pub fn from_elf(elf: &[u8]) -> Result<Self, CoreDumpError> {
+        if elf.len() < HEADER_SIZE as usize
+            || elf[..6] != [0x7f, b'E', b'L', b'F', 1, 1]
+            || elf.pread_with::<u16>(16, LE)? != ET_CORE
+        {
+            return Err(CoreDumpError::NotACoreFile);
+        }
+
+        let (architecture, register_count) = match elf.pread_with::<u16>(18, LE)? {
+            EM_ARM => (Architecture::Arm, 17),
+            EM_RISCV => (Architecture::Riscv, 32),
+            machine => return Err(CoreDumpError::UnsupportedMachine(machine)),
+        };
+
+        let program_header_offset = elf.pread_with::<u32>(28, LE)? as usize;
+        let program_header_size = elf.pread_with::<u16>(42, LE)? as usize;
+        let program_header_count = elf.pread_with::<u16>(44, LE)? as usize;
+
+        let mut dump = CoreDump {
+            target_name: String::new(),
+            architecture,
+            cores: vec![],
+            memory: vec![],
+        };
+
+        for index in 0..program_header_count {
+            let header = program_header_offset + index * program_header_size;
+
+            let segment_type: u32 = elf.pread_with(header, LE)?;
+            let offset = elf.pread_with::<u32>(header + 4, LE)? as usize;
+            let address: u32 = elf.pread_with(header + 8, LE)?;
+            let size = elf.pread_with::<u32>(header + 16, LE)? as usize;
+
+            let data = elf
+                .get(offset..offset + size)
+                .ok_or(scroll::Error::TooBig {
+                    size,
+                    len: elf.len(),
+                })?;
+
+            match segment_type {
+                PT_LOAD => dump.memory.push(DumpedMemory {
+                    address,
+                    data: data.to_vec(),
+                }),
+                PT_NOTE => dump.parse_notes(data, register_count)?,
+                _ => (),
+            }
+        }
+
+        Ok(dump)
+    }
+
+    fn parse_notes(
+        &mut self,
+        mut notes: &[u8],
+        register_count: usize,
+    ) -> Result<(), CoreDumpError> {
+        while !notes.is_empty() {
+            let name_size = notes.pread_with::<u32>(0, LE)? as usize;
+            let desc_size = notes.pread_with::<u32>(4, LE)? as usize;
+            let note_type: u32 = notes.pread_with(8, LE)?;
+
+            let desc_offset = 12 + align4(name_size);
+            let name = notes.pread_with::<&[u8]>(12, name_size)?;
+            let desc = notes.pread_with::<&[u8]>(desc_offset, desc_size)?;
+
+            match (name, note_type) {
+                (b"CORE\0", NT_PRSTATUS) => {
+                    let registers = (0..register_count)
+                        .map(|index| {
+                            desc.pread_with::<u32>(PRSTATUS_REGISTERS_OFFSET + 4 * index, LE)
+                        })
+                        .collect::<Result<Vec<_>, _>>()?;
+
+                    self.cores.push(DumpedCore {
+                        registers,
+                        fault_status: vec![],
+                    });
+                }
+                (b"PROBE-RS\0", NT_PROBE_RS_TARGET) => {
+                    self.target_name = String::from_utf8_lossy(desc).into_owned();
+                }
+                (b"PROBE-RS\0", NT_PROBE_RS_FAULT_STATUS) => {
+                    if let Some(core) = self.cores.last_mut() {
+                        core.fault_status = parse_fault_status(&String::from_utf8_lossy(desc));
+                    }
+                }
+                _ => (),
+            }
+
+            let note_size = desc_offset + align4(desc_size);
+            notes = notes.get(note_size..).unwrap_or(&[]);
+        }
+
+        Ok(())
+    }
+
+    fn find_memory(&self, address: u32, len: usize) -> Result<&[u8], CoreDumpError> {
+        self.memory
+            .iter()
+            .find(|memory| {
+                let range = memory.range();
+                range.contains(&address) && (address as u64 + len as u64) <= range.end as u64
+            })
+            .map(|memory| {
+                let offset = (address - memory.address) as usize;
+                &memory.data[offset..offset + len]
+            })
+            .ok_or(CoreDumpError::AddressNotDumped(address))
+    }
+}
+
+fn align4(size: usize) -> usize {
+    (size + 3) & !3
+}
+
+fn push_note(notes: &mut Vec<u8>, name: &[u8], note_type: u32, desc: &[u8]) {
+    notes.extend_from_slice(&(name.len() as u32 + 1).to_le_bytes());
+    notes.extend_from_slice(&(desc.len() as u32).to_le_bytes());
+    notes.extend_from_slice(&note_type.to_le_bytes());
+
+    notes.extend_from_slice(name);
+    notes.push(0);
+    notes.resize(align4(notes.len()), 0);
+
+    notes.extend_from_slice(desc);
+    notes.resize(align4(notes.len()), 0);
+}
+
+fn parse_fault_status(text: &str) -> Vec<(String, u32)> {
+    text.lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            let name = parts.next()?;
+            let value = parts.next()?.strip_prefix("0x")?;
+
+            Some((name.to_owned(), u32::from_str_radix(value, 16).ok()?))
+        })
+        .collect()
+}
+
+/// The memory of a core dump can be read like the memory of a halted target.
+/// Writing to it fails.
+impl MemoryInterface for CoreDump {
+    fn read_word_32(&mut self, address: u32) -> Result<u32, Error> {
+        let mut data = [0u32; 1];
+        self.read_32(address, &mut data)?;
+
+        Ok(data[0])
+    }
+
+    fn read_word_8(&mut self, address: u32) -> Result<u8, Error> {
+        Ok(self.find_memory(address, 1)?[0])
+    }
+
+    fn read_32(&mut self, address: u32, data: &mut [u32]) -> Result<(), Error> {
+        let bytes = self.find_memory(address, data.len() * 4)?;
+
+        for (word, bytes) in data.iter_mut().zip(bytes.chunks_exact(4)) {
+            *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+
+        Ok(())
+    }
+
+    fn read_8(&mut self, address: u32, data: &mut [u8]) -> Result<(), Error> {
+        data.copy_from_slice(self.find_memory(address, data.len())?);
+
+        Ok(())
+    }
+
+    fn write_word_32(&mut self, _address: u32, _data: u32) -> Result<(), Error> {
+        Err(CoreDumpError::ReadOnly.into())
+    }
+
+    fn write_word_8(&mut self, _address: u32, _data: u8) -> Result<(), Error> {
+        Err(CoreDumpError::ReadOnly.into())
+    }
+
+    fn write_32(&mut self, _address: u32, _data: &[u32]) -> Result<(), Error> {
+        Err(CoreDumpError::ReadOnly.into())
+    }
+
+    fn write_8(&mut self, _address: u32, _data: &[u8]) -> Result<(), Error> {
+        Err(CoreDumpError::ReadOnly.into())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arm_dump() -> CoreDump {
+        CoreDump {
+            target_name: "nRF52840_xxAA".to_owned(),
+            architecture: Architecture::Arm,
+            cores: vec![DumpedCore {
+                registers: (0..17).map(|r| 0x1000 + r).collect(),
+                fault_status: vec![("CFSR".to_owned(), 0x0000_8200), ("BFAR".to_owned(), 0x4)],
+            }],
+            memory: vec![
+                DumpedMemory {
+                    address: 0x2000_0000,
+                    data: (0..=255).collect(),
+                },
+                DumpedMemory {
+                    address: 0x2000_1000,
+                    data: vec![0xaa; 3],
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn partial_reads_stop_at_the_end_of_the_memory() {
+        let mut dump = arm_dump();
+        let mut data = [0u8; 256];
+
+        assert_eq!(dump.read_partial(0x2000_0008, &mut data).unwrap(), 248);
+        assert_eq!(data[..248], (8..=255).collect::<Vec<u8>>()[..]);
+
+        assert_eq!(dump.read_partial(0x2000_00fe, &mut data[..6]).unwrap(), 2);
+        assert!(dump.read_partial(0x3000_0000, &mut data).is_err());
+    }
+
+    #[test]
+    fn elf_header_of_core_file() {
+        let elf = arm_dump().to_elf();
+
+        assert_eq!(&elf[..4], b"\x7fELF");
+        assert_eq!(elf.pread_with::<u16>(16, LE).unwrap(), ET_CORE);
+        assert_eq!(elf.pread_with::<u16>(18, LE).unwrap(), EM_ARM);
+        // One note segment and one loadable segment per memory range
+        assert_eq!(elf.pread_with::<u16>(44, LE).unwrap(), 3);
+    }
+
+    #[test]
+    fn prstatus_note_has_linux_layout() {
+        let dump = arm_dump();
+        let notes = dump.notes();
+
+        // Skip the note with the target name.
+        let first = 12 + align4(9) + align4(dump.target_name.len());
+        let prstatus = &notes[first..];
+
+        assert_eq!(prstatus.pread_with::<u32>(0, LE).unwrap(), 5);
+        // r0-r15, cpsr, orig_r0 and pr_fpvalid
+        assert_eq!(prstatus.pread_with::<u32>(4, LE).unwrap(), 148);
+        assert_eq!(prstatus.pread_with::<u32>(8, LE).unwrap(), NT_PRSTATUS);
+        assert_eq!(&prstatus[12..17], b"CORE\0");
+
+        let pc = prstatus
+            .pread_with::<u32>(20 + PRSTATUS_REGISTERS_OFFSET + 15 * 4, LE)
+            .unwrap();
+        assert_eq!(pc, 0x100f);
+    }
+
+    #[test]
+    fn dump_survives_round_trip() {
+        let dump = arm_dump();
+
+        let loaded = CoreDump::from_elf(&dump.to_elf()).unwrap();
+
+        assert_eq!(loaded, dump);
+        assert_eq!(loaded.program_counter(0), Some(0x100f));
+        assert_eq!(loaded.stack_pointer(0), Some(0x100d));
+    }
+
+    #[test]
+    fn memory_of_dump_can_be_read() {
+        let mut dump = arm_dump();
+
+        assert_eq!(dump.read_word_32(0x2000_0004).unwrap(), 0x0706_0504);
+        assert_eq!(dump.read_word_8(0x2000_1002).unwrap(), 0xaa);
+
+        let mut data = [0u8; 4];
+        assert!(dump.read_8(0x2000_00fe, &mut data).is_err());
+        assert!(dump.read_8(0x2000_1003, &mut data[..1]).is_err());
+        assert!(dump.write_word_32(0x2000_0000, 0).is_err());
+    }
+
+    #[test]
+    fn other_files_are_rejected() {
+        let mut elf = arm_dump().to_elf();
+        elf[16] = 2; // ET_EXEC
+
+        assert!(matches!(
+            CoreDump::from_elf(&elf),
+            Err(CoreDumpError::NotACoreFile)
+        ));
+    }
+}
@@ -0,0 +1,906 @@
+//! Breakpoints which are kept across downloads and resets of the target.
+//!
+//! The [BreakpointManager] stores where a breakpoint was requested, e.g. a line of a source
+//! file or a symbol, instead of only its address. When a new image is downloaded, the
+//! breakpoints are resolved again against its debug information, so they follow the code
+//! when it moves. Breakpoints which can not be resolved are kept, but are not verified, and
+//! are installed as soon as they can be resolved.
+
+use super::DebugInfo;
+use crate::config::{MemoryRegion, RawFlashAlgorithm};
+use crate::core::{Architecture, CoreInformation};
+use crate::flashing::{download_data, DownloadOptions, FillPolicy};
+use crate::{DebugProbeError, Error, MemoryInterface, Session};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// The Thumb `BKPT #0` instruction, in little endian.
+const THUMB_BKPT: [u8; 2] = [0x00, 0xbe];
+
+/// Where a breakpoint was requested.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BreakpointLocation {
+    /// A fixed address.
+    Address(u32),
+    /// An offset in bytes from the address of a symbol of the ELF file.
+    Symbol { name: String, offset: u32 },
+    /// A line of a source file, and optionally a column of the line.
+    Source {
+        path: PathBuf,
+        line: u64,
+        column: Option<u64>,
+    },
+}
+
+impl BreakpointLocation {
+    /// Finds the address of the location in the debug information.
+    fn resolve(&self, debug_info: Option<&DebugInfo>) -> Result<u32, String> {
+        let debug_info = match (self, debug_info) {
+            (BreakpointLocation::Address(address), _) => return Ok(*address),
+            (_, Some(debug_info)) => debug_info,
+            (_, None) => return Err("No debug information is loaded.".to_owned()),
+        };
+
+        match self {
+            BreakpointLocation::Address(address) => Ok(*address),
+            BreakpointLocation::Symbol { name, offset } => debug_info
+                .get_symbol_address(name)
+                .map(|address| address as u32 + offset)
+                .ok_or_else(|| format!("The symbol '{}' was not found.", name)),
+            BreakpointLocation::Source { path, line, column } => {
+                match debug_info.get_breakpoint_location(path, *line, *column) {
+                    Ok(Some(address)) => Ok(address as u32),
+                    Ok(None) => Err(format!("No code was found for {}.", self)),
+                    Err(e) => Err(e.to_string()),
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for BreakpointLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BreakpointLocation::Address(address) => write!(f, "{:#010x}", address),
+            BreakpointLocation::Symbol { name, offset: 0 } => write!(f, "{}", name),
+            BreakpointLocation::Symbol { name, offset } => write!(f, "{}+{:#x}", name, offset),
+            BreakpointLocation::Source { path, line, column } => {
+                write!(f, "{}:{}", path.display(), line)?;
+                if let Some(column) = column {
+                    write!(f, ":{}", column)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+fn parse_number(s: &str) -> Option<u32> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+impl FromStr for BreakpointLocation {
+    type Err = String;
+
+    /// Parses `0x<address>`, `<file>:<line>[:<column>]` or `<symbol>[+<offset>]`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let invalid = || format!("'{}' is not a valid breakpoint location.", s);
+
+        if s.is_empty() {
+            return Err(invalid());
+        }
+
+        if s.starts_with("0x") {
+            return parse_number(s)
+                .map(BreakpointLocation::Address)
+                .ok_or_else(invalid);
+        }
+
+        // A path, followed by one or two numbers. Symbols can contain `::`, but not a number after it.
+        let split_number = |s: &str| -> Option<(String, u64)> {
+            let colon = s.rfind(':')?;
+            let number = s[colon + 1..].parse().ok()?;
+            if colon == 0 {
+                None
+            } else {
+                Some((s[..colon].to_owned(), number))
+            }
+        };
+        if let Some((rest, last)) = split_number(s) {
+            return Ok(match split_number(&rest) {
+                Some((path, line)) => BreakpointLocation::Source {
+                    path: path.into(),
+                    line,
+                    column: Some(last),
+                },
+                None => BreakpointLocation::Source {
+                    path: rest.into(),
+                    line: last,
+                    column: None,
+                },
+            });
+        }
+
+        match s.find('+') {
+            Some(plus) if plus > 0 => Ok(BreakpointLocation::Symbol {
+                name: s[..plus].trim().to_owned(),
+                offset: parse_number(s[plus + 1..].trim()).ok_or_else(invalid)?,
+            }),
+            Some(_) => Err(invalid()),
+            None => Ok(BreakpointLocation::Symbol {
+                name: s.to_owned(),
+                offset: 0,
+            }),
+        }
+    }
+}
+
+/// How a breakpoint is installed on the target.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BreakpointKind {
+    /// A comparator of the breakpoint unit of the core.
+    Hardware,
+    /// A breakpoint instruction, which replaces the instruction at the address.
+    Software,
+    /// A breakpoint instruction, which is programmed into the flash because all hardware
+    /// breakpoint units are in use. See [BreakpointManager::enable_flash_breakpoints].
+    Flash,
+}
+
+impl BreakpointKind {
+    /// Chooses the kind of breakpoint for `address`.
+    ///
+    /// Code in RAM gets software breakpoints, which are only limited by the memory,
+    /// while flash and unknown memory get hardware breakpoints, as they can not be written.
+    /// Software breakpoints are only supported on ARM cores.
+    pub fn for_address(
+        memory_map: &[MemoryRegion],
+        architecture: Architecture,
+        address: u32,
+    ) -> Self {
+        let in_ram = memory_map.iter().any(|region| match region {
+            MemoryRegion::Ram(ram) => ram.range.contains(&address),
+            _ => false,
+        });
+
+        if in_ram && architecture == Architecture::Arm {
+            BreakpointKind::Software
+        } else {
+            BreakpointKind::Hardware
+        }
+    }
+}
+
+/// A breakpoint of a [BreakpointManager].
+#[derive(Debug, Clone)]
+pub struct ManagedBreakpoint {
+    id: usize,
+    location: BreakpointLocation,
+    address: Option<u32>,
+    kind: Option<BreakpointKind>,
+    installed: bool,
+    /// The instruction which was replaced by a software breakpoint.
+    original: Option<[u8; 2]>,
+    /// Why the breakpoint is not verified.
+    message: Option<String>,
+}
+
+impl ManagedBreakpoint {
+    /// The number of the breakpoint, which is unique in its manager.
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Where the breakpoint was requested.
+    pub fn location(&self) -> &BreakpointLocation {
+        &self.location
+    }
+
+    /// The address the location was resolved to.
+    pub fn address(&self) -> Option<u32> {
+        self.address
+    }
+
+    /// How the breakpoint is installed on the target, once it was resolved.
+    pub fn kind(&self) -> Option<BreakpointKind> {
+        self.kind
+    }
+
+    /// `true` if the breakpoint is installed on the target, and will halt the core.
+    pub fn verified(&self) -> bool {
+        self.installed
+    }
+
+    /// The reason why the breakpoint is not verified.
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+}
+
+/// Finds the flash sector which contains `address`, with the sectors of the flash region or
+/// of its flash algorithm.
+fn flash_sector(
+    memory_map: &[MemoryRegion],
+    flash_algorithms: &[RawFlashAlgorithm],
+    address: u32,
+) -> Option<Range<u32>> {
+    let region = memory_map.iter().find_map(|region| match region {
+        MemoryRegion::Nvm(nvm) if nvm.range.contains(&address) => Some(nvm),
+        _ => None,
+    })?;
+
+    let (start, sectors) = if region.sectors.is_empty() {
+        let properties = &flash_algorithms
+            .iter()
+            .find(|algorithm| algorithm.flash_properties.address_range.contains(&address))?
+            .flash_properties;
+        (properties.address_range.start, &properties.sectors)
+    } else {
+        (region.range.start, &region.sectors)
+    };
+
+    let offset = address - start;
+    let sectors = sectors.iter().rfind(|sector| sector.address <= offset)?;
+    let sector_start = start + offset - (offset - sectors.address) % sectors.size;
+
+    Some(sector_start..sector_start + sectors.size)
+}
+
+/// Programs `data` into the flash at `address`, keeping the rest of the sector and the state of the core.
+fn program_flash(session: &mut Session, address: u32, data: &[u8]) -> Result<(), Error> {
+    let options = DownloadOptions {
+        fill_policy: FillPolicy::ReadBack,
+        keep_core_state: true,
+        ..Default::default()
+    };
+
+    download_data(session, &[(address, data.to_vec())], options).map_err(|e| Error::Other(e.into()))
+}
+
+/// The settings and the wear of breakpoints which are programmed into the flash.
+#[derive(Debug)]
+struct FlashPatching {
+    /// How often a sector may be programmed to install breakpoints in this session.
+    max_rewrites: u32,
+    /// How often each sector was programmed, by its start address.
+    rewrites: HashMap<u32, u32>,
+}
+
+/// Keeps the breakpoints of a core across downloads of new images and resets.
+///
+/// Software breakpoints are stepped over with [BreakpointManager::run] and
+/// [BreakpointManager::step], which should be used instead of
+/// [Core::run](crate::Core::run) and [Core::step](crate::Core::step).
+/// Before the debugger detaches, [BreakpointManager::uninstall] has to be called,
+/// to restore the instructions which were replaced by breakpoints.
+#[derive(Debug)]
+pub struct BreakpointManager {
+    core_index: usize,
+    memory_map: Vec<MemoryRegion>,
+    breakpoints: Vec<ManagedBreakpoint>,
+    next_id: usize,
+    flash_patching: Option<FlashPatching>,
+    /// True if the core was stepped since it was last resumed.
+    stepping: bool,
+}
+
+impl BreakpointManager {
+    /// Creates a manager for the breakpoints of the core `core_index` of the `session`.
+    pub fn new(session: &Session, core_index: usize) -> Self {
+        Self {
+            core_index,
+            memory_map: session.target().memory_map.clone(),
+            breakpoints: Vec::new(),
+            next_id: 1,
+            flash_patching: None,
+            stepping: false,
+        }
+    }
+
+    /// Programs breakpoint instructions into the flash when all hardware breakpoint units are in use.
+    ///
+    /// Each breakpoint erases and programs the flash sector which contains it, which wears the flash
+    /// and takes a while. Once a sector was programmed `max_rewrites_per_sector` times in this session,
+    /// including the removal of breakpoints, no more breakpoints are programmed into it. Breakpoints
+    /// are not programmed into the sector the core is stepped through either, until it is resumed.
+    /// Removing breakpoints from the flash is always allowed.
+    ///
+    /// This is only supported on ARM cores.
+    pub fn enable_flash_breakpoints(&mut self, max_rewrites_per_sector: u32) {
+        self.flash_patching = Some(FlashPatching {
+            max_rewrites: max_rewrites_per_sector,
+            rewrites: HashMap::new(),
+        });
+    }
+
+    /// All breakpoints, in the order they were added.
+    pub fn breakpoints(&self) -> &[ManagedBreakpoint] {
+        &self.breakpoints
+    }
+
+    /// Adds a breakpoint at `location`, and installs it if it can be resolved.
+    ///
+    /// A breakpoint which can not be resolved or installed is kept, but is not verified.
+    pub fn add(
+        &mut self,
+        session: &mut Session,
+        debug_info: Option<&DebugInfo>,
+        location: BreakpointLocation,
+    ) -> Result<&ManagedBreakpoint, Error> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.breakpoints.push(ManagedBreakpoint {
+            id,
+            location,
+            address: None,
+            kind: None,
+            installed: false,
+            original: None,
+            message: None,
+        });
+
+        let index = self.breakpoints.len() - 1;
+        self.resolve(index, debug_info);
+        self.install(session, index)?;
+
+        Ok(&self.breakpoints[index])
+    }
+
+    /// Removes the breakpoint `id` from the target and from the manager.
+    ///
+    /// Returns `false` if there is no such breakpoint.
+    pub fn remove(&mut self, session: &mut Session, id: usize) -> Result<bool, Error> {
+        let index = match self.breakpoints.iter().position(|bp| bp.id == id) {
+            Some(index) => index,
+            None => return Ok(false),
+        };
+
+        self.uninstall_breakpoint(session, index)?;
+        self.breakpoints.remove(index);
+
+        Ok(true)
+    }
+
+    /// Removes all breakpoints from the target, but keeps them in the manager.
+    ///
+    /// All breakpoints are removed, also if removing one of them fails,
+    /// so no breakpoint instruction is left behind. The first error is returned.
+    pub fn uninstall(&mut self, session: &mut Session) -> Result<(), Error> {
+        let mut result = Ok(());
+
+        for index in 0..self.breakpoints.len() {
+            if let Err(e) = self.uninstall_breakpoint(session, index) {
+                log::warn!(
+                    "Failed to remove breakpoint {}: {}",
+                    self.breakpoints[index].id,
+                    e
+                );
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Resolves all breakpoints against the debug information of a newly downloaded image,
+    /// and installs them at their new addresses.
+    pub fn reload(
+        &mut self,
+        session: &mut Session,
+        debug_info: Option<&DebugInfo>,
+    ) -> Result<(), Error> {
+        self.uninstall(session)?;
+
+        for index in 0..self.breakpoints.len() {
+            let breakpoint = &mut self.breakpoints[index];
+            breakpoint.original = None;
+            breakpoint.kind = None;
+
+            self.resolve(index, debug_info);
+            self.install(session, index)?;
+        }
+
+        Ok(())
+    }
+
+    /// Installs all breakpoints again, after the core was reset or attached to again.
+    ///
+    /// The addresses are not resolved again, as the image did not change.
+    pub fn reinstall(&mut self, session: &mut Session) -> Result<(), Error> {
+        let mut core = session.core(self.core_index)?;
+
+        for breakpoint in &mut self.breakpoints {
+            match breakpoint.kind {
+                // Breakpoints in the flash stay there.
+                Some(BreakpointKind::Flash) => continue,
+                Some(BreakpointKind::Hardware) if breakpoint.installed => {
+                    if let Some(address) = breakpoint.address {
+                        // The comparator may have been cleared, it is set again below.
+                        let _ = core.clear_hw_breakpoint(address);
+                    }
+                }
+                _ => {}
+            }
+            breakpoint.installed = false;
+        }
+        drop(core);
+
+        self.install_all(session)
+    }
+
+    /// Resumes the core, stepping over a software breakpoint at the program counter first.
+    ///
+    /// Breakpoints which could not be programmed into the flash while the core was stepped
+    /// are installed now.
+    pub fn run(&mut self, session: &mut Session) -> Result<(), Error> {
+        self.stepping = false;
+
+        if let Some(index) = self.breakpoint_at_pc(session)? {
+            self.step_over(session, index)?;
+        }
+        self.install_all(session)?;
+
+        session.core(self.core_index)?.run()
+    }
+
+    /// Steps a single instruction, also if a software breakpoint replaces it.
+    pub fn step(&mut self, session: &mut Session) -> Result<CoreInformation, Error> {
+        self.stepping = true;
+
+        match self.breakpoint_at_pc(session)? {
+            Some(index) => self.step_over(session, index),
+            None => session.core(self.core_index)?.step(),
+        }
+    }
+
+    fn resolve(&mut self, index: usize, debug_info: Option<&DebugInfo>) {
+        let breakpoint = &mut self.breakpoints[index];
+
+        match breakpoint.location.resolve(debug_info) {
+            Ok(address) => {
+                breakpoint.address = Some(address);
+                breakpoint.message = None;
+            }
+            Err(message) => {
+                log::debug!(
+                    "Breakpoint {} at {} is not resolved: {}",
+                    breakpoint.id,
+                    breakpoint.location,
+                    message
+                );
+                breakpoint.address = None;
+                breakpoint.message = Some(message);
+            }
+        }
+    }
+
+    /// The installed breakpoint at `address`, other than the one at `index`.
+    fn installed_at(&self, address: u32, index: usize) -> Option<&ManagedBreakpoint> {
+        self.breakpoints
+            .iter()
+            .enumerate()
+            .find(|(i, bp)| *i != index && bp.installed && bp.address == Some(address))
+            .map(|(_, bp)| bp)
+    }
+
+    /// Installs all resolved breakpoints which are not installed yet.
+    fn install_all(&mut self, session: &mut Session) -> Result<(), Error> {
+        for index in 0..self.breakpoints.len() {
+            self.install(session, index)?;
+        }
+
+        Ok(())
+    }
+
+    fn install(&mut self, session: &mut Session, index: usize) -> Result<(), Error> {
+        let address = match self.breakpoints[index].address {
+            Some(address) if !self.breakpoints[index].installed => address,
+            _ => return Ok(()),
+        };
+
+        // Another breakpoint already halts the core at this address.
+        if let Some(other) = self.installed_at(address, index) {
+            let (kind, original) = (other.kind, other.original);
+            let breakpoint = &mut self.breakpoints[index];
+            breakpoint.kind = kind;
+            breakpoint.original = original;
+            breakpoint.installed = true;
+            breakpoint.message = None;
+            return Ok(());
+        }
+
+        let mut core = session.core(self.core_index)?;
+        let kind = BreakpointKind::for_address(&self.memory_map, core.architecture(), address);
+
+        match kind {
+            BreakpointKind::Hardware => match core.set_hw_breakpoint(address) {
+                Ok(()) => {}
+                Err(Error::Probe(DebugProbeError::BreakpointUnitsExceeded)) => {
+                    drop(core);
+                    if self.flash_patching.is_some() {
+                        return self.install_in_flash(session, index, address);
+                    }
+
+                    let breakpoint = &mut self.breakpoints[index];
+                    breakpoint.kind = Some(kind);
+                    breakpoint.message = Some("All hardware breakpoint units are in use.".into());
+                    return Ok(());
+                }
+                Err(e) => return Err(e),
+            },
+            BreakpointKind::Software | BreakpointKind::Flash => {
+                if self.breakpoints[index].original.is_none() {
+                    let mut original = [0u8; 2];
+                    core.read_8(address, &mut original)?;
+                    self.breakpoints[index].original = Some(original);
+                }
+                core.write_8(address, &THUMB_BKPT)?;
+            }
+        }
+
+        let breakpoint = &mut self.breakpoints[index];
+        breakpoint.kind = Some(kind);
+        breakpoint.installed = true;
+        breakpoint.message = None;
+
+        Ok(())
+    }
+
+    /// Programs the breakpoint instruction into the flash, if the guards allow it.
+    fn install_in_flash(
+        &mut self,
+        session: &mut Session,
+        index: usize,
+        address: u32,
+    ) -> Result<(), Error> {
+        self.breakpoints[index].kind = Some(BreakpointKind::Flash);
+
+        let sector = match self.check_flash_sector(session, address)? {
+            Ok(sector) => sector,
+            Err(message) => {
+                log::debug!(
+                    "Breakpoint {} is not installed: {}",
+                    self.breakpoints[index].id,
+                    message
+                );
+                self.breakpoints[index].message = Some(message);
+                return Ok(());
+            }
+        };
+
+        let mut original = [0u8; 2];
+        session
+            .core(self.core_index)?
+            .read_8(address, &mut original)?;
+
+        self.count_rewrite(sector.start);
+        program_flash(session, address, &THUMB_BKPT)?;
+
+        let breakpoint = &mut self.breakpoints[index];
+        breakpoint.original = Some(original);
+        breakpoint.installed = true;
+        breakpoint.message = None;
+
+        Ok(())
+    }
+
+    /// Returns the flash sector of `address`, or the reason why it must not be programmed.
+    fn check_flash_sector(
+        &self,
+        session: &mut Session,
+        address: u32,
+    ) -> Result<Result<Range<u32>, String>, Error> {
+        let patching = match &self.flash_patching {
+            Some(patching) => patching,
+            None => return Ok(Err("All hardware breakpoint units are in use.".into())),
+        };
+
+        if session.architecture() != Architecture::Arm {
+            return Ok(Err(
+                "Breakpoints can only be programmed into the flash of ARM cores.".into(),
+            ));
+        }
+
+        let sector = match flash_sector(
+            &session.target().memory_map,
+            &session.target().flash_algorithms,
+            address,
+        ) {
+            Some(sector) => sector,
+            None => {
+                return Ok(Err(format!(
+                    "The flash sector of {:#010x} is unknown.",
+                    address
+                )))
+            }
+        };
+
+        let rewrites = patching.rewrites.get(&sector.start).copied().unwrap_or(0);
+        if rewrites >= patching.max_rewrites {
+            return Ok(Err(format!(
+                "The flash sector at {:#010x} was already programmed {} times in this session.",
+                sector.start, rewrites
+            )));
+        }
+
+        if self.stepping {
+            let mut core = session.core(self.core_index)?;
+            let pc = core.read_core_reg(core.registers().program_counter())?;
+            if sector.contains(&pc) {
+                return Ok(Err(format!(
+                    "The core is stepped through the flash sector at {:#010x}, \
+                    the breakpoint is installed when the core is resumed.",
+                    sector.start
+                )));
+            }
+        }
+
+        Ok(Ok(sector))
+    }
+
+    /// Counts a rewrite of the flash sector at `sector_start`.
+    fn count_rewrite(&mut self, sector_start: u32) {
+        if let Some(patching) = &mut self.flash_patching {
+            *patching.rewrites.entry(sector_start).or_insert(0) += 1;
+        }
+    }
+
+    /// Restores the instruction which a breakpoint instruction replaced, if it is still there.
+    ///
+    /// A new image may have replaced the breakpoint instruction already.
+    fn restore_instruction(
+        &mut self,
+        session: &mut Session,
+        kind: BreakpointKind,
+        address: u32,
+        original: [u8; 2],
+    ) -> Result<(), Error> {
+        let mut core = session.core(self.core_index)?;
+        let mut current = [0u8; 2];
+        core.read_8(address, &mut current)?;
+        if current != THUMB_BKPT {
+            return Ok(());
+        }
+
+        match kind {
+            BreakpointKind::Flash => {
+                drop(core);
+                if let Some(sector) = flash_sector(
+                    &session.target().memory_map,
+                    &session.target().flash_algorithms,
+                    address,
+                ) {
+                    self.count_rewrite(sector.start);
+                }
+                program_flash(session, address, &original)
+            }
+            _ => core.write_8(address, &original),
+        }
+    }
+
+    fn uninstall_breakpoint(&mut self, session: &mut Session, index: usize) -> Result<(), Error> {
+        let address = match self.breakpoints[index].address {
+            Some(address) if self.breakpoints[index].installed => address,
+            _ => return Ok(()),
+        };
+
+        self.breakpoints[index].installed = false;
+
+        if self.installed_at(address, index).is_some() {
+            return Ok(());
+        }
+
+        let breakpoint = &self.breakpoints[index];
+        match (breakpoint.kind, breakpoint.original) {
+            (Some(BreakpointKind::Hardware), _) => {
+                session.core(self.core_index)?.clear_hw_breakpoint(address)
+            }
+            (Some(kind), Some(original)) => {
+                self.restore_instruction(session, kind, address, original)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the breakpoint instruction the core is halted at.
+    fn breakpoint_at_pc(&self, session: &mut Session) -> Result<Option<usize>, Error> {
+        let mut core = session.core(self.core_index)?;
+        if !core.core_halted()? {
+            return Ok(None);
+        }
+
+        let pc = core.read_core_reg(core.registers().program_counter())?;
+
+        Ok(self.breakpoints.iter().position(|bp| {
+            bp.installed
+                && bp.address == Some(pc)
+                && matches!(
+                    bp.kind,
+                    Some(BreakpointKind::Software) | Some(BreakpointKind::Flash)
+                )
+        }))
+    }
+
+    /// Executes the instruction replaced by the breakpoint at `index`, and installs the breakpoint again.
+    fn step_over(&mut self, session: &mut Session, index: usize) -> Result<CoreInformation, Error> {
+        let breakpoint = &self.breakpoints[index];
+        let (address, original) = match (breakpoint.address, breakpoint.original) {
+            (Some(address), Some(original)) => (address, original),
+            _ => return session.core(self.core_index)?.step(),
+        };
+
+        if breakpoint.kind == Some(BreakpointKind::Software) {
+            let mut core = session.core(self.core_index)?;
+            core.write_8(address, &original)?;
+            let information = core.step();
+            core.write_8(address, &THUMB_BKPT)?;
+            return information;
+        }
+
+        // The flash has to be programmed twice, all breakpoints at the address are installed again
+        // after the step, unless the guards of the flash forbid it.
+        let sharing: Vec<usize> = (0..self.breakpoints.len())
+            .filter(|i| {
+                self.breakpoints[*i].installed && self.breakpoints[*i].address == Some(address)
+            })
+            .collect();
+        for i in &sharing {
+            self.breakpoints[*i].installed = false;
+        }
+
+        self.restore_instruction(session, BreakpointKind::Flash, address, original)?;
+        let information = session.core(self.core_index)?.step()?;
+
+        for i in sharing {
+            self.install(session, i)?;
+        }
+
+        Ok(information)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{flash_sector, BreakpointKind, BreakpointLocation};
+    use crate::config::{EraseMode, MemoryRegion, NvmRegion, RamRegion, SectorDescription};
+    use crate::core::Architecture;
+    use std::borrow::Cow;
+
+    #[test]
+    fn locations_are_parsed() {
+        let parse = |s: &str| s.parse::<BreakpointLocation>();
+
+        assert_eq!(
+            parse("0x08000400"),
+            Ok(BreakpointLocation::Address(0x0800_0400))
+        );
+        assert_eq!(
+            parse("main"),
+            Ok(BreakpointLocation::Symbol {
+                name: "main".into(),
+                offset: 0
+            })
+        );
+        assert_eq!(
+            parse("app::handler+0x10"),
+            Ok(BreakpointLocation::Symbol {
+                name: "app::handler".into(),
+                offset: 0x10
+            })
+        );
+        assert_eq!(
+            parse("src/main.rs:42"),
+            Ok(BreakpointLocation::Source {
+                path: "src/main.rs".into(),
+                line: 42,
+                column: None
+            })
+        );
+        assert_eq!(
+            parse("C:/src/main.rs:42:5"),
+            Ok(BreakpointLocation::Source {
+                path: "C:/src/main.rs".into(),
+                line: 42,
+                column: Some(5)
+            })
+        );
+        assert!(parse("").is_err());
+        assert!(parse("0xzz").is_err());
+        assert!(parse("main+x").is_err());
+    }
+
+    #[test]
+    fn locations_are_displayed_as_parsed() {
+        for location in &["0x08000400", "main", "main+0x4", "src/main.rs:42:5"] {
+            let parsed: BreakpointLocation = location.parse().unwrap();
+            assert_eq!(&parsed.to_string(), location);
+        }
+    }
+
+    #[test]
+    fn kind_depends_on_the_memory() {
+        let memory_map = [
+            MemoryRegion::Nvm(NvmRegion {
+                range: 0x0800_0000..0x0810_0000,
+                is_boot_memory: true,
+                is_otp: false,
+                erase_mode: EraseMode::Sector,
+                sectors: Cow::Borrowed(&[]),
+                memory_mapped: None,
+            }),
+            MemoryRegion::Ram(RamRegion {
+                range: 0x2000_0000..0x2002_0000,
+                is_boot_memory: false,
+                core_index: 0,
+            }),
+        ];
+
+        let kind =
+            |architecture, address| BreakpointKind::for_address(&memory_map, architecture, address);
+
+        assert_eq!(
+            kind(Architecture::Arm, 0x0800_0400),
+            BreakpointKind::Hardware
+        );
+        assert_eq!(
+            kind(Architecture::Arm, 0x2000_0400),
+            BreakpointKind::Software
+        );
+        assert_eq!(
+            kind(Architecture::Riscv, 0x2000_0400),
+            BreakpointKind::Hardware
+        );
+        assert_eq!(
+            kind(Architecture::Arm, 0x4000_0000),
+            BreakpointKind::Hardware
+        );
+    }
+    #[test]
+    fn flash_sectors_are_found() {
+        let memory_map = [
+            MemoryRegion::Nvm(NvmRegion {
+                range: 0x0800_0000..0x0810_0000,
+                is_boot_memory: true,
+                is_otp: false,
+                erase_mode: EraseMode::Sector,
+                sectors: Cow::Borrowed(&[
+                    SectorDescription {
+                        size: 0x4000,
+                        address: 0x0,
+                    },
+                    SectorDescription {
+                        size: 0x1_0000,
+                        address: 0x1_0000,
+                    },
+                ]),
+                memory_mapped: None,
+            }),
+            MemoryRegion::Ram(RamRegion {
+                range: 0x2000_0000..0x2002_0000,
+                is_boot_memory: false,
+                core_index: 0,
+            }),
+        ];
+
+        assert_eq!(
+            flash_sector(&memory_map, &[], 0x0800_5000),
+            Some(0x0800_4000..0x0800_8000)
+        );
+        assert_eq!(
+            flash_sector(&memory_map, &[], 0x0802_1234),
+            Some(0x0802_0000..0x0803_0000)
+        );
+        assert_eq!(flash_sector(&memory_map, &[], 0x2000_0400), None);
+    }
+}
@@ -0,0 +1,216 @@
+//! Finding the debug information of stripped ELF files in separate debug files.
+//!
+//! A stripped file names its debug file in two ways, which are tried in this order:
+//!
+//! - The build ID in `.note.gnu.build-id`, for which the debug file is looked up as
+//!   `.build-id/ab/cdef….debug` in the debug file directories.
+//! - The file name in `.gnu_debuglink`, which is looked up next to the ELF file, in its `.debug`
+//!   subdirectory and in the debug file directories. The section contains the CRC-32 of the
+//!   debug file, which has to match.
+
+use super::DebugError;
+use object::read::{Object, ObjectSection};
+
+use std::convert::TryInto;
+use std::path::{Path, PathBuf};
+
+/// The type of the note which contains the build ID.
+const NT_GNU_BUILD_ID: u32 = 3;
+
+/// Extracts the build ID from the contents of the `.note.gnu.build-id` section.
+pub(crate) fn build_id(note: &[u8]) -> Option<&[u8]> {
+    let name_size = word(note, 0)? as usize;
+    let description_size = word(note, 4)? as usize;
+    if word(note, 8)? != NT_GNU_BUILD_ID {
+        return None;
+    }
+
+    // The name is padded to a multiple of four bytes.
+    let description = 12 + (name_size + 3) / 4 * 4;
+    note.get(description..description.checked_add(description_size)?)
+        .filter(|id| !id.is_empty())
+}
+
+/// Extracts the file name and the CRC-32 of the debug file from the contents of the
+/// `.gnu_debuglink` section.
+pub(crate) fn debug_link(section: &[u8]) -> Option<(&str, u32)> {
+    let name_length = section.iter().position(|byte| *byte == 0)?;
+    let name = std::str::from_utf8(&section[..name_length]).ok()?;
+
+    // The CRC follows the name and its terminator, aligned to four bytes.
+    let crc = (name_length + 1 + 3) / 4 * 4;
+
+    Some((name, word(section, crc)?))
+}
+
+fn word(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+/// The CRC-32 which `.gnu_debuglink` uses, the one of zlib.
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut table = [0u32; 256];
+    for (index, entry) in table.iter_mut().enumerate() {
+        *entry = (0..8).fold(index as u32, |crc, _| {
+            if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            }
+        });
+    }
+
+    !data.iter().fold(!0, |crc, byte| {
+        table[((crc ^ u32::from(*byte)) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Finds the separate debug file of the ELF file `object` at `path`, and returns its contents.
+///
+/// Returns `None` if `object` names no debug file. If it names one which can not be found,
+/// or whose CRC does not match, an error tells which file is missing.
+pub(crate) fn find(
+    path: &Path,
+    object: &object::File,
+    directories: &[PathBuf],
+) -> Result<Option<Vec<u8>>, DebugError> {
+    let build_id = object
+        .section_by_name(".note.gnu.build-id")
+        .and_then(|section| section.data().ok())
+        .and_then(build_id);
+    let debug_link = object
+        .section_by_name(".gnu_debuglink")
+        .and_then(|section| section.data().ok())
+        .and_then(debug_link);
+
+    if let Some(build_id) = build_id {
+        let (directory, file) = build_id.split_at(1);
+        let file: String = file.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let name = Path::new(".build-id")
+            .join(format!("{:02x}", directory[0]))
+            .join(format!("{}.debug", file));
+
+        for directory in directories {
+            let candidate = directory.join(&name);
+            log::debug!("Looking for the debug file {}", candidate.display());
+
+            if let Ok(data) = std::fs::read(&candidate) {
+                log::info!("Found the debug file {} by build ID", candidate.display());
+                return Ok(Some(data));
+            }
+        }
+
+        if debug_link.is_none() {
+            return Err(DebugError::DebugFileNotFound(name.display().to_string()));
+        }
+    }
+
+    let (name, crc) = match debug_link {
+        Some(debug_link) => debug_link,
+        None => return Ok(None),
+    };
+
+    let elf_directory = path.parent().unwrap_or_else(|| Path::new(""));
+    let absolute_directory = elf_directory
+        .canonicalize()
+        .unwrap_or_else(|_| elf_directory.to_owned());
+
+    let mut candidates = vec![
+        elf_directory.join(name),
+        elf_directory.join(".debug").join(name),
+    ];
+    for directory in directories {
+        candidates.push(directory.join(name));
+        // Like GDB, the path of the ELF file is repeated in the debug file directory.
+        candidates.push(
+            directory
+                .join(
+                    absolute_directory
+                        .strip_prefix("/")
+                        .unwrap_or(&absolute_directory),
+                )
+                .join(name),
+        );
+    }
+
+    let mut mismatch = None;
+    for candidate in candidates {
+        log::debug!("Looking for the debug file {}", candidate.display());
+
+        let data = match std::fs::read(&candidate) {
+            Ok(data) => data,
+            Err(_) => continue,
+        };
+
+        let actual = crc32(&data);
+        if actual == crc {
+            log::info!("Found the debug file {}", candidate.display());
+            return Ok(Some(data));
+        }
+
+        log::warn!(
+            "The debug file {} does not match, its CRC is {:#010x} instead of {:#010x}",
+            candidate.display(),
+            actual,
+            crc
+        );
+        mismatch.get_or_insert(DebugError::DebugFileMismatch {
+            path: candidate,
+            expected: crc,
+            actual,
+        });
+    }
+
+    Err(mismatch.unwrap_or_else(|| DebugError::DebugFileNotFound(name.to_owned())))
+}
+
+/// Returns the name of the split DWARF file of the first compilation unit, if the debug
+/// information was built with `-gsplit-dwarf`.
+pub(crate) fn split_dwarf_name(dwarf: &gimli::Dwarf<super::DwarfReader>) -> Option<String> {
+    let header = dwarf.units().next().ok()??;
+    let unit = dwarf.unit(header).ok()?;
+    let mut entries = unit.entries();
+    let (_, root) = entries.next_dfs().ok()??;
+
+    let name = match root.attr_value(gimli::DW_AT_dwo_name).ok()? {
+        Some(name) => name,
+        None => root.attr_value(gimli::DW_AT_GNU_dwo_name).ok()??,
+    };
+    let name = dwarf.attr_string(&unit, name).ok()?;
+
+    Some(String::from_utf8_lossy(&name).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_id, crc32, debug_link};
+
+    #[test]
+    fn build_id_is_read_from_the_note() {
+        let mut note = vec![4, 0, 0, 0, 4, 0, 0, 0, 3, 0, 0, 0];
+        note.extend(b"GNU\0");
+        note.extend(&[0xde, 0xad, 0xbe, 0xef]);
+
+        assert_eq!(build_id(&note), Some(&[0xde, 0xad, 0xbe, 0xef][..]));
+
+        note[8] = 1;
+        assert_eq!(build_id(&note), None);
+    }
+
+    #[test]
+    fn debug_link_is_parsed() {
+        let mut section = b"firmware.debug\0".to_vec();
+        section.push(0);
+        section.extend(&0x1234_5678u32.to_le_bytes());
+
+        assert_eq!(debug_link(&section), Some(("firmware.debug", 0x1234_5678)));
+        assert_eq!(debug_link(b"firmware.debug"), None);
+    }
+
+    #[test]
+    fn crc_is_the_one_of_zlib() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+}
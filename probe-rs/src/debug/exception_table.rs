@@ -0,0 +1,302 @@
+//! Unwinding with the ARM exception tables, `.ARM.exidx` and `.ARM.extab`.
+//!
+//! The exception tables are part of many ARM binaries which carry no DWARF call frame
+//! information, e.g. because they were stripped. The index table `.ARM.exidx` has an entry for
+//! each function, which either contains the unwind instructions of the function, refers to
+//! them in `.ARM.extab`, or marks the function as not unwindable.
+//!
+//! Only the compact model of the ARM EHABI is supported, which is used by the personality
+//! routines `__aeabi_unwind_cpp_pr0` to `pr2`. Entries of the generic model, e.g. of C++
+//! functions with catch handlers, are not unwound.
+
+use super::{Registers, UnwindError};
+
+use std::convert::TryInto;
+
+/// The value of an index table entry for a function which can not be unwound.
+const EXIDX_CANTUNWIND: u32 = 1;
+
+/// The unwind entry of a function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Entry {
+    /// The function can not be unwound, e.g. because it never returns.
+    CantUnwind,
+    /// The unwind instructions of the function.
+    Instructions(Vec<u8>),
+    /// The entry uses the generic model, or is damaged.
+    Unsupported,
+}
+
+/// The exception tables of an ELF file.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ExceptionTable {
+    /// The start addresses of the functions and their unwind entries, sorted by address.
+    entries: Vec<(u32, Entry)>,
+}
+
+impl ExceptionTable {
+    /// Parses the index table `exidx`, and the unwind instructions it refers to in `extab`,
+    /// which are loaded at `exidx_address` and `extab_address`.
+    pub(crate) fn parse(
+        exidx: &[u8],
+        exidx_address: u32,
+        extab: &[u8],
+        extab_address: u32,
+    ) -> Self {
+        let mut entries: Vec<_> = exidx
+            .chunks_exact(8)
+            .enumerate()
+            .map(|(index, entry)| {
+                let address = exidx_address + index as u32 * 8;
+                let function = prel31(word(entry, 0).unwrap(), address);
+                let data = word(entry, 4).unwrap();
+
+                let entry = if data == EXIDX_CANTUNWIND {
+                    Entry::CantUnwind
+                } else if data & 0x8000_0000 != 0 {
+                    // Only personality routine 0 fits into the index table.
+                    compact_instructions(data, |_| None)
+                } else {
+                    let offset = prel31(data, address + 4).wrapping_sub(extab_address) as usize;
+                    match word(extab, offset) {
+                        Some(first) if first & 0x8000_0000 != 0 => {
+                            compact_instructions(first, |index| word(extab, offset + 4 * index))
+                        }
+                        _ => Entry::Unsupported,
+                    }
+                };
+
+                (function, entry)
+            })
+            .collect();
+
+        // The linker sorts the table already, but the lookup depends on it.
+        entries.sort_by_key(|(function, _)| *function);
+
+        Self { entries }
+    }
+
+    /// Returns `true` if the table has no entries, e.g. because the ELF file has no exception tables.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the unwind entry of the function which contains `pc`.
+    ///
+    /// The entry of a function is valid up to the start of the next function, so `pc` has to be
+    /// an address in the code.
+    pub(crate) fn entry(&self, pc: u32) -> Option<&Entry> {
+        let index = match self
+            .entries
+            .binary_search_by_key(&pc, |(function, _)| *function)
+        {
+            Ok(index) => index,
+            Err(0) => return None,
+            Err(index) => index - 1,
+        };
+
+        Some(&self.entries[index].1)
+    }
+}
+
+/// Resolves a place-relative offset of 31 bits, which is stored at `address`.
+fn prel31(word: u32, address: u32) -> u32 {
+    let offset = ((word << 1) as i32) >> 1;
+    address.wrapping_add(offset as u32)
+}
+
+fn word(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+/// Extracts the unwind instructions of the compact model from their `first` word.
+///
+/// `additional` returns the words which follow the first one, for personality routines 1 and 2.
+fn compact_instructions(first: u32, additional: impl Fn(usize) -> Option<u32>) -> Entry {
+    let bytes = first.to_be_bytes();
+
+    match (first >> 24) & 0xf {
+        0 => Entry::Instructions(bytes[1..].to_vec()),
+        1 | 2 => {
+            let mut instructions = bytes[2..].to_vec();
+            for index in 1..=usize::from(bytes[1]) {
+                match additional(index) {
+                    Some(word) => instructions.extend_from_slice(&word.to_be_bytes()),
+                    None => return Entry::Unsupported,
+                }
+            }
+            Entry::Instructions(instructions)
+        }
+        _ => Entry::Unsupported,
+    }
+}
+
+/// Executes the unwind `instructions` of a function on the `registers` of its frame, which
+/// become the registers of the caller.
+///
+/// The return address is stored in LR, also if the instructions restore it into the PC.
+pub(super) fn unwind(
+    instructions: &[u8],
+    registers: &mut Registers,
+    mut read: impl FnMut(u32) -> Result<u32, crate::Error>,
+) -> Result<(), UnwindError> {
+    let mut vsp = registers[13].ok_or(UnwindError::MissingRegister(13))?;
+    registers[15] = None;
+
+    let mut bytes = instructions.iter().copied();
+    while let Some(instruction) = bytes.next() {
+        let mut operand = || {
+            bytes
+                .next()
+                .ok_or(UnwindError::InvalidInstruction(instruction))
+        };
+
+        match instruction {
+            0x00..=0x3f => vsp = vsp.wrapping_add((u32::from(instruction & 0x3f) << 2) + 4),
+            0x40..=0x7f => vsp = vsp.wrapping_sub((u32::from(instruction & 0x3f) << 2) + 4),
+            0x80..=0x8f => {
+                let mask = u16::from(instruction & 0xf) << 8 | u16::from(operand()?);
+                if mask == 0 {
+                    // The function refuses to be unwound.
+                    return Err(UnwindError::InvalidInstruction(instruction));
+                }
+                pop(&mut read, &mut vsp, registers, mask, 4)?;
+                if mask & (1 << 9) != 0 {
+                    vsp = registers[13].unwrap();
+                }
+            }
+            0x90..=0x9f => match usize::from(instruction & 0xf) {
+                13 | 15 => return Err(UnwindError::InvalidInstruction(instruction)),
+                register => {
+                    vsp = registers[register].ok_or(UnwindError::MissingRegister(register))?
+                }
+            },
+            0xa0..=0xaf => {
+                let mut mask = (1 << ((instruction & 0x7) + 1)) - 1;
+                if instruction & 0x8 != 0 {
+                    // R14 follows R4 to R11 in the mask.
+                    mask |= 1 << 10;
+                }
+                pop(&mut read, &mut vsp, registers, mask, 4)?;
+            }
+            0xb0 => break,
+            0xb1 => match operand()? {
+                mask @ 0x01..=0x0f => pop(&mut read, &mut vsp, registers, u16::from(mask), 0)?,
+                _ => return Err(UnwindError::InvalidInstruction(instruction)),
+            },
+            0xb2 => {
+                let mut value = 0u32;
+                for shift in (0..32).step_by(7) {
+                    let byte = operand()?;
+                    value |= u32::from(byte & 0x7f) << shift;
+                    if byte & 0x80 == 0 {
+                        break;
+                    }
+                }
+                vsp = vsp.wrapping_add(0x204 + (value << 2));
+            }
+            // Registers which were saved with FSTMFDX, which stores an additional word.
+            0xb3 => vsp = vsp.wrapping_add((u32::from(operand()? & 0xf) + 1) * 8 + 4),
+            0xb8..=0xbf => vsp = vsp.wrapping_add((u32::from(instruction & 0x7) + 1) * 8 + 4),
+            // Registers which were saved with VPUSH.
+            0xc8 | 0xc9 => vsp = vsp.wrapping_add((u32::from(operand()? & 0xf) + 1) * 8),
+            0xd0..=0xd7 => vsp = vsp.wrapping_add((u32::from(instruction & 0x7) + 1) * 8),
+            _ => return Err(UnwindError::InvalidInstruction(instruction)),
+        }
+    }
+
+    registers[13] = Some(vsp);
+    if let Some(pc) = registers[15] {
+        registers[14] = Some(pc);
+    }
+
+    Ok(())
+}
+
+/// Pops the registers in `mask` from the stack at `vsp`, bit 0 of the mask is register `first`.
+fn pop(
+    read: &mut impl FnMut(u32) -> Result<u32, crate::Error>,
+    vsp: &mut u32,
+    registers: &mut Registers,
+    mask: u16,
+    first: usize,
+) -> Result<(), UnwindError> {
+    for bit in (0..16).filter(|bit| mask & (1 << bit) != 0) {
+        let value = read(*vsp).map_err(|_| UnwindError::UnreadableStack(*vsp))?;
+        registers[first + bit] = Some(value);
+        *vsp = vsp.wrapping_add(4);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{unwind, Entry, ExceptionTable};
+    use crate::core::Architecture;
+    use crate::debug::Registers;
+
+    /// An index table at 0x0800_1000, with the unwind instructions of its third entry at 0x0800_2000.
+    fn table() -> ExceptionTable {
+        let mut exidx = vec![];
+        // 0x0800_0100: pop {r4, lr}, inline with personality routine 0.
+        exidx.extend(&0x7fff_f100u32.to_le_bytes());
+        exidx.extend(&0x80a8_b0b0u32.to_le_bytes());
+        // 0x0800_0200: can not be unwound.
+        exidx.extend(&0x7fff_f1f8u32.to_le_bytes());
+        exidx.extend(&1u32.to_le_bytes());
+        // 0x0800_0300: in .ARM.extab.
+        exidx.extend(&0x7fff_f2f0u32.to_le_bytes());
+        exidx.extend(&0x0000_0fecu32.to_le_bytes());
+
+        // Personality routine 1 with one more word: add sp, #16; pop {r4-r7, lr}
+        let mut extab = vec![];
+        extab.extend(&0x8101_03abu32.to_le_bytes());
+        extab.extend(&0xb0b0_b0b0u32.to_le_bytes());
+
+        ExceptionTable::parse(&exidx, 0x0800_1000, &extab, 0x0800_2000)
+    }
+
+    #[test]
+    fn entries_are_found_by_address() {
+        let table = table();
+
+        assert_eq!(table.entry(0x0800_00fe), None);
+        assert_eq!(
+            table.entry(0x0800_0100),
+            Some(&Entry::Instructions(vec![0xa8, 0xb0, 0xb0]))
+        );
+        assert_eq!(table.entry(0x0800_0202), Some(&Entry::CantUnwind));
+        assert_eq!(
+            table.entry(0x0800_0400),
+            Some(&Entry::Instructions(vec![
+                0x03, 0xab, 0xb0, 0xb0, 0xb0, 0xb0
+            ]))
+        );
+    }
+
+    #[test]
+    fn registers_are_restored_from_the_stack() {
+        let stack = |address| Ok(address - 0x2000_0000);
+
+        let mut registers = Registers::new(Architecture::Arm);
+        registers[13] = Some(0x2000_0100);
+        unwind(&[0x03, 0xab, 0xb0], &mut registers, stack).unwrap();
+
+        // 16 bytes of locals, then R4 to R7 and LR.
+        assert_eq!(registers[4], Some(0x110));
+        assert_eq!(registers[7], Some(0x11c));
+        assert_eq!(registers[14], Some(0x120));
+        assert_eq!(registers[13], Some(0x2000_0124));
+
+        // pop {r4, pc} stores the return address in the PC.
+        let mut registers = Registers::new(Architecture::Arm);
+        registers[13] = Some(0x2000_0100);
+        unwind(&[0x88, 0x01], &mut registers, stack).unwrap();
+
+        assert_eq!(registers[4], Some(0x100));
+        assert_eq!(registers[14], Some(0x104));
+        assert_eq!(registers[13], Some(0x2000_0108));
+    }
+}
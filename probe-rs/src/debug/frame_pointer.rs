@@ -0,0 +1,136 @@
+//! A last resort unwinder, which follows the chain of frame records on the stack.
+//!
+//! Code which keeps a frame pointer, e.g. code compiled with `-C force-frame-pointers=yes`,
+//! saves the frame pointer and the return address of each function in a frame record on the
+//! stack, and points the frame pointer to it. In code without frame pointers the register holds
+//! arbitrary values, so every record has to be on the stack, and above the stack pointer.
+
+use super::{Registers, UnwindError};
+use crate::core::Architecture;
+
+use std::ops::Range;
+
+/// The frame pointer of Thumb code, R7.
+const THUMB_FRAME_POINTER: usize = 7;
+/// The frame pointer of ARM code, R11.
+const ARM_FRAME_POINTER: usize = 11;
+/// The frame pointer of RISC-V, s0 or fp.
+const RISCV_FRAME_POINTER: usize = 8;
+
+/// Unwinds a frame by following its frame pointer, and checks that the frame record
+/// is inside one of the `stack` regions, if any are known.
+///
+/// `thumb` selects the frame pointer of ARM cores, which depends on the instruction set
+/// of the function.
+pub(super) fn unwind(
+    registers: &mut Registers,
+    thumb: bool,
+    stack: &[Range<u32>],
+    mut read: impl FnMut(u32) -> Result<u32, crate::Error>,
+) -> Result<(), UnwindError> {
+    let architecture = registers.architecture();
+    let frame_pointer = match architecture {
+        Architecture::Arm if thumb => THUMB_FRAME_POINTER,
+        Architecture::Arm => ARM_FRAME_POINTER,
+        Architecture::Riscv => RISCV_FRAME_POINTER,
+    };
+
+    let fp = registers[frame_pointer].ok_or(UnwindError::MissingRegister(frame_pointer))?;
+    let sp = registers
+        .get_call_frame_address()
+        .ok_or(UnwindError::MissingRegister(registers.stack_pointer()))?;
+
+    // The ARM frame pointer points to the record, which is followed by the frame of the caller.
+    // The RISC-V frame pointer points to the frame of the caller, right above the record.
+    let record = match architecture {
+        Architecture::Arm => fp,
+        Architecture::Riscv => fp.wrapping_sub(8),
+    };
+    let record_end = record.checked_add(8);
+
+    let is_valid = fp != 0
+        && fp % 4 == 0
+        && record >= sp
+        && record_end.map_or(false, |end| {
+            stack.is_empty()
+                || stack
+                    .iter()
+                    .any(|region| region.start <= record && end <= region.end)
+        });
+    if !is_valid {
+        return Err(UnwindError::InvalidFramePointer(fp));
+    }
+
+    let caller_sp = match architecture {
+        Architecture::Arm => record + 8,
+        Architecture::Riscv => fp,
+    };
+
+    let mut read_stack = |address| read(address).map_err(|_| UnwindError::UnreadableStack(address));
+    let saved_fp = read_stack(record)?;
+    let return_address = read_stack(record + 4)?;
+
+    if return_address == 0 {
+        return Err(UnwindError::InvalidFramePointer(fp));
+    }
+
+    registers[frame_pointer] = Some(saved_fp);
+    registers.set_return_address(Some(return_address));
+    registers.set_call_frame_address(Some(caller_sp));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{unwind, UnwindError};
+    use crate::core::Architecture;
+    use crate::debug::Registers;
+
+    const STACK: &[std::ops::Range<u32>] = &[0x2000_0000..0x2000_1000];
+
+    /// A stack which contains a value at each address.
+    fn stack(address: u32) -> Result<u32, crate::Error> {
+        Ok(address + 1)
+    }
+
+    #[test]
+    fn thumb_frame_records_are_followed() {
+        let mut registers = Registers::new(Architecture::Arm);
+        registers[7] = Some(0x2000_0108);
+        registers[13] = Some(0x2000_0100);
+
+        unwind(&mut registers, true, STACK, stack).unwrap();
+
+        assert_eq!(registers[7], Some(0x2000_0109));
+        assert_eq!(registers[14], Some(0x2000_010d));
+        assert_eq!(registers[13], Some(0x2000_0110));
+    }
+
+    #[test]
+    fn riscv_frame_records_are_followed() {
+        let mut registers = Registers::new(Architecture::Riscv);
+        registers[8] = Some(0x2000_0110);
+        registers[2] = Some(0x2000_0100);
+
+        unwind(&mut registers, false, STACK, stack).unwrap();
+
+        assert_eq!(registers[8], Some(0x2000_0109));
+        assert_eq!(registers[1], Some(0x2000_010d));
+        assert_eq!(registers[2], Some(0x2000_0110));
+    }
+
+    #[test]
+    fn frame_pointers_outside_of_the_stack_are_rejected() {
+        for fp in &[0, 0x2000_0102, 0x2000_00f0, 0x0800_0100, 0x2000_0ffc] {
+            let mut registers = Registers::new(Architecture::Arm);
+            registers[7] = Some(*fp);
+            registers[13] = Some(0x2000_0100);
+
+            match unwind(&mut registers, true, STACK, stack) {
+                Err(UnwindError::InvalidFramePointer(invalid)) => assert_eq!(invalid, *fp),
+                other => panic!("Frame pointer {:#010x} was accepted: {:?}", fp, other),
+            }
+        }
+    }
+}
@@ -3,23 +3,44 @@
 //! The `debug` module contains various debug functionality, which can be
 //! used to implement a debugger based on `probe-rs`.
 
+mod breakpoints;
+mod debug_file;
+mod exception_table;
+mod frame_pointer;
+mod range_step;
+mod recording;
+mod rtos;
 mod typ;
+mod unit_index;
 mod variable;
 
-use crate::{core::Core, MemoryInterface};
+use crate::config::MemoryRegion;
+use crate::core::{Architecture, Core};
+use crate::{CoreRegisterAddress, MemoryInterface};
+pub use breakpoints::{BreakpointKind, BreakpointLocation, BreakpointManager, ManagedBreakpoint};
+use exception_table::ExceptionTable;
+pub use range_step::{RangeStep, RangeStepStatus};
+pub use recording::{PendingStep, RecordedStep, StepRecorder};
+pub use rtos::{detect_rtos, FreeRtos, RtosError, RtosProvider, RtosThread};
 use typ::Type;
+use unit_index::UnitIndex;
 use variable::Variable;
 
 use std::{
-    borrow, io,
+    borrow,
+    cell::RefCell,
+    collections::HashMap,
+    io,
+    ops::Range,
     path::{Path, PathBuf},
     rc::Rc,
     str::{from_utf8, Utf8Error},
 };
 
 use gimli::{FileEntry, LineProgramHeader};
-use log::{debug, error, info};
-use object::read::{Object, ObjectSection};
+use log::{debug, error};
+use object::read::{Object, ObjectSection, ObjectSymbol};
+use object::SymbolKind;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -34,6 +55,28 @@ pub enum DebugError {
     NonUtf8(#[from] Utf8Error),
     #[error("Error using the probe")]
     Probe(#[from] crate::Error),
+    #[error("The separate debug file `{0}` could not be found, add its directory to the debug file directories")]
+    DebugFileNotFound(String),
+    #[error("The separate debug file {} does not belong to the ELF file, its CRC is {actual:#010x} instead of {expected:#010x}", .path.display())]
+    DebugFileMismatch {
+        path: PathBuf,
+        expected: u32,
+        actual: u32,
+    },
+    #[error("The variable at {address:#010x} was not read, the memory region {region} has side effects on reads")]
+    ReadSideEffects { address: u64, region: String },
+}
+
+/// Where the debug information of an ELF file is looked up, and cached.
+#[derive(Debug, Clone, Default)]
+pub struct DebugInfoOptions {
+    /// The directory in which the index of the compilation units is cached.
+    pub cache_dir: Option<PathBuf>,
+    /// The directories in which the separate debug files of stripped ELF files are looked up,
+    /// by build ID and by the name in `.gnu_debuglink`.
+    ///
+    /// The debug link is looked up next to the ELF file too, and in its `.debug` subdirectory.
+    pub debug_file_directories: Vec<PathBuf>,
 }
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum ColumnType {
@@ -50,11 +93,39 @@ impl From<gimli::ColumnType> for ColumnType {
     }
 }
 
+/// How a stack frame was unwound, i.e. how the return address to its caller was found.
+///
+/// The strategies are tried in this order, and the later ones are less reliable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnwindStrategy {
+    /// The DWARF call frame information in `.debug_frame`.
+    Dwarf,
+    /// The ARM exception tables in `.ARM.exidx` and `.ARM.extab`.
+    ExceptionTable,
+    /// The chain of frame records, which the frame pointer points to.
+    ///
+    /// This only works for code which keeps a frame pointer, otherwise the frame records are
+    /// guessed from the register, and the rest of the backtrace can be wrong.
+    FramePointer,
+}
+
+impl std::fmt::Display for UnwindStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            UnwindStrategy::Dwarf => write!(f, "DWARF"),
+            UnwindStrategy::ExceptionTable => write!(f, "the exception table"),
+            UnwindStrategy::FramePointer => write!(f, "the frame pointer"),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct StackFrame {
     pub id: u64,
     pub function_name: String,
     pub source_location: Option<SourceLocation>,
+    /// The strategy which unwound the frame, or `None` if the backtrace ends with this frame.
+    pub unwind_strategy: Option<UnwindStrategy>,
     registers: Registers,
     pc: u32,
     pub variables: Vec<Variable>,
@@ -62,7 +133,11 @@ pub struct StackFrame {
 
 impl std::fmt::Display for StackFrame {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        writeln!(f, "{}: {}", self.id, self.function_name)?;
+        write!(f, "{}: {}", self.id, self.function_name)?;
+        if let Some(strategy) = self.unwind_strategy {
+            write!(f, " (unwound with {})", strategy)?;
+        }
+        writeln!(f)?;
         if let Some(si) = &self.source_location {
             write!(
                 f,
@@ -97,23 +172,71 @@ impl std::fmt::Display for StackFrame {
 }
 
 #[derive(Debug, Clone)]
-struct Registers([Option<u32>; 16]);
+struct Registers {
+    values: [Option<u32>; 16],
+    architecture: Architecture,
+}
 
 impl Registers {
+    pub fn new(architecture: Architecture) -> Self {
+        Registers {
+            values: [None; 16],
+            architecture,
+        }
+    }
+
     pub fn from_core(core: &mut Core) -> Self {
-        let mut registers = Registers([None; 16]);
-        for i in 0..16 {
-            registers[i as usize] = core.read_core_reg(i).ok();
+        let mut registers = Registers::new(core.architecture());
+        let addresses: Vec<_> = (0..16)
+            .map(|i| {
+                // The general purpose registers of RISC-V are x0 to x15, at the abstract register numbers from 0x1000.
+                match registers.architecture {
+                    Architecture::Arm => CoreRegisterAddress(i),
+                    Architecture::Riscv => CoreRegisterAddress(0x1000 + i),
+                }
+            })
+            .collect();
+
+        for (i, value) in core.read_core_regs(&addresses).into_iter().enumerate() {
+            registers[i] = value.ok();
         }
         registers
     }
 
+    pub fn architecture(&self) -> Architecture {
+        self.architecture
+    }
+
+    /// The number of the stack pointer, SP or x2.
+    pub fn stack_pointer(&self) -> usize {
+        match self.architecture {
+            Architecture::Arm => 13,
+            Architecture::Riscv => 2,
+        }
+    }
+
+    /// The number of the register with the return address, LR or x1.
+    pub fn return_address(&self) -> usize {
+        match self.architecture {
+            Architecture::Arm => 14,
+            Architecture::Riscv => 1,
+        }
+    }
+
     pub fn get_call_frame_address(&self) -> Option<u32> {
-        self.0[13]
+        self.values[self.stack_pointer()]
     }
 
     pub fn set_call_frame_address(&mut self, value: Option<u32>) {
-        self.0[13] = value;
+        self.values[self.stack_pointer()] = value;
+    }
+
+    pub fn get_return_address(&self) -> Option<u32> {
+        self.values[self.return_address()]
+    }
+
+    pub fn set_return_address(&mut self, value: Option<u32>) {
+        self.values[self.return_address()] = value;
     }
 }
 
@@ -121,13 +244,13 @@ impl std::ops::Index<usize> for Registers {
     type Output = Option<u32>;
 
     fn index(&self, index: usize) -> &Self::Output {
-        &self.0[index]
+        &self.values[index]
     }
 }
 
 impl std::ops::IndexMut<usize> for Registers {
     fn index_mut(&mut self, index: usize) -> &mut Self::Output {
-        &mut self.0[index]
+        &mut self.values[index]
     }
 }
 
@@ -135,17 +258,17 @@ impl std::ops::Index<std::ops::Range<usize>> for Registers {
     type Output = [Option<u32>];
 
     fn index(&self, index: std::ops::Range<usize>) -> &Self::Output {
-        &self.0[index]
+        &self.values[index]
     }
 }
 
 impl std::ops::IndexMut<std::ops::Range<usize>> for Registers {
     fn index_mut(&mut self, index: std::ops::Range<usize>) -> &mut Self::Output {
-        &mut self.0[index]
+        &mut self.values[index]
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct SourceLocation {
     pub line: Option<u64>,
     pub column: Option<ColumnType>,
@@ -154,12 +277,37 @@ pub struct SourceLocation {
     pub directory: Option<PathBuf>,
 }
 
+/// Why a frame could not be unwound with one of the strategies.
+#[derive(Debug, Error)]
+enum UnwindError {
+    #[error("No call frame information found")]
+    CallFrameInformation(#[from] gimli::read::Error),
+    #[error("The value of register {0} is unknown")]
+    MissingRegister(usize),
+    #[error("The stack at {0:#010x} could not be read")]
+    UnreadableStack(u32),
+    #[error("The unwind instruction {0:#04x} is not supported")]
+    InvalidInstruction(u8),
+    #[error("The frame pointer {0:#010x} does not point to a frame record on the stack")]
+    InvalidFramePointer(u32),
+}
+
+/// Unwinds the stack of a halted core.
+///
+/// Each frame is unwound with the DWARF call frame information if there is any for it,
+/// otherwise with the ARM exception tables, and as a last resort by following the frame pointer.
+/// The DWARF call frame information and the exception tables are only used on ARM cores.
 pub struct StackFrameIterator<'debuginfo, 'probe, 'core> {
     debug_info: &'debuginfo DebugInfo,
     core: &'core mut Core<'probe>,
     frame_count: u64,
     pc: Option<u64>,
     registers: Registers,
+    unreadable_stack: Option<u32>,
+    /// The RAM regions, which the frame records have to be in.
+    stack: Vec<Range<u32>>,
+    /// `true` if the function of the current frame is Thumb code.
+    thumb: bool,
 }
 
 impl<'debuginfo, 'probe, 'core> StackFrameIterator<'debuginfo, 'probe, 'core> {
@@ -177,58 +325,117 @@ impl<'debuginfo, 'probe, 'core> StackFrameIterator<'debuginfo, 'probe, 'core> {
             frame_count: 0,
             pc: Some(pc),
             registers,
+            unreadable_stack: None,
+            stack: vec![],
+            // The supported ARM cores only execute Thumb code.
+            thumb: true,
         }
     }
-}
 
-impl<'debuginfo, 'probe, 'core> Iterator for StackFrameIterator<'debuginfo, 'probe, 'core> {
-    type Item = StackFrame;
+    /// Only follows frame pointers to frame records in the RAM regions of `memory_map`.
+    ///
+    /// Without a memory map, frame records are accepted anywhere above the stack pointer.
+    pub fn with_memory_map(mut self, memory_map: &[MemoryRegion]) -> Self {
+        self.stack = memory_map
+            .iter()
+            .filter_map(|region| match region {
+                MemoryRegion::Ram(ram) => Some(ram.range.clone()),
+                _ => None,
+            })
+            .collect();
+        self
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        use gimli::UnwindSection;
-        let mut ctx = gimli::UninitializedUnwindContext::new();
-        let bases = gimli::BaseAddresses::default();
+    /// The address of the stack memory which could not be read, if the unwinding stopped there.
+    ///
+    /// This happens if the stack pointer is corrupt. The frames which were returned before are still valid.
+    pub fn unreadable_stack(&self) -> Option<u32> {
+        self.unreadable_stack
+    }
 
-        let pc = match self.pc {
-            Some(pc) => pc,
-            None => {
-                debug!("Unable to determine next frame, program counter is zero");
-                return None;
+    /// Unwinds the frame at `pc` with the first strategy which works, and returns the strategy.
+    ///
+    /// Returns `None` if the backtrace ends with the frame.
+    fn unwind(&mut self, pc: u64) -> Option<UnwindStrategy> {
+        let mut unreadable_stack = None;
+        let mut failed = |strategy: UnwindStrategy, error: UnwindError| {
+            debug!(
+                "Unable to unwind the frame at {:#010x} with {}: {}",
+                pc, strategy, error
+            );
+            if let UnwindError::UnreadableStack(address) = error {
+                unreadable_stack = Some(address);
             }
         };
 
+        if self.registers.architecture() == Architecture::Arm {
+            match self.unwind_dwarf(pc) {
+                Ok(()) => return Some(UnwindStrategy::Dwarf),
+                Err(e) => failed(UnwindStrategy::Dwarf, e),
+            }
+
+            let debug_info = self.debug_info;
+            match debug_info.exception_table.entry(pc as u32) {
+                Some(exception_table::Entry::CantUnwind) => {
+                    debug!("The function at {:#010x} can not be unwound", pc);
+                    return None;
+                }
+                Some(exception_table::Entry::Instructions(instructions)) => {
+                    let mut registers = self.registers.clone();
+                    let core = &mut self.core;
+                    match exception_table::unwind(instructions, &mut registers, |address| {
+                        core.read_word_32(address)
+                    }) {
+                        Ok(()) => {
+                            self.registers = registers;
+                            return Some(UnwindStrategy::ExceptionTable);
+                        }
+                        Err(e) => failed(UnwindStrategy::ExceptionTable, e),
+                    }
+                }
+                Some(exception_table::Entry::Unsupported) | None => {}
+            }
+        }
+
+        let mut registers = self.registers.clone();
+        let core = &mut self.core;
+        match frame_pointer::unwind(&mut registers, self.thumb, &self.stack, |address| {
+            core.read_word_32(address)
+        }) {
+            Ok(()) => {
+                self.registers = registers;
+                Some(UnwindStrategy::FramePointer)
+            }
+            Err(e) => {
+                failed(UnwindStrategy::FramePointer, e);
+                self.unreadable_stack = unreadable_stack;
+                None
+            }
+        }
+    }
+
+    /// Unwinds the frame at `pc` with the DWARF call frame information.
+    ///
+    /// If a register can not be read from the stack, the frame is still unwound,
+    /// but the backtrace ends with it.
+    fn unwind_dwarf(&mut self, pc: u64) -> Result<(), UnwindError> {
+        use gimli::UnwindSection;
+        let mut ctx = gimli::UninitializedUnwindContext::new();
+        let bases = gimli::BaseAddresses::default();
+
         let unwind_info = self.debug_info.frame_section.unwind_info_for_address(
             &bases,
             &mut ctx,
             pc,
             gimli::DebugFrame::cie_from_offset,
-        );
-
-        let unwind_info = match unwind_info {
-            Ok(uw) => uw,
-            Err(e) => {
-                info!(
-                    "Failed to retrieve debug information for program counter {:#x}: {}",
-                    pc, e
-                );
-                return None;
-            }
-        };
+        )?;
 
         let current_cfa = match unwind_info.cfa() {
             gimli::CfaRule::RegisterAndOffset { register, offset } => {
-                let reg_val = self.registers[register.0 as usize];
+                let reg_val = self.registers[register.0 as usize]
+                    .ok_or(UnwindError::MissingRegister(register.0 as usize))?;
 
-                match reg_val {
-                    Some(reg_val) => Some((i64::from(reg_val) + offset) as u32),
-                    None => {
-                        log::warn!(
-                            "Unable to calculate CFA: Missing value of register {}",
-                            register.0
-                        );
-                        return None;
-                    }
-                }
+                Some((i64::from(reg_val) + offset) as u32)
             }
             gimli::CfaRule::Expression(_) => unimplemented!(),
         };
@@ -263,15 +470,28 @@ impl<'debuginfo, 'probe, 'core> Iterator for StackFrameIterator<'debuginfo, 'pro
                 }
                 SameValue => self.registers[i],
                 Offset(o) => {
-                    let addr = i64::from(current_cfa.unwrap()) + o;
+                    let addr = (i64::from(current_cfa.unwrap()) + o) as u32;
                     let mut buff = [0u8; 4];
-                    self.core.read_8(addr as u32, &mut buff).unwrap();
 
-                    let val = u32::from_le_bytes(buff);
+                    match self.core.read_8(addr, &mut buff) {
+                        Ok(()) => {
+                            let val = u32::from_le_bytes(buff);
 
-                    debug!("reg[{: >}]={:#08x}", i, val);
+                            debug!("reg[{: >}]={:#08x}", i, val);
 
-                    Some(val)
+                            Some(val)
+                        }
+                        Err(e) => {
+                            log::warn!(
+                                "Unable to read register {} from the stack at {:#010x}, the backtrace ends here: {}",
+                                i,
+                                addr,
+                                e
+                            );
+                            self.unreadable_stack = Some(addr);
+                            None
+                        }
+                    }
                 }
                 _ => unimplemented!(),
             }
@@ -279,11 +499,30 @@ impl<'debuginfo, 'probe, 'core> Iterator for StackFrameIterator<'debuginfo, 'pro
 
         self.registers.set_call_frame_address(current_cfa);
 
+        Ok(())
+    }
+}
+
+impl<'debuginfo, 'probe, 'core> Iterator for StackFrameIterator<'debuginfo, 'probe, 'core> {
+    type Item = StackFrame;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let pc = match self.pc {
+            Some(pc) => pc,
+            None => {
+                debug!("Unable to determine next frame, program counter is zero");
+                return None;
+            }
+        };
+
+        let unwind_strategy = self.unwind(pc);
+
         let return_frame = match self.debug_info.get_stackframe_info(
             &mut self.core,
             pc,
             self.frame_count,
             self.registers.clone(),
+            unwind_strategy,
         ) {
             Ok(frame) => Some(frame),
             Err(e) => {
@@ -299,12 +538,38 @@ impl<'debuginfo, 'probe, 'core> Iterator for StackFrameIterator<'debuginfo, 'pro
         //
         // We also have to subtract one, as we want the calling instruction for
         // a backtrace, not the next instruction to be executed.
-        self.pc = self.registers[14].map(|pc| u64::from(pc & !1) - 1);
+        self.pc = match (unwind_strategy, self.unreadable_stack) {
+            (Some(_), None) => self
+                .registers
+                .get_return_address()
+                .and_then(|return_address| self.caller_pc(return_address)),
+            _ => None,
+        };
 
         return_frame
     }
 }
 
+impl StackFrameIterator<'_, '_, '_> {
+    /// Returns the address of the call instruction, which `return_address` returns to.
+    fn caller_pc(&mut self, return_address: u32) -> Option<u64> {
+        if self.registers.architecture() == Architecture::Arm {
+            // Exception frames are not unwound, also not the one of the reset value of LR.
+            if return_address >= 0xffff_ff00 {
+                debug!(
+                    "The return address {:#010x} returns from an exception, the backtrace ends here",
+                    return_address
+                );
+                return None;
+            }
+
+            self.thumb = return_address & 1 != 0;
+        }
+
+        u64::from(return_address & !1).checked_sub(1)
+    }
+}
+
 type R = gimli::EndianReader<gimli::LittleEndian, std::rc::Rc<[u8]>>;
 type DwarfReader = gimli::read::EndianRcSlice<gimli::LittleEndian>;
 type FunctionDie<'abbrev, 'unit> = gimli::DebuggingInformationEntry<
@@ -318,13 +583,34 @@ type EntriesCursor<'abbrev, 'unit> = gimli::EntriesCursor<
     'unit,
     gimli::EndianReader<gimli::LittleEndian, std::rc::Rc<[u8]>>,
 >;
-type UnitIter =
-    gimli::DebugInfoUnitHeadersIter<gimli::EndianReader<gimli::LittleEndian, std::rc::Rc<[u8]>>>;
+/// A line program which was run, and its sequences.
+type LineProgram = (
+    gimli::CompleteLineProgram<DwarfReader>,
+    Vec<gimli::LineSequence<DwarfReader>>,
+);
 
 /// Debug information which is parsed from DWARF debugging information.
+///
+/// The compilation units are parsed when they are used first, and found by address through an
+/// index of their address ranges. The parsed units, their line programs and the source locations
+/// which were looked up are kept.
 pub struct DebugInfo {
     dwarf: gimli::Dwarf<DwarfReader>,
+    unit_index: UnitIndex,
+    /// The units which were parsed, by their offset in `.debug_info`.
+    units: RefCell<HashMap<usize, Rc<gimli::Unit<DwarfReader>>>>,
+    /// The line programs which were run, by the offset of their unit.
+    line_programs: RefCell<HashMap<usize, Rc<LineProgram>>>,
+    /// The source locations which were looked up, by address.
+    source_locations: RefCell<HashMap<u64, Option<SourceLocation>>>,
     frame_section: gimli::DebugFrame<DwarfReader>,
+    /// The addresses of the symbols of the ELF file, by name.
+    symbols: HashMap<String, u64>,
+    /// The address, size and name of the function symbols of the ELF file, sorted by address.
+    functions: Vec<(u64, u64, String)>,
+    exception_table: ExceptionTable,
+    /// True if variables in regions with side effects on reads are read.
+    read_side_effects: bool,
 }
 
 impl DebugInfo {
@@ -335,13 +621,67 @@ impl DebugInfo {
         DebugInfo::from_raw(&data)
     }
 
+    /// Read debug info from a ELF file, and cache the index of its compilation units in `cache_dir`.
+    ///
+    /// The index is stored under the build ID of the file, and loaded from there when the same
+    /// file is read again, which saves most of the time it takes to read large files.
+    /// Files without a build ID are not cached.
+    pub fn from_file_cached<P: AsRef<Path>>(
+        path: P,
+        cache_dir: &Path,
+    ) -> Result<DebugInfo, DebugError> {
+        DebugInfo::from_file_with_options(
+            path,
+            &DebugInfoOptions {
+                cache_dir: Some(cache_dir.to_owned()),
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Read debug info from a ELF file, or from its separate debug file if it is stripped.
+    ///
+    /// Fails with [DebugError::DebugFileNotFound] or [DebugError::DebugFileMismatch] if the
+    /// ELF file names a separate debug file which is missing, or does not belong to it.
+    pub fn from_file_with_options<P: AsRef<Path>>(
+        path: P,
+        options: &DebugInfoOptions,
+    ) -> Result<DebugInfo, DebugError> {
+        let path = path.as_ref();
+        let data = std::fs::read(path)?;
+
+        let object = object::File::parse(&data[..])?;
+        let has_dwarf = object
+            .section_by_name(".debug_info")
+            .map_or(false, |section| section.size() > 0);
+
+        let debug_data = if has_dwarf {
+            None
+        } else {
+            debug_file::find(path, &object, &options.debug_file_directories)?
+        };
+
+        DebugInfo::parse(&data, debug_data.as_deref(), options.cache_dir.as_deref())
+    }
+
     /// Parse debug information directly from a buffer containing an ELF file.
     pub fn from_raw(data: &[u8]) -> Result<Self, DebugError> {
+        DebugInfo::parse(data, None, None)
+    }
+
+    /// Parses the ELF file `data`, whose DWARF sections and symbols are in `debug_data`
+    /// if it has a separate debug file.
+    fn parse(
+        data: &[u8],
+        debug_data: Option<&[u8]>,
+        cache_dir: Option<&Path>,
+    ) -> Result<Self, DebugError> {
         let object = object::File::parse(data)?;
+        let debug_object = object::File::parse(debug_data.unwrap_or(data))?;
 
         // Load a section and return as `Cow<[u8]>`.
         let load_section = |id: gimli::SectionId| -> Result<DwarfReader, gimli::Error> {
-            let data = object
+            let data = debug_object
                 .section_by_name(id.name())
                 .and_then(|section| section.uncompressed_data().ok())
                 .unwrap_or_else(|| borrow::Cow::Borrowed(&[][..]));
@@ -363,6 +703,48 @@ impl DebugInfo {
         // Load all of the sections.
         let dwarf_cow = gimli::Dwarf::load(&load_section, &load_section_sup)?;
 
+        if let Some(name) = debug_file::split_dwarf_name(&dwarf_cow) {
+            log::warn!(
+                "The debug information is split into files like {}, which are not supported. Build without `-gsplit-dwarf` to get source locations and variables.",
+                name
+            );
+        }
+
+        let build_id = object
+            .section_by_name(".note.gnu.build-id")
+            .and_then(|section| section.data().ok())
+            .and_then(debug_file::build_id);
+        let cached_index = match (cache_dir, build_id) {
+            (Some(cache_dir), Some(build_id)) => UnitIndex::load(cache_dir, build_id),
+            _ => None,
+        };
+
+        let unit_index = match cached_index {
+            Some(unit_index) => {
+                debug!("Loaded the index of the compilation units from the cache");
+                unit_index
+            }
+            None => {
+                let aranges = debug_object
+                    .section_by_name(".debug_aranges")
+                    .and_then(|section| section.uncompressed_data().ok())
+                    .unwrap_or_else(|| borrow::Cow::Borrowed(&[][..]));
+                let unit_index = UnitIndex::build(&dwarf_cow, &aranges)?;
+
+                if let (Some(cache_dir), Some(build_id)) = (cache_dir, build_id) {
+                    if let Err(e) = unit_index.store(cache_dir, build_id) {
+                        log::warn!(
+                            "Unable to cache the index of the compilation units in {}: {}",
+                            cache_dir.display(),
+                            e
+                        );
+                    }
+                }
+
+                unit_index
+            }
+        };
+
         use gimli::Section;
         let mut frame_section = gimli::DebugFrame::load(load_section)?;
 
@@ -370,122 +752,207 @@ impl DebugInfo {
         // we have to set the address size here.
         frame_section.set_address_size(4);
 
+        let symbols = debug_object
+            .symbols()
+            .filter(|symbol| symbol.is_definition())
+            .filter_map(|symbol| {
+                let address = match symbol.kind() {
+                    // The Thumb bit of ARM functions is not part of the address of the first instruction.
+                    SymbolKind::Text => symbol.address() & !1,
+                    _ => symbol.address(),
+                };
+                Some((symbol.name().ok()?.to_owned(), address))
+            })
+            .collect();
+
+        // The function symbols name the functions of binaries without DWARF debug information.
+        let mut functions: Vec<_> = debug_object
+            .symbols()
+            .filter(|symbol| symbol.is_definition() && symbol.kind() == SymbolKind::Text)
+            .filter_map(|symbol| {
+                Some((
+                    symbol.address() & !1,
+                    symbol.size(),
+                    symbol.name().ok()?.to_owned(),
+                ))
+            })
+            .collect();
+        functions.sort();
+
+        let section = |name| {
+            object
+                .section_by_name(name)
+                .and_then(|section| Some((section.address() as u32, section.data().ok()?)))
+                .unwrap_or((0, &[][..]))
+        };
+        let (exidx_address, exidx) = section(".ARM.exidx");
+        let (extab_address, extab) = section(".ARM.extab");
+        let exception_table = ExceptionTable::parse(exidx, exidx_address, extab, extab_address);
+
+        if !exception_table.is_empty() {
+            debug!("Found the ARM exception tables, they are used to unwind functions without call frame information");
+        }
+
         Ok(DebugInfo {
             //object,
             dwarf: dwarf_cow,
+            unit_index,
+            units: RefCell::new(HashMap::new()),
+            line_programs: RefCell::new(HashMap::new()),
+            source_locations: RefCell::new(HashMap::new()),
             frame_section,
+            symbols,
+            functions,
+            exception_table,
+            read_side_effects: false,
         })
     }
 
+    /// Selects if the values of variables are read from memory regions with side effects on reads.
+    ///
+    /// By default, such variables are not read and their value is unknown, so that looking at them
+    /// does not change the state of the target, e.g. by clearing the status flags of a peripheral.
+    pub fn set_read_side_effects(&mut self, read_side_effects: bool) {
+        self.read_side_effects = read_side_effects;
+    }
+
     pub fn get_source_location(&self, address: u64) -> Option<SourceLocation> {
-        let mut units = self.dwarf.units();
+        if let Some(location) = self.source_locations.borrow().get(&address) {
+            return location.clone();
+        }
 
-        while let Ok(Some(header)) = units.next() {
-            let unit = match self.dwarf.unit(header) {
-                Ok(unit) => unit,
-                Err(_) => continue,
-            };
+        let location = self.find_source_location(address);
+        self.source_locations
+            .borrow_mut()
+            .insert(address, location.clone());
+        location
+    }
 
-            let mut ranges = self.dwarf.unit_ranges(&unit).unwrap();
+    fn find_source_location(&self, address: u64) -> Option<SourceLocation> {
+        let offset = self.unit_index.find(address)?;
+        let unit = self.unit(offset)?;
+        let line_program = self.line_program(offset, &unit)?;
+        let (program, sequences) = &*line_program;
 
-            while let Ok(Some(range)) = ranges.next() {
-                if (range.begin <= address) && (address < range.end) {
-                    //debug!("Unit: {:?}", unit.name.as_ref().and_then(|raw_name| std::str::from_utf8(&raw_name).ok()).unwrap_or("<unknown>") );
+        // normalize address
+        let mut target_seq = None;
 
-                    // get function name
+        for seq in sequences {
+            //println!("Seq 0x{:08x} - 0x{:08x}", seq.start, seq.end);
+            if (seq.start <= address) && (address < seq.end) {
+                target_seq = Some(seq);
+                break;
+            }
+        }
 
-                    let ilnp = match unit.line_program.as_ref() {
-                        Some(ilnp) => ilnp,
-                        None => return None,
-                    };
+        target_seq.as_ref()?;
 
-                    let (program, sequences) = ilnp.clone().sequences().unwrap();
+        let mut previous_row: Option<gimli::LineRow> = None;
 
-                    // normalize address
-                    let mut target_seq = None;
+        let mut rows = program.resume_from(target_seq.as_ref().expect("Sequence not found"));
 
-                    for seq in sequences {
-                        //println!("Seq 0x{:08x} - 0x{:08x}", seq.start, seq.end);
-                        if (seq.start <= address) && (address < seq.end) {
-                            target_seq = Some(seq);
-                            break;
-                        }
-                    }
+        while let Ok(Some((header, row))) = rows.next_row() {
+            //println!("Row address: 0x{:08x}", row.address());
+            if row.address() == address {
+                let file = row.file(header).unwrap().path_name();
+                let file_name_str =
+                    std::str::from_utf8(&self.dwarf.attr_string(&unit, file).unwrap())
+                        .unwrap()
+                        .to_owned();
 
-                    target_seq.as_ref()?;
-
-                    let mut previous_row: Option<gimli::LineRow> = None;
-
-                    let mut rows =
-                        program.resume_from(target_seq.as_ref().expect("Sequence not found"));
-
-                    while let Ok(Some((header, row))) = rows.next_row() {
-                        //println!("Row address: 0x{:08x}", row.address());
-                        if row.address() == address {
-                            let file = row.file(header).unwrap().path_name();
-                            let file_name_str =
-                                std::str::from_utf8(&self.dwarf.attr_string(&unit, file).unwrap())
-                                    .unwrap()
-                                    .to_owned();
-
-                            let file_dir = row.file(header).unwrap().directory(header).unwrap();
-                            let file_dir_str = std::str::from_utf8(
-                                &self.dwarf.attr_string(&unit, file_dir).unwrap(),
-                            )
-                            .unwrap()
-                            .to_owned();
-
-                            return Some(SourceLocation {
-                                line: row.line(),
-                                column: Some(row.column().into()),
-                                file: file_name_str.into(),
-                                directory: Some(file_dir_str.into()),
-                            });
-                        } else if (row.address() > address) && previous_row.is_some() {
-                            let row = previous_row.unwrap();
-
-                            let file = row.file(header).unwrap().path_name();
-                            let file_name_str =
-                                std::str::from_utf8(&self.dwarf.attr_string(&unit, file).unwrap())
-                                    .unwrap()
-                                    .to_owned();
-
-                            let file_dir = row.file(header).unwrap().directory(header).unwrap();
-                            let file_dir_str = std::str::from_utf8(
-                                &self.dwarf.attr_string(&unit, file_dir).unwrap(),
-                            )
-                            .unwrap()
-                            .to_owned();
-
-                            return Some(SourceLocation {
-                                line: row.line(),
-                                column: Some(row.column().into()),
-                                file: file_name_str.into(),
-                                directory: Some(file_dir_str.into()),
-                            });
-                        }
-                        previous_row = Some(*row);
-                    }
-                }
+                let file_dir = row.file(header).unwrap().directory(header).unwrap();
+                let file_dir_str =
+                    std::str::from_utf8(&self.dwarf.attr_string(&unit, file_dir).unwrap())
+                        .unwrap()
+                        .to_owned();
+
+                return Some(SourceLocation {
+                    line: row.line(),
+                    column: Some(row.column().into()),
+                    file: file_name_str.into(),
+                    directory: Some(file_dir_str.into()),
+                });
+            } else if (row.address() > address) && previous_row.is_some() {
+                let row = previous_row.unwrap();
+
+                let file = row.file(header).unwrap().path_name();
+                let file_name_str =
+                    std::str::from_utf8(&self.dwarf.attr_string(&unit, file).unwrap())
+                        .unwrap()
+                        .to_owned();
+
+                let file_dir = row.file(header).unwrap().directory(header).unwrap();
+                let file_dir_str =
+                    std::str::from_utf8(&self.dwarf.attr_string(&unit, file_dir).unwrap())
+                        .unwrap()
+                        .to_owned();
+
+                return Some(SourceLocation {
+                    line: row.line(),
+                    column: Some(row.column().into()),
+                    file: file_name_str.into(),
+                    directory: Some(file_dir_str.into()),
+                });
             }
+            previous_row = Some(*row);
         }
         None
     }
 
-    fn get_units(&self) -> UnitIter {
-        self.dwarf.units()
+    /// Returns the unit at `offset` in `.debug_info`, which is parsed on first use.
+    fn unit(&self, offset: usize) -> Option<Rc<gimli::Unit<DwarfReader>>> {
+        if let Some(unit) = self.units.borrow().get(&offset) {
+            return Some(unit.clone());
+        }
+
+        let unit = self
+            .dwarf
+            .debug_info
+            .header_from_offset(gimli::DebugInfoOffset(offset))
+            .and_then(|header| self.dwarf.unit(header));
+
+        match unit {
+            Ok(unit) => {
+                let unit = Rc::new(unit);
+                self.units.borrow_mut().insert(offset, unit.clone());
+                Some(unit)
+            }
+            Err(e) => {
+                log::warn!(
+                    "Unable to parse the compilation unit at {:#x}: {}",
+                    offset,
+                    e
+                );
+                None
+            }
+        }
     }
 
-    fn get_next_unit_info(&self, units: &mut UnitIter) -> Option<UnitInfo> {
-        while let Ok(Some(header)) = units.next() {
-            if let Ok(unit) = self.dwarf.unit(header) {
-                return Some(UnitInfo {
-                    debug_info: self,
-                    unit,
-                });
-            };
+    /// Returns the line program of `unit` at `offset`, which is run on first use.
+    fn line_program(
+        &self,
+        offset: usize,
+        unit: &gimli::Unit<DwarfReader>,
+    ) -> Option<Rc<LineProgram>> {
+        if let Some(line_program) = self.line_programs.borrow().get(&offset) {
+            return Some(line_program.clone());
         }
-        None
+
+        let line_program = Rc::new(unit.line_program.clone()?.sequences().ok()?);
+        self.line_programs
+            .borrow_mut()
+            .insert(offset, line_program.clone());
+        Some(line_program)
+    }
+
+    /// Returns the unit whose code contains `address`.
+    fn get_unit_info(&self, address: u64) -> Option<UnitInfo<'_>> {
+        let unit = self.unit(self.unit_index.find(address)?)?;
+
+        Some(UnitInfo {
+            debug_info: self,
+            unit,
+        })
     }
 
     fn get_stackframe_info(
@@ -494,20 +961,22 @@ impl DebugInfo {
         address: u64,
         frame_count: u64,
         registers: Registers,
+        unwind_strategy: Option<UnwindStrategy>,
     ) -> Result<StackFrame, DebugError> {
-        let mut units = self.get_units();
-        let unknown_function = format!("<unknown_function_{}>", frame_count);
-        while let Some(unit_info) = self.get_next_unit_info(&mut units) {
+        let unknown_function = self
+            .get_function_symbol(address)
+            .map(str::to_owned)
+            .unwrap_or_else(|| format!("<unknown_function_{}>", frame_count));
+        if let Some(unit_info) = self.get_unit_info(address) {
             if let Some(die_cursor_state) = &mut unit_info.get_function_die(address) {
                 let function_name = unit_info
                     .get_function_name(&die_cursor_state.function_die)
                     .unwrap_or(unknown_function);
 
-                let variables = unit_info.get_variables(
-                    core,
-                    die_cursor_state,
-                    u64::from(registers.get_call_frame_address().unwrap()),
-                )?;
+                let variables = match registers.get_call_frame_address() {
+                    Some(cfa) => unit_info.get_variables(core, die_cursor_state, u64::from(cfa))?,
+                    None => vec![],
+                };
 
                 // dbg!(&variables);
 
@@ -515,6 +984,7 @@ impl DebugInfo {
                     id: frame_count,
                     function_name,
                     source_location: self.get_source_location(address),
+                    unwind_strategy,
                     registers,
                     pc: address as u32,
                     variables,
@@ -526,12 +996,34 @@ impl DebugInfo {
             id: frame_count,
             function_name: unknown_function,
             source_location: self.get_source_location(address),
+            unwind_strategy,
             registers,
             pc: address as u32,
             variables: vec![],
         })
     }
 
+    /// Returns the name of the function symbol which contains `address`.
+    ///
+    /// Symbols without a size, e.g. of assembly functions, extend up to the next symbol.
+    fn get_function_symbol(&self, address: u64) -> Option<&str> {
+        let index = match self
+            .functions
+            .binary_search_by_key(&address, |(start, _, _)| *start)
+        {
+            Ok(index) => index,
+            Err(0) => return None,
+            Err(index) => index - 1,
+        };
+
+        let (start, size, name) = &self.functions[index];
+        if *size == 0 || address < start + size {
+            Some(name)
+        } else {
+            None
+        }
+    }
+
     pub fn try_unwind<'probe, 'core>(
         &self,
         core: &'core mut Core<'probe>,
@@ -540,6 +1032,11 @@ impl DebugInfo {
         StackFrameIterator::new(&self, core, address)
     }
 
+    /// Returns the address of the symbol `name` from the symbol table of the ELF file.
+    pub fn get_symbol_address(&self, name: &str) -> Option<u64> {
+        self.symbols.get(name).copied()
+    }
+
     /// Find the program counter where a breakpoint should be set,
     /// given a source file, a line and optionally a column.
     pub fn get_breakpoint_location(
@@ -557,12 +1054,13 @@ impl DebugInfo {
                 .unwrap_or_else(|| "-".to_owned())
         );
 
-        let mut unit_iter = self.dwarf.units();
-
         let mut locations = Vec::new();
 
-        while let Some(unit_header) = unit_iter.next()? {
-            let unit = self.dwarf.unit(unit_header)?;
+        for &offset in self.unit_index.units() {
+            let unit = match self.unit(offset) {
+                Some(unit) => unit,
+                None => continue,
+            };
 
             let comp_dir = unit
                 .comp_dir
@@ -580,23 +1078,37 @@ impl DebugInfo {
                         .and_then(|dir| self.get_path(&dir, &unit, &header, file_name));
 
                     if combined_path.map(|p| p == path).unwrap_or(false) {
-                        let mut rows = line_program.clone().rows();
+                        let line_program = match self.line_program(offset, &unit) {
+                            Some(line_program) => line_program,
+                            None => continue,
+                        };
+                        let (program, sequences) = &*line_program;
 
-                        while let Some((header, row)) = rows.next_row()? {
-                            let row_path = comp_dir.as_ref().and_then(|dir| {
-                                self.get_path(&dir, &unit, &header, row.file(&header)?)
-                            });
+                        for sequence in sequences {
+                            let mut rows = program.resume_from(sequence);
 
-                            if row_path.map(|p| p != path).unwrap_or(true) {
-                                continue;
-                            }
+                            while let Some((header, row)) = rows.next_row()? {
+                                if row.end_sequence() {
+                                    break;
+                                }
+
+                                let row_path = comp_dir.as_ref().and_then(|dir| {
+                                    self.get_path(dir, &unit, header, row.file(header)?)
+                                });
+
+                                if row_path.map(|p| p != path).unwrap_or(true) {
+                                    continue;
+                                }
 
-                            if let Some(cur_line) = row.line() {
-                                if cur_line == line {
-                                    locations.push((row.address(), row.column()));
+                                if let Some(cur_line) = row.line() {
+                                    if cur_line == line {
+                                        locations.push((row.address(), row.column()));
+                                    }
                                 }
                             }
                         }
+
+                        break;
                     }
                 }
             }
@@ -689,7 +1201,7 @@ struct DieCursorState<'abbrev, 'unit> {
 
 struct UnitInfo<'debuginfo> {
     debug_info: &'debuginfo DebugInfo,
-    unit: gimli::Unit<gimli::EndianReader<gimli::LittleEndian, std::rc::Rc<[u8]>>, usize>,
+    unit: Rc<gimli::Unit<DwarfReader>>,
 }
 
 impl<'debuginfo> UnitInfo<'debuginfo> {
@@ -753,9 +1265,17 @@ impl<'debuginfo> UnitInfo<'debuginfo> {
             result = match result {
                 Complete => break,
                 RequiresMemory { address, size, .. } => {
+                    if !self.debug_info.read_side_effects {
+                        if let Some(region) = core.read_side_effects(address as u32, size.into()) {
+                            return Err(DebugError::ReadSideEffects {
+                                address,
+                                region: region.to_string(),
+                            });
+                        }
+                    }
+
                     let mut buff = vec![0u8; size as usize];
-                    core.read_8(address as u32, &mut buff)
-                        .expect("Failed to read memory");
+                    core.read_8(address as u32, &mut buff)?;
                     match size {
                         1 => evaluation.resume_with_memory(gimli::Value::U8(buff[0]))?,
                         2 => {
@@ -849,8 +1369,14 @@ impl<'debuginfo> UnitInfo<'debuginfo> {
                         }
                         gimli::DW_AT_location => {
                             variable.value =
-                                extract_location(&self, core, frame_base, attr.value())?
-                                    .unwrap_or_else(u64::max_value);
+                                match extract_location(&self, core, frame_base, attr.value()) {
+                                    Ok(value) => value.unwrap_or_else(u64::max_value),
+                                    Err(error @ DebugError::ReadSideEffects { .. }) => {
+                                        log::info!("{}", error);
+                                        u64::max_value()
+                                    }
+                                    Err(error) => return Err(error),
+                                };
                         }
                         _ => (),
                     }
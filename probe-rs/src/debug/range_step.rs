@@ -0,0 +1,308 @@
+//! Stepping through an address range without a round trip to the debugger for every instruction.
+//!
+//! Instead of single stepping every instruction, the code up to the next instruction which can
+//! change the program flow runs at full speed, with a temporary hardware breakpoint at that
+//! instruction. Only the branches themselves are single stepped, after which the program counter
+//! shows if the range was left. If no breakpoint unit is free, every instruction is stepped.
+//!
+//! Interrupts which occur while a branch is stepped are run until they return to the range.
+
+use crate::architecture::arm::core::register;
+use crate::core::{Architecture, Core};
+use crate::{DebugProbeError, Error, MemoryInterface};
+
+use std::ops::Range;
+
+/// The number of instructions which are single stepped, before the caller gets the control
+/// back to check for an interruption by the user.
+const MAX_STEPS: usize = 256;
+
+/// The number of bytes which are searched for the next branch at once.
+const SCAN_LENGTH: u32 = 64;
+
+/// The mask of the exception number in the XPSR register.
+const IPSR_MASK: u32 = 0x1ff;
+
+/// The offset of the return address in an exception stack frame.
+const FRAME_RETURN_ADDRESS: u32 = 0x18;
+
+/// The state of a range step after the core halted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RangeStepStatus {
+    /// The step goes on. The core is running, or it is halted to give the caller the chance to
+    /// cancel the step. In both cases [RangeStep::halted] has to be called once the core is halted.
+    Running,
+    /// The core is halted outside of the range, or it was halted by something else than the step.
+    Finished,
+    /// The core is halted at a hardware breakpoint which was not set by the step.
+    Breakpoint,
+}
+
+/// Steps a core until its program counter leaves an address range.
+#[derive(Debug)]
+pub struct RangeStep {
+    range: Range<u32>,
+    /// The exception number the core was in when the step started.
+    exception: u32,
+    /// The address the core runs to, and if the breakpoint there was set by the step.
+    temporary: Option<(u32, bool)>,
+}
+
+impl RangeStep {
+    /// Steps the halted `core` at least once, and keeps stepping while the program counter is in `range`.
+    pub fn start(core: &mut Core, range: Range<u32>) -> Result<(Self, RangeStepStatus), Error> {
+        let exception = match core.architecture() {
+            Architecture::Arm => core.read_core_reg(register::XPSR.address)? & IPSR_MASK,
+            Architecture::Riscv => 0,
+        };
+
+        let mut step = Self {
+            range,
+            exception,
+            temporary: None,
+        };
+
+        // The first instruction is executed even if a breakpoint is set on it.
+        core.step()?;
+        if step.at_breakpoint(core)? {
+            return Ok((step, RangeStepStatus::Breakpoint));
+        }
+
+        let status = step.advance(core)?;
+        Ok((step, status))
+    }
+
+    /// Continues the step after the core halted.
+    pub fn halted(&mut self, core: &mut Core) -> Result<RangeStepStatus, Error> {
+        if let Some((address, owned)) = self.temporary.take() {
+            if owned {
+                core.clear_hw_breakpoint(address)?;
+            }
+
+            if self.at_breakpoint(core)? {
+                return Ok(RangeStepStatus::Breakpoint);
+            }
+
+            let pc = core.read_core_reg(core.registers().program_counter())?;
+            if pc != address {
+                // The core was halted by something else, e.g. a breakpoint instruction.
+                return Ok(RangeStepStatus::Finished);
+            }
+        }
+
+        self.advance(core)
+    }
+
+    /// Removes the temporary breakpoint, if the step is stopped before it finished.
+    pub fn cancel(&mut self, core: &mut Core) -> Result<(), Error> {
+        match self.temporary.take() {
+            Some((address, true)) => core.clear_hw_breakpoint(address),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns `true` if the core is halted at a hardware breakpoint which was not set by the step.
+    fn at_breakpoint(&self, core: &mut Core) -> Result<bool, Error> {
+        let pc = core.read_core_reg(core.registers().program_counter())?;
+        Ok(core.hw_breakpoints().contains(&pc))
+    }
+
+    fn advance(&mut self, core: &mut Core) -> Result<RangeStepStatus, Error> {
+        for _ in 0..MAX_STEPS {
+            let pc = core.read_core_reg(core.registers().program_counter())?;
+
+            if !self.range.contains(&pc) {
+                return match self.interrupted_return_address(core)? {
+                    // The interrupt is run until it returns to the range.
+                    Some(address) if self.run_to(core, address)? => Ok(RangeStepStatus::Running),
+                    _ => Ok(RangeStepStatus::Finished),
+                };
+            }
+
+            let branch = self.next_branch(core, pc)?;
+            if branch != pc && self.run_to(core, branch)? {
+                return Ok(RangeStepStatus::Running);
+            }
+
+            core.step()?;
+            if self.at_breakpoint(core)? {
+                return Ok(RangeStepStatus::Breakpoint);
+            }
+        }
+
+        Ok(RangeStepStatus::Running)
+    }
+
+    /// Sets a temporary breakpoint at `address` and resumes the core.
+    ///
+    /// Returns `false` if no breakpoint unit is free.
+    fn run_to(&mut self, core: &mut Core, address: u32) -> Result<bool, Error> {
+        // A breakpoint of the user at the address already halts the core.
+        let owned = !core.hw_breakpoints().contains(&address);
+        if owned {
+            match core.set_hw_breakpoint(address) {
+                Ok(()) => {}
+                Err(Error::Probe(DebugProbeError::BreakpointUnitsExceeded)) => return Ok(false),
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.temporary = Some((address, owned));
+        core.run()?;
+
+        Ok(true)
+    }
+
+    /// Returns the address of the next instruction from `pc` which can change the program flow.
+    ///
+    /// If the search reaches the end of the range, the address of the first instruction
+    /// after the range is returned.
+    fn next_branch(&self, core: &mut Core, pc: u32) -> Result<u32, Error> {
+        if core.architecture() != Architecture::Arm {
+            return Ok(pc);
+        }
+
+        let length = (self.range.end - pc).min(SCAN_LENGTH);
+        // The last instruction may be 32 bits long.
+        let mut code = vec![0u8; length as usize + 2];
+        if core.read_8(pc, &mut code).is_err() {
+            return Ok(pc);
+        }
+
+        let halfword = |offset: u32| {
+            let offset = offset as usize;
+            u16::from_le_bytes([code[offset], code[offset + 1]])
+        };
+
+        let mut offset = 0;
+        while offset < length {
+            let first = halfword(offset);
+            let size = thumb_instruction_size(first);
+            let second = if size == 4 { halfword(offset + 2) } else { 0 };
+
+            if is_thumb_control_flow(first, second) {
+                return Ok(pc + offset);
+            }
+            offset += size;
+        }
+
+        Ok(pc + offset)
+    }
+
+    /// Returns the address the interrupt returns to, if the core entered an exception handler
+    /// while it was stepped.
+    fn interrupted_return_address(&self, core: &mut Core) -> Result<Option<u32>, Error> {
+        if core.architecture() != Architecture::Arm {
+            return Ok(None);
+        }
+
+        let exception = core.read_core_reg(register::XPSR.address)? & IPSR_MASK;
+        if exception == 0 || exception == self.exception {
+            return Ok(None);
+        }
+
+        let sp = core.read_core_reg(core.registers().stack_pointer())?;
+        let address = core.read_word_32(sp + FRAME_RETURN_ADDRESS)?;
+
+        Ok(Some(address).filter(|address| self.range.contains(address)))
+    }
+}
+
+/// Returns the size of the Thumb instruction which starts with the halfword `first`.
+fn thumb_instruction_size(first: u16) -> u32 {
+    match first >> 11 {
+        0b11101..=0b11111 => 4,
+        _ => 2,
+    }
+}
+
+/// Returns `true` if the Thumb instruction can change the program flow, or raise an exception.
+///
+/// `second` is the second halfword of 32 bit instructions. Some instructions which do not branch
+/// are included, which only costs an additional single step.
+fn is_thumb_control_flow(first: u16, second: u16) -> bool {
+    if thumb_instruction_size(first) == 2 {
+        // B<c>, UDF and SVC
+        first & 0xf000 == 0xd000
+            // B
+            || first & 0xf800 == 0xe000
+            // CBZ and CBNZ
+            || first & 0xf500 == 0xb100
+            // POP with the PC
+            || first & 0xff00 == 0xbd00
+            // BKPT
+            || first & 0xff00 == 0xbe00
+            // BX and BLX
+            || first & 0xff00 == 0x4700
+            // ADD and MOV to the PC
+            || (matches!(first & 0xff00, 0x4400 | 0x4600) && first & 0x87 == 0x87)
+    } else {
+        // B, BL, B<c> and the miscellaneous control instructions,
+        // as well as LDM and LDMDB with the PC
+        ((first & 0xf800 == 0xf000 || first & 0xfe50 == 0xe810) && second & 0x8000 != 0)
+            // LDR to the PC
+            || (first & 0xff70 == 0xf850 && second & 0xf000 == 0xf000)
+            // TBB and TBH
+            || (first & 0xfff0 == 0xe8d0 && second & 0xffe0 == 0xf000)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_thumb_control_flow, thumb_instruction_size};
+
+    #[test]
+    fn thumb_branches_are_detected() {
+        let branches: &[(u16, u16)] = &[
+            (0xd1fc, 0),      // bne
+            (0xe7fe, 0),      // b
+            (0xb11b, 0),      // cbz r3
+            (0xbd10, 0),      // pop {r4, pc}
+            (0x4770, 0),      // bx lr
+            (0x4798, 0),      // blx r3
+            (0x46f7, 0),      // mov pc, lr
+            (0xdf00, 0),      // svc 0
+            (0xf000, 0xf800), // bl
+            (0xf040, 0x8000), // bne.w
+            (0xe8bd, 0x8010), // pop.w {r4, pc}
+            (0xf85d, 0xfb04), // ldr pc, [sp], #4
+            (0xe8df, 0xf003), // tbb [pc, r3]
+        ];
+        for (first, second) in branches {
+            assert!(
+                is_thumb_control_flow(*first, *second),
+                "{:04x} {:04x} is a branch",
+                first,
+                second
+            );
+        }
+
+        let others: &[(u16, u16)] = &[
+            (0x3301, 0),      // adds r3, #1
+            (0x4618, 0),      // mov r0, r3
+            (0x4543, 0),      // cmp r3, r8
+            (0xb510, 0),      // push {r4, lr}
+            (0xbc10, 0),      // pop {r4}
+            (0xf8d3, 0x3004), // ldr.w r3, [r3, #4]
+            (0xe8bd, 0x4010), // pop.w {r4, lr}
+            (0xfb02, 0xf303), // mul.w r3, r2, r3
+        ];
+        for (first, second) in others {
+            assert!(
+                !is_thumb_control_flow(*first, *second),
+                "{:04x} {:04x} is not a branch",
+                first,
+                second
+            );
+        }
+    }
+
+    #[test]
+    fn thumb_instruction_sizes() {
+        assert_eq!(thumb_instruction_size(0x4770), 2);
+        assert_eq!(thumb_instruction_size(0xe7fe), 2);
+        assert_eq!(thumb_instruction_size(0xe8bd), 4);
+        assert_eq!(thumb_instruction_size(0xf000), 4);
+        assert_eq!(thumb_instruction_size(0xfb02), 4);
+    }
+}
@@ -0,0 +1,348 @@
+//! A recording of single stepped execution, which can be stepped back through.
+//!
+//! Before each step, the registers of the core are saved, and on ARM cores the bytes which the
+//! stepped instruction stores to memory. After the step, only the registers which changed are
+//! kept. Stepping back writes the saved values back, newest step first.
+//!
+//! This is not full reverse execution: memory written by instructions which are not decoded,
+//! like floating point stores, or by interrupts which occur during a step is not restored.
+
+use crate::architecture::arm::core::register;
+use crate::core::{Architecture, Core, CoreInformation, CoreRegisterAddress};
+use crate::{Error, MemoryInterface};
+
+use std::collections::VecDeque;
+use std::ops::Range;
+
+/// The state of a core before a step, from [StepRecorder::prepare].
+#[derive(Debug)]
+pub struct PendingStep {
+    registers: Vec<(CoreRegisterAddress, u32)>,
+    memory: Option<(u32, Vec<u8>)>,
+}
+
+/// A step of the recording, with the state it changed.
+#[derive(Debug, Clone)]
+pub struct RecordedStep {
+    pc: u32,
+    /// The registers which changed, with their values before the step.
+    registers: Vec<(CoreRegisterAddress, u32)>,
+    /// The memory the step stored to, with its content before the step.
+    memory: Option<(u32, Vec<u8>)>,
+}
+
+impl RecordedStep {
+    /// The address of the stepped instruction.
+    pub fn pc(&self) -> u32 {
+        self.pc
+    }
+}
+
+/// Records the steps of a core in a ring buffer, and restores the state before them.
+#[derive(Debug)]
+pub struct StepRecorder {
+    capacity: usize,
+    steps: VecDeque<RecordedStep>,
+}
+
+impl StepRecorder {
+    /// Creates a recorder which keeps the last `capacity` steps.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            steps: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// The number of steps which can be stepped back.
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// The recorded steps, the oldest one first.
+    pub fn steps(&self) -> impl Iterator<Item = &RecordedStep> {
+        self.steps.iter()
+    }
+
+    /// Discards the recording, e.g. when the core runs at full speed.
+    pub fn clear(&mut self) {
+        self.steps.clear();
+    }
+
+    /// Saves the state of the halted `core`, before it is stepped.
+    pub fn prepare(&self, core: &mut Core) -> Result<PendingStep, Error> {
+        let registers = recorded_registers(core)
+            .into_iter()
+            .map(|address| Ok((address, core.read_core_reg(address)?)))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let memory = match store_range(core, &registers)? {
+            Some(range) => {
+                let mut data = vec![0; range.end.wrapping_sub(range.start) as usize];
+                core.read_8(range.start, &mut data)?;
+                Some((range.start, data))
+            }
+            None => None,
+        };
+
+        Ok(PendingStep { registers, memory })
+    }
+
+    /// Adds the step to the recording, after the `core` was stepped.
+    pub fn record(&mut self, core: &mut Core, pending: PendingStep) -> Result<(), Error> {
+        let pc_address = core.registers().program_counter().address;
+        let mut pc = 0;
+        let mut registers = vec![];
+
+        for (address, value) in pending.registers {
+            if address == pc_address {
+                pc = value;
+            }
+            if core.read_core_reg(address)? != value {
+                registers.push((address, value));
+            }
+        }
+
+        if self.steps.len() == self.capacity {
+            self.steps.pop_front();
+        }
+        if self.capacity > 0 {
+            self.steps.push_back(RecordedStep {
+                pc,
+                registers,
+                memory: pending.memory,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Steps the `core` and records the step.
+    pub fn step(&mut self, core: &mut Core) -> Result<CoreInformation, Error> {
+        let pending = self.prepare(core)?;
+        let information = core.step()?;
+        self.record(core, pending)?;
+
+        Ok(information)
+    }
+
+    /// Restores the state before the last recorded step.
+    ///
+    /// Returns the program counter after the step back, or `None` if the recording is empty.
+    pub fn step_back(&mut self, core: &mut Core) -> Result<Option<u32>, Error> {
+        let step = match self.steps.pop_back() {
+            Some(step) => step,
+            None => return Ok(None),
+        };
+
+        if let Some((address, data)) = &step.memory {
+            core.write_8(*address, data)?;
+        }
+        for (address, value) in &step.registers {
+            core.write_core_reg(*address, *value)?;
+        }
+
+        Ok(Some(step.pc))
+    }
+
+    /// Steps back until the program counter is at one of the `breakpoints`, or the recording is empty.
+    ///
+    /// Returns the program counter after the last step back, or `None` if the recording was empty.
+    pub fn reverse_continue(
+        &mut self,
+        core: &mut Core,
+        breakpoints: &[u32],
+    ) -> Result<Option<u32>, Error> {
+        let mut pc = None;
+
+        while let Some(address) = self.step_back(core)? {
+            pc = Some(address);
+            if breakpoints.contains(&address) {
+                break;
+            }
+        }
+
+        Ok(pc)
+    }
+}
+
+/// The registers which are saved before a step.
+fn recorded_registers(core: &Core) -> Vec<CoreRegisterAddress> {
+    let mut addresses: Vec<_> = core.registers().registers().map(|r| r.address).collect();
+    if core.architecture() == Architecture::Arm {
+        addresses.push(register::XPSR.address);
+    }
+    addresses
+}
+
+/// Returns the memory the instruction at the program counter stores to.
+fn store_range(
+    core: &mut Core,
+    registers: &[(CoreRegisterAddress, u32)],
+) -> Result<Option<Range<u32>>, Error> {
+    if core.architecture() != Architecture::Arm {
+        return Ok(None);
+    }
+
+    let register = |n: u16| {
+        registers
+            .iter()
+            .find(|(address, _)| *address == CoreRegisterAddress(n))
+            .map_or(0, |(_, value)| *value)
+    };
+
+    let mut code = [0u8; 4];
+    core.read_8(register(15), &mut code)?;
+    let first = u16::from_le_bytes([code[0], code[1]]);
+    let second = u16::from_le_bytes([code[2], code[3]]);
+
+    Ok(thumb_store(first, second, register))
+}
+
+/// Returns the memory a Thumb instruction stores to, with `register` returning the value of a register.
+///
+/// Only the integer stores are decoded.
+fn thumb_store(first: u16, second: u16, register: impl Fn(u16) -> u32) -> Option<Range<u32>> {
+    let at = |address: u32, length: u32| Some(address..address.wrapping_add(length));
+
+    if !matches!(first >> 11, 0b11101..=0b11111) {
+        let low = |shift: u16| register((first >> shift) & 0x7);
+        let imm5 = u32::from((first >> 6) & 0x1f);
+
+        return match first >> 11 {
+            // STR, STRB and STRH with an immediate offset
+            0b01100 => at(low(3).wrapping_add(imm5 * 4), 4),
+            0b01110 => at(low(3).wrapping_add(imm5), 1),
+            0b10000 => at(low(3).wrapping_add(imm5 * 2), 2),
+            // STR relative to the SP
+            0b10010 => at(register(13).wrapping_add(u32::from(first & 0xff) * 4), 4),
+            // STM
+            0b11000 => at(low(8), 4 * (first & 0xff).count_ones()),
+            _ => match first >> 9 {
+                // STR, STRH and STRB with a register offset
+                0b0101000 => at(low(3).wrapping_add(low(6)), 4),
+                0b0101001 => at(low(3).wrapping_add(low(6)), 2),
+                0b0101010 => at(low(3).wrapping_add(low(6)), 1),
+                // PUSH
+                0b1011010 => {
+                    let length = 4 * (first & 0x1ff).count_ones();
+                    at(register(13).wrapping_sub(length), length)
+                }
+                _ => None,
+            },
+        };
+    }
+
+    let base = register(first & 0xf);
+    let list_length = 4 * second.count_ones();
+
+    match first & 0xffd0 {
+        // STM.W
+        0xe880 => return at(base, list_length),
+        // STMDB and PUSH.W
+        0xe900 => return at(base.wrapping_sub(list_length), list_length),
+        _ => {}
+    }
+
+    let size = match first & 0xfff0 {
+        0xf8c0 | 0xf840 => 4,
+        0xf8a0 | 0xf820 => 2,
+        0xf880 | 0xf800 => 1,
+        // STREX
+        0xe840 => return at(base.wrapping_add(u32::from(second & 0xff) * 4), 4),
+        // STRD
+        _ if first & 0xfe50 == 0xe840 && first & 0x0120 != 0 => {
+            let offset = u32::from(second & 0xff) * 4;
+            let address = match (first & 0x100 != 0, first & 0x80 != 0) {
+                (true, true) => base.wrapping_add(offset),
+                (true, false) => base.wrapping_sub(offset),
+                (false, _) => base,
+            };
+            return at(address, 8);
+        }
+        _ => return None,
+    };
+
+    if first & 0x0080 != 0 {
+        // 12 bit immediate offset
+        at(base.wrapping_add(u32::from(second & 0xfff)), size)
+    } else if second & 0x0800 != 0 {
+        // 8 bit immediate offset, with pre- or post-indexing
+        let offset = u32::from(second & 0xff);
+        let address = match (second & 0x400 != 0, second & 0x200 != 0) {
+            (true, true) => base.wrapping_add(offset),
+            (true, false) => base.wrapping_sub(offset),
+            (false, _) => base,
+        };
+        at(address, size)
+    } else if second & 0x0fc0 == 0 {
+        // Shifted register offset
+        let offset = register(second & 0xf) << ((second >> 4) & 0x3);
+        at(base.wrapping_add(offset), size)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::thumb_store;
+
+    /// The registers r0 to r15 have the values 0x100 to 0x10f, the SP is 0x2000_1000.
+    fn register(n: u16) -> u32 {
+        match n {
+            13 => 0x2000_1000,
+            n => 0x100 + u32::from(n),
+        }
+    }
+
+    #[test]
+    fn thumb_stores_are_decoded() {
+        let stores: &[(u16, u16, Option<std::ops::Range<u32>>)] = &[
+            // str r1, [r2, #8]
+            (0x6091, 0, Some(0x10a..0x10e)),
+            // strb r1, [r2, #3]
+            (0x70d1, 0, Some(0x105..0x106)),
+            // strh r1, [r2, #2]
+            (0x8051, 0, Some(0x104..0x106)),
+            // str r0, [sp, #4]
+            (0x9001, 0, Some(0x2000_1004..0x2000_1008)),
+            // str r1, [r2, r3]
+            (0x50d1, 0, Some(0x205..0x209)),
+            // push {r4, r5, lr}
+            (0xb530, 0, Some(0x2000_0ff4..0x2000_1000)),
+            // stmia r0!, {r1, r2}
+            (0xc006, 0, Some(0x100..0x108)),
+            // str.w r1, [r2, #0x100]
+            (0xf8c2, 0x1100, Some(0x202..0x206)),
+            // str r1, [sp, #-4]!
+            (0xf84d, 0x1d04, Some(0x2000_0ffc..0x2000_1000)),
+            // strb.w r1, [r2, r3, lsl #2]
+            (0xf802, 0x1023, Some(0x50e..0x50f)),
+            // push.w {r4-r11, lr}
+            (0xe92d, 0x4ff0, Some(0x2000_0fdc..0x2000_1000)),
+            // strd r0, r1, [sp, #8]
+            (0xe9cd, 0x0102, Some(0x2000_1008..0x2000_1010)),
+            // adds r3, #1
+            (0x3301, 0, None),
+            // ldr.w r3, [r3, #4]
+            (0xf8d3, 0x3004, None),
+            // pop {r4, pc}
+            (0xbd10, 0, None),
+        ];
+
+        for (first, second, range) in stores {
+            assert_eq!(
+                &thumb_store(*first, *second, register),
+                range,
+                "{:04x} {:04x}",
+                first,
+                second
+            );
+        }
+    }
+}
@@ -0,0 +1,337 @@
+//! Thread awareness for real time operating systems.
+//!
+//! An [RtosProvider] finds the threads of an RTOS in the memory of a halted target,
+//! using the symbols of the ELF file the target was programmed with. The registers
+//! of threads which are not running are restored from the context the RTOS saved
+//! on their stack.
+//!
+//! RTIC does not need a provider: its tasks run on the main stack as nested exception
+//! handlers, so they are already visible in the call stack of the running core.
+
+use super::DebugError;
+use crate::{CoreRegisterAddress, MemoryInterface};
+use object::read::{Object, ObjectSymbol};
+use thiserror::Error;
+
+/// An error which occured while reading the threads of an RTOS.
+#[derive(Debug, Error)]
+pub enum RtosError {
+    /// The memory of the target could not be read.
+    #[error("Error reading the RTOS data structures")]
+    Memory(#[from] crate::Error),
+    /// A list of threads is damaged, e.g. because it was read while it was modified.
+    #[error("The thread list at {0:#010x} is damaged")]
+    DamagedList(u32),
+}
+
+/// A thread of an RTOS.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RtosThread {
+    /// A unique, non-zero identifier of the thread, usually the address of its control block.
+    pub id: u32,
+    /// The name of the thread.
+    pub name: String,
+    /// `true` if this is the thread which is running on the core.
+    pub is_current: bool,
+    /// The registers which were saved when the thread was suspended.
+    ///
+    /// This is empty for the running thread, its registers are the ones of the core.
+    pub registers: Vec<(CoreRegisterAddress, u32)>,
+}
+
+/// Finds the threads of a specific RTOS.
+pub trait RtosProvider: std::fmt::Debug + Send {
+    /// The name of the RTOS.
+    fn name(&self) -> &'static str;
+
+    /// Reads all threads of the RTOS from the memory of the halted target.
+    fn threads(&self, memory: &mut dyn MemoryInterface) -> Result<Vec<RtosThread>, RtosError>;
+}
+
+/// Finds the RTOS the ELF file `data` was built with.
+///
+/// Returns `None` if the file does not use any of the supported RTOSes.
+pub fn detect_rtos(data: &[u8]) -> Result<Option<Box<dyn RtosProvider>>, DebugError> {
+    let file = object::File::parse(data)?;
+
+    let symbol = |name: &str| {
+        file.symbols()
+            .find(|symbol| symbol.name().ok() == Some(name))
+            .map(|symbol| (symbol.address() as u32, symbol.size() as u32))
+    };
+
+    if let Some(freertos) = FreeRtos::from_symbols(symbol) {
+        return Ok(Some(Box::new(freertos)));
+    }
+
+    Ok(None)
+}
+
+/// The size of a `List_t` of FreeRTOS on a 32 bit target.
+const FREERTOS_LIST_SIZE: u32 = 20;
+/// The offset of `xListEnd` in a `List_t`.
+const FREERTOS_LIST_END: u32 = 8;
+/// The offset of `pxNext` in a `ListItem_t`.
+const FREERTOS_ITEM_NEXT: u32 = 4;
+/// The offset of `pvOwner` in a `ListItem_t`.
+const FREERTOS_ITEM_OWNER: u32 = 12;
+/// The offset of `pcTaskName` in a `TCB_t`, for ports which do not use the MPU.
+const FREERTOS_TASK_NAME: u32 = 52;
+/// The default `configMAX_TASK_NAME_LEN`.
+const FREERTOS_TASK_NAME_LENGTH: usize = 16;
+/// The maximum number of items a list is followed for, to detect damaged lists.
+const FREERTOS_MAX_LIST_ITEMS: usize = 1024;
+
+/// Thread awareness for FreeRTOS on Cortex-M cores.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FreeRtos {
+    /// The address of `pxCurrentTCB`.
+    current_tcb: u32,
+    /// The addresses of all lists which contain tasks.
+    lists: Vec<u32>,
+}
+
+impl FreeRtos {
+    /// Creates the provider from the symbols of the kernel,
+    /// or returns `None` if the symbols are missing.
+    ///
+    /// `symbol` returns the address and the size of a symbol.
+    fn from_symbols(symbol: impl Fn(&str) -> Option<(u32, u32)>) -> Option<Self> {
+        let (current_tcb, _) = symbol("pxCurrentTCB")?;
+        let (ready_lists, ready_lists_size) = symbol("pxReadyTasksLists")?;
+
+        let mut lists = (0..ready_lists_size / FREERTOS_LIST_SIZE)
+            .map(|priority| ready_lists + priority * FREERTOS_LIST_SIZE)
+            .collect::<Vec<_>>();
+
+        // Not all of the lists exist in every configuration of the kernel.
+        for name in &[
+            "xDelayedTaskList1",
+            "xDelayedTaskList2",
+            "xPendingReadyList",
+            "xSuspendedTaskList",
+            "xTasksWaitingTermination",
+        ] {
+            if let Some((list, _)) = symbol(name) {
+                lists.push(list);
+            }
+        }
+
+        Some(Self { current_tcb, lists })
+    }
+
+    /// Returns the control blocks of all tasks in `list`.
+    fn tasks(&self, memory: &mut dyn MemoryInterface, list: u32) -> Result<Vec<u32>, RtosError> {
+        let end = list + FREERTOS_LIST_END;
+        let mut tasks = vec![];
+
+        let mut item = memory.read_word_32(end + FREERTOS_ITEM_NEXT)?;
+        while item != end {
+            if item == 0 || tasks.len() >= FREERTOS_MAX_LIST_ITEMS {
+                return Err(RtosError::DamagedList(list));
+            }
+
+            tasks.push(memory.read_word_32(item + FREERTOS_ITEM_OWNER)?);
+            item = memory.read_word_32(item + FREERTOS_ITEM_NEXT)?;
+        }
+
+        Ok(tasks)
+    }
+}
+
+impl RtosProvider for FreeRtos {
+    fn name(&self) -> &'static str {
+        "FreeRTOS"
+    }
+
+    fn threads(&self, memory: &mut dyn MemoryInterface) -> Result<Vec<RtosThread>, RtosError> {
+        let current = memory.read_word_32(self.current_tcb)?;
+
+        // The scheduler was not started yet.
+        if current == 0 {
+            return Ok(vec![]);
+        }
+
+        let mut threads = vec![];
+
+        for list in &self.lists {
+            for tcb in self.tasks(memory, *list)? {
+                let mut name = [0; FREERTOS_TASK_NAME_LENGTH];
+                memory.read_8(tcb + FREERTOS_TASK_NAME, &mut name)?;
+                let length = name.iter().position(|c| *c == 0).unwrap_or(name.len());
+
+                let registers = if tcb == current {
+                    vec![]
+                } else {
+                    let stack_pointer = memory.read_word_32(tcb)?;
+                    unstack_cortex_m(stack_pointer, |address| memory.read_word_32(address))?
+                };
+
+                threads.push(RtosThread {
+                    id: tcb,
+                    name: String::from_utf8_lossy(&name[..length]).into_owned(),
+                    is_current: tcb == current,
+                    registers,
+                });
+            }
+        }
+
+        Ok(threads)
+    }
+}
+
+/// The register numbers of the Cortex-M cores.
+const XPSR: u16 = 16;
+const SP: u16 = 13;
+const FIRST_FP_REGISTER: u16 = 64;
+
+/// Restores the registers of a thread from the context which was pushed to its stack
+/// by the context switch of a Cortex-M port.
+///
+/// The stack contains `R4`-`R11`, the `EXC_RETURN` value and `S16`-`S31` for ports
+/// with FPU support, followed by the frame the core pushed on exception entry.
+fn unstack_cortex_m(
+    stack_pointer: u32,
+    mut read: impl FnMut(u32) -> Result<u32, crate::Error>,
+) -> Result<Vec<(CoreRegisterAddress, u32)>, RtosError> {
+    let mut registers = vec![];
+    let mut address = stack_pointer;
+    let mut pop = |address: &mut u32| -> Result<u32, crate::Error> {
+        let value = read(*address)?;
+        *address += 4;
+        Ok(value)
+    };
+
+    for register in 4..=11 {
+        registers.push((CoreRegisterAddress(register), pop(&mut address)?));
+    }
+
+    // Ports with FPU support save the EXC_RETURN value, which shows if the FPU was used.
+    let mut extended_frame = false;
+    let mut after_exc_return = address;
+    let exc_return = pop(&mut after_exc_return)?;
+    if exc_return & 0xFFFF_FF00 == 0xFFFF_FF00 {
+        address = after_exc_return;
+        extended_frame = exc_return & (1 << 4) == 0;
+
+        if extended_frame {
+            for register in 16..32 {
+                let value = pop(&mut address)?;
+                registers.push((CoreRegisterAddress(FIRST_FP_REGISTER + register), value));
+            }
+        }
+    }
+
+    for register in &[0, 1, 2, 3, 12, 14, 15] {
+        registers.push((CoreRegisterAddress(*register), pop(&mut address)?));
+    }
+    let xpsr = pop(&mut address)?;
+    registers.push((CoreRegisterAddress(XPSR), xpsr));
+
+    if extended_frame {
+        for register in 0..16 {
+            let value = pop(&mut address)?;
+            registers.push((CoreRegisterAddress(FIRST_FP_REGISTER + register), value));
+        }
+        // FPSCR and a reserved word.
+        address += 8;
+    }
+
+    // The core aligned the stack to 8 bytes on exception entry.
+    if xpsr & (1 << 9) != 0 {
+        address += 4;
+    }
+
+    registers.push((CoreRegisterAddress(SP), address));
+    registers.sort_by_key(|(register, _)| register.0);
+
+    Ok(registers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{unstack_cortex_m, CoreRegisterAddress, FreeRtos};
+    use std::collections::HashMap;
+
+    fn stack(words: &[u32]) -> HashMap<u32, u32> {
+        (0..)
+            .step_by(4)
+            .map(|offset| 0x2000_1000 + offset)
+            .zip(words.iter().copied())
+            .collect()
+    }
+
+    fn register(registers: &[(CoreRegisterAddress, u32)], register: u16) -> Option<u32> {
+        registers
+            .iter()
+            .find(|(address, _)| address.0 == register)
+            .map(|(_, value)| *value)
+    }
+
+    #[test]
+    fn basic_frame_is_unstacked() {
+        // R4-R11, then R0-R3, R12, LR, PC and xPSR.
+        let words = stack(&[
+            4,
+            5,
+            6,
+            7,
+            8,
+            9,
+            10,
+            11,
+            0,
+            1,
+            2,
+            3,
+            12,
+            14,
+            15,
+            0x0100_0000,
+        ]);
+
+        let registers = unstack_cortex_m(0x2000_1000, |address| Ok(words[&address])).unwrap();
+
+        for number in (0..=12).chain(14..=15) {
+            assert_eq!(register(&registers, number), Some(u32::from(number)));
+        }
+        assert_eq!(register(&registers, 16), Some(0x0100_0000));
+        assert_eq!(register(&registers, 13), Some(0x2000_1040));
+        assert_eq!(register(&registers, 64), None);
+    }
+
+    #[test]
+    fn fpu_frame_is_unstacked() {
+        let mut words = vec![4, 5, 6, 7, 8, 9, 10, 11, 0xFFFF_FFED];
+        words.extend(116..132);
+        words.extend(&[0, 1, 2, 3, 12, 14, 15, 0x0100_0200]);
+        words.extend(100..116);
+        words.extend(&[0, 0]);
+        let words = stack(&words);
+
+        let registers = unstack_cortex_m(0x2000_1000, |address| Ok(words[&address])).unwrap();
+
+        assert_eq!(register(&registers, 15), Some(15));
+        assert_eq!(register(&registers, 64), Some(100));
+        assert_eq!(register(&registers, 64 + 31), Some(131));
+        // 51 words, and the alignment word the core inserted.
+        assert_eq!(register(&registers, 13), Some(0x2000_1000 + 52 * 4));
+    }
+
+    #[test]
+    fn freertos_needs_the_kernel_symbols() {
+        assert_eq!(FreeRtos::from_symbols(|_| None), None);
+
+        let freertos = FreeRtos::from_symbols(|name| match name {
+            "pxCurrentTCB" => Some((0x2000_0000, 4)),
+            "pxReadyTasksLists" => Some((0x2000_0100, 5 * 20)),
+            "xSuspendedTaskList" => Some((0x2000_0200, 20)),
+            _ => None,
+        })
+        .unwrap();
+
+        assert_eq!(freertos.lists.len(), 6);
+        assert_eq!(freertos.lists[4], 0x2000_0150);
+        assert_eq!(freertos.lists[5], 0x2000_0200);
+    }
+}
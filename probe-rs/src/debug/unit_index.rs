@@ -0,0 +1,233 @@
+//! The index of the compilation units of the DWARF debug information, by address.
+//!
+//! The index is built from `.debug_aranges`, and from the root entries of the units which are
+//! missing there, so the units themselves are only parsed when they are used. As building the
+//! index still takes a while for large ELF files, it can be stored in a cache directory under
+//! the build ID of the file.
+
+use super::DwarfReader;
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// The version of the format of the cached index, which is part of the file name.
+const CACHE_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct UnitIndex {
+    /// The offsets of all units in `.debug_info`.
+    units: Vec<usize>,
+    /// The start and end addresses of the ranges of the units, and the offset of their unit,
+    /// sorted by start address.
+    ranges: Vec<(u64, u64, usize)>,
+}
+
+impl UnitIndex {
+    /// Builds the index from the address ranges in `aranges`, the contents of `.debug_aranges`,
+    /// and the root entries of the units which are missing there.
+    pub(crate) fn build(
+        dwarf: &gimli::Dwarf<DwarfReader>,
+        aranges: &[u8],
+    ) -> Result<Self, gimli::Error> {
+        let mut ranges = parse_aranges(aranges);
+        let covered: HashSet<_> = ranges.iter().map(|(_, _, offset)| *offset).collect();
+
+        let mut units = vec![];
+        let mut headers = dwarf.units();
+        while let Some(header) = headers.next()? {
+            let offset = match header.offset() {
+                gimli::UnitSectionOffset::DebugInfoOffset(offset) => offset.0,
+                gimli::UnitSectionOffset::DebugTypesOffset(_) => continue,
+            };
+            units.push(offset);
+
+            if covered.contains(&offset) {
+                continue;
+            }
+
+            let unit = dwarf.unit(header)?;
+            let mut unit_ranges = dwarf.unit_ranges(&unit)?;
+            while let Some(range) = unit_ranges.next()? {
+                ranges.push((range.begin, range.end, offset));
+            }
+        }
+
+        ranges.sort_unstable();
+
+        log::debug!(
+            "Indexed {} compilation units with {} address ranges",
+            units.len(),
+            ranges.len()
+        );
+
+        Ok(Self { units, ranges })
+    }
+
+    /// The offsets of all units in `.debug_info`.
+    pub(crate) fn units(&self) -> &[usize] {
+        &self.units
+    }
+
+    /// Returns the offset of the unit whose code contains `address`.
+    pub(crate) fn find(&self, address: u64) -> Option<usize> {
+        // All ranges which start at or before the address, the closest one first.
+        let candidates = match self
+            .ranges
+            .binary_search_by(|(start, _, _)| start.cmp(&address).then(std::cmp::Ordering::Less))
+        {
+            Ok(index) | Err(index) => &self.ranges[..index],
+        };
+
+        candidates
+            .iter()
+            .rev()
+            .find(|(start, end, _)| *start <= address && address < *end)
+            .map(|(_, _, offset)| *offset)
+    }
+
+    /// Loads the index of the file with `build_id` from `cache_dir`.
+    pub(crate) fn load(cache_dir: &Path, build_id: &[u8]) -> Option<Self> {
+        let path = cache_file(cache_dir, build_id);
+        let data = std::fs::read(&path).ok()?;
+
+        match serde_json::from_slice(&data) {
+            Ok(index) => Some(index),
+            Err(e) => {
+                log::warn!("The cached index {} is damaged: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    /// Stores the index of the file with `build_id` in `cache_dir`, which is created if necessary.
+    pub(crate) fn store(&self, cache_dir: &Path, build_id: &[u8]) -> std::io::Result<()> {
+        std::fs::create_dir_all(cache_dir)?;
+        std::fs::write(cache_file(cache_dir, build_id), serde_json::to_vec(self)?)
+    }
+}
+
+fn cache_file(cache_dir: &Path, build_id: &[u8]) -> PathBuf {
+    let build_id: String = build_id
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+
+    cache_dir.join(format!("{}-v{}.json", build_id, CACHE_VERSION))
+}
+
+/// Parses the address ranges of the units in `.debug_aranges`.
+///
+/// Sets in the 64 bit DWARF format, or with segment selectors, are skipped.
+fn parse_aranges(data: &[u8]) -> Vec<(u64, u64, usize)> {
+    let mut ranges = vec![];
+    let mut set = 0;
+
+    while let Some(length) = read(data, set, 4) {
+        let end = match (set + 4).checked_add(length as usize) {
+            Some(end) if length < 0xffff_fff0 && end <= data.len() => end,
+            _ => break,
+        };
+        let entries = &data[..end];
+
+        let offset = read(entries, set + 6, 4);
+        let address_size = read(entries, set + 10, 1);
+        let segment_size = read(entries, set + 11, 1);
+
+        match (offset, address_size, segment_size) {
+            (Some(offset), Some(size), Some(0)) if size == 4 || size == 8 => {
+                let size = size as usize;
+
+                // The entries are aligned to the size of an entry, from the start of the set.
+                let mut entry = set + 12;
+                entry += (2 * size - 12 % (2 * size)) % (2 * size);
+
+                while let (Some(address), Some(length)) = (
+                    read(entries, entry, size),
+                    read(entries, entry + size, size),
+                ) {
+                    if address == 0 && length == 0 {
+                        break;
+                    }
+                    ranges.push((address, address.saturating_add(length), offset as usize));
+                    entry += 2 * size;
+                }
+            }
+            _ => log::debug!(
+                "Skipping the address ranges at {:#x} of .debug_aranges",
+                set
+            ),
+        }
+
+        set = end;
+    }
+
+    ranges
+}
+
+/// Reads a little endian number of `size` bytes.
+fn read(data: &[u8], offset: usize, size: usize) -> Option<u64> {
+    let bytes = data.get(offset..offset.checked_add(size)?)?;
+
+    let mut value = [0; 8];
+    value[..size].copy_from_slice(bytes);
+    Some(u64::from_le_bytes(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_aranges, UnitIndex};
+
+    fn aranges() -> Vec<u8> {
+        let mut data = vec![];
+        // Version 2, unit at 0x40, 4 byte addresses, no segments, padding.
+        data.extend(&[28, 0, 0, 0, 2, 0, 0x40, 0, 0, 0, 4, 0, 0, 0, 0, 0]);
+        data.extend(&[0x00, 0x01, 0x00, 0x08, 0x20, 0x00, 0x00, 0x00]);
+        data.extend(&[0, 0, 0, 0, 0, 0, 0, 0]);
+        data
+    }
+
+    #[test]
+    fn address_ranges_are_parsed() {
+        let mut data = aranges();
+        // A set in the 64 bit format ends the table.
+        data.extend(&[0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0]);
+
+        assert_eq!(parse_aranges(&data), vec![(0x0800_0100, 0x0800_0120, 0x40)]);
+    }
+
+    #[test]
+    fn units_are_found_by_address() {
+        let index = UnitIndex {
+            units: vec![0, 0x40, 0x80],
+            ranges: vec![
+                (0x0800_0000, 0x0800_1000, 0),
+                (0x0800_0100, 0x0800_0120, 0x40),
+                (0x0800_1000, 0x0800_1010, 0x80),
+            ],
+        };
+
+        assert_eq!(index.find(0x0800_0104), Some(0x40));
+        assert_eq!(index.find(0x0800_0120), Some(0));
+        assert_eq!(index.find(0x0800_1000), Some(0x80));
+        assert_eq!(index.find(0x0800_1010), None);
+        assert_eq!(index.find(0x0000_0100), None);
+    }
+
+    #[test]
+    fn index_is_cached_by_build_id() {
+        let cache_dir =
+            std::env::temp_dir().join(format!("probe-rs-unit-index-{}", std::process::id()));
+        let index = UnitIndex {
+            units: vec![0x40],
+            ranges: parse_aranges(&aranges()),
+        };
+
+        assert_eq!(UnitIndex::load(&cache_dir, &[0xab, 0xcd]), None);
+        index.store(&cache_dir, &[0xab, 0xcd]).unwrap();
+        assert_eq!(UnitIndex::load(&cache_dir, &[0xab, 0xcd]), Some(index));
+        assert_eq!(UnitIndex::load(&cache_dir, &[0xab]), None);
+
+        std::fs::remove_dir_all(cache_dir).unwrap();
+    }
+}
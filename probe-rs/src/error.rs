@@ -1,5 +1,6 @@
-use crate::DebugProbeError;
-use crate::{architecture::arm::ap::AccessPortError, config::RegistryError};
+use crate::architecture::arm::{ap::AccessPortError, lock::RecoveryOption};
+use crate::config::{AccessAttribute, RegistryError};
+use crate::{AccessSize, DebugProbeError, OptionBytesError, ResetType};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -16,6 +17,51 @@ pub enum Error {
     ChipNotFound(#[from] RegistryError),
     #[error("This feature requires one of the following architectures: {0:?}")]
     ArchitectureRequired(&'static [&'static str]),
+    #[error("This operation requires the permission '{0}', which was not granted")]
+    MissingPermissions(String),
+    #[error("{0} accesses are not supported by the target or the probe")]
+    AccessSizeNotSupported(AccessSize),
+    #[error("An error with the option bytes occured")]
+    OptionBytes(#[from] OptionBytesError),
+    #[error(
+        "The reset type {reset_type} is not supported by the target. Supported reset types: {}",
+        format_list(.supported)
+    )]
+    UnsupportedResetType {
+        reset_type: ResetType,
+        supported: Vec<ResetType>,
+    },
+    #[error(
+        "The target has no debug freeze bit {name}. Available freeze bits: {}",
+        format_list(.available)
+    )]
+    UnknownFreezeBit {
+        name: String,
+        available: Vec<String>,
+    },
+    #[error("The operation was cancelled")]
+    Cancelled,
+    #[error(
+        "The debug access of the target{} is locked. It can be recovered with {}",
+        format_family(.family_hint),
+        format_recovery(.recovery_options)
+    )]
+    DeviceLocked {
+        /// The chip family whose protection was found, if it is known.
+        family_hint: Option<String>,
+        /// The ways to recover the chip, see [RecoveryOption].
+        recovery_options: Vec<RecoveryOption>,
+    },
+    #[error(
+        "The access at {address:#010x} is not possible, the memory region {region} {attribute}"
+    )]
+    MemoryAccessViolation {
+        address: u32,
+        /// The name or the address range of the region.
+        region: String,
+        /// The attribute of the region which the access violated.
+        attribute: AccessAttribute,
+    },
     #[error(transparent)]
     Other(#[from] anyhow::Error),
 }
@@ -31,3 +77,26 @@ impl From<AccessPortError> for Error {
         Error::architecture_specific(err)
     }
 }
+
+fn format_list(items: &[impl ToString]) -> String {
+    items
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn format_family(family_hint: &Option<String>) -> String {
+    family_hint
+        .as_ref()
+        .map(|family| format!(" ({})", family))
+        .unwrap_or_default()
+}
+
+fn format_recovery(recovery_options: &[RecoveryOption]) -> String {
+    recovery_options
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", or ")
+}
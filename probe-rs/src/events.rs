@@ -0,0 +1,161 @@
+//! Events of a [Session](crate::Session), for consumers which do not want to poll the target themselves.
+//!
+//! Every subscriber gets its own bounded channel. A subscriber which does not keep up misses
+//! the events which do not fit into its channel, which are counted, so the session never blocks.
+
+use crate::flashing::ProgressEvent;
+use crate::HaltReason;
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The number of events a subscriber can lag behind, unless it is given with
+/// [Session::subscribe_with_capacity](crate::Session::subscribe_with_capacity).
+pub(crate) const DEFAULT_CAPACITY: usize = 256;
+
+/// An event of a session.
+#[derive(Debug, Clone)]
+pub enum SessionEvent {
+    /// The core halted.
+    Halted {
+        /// The index of the core.
+        core: usize,
+        /// Why the core halted.
+        reason: HaltReason,
+    },
+    /// The core was resumed, or it was found running.
+    Running {
+        /// The index of the core.
+        core: usize,
+    },
+    /// The core was reset since it was last polled.
+    ResetDetected {
+        /// The index of the core.
+        core: usize,
+    },
+    /// Polling the target failed.
+    ProbeError(String),
+    /// New data can be read from an RTT up channel.
+    RttDataAvailable {
+        /// The number of the up channel.
+        channel: usize,
+    },
+    /// Progress of a flash download.
+    FlashProgress(ProgressEvent),
+}
+
+#[derive(Debug)]
+struct Subscriber {
+    sender: SyncSender<SessionEvent>,
+    dropped: Arc<AtomicU64>,
+}
+
+/// The subscribers of a session.
+///
+/// Clones share the subscribers, so events can be sent from places which do not hold the session.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct EventBus {
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+}
+
+impl EventBus {
+    pub(crate) fn subscribe(&self, capacity: usize) -> EventReceiver {
+        let (sender, receiver) = sync_channel(capacity);
+        let dropped = Arc::new(AtomicU64::new(0));
+
+        self.subscribers.lock().unwrap().push(Subscriber {
+            sender,
+            dropped: dropped.clone(),
+        });
+
+        EventReceiver { receiver, dropped }
+    }
+
+    /// Sends the event to all subscribers, and forgets the subscribers which are gone.
+    pub(crate) fn emit(&self, event: SessionEvent) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+
+        subscribers.retain(
+            |subscriber| match subscriber.sender.try_send(event.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    subscriber.dropped.fetch_add(1, Ordering::Relaxed);
+                    true
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            },
+        );
+    }
+
+    /// Returns `true` if anyone receives the events, so they do not have to be created otherwise.
+    pub(crate) fn has_subscribers(&self) -> bool {
+        !self.subscribers.lock().unwrap().is_empty()
+    }
+}
+
+/// Receives the events of a session, from [Session::subscribe](crate::Session::subscribe).
+#[derive(Debug)]
+pub struct EventReceiver {
+    receiver: Receiver<SessionEvent>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl EventReceiver {
+    /// Returns the next event, if there is one.
+    pub fn try_recv(&self) -> Option<SessionEvent> {
+        self.receiver.try_recv().ok()
+    }
+
+    /// Waits for the next event, at most for `timeout`.
+    ///
+    /// Returns `None` if no event was sent in time, or if the session is gone.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<SessionEvent> {
+        match self.receiver.recv_timeout(timeout) {
+            Ok(event) => Some(event),
+            Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => None,
+        }
+    }
+
+    /// Returns the events which were sent so far, without waiting for more.
+    pub fn try_iter(&self) -> impl Iterator<Item = SessionEvent> + '_ {
+        self.receiver.try_iter()
+    }
+
+    /// The number of events which were dropped, because this receiver did not keep up.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EventBus, SessionEvent};
+
+    #[test]
+    fn slow_subscribers_count_dropped_events() {
+        let bus = EventBus::default();
+        let slow = bus.subscribe(2);
+        let fast = bus.subscribe(8);
+
+        for core in 0..4 {
+            bus.emit(SessionEvent::Running { core });
+        }
+
+        assert_eq!(slow.try_iter().count(), 2);
+        assert_eq!(slow.dropped(), 2);
+        assert_eq!(fast.try_iter().count(), 4);
+        assert_eq!(fast.dropped(), 0);
+    }
+
+    #[test]
+    fn gone_subscribers_are_removed() {
+        let bus = EventBus::default();
+        drop(bus.subscribe(1));
+        assert!(bus.has_subscribers());
+
+        bus.emit(SessionEvent::ProbeError("gone".into()));
+        assert!(!bus.has_subscribers());
+    }
+}
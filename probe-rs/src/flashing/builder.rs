@@ -1,6 +1,7 @@
 use std::fmt::{Debug, Formatter};
+use std::ops::Range;
 
-use super::{FlashError, FlashVisualizer};
+use super::{FillPolicy, FlashError, FlashVisualizer, PreservePriority};
 use crate::config::{FlashAlgorithm, MemoryRange, PageInfo, SectorInfo};
 
 /// The description of a page in flash.
@@ -136,6 +137,13 @@ impl FlashLayout {
         &mut self.pages
     }
 
+    /// Returns `true` if `page` is the first page of one of the sectors.
+    pub(super) fn starts_sector(&self, page: &FlashPage) -> bool {
+        self.sectors
+            .binary_search_by_key(&page.address(), FlashSector::address)
+            .is_ok()
+    }
+
     /// Get the fills of the flash layout.
     pub fn fills(&self) -> &[FlashFill] {
         &self.fills
@@ -149,6 +157,129 @@ impl FlashLayout {
     pub fn visualize(&self) -> FlashVisualizer {
         FlashVisualizer::new(&self)
     }
+
+    /// Creates a layout which erases all sectors in `range`, without programming any pages.
+    pub(super) fn erase_range(flash_algorithm: &FlashAlgorithm, range: &Range<u32>) -> Self {
+        let mut sectors = vec![];
+        let mut address = range.start;
+
+        while address < range.end {
+            let sector_info = match flash_algorithm.sector_info(address) {
+                Some(sector_info) => sector_info,
+                None => break,
+            };
+
+            sectors.push(FlashSector::new(&sector_info));
+            address = sector_info.base_address + sector_info.size;
+        }
+
+        Self {
+            sectors,
+            pages: vec![],
+            fills: vec![],
+            data_blocks: vec![],
+        }
+    }
+
+    /// Removes the given sectors from the layout.
+    ///
+    /// All pages which are not part of any remaining sector are removed as well,
+    /// together with their fills.
+    pub(super) fn remove_sectors(&mut self, removed: &[FlashSector]) {
+        self.sectors.retain(|sector| !removed.contains(sector));
+
+        let sectors = &self.sectors;
+        let mut page_indices = Vec::with_capacity(self.pages.len());
+        let mut next_index = 0;
+
+        for page in &self.pages {
+            let page_range = page.address()..page.address() + page.size();
+            let keep = sectors.iter().any(|sector| {
+                page_range.intersects_range(&(sector.address()..sector.address() + sector.size()))
+            });
+
+            if keep {
+                page_indices.push(Some(next_index));
+                next_index += 1;
+            } else {
+                page_indices.push(None);
+            }
+        }
+
+        let mut index = 0;
+        self.pages.retain(|_| {
+            index += 1;
+            page_indices[index - 1].is_some()
+        });
+
+        self.fills
+            .retain(|fill| page_indices[fill.page_index].is_some());
+        for fill in &mut self.fills {
+            // The fill was retained, so the page was kept as well.
+            fill.page_index = page_indices[fill.page_index].unwrap();
+        }
+    }
+
+    /// Adds fills for all parts of `range` which are contained in a page of the layout.
+    fn add_fills_for_range(&mut self, range: &Range<u32>) {
+        for (page_index, page) in self.pages.iter().enumerate() {
+            let start = range.start.max(page.address());
+            let end = range.end.min(page.address() + page.size());
+
+            if start < end {
+                add_fill(start, end - start, &mut self.fills, page_index);
+            }
+        }
+    }
+
+    /// Returns an error for the first fill which is not contained in one of the `allowed` ranges.
+    fn check_fills_within(&self, allowed: &[Range<u32>]) -> Result<(), FlashError> {
+        for fill in &self.fills {
+            let mut remaining = vec![fill.address()..fill.address() + fill.size()];
+
+            for range in allowed {
+                remaining = remaining
+                    .into_iter()
+                    .flat_map(|part| {
+                        vec![
+                            part.start..range.start.min(part.end),
+                            range.end.max(part.start)..part.end,
+                        ]
+                    })
+                    .filter(|part| part.start < part.end)
+                    .collect();
+            }
+
+            if let Some(part) = remaining.first() {
+                return Err(FlashError::UnwrittenBytes {
+                    start: part.start,
+                    end: part.end,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Shrinks all fills to the parts which intersect one of the given `ranges`.
+    ///
+    /// Fills which do not intersect any of the ranges are removed.
+    fn restrict_fills_to(&mut self, ranges: &[Range<u32>]) {
+        let mut fills = vec![];
+
+        for fill in &self.fills {
+            for range in ranges {
+                let start = range.start.max(fill.address());
+                let end = range.end.min(fill.address() + fill.size());
+
+                if start < end {
+                    add_fill(start, end - start, &mut fills, fill.page_index());
+                }
+            }
+        }
+
+        self.fills = fills;
+    }
 }
 
 /// A block of data that is to be written to flash.
@@ -216,6 +347,8 @@ impl<'data> From<&FlashDataBlock<'data>> for FlashDataBlockSpan {
 #[derive(Default)]
 pub(super) struct FlashBuilder<'data> {
     data_blocks: Vec<FlashDataBlock<'data>>,
+    preserved_ranges: Vec<Range<u32>>,
+    preserve_priority: Option<PreservePriority>,
 }
 
 impl<'data> FlashBuilder<'data> {
@@ -223,7 +356,111 @@ impl<'data> FlashBuilder<'data> {
     pub(super) fn new() -> Self {
         Self {
             data_blocks: vec![],
+            preserved_ranges: vec![],
+            preserve_priority: None,
+        }
+    }
+
+    /// Sets the address ranges whose current flash contents have to be preserved.
+    ///
+    /// `priority` decides which data wins if a preserved range overlaps with data to be programmed.
+    /// If it is `None`, such an overlap is an error.
+    pub(super) fn preserve(&mut self, ranges: &[Range<u32>], priority: Option<PreservePriority>) {
+        self.preserved_ranges = ranges.to_vec();
+        self.preserve_priority = priority;
+    }
+
+    /// Returns `true` if any address ranges have to be preserved.
+    pub(super) fn has_preserved_ranges(&self) -> bool {
+        !self.preserved_ranges.is_empty()
+    }
+
+    /// Returns the added blocks of data, sorted by their address.
+    pub(super) fn data_blocks(&self) -> impl Iterator<Item = (u32, &'data [u8])> + '_ {
+        self.data_blocks
+            .iter()
+            .map(|block| (block.address, block.data))
+    }
+
+    /// Returns all data which is expected to be in flash after programming.
+    ///
+    /// Data in preserved ranges is left out if the existing flash contents take priority.
+    pub(super) fn expected_data(&self) -> Vec<(u32, &'data [u8])> {
+        let mut expected = vec![];
+
+        for block in &self.data_blocks {
+            let mut pieces = vec![(block.address, block.data)];
+
+            if self.preserve_priority == Some(PreservePriority::Existing) {
+                for range in &self.preserved_ranges {
+                    pieces = pieces
+                        .into_iter()
+                        .flat_map(|(address, data): (u32, &'data [u8])| {
+                            let end = address + data.len() as u32;
+                            let cut_start = range.start.max(address).min(end);
+                            let cut_end = range.end.min(end).max(cut_start);
+
+                            vec![
+                                (address, &data[..(cut_start - address) as usize]),
+                                (cut_end, &data[(cut_end - address) as usize..]),
+                            ]
+                        })
+                        .filter(|(_, data)| !data.is_empty())
+                        .collect();
+                }
+            }
+
+            expected.extend(pieces);
         }
+
+        expected
+    }
+
+    /// Adds the fills required to preserve the contents of the preserved ranges to `flash_layout`.
+    ///
+    /// The layout has to be built including empty pages.
+    /// If `restore_unwritten_bytes` is `false`, all fills which are not required
+    /// to preserve the preserved ranges are removed.
+    pub(super) fn apply_preserved_ranges(
+        &self,
+        flash_layout: &mut FlashLayout,
+        restore_unwritten_bytes: bool,
+    ) -> Result<(), FlashError> {
+        for range in &self.preserved_ranges {
+            for block in &self.data_blocks {
+                let start = range.start.max(block.address());
+                let end = range.end.min(block.address() + block.size());
+
+                if start >= end {
+                    continue;
+                }
+
+                match self.preserve_priority {
+                    None => return Err(FlashError::PreservedRangeOverlap { start, end }),
+                    Some(PreservePriority::Image) => {
+                        log::warn!(
+                            "The preserved range {:#010x}..{:#010x} is overwritten by the image.",
+                            start,
+                            end
+                        );
+                    }
+                    Some(PreservePriority::Existing) => {
+                        log::warn!(
+                            "Keeping the existing contents of {:#010x}..{:#010x} instead of the image data.",
+                            start,
+                            end
+                        );
+                        flash_layout.add_fills_for_range(&(start..end));
+                    }
+                }
+            }
+        }
+
+        if !restore_unwritten_bytes {
+            flash_layout.restrict_fills_to(&self.preserved_ranges);
+        }
+
+        Ok(())
     }
 
     /// Add a block of data to be programmed.
@@ -271,6 +508,34 @@ impl<'data> FlashBuilder<'data> {
         Ok(())
     }
 
+    /// Layouts the contents of a flash memory and applies the `fill_policy` and the preserved ranges.
+    ///
+    /// With [FillPolicy::ReadBack] and [FillPolicy::Error], and if ranges have to be preserved,
+    /// the sectors are covered by pages completely.
+    pub(super) fn build_layout(
+        &self,
+        flash_algorithm: &FlashAlgorithm,
+        fill_policy: FillPolicy,
+    ) -> Result<FlashLayout, FlashError> {
+        let preserving = self.has_preserved_ranges();
+
+        let mut flash_layout = self.build_sectors_and_pages(
+            flash_algorithm,
+            fill_policy != FillPolicy::EraseValue || preserving,
+        )?;
+
+        // Bytes in preserved ranges are not lost, so they don't have to be written by the image.
+        if fill_policy == FillPolicy::Error {
+            flash_layout.check_fills_within(&self.preserved_ranges)?;
+        }
+
+        if preserving {
+            self.apply_preserved_ranges(&mut flash_layout, fill_policy == FillPolicy::ReadBack)?;
+        }
+
+        Ok(flash_layout)
+    }
+
     /// Layouts the contents of a flash memory according to the contents of the flash builder.
     pub(super) fn build_sectors_and_pages(
         &self,
@@ -345,10 +610,17 @@ impl<'data> FlashBuilder<'data> {
                 page.data[page_offset..page_offset + size]
                     .copy_from_slice(&block.data[block_offset..block_offset + size]);
 
+                // If the previous block ended in this page, its fill already covers the gap
+                // up to the start of this block.
+                let previous_block_in_page = n > 0 && {
+                    let previous = &self.data_blocks[n - 1];
+                    previous.address + previous.size() > page_address
+                };
+
                 // If we start working a new block (condition: block_offset == 0)
                 // and we don't start a new page (condition: page_offset == 0)
                 // We need to fill the start of the page up until the page offset where the new data will start.
-                if block_offset == 0 && page_offset != 0 {
+                if block_offset == 0 && page_offset != 0 && !previous_block_in_page {
                     add_fill(
                         page_address,
                         page_offset as u32,
@@ -387,18 +659,21 @@ impl<'data> FlashBuilder<'data> {
                     );
                 }
 
-                // Denotes whether a new sector will be done next iteration round.
-                let start_new_sector =
-                    current_block_address + size as u32 >= sector_address + sector_size;
-                // Denotes whether we are done with the flash building process now.
-                let last_bit_of_block = block_offset + size == block.size() as usize
-                    && !self.data_blocks.is_empty()
-                    && n == self.data_blocks.len() - 1;
+                // The address of the data which is written next, if there is any.
+                let next_data_address = if block_offset + size == block.size() as usize {
+                    data_iter.peek().map(|(_, next_block)| next_block.address)
+                } else {
+                    Some(current_block_address + size as u32)
+                };
+                // Denotes whether we are done with the current sector, because the next data
+                // is in a different sector or all data has been written.
+                let sector_done = next_data_address
+                    .map_or(true, |address| address >= sector_address + sector_size);
 
-                // If one of the two conditions resolves to true, and we are including
+                // If we are done with the sector, and we are including
                 // pages which will only contain fill, then we fill all remaining pages
                 // for the current sector.
-                if (start_new_sector || last_bit_of_block) && include_empty_pages {
+                if sector_done && include_empty_pages {
                     // Iterate all possible sector pages and see if they have been created yet.
                     let pages_per_sector =
                         (sector_size / flash_algorithm.flash_properties.page_size) as usize;
@@ -1262,4 +1537,366 @@ mod tests {
             }
         )
     }
+
+    #[test]
+    fn remove_sector_with_its_pages_and_fills() {
+        let flash_algorithm = assemble_demo_flash1();
+        let mut flash_builder = FlashBuilder::new();
+        let data = vec![42; 0x1100];
+        flash_builder.add_data(0x0, &data).unwrap();
+        let mut flash_layout = flash_builder
+            .build_sectors_and_pages(&flash_algorithm, true)
+            .unwrap();
+
+        let first_sector = flash_layout.sectors()[0].clone();
+        flash_layout.remove_sectors(&[first_sector]);
+
+        assert_eq!(
+            flash_layout.sectors(),
+            &[FlashSector {
+                address: 0x1000,
+                size: 0x1000,
+            }]
+        );
+        assert_eq!(
+            flash_layout
+                .pages()
+                .iter()
+                .map(|page| page.address())
+                .collect::<Vec<_>>(),
+            vec![0x1000, 0x1400, 0x1800, 0x1C00]
+        );
+        assert_eq!(
+            flash_layout.fills(),
+            &[
+                FlashFill {
+                    address: 0x1100,
+                    size: 0x300,
+                    page_index: 0,
+                },
+                FlashFill {
+                    address: 0x1400,
+                    size: 0x400,
+                    page_index: 1,
+                },
+                FlashFill {
+                    address: 0x1800,
+                    size: 0x400,
+                    page_index: 2,
+                },
+                FlashFill {
+                    address: 0x1C00,
+                    size: 0x400,
+                    page_index: 3,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn preserved_range_overlapping_data_is_an_error() {
+        let flash_algorithm = assemble_demo_flash1();
+        let mut flash_builder = FlashBuilder::new();
+        flash_builder.add_data(0x0, &[42; 0x100]).unwrap();
+        flash_builder.preserve(&[0x80..0x200], None);
+        let mut flash_layout = flash_builder
+            .build_sectors_and_pages(&flash_algorithm, true)
+            .unwrap();
+
+        assert!(matches!(
+            flash_builder.apply_preserved_ranges(&mut flash_layout, false),
+            Err(FlashError::PreservedRangeOverlap {
+                start: 0x80,
+                end: 0x100
+            })
+        ));
+    }
+
+    #[test]
+    fn preserved_range_is_filled() {
+        let flash_algorithm = assemble_demo_flash1();
+        let mut flash_builder = FlashBuilder::new();
+        flash_builder.add_data(0x0, &[42; 0x100]).unwrap();
+        flash_builder.preserve(&[0xF00..0x1000], None);
+        let mut flash_layout = flash_builder
+            .build_sectors_and_pages(&flash_algorithm, true)
+            .unwrap();
+
+        flash_builder
+            .apply_preserved_ranges(&mut flash_layout, false)
+            .unwrap();
+
+        assert_eq!(
+            flash_layout.fills(),
+            &[FlashFill {
+                address: 0xF00,
+                size: 0x100,
+                page_index: 3,
+            }]
+        );
+    }
+
+    #[test]
+    fn preserved_range_with_existing_priority() {
+        let flash_algorithm = assemble_demo_flash1();
+        let mut flash_builder = FlashBuilder::new();
+        flash_builder.add_data(0x0, &[42; 0x100]).unwrap();
+        flash_builder.preserve(&[0x80..0x200], Some(PreservePriority::Existing));
+        let mut flash_layout = flash_builder
+            .build_sectors_and_pages(&flash_algorithm, true)
+            .unwrap();
+
+        flash_builder
+            .apply_preserved_ranges(&mut flash_layout, false)
+            .unwrap();
+
+        assert_eq!(
+            flash_layout.fills(),
+            &[
+                FlashFill {
+                    address: 0x100,
+                    size: 0x100,
+                    page_index: 0,
+                },
+                FlashFill {
+                    address: 0x80,
+                    size: 0x80,
+                    page_index: 0,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn expected_data_excludes_existing_preserved_ranges() {
+        let data = [42; 0x100];
+        let mut flash_builder = FlashBuilder::new();
+        flash_builder.add_data(0x0, &data).unwrap();
+
+        flash_builder.preserve(&[0x80..0x90], Some(PreservePriority::Image));
+        assert_eq!(flash_builder.expected_data(), vec![(0x0, &data[..])]);
+
+        flash_builder.preserve(&[0x80..0x90], Some(PreservePriority::Existing));
+        assert_eq!(
+            flash_builder.expected_data(),
+            vec![(0x0, &data[..0x80]), (0x90, &data[0x90..])]
+        );
+    }
+
+    fn page_addresses(flash_layout: &FlashLayout) -> Vec<u32> {
+        flash_layout
+            .pages()
+            .iter()
+            .map(|page| page.address())
+            .collect()
+    }
+
+    #[test]
+    fn data_starting_mid_page() {
+        let flash_algorithm = assemble_demo_flash1();
+        let mut flash_builder = FlashBuilder::new();
+        flash_builder.add_data(0x410, &[42; 17]).unwrap();
+        let flash_layout = flash_builder
+            .build_layout(&flash_algorithm, FillPolicy::EraseValue)
+            .unwrap();
+
+        assert_eq!(page_addresses(&flash_layout), vec![0x400]);
+        assert_eq!(
+            flash_layout.fills(),
+            &[
+                FlashFill {
+                    address: 0x400,
+                    size: 0x10,
+                    page_index: 0,
+                },
+                FlashFill {
+                    address: 0x421,
+                    size: 0x3DF,
+                    page_index: 0,
+                },
+            ]
+        );
+
+        let data = flash_layout.pages()[0].data();
+        assert!(data[..0x10].iter().all(|&b| b == 0xFF));
+        assert!(data[0x10..0x21].iter().all(|&b| b == 42));
+        assert!(data[0x21..].iter().all(|&b| b == 0xFF));
+    }
+
+    #[test]
+    fn data_ending_mid_page() {
+        let flash_algorithm = assemble_demo_flash1();
+        let mut flash_builder = FlashBuilder::new();
+        flash_builder.add_data(0x0, &[42; 0x500]).unwrap();
+        let flash_layout = flash_builder
+            .build_layout(&flash_algorithm, FillPolicy::ReadBack)
+            .unwrap();
+
+        assert_eq!(
+            page_addresses(&flash_layout),
+            vec![0x0, 0x400, 0x800, 0xC00]
+        );
+        assert_eq!(
+            flash_layout.fills(),
+            &[
+                FlashFill {
+                    address: 0x500,
+                    size: 0x300,
+                    page_index: 1,
+                },
+                FlashFill {
+                    address: 0x800,
+                    size: 0x400,
+                    page_index: 2,
+                },
+                FlashFill {
+                    address: 0xC00,
+                    size: 0x400,
+                    page_index: 3,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn two_chunks_in_one_page_with_gap() {
+        let flash_algorithm = assemble_demo_flash1();
+        let mut flash_builder = FlashBuilder::new();
+        flash_builder.add_data(0x0, &[42; 0x10]).unwrap();
+        flash_builder.add_data(0x20, &[43; 0x10]).unwrap();
+        let flash_layout = flash_builder
+            .build_layout(&flash_algorithm, FillPolicy::ReadBack)
+            .unwrap();
+
+        // The gap is filled exactly once, and no fill covers any of the data.
+        assert_eq!(
+            &flash_layout.fills()[..2],
+            &[
+                FlashFill {
+                    address: 0x10,
+                    size: 0x10,
+                    page_index: 0,
+                },
+                FlashFill {
+                    address: 0x30,
+                    size: 0x3D0,
+                    page_index: 0,
+                },
+            ]
+        );
+        assert_eq!(flash_layout.fills().len(), 5);
+
+        let data = flash_layout.pages()[0].data();
+        assert!(data[..0x10].iter().all(|&b| b == 42));
+        assert!(data[0x20..0x30].iter().all(|&b| b == 43));
+    }
+
+    #[test]
+    fn chunk_spanning_sector_boundary() {
+        let flash_algorithm = assemble_demo_flash1();
+        let mut flash_builder = FlashBuilder::new();
+        flash_builder.add_data(0xFF0, &[42; 0x20]).unwrap();
+        let flash_layout = flash_builder
+            .build_layout(&flash_algorithm, FillPolicy::ReadBack)
+            .unwrap();
+
+        assert_eq!(
+            flash_layout.sectors(),
+            &[
+                FlashSector {
+                    address: 0x0,
+                    size: 0x1000,
+                },
+                FlashSector {
+                    address: 0x1000,
+                    size: 0x1000,
+                },
+            ]
+        );
+        assert_eq!(
+            page_addresses(&flash_layout),
+            vec![0xC00, 0x0, 0x400, 0x800, 0x1000, 0x1400, 0x1800, 0x1C00]
+        );
+        assert_eq!(
+            flash_layout.fills()[0],
+            FlashFill {
+                address: 0xC00,
+                size: 0x3F0,
+                page_index: 0,
+            }
+        );
+        assert_eq!(
+            flash_layout.fills()[4],
+            FlashFill {
+                address: 0x1010,
+                size: 0x3F0,
+                page_index: 4,
+            }
+        );
+        assert_eq!(flash_layout.fills().len(), 8);
+    }
+
+    #[test]
+    fn chunks_in_separate_sectors_fill_both_sectors() {
+        let flash_algorithm = assemble_demo_flash1();
+        let mut flash_builder = FlashBuilder::new();
+        flash_builder.add_data(0x0, &[42; 0x10]).unwrap();
+        flash_builder.add_data(0x1000, &[42; 0x10]).unwrap();
+        let flash_layout = flash_builder
+            .build_layout(&flash_algorithm, FillPolicy::ReadBack)
+            .unwrap();
+
+        assert_eq!(
+            page_addresses(&flash_layout),
+            vec![0x0, 0x400, 0x800, 0xC00, 0x1000, 0x1400, 0x1800, 0x1C00]
+        );
+    }
+
+    #[test]
+    fn error_fill_policy_rejects_unwritten_bytes() {
+        let flash_algorithm = assemble_demo_flash1();
+        let mut flash_builder = FlashBuilder::new();
+        flash_builder.add_data(0x410, &[42; 17]).unwrap();
+
+        assert!(matches!(
+            flash_builder.build_layout(&flash_algorithm, FillPolicy::Error),
+            Err(FlashError::UnwrittenBytes {
+                start: 0x400,
+                end: 0x410
+            })
+        ));
+
+        let data = [42; 0x1000];
+        let mut flash_builder = FlashBuilder::new();
+        flash_builder.add_data(0x0, &data[..0xF00]).unwrap();
+        assert!(flash_builder
+            .build_layout(&flash_algorithm, FillPolicy::Error)
+            .is_err());
+
+        // Bytes in preserved ranges are allowed to be left out of the image.
+        flash_builder.preserve(&[0xF00..0x1000], None);
+        assert!(flash_builder
+            .build_layout(&flash_algorithm, FillPolicy::Error)
+            .is_ok());
+    }
+
+    #[test]
+    fn erase_range_contains_all_sectors() {
+        let flash_algorithm = assemble_demo_flash1();
+        let flash_layout = FlashLayout::erase_range(&flash_algorithm, &(0x1000..0x4000));
+
+        assert_eq!(
+            flash_layout
+                .sectors()
+                .iter()
+                .map(|sector| sector.address())
+                .collect::<Vec<_>>(),
+            vec![0x1000, 0x2000, 0x3000]
+        );
+        assert!(flash_layout.pages().is_empty());
+
+        // Sectors outside of the flash are not part of the layout.
+        let flash_layout = FlashLayout::erase_range(&flash_algorithm, &(0xF000..0x20000));
+        assert_eq!(flash_layout.sectors().len(), 1);
+    }
 }
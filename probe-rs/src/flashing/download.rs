@@ -7,11 +7,17 @@ use object::{
 use std::{
     fs::File,
     io::{Read, Seek, SeekFrom},
-    path::Path,
+    ops::Range,
+    path::{Path, PathBuf},
 };
 
 use super::*;
-use crate::{config::MemoryRange, session::Session};
+use crate::{
+    asynchronous::Cancellation,
+    config::{MemoryRange, RawFlashAlgorithm, Target},
+    session::Session,
+    Permissions,
+};
 
 use thiserror::Error;
 
@@ -24,6 +30,14 @@ pub struct BinOptions {
     pub skip: u32,
 }
 
+/// Extended options for flashing a UF2 file.
+#[derive(Debug, Default)]
+pub struct Uf2Options {
+    /// If `allow_family_mismatch` is `true`, a family ID in the file which does not match the
+    /// selected target only results in a warning instead of an error.
+    pub allow_family_mismatch: bool,
+}
+
 /// A finite list of all the available binary formats probe-rs understands.
 #[derive(Debug)]
 pub enum Format {
@@ -35,6 +49,18 @@ pub enum Format {
     Hex,
     /// Marks a file in the [ELF](https://en.wikipedia.org/wiki/Executable_and_Linkable_Format) format.
     Elf,
+    /// Marks a file in the [UF2](https://github.com/microsoft/uf2) format.
+    /// [Uf2Options] can be used to control how the family ID in the file is checked against the target.
+    Uf2(Uf2Options),
+}
+
+/// A file which is downloaded together with other files, see [download_files_with_options].
+#[derive(Debug)]
+pub struct Image {
+    /// The path of the file.
+    pub path: PathBuf,
+    /// The format of the file.
+    pub format: Format,
 }
 
 /// A finite list of all the errors that can occur when flashing a given file.
@@ -65,6 +91,51 @@ pub enum FileDownloadError {
     /// This is most likely because of a bad linker script.
     #[error("No loadable ELF sections were found.")]
     NoLoadableSegments,
+    /// Reading and decoding the given UF2 file has resulted in the given error.
+    #[error("Could not read UF2 file")]
+    Uf2(#[from] Uf2Error),
+    /// Two of the downloaded files contain data for the same addresses.
+    #[error("{first} and {second} both contain data for {start:#010x}..{end:#010x}.")]
+    ImageOverlap {
+        /// The path of the file which was added first.
+        first: String,
+        /// The path of the file which overlaps with it.
+        second: String,
+        /// The start of the overlapping range.
+        start: u32,
+        /// The end of the overlapping range.
+        end: u32,
+    },
+}
+
+/// Decides which data is written if a preserved range overlaps with data in the image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PreservePriority {
+    /// The existing flash contents are kept, the image data is dropped.
+    Existing,
+    /// The image data is written, the existing flash contents are lost.
+    Image,
+}
+
+/// Decides what is written to the bytes of erased flash sectors which are not part of the image.
+///
+/// This applies to the bytes in front of and after the data in a page, to gaps between
+/// data chunks, and to the pages of a sector which contain no data at all.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FillPolicy {
+    /// The bytes are left erased.
+    EraseValue,
+    /// The bytes are read from the flash before erasing and written again afterwards,
+    /// such that their contents are untouched.
+    ReadBack,
+    /// Flashing fails if any bytes would be erased without being written by the image.
+    Error,
+}
+
+impl Default for FillPolicy {
+    fn default() -> Self {
+        FillPolicy::EraseValue
+    }
 }
 
 /// Options for downloading a file onto a target chip.
@@ -72,13 +143,79 @@ pub enum FileDownloadError {
 pub struct DownloadOptions<'progress> {
     /// An optional progress reporter which is used if this argument is set to `Some(...)`.
     pub progress: Option<&'progress FlashProgress>,
-    /// If `keep_unwritten_bytes` is `true`, erased portions of the flash that are not overwritten by the ELF data
-    /// are restored afterwards, such that the old contents are untouched.
+    /// Decides what happens to erased portions of the flash that are not overwritten by the image.
     ///
-    /// This is necessary because the flash can only be erased in sectors. If only parts of the erased sector are written thereafter,
+    /// The flash can only be erased in sectors. If only parts of the erased sector are written thereafter,
     /// instead of the full sector, the excessively erased bytes wont match the contents before the erase which might not be intuitive
     /// to the user or even worse, result in unexpected behavior if those contents contain important data.
-    pub keep_unwritten_bytes: bool,
+    /// Use [FillPolicy::ReadBack] to restore these bytes.
+    pub fill_policy: FillPolicy,
+    /// If `skip_unchanged_sectors` is `true`, the flash contents are read back before erasing,
+    /// and sectors which already contain the data to be written are neither erased nor programmed.
+    ///
+    /// This speeds up flashing considerably if only small parts of a large image have changed.
+    pub skip_unchanged_sectors: bool,
+    /// If `verify` is `true`, the flash contents are compared with the image after programming.
+    ///
+    /// The `Verify()` routine of the flash algorithm is used if available,
+    /// otherwise the flash contents are read back.
+    pub verify: bool,
+    /// If `disable_double_buffering` is `true`, each page is transferred to the target only after
+    /// the previous page has been programmed.
+    ///
+    /// By default, the next page is transferred while the previous one is being programmed,
+    /// if the RAM of the target is large enough for two page buffers.
+    /// Disabling this can be necessary for flash algorithms which do not tolerate RAM accesses while they run.
+    pub disable_double_buffering: bool,
+    /// Address ranges whose current flash contents are preserved.
+    ///
+    /// Sectors overlapping these ranges are read before erasing, and the preserved contents
+    /// are programmed again together with the new data.
+    pub preserved_ranges: Vec<Range<u32>>,
+    /// Decides which data is written if a preserved range overlaps with data in the image.
+    ///
+    /// If this is `None`, such an overlap results in an error.
+    pub preserve_priority: Option<PreservePriority>,
+    /// If `flash_algo_debug` is `true`, the registers and the stack of the flash algorithm are logged
+    /// when one of its routines fails, and the core is left halted at the failure.
+    ///
+    /// This is meant for developing and debugging flash algorithms.
+    pub flash_algo_debug: bool,
+    /// The name of a flash algorithm routine, e.g. `program_page`, which is not run.
+    /// Instead, the core is left halted at the entry of the routine with its arguments set up,
+    /// such that the routine can be stepped through with a debugger.
+    ///
+    /// This implies `flash_algo_debug`.
+    pub flash_algo_break: Option<String>,
+    /// How often programming a flash region is retried after a failure which was not reported
+    /// by the flash algorithm itself, e.g. a communication error with the probe.
+    ///
+    /// Sectors which were completely programmed before the failure are not erased and programmed again.
+    /// Retrying is disabled if erased bytes are read back, as their contents might already be lost.
+    pub retries: u32,
+    /// The permissions for writing to special regions.
+    ///
+    /// Writing to one-time programmable regions requires [Permissions::allow_otp_write].
+    pub permissions: Permissions,
+    /// Additional flash algorithms which are only used for this download,
+    /// e.g. loaded with [RawFlashAlgorithm::from_elf].
+    ///
+    /// They are used instead of the algorithms of the target for their address range.
+    /// Address ranges which are not part of the memory map of the target, like an external flash,
+    /// are added to it.
+    pub flash_algorithms: Vec<RawFlashAlgorithm>,
+    /// If `keep_core_state` is `true`, the core is only halted before the flash algorithm runs,
+    /// instead of being reset, and its registers and the RAM used by the flash algorithm are
+    /// restored after programming.
+    ///
+    /// This allows to program the flash of a target which is being debugged, e.g. to patch
+    /// breakpoint instructions into it, and to continue the program afterwards.
+    pub keep_core_state: bool,
+    /// If set, erasing and programming stop at the next sector boundary once the cancellation
+    /// is cancelled, and the download fails with [FlashError::Cancelled].
+    ///
+    /// Sectors which were erased but not yet programmed stay erased.
+    pub cancellation: Option<Cancellation>,
 }
 
 /// Downloads a file of given `format` at `path` to the flash of the target given in `session`.
@@ -105,21 +242,30 @@ pub fn download_file_with_options(
     format: Format,
     options: DownloadOptions<'_>,
 ) -> Result<(), FileDownloadError> {
-    let mut file = match File::open(path) {
-        Ok(file) => file,
-        Err(e) => return Err(FileDownloadError::IO(e)),
-    };
-    let mut buffer = vec![];
-    let mut buffer_vec = vec![];
-    // IMPORTANT: Change this to an actual memory map of a real chip
-    let memory_map = session.target().memory_map.clone();
-    let mut loader = FlashLoader::new(&memory_map, options.keep_unwritten_bytes);
+    download_files_with_options(
+        session,
+        vec![Image {
+            path: path.to_owned(),
+            format,
+        }],
+        options,
+    )
+}
 
-    match format {
-        Format::Bin(options) => download_bin(&mut buffer, &mut file, &mut loader, options),
-        Format::Elf => download_elf(&mut buffer, &mut file, &mut loader),
-        Format::Hex => download_hex(&mut buffer_vec, &mut file, &mut loader),
-    }?;
+/// Downloads several files, e.g. a bootloader and an application, to the flash of the target given in `session`.
+///
+/// The data of all files is merged before the flash is erased and programmed once,
+/// files which contain data for the same addresses result in [FileDownloadError::ImageOverlap].
+pub fn download_files_with_options(
+    session: &mut Session,
+    images: Vec<Image>,
+    options: DownloadOptions<'_>,
+) -> Result<(), FileDownloadError> {
+    let target = session.target().clone();
+    let mut buffers = ImageBuffers::open(&images)?;
+    let mut loader = flash_loader(&target, &options)?;
+
+    buffers.add_to(&mut loader, images, &target)?;
 
     loader
         // TODO: hand out chip erase flag
@@ -131,6 +277,210 @@ pub fn download_file_with_options(
         .map_err(FileDownloadError::Flash)
 }
 
+/// Compares the flash contents of the target given in `session` with the files, without programming anything.
+///
+/// The files are laid out like [download_files_with_options] does. The returned ranges differ
+/// from the files, if the flash contains exactly the data of the files, the result is empty.
+/// The `Verify()` routine of the flash algorithms is used where it is available,
+/// otherwise the flash contents are read back.
+pub fn verify_files_with_options(
+    session: &mut Session,
+    images: Vec<Image>,
+    options: DownloadOptions<'_>,
+) -> Result<Vec<Range<u32>>, FileDownloadError> {
+    let target = session.target().clone();
+    let mut buffers = ImageBuffers::open(&images)?;
+    let mut loader = flash_loader(&target, &options)?;
+
+    buffers.add_to(&mut loader, images, &target)?;
+
+    loader
+        .verify(
+            session,
+            options.progress.unwrap_or(&FlashProgress::new(|_| {})),
+        )
+        .map_err(FileDownloadError::Flash)
+}
+
+/// Downloads chunks of data to the flash of the target given in `session`.
+///
+/// Each chunk is given by its start address and its contents. This is useful if the data
+/// does not come from a file, e.g. if it was received from a debugger.
+/// Chunks which lie in RAM are written to the RAM after the flash is programmed.
+pub fn download_data(
+    session: &mut Session,
+    chunks: &[(u32, Vec<u8>)],
+    options: DownloadOptions<'_>,
+) -> Result<(), FlashError> {
+    let target = session.target().clone();
+    let mut loader = flash_loader(&target, &options)?;
+
+    for (address, data) in chunks {
+        loader.add_data(*address, data)?;
+    }
+
+    loader.commit(
+        session,
+        options.progress.unwrap_or(&FlashProgress::new(|_| {})),
+        false,
+    )
+}
+
+/// Creates a [FlashLoader] for the memory map of `target` which is configured with `options`.
+fn flash_loader<'mmap, 'data>(
+    target: &'mmap Target,
+    options: &DownloadOptions<'_>,
+) -> Result<FlashLoader<'mmap, 'data>, FlashError> {
+    let mut loader = FlashLoader::new(
+        &target.memory_map,
+        options.fill_policy,
+        options.skip_unchanged_sectors,
+        options.verify,
+        !options.disable_double_buffering,
+    );
+    loader.preserve(&options.preserved_ranges, options.preserve_priority);
+    loader.retry(options.retries);
+    loader.permissions(&options.permissions);
+    if options.keep_core_state {
+        loader.keep_core_state();
+    }
+    if let Some(cancellation) = &options.cancellation {
+        loader.cancellation(cancellation);
+    }
+
+    if options.flash_algo_debug || options.flash_algo_break.is_some() {
+        loader.debug_algorithm(AlgorithmDebug {
+            break_at: options.flash_algo_break.clone(),
+        });
+    }
+
+    for algorithm in &options.flash_algorithms {
+        loader.add_algorithm(algorithm.clone(), target)?;
+    }
+
+    Ok(loader)
+}
+
+/// Lays out the file of given `format` at `path` for the flash of `target`, without accessing a probe.
+///
+/// The returned [FlashImage] contains exactly the data which [download_file_with_options]
+/// would program with the same `options`, after filling pages and merging all sections of the file.
+/// Bytes which would be read back from the flash are reported as restored ranges, but contain
+/// the erased value in the image.
+pub fn layout_file(
+    target: &Target,
+    path: &Path,
+    format: Format,
+    options: &DownloadOptions<'_>,
+) -> Result<FlashImage, FileDownloadError> {
+    layout_files(
+        target,
+        vec![Image {
+            path: path.to_owned(),
+            format,
+        }],
+        options,
+    )
+}
+
+/// Lays out several files for the flash of `target`, like [download_files_with_options] would program them,
+/// without accessing a probe.
+pub fn layout_files(
+    target: &Target,
+    images: Vec<Image>,
+    options: &DownloadOptions<'_>,
+) -> Result<FlashImage, FileDownloadError> {
+    let mut buffers = ImageBuffers::open(&images)?;
+    let mut loader = flash_loader(target, options)?;
+
+    buffers.add_to(&mut loader, images, target)?;
+
+    loader.to_image(target).map_err(FileDownloadError::Flash)
+}
+
+/// An opened image file, together with the buffer for its data and the buffers for its sections.
+type ImageBuffer = (File, Vec<u8>, Vec<(u32, Vec<u8>)>);
+
+/// The opened files of several images, and the buffers which hold their data while it is flashed.
+struct ImageBuffers {
+    paths: Vec<PathBuf>,
+    files: Vec<ImageBuffer>,
+}
+
+impl ImageBuffers {
+    /// Opens the files of all `images`, such that a missing file is reported before any data is read.
+    fn open(images: &[Image]) -> Result<Self, FileDownloadError> {
+        let files = images
+            .iter()
+            .map(|image| Ok((File::open(&image.path)?, vec![], vec![])))
+            .collect::<Result<_, std::io::Error>>()?;
+
+        Ok(Self {
+            paths: images.iter().map(|image| image.path.clone()).collect(),
+            files,
+        })
+    }
+
+    /// Reads the files and adds their data to the `loader`.
+    fn add_to<'buffer>(
+        &'buffer mut self,
+        loader: &mut FlashLoader<'_, 'buffer>,
+        images: Vec<Image>,
+        target: &Target,
+    ) -> Result<(), FileDownloadError> {
+        let uf2_families = uf2::family_ids_for_chip(&target.name);
+        let paths = &self.paths;
+
+        for (index, ((file, buffer, buffer_vec), image)) in
+            self.files.iter_mut().zip(images).enumerate()
+        {
+            loader.begin_image(index);
+
+            add_file_data(
+                buffer,
+                buffer_vec,
+                file,
+                loader,
+                image.format,
+                &uf2_families,
+            )
+            .map_err(|error| match error {
+                FileDownloadError::Flash(FlashError::ImageOverlap {
+                    first,
+                    second,
+                    start,
+                    end,
+                }) => FileDownloadError::ImageOverlap {
+                    first: paths[first].display().to_string(),
+                    second: paths[second].display().to_string(),
+                    start,
+                    end,
+                },
+                error => error,
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads the file of given `format` and adds its data to the `loader`.
+fn add_file_data<'buffer>(
+    buffer: &'buffer mut Vec<u8>,
+    buffer_vec: &'buffer mut Vec<(u32, Vec<u8>)>,
+    file: &'buffer mut File,
+    loader: &mut FlashLoader<'_, 'buffer>,
+    format: Format,
+    uf2_families: &[u32],
+) -> Result<(), FileDownloadError> {
+    match format {
+        Format::Bin(options) => download_bin(buffer, file, loader, options),
+        Format::Elf => download_elf(buffer, file, loader),
+        Format::Hex => download_hex(buffer_vec, file, loader),
+        Format::Uf2(options) => download_uf2(buffer_vec, file, loader, uf2_families, options),
+    }
+}
+
 /// Starts the download of a binary file.
 fn download_bin<'buffer, T: Read + Seek>(
     buffer: &'buffer mut Vec<u8>,
@@ -193,6 +543,32 @@ fn download_hex<'buffer, T: Read + Seek>(
     Ok(())
 }
 
+/// Starts the download of a UF2 file.
+fn download_uf2<'buffer, T: Read + Seek>(
+    buffer: &'buffer mut Vec<(u32, Vec<u8>)>,
+    file: &mut T,
+    loader: &mut FlashLoader<'_, 'buffer>,
+    expected_families: &[u32],
+    options: Uf2Options,
+) -> Result<(), FileDownloadError> {
+    let mut data = vec![];
+    file.read_to_end(&mut data)?;
+
+    if expected_families.is_empty() {
+        log::warn!("No UF2 family ID is known for the selected target, skipping the family check.");
+    }
+
+    for chunk in uf2::parse(&data, expected_families, options.allow_family_mismatch)? {
+        buffer.push((chunk.address, chunk.data));
+    }
+
+    for (address, data) in buffer {
+        loader.add_data(*address, data.as_slice())?;
+    }
+
+    Ok(())
+}
+
 /// Starts the download of a elf file.
 fn download_elf<'buffer, T: Read + Seek>(
     buffer: &'buffer mut Vec<u8>,
@@ -0,0 +1,332 @@
+use super::FlashError;
+use crate::config::{FlashProperties, RawFlashAlgorithm, SectorDescription};
+use object::read::elf::FileHeader;
+use object::{elf, Bytes, Endianness, Object, ObjectSection, ObjectSymbol};
+use std::{borrow::Cow, convert::TryInto, ops::Range};
+
+/// The section which contains the code of a flash algorithm.
+const CODE_SECTION: &str = "PrgCode";
+/// The section which contains the data of a flash algorithm, including its zero initialized data.
+const DATA_SECTION: &str = "PrgData";
+/// The symbol of the `FlashDevice` structure, which describes the flash of a CMSIS flash algorithm.
+const FLASH_DEVICE: &str = "FlashDevice";
+
+/// The offset of the first sector in the `FlashDevice` structure.
+const FLASH_DEVICE_SECTORS: usize = 160;
+/// Marks the end of the sector list of the `FlashDevice` structure.
+const SECTOR_END: u32 = 0xFFFF_FFFF;
+
+/// The properties of a flash algorithm which is loaded from an ELF file with [RawFlashAlgorithm::from_elf].
+///
+/// All properties are optional if the file contains the `FlashDevice` description
+/// of a CMSIS flash algorithm (FLM).
+#[derive(Debug, Default, Clone)]
+pub struct FlashAlgorithmProperties {
+    /// The name of the algorithm.
+    ///
+    /// Defaults to the device name of the `FlashDevice` description.
+    pub name: Option<String>,
+    /// The address range which is programmed with the algorithm.
+    ///
+    /// This replaces the address range of the flash properties, e.g. to
+    /// use an algorithm for an external flash which is mapped to a different address.
+    pub address_range: Option<Range<u32>>,
+    /// The properties of the flash.
+    ///
+    /// Defaults to the properties of the `FlashDevice` description.
+    pub flash_properties: Option<FlashProperties>,
+    /// The index of the core which runs the algorithm.
+    pub core_index: usize,
+}
+
+impl RawFlashAlgorithm {
+    /// Loads a flash algorithm from an ELF file, like the FLM files of CMSIS-Packs.
+    ///
+    /// The code and the data of the algorithm are read from the `PrgCode` and `PrgData` sections,
+    /// its entry points from the `Init`, `UnInit`, `EraseChip`, `EraseSector`, `ProgramPage`,
+    /// `Verify` and `BlankCheck` symbols. `EraseSector` and `ProgramPage` are required.
+    pub fn from_elf(
+        data: &[u8],
+        properties: &FlashAlgorithmProperties,
+    ) -> Result<RawFlashAlgorithm, FlashError> {
+        let file = object::File::parse(data)?;
+
+        if !is_supported_machine(data) {
+            return Err(FlashError::InvalidAlgorithmElf(
+                "only 32 bit ARM and RISC-V algorithms are supported",
+            ));
+        }
+
+        let code = file
+            .section_by_name(CODE_SECTION)
+            .ok_or(FlashError::InvalidAlgorithmElf(
+                "the PrgCode section is missing",
+            ))?;
+        let code_start = code.address();
+
+        let mut instructions = code.data()?.to_vec();
+
+        // The data follows the code, gaps and the zero initialized data are filled with zeros.
+        let data_section_offset = match file.section_by_name(DATA_SECTION) {
+            Some(section) => {
+                let offset = section
+                    .address()
+                    .checked_sub(code_start + code.size())
+                    .map(|gap| code.size() + gap)
+                    .ok_or(FlashError::InvalidAlgorithmElf(
+                        "the PrgData section overlaps with the PrgCode section",
+                    ))?;
+
+                instructions.resize(offset as usize, 0);
+                instructions.extend_from_slice(section.data()?);
+                instructions.resize((offset + section.size()) as usize, 0);
+
+                offset
+            }
+            None => instructions.len() as u64,
+        };
+
+        // The instructions are loaded as 32 bit words.
+        instructions.resize((instructions.len() + 3) / 4 * 4, 0);
+
+        let mut entry_points = std::collections::HashMap::new();
+        let mut flash_device = None;
+
+        for symbol in file.symbols() {
+            let name = match symbol.name() {
+                Ok(name) => name,
+                Err(_) => continue,
+            };
+
+            match name {
+                "Init" | "UnInit" | "EraseChip" | "EraseSector" | "ProgramPage" | "Verify"
+                | "BlankCheck" => {
+                    let offset = symbol.address().checked_sub(code_start).ok_or(
+                        FlashError::InvalidAlgorithmElf(
+                            "an entry point lies outside of the PrgCode section",
+                        ),
+                    )?;
+                    entry_points.insert(name, offset as u32);
+                }
+                FLASH_DEVICE => {
+                    let section = symbol
+                        .section_index()
+                        .and_then(|index| file.section_by_index(index).ok());
+
+                    if let Some(section) = section {
+                        let offset = (symbol.address() - section.address()) as usize;
+                        flash_device = section.data()?.get(offset..);
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        let (device_name, mut flash_properties) = match (&properties.flash_properties, flash_device)
+        {
+            (Some(flash_properties), _) => (None, flash_properties.clone()),
+            (None, Some(flash_device)) => {
+                let (name, flash_properties) = parse_flash_device(flash_device)?;
+                (Some(name), flash_properties)
+            }
+            (None, None) => {
+                return Err(FlashError::InvalidAlgorithmElf(
+                    "the FlashDevice description and the flash properties are missing",
+                ))
+            }
+        };
+
+        if let Some(address_range) = &properties.address_range {
+            flash_properties.address_range = address_range.clone();
+        }
+
+        let name = properties
+            .name
+            .clone()
+            .or(device_name)
+            .ok_or(FlashError::InvalidAlgorithmElf("the algorithm has no name"))?;
+
+        Ok(RawFlashAlgorithm {
+            name: name.into(),
+            description: Cow::Borrowed(""),
+            default: false,
+            instructions: instructions.into(),
+            pc_init: entry_points.get("Init").copied(),
+            pc_uninit: entry_points.get("UnInit").copied(),
+            pc_program_page: *entry_points.get("ProgramPage").ok_or(
+                FlashError::InvalidAlgorithmElf("the ProgramPage entry point is missing"),
+            )?,
+            pc_erase_sector: *entry_points.get("EraseSector").ok_or(
+                FlashError::InvalidAlgorithmElf("the EraseSector entry point is missing"),
+            )?,
+            pc_erase_all: entry_points.get("EraseChip").copied(),
+            pc_verify: entry_points.get("Verify").copied(),
+            pc_blank_check: entry_points.get("BlankCheck").copied(),
+            data_section_offset: data_section_offset as u32,
+            core_index: properties.core_index,
+            pre_flash_sequence: Cow::Borrowed(&[]),
+            post_flash_sequence: Cow::Borrowed(&[]),
+            flash_properties,
+        })
+    }
+}
+
+/// Reads the device name and the flash properties from the `FlashDevice` structure
+/// of a CMSIS flash algorithm.
+fn parse_flash_device(data: &[u8]) -> Result<(String, FlashProperties), FlashError> {
+    let invalid = || FlashError::InvalidAlgorithmElf("the FlashDevice description is truncated");
+
+    let word = |offset: usize| {
+        data.get(offset..offset + 4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+            .ok_or_else(invalid)
+    };
+
+    let name = data.get(2..130).ok_or_else(invalid)?;
+    let name_length = name.iter().position(|c| *c == 0).unwrap_or(name.len());
+    let name = String::from_utf8_lossy(&name[..name_length]).into_owned();
+
+    let start = word(132)?;
+    let size = word(136)?;
+
+    let mut sectors = vec![];
+    let mut offset = FLASH_DEVICE_SECTORS;
+    loop {
+        let sector_size = word(offset)?;
+        let sector_address = word(offset + 4)?;
+
+        if sector_size == SECTOR_END && sector_address == SECTOR_END {
+            break;
+        }
+
+        sectors.push(SectorDescription {
+            size: sector_size,
+            address: sector_address,
+        });
+        offset += 8;
+    }
+
+    let flash_properties = FlashProperties {
+        address_range: start..start + size,
+        page_size: word(140)?,
+        erased_byte_value: *data.get(148).ok_or_else(invalid)?,
+        program_page_timeout: word(152)?,
+        erase_sector_timeout: word(156)?,
+        sectors: sectors.into(),
+    };
+
+    Ok((name, flash_properties))
+}
+
+/// Returns true if `data` is a 32 bit ELF file for ARM or RISC-V.
+///
+/// RISC-V files are not recognized by [Object::architecture], so the ELF header is checked.
+fn is_supported_machine(data: &[u8]) -> bool {
+    let header = match elf::FileHeader32::<Endianness>::parse(Bytes(data)) {
+        Ok(header) => header,
+        // 64 bit files are rejected here, because their class is not 32 bit.
+        Err(_) => return false,
+    };
+
+    match header.endian() {
+        Ok(endian) => matches!(header.e_machine(endian), elf::EM_ARM | elf::EM_RISCV),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flash_device(name: &str, sectors: &[(u32, u32)]) -> Vec<u8> {
+        let mut data = vec![0; FLASH_DEVICE_SECTORS];
+
+        data[0..2].copy_from_slice(&0x0101_u16.to_le_bytes());
+        data[2..2 + name.len()].copy_from_slice(name.as_bytes());
+        data[130..132].copy_from_slice(&5_u16.to_le_bytes());
+        data[132..136].copy_from_slice(&0x9000_0000_u32.to_le_bytes());
+        data[136..140].copy_from_slice(&0x0100_0000_u32.to_le_bytes());
+        data[140..144].copy_from_slice(&0x100_u32.to_le_bytes());
+        data[148] = 0xFF;
+        data[152..156].copy_from_slice(&100_u32.to_le_bytes());
+        data[156..160].copy_from_slice(&3000_u32.to_le_bytes());
+
+        for (size, address) in sectors {
+            data.extend_from_slice(&size.to_le_bytes());
+            data.extend_from_slice(&address.to_le_bytes());
+        }
+
+        data
+    }
+
+    fn elf_header(class: u8, machine: u16) -> Vec<u8> {
+        let mut header = vec![0; 64];
+
+        header[0..4].copy_from_slice(&elf::ELFMAG);
+        header[4] = class;
+        header[5] = elf::ELFDATA2LSB;
+        header[6] = elf::EV_CURRENT;
+        header[18..20].copy_from_slice(&machine.to_le_bytes());
+
+        header
+    }
+
+    #[test]
+    fn only_32_bit_arm_and_riscv_files_are_supported() {
+        assert!(is_supported_machine(&elf_header(
+            elf::ELFCLASS32,
+            elf::EM_ARM
+        )));
+        assert!(is_supported_machine(&elf_header(
+            elf::ELFCLASS32,
+            elf::EM_RISCV
+        )));
+        assert!(!is_supported_machine(&elf_header(
+            elf::ELFCLASS64,
+            elf::EM_RISCV
+        )));
+        assert!(!is_supported_machine(&elf_header(
+            elf::ELFCLASS32,
+            elf::EM_386
+        )));
+        assert!(!is_supported_machine(b"not an ELF file"));
+    }
+
+    #[test]
+    fn flash_device_description() {
+        let mut data = flash_device("QSPI Flash", &[(0x1000, 0x0), (0x1_0000, 0x10_0000)]);
+        data.extend_from_slice(&[0xFF; 8]);
+
+        let (name, properties) = parse_flash_device(&data).unwrap();
+
+        assert_eq!(name, "QSPI Flash");
+        assert_eq!(properties.address_range, 0x9000_0000..0x9100_0000);
+        assert_eq!(properties.page_size, 0x100);
+        assert_eq!(properties.erased_byte_value, 0xFF);
+        assert_eq!(properties.program_page_timeout, 100);
+        assert_eq!(properties.erase_sector_timeout, 3000);
+        assert_eq!(
+            properties.sectors,
+            Cow::Borrowed(&[
+                SectorDescription {
+                    size: 0x1000,
+                    address: 0x0,
+                },
+                SectorDescription {
+                    size: 0x1_0000,
+                    address: 0x10_0000,
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn flash_device_without_end_marker() {
+        let data = flash_device("QSPI Flash", &[(0x1000, 0x0)]);
+
+        assert!(matches!(
+            parse_flash_device(&data),
+            Err(FlashError::InvalidAlgorithmElf(_))
+        ));
+    }
+}
@@ -0,0 +1,363 @@
+use super::{FlashError, FlashLoader, FlashProgress, Flasher};
+use crate::architecture::arm::{
+    ap::MemoryAP, communication_interface::ArmProbeInterface, kinetis, nordic,
+};
+use crate::config::{EraseMode, MemoryRegion, UnlockSequence};
+use crate::{Error, Permissions, Probe, Session};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// The time after which an unlock sequence which did not complete is aborted.
+const UNLOCK_TIMEOUT: Duration = Duration::from_secs(30);
+
+const STM32F4_FLASH_OPTKEYR: u32 = 0x4002_3C08;
+const STM32F4_FLASH_SR: u32 = 0x4002_3C0C;
+const STM32F4_FLASH_OPTCR: u32 = 0x4002_3C14;
+const STM32F4_OPTKEY1: u32 = 0x0819_2A3B;
+const STM32F4_OPTKEY2: u32 = 0x4C5D_6E7F;
+const STM32F4_SR_BSY: u32 = 1 << 16;
+const STM32F4_OPTCR_OPTLOCK: u32 = 1 << 0;
+const STM32F4_OPTCR_OPTSTRT: u32 = 1 << 1;
+const STM32F4_RDP_LEVEL_0: u32 = 0xAA;
+
+/// An error which occured while unlocking a chip.
+#[derive(Debug, Error)]
+pub enum UnlockError {
+    /// The unlock sequence did not complete in time.
+    #[error("The unlock sequence did not complete within {0:?}. The chip might still be locked.")]
+    Timeout(Duration),
+    /// The chip does not allow a mass erase from the debug port.
+    #[error("Mass erase is disabled on this chip.")]
+    MassEraseDisabled,
+    /// The access port used by the unlock sequence has an unexpected IDR.
+    #[error("Access port {port} has the IDR {idr:#010x}, but {expected:#010x} was expected. Is the unlock sequence correct for this chip?")]
+    UnexpectedAccessPort {
+        /// The index of the access port.
+        port: u8,
+        /// The IDR which was read from the access port.
+        idr: u32,
+        /// The IDR which was expected.
+        expected: u32,
+    },
+}
+
+/// Erases the complete flash of the target.
+///
+/// The `EraseChip` routine of the flash algorithms is used where it is available,
+/// otherwise all sectors of the flash are erased one by one.
+///
+/// Regions which can not be erased, like one-time programmable ones, are skipped.
+///
+/// This does not work on read protected chips, these have to be unlocked with [unlock] first.
+pub fn erase_all(
+    session: &mut Session,
+    progress: Option<&FlashProgress>,
+) -> Result<(), FlashError> {
+    let default_progress = FlashProgress::new(|_| {});
+    let progress = progress.unwrap_or(&default_progress);
+
+    let target = session.target().clone();
+
+    // A chip erase covers all the regions of an algorithm, so it has to run only once.
+    let mut chip_erased = vec![];
+
+    for region in &target.memory_map {
+        let region = match region {
+            MemoryRegion::Nvm(region) => region,
+            _ => continue,
+        };
+
+        if region.erase_mode == EraseMode::None {
+            log::debug!(
+                "Region {:#010x}..{:#010x} can not be erased.",
+                region.range.start,
+                region.range.end
+            );
+            continue;
+        }
+
+        let flash_algorithm = match FlashLoader::flash_algorithm_for_region(&target, &[], region) {
+            Ok(flash_algorithm) => flash_algorithm,
+            Err(FlashError::NoFlashLoaderAlgorithmAttached) => {
+                log::warn!(
+                    "No flash algorithm found for region {:#010x}..{:#010x}, it is not erased.",
+                    region.range.start,
+                    region.range.end
+                );
+                continue;
+            }
+            Err(error) => return Err(error),
+        };
+
+        if region.erase_mode == EraseMode::ChipOnly && flash_algorithm.pc_erase_all.is_none() {
+            log::warn!(
+                "Region {:#010x}..{:#010x} can only be erased with a chip erase, which its flash algorithm does not support.",
+                region.range.start,
+                region.range.end
+            );
+            continue;
+        }
+
+        if flash_algorithm.pc_erase_all.is_some() {
+            if chip_erased.contains(&flash_algorithm.name) {
+                continue;
+            }
+            chip_erased.push(flash_algorithm.name.clone());
+        }
+
+        log::info!(
+            "Erasing region {:#010x}..{:#010x}",
+            region.range.start,
+            region.range.end
+        );
+
+        let mut flasher = Flasher::new(session, flash_algorithm, region.clone());
+        flasher
+            .erase_region(progress)
+            .map_err(FlashError::from_anyhow)?;
+    }
+
+    Ok(())
+}
+
+/// Unlocks a read protected chip with the given vendor specific sequence.
+///
+/// This erases the complete chip, including its configuration and protection settings,
+/// and thus requires the `erase_all` permission. The chip might have to be power cycled
+/// before the changes take effect.
+///
+/// The probe is returned unchanged, so it can be used to attach to the chip afterwards.
+pub fn unlock(
+    mut probe: Probe,
+    sequence: UnlockSequence,
+    permissions: &Permissions,
+) -> Result<Probe, Error> {
+    permissions.erase_all()?;
+
+    probe.attach_to_unspecified()?;
+
+    log::info!("Unlocking the chip with {:?}", sequence);
+
+    let unlock_arm: fn(&mut dyn ArmProbeInterface) -> Result<(), Error> = match sequence {
+        UnlockSequence::NrfCtrlApEraseAll => nrf_ctrl_ap_erase_all,
+        UnlockSequence::Nrf53CtrlApEraseAll => nrf53_ctrl_ap_erase_all,
+        UnlockSequence::Nrf91CtrlApEraseAll => nrf91_ctrl_ap_erase_all,
+        UnlockSequence::KinetisMdmApMassErase => kinetis_mdm_ap_mass_erase,
+        UnlockSequence::Stm32f4RdpRegression => stm32f4_rdp_regression,
+        UnlockSequence::WchLinkUnprotect => {
+            // The probe removes the protection on its own.
+            probe.disable_flash_protection()?;
+            return Ok(probe);
+        }
+    };
+
+    let mut interface = probe
+        .into_arm_interface()?
+        .ok_or(Error::ArchitectureRequired(&["ARMv7", "ARMv8"]))?;
+
+    let result = unlock_arm(&mut *interface);
+
+    let probe = interface.close();
+
+    result.map(|()| probe)
+}
+
+/// Polls `condition` until it is true, or until the unlock timeout expires.
+fn wait_for(mut condition: impl FnMut() -> Result<bool, Error>) -> Result<(), Error> {
+    let start = Instant::now();
+
+    while !condition()? {
+        if start.elapsed() > UNLOCK_TIMEOUT {
+            return Err(Error::architecture_specific(UnlockError::Timeout(
+                UNLOCK_TIMEOUT,
+            )));
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    Ok(())
+}
+
+/// Makes sure that the access port `port` is the vendor specific AP the sequence expects.
+fn check_access_port(
+    interface: &mut dyn ArmProbeInterface,
+    port: u8,
+    expected: u32,
+) -> Result<(), Error> {
+    let idr = interface.read_raw_ap_register(port, 0xFC)?;
+
+    if idr != expected {
+        return Err(Error::architecture_specific(
+            UnlockError::UnexpectedAccessPort {
+                port,
+                idr,
+                expected,
+            },
+        ));
+    }
+
+    Ok(())
+}
+
+fn nrf_ctrl_ap_erase_all(interface: &mut dyn ArmProbeInterface) -> Result<(), Error> {
+    nrf_ctrl_ap_erase(interface, nordic::CTRL_AP, nordic::CTRL_AP_IDR)
+}
+
+fn nrf53_ctrl_ap_erase_all(interface: &mut dyn ArmProbeInterface) -> Result<(), Error> {
+    // Like nrfjprog, the network core is erased before the application core.
+    nrf_ctrl_ap_erase(
+        interface,
+        nordic::NRF53_NETWORK_CTRL_AP,
+        nordic::CTRL_AP_V2_IDR,
+    )?;
+    nrf_ctrl_ap_erase(
+        interface,
+        nordic::NRF53_APPLICATION_CTRL_AP,
+        nordic::CTRL_AP_V2_IDR,
+    )
+}
+
+fn nrf91_ctrl_ap_erase_all(interface: &mut dyn ArmProbeInterface) -> Result<(), Error> {
+    nrf_ctrl_ap_erase(interface, nordic::NRF91_CTRL_AP, nordic::CTRL_AP_V2_IDR)
+}
+
+/// Erases the flash and the UICR behind the Nordic CTRL-AP `port`, and resets its domain.
+fn nrf_ctrl_ap_erase(
+    interface: &mut dyn ArmProbeInterface,
+    port: u8,
+    idr: u32,
+) -> Result<(), Error> {
+    check_access_port(interface, port, idr)?;
+
+    interface.write_raw_ap_register(port, nordic::ERASEALL, 1)?;
+
+    let result =
+        wait_for(|| Ok(interface.read_raw_ap_register(port, nordic::ERASEALLSTATUS)? == 0));
+
+    // Reset the chip and clear the erase request, also if the erase timed out.
+    interface.write_raw_ap_register(port, nordic::RESET, 1)?;
+    interface.write_raw_ap_register(port, nordic::RESET, 0)?;
+    interface.write_raw_ap_register(port, nordic::ERASEALL, 0)?;
+
+    result
+}
+
+fn kinetis_mdm_ap_mass_erase(interface: &mut dyn ArmProbeInterface) -> Result<(), Error> {
+    check_access_port(interface, kinetis::MDM_AP, kinetis::MDM_AP_IDR)?;
+
+    // Hold the chip in reset, so the firmware can not interfere with the erase.
+    interface.write_raw_ap_register(
+        kinetis::MDM_AP,
+        kinetis::CONTROL,
+        kinetis::CONTROL_SYSTEM_RESET,
+    )?;
+
+    // The flash controller only accepts the erase once the reset took effect.
+    wait_for(|| {
+        let status = interface.read_raw_ap_register(kinetis::MDM_AP, kinetis::STATUS)?;
+        Ok(status & kinetis::STATUS_SYSTEM_RESET == 0 && status & kinetis::STATUS_FLASH_READY != 0)
+    })?;
+
+    let status = interface.read_raw_ap_register(kinetis::MDM_AP, kinetis::STATUS)?;
+    if status & kinetis::STATUS_MASS_ERASE_ENABLE == 0 {
+        return Err(Error::architecture_specific(UnlockError::MassEraseDisabled));
+    }
+
+    interface.write_raw_ap_register(
+        kinetis::MDM_AP,
+        kinetis::CONTROL,
+        kinetis::CONTROL_SYSTEM_RESET | kinetis::CONTROL_MASS_ERASE,
+    )?;
+
+    wait_for(|| {
+        let control = interface.read_raw_ap_register(kinetis::MDM_AP, kinetis::CONTROL)?;
+        Ok(control & kinetis::CONTROL_MASS_ERASE == 0)
+    })?;
+
+    interface.write_raw_ap_register(kinetis::MDM_AP, kinetis::CONTROL, 0)?;
+
+    // Wait until the chip left the reset, before it is attached to again.
+    wait_for(|| {
+        let status = interface.read_raw_ap_register(kinetis::MDM_AP, kinetis::STATUS)?;
+        Ok(status & kinetis::STATUS_SYSTEM_RESET != 0)
+    })
+}
+
+fn stm32f4_rdp_regression(interface: &mut dyn ArmProbeInterface) -> Result<(), Error> {
+    let mut memory = interface.memory_interface(MemoryAP::new(0))?;
+
+    let optcr = memory.read_word_32(STM32F4_FLASH_OPTCR)?;
+    if (optcr >> 8) & 0xFF == STM32F4_RDP_LEVEL_0 {
+        log::info!("The read protection is already disabled.");
+        return Ok(());
+    }
+
+    if optcr & STM32F4_OPTCR_OPTLOCK != 0 {
+        memory.write_word_32(STM32F4_FLASH_OPTKEYR, STM32F4_OPTKEY1)?;
+        memory.write_word_32(STM32F4_FLASH_OPTKEYR, STM32F4_OPTKEY2)?;
+    }
+
+    // Regressing to level 0 triggers a mass erase of the flash.
+    let optcr = (optcr & !0xFF00) | (STM32F4_RDP_LEVEL_0 << 8);
+    memory.write_word_32(STM32F4_FLASH_OPTCR, optcr)?;
+    memory.write_word_32(STM32F4_FLASH_OPTCR, optcr | STM32F4_OPTCR_OPTSTRT)?;
+
+    wait_for(|| Ok(memory.read_word_32(STM32F4_FLASH_SR)? & STM32F4_SR_BSY == 0))?;
+
+    log::info!("The read protection is disabled, power cycle the chip to apply the change.");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::architecture::arm::{ArmCommunicationInterface, PortType};
+
+    #[test]
+    fn secured_kinetis_chip_is_recovered() {
+        let probe = kinetis::fake_chip(true, true);
+        let writes = probe.writes.clone();
+        let mut interface = ArmCommunicationInterface::new(Box::new(probe), false).unwrap();
+
+        kinetis_mdm_ap_mass_erase(&mut interface).unwrap();
+
+        assert!(!kinetis::is_secured(&mut interface).unwrap());
+
+        // The chip is held in reset during the erase, and released afterwards.
+        let control_writes: Vec<_> = writes
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|&&(port, address, _)| {
+                port == PortType::AccessPort(u16::from(kinetis::MDM_AP))
+                    && address == u16::from(kinetis::CONTROL)
+            })
+            .map(|&(_, _, value)| value)
+            .collect();
+        assert_eq!(
+            control_writes,
+            vec![
+                kinetis::CONTROL_SYSTEM_RESET,
+                kinetis::CONTROL_SYSTEM_RESET | kinetis::CONTROL_MASS_ERASE,
+                0
+            ]
+        );
+    }
+
+    #[test]
+    fn disabled_mass_erase_is_reported() {
+        let probe = kinetis::fake_chip(true, false);
+        let mut interface = ArmCommunicationInterface::new(Box::new(probe), false).unwrap();
+
+        match kinetis_mdm_ap_mass_erase(&mut interface) {
+            Err(Error::ArchitectureSpecific(error)) => assert!(matches!(
+                error.downcast_ref::<UnlockError>(),
+                Some(UnlockError::MassEraseDisabled)
+            )),
+            result => panic!("Unexpected result {:?}", result),
+        }
+
+        assert!(kinetis::is_secured(&mut interface).unwrap());
+    }
+}
@@ -1,9 +1,12 @@
 #![allow(missing_docs)]
 
+use std::fmt;
+use std::ops::Range;
 use thiserror::Error;
 
 use crate::config::NvmRegion;
 use crate::error;
+use crate::DebugProbeError;
 
 /// Describes any error that happened during the or in preparation for the flashing procedure.
 #[derive(Error, Debug)]
@@ -34,12 +37,279 @@ pub enum FlashError {
     PageWrite { page_address: u32, error_code: u32 },
     #[error("Overlap in data, address {0:#010x} was already written earlier.")]
     DataOverlap(u32),
+    #[error("The images {first} and {second} both contain data for {start:#010x}..{end:#010x}.")]
+    ImageOverlap {
+        first: usize,
+        second: usize,
+        start: u32,
+        end: u32,
+    },
     #[error("Address {0:#010x} is not a valid address in the flash area.")]
     InvalidFlashAddress(u32),
     #[error("No NVM memory contains the entire requested memory range {start:#08X}..{end:#08X}.")]
     NoSuitableNvm { start: u32, end: u32 },
+    #[error("The preserved range {start:#010x}..{end:#010x} overlaps with data to be programmed.")]
+    PreservedRangeOverlap { start: u32, end: u32 },
+    #[error(
+        "The bytes {start:#010x}..{end:#010x} would be erased without being written by the image."
+    )]
+    UnwrittenBytes { start: u32, end: u32 },
+    #[error("Verification failed, the flash contents at address {address:#010x} do not match the expected data.")]
+    Verify { address: u32 },
+    #[error("Reading the memory at address {0:#010x} a second time returned different contents.")]
+    ReadVerifyFailed(u32),
+    #[error("The range {start:#010x}..{end:#010x} can not be read, the memory region {region} is {reason}.")]
+    NotReadable {
+        start: u32,
+        end: u32,
+        region: String,
+        reason: &'static str,
+    },
+    #[error("The core was halted at the entry {address:#010x} of the '{name}' routine to debug the flash algorithm.")]
+    HaltedAtRoutineEntry { name: &'static str, address: u32 },
+    #[error("The region {start:#010x}..{end:#010x} is one-time programmable, writing to it requires the 'otp_write' permission.")]
+    OtpWriteNotAllowed { start: u32, end: u32 },
+    #[error("Writing {requested:#04x} to address {address:#010x} would require clearing the already programmed bits {conflicting:#04x} of the current value {current:#04x}, which is only possible by erasing.")]
+    ProgrammedBitsConflict {
+        address: u32,
+        current: u8,
+        requested: u8,
+        conflicting: u8,
+    },
     #[error("Trying to write flash, but no suitable flash loader algorithm is linked to the given target information.")]
     NoFlashLoaderAlgorithmAttached,
+    #[error("The sectors of the region {start:#010x}..{end:#010x} are invalid, {reason}.")]
+    InvalidSectors {
+        start: u32,
+        end: u32,
+        reason: &'static str,
+    },
+    #[error("The flash algorithm ELF file is invalid, {0}.")]
+    InvalidAlgorithmElf(&'static str),
+    #[error("Could not read the flash algorithm ELF file")]
+    AlgorithmElf(#[from] object::read::Error),
+    #[error("The flash algorithm '{name}' requires {required} bytes of RAM, but the RAM of the target only has {available} bytes.")]
+    AlgorithmDoesNotFitInRam {
+        name: String,
+        required: u32,
+        available: u32,
+    },
+    #[error("Flashing was cancelled.")]
+    Cancelled,
+    #[error("{failure}")]
+    OperationFailed {
+        failure: FlashFailure,
+        #[source]
+        source: Box<FlashError>,
+    },
     #[error(transparent)]
     Other(#[from] anyhow::Error),
 }
+
+impl FlashError {
+    /// Converts an error of the flasher, keeping the [FlashError] it contains if there is one.
+    pub(super) fn from_anyhow(error: anyhow::Error) -> Self {
+        match error.downcast::<FlashError>() {
+            Ok(error) => error,
+            Err(error) => FlashError::Other(error),
+        }
+    }
+
+    /// Returns the raw return code of the flash algorithm routine which reported this error.
+    fn return_code(&self) -> Option<u32> {
+        match self {
+            FlashError::EraseFailed { errorcode, .. }
+            | FlashError::RoutineCallFailed { errorcode, .. } => Some(*errorcode),
+            FlashError::PageWrite { error_code, .. } => Some(*error_code),
+            FlashError::OperationFailed { source, .. } => source.return_code(),
+            FlashError::Other(error) => error
+                .downcast_ref::<FlashError>()
+                .and_then(FlashError::return_code),
+            _ => None,
+        }
+    }
+
+    /// Returns `true` if the probe was reconnected while the error occured.
+    fn probe_reconnected(&self) -> bool {
+        fn is_reconnected(error: &(dyn std::error::Error + 'static)) -> bool {
+            matches!(
+                error.downcast_ref::<DebugProbeError>(),
+                Some(DebugProbeError::ProbeReconnected)
+            )
+        }
+
+        match self {
+            FlashError::OperationFailed { source, .. } => source.probe_reconnected(),
+            FlashError::Other(error) => error.chain().any(is_reconnected),
+            error => {
+                let mut source = std::error::Error::source(error);
+                while let Some(error) = source {
+                    if is_reconnected(error) {
+                        return true;
+                    }
+                    source = error.source();
+                }
+                false
+            }
+        }
+    }
+}
+
+/// The flash operations which can fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlashOperation {
+    /// Reading back the flash contents which are written again after erasing.
+    Fill,
+    /// Erasing a sector or the complete chip.
+    Erase,
+    /// Programming a page.
+    Program,
+    /// Verifying the programmed data.
+    Verify,
+}
+
+impl fmt::Display for FlashOperation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            FlashOperation::Fill => "Filling",
+            FlashOperation::Erase => "Erasing",
+            FlashOperation::Program => "Programming",
+            FlashOperation::Verify => "Verifying",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Describes where a flash operation failed and how far flashing got before the failure.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlashFailure {
+    /// The operation which failed.
+    pub operation: FlashOperation,
+    /// The address of the page or sector which was processed when the failure occured.
+    pub address: u32,
+    /// The sector which contains `address`.
+    pub sector: Option<Range<u32>>,
+    /// The raw return code of the flash algorithm, if the failure was reported by the algorithm itself.
+    pub return_code: Option<u32>,
+    /// The amount of bytes which were programmed successfully before the failure.
+    pub bytes_programmed: u32,
+    /// If set, all sectors below this address are completely programmed,
+    /// and programming can be resumed from here.
+    pub resume_address: Option<u32>,
+    /// The connection to the probe was lost and restored during the operation,
+    /// so the state of the flash algorithm on the target is unknown.
+    pub probe_reconnected: bool,
+}
+
+impl FlashFailure {
+    pub(super) fn new(
+        operation: FlashOperation,
+        address: u32,
+        sector: Option<Range<u32>>,
+        bytes_programmed: u32,
+        source: &FlashError,
+    ) -> Self {
+        let resume_address = match operation {
+            FlashOperation::Program => Some(sector.as_ref().map_or(address, |s| s.start)),
+            _ => None,
+        };
+
+        Self {
+            operation,
+            address,
+            sector,
+            return_code: source.return_code(),
+            bytes_programmed,
+            resume_address,
+            probe_reconnected: source.probe_reconnected(),
+        }
+    }
+
+    /// Returns `true` if the failure was not reported by the flash algorithm,
+    /// e.g. because the communication with the probe failed, and might not happen again.
+    ///
+    /// Failures during which the probe was reconnected are not transient,
+    /// as it is unknown how far the flash algorithm got.
+    pub fn is_transient(&self) -> bool {
+        self.return_code.is_none() && !self.probe_reconnected
+    }
+}
+
+impl fmt::Display for FlashFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} failed at address {:#010x}",
+            self.operation, self.address
+        )?;
+
+        if let Some(sector) = &self.sector {
+            write!(f, " in sector {:#010x}..{:#010x}", sector.start, sector.end)?;
+        }
+
+        if let Some(return_code) = self.return_code {
+            write!(f, ", the flash algorithm returned {:#x}", return_code)?;
+        }
+
+        write!(
+            f,
+            ". {} bytes were programmed before the failure.",
+            self.bytes_programmed
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FlashError, FlashFailure, FlashOperation};
+    use crate::DebugProbeError;
+
+    #[test]
+    fn failure_contains_return_code_and_resume_address() {
+        let source = FlashError::Other(anyhow::anyhow!(FlashError::PageWrite {
+            page_address: 0x1400,
+            error_code: 3,
+        }));
+        let failure = FlashFailure::new(
+            FlashOperation::Program,
+            0x1400,
+            Some(0x1000..0x2000),
+            0x400,
+            &source,
+        );
+
+        assert_eq!(failure.return_code, Some(3));
+        assert_eq!(failure.resume_address, Some(0x1000));
+        assert!(!failure.is_transient());
+
+        // Erasing can not be resumed, as no sector has been programmed yet.
+        let failure = FlashFailure::new(
+            FlashOperation::Erase,
+            0x1000,
+            Some(0x1000..0x2000),
+            0,
+            &FlashError::InvalidFlashAlgorithmLength,
+        );
+
+        assert_eq!(failure.resume_address, None);
+        assert!(failure.is_transient());
+    }
+
+    #[test]
+    fn failure_after_reconnecting_is_not_transient() {
+        let source = FlashError::Core(DebugProbeError::ProbeReconnected.into());
+        let failure = FlashFailure::new(
+            FlashOperation::Program,
+            0x1400,
+            Some(0x1000..0x2000),
+            0x400,
+            &source,
+        );
+
+        assert!(failure.probe_reconnected);
+        assert!(!failure.is_transient());
+
+        let source = FlashError::Other(anyhow::anyhow!(DebugProbeError::ProbeReconnected));
+        assert!(source.probe_reconnected());
+    }
+}
@@ -1,6 +1,7 @@
-use super::FlashProgress;
-use super::{FlashBuilder, FlashError, FlashFill, FlashLayout, FlashPage};
-use crate::config::{FlashAlgorithm, MemoryRange, NvmRegion};
+use super::{FillPolicy, FlashFailure, FlashOperation, FlashProgress};
+use super::{FlashBuilder, FlashError, FlashFill, FlashLayout, FlashPage, FlashSector};
+use crate::asynchronous::Cancellation;
+use crate::config::{FlashAlgorithm, MemoryRange, NvmRegion, RegisterWrite};
 use crate::memory::MemoryInterface;
 use crate::{
     core::{Architecture, RegisterFile},
@@ -46,6 +47,18 @@ impl Operation for Verify {
     }
 }
 
+/// A routine of the flash algorithm which compares the flash contents on the target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum CheckRoutine {
+    /// The `Verify()` routine, which compares the flash with data in RAM.
+    Verify,
+    /// The `BlankCheck()` routine, which checks that the flash is erased.
+    BlankCheck,
+}
+
+/// The amount of bytes which the `BlankCheck()` routine checks at once.
+const BLANK_CHECK_CHUNK_SIZE: u32 = 0x1_0000;
+
 /// A structure to control the flash of an attached microchip.
 ///
 /// Once constructed it can be used to program date to the flash.
@@ -59,6 +72,25 @@ pub struct Flasher<'session> {
     flash_algorithm: FlashAlgorithm,
     region: NvmRegion,
     double_buffering_supported: bool,
+    algorithm_debug: Option<AlgorithmDebug>,
+    resume_address: Option<u32>,
+    /// True if the restore sequence of a memory mapped region was run
+    /// since the flash algorithm was last initialized.
+    memory_mapped_mode: bool,
+    /// True if the core is only halted before the flash algorithm runs, instead of reset.
+    keep_core_state: bool,
+    cancellation: Option<Cancellation>,
+}
+
+/// Settings for debugging a flash algorithm which fails on the target.
+///
+/// If enabled, the registers and the stack of the algorithm are logged when one of its
+/// routines fails, and the core is left halted at the failure.
+#[derive(Debug, Clone, Default)]
+pub(super) struct AlgorithmDebug {
+    /// The name of a routine, e.g. `program_page`. Instead of running this routine,
+    /// the core is left halted at its entry, with the arguments already set up.
+    pub(super) break_at: Option<String>,
 }
 
 impl<'session> Flasher<'session> {
@@ -67,14 +99,46 @@ impl<'session> Flasher<'session> {
         flash_algorithm: FlashAlgorithm,
         region: NvmRegion,
     ) -> Self {
+        // The next page buffer is written while the core runs the flash algorithm,
+        // which requires memory access to a running core. This is only possible on ARM targets.
+        let double_buffering_supported =
+            flash_algorithm.page_buffers.len() > 1 && session.architecture() == Architecture::Arm;
+
         Self {
             session,
             flash_algorithm,
             region,
-            double_buffering_supported: false,
+            double_buffering_supported,
+            algorithm_debug: None,
+            resume_address: None,
+            memory_mapped_mode: false,
+            keep_core_state: false,
+            cancellation: None,
         }
     }
 
+    /// Enables debugging of the flash algorithm, see [AlgorithmDebug].
+    pub(super) fn debug_algorithm(&mut self, debug: AlgorithmDebug) {
+        self.algorithm_debug = Some(debug);
+    }
+
+    /// Halts the core instead of resetting it, before the flash algorithm runs.
+    pub(super) fn keep_core_state(&mut self) {
+        self.keep_core_state = true;
+    }
+
+    /// Stops erasing and programming at the next sector boundary once `cancellation` is cancelled.
+    pub(super) fn cancel_with(&mut self, cancellation: Cancellation) {
+        self.cancellation = Some(cancellation);
+    }
+
+    /// Resumes programming at `address`, skipping all sectors below it.
+    ///
+    /// This is used to retry programming after a [FlashFailure] with a `resume_address`.
+    pub(super) fn resume_from(&mut self, address: Option<u32>) {
+        self.resume_address = address;
+    }
+
     pub(super) fn flash_algorithm(&self) -> &FlashAlgorithm {
         &self.flash_algorithm
     }
@@ -95,8 +159,17 @@ impl<'session> Flasher<'session> {
             address = Some(self.region.nvm_info().rom_start);
         }
 
-        // Attach to memory and core.
-        let mut core = self.session.core(0).map_err(FlashError::Memory)?;
+        let bank_swap = match &self.session.target().bank_swap {
+            Some(bank_swap) if self.region.is_boot_memory => Some(bank_swap.clone()),
+            _ => None,
+        };
+
+        // Attach to memory and the core which runs the flash algorithm.
+        log::debug!("Using core {} to run the flash algorithm.", algo.core_index);
+        let mut core = self
+            .session
+            .core(algo.core_index)
+            .map_err(FlashError::Memory)?;
 
         // TODO: Halt & reset target.
         log::debug!("Halting core.");
@@ -104,11 +177,63 @@ impl<'session> Flasher<'session> {
             .halt(Duration::from_millis(100))
             .map_err(FlashError::Core)?;
         log::debug!("PC = 0x{:08x}", cpu_info.pc);
-        log::debug!("Reset and halt");
-        core.reset_and_halt(Duration::from_millis(500))
-            .map_err(FlashError::Core)?;
+        if !self.keep_core_state {
+            log::debug!("Reset and halt");
+            core.reset_and_halt(Duration::from_millis(500))
+                .map_err(FlashError::Core)?;
+        }
+
+        // Prepare the target, e.g. disable caches or enable the clocks required by the flash.
+        let mut restore_values = Vec::with_capacity(algo.pre_flash_sequence.len());
+        for write in &algo.pre_flash_sequence {
+            let current = core
+                .read_word_32(write.address)
+                .map_err(FlashError::Memory)?;
+            let value = write.apply(current);
+            log::debug!(
+                "Pre-flash write to {:#010x}: {:#010x} -> {:#010x}",
+                write.address,
+                current,
+                value
+            );
+            core.write_word_32(write.address, value)
+                .map_err(FlashError::Memory)?;
+            restore_values.push((write.address, current));
+        }
 
-        // TODO: Possible special preparation of the target such as enabling faster clocks for the flash e.g.
+        // The controller of a memory mapped flash has to leave the memory mapped mode
+        // before the flash algorithm can use it.
+        if let Some(memory_mapped) = &self.region.memory_mapped {
+            write_registers(&mut core, &memory_mapped.pre_algorithm_sequence)?;
+        }
+        self.memory_mapped_mode = false;
+
+        // The flash algorithm works on the banks at their own addresses,
+        // the swap is restored in reverse order when the algorithm is uninitialized.
+        let mut unswap_restore = vec![];
+        for write in bank_swap
+            .iter()
+            .flat_map(|bank_swap| bank_swap.unswap.iter())
+        {
+            let current = core
+                .read_word_32(write.address)
+                .map_err(FlashError::Memory)?;
+            core.write_word_32(write.address, write.apply(current))
+                .map_err(FlashError::Memory)?;
+            unswap_restore.push(RegisterWrite {
+                address: write.address,
+                value: current,
+                mask: write.mask,
+            });
+        }
+
+        let mut restore_sequence = self
+            .region
+            .memory_mapped
+            .as_ref()
+            .map(|memory_mapped| memory_mapped.restore_sequence.to_vec())
+            .unwrap_or_default();
+        restore_sequence.extend(unswap_restore.into_iter().rev());
 
         // Load flash algorithm code into target RAM.
         log::debug!(
@@ -152,6 +277,9 @@ impl<'session> Flasher<'session> {
             core,
             flash_algorithm: self.flash_algorithm.clone(),
             _double_buffering_supported: self.double_buffering_supported,
+            restore_values,
+            restore_sequence,
+            algorithm_debug: self.algorithm_debug.clone(),
             _operation: core::marker::PhantomData,
         };
 
@@ -169,6 +297,8 @@ impl<'session> Flasher<'session> {
         let mut active = self.init(None, None)?;
         let r = f(&mut active)?;
         active.uninit()?;
+        drop(active);
+        self.memory_mapped_mode = true;
         Ok(r)
     }
 
@@ -181,6 +311,8 @@ impl<'session> Flasher<'session> {
         let mut active = self.init(None, None)?;
         let r = f(&mut active)?;
         active.uninit()?;
+        drop(active);
+        self.memory_mapped_mode = true;
         Ok(r)
     }
 
@@ -193,9 +325,38 @@ impl<'session> Flasher<'session> {
         let mut active = self.init(None, None)?;
         let r = f(&mut active)?;
         active.uninit()?;
+        drop(active);
+        self.memory_mapped_mode = true;
         Ok(r)
     }
 
+    /// Runs `f` with the core through which the flash contents are read.
+    ///
+    /// Memory mapped regions can only be read while their controller is in memory mapped mode,
+    /// so they are read through the memory bus after running the restore sequence of the region.
+    /// All other regions are read with the flash algorithm initialized for verifying.
+    fn run_read<T, F>(&mut self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Core<'_>) -> Result<T>,
+    {
+        let restore_sequence = match &self.region.memory_mapped {
+            Some(memory_mapped) => memory_mapped.restore_sequence.clone(),
+            None => return self.run_verify(|active| f(&mut active.core)),
+        };
+
+        let mut core = self
+            .session
+            .core(self.flash_algorithm.core_index)
+            .map_err(FlashError::Memory)?;
+
+        if !self.memory_mapped_mode {
+            write_registers(&mut core, &restore_sequence)?;
+            self.memory_mapped_mode = true;
+        }
+
+        f(&mut core)
+    }
+
     /// Writes a single block of data to a given address in the flash.
     ///
     /// This will not check any physical flash boundaries.
@@ -222,42 +383,110 @@ impl<'session> Flasher<'session> {
 
         let mut fb = FlashBuilder::new();
         fb.add_data(address, data)?;
-        self.program(&fb, do_chip_erase, true, false, progress)?;
+        self.program(
+            &fb,
+            do_chip_erase,
+            FillPolicy::ReadBack,
+            false,
+            false,
+            progress,
+        )?;
 
         Ok(())
     }
 
     /// Program the contents of given `FlashBuilder` to the flash.
     ///
-    /// If `restore_unwritten_bytes` is `true`, all bytes of a sector,
-    /// that are not to be written during flashing will be read from the flash first
-    /// and written again once the sector is erased.
+    /// The `fill_policy` decides what is written to all bytes of a sector
+    /// that are not to be written during flashing. With [FillPolicy::ReadBack] they are
+    /// read from the flash first and written again once the sector is erased.
+    ///
+    /// If `skip_unchanged_sectors` is `true`, all sectors are read back first and
+    /// sectors which already contain the data to be written are neither erased nor programmed.
+    /// This has no effect if a chip erase is done.
+    ///
+    /// Regions which can not be erased sector by sector are programmed over their current contents.
+    /// In this case, the `fill_policy` is ignored and bits which are already programmed have to stay programmed.
     pub(super) fn program(
         &mut self,
         flash_builder: &FlashBuilder,
         mut do_chip_erase: bool,
-        restore_unwritten_bytes: bool,
+        mut fill_policy: FillPolicy,
         enable_double_buffering: bool,
+        skip_unchanged_sectors: bool,
         progress: &FlashProgress,
     ) -> Result<()> {
-        // Convert the list of flash operations into flash sectors and pages.
-        let mut flash_layout = flash_builder
-            .build_sectors_and_pages(&self.flash_algorithm().clone(), restore_unwritten_bytes)?;
+        let preserving = flash_builder.has_preserved_ranges();
 
-        progress.initialized(flash_layout.clone());
+        // Without erasing, all bytes which are not written keep their current contents.
+        let erasing = self.region.is_sector_erasable();
+        if !erasing {
+            fill_policy = FillPolicy::ReadBack;
+            do_chip_erase = false;
+        }
+
+        // Convert the list of flash operations into flash sectors and pages.
+        let mut flash_layout =
+            flash_builder.build_layout(&self.flash_algorithm().clone(), fill_policy)?;
 
         // If the flash algo doesn't support erase all, disable chip erase.
         if self.flash_algorithm().pc_erase_all.is_none() {
             do_chip_erase = false;
         }
 
+        // The sectors below the resume address are already programmed and must not be erased again.
+        if let Some(resume_address) = self.resume_address {
+            let programmed_sectors = flash_layout
+                .sectors()
+                .iter()
+                .filter(|sector| sector.address() + sector.size() <= resume_address)
+                .cloned()
+                .collect::<Vec<_>>();
+            flash_layout.remove_sectors(&programmed_sectors);
+
+            log::debug!("Resuming programming at {:#010x}.", resume_address);
+            do_chip_erase = false;
+        }
+
+        // A chip erase would also erase preserved ranges in sectors without any new data.
+        if do_chip_erase && preserving {
+            log::warn!("Chip erase is disabled because address ranges have to be preserved.");
+            do_chip_erase = false;
+        }
+
+        // The fills are read back from the flash before erasing.
+        let read_fills = fill_policy == FillPolicy::ReadBack || preserving;
+
+        let mut skipped_sectors = vec![];
+        if skip_unchanged_sectors && !do_chip_erase {
+            skipped_sectors = self.unchanged_sectors(&flash_layout, read_fills)?;
+            flash_layout.remove_sectors(&skipped_sectors);
+
+            log::debug!(
+                "Skipping {} sectors which already contain the data to be written.",
+                skipped_sectors.len()
+            );
+        }
+
+        progress.initialized(flash_layout.clone());
+
+        if skip_unchanged_sectors && !do_chip_erase {
+            progress.sectors_skipped(
+                skipped_sectors.len(),
+                skipped_sectors.iter().map(|sector| sector.size()).sum(),
+            );
+        }
+
         log::debug!("Full Chip Erase enabled: {:?}", do_chip_erase);
         log::debug!("Double Buffering enabled: {:?}", enable_double_buffering);
 
         // Read all fill areas from the flash.
         progress.started_filling();
 
-        if restore_unwritten_bytes {
+        let fill_start = std::time::Instant::now();
+        let mut filled_bytes = 0;
+
+        if read_fills {
             let fills = flash_layout.fills().to_vec();
             for fill in fills {
                 let t = std::time::Instant::now();
@@ -265,23 +494,34 @@ impl<'session> Flasher<'session> {
                 let result = self.fill_page(page, &fill);
 
                 // If we encounter an error, catch it, gracefully report the failure and return the error.
-                if result.is_err() {
+                if let Err(error) = result {
                     progress.failed_filling();
-                    return result;
+                    return Err(self.operation_failed(
+                        FlashOperation::Fill,
+                        fill.address(),
+                        0,
+                        error,
+                    ));
                 } else {
-                    progress.page_filled(fill.size(), t.elapsed());
+                    progress.page_filled(fill.address(), fill.size(), t.elapsed());
+                    filled_bytes += fill.size();
                 }
             }
         }
 
         // We successfully finished filling.
-        progress.finished_filling();
+        progress.finished_filling(filled_bytes, fill_start.elapsed());
 
         // Erase all necessary sectors.
         if do_chip_erase {
             self.chip_erase(&flash_layout, progress)?;
-        } else {
+        } else if erasing {
             self.sector_erase(&flash_layout, progress)?;
+        } else {
+            self.check_programmed_bits(&flash_layout)?;
+
+            progress.started_erasing();
+            progress.finished_erasing(0, Duration::from_secs(0));
         }
 
         // Flash all necessary pages.
@@ -303,7 +543,267 @@ impl<'session> Flasher<'session> {
     pub(super) fn fill_page(&mut self, page: &mut FlashPage, fill: &FlashFill) -> Result<()> {
         let page_offset = (fill.address() - page.address()) as usize;
         let page_slice = &mut page.data_mut()[page_offset..page_offset + fill.size() as usize];
-        self.run_verify(|active| active.read_block8(fill.address(), page_slice))
+        self.run_read(|core| read_block8(core, fill.address(), page_slice))
+    }
+
+    /// Makes sure that programming the pages of `flash_layout` without erasing them first
+    /// does not require clearing any bits which are already programmed.
+    fn check_programmed_bits(&mut self, flash_layout: &FlashLayout) -> Result<()> {
+        let erased_byte_value = self.flash_algorithm().flash_properties.erased_byte_value;
+
+        self.run_read(|core| {
+            for page in flash_layout.pages() {
+                let mut current = vec![0; page.size() as usize];
+                read_block8(core, page.address(), &mut current)?;
+
+                for (offset, (current, requested)) in current.iter().zip(page.data()).enumerate() {
+                    let conflicting = conflicting_bits(*current, *requested, erased_byte_value);
+
+                    if conflicting != 0 {
+                        return Err(anyhow!(FlashError::ProgrammedBitsConflict {
+                            address: page.address() + offset as u32,
+                            current: *current,
+                            requested: *requested,
+                            conflicting,
+                        }));
+                    }
+                }
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Reads back all sectors of `flash_layout` and returns the ones which already
+    /// contain exactly the data they would contain after erasing and programming them.
+    ///
+    /// If `read_fills` is `true`, the fills of the layout are not compared,
+    /// as they would be restored to their current contents anyway.
+    fn unchanged_sectors(
+        &mut self,
+        flash_layout: &FlashLayout,
+        read_fills: bool,
+    ) -> Result<Vec<FlashSector>> {
+        let erased_byte_value = self.flash_algorithm().flash_properties.erased_byte_value;
+
+        self.run_read(|core| {
+            let mut unchanged = vec![];
+
+            for sector in flash_layout.sectors() {
+                let sector_start = sector.address();
+                let sector_end = sector.address() + sector.size();
+
+                let mut current = vec![0; sector.size() as usize];
+                read_block32(core, sector_start, &mut current)?;
+
+                // Assemble the contents the sector will have after flashing.
+                let mut expected = vec![erased_byte_value; sector.size() as usize];
+                for page in flash_layout.pages() {
+                    let start = page.address().max(sector_start);
+                    let end = (page.address() + page.size()).min(sector_end);
+
+                    if start < end {
+                        expected[(start - sector_start) as usize..(end - sector_start) as usize]
+                            .copy_from_slice(
+                                &page.data()[(start - page.address()) as usize
+                                    ..(end - page.address()) as usize],
+                            );
+                    }
+                }
+
+                if read_fills {
+                    for fill in flash_layout.fills() {
+                        let start = fill.address().max(sector_start);
+                        let end = (fill.address() + fill.size()).min(sector_end);
+
+                        if start < end {
+                            let range =
+                                (start - sector_start) as usize..(end - sector_start) as usize;
+                            expected[range.clone()].copy_from_slice(&current[range]);
+                        }
+                    }
+                }
+
+                if current == expected {
+                    unchanged.push(sector.clone());
+                }
+            }
+
+            Ok(unchanged)
+        })
+    }
+
+    /// Verifies that the flash contains the data of `flash_builder`.
+    ///
+    /// If the flash algorithm has a `Verify()` routine, it is used to compare the data on the target.
+    /// Otherwise the flash contents are read back and compared on the host.
+    pub(super) fn verify(
+        &mut self,
+        flash_builder: &FlashBuilder,
+        progress: &FlashProgress,
+    ) -> Result<()> {
+        let page_size = self.flash_algorithm().flash_properties.page_size;
+        let use_routine = self.flash_algorithm().pc_verify.is_some();
+
+        if !use_routine {
+            log::debug!("The flash algorithm has no Verify routine, reading back the flash.");
+        }
+
+        progress.started_verifying();
+
+        let start = std::time::Instant::now();
+        let mut total_bytes = 0;
+        let mut failed_address = self.region.range.start;
+
+        let mut verify_chunks = |check: &mut dyn FnMut(u32, &[u8]) -> Result<()>| {
+            for (address, data) in flash_builder.expected_data() {
+                let mut offset = 0;
+
+                while offset < data.len() {
+                    let chunk_address = address + offset as u32;
+                    // Never cross a page boundary, the page buffer in RAM is only one page large.
+                    let chunk_size =
+                        ((page_size - chunk_address % page_size) as usize).min(data.len() - offset);
+                    let chunk = &data[offset..offset + chunk_size];
+                    failed_address = chunk_address;
+
+                    let t = std::time::Instant::now();
+
+                    check(chunk_address, chunk)?;
+
+                    progress.page_verified(chunk_address, chunk_size as u32, t.elapsed());
+                    total_bytes += chunk_size as u32;
+                    offset += chunk_size;
+                }
+            }
+
+            Ok(())
+        };
+
+        let result = if use_routine {
+            self.run_verify(|active| {
+                verify_chunks(&mut |address, chunk| active.verify_block(address, chunk))
+            })
+        } else {
+            self.run_read(|core| {
+                verify_chunks(&mut |address, chunk| {
+                    let mut current = vec![0; chunk.len()];
+                    read_block8(core, address, &mut current)?;
+
+                    match current.iter().zip(chunk).position(|(a, b)| a != b) {
+                        Some(index) => Err(anyhow!(FlashError::Verify {
+                            address: address + index as u32,
+                        })),
+                        None => Ok(()),
+                    }
+                })
+            })
+        };
+
+        if result.is_ok() {
+            progress.finished_verifying(total_bytes, start.elapsed());
+        } else {
+            progress.failed_verifying();
+        }
+
+        // Verification only runs once everything is programmed.
+        let bytes_programmed = flash_builder
+            .expected_data()
+            .iter()
+            .map(|(_, data)| data.len() as u32)
+            .sum();
+
+        result.map_err(|error| {
+            self.operation_failed(
+                FlashOperation::Verify,
+                failed_address,
+                bytes_programmed,
+                error,
+            )
+        })
+    }
+
+    /// Checks the flash contents of the `chunks` with the `routine` of the flash algorithm,
+    /// and returns the parts of the chunks which differ.
+    ///
+    /// The chunks are checked in pieces of a page for `Verify()`, which compares with a page buffer,
+    /// so the returned parts are only as precise as these pieces. The flash algorithm has to have the routine.
+    pub(super) fn check<'data>(
+        &mut self,
+        chunks: &[(u32, &'data [u8])],
+        routine: CheckRoutine,
+        progress: &FlashProgress,
+    ) -> Result<Vec<(u32, &'data [u8])>> {
+        let piece_size = match routine {
+            CheckRoutine::Verify => self.flash_algorithm().flash_properties.page_size,
+            CheckRoutine::BlankCheck => BLANK_CHECK_CHUNK_SIZE,
+        };
+
+        progress.started_verifying();
+        let start = std::time::Instant::now();
+        let mut total_bytes = 0;
+
+        let result = self.run_verify(|active| {
+            let mut differing = vec![];
+
+            for (address, data) in chunks {
+                let mut offset = 0;
+
+                while offset < data.len() {
+                    let piece_address = address + offset as u32;
+                    let piece_size = ((piece_size - piece_address % piece_size) as usize)
+                        .min(data.len() - offset);
+                    let piece = &data[offset..offset + piece_size];
+
+                    let t = std::time::Instant::now();
+
+                    let matches = match routine {
+                        CheckRoutine::Verify => match active.verify_block(piece_address, piece) {
+                            Ok(()) => true,
+                            Err(error) => match error.downcast_ref::<FlashError>() {
+                                Some(FlashError::Verify { .. }) => false,
+                                _ => return Err(error),
+                            },
+                        },
+                        CheckRoutine::BlankCheck => {
+                            active.blank_check_block(piece_address, piece_size as u32)?
+                        }
+                    };
+                    if !matches {
+                        differing.push((piece_address, piece));
+                    }
+
+                    progress.page_verified(piece_address, piece_size as u32, t.elapsed());
+                    total_bytes += piece_size as u32;
+                    offset += piece_size;
+                }
+            }
+
+            Ok(differing)
+        });
+
+        match &result {
+            Ok(_) => progress.finished_verifying(total_bytes, start.elapsed()),
+            Err(_) => progress.failed_verifying(),
+        }
+
+        result
+    }
+
+    /// Erases the complete flash region of this flasher.
+    ///
+    /// The `EraseChip` routine of the flash algorithm is used if it is available,
+    /// otherwise all sectors of the region are erased one by one.
+    pub(super) fn erase_region(&mut self, progress: &FlashProgress) -> Result<()> {
+        let flash_layout = FlashLayout::erase_range(&self.flash_algorithm, &self.region.range);
+
+        progress.initialized(flash_layout.clone());
+
+        if self.flash_algorithm.pc_erase_all.is_some() {
+            self.chip_erase(&flash_layout, progress)
+        } else {
+            self.sector_erase(&flash_layout, progress)
+        }
     }
 
     /// Erase the entire flash of the chip.
@@ -311,21 +811,27 @@ impl<'session> Flasher<'session> {
     /// This takes the list of available sectors only for progress reporting reasons.
     /// It does not indeed erase single sectors but erases the entire flash.
     fn chip_erase(&mut self, flash_layout: &FlashLayout, progress: &FlashProgress) -> Result<()> {
+        self.cancellation_point().check()?;
+
         progress.started_erasing();
 
-        let mut t = std::time::Instant::now();
+        let start = std::time::Instant::now();
+        let mut t = start;
         let result = self.run_erase(|active| active.erase_all());
         for sector in flash_layout.sectors() {
-            progress.sector_erased(sector.size(), t.elapsed());
+            progress.sector_erased(sector.address(), sector.size(), t.elapsed());
             t = std::time::Instant::now();
         }
 
         if result.is_ok() {
-            progress.finished_erasing();
+            let total_bytes = flash_layout.sectors().iter().map(|s| s.size()).sum();
+            progress.finished_erasing(total_bytes, start.elapsed());
         } else {
             progress.failed_erasing();
         }
-        result
+
+        let address = self.region.range.start;
+        result.map_err(|error| self.operation_failed(FlashOperation::Erase, address, 0, error))
     }
 
     /// Programs the pages given in `flash_layout` into the flash.
@@ -336,102 +842,327 @@ impl<'session> Flasher<'session> {
     ) -> Result<()> {
         progress.started_programming();
 
-        let mut t = std::time::Instant::now();
+        let start = std::time::Instant::now();
+        let mut programmed = ProgrammedPages::new(self.region.range.start);
+        let mut cancellation = self.cancellation_point();
         let result = self.run_program(|active| {
-            for page in flash_layout.pages() {
-                active.program_page(page.address(), page.data())?;
-                progress.page_programmed(page.size(), t.elapsed());
-                t = std::time::Instant::now();
-            }
-            Ok(())
+            program_pages(
+                active,
+                flash_layout,
+                progress,
+                &mut cancellation,
+                &mut programmed,
+            )
         });
+        let result = result.and_then(|()| cancellation.check());
 
         if result.is_ok() {
-            progress.finished_programming();
+            let total_bytes = flash_layout.pages().iter().map(|p| p.size()).sum();
+            progress.finished_programming(total_bytes, start.elapsed());
         } else {
             progress.failed_programming();
         }
 
-        result
+        result.map_err(|error| {
+            self.operation_failed(
+                FlashOperation::Program,
+                programmed.failed_address,
+                programmed.bytes,
+                error,
+            )
+        })
     }
 
     /// Perform an erase of all sectors given in `flash_layout`.
     fn sector_erase(&mut self, flash_layout: &FlashLayout, progress: &FlashProgress) -> Result<()> {
         progress.started_erasing();
 
-        let mut t = std::time::Instant::now();
+        let start = std::time::Instant::now();
+        let mut t = start;
+        let mut failed_address = self.region.range.start;
+        let mut cancellation = self.cancellation_point();
         let result = self.run_erase(|active| {
             for sector in flash_layout.sectors() {
+                if cancellation.stop() {
+                    break;
+                }
+                failed_address = sector.address();
                 active.erase_sector(sector.address())?;
-                progress.sector_erased(sector.size(), t.elapsed());
+                progress.sector_erased(sector.address(), sector.size(), t.elapsed());
                 t = std::time::Instant::now();
             }
             Ok(())
         });
+        let result = result.and_then(|()| cancellation.check());
 
         if result.is_ok() {
-            progress.finished_erasing();
+            let total_bytes = flash_layout.sectors().iter().map(|s| s.size()).sum();
+            progress.finished_erasing(total_bytes, start.elapsed());
         } else {
             progress.failed_erasing();
         }
 
         result
+            .map_err(|error| self.operation_failed(FlashOperation::Erase, failed_address, 0, error))
     }
 
     /// Flash a program using double buffering.
     ///
-    /// UNTESTED
+    /// While the flash algorithm programs the page in one buffer,
+    /// the data of the next page is transferred into the other buffer.
     fn program_double_buffer(
         &mut self,
         flash_layout: &FlashLayout,
         progress: &FlashProgress,
     ) -> Result<()> {
-        let mut current_buf = 0;
+        let timeout = Duration::from_millis(
+            self.flash_algorithm()
+                .flash_properties
+                .program_page_timeout
+                .into(),
+        );
 
         progress.started_programming();
 
-        let mut t = std::time::Instant::now();
+        let start = std::time::Instant::now();
+        let mut programmed = ProgrammedPages::new(self.region.range.start);
+        let mut cancellation = self.cancellation_point();
         let result = self.run_program(|active| {
-            for page in flash_layout.pages() {
-                // At the start of each loop cycle load the next page buffer into RAM.
-                active.load_page_buffer(page.address(), page.data(), current_buf)?;
+            program_pages_double_buffered(
+                active,
+                flash_layout,
+                timeout,
+                progress,
+                &mut cancellation,
+                &mut programmed,
+            )
+        });
+        let result = result.and_then(|()| cancellation.check().map_err(FlashError::from_anyhow));
 
-                // Then wait for the active RAM -> Flash copy process to finish.
-                // Also check if it finished properly. If it didn't, return an error.
-                let result = active.wait_for_completion(Duration::from_secs(2))?;
-                progress.page_programmed(page.size(), t.elapsed());
-                t = std::time::Instant::now();
-                if result != 0 {
-                    return Err(FlashError::PageWrite {
-                        page_address: page.address(),
-                        error_code: result,
-                    });
-                }
+        if result.is_ok() {
+            let total_bytes = flash_layout.pages().iter().map(|p| p.size()).sum();
+            progress.finished_programming(total_bytes, start.elapsed());
+        } else {
+            progress.failed_programming();
+        }
 
-                // Start the next copy process.
-                active.start_program_page_with_buffer(page.address(), current_buf)?;
+        result.map_err(|error| {
+            self.operation_failed(
+                FlashOperation::Program,
+                programmed.failed_address,
+                programmed.bytes,
+                anyhow!(error),
+            )
+        })
+    }
 
-                // Swap the buffers
-                if current_buf == 1 {
-                    current_buf = 0;
-                } else {
-                    current_buf = 1;
-                }
-            }
+    fn cancellation_point(&self) -> CancellationPoint {
+        CancellationPoint {
+            cancellation: self.cancellation.clone(),
+            stopped: false,
+        }
+    }
 
-            Ok(())
-        });
+    /// Adds the context of the failed `operation` to `error`.
+    fn operation_failed(
+        &self,
+        operation: FlashOperation,
+        address: u32,
+        bytes_programmed: u32,
+        error: anyhow::Error,
+    ) -> anyhow::Error {
+        let source = FlashError::from_anyhow(error);
+
+        // Keep the context of the innermost failure. A cancellation is no failure of the operation.
+        if let FlashError::OperationFailed { .. } | FlashError::Cancelled = source {
+            return anyhow!(source);
+        }
 
-        if result.is_ok() {
-            progress.finished_programming();
+        let sector = self
+            .flash_algorithm
+            .sector_info(address)
+            .map(|sector| sector.base_address..sector.base_address + sector.size);
+        let failure = FlashFailure::new(operation, address, sector, bytes_programmed, &source);
+
+        log::error!("{}", failure);
+
+        anyhow!(FlashError::OperationFailed {
+            failure,
+            source: Box::new(source),
+        })
+    }
+}
+
+/// Stops a flash operation between two sectors, once its [Cancellation] is cancelled.
+///
+/// The operation leaves its loop instead of returning an error right away,
+/// so the flash algorithm is still uninitialized.
+struct CancellationPoint {
+    cancellation: Option<Cancellation>,
+    stopped: bool,
+}
+
+impl CancellationPoint {
+    /// Returns `true` if the operation has to stop before the next sector.
+    fn stop(&mut self) -> bool {
+        self.stopped = self
+            .cancellation
+            .as_ref()
+            .map_or(false, Cancellation::is_cancelled);
+        self.stopped
+    }
+
+    /// Returns [FlashError::Cancelled] if the operation was stopped, or if it is cancelled already.
+    fn check(&mut self) -> Result<()> {
+        if self.stopped || self.stop() {
+            log::info!("Flashing was cancelled.");
+            Err(anyhow!(FlashError::Cancelled))
         } else {
-            progress.failed_programming();
+            Ok(())
         }
+    }
+}
+
+/// The routines of a flash algorithm which program pages, as provided by [ActiveFlasher].
+trait PageProgrammer {
+    /// Programs a single page and waits for it.
+    fn program_page(&mut self, address: u32, bytes: &[u8]) -> Result<()>;
+
+    /// Transfers the data of a page into one of the page buffers.
+    fn load_page_buffer(&mut self, address: u32, bytes: &[u8], buffer_number: usize) -> Result<()>;
 
-        Ok(result?)
+    /// Starts programming a page from one of the page buffers, without waiting for it.
+    fn start_program_page_with_buffer(&mut self, address: u32, buffer_number: usize) -> Result<()>;
+
+    /// Waits for the page which is being programmed to finish.
+    fn wait_for_page_programmed(
+        &mut self,
+        page_address: u32,
+        timeout: Duration,
+    ) -> Result<(), FlashError>;
+}
+
+impl PageProgrammer for ActiveFlasher<'_, Program> {
+    fn program_page(&mut self, address: u32, bytes: &[u8]) -> Result<()> {
+        ActiveFlasher::program_page(self, address, bytes)
+    }
+
+    fn load_page_buffer(&mut self, address: u32, bytes: &[u8], buffer_number: usize) -> Result<()> {
+        ActiveFlasher::load_page_buffer(self, address, bytes, buffer_number)
+    }
+
+    fn start_program_page_with_buffer(&mut self, address: u32, buffer_number: usize) -> Result<()> {
+        ActiveFlasher::start_program_page_with_buffer(self, address, buffer_number)
+    }
+
+    fn wait_for_page_programmed(
+        &mut self,
+        page_address: u32,
+        timeout: Duration,
+    ) -> Result<(), FlashError> {
+        ActiveFlasher::wait_for_page_programmed(self, page_address, timeout)
+    }
+}
+
+/// How far programming got, such that a failure can be attributed to a page.
+struct ProgrammedPages {
+    /// The address of the page which is programmed at the moment.
+    failed_address: u32,
+    /// The amount of bytes which were programmed successfully.
+    bytes: u32,
+}
+
+impl ProgrammedPages {
+    fn new(start_address: u32) -> Self {
+        Self {
+            failed_address: start_address,
+            bytes: 0,
+        }
+    }
+}
+
+/// Programs the pages of `flash_layout` one after another.
+fn program_pages(
+    programmer: &mut impl PageProgrammer,
+    flash_layout: &FlashLayout,
+    progress: &FlashProgress,
+    cancellation: &mut CancellationPoint,
+    programmed: &mut ProgrammedPages,
+) -> Result<()> {
+    let mut t = std::time::Instant::now();
+    for page in flash_layout.pages() {
+        if flash_layout.starts_sector(page) && cancellation.stop() {
+            break;
+        }
+        programmed.failed_address = page.address();
+        programmer.program_page(page.address(), page.data())?;
+        programmed.bytes += page.size();
+        progress.page_programmed(page.address(), page.size(), t.elapsed());
+        t = std::time::Instant::now();
+    }
+    Ok(())
+}
+
+/// Programs the pages of `flash_layout` using two page buffers.
+///
+/// While the flash algorithm programs the page in one buffer,
+/// the data of the next page is transferred into the other buffer.
+fn program_pages_double_buffered(
+    programmer: &mut impl PageProgrammer,
+    flash_layout: &FlashLayout,
+    timeout: Duration,
+    progress: &FlashProgress,
+    cancellation: &mut CancellationPoint,
+    programmed: &mut ProgrammedPages,
+) -> Result<(), FlashError> {
+    let mut t = std::time::Instant::now();
+    let mut current_buf = 0;
+
+    // The page which is currently being programmed by the target.
+    let mut pending: Option<&FlashPage> = None;
+
+    for page in flash_layout.pages() {
+        // The pending page is still completed below.
+        if flash_layout.starts_sector(page) && cancellation.stop() {
+            break;
+        }
+
+        // A failure is attributed to the oldest page which is not programmed yet.
+        programmed.failed_address = pending.map_or(page.address(), |previous| previous.address());
+
+        // Transfer the page into the free buffer while the previous page is programmed.
+        programmer.load_page_buffer(page.address(), page.data(), current_buf)?;
+
+        // Then wait for the previous RAM -> Flash copy process to finish.
+        if let Some(previous) = pending.take() {
+            programmer.wait_for_page_programmed(previous.address(), timeout)?;
+            programmed.bytes += previous.size();
+            progress.page_programmed(previous.address(), previous.size(), t.elapsed());
+            t = std::time::Instant::now();
+        }
+
+        // Start the next copy process.
+        programmed.failed_address = page.address();
+        programmer.start_program_page_with_buffer(page.address(), current_buf)?;
+        pending = Some(page);
+
+        // Swap the buffers
+        current_buf = 1 - current_buf;
+    }
+
+    // Wait for the last page.
+    if let Some(previous) = pending {
+        programmer.wait_for_page_programmed(previous.address(), timeout)?;
+        programmed.bytes += previous.size();
+        progress.page_programmed(previous.address(), previous.size(), t.elapsed());
     }
+
+    Ok(())
 }
 
+/// The maximum amount of stack words which are logged when debugging a flash algorithm.
+const STACK_DUMP_WORDS: u32 = 16;
+
 struct Registers {
     pc: u32,
     r0: Option<u32>,
@@ -454,6 +1185,11 @@ pub(super) struct ActiveFlasher<'probe, O: Operation> {
     core: Core<'probe>,
     flash_algorithm: FlashAlgorithm,
     _double_buffering_supported: bool,
+    /// The original values of the registers modified by the pre-flash sequence.
+    restore_values: Vec<(u32, u32)>,
+    /// The register writes which switch a memory mapped region back to memory mapped mode.
+    restore_sequence: Vec<RegisterWrite>,
+    algorithm_debug: Option<AlgorithmDebug>,
     _operation: core::marker::PhantomData<O>,
 }
 
@@ -474,9 +1210,11 @@ impl<'probe, O: Operation> ActiveFlasher<'probe, O> {
                 },
                 true,
                 Duration::from_secs(2),
+                "init",
             )?;
 
             if result != 0 {
+                self.report_failure("init");
                 return Err(anyhow!(FlashError::RoutineCallFailed {
                     name: "init",
                     errorcode: result,
@@ -506,15 +1244,40 @@ impl<'probe, O: Operation> ActiveFlasher<'probe, O> {
                 },
                 false,
                 Duration::from_secs(2),
+                "uninit",
             )?;
 
             if result != 0 {
+                self.report_failure("uninit");
                 return Err(anyhow!(FlashError::RoutineCallFailed {
                     name: "uninit",
                     errorcode: result,
                 }));
             }
         }
+
+        self.run_post_flash_sequence()?;
+
+        let restore_sequence = std::mem::take(&mut self.restore_sequence);
+        write_registers(&mut self.core, &restore_sequence)?;
+
+        Ok(())
+    }
+
+    /// Runs the post-flash sequence of the algorithm, or restores the registers
+    /// modified by the pre-flash sequence if there is none.
+    fn run_post_flash_sequence(&mut self) -> Result<()> {
+        if self.flash_algorithm.post_flash_sequence.is_empty() {
+            for (address, value) in self.restore_values.drain(..).rev() {
+                log::debug!("Restoring {:#010x} to {:#010x}", address, value);
+                self.core
+                    .write_word_32(address, value)
+                    .map_err(FlashError::Memory)?;
+            }
+        } else {
+            write_registers(&mut self.core, &self.flash_algorithm.post_flash_sequence)?;
+        }
+
         Ok(())
     }
 
@@ -523,13 +1286,28 @@ impl<'probe, O: Operation> ActiveFlasher<'probe, O> {
         registers: &Registers,
         init: bool,
         duration: Duration,
+        name: &'static str,
     ) -> Result<u32> {
-        self.call_function(registers, init)?;
-        self.wait_for_completion(duration)
+        self.call_function(registers, init, name)?;
+
+        match self.wait_for_completion(duration) {
+            Ok(result) => Ok(result),
+            Err(e) => {
+                self.report_failure(name);
+                Err(e)
+            }
+        }
     }
 
-    fn call_function(&mut self, registers: &Registers, init: bool) -> Result<()> {
-        log::debug!("Calling routine {:?}, init={})", &registers, init);
+    fn call_function(
+        &mut self,
+        registers: &Registers,
+        init: bool,
+        name: &'static str,
+    ) -> Result<()> {
+        log::debug!("Calling routine {} {:?}, init={})", name, &registers, init);
+
+        let entry = registers.pc;
 
         let algo = &self.flash_algorithm;
         let regs: &'static RegisterFile = self.core.registers();
@@ -592,12 +1370,84 @@ impl<'probe, O: Operation> ActiveFlasher<'probe, O> {
                 .map_err(FlashError::Core)?;
         }
 
+        let break_at_entry = self
+            .algorithm_debug
+            .as_ref()
+            .and_then(|debug| debug.break_at.as_deref())
+            == Some(name);
+
+        if break_at_entry {
+            log::warn!(
+                "Not running '{}', the core is halted at its entry {:#010x}.",
+                name,
+                entry
+            );
+            return Err(anyhow!(FlashError::HaltedAtRoutineEntry {
+                name,
+                address: entry,
+            }));
+        }
+
         // Resume target operation.
         self.core.run().map_err(FlashError::Core)?;
 
         Ok(())
     }
 
+    /// Logs the state of the flash algorithm after its routine `name` failed.
+    ///
+    /// This only does something if flash algorithm debugging is enabled.
+    /// The core is halted and left in this state, such that it can be inspected with a debugger.
+    fn report_failure(&mut self, name: &'static str) {
+        if self.algorithm_debug.is_none() {
+            return;
+        }
+
+        if let Err(e) = self.dump_state(name) {
+            log::warn!("Failed to read the state of the flash algorithm: {}", e);
+        }
+    }
+
+    fn dump_state(&mut self, name: &'static str) -> Result<(), crate::Error> {
+        if !self.core.core_halted()? {
+            self.core.halt(Duration::from_millis(100))?;
+        }
+
+        let regs: &'static RegisterFile = self.core.registers();
+
+        let pc = self.core.read_core_reg(regs.program_counter().address)?;
+        let location = match self.flash_algorithm.routine_at(pc) {
+            Some((routine, offset)) => format!("{}+{:#x}", routine, offset),
+            None => "outside of the flash algorithm".to_owned(),
+        };
+        log::error!(
+            "The '{}' routine of the flash algorithm failed, the core is halted at {:#010x} ({}).",
+            name,
+            pc,
+            location
+        );
+
+        let mut registers = vec![regs.stack_pointer(), regs.return_address()];
+        registers.extend((0..4).filter_map(|index| regs.get_argument_register(index)));
+        registers.extend(regs.get_platform_register(9));
+
+        for description in registers {
+            let value = self.core.read_core_reg(description.address)?;
+            log::error!("{:>4}: {:#010x}", description.name, value);
+        }
+
+        // The stack grows downwards from `begin_stack`, only the used part of it is dumped.
+        let sp = self.core.read_core_reg(regs.stack_pointer().address)?;
+        if sp < self.flash_algorithm.begin_stack {
+            let words = ((self.flash_algorithm.begin_stack - sp) / 4).min(STACK_DUMP_WORDS);
+            let mut stack = vec![0u32; words as usize];
+            self.core.read_32(sp, &mut stack)?;
+            log::error!("Stack at {:#010x}: {:08x?}", sp, stack);
+        }
+
+        Ok(())
+    }
+
     pub(super) fn wait_for_completion(&mut self, timeout: Duration) -> Result<u32> {
         log::debug!("Waiting for routine call completion.");
         let regs = self.core.registers();
@@ -612,15 +1462,125 @@ impl<'probe, O: Operation> ActiveFlasher<'probe, O> {
             .map_err(FlashError::Core)?;
         Ok(r)
     }
+}
+
+/// Reads a block of data from the flash.
+fn read_block8(core: &mut Core<'_>, address: u32, data: &mut [u8]) -> Result<()> {
+    core.read_8(address, data).map_err(FlashError::Memory)?;
+    Ok(())
+}
 
-    pub(super) fn read_block8(&mut self, address: u32, data: &mut [u8]) -> Result<()> {
+/// Reads a block of data using 32 bit accesses.
+///
+/// `address` and the length of `data` have to be 4 byte aligned.
+fn read_block32(core: &mut Core<'_>, address: u32, data: &mut [u8]) -> Result<()> {
+    let mut words = vec![0u32; data.len() / 4];
+    core.read_32(address, &mut words)
+        .map_err(FlashError::Memory)?;
+
+    for (bytes, word) in data.chunks_exact_mut(4).zip(words) {
+        bytes.copy_from_slice(&word.to_le_bytes());
+    }
+    Ok(())
+}
+
+/// Applies a sequence of read-modify-write register accesses.
+pub(super) fn write_registers(
+    core: &mut Core<'_>,
+    writes: &[RegisterWrite],
+) -> Result<(), FlashError> {
+    for write in writes {
+        let current = core
+            .read_word_32(write.address)
+            .map_err(FlashError::Memory)?;
+        core.write_word_32(write.address, write.apply(current))
+            .map_err(FlashError::Memory)?;
+    }
+
+    Ok(())
+}
+
+impl<'probe> ActiveFlasher<'probe, Verify> {
+    /// Compares `bytes` with the flash contents at `address` using the `Verify()` routine.
+    ///
+    /// `bytes` must not be larger than a page.
+    pub(super) fn verify_block(&mut self, address: u32, bytes: &[u8]) -> Result<()> {
+        let pc_verify = self
+            .flash_algorithm
+            .pc_verify
+            .ok_or(FlashError::RoutineNotSupported("verify"))?;
+
+        log::debug!(
+            "Verifying {} bytes at address {:#010x}",
+            bytes.len(),
+            address
+        );
+
+        // Transfer the expected bytes to RAM.
         self.core
-            .read_8(address, data)
+            .write_8(self.flash_algorithm.begin_data, bytes)
             .map_err(FlashError::Memory)?;
+
+        let result = self.call_function_and_wait(
+            &Registers {
+                pc: pc_verify,
+                r0: Some(address),
+                r1: Some(bytes.len() as u32),
+                r2: Some(self.flash_algorithm.begin_data),
+                r3: None,
+            },
+            false,
+            Duration::from_secs(2),
+            "verify",
+        )?;
+
+        // The routine returns the end address of the block if everything matches,
+        // or the address of the first mismatch.
+        let end = address + bytes.len() as u32;
+        if result != end {
+            return Err(anyhow!(FlashError::Verify { address: result }));
+        }
+
         Ok(())
     }
 }
 
+impl<'probe> ActiveFlasher<'probe, Verify> {
+    /// Checks that the `size` bytes at `address` are erased using the `BlankCheck()` routine.
+    ///
+    /// Returns `false` if any of the bytes is not erased.
+    pub(super) fn blank_check_block(&mut self, address: u32, size: u32) -> Result<bool> {
+        let pc_blank_check = self
+            .flash_algorithm
+            .pc_blank_check
+            .ok_or(FlashError::RoutineNotSupported("blank_check"))?;
+
+        log::debug!(
+            "Checking that {} bytes at address {:#010x} are erased",
+            size,
+            address
+        );
+
+        let result = self.call_function_and_wait(
+            &Registers {
+                pc: pc_blank_check,
+                r0: Some(address),
+                r1: Some(size),
+                r2: Some(u32::from(
+                    self.flash_algorithm.flash_properties.erased_byte_value,
+                )),
+                r3: None,
+            },
+            false,
+            Duration::from_secs(2),
+            "blank_check",
+        )?;
+
+        // The routine returns 0 if all bytes have the given value.
+        Ok(result == 0)
+    }
+}
+
 impl<'probe> ActiveFlasher<'probe, Erase> {
     pub(super) fn erase_all(&mut self) -> Result<()> {
         log::debug!("Erasing entire chip.");
@@ -638,9 +1598,11 @@ impl<'probe> ActiveFlasher<'probe, Erase> {
                 },
                 false,
                 Duration::from_secs(5),
+                "erase_all",
             )?;
 
             if result != 0 {
+                flasher.report_failure("erase_all");
                 Err(anyhow!(FlashError::EraseFailed {
                     name: "erase_all",
                     errorcode: result,
@@ -667,6 +1629,7 @@ impl<'probe> ActiveFlasher<'probe, Erase> {
             },
             false,
             Duration::from_secs(5),
+            "erase_sector",
         )?;
         log::info!(
             "Done erasing sector. Result is {}. This took {:?}",
@@ -675,6 +1638,7 @@ impl<'probe> ActiveFlasher<'probe, Erase> {
         );
 
         if result != 0 {
+            self.report_failure("erase_sector");
             Err(anyhow!(FlashError::EraseFailed {
                 name: "erase_sector",
                 errorcode: result,
@@ -710,10 +1674,12 @@ impl<'p> ActiveFlasher<'p, Program> {
             },
             false,
             Duration::from_secs(2),
+            "program_page",
         )?;
         log::info!("Flashing took: {:?}", t1.elapsed());
 
         if result != 0 {
+            self.report_failure("program_page");
             Err(anyhow!(FlashError::RoutineCallFailed {
                 name: "program_page",
                 errorcode: result,
@@ -729,7 +1695,7 @@ impl<'p> ActiveFlasher<'p, Program> {
         buffer_number: usize,
     ) -> Result<()> {
         // Check the buffer number.
-        if buffer_number < self.flash_algorithm.page_buffers.len() {
+        if buffer_number >= self.flash_algorithm.page_buffers.len() {
             return Err(anyhow!(FlashError::InvalidBufferNumber {
                 n: buffer_number,
                 max: self.flash_algorithm.page_buffers.len(),
@@ -745,11 +1711,49 @@ impl<'p> ActiveFlasher<'p, Program> {
                 r3: None,
             },
             false,
+            "program_page",
         )?;
 
         Ok(())
     }
 
+    /// Waits for the `ProgramPage()` call started with [start_program_page_with_buffer] to finish.
+    ///
+    /// If the routine does not finish in time, the core is halted again,
+    /// such that the target is left in a defined state.
+    ///
+    /// [start_program_page_with_buffer]: ActiveFlasher::start_program_page_with_buffer
+    pub(super) fn wait_for_page_programmed(
+        &mut self,
+        page_address: u32,
+        timeout: Duration,
+    ) -> Result<(), FlashError> {
+        let result = match self.wait_for_completion(timeout) {
+            Ok(result) => result,
+            Err(e) => {
+                log::error!(
+                    "Programming the page at {:#010x} did not finish, halting the core.",
+                    page_address
+                );
+                self.report_failure("program_page");
+                if let Err(halt_error) = self.core.halt(Duration::from_millis(100)) {
+                    log::warn!("Failed to halt the core: {}", halt_error);
+                }
+                return Err(FlashError::Other(e));
+            }
+        };
+
+        if result != 0 {
+            self.report_failure("program_page");
+            return Err(FlashError::PageWrite {
+                page_address,
+                error_code: result,
+            });
+        }
+
+        Ok(())
+    }
+
     pub(super) fn load_page_buffer(
         &mut self,
         _address: u32,
@@ -760,7 +1764,7 @@ impl<'p> ActiveFlasher<'p, Program> {
         let algo = &flasher.flash_algorithm;
 
         // Check the buffer number.
-        if buffer_number < algo.page_buffers.len() {
+        if buffer_number >= algo.page_buffers.len() {
             return Err(anyhow!(FlashError::InvalidBufferNumber {
                 n: buffer_number,
                 max: algo.page_buffers.len(),
@@ -778,3 +1782,231 @@ impl<'p> ActiveFlasher<'p, Program> {
         Ok(())
     }
 }
+
+/// Returns the bits which are programmed in `current`, but not in `requested`.
+///
+/// Programming can only change bits from their erased value, so these bits
+/// could only be cleared by erasing.
+fn conflicting_bits(current: u8, requested: u8, erased_byte_value: u8) -> u8 {
+    (current ^ erased_byte_value) & !(requested ^ erased_byte_value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{FlashProperties, SectorDescription};
+    use crate::flashing::ProgressEvent;
+    use std::sync::mpsc::channel;
+
+    /// The time the mock flash algorithm takes to program a page.
+    const PAGE_PROGRAM_TIME: Duration = Duration::from_millis(5);
+
+    /// A call to a routine of the flash algorithm.
+    #[derive(Debug, PartialEq)]
+    enum Call {
+        Program(u32),
+        Load(u32, usize),
+        Start(u32, usize),
+        Wait(u32),
+    }
+
+    /// A flash algorithm which records the calls to its routines.
+    #[derive(Default)]
+    struct MockProgrammer {
+        calls: Vec<Call>,
+        /// The buffer which is being programmed into the flash at the moment.
+        busy_buffer: Option<usize>,
+    }
+
+    impl PageProgrammer for MockProgrammer {
+        fn program_page(&mut self, address: u32, _bytes: &[u8]) -> Result<()> {
+            std::thread::sleep(PAGE_PROGRAM_TIME);
+            self.calls.push(Call::Program(address));
+            Ok(())
+        }
+
+        fn load_page_buffer(
+            &mut self,
+            address: u32,
+            _bytes: &[u8],
+            buffer_number: usize,
+        ) -> Result<()> {
+            assert_ne!(
+                self.busy_buffer,
+                Some(buffer_number),
+                "The buffer is overwritten while it is programmed"
+            );
+            self.calls.push(Call::Load(address, buffer_number));
+            Ok(())
+        }
+
+        fn start_program_page_with_buffer(
+            &mut self,
+            address: u32,
+            buffer_number: usize,
+        ) -> Result<()> {
+            assert_eq!(
+                self.busy_buffer, None,
+                "The previous page is still programmed"
+            );
+            self.busy_buffer = Some(buffer_number);
+            self.calls.push(Call::Start(address, buffer_number));
+            Ok(())
+        }
+
+        fn wait_for_page_programmed(
+            &mut self,
+            page_address: u32,
+            _timeout: Duration,
+        ) -> Result<(), FlashError> {
+            assert!(self.busy_buffer.is_some(), "No page is programmed");
+            std::thread::sleep(PAGE_PROGRAM_TIME);
+            self.busy_buffer = None;
+            self.calls.push(Call::Wait(page_address));
+            Ok(())
+        }
+    }
+
+    /// Layouts 2 KiB of data at 0x100 into pages of 1 KiB, which touches the pages at 0x0, 0x400 and 0x800.
+    fn demo_flash_layout() -> FlashLayout {
+        let flash_algorithm = FlashAlgorithm {
+            flash_properties: FlashProperties {
+                address_range: 0..1 << 16,
+                page_size: 1024,
+                erased_byte_value: 255,
+                program_page_timeout: 200,
+                erase_sector_timeout: 200,
+                sectors: std::borrow::Cow::Owned(vec![SectorDescription {
+                    size: 4096,
+                    address: 0,
+                }]),
+            },
+            ..Default::default()
+        };
+
+        let data = [0x42; 2048];
+        let mut flash_builder = FlashBuilder::new();
+        flash_builder.add_data(0x100, &data).unwrap();
+        flash_builder
+            .build_sectors_and_pages(&flash_algorithm, false)
+            .unwrap()
+    }
+
+    fn no_cancellation() -> CancellationPoint {
+        CancellationPoint {
+            cancellation: None,
+            stopped: false,
+        }
+    }
+
+    #[test]
+    fn programming_reports_every_page() {
+        let flash_layout = demo_flash_layout();
+        let (sender, receiver) = channel();
+        let progress = FlashProgress::from_sender(sender);
+        let mut programmer = MockProgrammer::default();
+        let mut programmed = ProgrammedPages::new(0);
+
+        let start = std::time::Instant::now();
+        program_pages(
+            &mut programmer,
+            &flash_layout,
+            &progress,
+            &mut no_cancellation(),
+            &mut programmed,
+        )
+        .unwrap();
+        let total_time = start.elapsed();
+
+        assert_eq!(
+            programmer.calls,
+            vec![
+                Call::Program(0x0),
+                Call::Program(0x400),
+                Call::Program(0x800)
+            ]
+        );
+        assert_eq!(programmed.bytes, 3 * 1024);
+        assert_eq!(programmed.failed_address, 0x800);
+
+        let mut addresses = vec![];
+        let mut page_times = Duration::default();
+        for event in receiver.try_iter() {
+            match event {
+                ProgressEvent::PageProgrammed {
+                    address,
+                    size,
+                    time,
+                } => {
+                    assert_eq!(size, 1024);
+                    // Every page is timed on its own.
+                    assert!(time >= PAGE_PROGRAM_TIME, "{:?}", time);
+                    addresses.push(address);
+                    page_times += time;
+                }
+                event => panic!("Unexpected event {:?}", event),
+            }
+        }
+        assert_eq!(addresses, vec![0x0, 0x400, 0x800]);
+        assert!(page_times <= total_time);
+    }
+
+    #[test]
+    fn double_buffering_alternates_the_buffers() {
+        let flash_layout = demo_flash_layout();
+        let (sender, receiver) = channel();
+        let progress = FlashProgress::from_sender(sender);
+        let mut programmer = MockProgrammer::default();
+        let mut programmed = ProgrammedPages::new(0);
+
+        program_pages_double_buffered(
+            &mut programmer,
+            &flash_layout,
+            Duration::from_millis(200),
+            &progress,
+            &mut no_cancellation(),
+            &mut programmed,
+        )
+        .unwrap();
+
+        // The next page is loaded into the free buffer before waiting for the previous page.
+        assert_eq!(
+            programmer.calls,
+            vec![
+                Call::Load(0x0, 0),
+                Call::Start(0x0, 0),
+                Call::Load(0x400, 1),
+                Call::Wait(0x0),
+                Call::Start(0x400, 1),
+                Call::Load(0x800, 0),
+                Call::Wait(0x400),
+                Call::Start(0x800, 0),
+                Call::Wait(0x800),
+            ]
+        );
+        // The last page is waited for as well.
+        assert_eq!(programmer.busy_buffer, None);
+        assert_eq!(programmed.bytes, 3 * 1024);
+
+        let addresses: Vec<_> = receiver
+            .try_iter()
+            .map(|event| match event {
+                ProgressEvent::PageProgrammed { address, .. } => address,
+                event => panic!("Unexpected event {:?}", event),
+            })
+            .collect();
+        assert_eq!(addresses, vec![0x0, 0x400, 0x800]);
+    }
+
+    #[test]
+    fn programmed_bits_can_not_be_cleared() {
+        // Programming clears bits if the erased value is 0xFF.
+        assert_eq!(conflicting_bits(0xFF, 0x0F, 0xFF), 0x00);
+        assert_eq!(conflicting_bits(0x0F, 0x03, 0xFF), 0x00);
+        assert_eq!(conflicting_bits(0x0F, 0x3C, 0xFF), 0x30);
+
+        // Programming sets bits if the erased value is 0x00, like for eFuses.
+        assert_eq!(conflicting_bits(0x00, 0xF0, 0x00), 0x00);
+        assert_eq!(conflicting_bits(0x81, 0x80, 0x00), 0x01);
+    }
+}
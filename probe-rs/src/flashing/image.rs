@@ -0,0 +1,169 @@
+use std::fmt::Write;
+use std::ops::Range;
+
+/// The contents of the flash as they would be programmed, including all fill bytes.
+///
+/// This is created with [layout_file] and can be used to inspect what would be written
+/// to the flash without touching the target.
+///
+/// [layout_file]: crate::flashing::layout_file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FlashImage {
+    /// The contiguous chunks of data which are programmed, sorted by their address.
+    pub chunks: Vec<(u32, Vec<u8>)>,
+    /// Address ranges which are erased, but not programmed.
+    pub erased_ranges: Vec<Range<u32>>,
+    /// Address ranges whose current contents are read from the flash before erasing
+    /// and programmed again afterwards.
+    ///
+    /// The chunks only contain the erased value for these bytes, as the real contents
+    /// are only known once the flash is read.
+    pub restored_ranges: Vec<Range<u32>>,
+}
+
+impl FlashImage {
+    /// Adds a chunk of data, merging it with the previous chunk if they are contiguous.
+    ///
+    /// Chunks have to be added in ascending order.
+    pub(super) fn add_chunk(&mut self, address: u32, data: &[u8]) {
+        if let Some((last_address, last_data)) = self.chunks.last_mut() {
+            if *last_address + last_data.len() as u32 == address {
+                last_data.extend_from_slice(data);
+                return;
+            }
+        }
+
+        self.chunks.push((address, data.to_vec()));
+    }
+
+    /// Returns the contents of the image in the Intel HEX format.
+    pub fn to_hex(&self) -> Result<String, ihex::WriterError> {
+        let mut records = vec![];
+
+        for (address, data) in &self.chunks {
+            let mut upper_address = None;
+
+            for (index, chunk) in data.chunks(16).enumerate() {
+                let chunk_address = address + index as u32 * 16;
+
+                if upper_address != Some(chunk_address >> 16) {
+                    upper_address = Some(chunk_address >> 16);
+                    records.push(ihex::Record::ExtendedLinearAddress(
+                        (chunk_address >> 16) as u16,
+                    ));
+                }
+
+                records.push(ihex::Record::Data {
+                    offset: chunk_address as u16,
+                    value: chunk.to_vec(),
+                });
+            }
+        }
+
+        records.push(ihex::Record::EndOfFile);
+
+        ihex::create_object_file_representation(&records)
+    }
+
+    /// Returns the contents of the image as a raw binary, starting at `base_address`.
+    ///
+    /// Gaps between the chunks are filled with `0xFF`.
+    /// Data below `base_address` is not part of the binary.
+    pub fn to_bin(&self, base_address: u32) -> Vec<u8> {
+        let end = self
+            .chunks
+            .iter()
+            .map(|(address, data)| address + data.len() as u32)
+            .max()
+            .unwrap_or(base_address)
+            .max(base_address);
+
+        let mut binary = vec![0xFF; (end - base_address) as usize];
+
+        for (address, data) in &self.chunks {
+            let skip = base_address.saturating_sub(*address) as usize;
+            if skip >= data.len() {
+                continue;
+            }
+
+            let offset = (address + skip as u32 - base_address) as usize;
+            binary[offset..offset + data.len() - skip].copy_from_slice(&data[skip..]);
+        }
+
+        binary
+    }
+
+    /// Returns a human readable report of the programmed, erased and restored ranges.
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+
+        let mut section = |title: &str, ranges: &mut dyn Iterator<Item = Range<u32>>| {
+            let _ = writeln!(report, "{}:", title);
+            let mut empty = true;
+            for range in ranges {
+                empty = false;
+                let _ = writeln!(
+                    report,
+                    "  {:#010x}..{:#010x} ({} bytes)",
+                    range.start,
+                    range.end,
+                    range.end - range.start
+                );
+            }
+            if empty {
+                let _ = writeln!(report, "  none");
+            }
+        };
+
+        section(
+            "Programmed",
+            &mut self
+                .chunks
+                .iter()
+                .map(|(address, data)| *address..address + data.len() as u32),
+        );
+        section(
+            "Erased but not programmed",
+            &mut self.erased_ranges.iter().cloned(),
+        );
+        section(
+            "Restored from the current flash contents",
+            &mut self.restored_ranges.iter().cloned(),
+        );
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FlashImage;
+
+    #[test]
+    fn contiguous_chunks_are_merged() {
+        let mut image = FlashImage::default();
+        image.add_chunk(0x1000, &[1; 4]);
+        image.add_chunk(0x1004, &[2; 4]);
+        image.add_chunk(0x2000, &[3; 4]);
+
+        assert_eq!(
+            image.chunks,
+            vec![
+                (0x1000, vec![1, 1, 1, 1, 2, 2, 2, 2]),
+                (0x2000, vec![3, 3, 3, 3])
+            ]
+        );
+    }
+
+    #[test]
+    fn binary_starts_at_base_address() {
+        let mut image = FlashImage::default();
+        image.add_chunk(0x1000, &[1; 4]);
+        image.add_chunk(0x1008, &[2; 2]);
+
+        assert_eq!(
+            image.to_bin(0x1002),
+            vec![1, 1, 0xFF, 0xFF, 0xFF, 0xFF, 2, 2]
+        );
+    }
+}
@@ -1,36 +1,275 @@
-use super::{FlashBuilder, FlashError, FlashProgress, Flasher};
-use crate::config::{MemoryRange, MemoryRegion, NvmRegion};
+use super::{
+    verify, AlgorithmDebug, CheckRoutine, FillPolicy, FlashBuilder, FlashError, FlashImage,
+    FlashProgress, Flasher, PreservePriority,
+};
+use crate::architecture::arm::core::register;
+use crate::asynchronous::Cancellation;
+use crate::config::{
+    BankSwap, EraseMode, FlashAlgorithm, MemoryRange, MemoryRegion, NvmRegion, RawFlashAlgorithm,
+    Target,
+};
+use crate::core::Architecture;
 use crate::memory::MemoryInterface;
 use crate::session::Session;
+use crate::{CoreRegisterAddress, Permissions};
 use anyhow::anyhow;
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::ops::Range;
+use std::time::Duration;
 
 struct RamWrite<'data> {
     address: u32,
     data: &'data [u8],
 }
 
+/// The registers of a core and the contents of the RAM which a flash algorithm overwrites.
+struct CoreSnapshot {
+    core_index: usize,
+    registers: Vec<(CoreRegisterAddress, u32)>,
+    ram_address: u32,
+    ram: Vec<u8>,
+}
+
+impl CoreSnapshot {
+    /// Halts the core which runs `flash_algorithm`, and saves its state.
+    fn save(session: &mut Session, flash_algorithm: &FlashAlgorithm) -> Result<Self, FlashError> {
+        // The algorithm uses the RAM from the start of the region with its stack, up to its last page buffer.
+        let ram_address = session
+            .target()
+            .memory_map
+            .iter()
+            .find_map(|region| match region {
+                MemoryRegion::Ram(ram) if ram.range.contains(&flash_algorithm.load_address) => {
+                    Some(ram.range.start)
+                }
+                _ => None,
+            })
+            .unwrap_or(flash_algorithm.load_address);
+        let code_end = flash_algorithm.load_address + 4 * flash_algorithm.instructions.len() as u32;
+        let ram_end = flash_algorithm
+            .page_buffers
+            .iter()
+            .map(|buffer| buffer + flash_algorithm.flash_properties.page_size)
+            .fold(code_end, u32::max);
+
+        let architecture = session.architecture();
+        let mut core = session
+            .core(flash_algorithm.core_index)
+            .map_err(FlashError::Memory)?;
+        core.halt(Duration::from_millis(100))
+            .map_err(FlashError::Core)?;
+
+        let mut addresses: Vec<CoreRegisterAddress> =
+            core.registers().registers().map(|r| r.address).collect();
+        if architecture == Architecture::Arm {
+            addresses.push(register::XPSR.address);
+        }
+
+        let registers = addresses
+            .into_iter()
+            .map(|address| Ok((address, core.read_core_reg(address)?)))
+            .collect::<Result<Vec<_>, crate::Error>>()
+            .map_err(FlashError::Core)?;
+
+        let mut ram = vec![0; (ram_end - ram_address) as usize];
+        core.read_8(ram_address, &mut ram)
+            .map_err(FlashError::Memory)?;
+
+        Ok(Self {
+            core_index: flash_algorithm.core_index,
+            registers,
+            ram_address,
+            ram,
+        })
+    }
+
+    /// Restores the saved state, and leaves the core halted.
+    fn restore(self, session: &mut Session) -> Result<(), FlashError> {
+        let mut core = session.core(self.core_index).map_err(FlashError::Memory)?;
+
+        core.write_8(self.ram_address, &self.ram)
+            .map_err(FlashError::Memory)?;
+        for (address, value) in self.registers {
+            core.write_core_reg(address, value)
+                .map_err(FlashError::Core)?;
+        }
+
+        Ok(())
+    }
+}
+
 /// `FlashLoader` is a struct which manages the flashing of any chunks of data onto any sections of flash.
 /// Use `add_data()` to add a chunks of data.
 /// Once you are done adding all your data, use `commit()` to flash the data.
 /// The flash loader will make sure to select the appropriate flash region for the right data chunks.
 /// Region crossing data chunks are allowed as long as the regions are contiguous.
 pub(super) struct FlashLoader<'mmap, 'data> {
-    memory_map: &'mmap [MemoryRegion],
+    memory_map: Cow<'mmap, [MemoryRegion]>,
+    custom_algorithms: Vec<RawFlashAlgorithm>,
     builders: HashMap<NvmRegion, FlashBuilder<'data>>,
     ram_write: Vec<RamWrite<'data>>,
-    keep_unwritten: bool,
+    fill_policy: FillPolicy,
+    skip_unchanged: bool,
+    verify: bool,
+    double_buffering: bool,
+    preserved_ranges: Vec<Range<u32>>,
+    preserve_priority: Option<PreservePriority>,
+    algorithm_debug: Option<AlgorithmDebug>,
+    retries: u32,
+    permissions: Permissions,
+    keep_core_state: bool,
+    cancellation: Option<Cancellation>,
+    /// The index of the image whose data is added, if several images are loaded together.
+    image: usize,
+    /// The address ranges of the data which was added, and the index of their image.
+    image_ranges: Vec<(Range<u32>, usize)>,
 }
 
 impl<'mmap, 'data> FlashLoader<'mmap, 'data> {
-    pub(super) fn new(memory_map: &'mmap [MemoryRegion], keep_unwritten: bool) -> Self {
+    pub(super) fn new(
+        memory_map: &'mmap [MemoryRegion],
+        fill_policy: FillPolicy,
+        skip_unchanged: bool,
+        verify: bool,
+        double_buffering: bool,
+    ) -> Self {
         Self {
-            memory_map,
+            memory_map: Cow::Borrowed(memory_map),
+            custom_algorithms: Vec::new(),
             builders: HashMap::new(),
             ram_write: Vec::new(),
-            keep_unwritten,
+            fill_policy,
+            skip_unchanged,
+            verify,
+            double_buffering,
+            preserved_ranges: Vec::new(),
+            preserve_priority: None,
+            algorithm_debug: None,
+            retries: 0,
+            permissions: Permissions::new(),
+            keep_core_state: false,
+            cancellation: None,
+            image: 0,
+            image_ranges: Vec::new(),
         }
     }
+
+    /// Enables debugging of the flash algorithms which are used to program the data.
+    pub(super) fn debug_algorithm(&mut self, debug: AlgorithmDebug) {
+        self.algorithm_debug = Some(debug);
+    }
+
+    /// Sets how often programming a region is retried after a transient failure.
+    pub(super) fn retry(&mut self, retries: u32) {
+        self.retries = retries;
+    }
+
+    /// Sets the permissions which are required to write to special regions, like one-time programmable ones.
+    pub(super) fn permissions(&mut self, permissions: &Permissions) {
+        self.permissions = permissions.clone();
+    }
+
+    /// Keeps the state of the core which runs the flash algorithm, instead of resetting it.
+    ///
+    /// The registers of the core and the RAM used by the flash algorithm are restored after programming.
+    pub(super) fn keep_core_state(&mut self) {
+        self.keep_core_state = true;
+    }
+
+    /// Stops erasing and programming at the next sector boundary once `cancellation` is cancelled.
+    pub(super) fn cancellation(&mut self, cancellation: &Cancellation) {
+        self.cancellation = Some(cancellation.clone());
+    }
+
+    /// Marks the data which is added from now on as part of the image with the given `index`.
+    ///
+    /// Data of different images must not overlap, which is reported with [FlashError::ImageOverlap].
+    pub(super) fn begin_image(&mut self, index: usize) {
+        self.image = index;
+    }
+
+    /// Sets the address ranges whose current flash contents have to be preserved.
+    ///
+    /// This has to be called before any data is added.
+    pub(super) fn preserve(&mut self, ranges: &[Range<u32>], priority: Option<PreservePriority>) {
+        self.preserved_ranges = ranges.to_vec();
+        self.preserve_priority = priority;
+    }
+
+    /// Adds a flash algorithm which is used instead of the algorithms of `target`
+    /// for all regions in its address range.
+    ///
+    /// If the memory map has no region at the address range of the algorithm,
+    /// e.g. for an external flash, a flash region is added for it.
+    /// This has to be called before any data is added.
+    pub(super) fn add_algorithm(
+        &mut self,
+        algorithm: RawFlashAlgorithm,
+        target: &Target,
+    ) -> Result<(), FlashError> {
+        let range = algorithm.flash_properties.address_range.clone();
+
+        let available = self
+            .memory_map
+            .iter()
+            .find_map(|region| match region {
+                MemoryRegion::Ram(ram) if ram.core_index == algorithm.core_index => {
+                    Some(ram.range.end - ram.range.start)
+                }
+                _ => None,
+            })
+            .ok_or_else(|| {
+                anyhow!(
+                    "No RAM defined for core {} of the chip.",
+                    algorithm.core_index
+                )
+            })?;
+        let required = algorithm.required_ram(target.architecture());
+
+        if required > available {
+            return Err(FlashError::AlgorithmDoesNotFitInRam {
+                name: algorithm.name.into_owned(),
+                required,
+                available,
+            });
+        }
+
+        for replaced in target
+            .flash_algorithms
+            .iter()
+            .filter(|fa| fa.flash_properties.address_range.intersects_range(&range))
+        {
+            log::warn!(
+                "The flash algorithm '{}' is used instead of '{}' for {:#010x}..{:#010x}.",
+                algorithm.name,
+                replaced.name,
+                range.start,
+                range.end
+            );
+        }
+
+        let mapped = self.memory_map.iter().any(|region| match region {
+            MemoryRegion::Ram(r) => r.range.intersects_range(&range),
+            MemoryRegion::Nvm(r) => r.range.intersects_range(&range),
+            MemoryRegion::Generic(r) => r.range.intersects_range(&range),
+        });
+        if !mapped {
+            self.memory_map.to_mut().push(MemoryRegion::Nvm(NvmRegion {
+                range,
+                is_boot_memory: false,
+                is_otp: false,
+                erase_mode: EraseMode::Sector,
+                sectors: Cow::Borrowed(&[]),
+                memory_mapped: None,
+            }));
+        }
+
+        self.custom_algorithms.push(algorithm);
+
+        Ok(())
+    }
+
     /// Stages a chunk of data to be programmed.
     ///
     /// The chunk can cross flash boundaries as long as one flash region connects to another flash region.
@@ -40,26 +279,43 @@ impl<'mmap, 'data> FlashLoader<'mmap, 'data> {
         data: &'data [u8],
     ) -> Result<(), FlashError> {
         let size = data.len();
+        let range = address..address.saturating_add(size as u32);
+        if let Some((other, image)) = self.image_ranges.iter().find(|(other, image)| {
+            *image != self.image && size > 0 && other.intersects_range(&range)
+        }) {
+            return Err(FlashError::ImageOverlap {
+                first: *image,
+                second: self.image,
+                start: u32::max(other.start, range.start),
+                end: u32::min(other.end, range.end),
+            });
+        }
+        self.image_ranges.push((range, self.image));
+
         let mut remaining = size;
         while remaining > 0 {
             // Get the flash region in with this chunk of data starts.
-            let possible_region = Self::get_region_for_address(self.memory_map, address);
+            let possible_region = Self::get_region_for_address(&self.memory_map, address);
             // If we found a corresponding region, create a builder.
             match possible_region {
                 Some(MemoryRegion::Nvm(region)) => {
                     // Get our builder instance.
                     if !self.builders.contains_key(region) {
-                        self.builders.insert(region.clone(), FlashBuilder::new());
+                        let mut builder = FlashBuilder::new();
+                        builder.preserve(&self.preserved_ranges, self.preserve_priority);
+                        self.builders.insert(region.clone(), builder);
                     };
 
                     // Determine how much more data can be contained by this region.
                     let program_length =
-                        usize::min(remaining, (region.range.end - address + 1) as usize);
+                        usize::min(remaining, (region.range.end - address) as usize);
 
                     // Add as much data to the builder as can be contained by this region.
+                    let offset = size - remaining;
                     self.builders
-                        .get_mut(&region)
-                        .map(|r| r.add_data(address, &data[size - remaining..program_length]));
+                        .get_mut(region)
+                        .unwrap()
+                        .add_data(address, &data[offset..offset + program_length])?;
 
                     // Advance the cursors.
                     remaining -= program_length;
@@ -68,10 +324,11 @@ impl<'mmap, 'data> FlashLoader<'mmap, 'data> {
                 Some(MemoryRegion::Ram(region)) => {
                     // Determine how much more data can be contained by this region.
                     let program_length =
-                        usize::min(remaining, (region.range.end - address + 1) as usize);
+                        usize::min(remaining, (region.range.end - address) as usize);
 
                     // Add data to be written to the vector.
-                    let data = &data[size - remaining..program_length];
+                    let offset = size - remaining;
+                    let data = &data[offset..offset + program_length];
                     self.ram_write.push(RamWrite { address, data });
 
                     // Advance the cursors.
@@ -106,6 +363,146 @@ impl<'mmap, 'data> FlashLoader<'mmap, 'data> {
         None
     }
 
+    /// Finds the flash algorithm for `region` and assembles it.
+    ///
+    /// The `custom_algorithms` are preferred over the algorithms of `target`.
+    pub(super) fn flash_algorithm_for_region(
+        target: &Target,
+        custom_algorithms: &[RawFlashAlgorithm],
+        region: &NvmRegion,
+    ) -> Result<FlashAlgorithm, FlashError> {
+        // Try to find a flash algorithm for the range of the current builder
+        for algorithm in &target.flash_algorithms {
+            log::debug!(
+                "Algorithm {} - start: {:#08x} - size: {:#08x}",
+                algorithm.name,
+                algorithm.flash_properties.address_range.start,
+                algorithm.flash_properties.address_range.end
+                    - algorithm.flash_properties.address_range.start
+            );
+        }
+
+        let custom_algorithm = custom_algorithms.iter().rev().find(|fa| {
+            fa.flash_properties
+                .address_range
+                .contains_range(&region.range)
+        });
+
+        let algorithms = target
+            .flash_algorithms
+            .iter()
+            .filter(|fa| {
+                fa.flash_properties
+                    .address_range
+                    .contains_range(&region.range)
+            })
+            .collect::<Vec<_>>();
+
+        log::debug!("Algorithms: {:?}", &algorithms);
+
+        let raw_flash_algorithm = match (custom_algorithm, algorithms.len()) {
+            (Some(custom_algorithm), _) => custom_algorithm,
+            (None, 0) => {
+                return Err(FlashError::NoFlashLoaderAlgorithmAttached);
+            }
+            (None, 1) => algorithms[0],
+            (None, _) => algorithms
+                .iter()
+                .find(|a| a.default)
+                .ok_or(FlashError::NoFlashLoaderAlgorithmAttached)?,
+        };
+
+        // The algorithm runs in the RAM of the core which executes it.
+        let core_index = raw_flash_algorithm.core_index;
+        let ram = target
+            .memory_map
+            .iter()
+            .find_map(|mm| match mm {
+                MemoryRegion::Ram(ram) if ram.core_index == core_index => Some(ram),
+                _ => None,
+            })
+            .ok_or_else(|| anyhow!("No RAM defined for core {} of the chip.", core_index))?;
+
+        region
+            .validate_sectors()
+            .map_err(|reason| FlashError::InvalidSectors {
+                start: region.range.start,
+                end: region.range.end,
+                reason,
+            })?;
+
+        let mut flash_algorithm = raw_flash_algorithm.assemble(ram, target.architecture())?;
+        flash_algorithm.use_sectors_of_region(region);
+
+        Ok(flash_algorithm)
+    }
+
+    /// Lays out all the stored data chunks like [commit] would, without accessing the target.
+    ///
+    /// The returned image contains the data of all pages which would be programmed,
+    /// including the fill bytes, and the ranges which would be erased or restored.
+    ///
+    /// [commit]: FlashLoader::commit
+    pub(super) fn to_image(&self, target: &Target) -> Result<FlashImage, FlashError> {
+        let mut builders = self.builders.iter().collect::<Vec<_>>();
+        builders.sort_by_key(|(region, _)| region.range.start);
+
+        let mut image = FlashImage::default();
+
+        for (region, builder) in builders {
+            let flash_algorithm =
+                Self::flash_algorithm_for_region(target, &self.custom_algorithms, region)?;
+
+            // Regions which are not erased keep the current contents of all unwritten bytes.
+            let fill_policy = if region.is_sector_erasable() {
+                self.fill_policy
+            } else {
+                FillPolicy::ReadBack
+            };
+            let flash_layout = builder.build_layout(&flash_algorithm, fill_policy)?;
+
+            let mut pages = flash_layout.pages().iter().collect::<Vec<_>>();
+            pages.sort_by_key(|page| page.address());
+
+            for page in &pages {
+                image.add_chunk(page.address(), page.data());
+            }
+
+            for sector in flash_layout.sectors() {
+                let mut start = sector.address();
+                let end = sector.address() + sector.size();
+
+                for page in &pages {
+                    let page_end = page.address() + page.size();
+                    if page_end <= start || page.address() >= end {
+                        continue;
+                    }
+                    if page.address() > start {
+                        image.erased_ranges.push(start..page.address());
+                    }
+                    start = page_end;
+                }
+
+                if start < end {
+                    image.erased_ranges.push(start..end);
+                }
+            }
+
+            if fill_policy == FillPolicy::ReadBack || builder.has_preserved_ranges() {
+                image.restored_ranges.extend(
+                    flash_layout
+                        .fills()
+                        .iter()
+                        .map(|fill| fill.address()..fill.address() + fill.size()),
+                );
+            }
+        }
+
+        image.restored_ranges.sort_by_key(|range| range.start);
+
+        Ok(image)
+    }
+
     /// Writes all the stored data chunks to flash.
     ///
     /// Requires a session with an attached target that has a known flash algorithm.
@@ -117,79 +514,674 @@ impl<'mmap, 'data> FlashLoader<'mmap, 'data> {
         progress: &FlashProgress,
         do_chip_erase: bool,
     ) -> Result<(), FlashError> {
+        let progress = &progress.with_events(session.event_bus().clone());
+
+        // Nothing is written unless all one-time programmable regions may be written.
+        for region in self.builders.keys() {
+            if region.is_otp && self.permissions.otp_write().is_err() {
+                return Err(FlashError::OtpWriteNotAllowed {
+                    start: region.range.start,
+                    end: region.range.end,
+                });
+            }
+        }
+
+        // While the banks are swapped, the data is programmed into the bank which appears at its address.
+        if let Some(bank_swap) = session.target().bank_swap.clone() {
+            let value = session
+                .core(0)
+                .and_then(|mut core| core.read_word_32(bank_swap.swapped.address))
+                .map_err(FlashError::Memory)?;
+
+            if bank_swap.is_swapped(value) {
+                log::info!("The flash banks are swapped.");
+
+                let regions = self
+                    .builders
+                    .keys()
+                    .filter(|region| region.is_boot_memory)
+                    .cloned()
+                    .collect::<Vec<_>>();
+                for region in regions {
+                    self.swap_banks(&region)?;
+                }
+            }
+        }
+
+        // Regions which are only erased together with the complete chip are programmed last,
+        // so a chip erase of another region does not erase them again.
+        let mut builders = self.builders.iter().collect::<Vec<_>>();
+        builders.sort_by_key(|(region, _)| (!region.is_sector_erasable(), region.range.start));
+
+        // Regions which share a flash algorithm, like the banks of a flash with a single controller,
+        // must only be erased once with a chip erase, it would erase the banks programmed before.
+        let mut chip_erased = vec![];
+
+        // The cores other than the first one which ran a flash algorithm, and have to be restarted.
+        let mut secondary_cores = vec![];
+
         // Iterate over builders we've created and program the data.
-        for (region, builder) in &self.builders {
+        // The regions are programmed in sequence, each one with its own instance of the flash algorithm.
+        for (region, builder) in builders {
             log::debug!(
                 "Using builder for region (0x{:08x}..0x{:08x})",
                 region.range.start,
                 region.range.end
             );
 
-            // Try to find a flash algorithm for the range of the current builder
-            for algorithm in session.flash_algorithms() {
-                log::debug!(
-                    "Algorithm {} - start: {:#08x} - size: {:#08x}",
-                    algorithm.name,
-                    algorithm.flash_properties.address_range.start,
-                    algorithm.flash_properties.address_range.end
-                        - algorithm.flash_properties.address_range.start
-                );
-            }
+            let flash_algorithm = Self::flash_algorithm_for_region(
+                session.target(),
+                &self.custom_algorithms,
+                region,
+            )?;
 
-            let algorithms = session.flash_algorithms();
-            let algorithms = algorithms
-                .iter()
-                .filter(|fa| {
-                    fa.flash_properties
-                        .address_range
-                        .contains_range(&region.range)
-                })
-                .collect::<Vec<_>>();
+            if flash_algorithm.core_index != 0
+                && !secondary_cores.contains(&flash_algorithm.core_index)
+            {
+                secondary_cores.push(flash_algorithm.core_index);
+            }
 
-            log::debug!("Algorithms: {:?}", &algorithms);
+            let chip_erase = do_chip_erase && !chip_erased.contains(&flash_algorithm.name);
+            if chip_erase {
+                chip_erased.push(flash_algorithm.name.clone());
+            }
 
-            let raw_flash_algorithm = match algorithms.len() {
-                0 => {
-                    return Err(FlashError::NoFlashLoaderAlgorithmAttached);
-                }
-                1 => &algorithms[0],
-                _ => algorithms
-                    .iter()
-                    .find(|a| a.default)
-                    .ok_or(FlashError::NoFlashLoaderAlgorithmAttached)?,
+            // The flash algorithm overwrites the registers of the core and a part of the RAM.
+            let snapshot = if self.keep_core_state {
+                Some(CoreSnapshot::save(session, &flash_algorithm)?)
+            } else {
+                None
             };
 
-            let mm = &session.target().memory_map;
-            let ram = mm
-                .iter()
-                .find_map(|mm| match mm {
-                    MemoryRegion::Ram(ram) => Some(ram),
-                    _ => None,
-                })
-                .ok_or_else(|| anyhow!("No RAM defined for chip."))?;
+            let result = self.program_region(
+                session,
+                region,
+                builder,
+                flash_algorithm,
+                chip_erase,
+                progress,
+            );
 
-            let flash_algorithm = raw_flash_algorithm.assemble(ram, session.architecture())?;
+            match snapshot {
+                // The state is restored even if programming failed.
+                Some(snapshot) => {
+                    let restored = snapshot.restore(session);
+                    result?;
+                    restored?;
+                }
+                None => result?,
+            }
+        }
 
-            // Program the data.
-            let mut flasher = Flasher::new(session, flash_algorithm, region.clone());
-            flasher.program(builder, do_chip_erase, self.keep_unwritten, false, progress)?
+        // The flash algorithms left the cores halted. The first core is left to the caller,
+        // the others are reset here, so they run the programmed firmware.
+        if !self.keep_core_state {
+            for core_index in secondary_cores {
+                log::debug!("Resetting core {}", core_index);
+                session
+                    .core(core_index)
+                    .and_then(|mut core| core.reset())
+                    .map_err(FlashError::Core)?;
+            }
         }
 
         // Write data to ram.
-
-        // Attach to memory and core.
-        let mut core = session.core(0).map_err(FlashError::Memory)?;
-
         for RamWrite { address, data } in &self.ram_write {
             log::info!(
                 "Ram write program data @ {:X} {} bytes",
                 *address,
                 data.len()
             );
+
+            // The RAM of a core might only be accessible through that core.
+            let core_index = self
+                .memory_map
+                .iter()
+                .find_map(|region| match region {
+                    MemoryRegion::Ram(ram) if ram.range.contains(address) => Some(ram.core_index),
+                    _ => None,
+                })
+                .unwrap_or(0);
+
             // Write data to memory.
+            let mut core = session.core(core_index).map_err(FlashError::Memory)?;
             core.write_8(*address, data).map_err(FlashError::Memory)?;
         }
 
         Ok(())
     }
+
+    /// Compares the stored data chunks with the flash contents, without programming anything,
+    /// and returns the ranges which differ.
+    ///
+    /// The `Verify()` routine of the flash algorithms is used where it is available, otherwise the
+    /// flash is read back while the core keeps running. The core is only halted while a routine runs,
+    /// and its state is restored afterwards.
+    pub(super) fn verify(
+        &self,
+        session: &mut Session,
+        progress: &FlashProgress,
+    ) -> Result<Vec<Range<u32>>, FlashError> {
+        let mut builders = self.builders.iter().collect::<Vec<_>>();
+        builders.sort_by_key(|(region, _)| region.range.start);
+
+        let mut differing = vec![];
+
+        for (region, builder) in builders {
+            let flash_algorithm = Self::flash_algorithm_for_region(
+                session.target(),
+                &self.custom_algorithms,
+                region,
+            )?;
+            let expected = builder.expected_data();
+
+            if flash_algorithm.pc_verify.is_some() {
+                differing.extend(self.check_halted(
+                    session,
+                    region,
+                    flash_algorithm,
+                    &expected,
+                    CheckRoutine::Verify,
+                    progress,
+                )?);
+            } else {
+                log::debug!("The flash algorithm has no Verify routine, reading back the flash.");
+                differing.extend(expected);
+            }
+        }
+
+        if !self.ram_write.is_empty() {
+            log::debug!("Data for the RAM is not verified.");
+        }
+
+        // The pieces which the routines could not confirm are read back to find the differing bytes.
+        verify::compare(session, &differing, Some(progress))
+    }
+
+    /// Checks that the given memory `ranges` are erased, and returns the ranges which are not.
+    ///
+    /// The `BlankCheck()` routine of the flash algorithms is used where it is available, otherwise the
+    /// flash is read back while the core keeps running.
+    pub(super) fn blank_check(
+        &self,
+        session: &mut Session,
+        ranges: &[Range<u32>],
+        progress: &FlashProgress,
+    ) -> Result<Vec<Range<u32>>, FlashError> {
+        let mut erased = vec![];
+
+        for range in ranges {
+            let mut covered = 0;
+
+            for region in self.memory_map.iter() {
+                let region = match region {
+                    MemoryRegion::Nvm(region) if region.range.intersects_range(range) => region,
+                    _ => continue,
+                };
+                let start = range.start.max(region.range.start);
+                let end = range.end.min(region.range.end);
+                covered += end - start;
+
+                let flash_algorithm = Self::flash_algorithm_for_region(
+                    session.target(),
+                    &self.custom_algorithms,
+                    region,
+                )?;
+                let erased_byte_value = flash_algorithm.flash_properties.erased_byte_value;
+                let has_routine = flash_algorithm.pc_blank_check.is_some();
+
+                erased.push((
+                    region,
+                    flash_algorithm,
+                    start,
+                    vec![erased_byte_value; (end - start) as usize],
+                    has_routine,
+                ));
+            }
+
+            if covered < range.end - range.start {
+                return Err(FlashError::NoSuitableNvm {
+                    start: range.start,
+                    end: range.end,
+                });
+            }
+        }
+
+        let mut differing = vec![];
+
+        for (region, flash_algorithm, start, data, has_routine) in &erased {
+            let chunk = [(*start, &data[..])];
+
+            if *has_routine {
+                differing.extend(self.check_halted(
+                    session,
+                    region,
+                    flash_algorithm.clone(),
+                    &chunk,
+                    CheckRoutine::BlankCheck,
+                    progress,
+                )?);
+            } else {
+                log::debug!(
+                    "The flash algorithm has no BlankCheck routine, reading back the flash."
+                );
+                differing.extend_from_slice(&chunk);
+            }
+        }
+
+        verify::compare(session, &differing, Some(progress))
+    }
+
+    /// Checks the `chunks` of `region` with a `routine` of `flash_algorithm`, and returns the parts which differ.
+    ///
+    /// The core which runs the flash algorithm is only halted, not reset, and its state is restored afterwards.
+    /// If it was running before, it is resumed.
+    fn check_halted<'chunk>(
+        &self,
+        session: &mut Session,
+        region: &NvmRegion,
+        flash_algorithm: FlashAlgorithm,
+        chunks: &[(u32, &'chunk [u8])],
+        routine: CheckRoutine,
+        progress: &FlashProgress,
+    ) -> Result<Vec<(u32, &'chunk [u8])>, FlashError> {
+        let core_index = flash_algorithm.core_index;
+        let was_running = !session
+            .core(core_index)
+            .and_then(|mut core| core.core_halted())
+            .map_err(FlashError::Core)?;
+
+        let snapshot = CoreSnapshot::save(session, &flash_algorithm)?;
+
+        let result = {
+            let mut flasher = Flasher::new(session, flash_algorithm, region.clone());
+            flasher.keep_core_state();
+            if let Some(debug) = &self.algorithm_debug {
+                flasher.debug_algorithm(debug.clone());
+            }
+
+            flasher
+                .check(chunks, routine, progress)
+                .map_err(FlashError::from_anyhow)
+        };
+
+        // The state is restored even if the check failed.
+        let restored = snapshot.restore(session);
+        let differing = result?;
+        restored?;
+
+        if was_running {
+            session
+                .core(core_index)
+                .and_then(|mut core| core.run())
+                .map_err(FlashError::Core)?;
+        }
+
+        Ok(differing)
+    }
+
+    /// Moves the data of the boot memory `region` to the addresses in the unswapped flash,
+    /// at which it appears while the banks are swapped, see [BankSwap].
+    fn swap_banks(&mut self, region: &NvmRegion) -> Result<(), FlashError> {
+        let builder = match self.builders.remove(region) {
+            Some(builder) => builder,
+            None => return Ok(()),
+        };
+
+        let flash = &region.range;
+        let middle = flash.start + (flash.end - flash.start) / 2;
+
+        // Ranges which cross the middle of the flash end up at both ends of the flash.
+        let split = |range: Range<u32>| {
+            if range.start < middle && range.end > middle {
+                vec![range.start..middle, middle..range.end]
+            } else {
+                vec![range]
+            }
+        };
+        let unswapped = |range: Range<u32>| {
+            let start = BankSwap::unswapped_address(flash, range.start);
+            start..start + (range.end - range.start)
+        };
+
+        let preserved_ranges = self
+            .preserved_ranges
+            .iter()
+            .flat_map(|range| split(range.clone()))
+            .map(unswapped)
+            .collect::<Vec<_>>();
+
+        let mut swapped = FlashBuilder::new();
+        swapped.preserve(&preserved_ranges, self.preserve_priority);
+
+        let mut second_bank: Option<Range<u32>> = None;
+        for (address, data) in builder.data_blocks() {
+            for part in split(address..address + data.len() as u32) {
+                if part.start >= middle {
+                    second_bank = Some(match second_bank {
+                        Some(range) => range.start.min(part.start)..range.end.max(part.end),
+                        None => part.clone(),
+                    });
+                }
+
+                let data = &data[(part.start - address) as usize..(part.end - address) as usize];
+                swapped.add_data(unswapped(part).start, data)?;
+            }
+        }
+
+        if let Some(range) = second_bank {
+            log::warn!(
+                "The image contains data for {:#010x}..{:#010x}, where the inactive bank appears while the banks are swapped. \
+                The chip does not boot from this data, it was probably built for the other bank.",
+                range.start,
+                range.end
+            );
+        }
+
+        self.builders.insert(region.clone(), swapped);
+
+        Ok(())
+    }
+
+    /// Programs the data of `builder` into `region` with `flash_algorithm`.
+    fn program_region(
+        &self,
+        session: &mut Session,
+        region: &NvmRegion,
+        builder: &FlashBuilder<'data>,
+        flash_algorithm: FlashAlgorithm,
+        chip_erase: bool,
+        progress: &FlashProgress,
+    ) -> Result<(), FlashError> {
+        // Program the data.
+        let mut flasher = Flasher::new(session, flash_algorithm, region.clone());
+        if let Some(debug) = &self.algorithm_debug {
+            flasher.debug_algorithm(debug.clone());
+        }
+        if self.keep_core_state {
+            flasher.keep_core_state();
+        }
+        if let Some(cancellation) = &self.cancellation {
+            flasher.cancel_with(cancellation.clone());
+        }
+
+        // Once a sector is erased, its old contents can not be read back again.
+        let retries = if self.fill_policy == FillPolicy::ReadBack || builder.has_preserved_ranges()
+        {
+            0
+        } else {
+            self.retries
+        };
+
+        let mut attempt = 0;
+        loop {
+            let result = flasher
+                .program(
+                    builder,
+                    chip_erase,
+                    self.fill_policy,
+                    self.double_buffering,
+                    self.skip_unchanged,
+                    progress,
+                )
+                .map_err(FlashError::from_anyhow);
+
+            match result {
+                Err(FlashError::OperationFailed { failure, .. })
+                    if failure.is_transient() && attempt < retries =>
+                {
+                    attempt += 1;
+                    log::warn!("{} Retrying ({}/{}).", failure, attempt, retries);
+
+                    // Sectors which are completely programmed are not touched again.
+                    if failure.resume_address.is_some() {
+                        flasher.resume_from(failure.resume_address);
+                    }
+                }
+                result => break result?,
+            }
+        }
+
+        if self.verify {
+            flasher
+                .verify(builder, progress)
+                .map_err(FlashError::from_anyhow)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{FlashProperties, RamRegion};
+    use crate::{CoreType, ResetType};
+
+    fn bank(range: Range<u32>) -> NvmRegion {
+        NvmRegion {
+            range,
+            is_boot_memory: false,
+            is_otp: false,
+            erase_mode: EraseMode::Sector,
+            sectors: Cow::Borrowed(&[]),
+            memory_mapped: None,
+        }
+    }
+
+    #[test]
+    fn data_crossing_two_banks() {
+        let memory_map = [
+            MemoryRegion::Nvm(bank(0x0800_0000..0x0810_0000)),
+            MemoryRegion::Nvm(bank(0x0810_0000..0x0820_0000)),
+        ];
+
+        let data = (0..0x200).map(|i| i as u8).collect::<Vec<_>>();
+
+        let mut loader = FlashLoader::new(&memory_map, FillPolicy::default(), false, false, false);
+        loader.add_data(0x080F_FF00, &data).unwrap();
+
+        let first = &loader.builders[&bank(0x0800_0000..0x0810_0000)];
+        assert_eq!(first.expected_data(), vec![(0x080F_FF00, &data[..0x100])]);
+
+        let second = &loader.builders[&bank(0x0810_0000..0x0820_0000)];
+        assert_eq!(second.expected_data(), vec![(0x0810_0000, &data[0x100..])]);
+    }
+
+    #[test]
+    fn data_crossing_into_ram() {
+        let memory_map = [
+            MemoryRegion::Nvm(bank(0x0800_0000..0x0800_1000)),
+            MemoryRegion::Ram(RamRegion {
+                range: 0x0800_1000..0x0800_2000,
+                is_boot_memory: false,
+                core_index: 0,
+            }),
+        ];
+
+        let data = [0x55; 0x20];
+
+        let mut loader = FlashLoader::new(&memory_map, FillPolicy::default(), false, false, false);
+        loader.add_data(0x0800_0ff0, &data).unwrap();
+
+        assert_eq!(loader.ram_write.len(), 1);
+        assert_eq!(loader.ram_write[0].address, 0x0800_1000);
+        assert_eq!(loader.ram_write[0].data.len(), 0x10);
+    }
+
+    #[test]
+    fn overlapping_images() {
+        let memory_map = [MemoryRegion::Nvm(bank(0x0800_0000..0x0810_0000))];
+
+        let data = [0x55; 0x100];
+
+        let mut loader = FlashLoader::new(&memory_map, FillPolicy::default(), false, false, false);
+        loader.add_data(0x0800_0000, &data).unwrap();
+        loader.begin_image(1);
+        loader.add_data(0x0800_0100, &data).unwrap();
+        loader.begin_image(2);
+
+        match loader.add_data(0x0800_01c0, &data) {
+            Err(FlashError::ImageOverlap {
+                first,
+                second,
+                start,
+                end,
+            }) => {
+                assert_eq!((first, second), (1, 2));
+                assert_eq!(start..end, 0x0800_01c0..0x0800_0200);
+            }
+            other => panic!("The overlap was not detected: {:?}", other.err()),
+        }
+    }
+
+    fn target(memory_map: Vec<MemoryRegion>) -> Target {
+        Target {
+            name: "test".to_string(),
+            flash_algorithms: vec![],
+            core_type: CoreType::M4,
+            memory_map,
+            cores: vec![],
+            unlock_sequence: None,
+            option_bytes: None,
+            recommended_speed_khz: None,
+            debug_vars: None,
+            debug_sequences: vec![],
+            reset_types: vec![ResetType::SystemReset],
+            default_reset_type: ResetType::SystemReset,
+            watchdog_freeze: None,
+            debug_freeze: None,
+            low_power_debug: None,
+            bank_swap: None,
+            core_clock: None,
+        }
+    }
+
+    fn algorithm(range: Range<u32>, code_size: usize) -> RawFlashAlgorithm {
+        RawFlashAlgorithm {
+            name: "qspi".into(),
+            instructions: vec![0; code_size].into(),
+            flash_properties: FlashProperties {
+                address_range: range,
+                page_size: 0x100,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn custom_algorithm_adds_region() {
+        let target = target(vec![
+            MemoryRegion::Nvm(bank(0x0800_0000..0x0810_0000)),
+            MemoryRegion::Ram(RamRegion {
+                range: 0x2000_0000..0x2000_4000,
+                is_boot_memory: false,
+                core_index: 0,
+            }),
+        ]);
+
+        let mut loader = FlashLoader::new(
+            &target.memory_map,
+            FillPolicy::default(),
+            false,
+            false,
+            false,
+        );
+        loader
+            .add_algorithm(algorithm(0x9000_0000..0x9100_0000, 0x400), &target)
+            .unwrap();
+        loader.add_data(0x9000_0000, &[0x55; 0x10]).unwrap();
+
+        let region = bank(0x9000_0000..0x9100_0000);
+        assert!(loader.builders.contains_key(&region));
+
+        let flash_algorithm =
+            FlashLoader::flash_algorithm_for_region(&target, &loader.custom_algorithms, &region)
+                .unwrap();
+        assert_eq!(flash_algorithm.name, "qspi");
+    }
+
+    #[test]
+    fn custom_algorithm_too_large_for_ram() {
+        let target = target(vec![MemoryRegion::Ram(RamRegion {
+            range: 0x2000_0000..0x2000_1000,
+            is_boot_memory: false,
+            core_index: 0,
+        })]);
+
+        let mut loader = FlashLoader::new(
+            &target.memory_map,
+            FillPolicy::default(),
+            false,
+            false,
+            false,
+        );
+        let result = loader.add_algorithm(algorithm(0x9000_0000..0x9100_0000, 0x1000), &target);
+
+        assert!(matches!(
+            result,
+            Err(FlashError::AlgorithmDoesNotFitInRam {
+                required: 0x1160,
+                available: 0x1000,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn algorithm_runs_in_the_ram_of_its_core() {
+        let mut target = target(vec![
+            MemoryRegion::Nvm(bank(0x0100_0000..0x0104_0000)),
+            MemoryRegion::Ram(RamRegion {
+                range: 0x2000_0000..0x2008_0000,
+                is_boot_memory: false,
+                core_index: 0,
+            }),
+            MemoryRegion::Ram(RamRegion {
+                range: 0x2100_0000..0x2101_0000,
+                is_boot_memory: false,
+                core_index: 1,
+            }),
+        ]);
+        target.flash_algorithms.push(RawFlashAlgorithm {
+            core_index: 1,
+            ..algorithm(0x0100_0000..0x0104_0000, 0x100)
+        });
+
+        let flash_algorithm =
+            FlashLoader::flash_algorithm_for_region(&target, &[], &bank(0x0100_0000..0x0104_0000))
+                .unwrap();
+
+        assert_eq!(flash_algorithm.core_index, 1);
+        assert!((0x2100_0000..0x2101_0000).contains(&flash_algorithm.load_address));
+    }
+
+    #[test]
+    fn data_is_moved_to_the_other_bank() {
+        let memory_map = [MemoryRegion::Nvm(NvmRegion {
+            is_boot_memory: true,
+            ..bank(0x0800_0000..0x0810_0000)
+        })];
+        let region = match &memory_map[0] {
+            MemoryRegion::Nvm(region) => region.clone(),
+            _ => unreachable!(),
+        };
+
+        let data = [0x55; 0x10];
+
+        let mut loader = FlashLoader::new(&memory_map, FillPolicy::default(), false, false, false);
+        loader.add_data(0x0800_0000, &data).unwrap();
+        loader.add_data(0x0807_fff8, &data).unwrap();
+        loader.swap_banks(&region).unwrap();
+
+        let blocks = loader.builders[&region]
+            .data_blocks()
+            .map(|(address, data)| (address, data.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            blocks,
+            vec![(0x0800_0000, 8), (0x0808_0000, 0x10), (0x080f_fff8, 8)]
+        );
+    }
 }
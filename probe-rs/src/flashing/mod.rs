@@ -2,23 +2,36 @@
 
 //! Flash programming operations.
 //!
-//! This modules provides a means to do flash unlocking, erasing and programming.
+//! This modules provides a means to do flash unlocking, erasing and programming,
+//! as well as reading back the flash contents.
 //!
-//! It provides a convenient highlevel interface that can flash an ELF, IHEX or BIN file
+//! It provides a convenient highlevel interface that can flash an ELF, IHEX, UF2 or BIN file
 //! as well as a lower level block based interface.
 
 mod builder;
 mod download;
+mod elf_algorithm;
+mod erase;
 mod error;
 mod flasher;
+mod image;
 mod loader;
 mod progress;
+mod read;
+mod uf2;
+mod verify;
 mod visualizer;
 
 use builder::*;
 pub use download::*;
+pub use elf_algorithm::FlashAlgorithmProperties;
+pub use erase::*;
 pub use error::*;
 pub use flasher::*;
+pub use image::FlashImage;
 use loader::*;
 pub use progress::*;
+pub use read::*;
+pub use uf2::Uf2Error;
+pub use verify::blank_check;
 pub use visualizer::*;
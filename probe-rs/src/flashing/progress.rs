@@ -1,5 +1,6 @@
 use super::FlashLayout;
-use std::time::Duration;
+use crate::events::{EventBus, SessionEvent};
+use std::{rc::Rc, sync::mpsc::Sender, time::Duration};
 
 /// A structure to manage the flashing procedure progress reporting.
 ///
@@ -14,20 +15,64 @@ use std::time::Duration;
 /// // Print events
 /// let progress = FlashProgress::new(|event| println!("Event: {:#?}", event));
 /// ```
+///
+/// The events can also be sent to another thread:
+///
+/// ```
+/// use probe_rs::flashing::FlashProgress;
+/// use std::sync::mpsc::channel;
+///
+/// let (sender, receiver) = channel();
+/// let progress = FlashProgress::from_sender(sender);
+///
+/// std::thread::spawn(move || {
+///     for event in receiver {
+///         println!("Event: {:#?}", event);
+///     }
+/// });
+/// ```
 pub struct FlashProgress {
-    handler: Box<dyn Fn(ProgressEvent)>,
+    handler: Rc<dyn Fn(ProgressEvent)>,
+    /// The subscribers of the session, which get the events as well.
+    events: Option<EventBus>,
 }
 
 impl FlashProgress {
     /// Create a new `FlashProgress` structure with a given `handler` to be called on events.
     pub fn new(handler: impl Fn(ProgressEvent) + 'static) -> Self {
         Self {
-            handler: Box::new(handler),
+            handler: Rc::new(handler),
+            events: None,
+        }
+    }
+
+    /// Create a new `FlashProgress` structure which sends all events to the given `sender`.
+    ///
+    /// This is useful if the events should be processed on another thread.
+    /// Events are silently dropped once the receiving end has been disconnected.
+    pub fn from_sender(sender: Sender<ProgressEvent>) -> Self {
+        Self::new(move |event| {
+            let _ = sender.send(event);
+        })
+    }
+
+    /// Returns a reporter with the same handler, which also sends the events to the subscribers of a session.
+    pub(super) fn with_events(&self, events: EventBus) -> Self {
+        Self {
+            handler: self.handler.clone(),
+            events: Some(events),
         }
     }
 
     /// Emit a flashing progress event.
     fn emit(&self, event: ProgressEvent) {
+        if let Some(events) = self
+            .events
+            .as_ref()
+            .filter(|events| events.has_subscribers())
+        {
+            events.emit(SessionEvent::FlashProgress(event.clone()));
+        }
         (self.handler)(event);
     }
 
@@ -36,6 +81,11 @@ impl FlashProgress {
         self.emit(ProgressEvent::Initialized { flash_layout });
     }
 
+    /// Signalize that sectors were skipped because they already contain the data to be written.
+    pub(super) fn sectors_skipped(&self, count: usize, size: u32) {
+        self.emit(ProgressEvent::SectorsSkipped { count, size });
+    }
+
     /// Signalize that the erasing procedure started.
     pub(super) fn started_erasing(&self) {
         self.emit(ProgressEvent::StartedErasing);
@@ -52,18 +102,30 @@ impl FlashProgress {
     }
 
     /// Signalize that the page programming procedure has made progress.
-    pub(super) fn page_programmed(&self, size: u32, time: Duration) {
-        self.emit(ProgressEvent::PageProgrammed { size, time });
+    pub(super) fn page_programmed(&self, address: u32, size: u32, time: Duration) {
+        self.emit(ProgressEvent::PageProgrammed {
+            address,
+            size,
+            time,
+        });
     }
 
     /// Signalize that the sector erasing procedure has made progress.
-    pub(super) fn sector_erased(&self, size: u32, time: Duration) {
-        self.emit(ProgressEvent::SectorErased { size, time });
+    pub(super) fn sector_erased(&self, address: u32, size: u32, time: Duration) {
+        self.emit(ProgressEvent::SectorErased {
+            address,
+            size,
+            time,
+        });
     }
 
     /// Signalize that the page filling procedure has made progress.
-    pub(super) fn page_filled(&self, size: u32, time: Duration) {
-        self.emit(ProgressEvent::PageFilled { size, time });
+    pub(super) fn page_filled(&self, address: u32, size: u32, time: Duration) {
+        self.emit(ProgressEvent::PageFilled {
+            address,
+            size,
+            time,
+        });
     }
 
     /// Signalize that the programming procedure failed.
@@ -72,8 +134,11 @@ impl FlashProgress {
     }
 
     /// Signalize that the programming procedure completed successfully.
-    pub(super) fn finished_programming(&self) {
-        self.emit(ProgressEvent::FinishedProgramming);
+    pub(super) fn finished_programming(&self, total_bytes: u32, total_time: Duration) {
+        self.emit(ProgressEvent::FinishedProgramming {
+            total_bytes,
+            total_time,
+        });
     }
 
     /// Signalize that the erasing procedure failed.
@@ -82,8 +147,11 @@ impl FlashProgress {
     }
 
     /// Signalize that the erasing procedure completed successfully.
-    pub(super) fn finished_erasing(&self) {
-        self.emit(ProgressEvent::FinishedErasing);
+    pub(super) fn finished_erasing(&self, total_bytes: u32, total_time: Duration) {
+        self.emit(ProgressEvent::FinishedErasing {
+            total_bytes,
+            total_time,
+        });
     }
 
     /// Signalize that the filling procedure failed.
@@ -92,8 +160,65 @@ impl FlashProgress {
     }
 
     /// Signalize that the filling procedure completed successfully.
-    pub(super) fn finished_filling(&self) {
-        self.emit(ProgressEvent::FinishedFilling);
+    pub(super) fn finished_filling(&self, total_bytes: u32, total_time: Duration) {
+        self.emit(ProgressEvent::FinishedFilling {
+            total_bytes,
+            total_time,
+        });
+    }
+
+    /// Signalize that the verification procedure started.
+    pub(super) fn started_verifying(&self) {
+        self.emit(ProgressEvent::StartedVerifying);
+    }
+
+    /// Signalize that the verification procedure has made progress.
+    pub(super) fn page_verified(&self, address: u32, size: u32, time: Duration) {
+        self.emit(ProgressEvent::PageVerified {
+            address,
+            size,
+            time,
+        });
+    }
+
+    /// Signalize that the verification procedure failed.
+    pub(super) fn failed_verifying(&self) {
+        self.emit(ProgressEvent::FailedVerifying);
+    }
+
+    /// Signalize that the verification procedure completed successfully.
+    pub(super) fn finished_verifying(&self, total_bytes: u32, total_time: Duration) {
+        self.emit(ProgressEvent::FinishedVerifying {
+            total_bytes,
+            total_time,
+        });
+    }
+
+    /// Signalize that reading back the memory contents started.
+    pub(super) fn started_reading(&self) {
+        self.emit(ProgressEvent::StartedReading);
+    }
+
+    /// Signalize that the reading procedure has made progress.
+    pub(super) fn data_read(&self, address: u32, size: u32, time: Duration) {
+        self.emit(ProgressEvent::DataRead {
+            address,
+            size,
+            time,
+        });
+    }
+
+    /// Signalize that the reading procedure failed.
+    pub(super) fn failed_reading(&self) {
+        self.emit(ProgressEvent::FailedReading);
+    }
+
+    /// Signalize that the reading procedure completed successfully.
+    pub(super) fn finished_reading(&self, total_bytes: u32, total_time: Duration) {
+        self.emit(ProgressEvent::FinishedReading {
+            total_bytes,
+            total_time,
+        });
     }
 }
 
@@ -103,6 +228,7 @@ impl FlashProgress {
 /// following order:
 ///
 /// * `Initialized`
+/// * `SectorsSkipped`, only if unchanged sectors are skipped
 /// * `StartedFilling`
 /// * `PageFilled` for every page
 /// * `FinishedFilling`
@@ -112,10 +238,16 @@ impl FlashProgress {
 /// * `StartedProgramming`
 /// * `PageProgrammed` for every page
 /// * `FinishedProgramming`
+/// * `StartedVerifying`, only if verification is enabled
+/// * `PageVerified` for every verified block
+/// * `FinishedVerifying`
 ///
 /// If an erorr occurs in any stage, one of the `Failed*` event will be returned,
 /// and no further events will be returned.
-#[derive(Debug)]
+///
+/// When reading back the memory contents, `StartedReading` is followed by
+/// `DataRead` for every chunk, and finally `FinishedReading` or `FailedReading`.
+#[derive(Debug, Clone)]
 pub enum ProgressEvent {
     /// The flash layout has been built and the flashing procedure was initialized.
     Initialized {
@@ -123,13 +255,22 @@ pub enum ProgressEvent {
         /// This is an exact report of what the flashing procedure will do during the flashing process.
         flash_layout: FlashLayout,
     },
+    /// Sectors were removed from the flash layout because they already contain the data to be written.
+    SectorsSkipped {
+        /// The number of skipped sectors.
+        count: usize,
+        /// The total size of the skipped sectors in bytes.
+        size: u32,
+    },
     /// Filling of flash pages has started.
     StartedFilling,
     /// A page has been filled successfully.
     /// This does not mean the page has been programmed yet.
     /// Only its contents are determined at this point!
     PageFilled {
-        /// The size of the page in bytes.
+        /// The start address of the filled area.
+        address: u32,
+        /// The size of the filled area in bytes.
         size: u32,
         /// The time it took to fill this flash page.
         time: Duration,
@@ -137,11 +278,18 @@ pub enum ProgressEvent {
     /// Filling of the pages has failed.
     FailedFilling,
     /// Filling of the pages has finished successfully.
-    FinishedFilling,
+    FinishedFilling {
+        /// The total amount of filled bytes.
+        total_bytes: u32,
+        /// The total time the procedure took.
+        total_time: Duration,
+    },
     /// Erasing of flash has started.
     StartedErasing,
     /// A sector has been erased successfully.
     SectorErased {
+        /// The start address of the sector.
+        address: u32,
         /// The size of the sector in bytes.
         size: u32,
         /// The time it took to erase this sector.
@@ -150,11 +298,18 @@ pub enum ProgressEvent {
     /// Erasing of the flash has failed.
     FailedErasing,
     /// Erasing of the flash has finished successfully.
-    FinishedErasing,
+    FinishedErasing {
+        /// The total amount of erased bytes.
+        total_bytes: u32,
+        /// The total time the procedure took.
+        total_time: Duration,
+    },
     /// Programming of the flash has started.
     StartedProgramming,
     /// A flash page has been programmed successfully.
     PageProgrammed {
+        /// The start address of the page.
+        address: u32,
         /// The size of this page in bytes.
         size: u32,
         /// The time it took to program this page.
@@ -163,5 +318,51 @@ pub enum ProgressEvent {
     /// Programming of the flash failed.
     FailedProgramming,
     /// Programming of the flash has finished successfully.
-    FinishedProgramming,
+    FinishedProgramming {
+        /// The total amount of programmed bytes.
+        total_bytes: u32,
+        /// The total time the procedure took.
+        total_time: Duration,
+    },
+    /// Verification of the flash contents has started.
+    StartedVerifying,
+    /// A block of flash has been verified successfully.
+    /// A block is never larger than a page.
+    PageVerified {
+        /// The start address of the block.
+        address: u32,
+        /// The size of the block in bytes.
+        size: u32,
+        /// The time it took to verify this block.
+        time: Duration,
+    },
+    /// Verification of the flash contents failed.
+    FailedVerifying,
+    /// Verification of the flash contents has finished successfully.
+    FinishedVerifying {
+        /// The total amount of verified bytes.
+        total_bytes: u32,
+        /// The total time the procedure took.
+        total_time: Duration,
+    },
+    /// Reading back the memory contents has started.
+    StartedReading,
+    /// A chunk of memory has been read successfully.
+    DataRead {
+        /// The start address of the chunk.
+        address: u32,
+        /// The size of the chunk in bytes.
+        size: u32,
+        /// The time it took to read this chunk.
+        time: Duration,
+    },
+    /// Reading back the memory contents failed.
+    FailedReading,
+    /// Reading back the memory contents has finished successfully.
+    FinishedReading {
+        /// The total amount of read bytes.
+        total_bytes: u32,
+        /// The total time the procedure took.
+        total_time: Duration,
+    },
 }
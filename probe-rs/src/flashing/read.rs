@@ -0,0 +1,410 @@
+use std::{ops::Range, time::Instant};
+
+use super::{flasher::write_registers, FlashError, FlashProgress};
+use crate::{
+    config::{unreadable_region, MemoryRegion},
+    error, MemoryInterface, Session,
+};
+
+/// The amount of bytes which are read from the target at once.
+///
+/// If a read fails, only this chunk has to be read again.
+const READ_CHUNK_SIZE: u32 = 0x1000;
+
+/// The amount of times the read of a chunk is retried before giving up.
+const READ_RETRIES: usize = 3;
+
+/// When verifying, every n-th chunk is read a second time.
+const VERIFY_SAMPLE_INTERVAL: usize = 8;
+
+/// Options for reading back the flash contents of a target.
+#[derive(Default)]
+pub struct ReadOptions<'progress> {
+    /// An optional progress reporter which is used if this argument is set to `Some(...)`.
+    pub progress: Option<&'progress FlashProgress>,
+    /// If `verify` is `true`, a sample of the read chunks is read a second time
+    /// and compared to the first result.
+    pub verify: bool,
+}
+
+/// Reads the contents of the given memory `ranges` of the target in `session`.
+///
+/// If `ranges` is empty, all NVM regions of the target are read.
+/// The result contains the start address and the data for each range.
+///
+/// If you are looking for more options, have a look at [read_flash_with_options].
+pub fn read_flash(
+    session: &mut Session,
+    ranges: &[Range<u32>],
+) -> Result<Vec<(u32, Vec<u8>)>, FlashError> {
+    read_flash_with_options(session, ranges, ReadOptions::default())
+}
+
+/// Reads the contents of the given memory `ranges` of the target in `session`.
+///
+/// If `ranges` is empty, all NVM regions of the target are read.
+/// The result contains the start address and the data for each range.
+///
+/// Returns [FlashError::NotReadable] if a range contains a region which is write-only,
+/// or which can not be read while the core is halted and the core is halted.
+///
+/// If you are looking for a simple version without many options, have a look at [read_flash].
+pub fn read_flash_with_options(
+    session: &mut Session,
+    ranges: &[Range<u32>],
+    options: ReadOptions<'_>,
+) -> Result<Vec<(u32, Vec<u8>)>, FlashError> {
+    let ranges = if ranges.is_empty() {
+        session
+            .target()
+            .memory_map
+            .iter()
+            .filter_map(|region| match region {
+                MemoryRegion::Nvm(region) => Some(region.range.clone()),
+                _ => None,
+            })
+            .collect()
+    } else {
+        ranges.to_vec()
+    };
+
+    let default_progress = FlashProgress::new(|_| {});
+    let progress = options.progress.unwrap_or(&default_progress);
+
+    // Memory mapped flash can only be read while its controller is in memory mapped mode.
+    let restore_sequences: Vec<_> = session
+        .target()
+        .memory_map
+        .iter()
+        .filter_map(|region| match region {
+            MemoryRegion::Nvm(region) => region.memory_mapped.as_ref().map(|m| (region, m)),
+            _ => None,
+        })
+        .filter(|(region, _)| {
+            ranges
+                .iter()
+                .any(|range| range.start < region.range.end && region.range.start < range.end)
+        })
+        .map(|(_, memory_mapped)| memory_mapped.restore_sequence.clone())
+        .collect();
+
+    let halted = session
+        .core(0)
+        .and_then(|mut core| core.core_halted())
+        .map_err(FlashError::Core)?;
+    check_readable(&session.target().memory_map, &ranges, halted)?;
+
+    let mut core = session.core(0).map_err(FlashError::Core)?;
+
+    for restore_sequence in &restore_sequences {
+        write_registers(&mut core, restore_sequence)?;
+    }
+
+    progress.started_reading();
+
+    let start = Instant::now();
+    let result = read_ranges(&mut core, &ranges, options.verify, progress);
+
+    match &result {
+        Ok(contents) => {
+            let total_bytes = contents.iter().map(|(_, data)| data.len() as u32).sum();
+            progress.finished_reading(total_bytes, start.elapsed())
+        }
+        Err(_) => progress.failed_reading(),
+    }
+
+    result
+}
+
+/// Checks that none of the `ranges` contains a region of `memory_map` which can not be read.
+fn check_readable(
+    memory_map: &[MemoryRegion],
+    ranges: &[Range<u32>],
+    halted: bool,
+) -> Result<(), FlashError> {
+    for range in ranges {
+        let len = range.end.saturating_sub(range.start) as usize;
+        if let Some(region) = unreadable_region(memory_map, range.start, len, halted) {
+            return Err(FlashError::NotReadable {
+                start: range.start,
+                end: range.end,
+                region: region.to_string(),
+                reason: if region.access.write_only {
+                    "write-only"
+                } else {
+                    "not readable while the core is halted"
+                },
+            });
+        }
+    }
+
+    Ok(())
+}
+
+fn read_ranges(
+    memory: &mut impl MemoryInterface,
+    ranges: &[Range<u32>],
+    verify: bool,
+    progress: &FlashProgress,
+) -> Result<Vec<(u32, Vec<u8>)>, FlashError> {
+    let mut result = Vec::with_capacity(ranges.len());
+    let mut chunk_index = 0;
+
+    for range in ranges {
+        log::debug!(
+            "Reading memory range {:#010x}..{:#010x}",
+            range.start,
+            range.end
+        );
+
+        let mut data = vec![0u8; (range.end - range.start) as usize];
+
+        for (offset, chunk) in data.chunks_mut(READ_CHUNK_SIZE as usize).enumerate() {
+            let address = range.start + offset as u32 * READ_CHUNK_SIZE;
+            let t = Instant::now();
+
+            read_chunk_with_retries(memory, address, chunk)?;
+
+            if verify && chunk_index % VERIFY_SAMPLE_INTERVAL == 0 {
+                let mut verify_data = vec![0u8; chunk.len()];
+                read_chunk_with_retries(memory, address, &mut verify_data)?;
+
+                if verify_data != chunk {
+                    return Err(FlashError::ReadVerifyFailed(address));
+                }
+            }
+
+            progress.data_read(address, chunk.len() as u32, t.elapsed());
+            chunk_index += 1;
+        }
+
+        result.push((range.start, data));
+    }
+
+    Ok(result)
+}
+
+fn read_chunk_with_retries(
+    memory: &mut impl MemoryInterface,
+    address: u32,
+    data: &mut [u8],
+) -> Result<(), FlashError> {
+    let mut attempt = 0;
+
+    loop {
+        match read_chunk(memory, address, data) {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt < READ_RETRIES => {
+                log::warn!(
+                    "Failed to read {} bytes at address {:#010x}, retrying: {}",
+                    data.len(),
+                    address,
+                    e
+                );
+                attempt += 1;
+            }
+            Err(e) => return Err(FlashError::Memory(e)),
+        }
+    }
+}
+
+/// Reads a single chunk, using 32 bit accesses whenever possible as they are a lot faster.
+fn read_chunk(
+    memory: &mut impl MemoryInterface,
+    address: u32,
+    data: &mut [u8],
+) -> Result<(), error::Error> {
+    if address % 4 == 0 && data.len() % 4 == 0 {
+        let mut words = vec![0u32; data.len() / 4];
+        memory.read_32(address, &mut words)?;
+
+        for (bytes, word) in data.chunks_exact_mut(4).zip(words) {
+            bytes.copy_from_slice(&word.to_le_bytes());
+        }
+
+        Ok(())
+    } else {
+        memory.read_8(address, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_readable, read_ranges, READ_CHUNK_SIZE};
+    use crate::config::{GenericRegion, MemoryAccess, MemoryRegion};
+    use crate::flashing::{FlashError, FlashProgress, ProgressEvent};
+    use crate::{DebugProbeError, Error, MemoryInterface};
+    use std::sync::mpsc::channel;
+
+    /// Memory in which each byte is the low byte of its address.
+    ///
+    /// The first `failures` reads fail, and with `unstable` set, every read returns
+    /// different contents.
+    #[derive(Default)]
+    struct Memory {
+        failures: usize,
+        unstable: bool,
+        reads: u8,
+    }
+
+    impl Memory {
+        fn byte(&self, address: u32) -> u8 {
+            (address as u8).wrapping_add(self.reads)
+        }
+
+        fn read(&mut self, address: u32, data: &mut [u8]) -> Result<(), Error> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(DebugProbeError::Timeout.into());
+            }
+
+            for (offset, byte) in data.iter_mut().enumerate() {
+                *byte = self.byte(address + offset as u32);
+            }
+
+            if self.unstable {
+                self.reads += 1;
+            }
+            Ok(())
+        }
+    }
+
+    impl MemoryInterface for Memory {
+        fn read_word_32(&mut self, address: u32) -> Result<u32, Error> {
+            let mut word = [0; 4];
+            self.read(address, &mut word)?;
+            Ok(u32::from_le_bytes(word))
+        }
+
+        fn read_word_8(&mut self, address: u32) -> Result<u8, Error> {
+            let mut byte = [0];
+            self.read(address, &mut byte)?;
+            Ok(byte[0])
+        }
+
+        fn read_32(&mut self, address: u32, data: &mut [u32]) -> Result<(), Error> {
+            let mut bytes = vec![0; data.len() * 4];
+            self.read(address, &mut bytes)?;
+
+            for (word, bytes) in data.iter_mut().zip(bytes.chunks_exact(4)) {
+                *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+            }
+            Ok(())
+        }
+
+        fn read_8(&mut self, address: u32, data: &mut [u8]) -> Result<(), Error> {
+            self.read(address, data)
+        }
+
+        fn write_word_32(&mut self, _address: u32, _data: u32) -> Result<(), Error> {
+            unimplemented!()
+        }
+
+        fn write_word_8(&mut self, _address: u32, _data: u8) -> Result<(), Error> {
+            unimplemented!()
+        }
+
+        fn write_32(&mut self, _address: u32, _data: &[u32]) -> Result<(), Error> {
+            unimplemented!()
+        }
+
+        fn write_8(&mut self, _address: u32, _data: &[u8]) -> Result<(), Error> {
+            unimplemented!()
+        }
+
+        fn flush(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn ranges_are_read_in_chunks() {
+        let (sender, receiver) = channel();
+        let progress = FlashProgress::from_sender(sender);
+        let ranges = [
+            0x0800_0000..0x0800_0000 + READ_CHUNK_SIZE + 6,
+            0x0800_3001..0x0800_3004,
+        ];
+
+        let contents = read_ranges(&mut Memory::default(), &ranges, false, &progress).unwrap();
+
+        assert_eq!(contents.len(), 2);
+        assert_eq!(contents[0].0, 0x0800_0000);
+        assert_eq!(contents[0].1.len(), READ_CHUNK_SIZE as usize + 6);
+        assert_eq!(contents[0].1[0x1005], 0x05);
+        assert_eq!(contents[1], (0x0800_3001, vec![0x01, 0x02, 0x03]));
+
+        let chunks: Vec<_> = receiver
+            .try_iter()
+            .map(|event| match event {
+                ProgressEvent::DataRead { address, size, .. } => (address, size),
+                event => panic!("Unexpected event {:?}", event),
+            })
+            .collect();
+        assert_eq!(
+            chunks,
+            vec![
+                (0x0800_0000, READ_CHUNK_SIZE),
+                (0x0800_0000 + READ_CHUNK_SIZE, 6),
+                (0x0800_3001, 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn failed_chunks_are_read_again() {
+        let progress = FlashProgress::new(|_| {});
+        let mut memory = Memory {
+            failures: 2,
+            ..Default::default()
+        };
+
+        let contents = read_ranges(&mut memory, &[0x100..0x108], false, &progress).unwrap();
+        assert_eq!(contents[0].1, vec![0, 1, 2, 3, 4, 5, 6, 7]);
+
+        let mut memory = Memory {
+            failures: 10,
+            ..Default::default()
+        };
+        assert!(matches!(
+            read_ranges(&mut memory, &[0x100..0x108], false, &progress),
+            Err(FlashError::Memory(_))
+        ));
+    }
+
+    #[test]
+    fn verification_detects_unstable_reads() {
+        let progress = FlashProgress::new(|_| {});
+        let mut memory = Memory {
+            unstable: true,
+            ..Default::default()
+        };
+
+        assert!(read_ranges(&mut memory, &[0x100..0x108], false, &progress).is_ok());
+        assert!(matches!(
+            read_ranges(&mut memory, &[0x100..0x108], true, &progress),
+            Err(FlashError::ReadVerifyFailed(0x100))
+        ));
+    }
+
+    #[test]
+    fn unreadable_regions_are_rejected() {
+        let memory_map = [MemoryRegion::Generic(GenericRegion {
+            range: 0x4000_0000..0x4000_1000,
+            name: Some("RNG".into()),
+            access: MemoryAccess {
+                not_readable_while_halted: true,
+                ..Default::default()
+            },
+        })];
+
+        assert!(check_readable(&memory_map, &[0x4000_0000..0x4000_0004], false).is_ok());
+        assert!(check_readable(&memory_map, &[0x3fff_f000..0x4000_0000], true).is_ok());
+
+        let error = check_readable(&memory_map, &[0x3fff_fff0..0x4000_0010], true).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The range 0x3ffffff0..0x40000010 can not be read, the memory region RNG (0x40000000..0x40001000) is not readable while the core is halted."
+        );
+    }
+}
@@ -0,0 +1,292 @@
+//! Parser for the [UF2](https://github.com/microsoft/uf2) file format.
+
+use std::convert::TryInto;
+use thiserror::Error;
+
+const UF2_MAGIC_START0: u32 = 0x0A32_4655;
+const UF2_MAGIC_START1: u32 = 0x9E5D_5157;
+const UF2_MAGIC_END: u32 = 0x0AB1_6F30;
+
+const UF2_BLOCK_SIZE: usize = 512;
+const UF2_MAX_PAYLOAD_SIZE: u32 = 476;
+
+/// The block is not meant to be written to main flash.
+const UF2_FLAG_NOT_MAIN_FLASH: u32 = 0x0000_0001;
+/// The block is part of a file container, which is not supported.
+const UF2_FLAG_FILE_CONTAINER: u32 = 0x0000_1000;
+/// The `file_size` field contains the family ID instead.
+const UF2_FLAG_FAMILY_ID_PRESENT: u32 = 0x0000_2000;
+
+/// Known UF2 family IDs, taken from the list maintained in the UF2 repository.
+///
+/// The first element is a prefix of the chip name as used in the target descriptions.
+const UF2_FAMILIES: &[(&str, u32)] = &[
+    ("stm32f0", 0x6478_24b6),
+    ("stm32f1", 0x5ee2_1072),
+    ("stm32f2", 0x5d1a_0a2e),
+    ("stm32f3", 0x6b84_6188),
+    ("stm32f401", 0x5775_5a57),
+    ("stm32f407", 0x6d09_22fa),
+    ("stm32f7", 0x53b8_0f00),
+    ("stm32g0", 0x300f_5633),
+    ("stm32g4", 0x4c71_240a),
+    ("stm32h7", 0x6db6_6082),
+    ("stm32l0", 0x202e_3a91),
+    ("stm32l1", 0x1e1f_432d),
+    ("stm32l4", 0x00ff_6919),
+    ("stm32l5", 0x0424_0bdf),
+    ("stm32wb", 0x70d1_6653),
+    ("stm32wl", 0x2146_0ff0),
+    ("atsamd21", 0x68ed_2b88),
+    ("atsamd51", 0x5511_4460),
+    ("nrf52833", 0x621e_937a),
+    ("nrf52840", 0xada5_2840),
+    ("nrf52", 0x1b57_745f),
+    ("lpc55", 0x2abc_77ec),
+];
+
+/// Returns the UF2 family IDs which are valid for the chip with the given name.
+///
+/// An empty list is returned if no family ID is known for the chip.
+pub(crate) fn family_ids_for_chip(chip_name: &str) -> Vec<u32> {
+    let chip_name = chip_name.to_ascii_lowercase();
+
+    UF2_FAMILIES
+        .iter()
+        .filter(|(prefix, _)| chip_name.starts_with(prefix))
+        .map(|(_, id)| *id)
+        .collect()
+}
+
+/// Errors which can occur when parsing a UF2 file.
+#[derive(Debug, Error)]
+pub enum Uf2Error {
+    /// The file size is not a multiple of the UF2 block size.
+    #[error("The file size {0} is not a multiple of 512 bytes.")]
+    InvalidFileSize(usize),
+    /// One of the magic values in the block is wrong.
+    #[error("Block {0} contains an invalid magic value.")]
+    InvalidMagic(usize),
+    /// The payload size of a block is larger than allowed.
+    #[error("Block {block} has an invalid payload size of {size} bytes.")]
+    InvalidPayloadSize {
+        /// Index of the block in the file.
+        block: usize,
+        /// The payload size given in the block.
+        size: u32,
+    },
+    /// The block is part of a file container, which is not supported.
+    #[error("Block {0} is part of a file container, which is not supported.")]
+    FileContainer(usize),
+    /// The family ID in the file does not match the selected target.
+    #[error("The UF2 family ID {found:#010x} does not match the selected target, expected one of {expected:#010x?}.")]
+    FamilyMismatch {
+        /// The family ID found in the file.
+        found: u32,
+        /// The family IDs valid for the selected target.
+        expected: Vec<u32>,
+    },
+}
+
+/// A contiguous chunk of data extracted from a UF2 file.
+#[derive(Debug, PartialEq, Eq)]
+pub(super) struct Uf2Chunk {
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+fn read_u32(block: &[u8], offset: usize) -> u32 {
+    // Unwrap is safe, the slice has exactly 4 bytes.
+    u32::from_le_bytes(block[offset..offset + 4].try_into().unwrap())
+}
+
+/// Parse the contents of a UF2 file.
+///
+/// Contiguous payloads are merged into a single chunk. If `expected_families` is not empty,
+/// the family ID of every block which contains one is checked against it, unless
+/// `allow_family_mismatch` is set.
+pub(super) fn parse(
+    data: &[u8],
+    expected_families: &[u32],
+    allow_family_mismatch: bool,
+) -> Result<Vec<Uf2Chunk>, Uf2Error> {
+    if data.len() % UF2_BLOCK_SIZE != 0 {
+        return Err(Uf2Error::InvalidFileSize(data.len()));
+    }
+
+    let mut chunks: Vec<Uf2Chunk> = Vec::new();
+
+    for (index, block) in data.chunks_exact(UF2_BLOCK_SIZE).enumerate() {
+        if read_u32(block, 0) != UF2_MAGIC_START0
+            || read_u32(block, 4) != UF2_MAGIC_START1
+            || read_u32(block, UF2_BLOCK_SIZE - 4) != UF2_MAGIC_END
+        {
+            return Err(Uf2Error::InvalidMagic(index));
+        }
+
+        let flags = read_u32(block, 8);
+        let address = read_u32(block, 12);
+        let payload_size = read_u32(block, 16);
+
+        if flags & UF2_FLAG_FILE_CONTAINER != 0 {
+            return Err(Uf2Error::FileContainer(index));
+        }
+
+        if flags & UF2_FLAG_NOT_MAIN_FLASH != 0 {
+            log::warn!(
+                "Skipping UF2 block {} at address {:#010x}, it is not meant for main flash.",
+                index,
+                address
+            );
+            continue;
+        }
+
+        if flags & UF2_FLAG_FAMILY_ID_PRESENT != 0 && !expected_families.is_empty() {
+            let family_id = read_u32(block, 28);
+
+            if !expected_families.contains(&family_id) {
+                if allow_family_mismatch {
+                    log::warn!(
+                        "UF2 family ID {:#010x} does not match the target, ignoring it as requested.",
+                        family_id
+                    );
+                } else {
+                    return Err(Uf2Error::FamilyMismatch {
+                        found: family_id,
+                        expected: expected_families.to_vec(),
+                    });
+                }
+            }
+        }
+
+        if payload_size > UF2_MAX_PAYLOAD_SIZE {
+            return Err(Uf2Error::InvalidPayloadSize {
+                block: index,
+                size: payload_size,
+            });
+        }
+
+        let payload = &block[32..32 + payload_size as usize];
+
+        match chunks.last_mut() {
+            Some(chunk) if chunk.address.checked_add(chunk.data.len() as u32) == Some(address) => {
+                chunk.data.extend_from_slice(payload)
+            }
+            _ => chunks.push(Uf2Chunk {
+                address,
+                data: payload.to_vec(),
+            }),
+        }
+    }
+
+    Ok(chunks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(flags: u32, address: u32, payload: &[u8], family_id: u32) -> Vec<u8> {
+        let mut block = vec![0u8; UF2_BLOCK_SIZE];
+
+        block[0..4].copy_from_slice(&UF2_MAGIC_START0.to_le_bytes());
+        block[4..8].copy_from_slice(&UF2_MAGIC_START1.to_le_bytes());
+        block[8..12].copy_from_slice(&flags.to_le_bytes());
+        block[12..16].copy_from_slice(&address.to_le_bytes());
+        block[16..20].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+        block[28..32].copy_from_slice(&family_id.to_le_bytes());
+        block[32..32 + payload.len()].copy_from_slice(payload);
+        block[UF2_BLOCK_SIZE - 4..].copy_from_slice(&UF2_MAGIC_END.to_le_bytes());
+
+        block
+    }
+
+    #[test]
+    fn contiguous_blocks_are_merged() {
+        let mut data = block(0, 0x1000, &[1; 256], 0);
+        data.extend(block(0, 0x1100, &[2; 256], 0));
+        data.extend(block(0, 0x2000, &[3; 16], 0));
+
+        let chunks = parse(&data, &[], false).unwrap();
+
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].address, 0x1000);
+        assert_eq!(chunks[0].data.len(), 512);
+        assert_eq!(chunks[0].data[256], 2);
+        assert_eq!(chunks[1].address, 0x2000);
+        assert_eq!(chunks[1].data, vec![3; 16]);
+    }
+
+    #[test]
+    fn blocks_at_the_end_of_the_address_space_are_not_merged() {
+        let mut data = block(0, 0xffff_ff00, &[1; 256], 0);
+        data.extend(block(0, 0x0, &[2; 256], 0));
+
+        let chunks = parse(&data, &[], false).unwrap();
+
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[1].address, 0x0);
+    }
+
+    #[test]
+    fn not_main_flash_blocks_are_skipped() {
+        let mut data = block(UF2_FLAG_NOT_MAIN_FLASH, 0x1000, &[1; 256], 0);
+        data.extend(block(0, 0x1100, &[2; 256], 0));
+
+        let chunks = parse(&data, &[], false).unwrap();
+
+        assert_eq!(
+            chunks,
+            vec![Uf2Chunk {
+                address: 0x1100,
+                data: vec![2; 256]
+            }]
+        );
+    }
+
+    #[test]
+    fn invalid_magic() {
+        let mut data = block(0, 0x1000, &[1; 256], 0);
+        data[0] = 0;
+
+        assert!(matches!(
+            parse(&data, &[], false),
+            Err(Uf2Error::InvalidMagic(0))
+        ));
+    }
+
+    #[test]
+    fn invalid_file_size() {
+        let data = vec![0u8; 100];
+
+        assert!(matches!(
+            parse(&data, &[], false),
+            Err(Uf2Error::InvalidFileSize(100))
+        ));
+    }
+
+    #[test]
+    fn family_mismatch() {
+        let data = block(UF2_FLAG_FAMILY_ID_PRESENT, 0x1000, &[1; 256], 0xada5_2840);
+
+        assert!(matches!(
+            parse(&data, &[0x5775_5a57], false),
+            Err(Uf2Error::FamilyMismatch {
+                found: 0xada5_2840,
+                ..
+            })
+        ));
+
+        assert!(parse(&data, &[0x5775_5a57], true).is_ok());
+        assert!(parse(&data, &[0xada5_2840], false).is_ok());
+    }
+
+    #[test]
+    fn family_ids_for_known_chip() {
+        assert_eq!(
+            family_ids_for_chip("nRF52840_xxAA"),
+            vec![0xada5_2840, 0x1b57_745f]
+        );
+        assert!(family_ids_for_chip("HT32F1653").is_empty());
+    }
+}
@@ -0,0 +1,131 @@
+use std::ops::Range;
+
+use super::{read_flash_with_options, FlashError, FlashLoader, FlashProgress, ReadOptions};
+use crate::{config::MemoryRegion, Session};
+
+/// Checks that the given memory `ranges` of the target in `session` are erased.
+///
+/// If `ranges` is empty, all NVM regions of the target are checked.
+/// The `BlankCheck()` routine of the flash algorithms is used where it is available,
+/// otherwise the flash contents are read back. The returned ranges are not erased,
+/// if all bytes are erased, the result is empty.
+pub fn blank_check(
+    session: &mut Session,
+    ranges: &[Range<u32>],
+    progress: Option<&FlashProgress>,
+) -> Result<Vec<Range<u32>>, FlashError> {
+    let default_progress = FlashProgress::new(|_| {});
+    let progress = progress.unwrap_or(&default_progress);
+
+    let target = session.target().clone();
+    let ranges = if ranges.is_empty() {
+        target
+            .memory_map
+            .iter()
+            .filter_map(|region| match region {
+                MemoryRegion::Nvm(region) => Some(region.range.clone()),
+                _ => None,
+            })
+            .collect()
+    } else {
+        ranges.to_vec()
+    };
+
+    let loader = FlashLoader::new(&target.memory_map, Default::default(), false, false, false);
+
+    loader.blank_check(session, &ranges, progress)
+}
+
+/// Reads back the flash contents of the `chunks`, and returns the ranges which differ from the chunks.
+///
+/// The flash is read through the memory bus, like [read_flash_with_options] does,
+/// so the core does not have to be halted.
+pub(super) fn compare(
+    session: &mut Session,
+    chunks: &[(u32, &[u8])],
+    progress: Option<&FlashProgress>,
+) -> Result<Vec<Range<u32>>, FlashError> {
+    if chunks.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let ranges = chunks
+        .iter()
+        .map(|(address, data)| *address..*address + data.len() as u32)
+        .collect::<Vec<_>>();
+    let contents = read_flash_with_options(
+        session,
+        &ranges,
+        ReadOptions {
+            progress,
+            verify: false,
+        },
+    )?;
+
+    let mut differing = vec![];
+    for ((address, expected), (_, current)) in chunks.iter().zip(&contents) {
+        differing.extend(differences(*address, expected, current));
+    }
+
+    Ok(merge(differing))
+}
+
+/// Returns the ranges of the bytes at `address` in which `expected` and `current` differ.
+fn differences(address: u32, expected: &[u8], current: &[u8]) -> Vec<Range<u32>> {
+    let mut ranges: Vec<Range<u32>> = vec![];
+
+    for (offset, _) in expected
+        .iter()
+        .zip(current)
+        .enumerate()
+        .filter(|(_, (expected, current))| expected != current)
+    {
+        let byte = address + offset as u32;
+        match ranges.last_mut() {
+            Some(range) if range.end == byte => range.end += 1,
+            _ => ranges.push(byte..byte + 1),
+        }
+    }
+
+    ranges
+}
+
+/// Sorts the `ranges` and merges the ones which touch each other.
+fn merge(mut ranges: Vec<Range<u32>>) -> Vec<Range<u32>> {
+    ranges.sort_by_key(|range| range.start);
+
+    let mut merged: Vec<Range<u32>> = vec![];
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if last.end >= range.start => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{differences, merge};
+
+    #[test]
+    fn differing_bytes_are_grouped_into_ranges() {
+        let expected = [0, 1, 2, 3, 4, 5, 6, 7];
+        let current = [0, 9, 9, 3, 4, 5, 9, 9];
+
+        assert_eq!(
+            differences(0x0800_0000, &expected, &current),
+            vec![0x0800_0001..0x0800_0003, 0x0800_0006..0x0800_0008]
+        );
+        assert_eq!(differences(0x0800_0000, &expected, &expected), vec![]);
+    }
+
+    #[test]
+    fn touching_ranges_are_merged() {
+        assert_eq!(
+            merge(vec![0x300..0x400, 0x100..0x200, 0x200..0x280, 0x500..0x600]),
+            vec![0x100..0x280, 0x300..0x400, 0x500..0x600]
+        );
+    }
+}
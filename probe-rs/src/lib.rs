@@ -71,25 +71,44 @@
 extern crate serde;
 
 pub mod architecture;
+pub mod asynchronous;
+pub mod benchmark;
+pub mod clock;
 pub mod config;
 mod core;
+pub mod core_dump;
 pub mod debug;
 mod error;
+mod events;
 pub mod flashing;
 mod memory;
+mod option_bytes;
+mod permissions;
 mod probe;
+pub mod report;
 mod session;
+pub mod session_config;
+mod shared_session;
+pub mod svd;
 
 pub use crate::config::Target;
 pub use crate::core::CoreType;
 pub use crate::core::{
     Architecture, Breakpoint, BreakpointId, CommunicationInterface, Core, CoreInformation,
-    CoreInterface, CoreList, CoreRegister, CoreRegisterAddress, CoreStatus, HaltReason,
+    CoreInterface, CoreList, CoreRegister, CoreRegisterAddress, CoreStatus, HaltReason, ResetType,
 };
 pub use crate::error::Error;
-pub use crate::memory::{Memory, MemoryInterface, MemoryList};
+pub use crate::events::{EventReceiver, SessionEvent};
+pub use crate::memory::{AccessSize, Memory, MemoryInterface, MemoryList, MemoryStream};
+pub use crate::option_bytes::{OptionBytesError, TargetOptions};
+pub use crate::permissions::Permissions;
 pub use crate::probe::{
-    AttachMethod, DebugProbe, DebugProbeError, DebugProbeInfo, DebugProbeSelector, DebugProbeType,
-    Probe, WireProtocol,
+    register_probe_factory, AttachMethod, DebugProbe, DebugProbeError, DebugProbeInfo,
+    DebugProbeSelector, DebugProbeType, JTAGAccess, PowerCapabilities, Probe, ProbeCreationError,
+    ProbeFactory, ProbeServer, ReconnectPolicy, SpeedSelection, WireProtocol,
 };
+#[cfg(feature = "ftdi")]
+pub use crate::probe::{FtdiProbe, SwdioDirectionPin};
 pub use crate::session::Session;
+pub use crate::session_config::SessionConfig;
+pub use crate::shared_session::{SessionGuard, SharedSession};
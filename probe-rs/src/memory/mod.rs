@@ -6,6 +6,42 @@ use crate::{
 
 use anyhow::Result;
 
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// The size of a single access to the memory of the target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessSize {
+    U8,
+    U16,
+    U32,
+    U64,
+}
+
+impl AccessSize {
+    /// The size of an access in bytes.
+    pub fn bytes(self) -> usize {
+        match self {
+            AccessSize::U8 => 1,
+            AccessSize::U16 => 2,
+            AccessSize::U32 => 4,
+            AccessSize::U64 => 8,
+        }
+    }
+
+    /// Returns true if `address` and `len` are multiples of the access size.
+    pub(crate) fn is_aligned(self, address: u32, len: usize) -> bool {
+        address as usize % self.bytes() == 0 && len % self.bytes() == 0
+    }
+}
+
+impl fmt::Display for AccessSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} bit", self.bytes() * 8)
+    }
+}
+
 pub trait MemoryInterface {
     /// Read a 32bit word of at `address`.
     ///
@@ -16,6 +52,17 @@ pub trait MemoryInterface {
     /// Read an 8bit word of at `address`.
     fn read_word_8(&mut self, address: u32) -> Result<u8, error::Error>;
 
+    /// Read a 64bit word at `address`.
+    ///
+    /// Unless the implementation supports 64bit accesses, the word is read with two
+    /// 32bit accesses: first the lower half at `address`, then the upper half at `address + 4`.
+    fn read_word_64(&mut self, address: u32) -> Result<u64, error::Error> {
+        let low = self.read_word_32(address)?;
+        let high = self.read_word_32(address + 4)?;
+
+        Ok(u64::from(high) << 32 | u64::from(low))
+    }
+
     /// Read a block of 32bit words at `address`.
     ///
     /// The number of words read is `data.len()`.
@@ -26,6 +73,68 @@ pub trait MemoryInterface {
     /// Read a block of 8bit words at `address`.
     fn read_8(&mut self, address: u32, data: &mut [u8]) -> Result<(), error::Error>;
 
+    /// Read a block of 16bit words at `address`.
+    ///
+    /// The number of words read is `data.len()`. Unless the implementation supports
+    /// 16bit accesses, the memory is read with [MemoryInterface::read_8].
+    fn read_16(&mut self, address: u32, data: &mut [u16]) -> Result<(), error::Error> {
+        let mut bytes = vec![0u8; data.len() * 2];
+        self.read_8(address, &mut bytes)?;
+
+        for (word, bytes) in data.iter_mut().zip(bytes.chunks_exact(2)) {
+            *word = u16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+
+        Ok(())
+    }
+
+    /// Read as many bytes as possible of `data.len()` bytes at `address`.
+    ///
+    /// Returns the number of bytes at the start of `data` which were read. If fewer bytes
+    /// than requested were read, the memory at `address` plus this number could not be read,
+    /// e.g. because the range extends past the end of the RAM.
+    ///
+    /// Unless the implementation knows the readable memory, a failed read is split in halves,
+    /// down to single words, until the readable part is found.
+    /// Returns the error of the first word if none of the memory can be read.
+    fn read_partial(&mut self, address: u32, data: &mut [u8]) -> Result<usize, error::Error> {
+        read_bisected(self, address, data)
+    }
+
+    /// Read `data.len()` bytes at `address`, as one of the repeated reads of a [MemoryStream].
+    ///
+    /// `restart` is set for the first read of the stream, and for the first read after a failed one.
+    /// Otherwise, the implementation may skip the setup which the previous read of the stream
+    /// already did, e.g. the access port of an ARM chip is not configured again.
+    /// Unless the implementation supports this, the memory is read with [MemoryInterface::read_8].
+    fn read_stream(
+        &mut self,
+        address: u32,
+        data: &mut [u8],
+        _restart: bool,
+    ) -> Result<(), error::Error> {
+        self.read_8(address, data)
+    }
+
+    /// Returns an endless iterator which reads `len` bytes at `address` every `interval`.
+    ///
+    /// The reads use [MemoryInterface::read_stream], which avoids most of the setup of single reads.
+    /// A failed read does not end the stream, the next read sets up the access again.
+    /// With an `interval` of zero, the memory is read as fast as possible.
+    fn stream(&mut self, address: u32, len: usize, interval: Duration) -> MemoryStream<'_, Self>
+    where
+        Self: Sized,
+    {
+        MemoryStream {
+            memory: self,
+            address,
+            len,
+            interval,
+            next_read: None,
+            restart: true,
+        }
+    }
+
     /// Write a 32bit word at `address`.
     ///
     /// The address where the write should be performed at has to be word aligned.
@@ -35,6 +144,15 @@ pub trait MemoryInterface {
     /// Write an 8bit word at `address`.
     fn write_word_8(&mut self, address: u32, data: u8) -> Result<(), error::Error>;
 
+    /// Write a 64bit word at `address`.
+    ///
+    /// Unless the implementation supports 64bit accesses, the word is written with two
+    /// 32bit accesses: first the lower half at `address`, then the upper half at `address + 4`.
+    fn write_word_64(&mut self, address: u32, data: u64) -> Result<(), error::Error> {
+        self.write_word_32(address, data as u32)?;
+        self.write_word_32(address + 4, (data >> 32) as u32)
+    }
+
     /// Write a block of 32bit words at `address`.
     ///
     /// The number of words written is `data.len()`.
@@ -45,6 +163,46 @@ pub trait MemoryInterface {
     /// Write a block of 8bit words at `address`.
     fn write_8(&mut self, address: u32, data: &[u8]) -> Result<(), error::Error>;
 
+    /// Write a block of 16bit words at `address`.
+    ///
+    /// The number of words written is `data.len()`. Unless the implementation supports
+    /// 16bit accesses, the memory is written with [MemoryInterface::write_8].
+    fn write_16(&mut self, address: u32, data: &[u16]) -> Result<(), error::Error> {
+        let bytes: Vec<u8> = data
+            .iter()
+            .flat_map(|word| word.to_le_bytes().to_vec())
+            .collect();
+
+        self.write_8(address, &bytes)
+    }
+
+    /// Read `data.len()` bytes at `address`, using only accesses of the given size.
+    ///
+    /// Every bus transaction has exactly the requested size, accesses are neither combined
+    /// nor split. `address` and the length of `data` have to be multiples of the size.
+    /// Returns [Error::AccessSizeNotSupported](error::Error::AccessSizeNotSupported) if
+    /// the target or the probe can not make accesses of this size.
+    fn read_mem_with_size(
+        &mut self,
+        _address: u32,
+        _data: &mut [u8],
+        size: AccessSize,
+    ) -> Result<(), error::Error> {
+        Err(error::Error::AccessSizeNotSupported(size))
+    }
+
+    /// Write `data` at `address`, using only accesses of the given size.
+    ///
+    /// See [MemoryInterface::read_mem_with_size] for the guarantees and requirements.
+    fn write_mem_with_size(
+        &mut self,
+        _address: u32,
+        _data: &[u8],
+        size: AccessSize,
+    ) -> Result<(), error::Error> {
+        Err(error::Error::AccessSizeNotSupported(size))
+    }
+
     /// Flush any outstanding operations.
     ///
     /// For performance, debug probe implementations may choose to batch writes;
@@ -66,6 +224,10 @@ where
         (*self).read_word_8(address)
     }
 
+    fn read_word_64(&mut self, address: u32) -> Result<u64, error::Error> {
+        (*self).read_word_64(address)
+    }
+
     fn read_32(&mut self, address: u32, data: &mut [u32]) -> Result<(), error::Error> {
         (*self).read_32(address, data)
     }
@@ -74,6 +236,23 @@ where
         (*self).read_8(address, data)
     }
 
+    fn read_16(&mut self, address: u32, data: &mut [u16]) -> Result<(), error::Error> {
+        (*self).read_16(address, data)
+    }
+
+    fn read_partial(&mut self, address: u32, data: &mut [u8]) -> Result<usize, error::Error> {
+        (*self).read_partial(address, data)
+    }
+
+    fn read_stream(
+        &mut self,
+        address: u32,
+        data: &mut [u8],
+        restart: bool,
+    ) -> Result<(), error::Error> {
+        (*self).read_stream(address, data, restart)
+    }
+
     fn write_word_32(&mut self, addr: u32, data: u32) -> Result<(), error::Error> {
         (*self).write_word_32(addr, data)
     }
@@ -82,6 +261,10 @@ where
         (*self).write_word_8(addr, data)
     }
 
+    fn write_word_64(&mut self, addr: u32, data: u64) -> Result<(), error::Error> {
+        (*self).write_word_64(addr, data)
+    }
+
     fn write_32(&mut self, addr: u32, data: &[u32]) -> Result<(), error::Error> {
         (*self).write_32(addr, data)
     }
@@ -90,11 +273,102 @@ where
         (*self).write_8(addr, data)
     }
 
+    fn write_16(&mut self, addr: u32, data: &[u16]) -> Result<(), error::Error> {
+        (*self).write_16(addr, data)
+    }
+
+    fn read_mem_with_size(
+        &mut self,
+        address: u32,
+        data: &mut [u8],
+        size: AccessSize,
+    ) -> Result<(), error::Error> {
+        (*self).read_mem_with_size(address, data, size)
+    }
+
+    fn write_mem_with_size(
+        &mut self,
+        address: u32,
+        data: &[u8],
+        size: AccessSize,
+    ) -> Result<(), error::Error> {
+        (*self).write_mem_with_size(address, data, size)
+    }
+
     fn flush(&mut self) -> Result<(), error::Error> {
         (*self).flush()
     }
 }
 
+/// Repeated reads of the same memory, see [MemoryInterface::stream].
+pub struct MemoryStream<'memory, M: MemoryInterface> {
+    memory: &'memory mut M,
+    address: u32,
+    len: usize,
+    interval: Duration,
+    /// When the next read is due, or `None` before the first read.
+    next_read: Option<Instant>,
+    restart: bool,
+}
+
+impl<M: MemoryInterface> Iterator for MemoryStream<'_, M> {
+    type Item = Result<Vec<u8>, error::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(next_read) = self.next_read {
+            let now = Instant::now();
+            if next_read > now {
+                std::thread::sleep(next_read - now);
+            }
+        }
+
+        // A late read is not caught up with, the following reads are due one interval after it.
+        let started = Instant::now();
+        self.next_read = Some(match self.next_read {
+            Some(next_read) => (next_read + self.interval).max(started),
+            None => started + self.interval,
+        });
+
+        let mut data = vec![0; self.len];
+        let result = self
+            .memory
+            .read_stream(self.address, &mut data, self.restart);
+        self.restart = result.is_err();
+
+        Some(result.map(|()| data))
+    }
+}
+
+/// Reads the readable start of `data`, by splitting failed reads at word boundaries.
+fn read_bisected<M: MemoryInterface + ?Sized>(
+    memory: &mut M,
+    address: u32,
+    data: &mut [u8],
+) -> Result<usize, error::Error> {
+    let error = match memory.read_8(address, data) {
+        Ok(()) => return Ok(data.len()),
+        Err(error) => error,
+    };
+
+    // The bytes up to the next word boundary are the smallest unit which is split.
+    let first_word = 4 - (address % 4) as usize;
+    if data.len() <= first_word {
+        return Err(error);
+    }
+
+    // Split at the word boundary closest to the middle, but after the first word.
+    let middle = ((address as usize + data.len() / 2) & !3).saturating_sub(address as usize);
+    let middle = middle.max(first_word);
+
+    let (start, end) = data.split_at_mut(middle);
+    let read = read_bisected(memory, address, start)?;
+    if read < start.len() {
+        return Ok(read);
+    }
+
+    Ok(middle + read_bisected(memory, address + middle as u32, end).unwrap_or(0))
+}
+
 pub struct Memory<'probe> {
     inner: Box<dyn ArmProbe + 'probe>,
     ap_sel: MemoryAP,
@@ -130,6 +404,15 @@ impl<'probe> Memory<'probe> {
         self.inner.read_8(self.ap_sel, address, data)
     }
 
+    pub fn read_stream(
+        &mut self,
+        address: u32,
+        data: &mut [u8],
+        restart: bool,
+    ) -> Result<(), error::Error> {
+        self.inner.read_stream(self.ap_sel, address, data, restart)
+    }
+
     pub fn write_word_32(&mut self, addr: u32, data: u32) -> Result<(), error::Error> {
         self.inner.write_32(self.ap_sel, addr, &[data])
     }
@@ -146,10 +429,69 @@ impl<'probe> Memory<'probe> {
         self.inner.write_8(self.ap_sel, addr, data)
     }
 
+    /// Read a 64bit word at `address`.
+    ///
+    /// If the AP does not support 64bit accesses, the lower half is read first.
+    pub fn read_word_64(&mut self, address: u32) -> Result<u64, error::Error> {
+        let mut buff = [0u8; 8];
+
+        if self.inner.supports_access_size(AccessSize::U64) {
+            self.inner
+                .read_with_size(self.ap_sel, address, &mut buff, AccessSize::U64)?;
+        } else {
+            self.inner
+                .read_with_size(self.ap_sel, address, &mut buff[..4], AccessSize::U32)?;
+            self.inner
+                .read_with_size(self.ap_sel, address + 4, &mut buff[4..], AccessSize::U32)?;
+        }
+
+        Ok(u64::from_le_bytes(buff))
+    }
+
+    /// Write a 64bit word at `address`.
+    ///
+    /// If the AP does not support 64bit accesses, the lower half is written first.
+    pub fn write_word_64(&mut self, addr: u32, data: u64) -> Result<(), error::Error> {
+        let buff = data.to_le_bytes();
+
+        if self.inner.supports_access_size(AccessSize::U64) {
+            self.inner
+                .write_with_size(self.ap_sel, addr, &buff, AccessSize::U64)
+        } else {
+            self.inner
+                .write_with_size(self.ap_sel, addr, &buff[..4], AccessSize::U32)?;
+            self.inner
+                .write_with_size(self.ap_sel, addr + 4, &buff[4..], AccessSize::U32)
+        }
+    }
+
+    pub fn read_mem_with_size(
+        &mut self,
+        address: u32,
+        data: &mut [u8],
+        size: AccessSize,
+    ) -> Result<(), error::Error> {
+        self.inner.read_with_size(self.ap_sel, address, data, size)
+    }
+
+    pub fn write_mem_with_size(
+        &mut self,
+        addr: u32,
+        data: &[u8],
+        size: AccessSize,
+    ) -> Result<(), error::Error> {
+        self.inner.write_with_size(self.ap_sel, addr, data, size)
+    }
+
     pub fn flush(&mut self) -> Result<(), error::Error> {
         self.inner.flush()
     }
 
+    /// Resets the target with the reset pin of the debug probe.
+    pub fn target_reset(&mut self) -> Result<(), error::Error> {
+        self.inner.target_reset()
+    }
+
     pub fn read_core_reg(&mut self, addr: CoreRegisterAddress) -> Result<u32, error::Error> {
         self.inner.read_core_reg(self.ap_sel, addr)
     }
@@ -161,6 +503,20 @@ impl<'probe> Memory<'probe> {
     ) -> Result<(), error::Error> {
         self.inner.write_core_reg(self.ap_sel, addr, value)
     }
+
+    pub fn read_core_regs(
+        &mut self,
+        addrs: &[CoreRegisterAddress],
+    ) -> Vec<Result<u32, error::Error>> {
+        self.inner.read_core_regs(self.ap_sel, addrs)
+    }
+
+    pub fn write_core_regs(
+        &mut self,
+        values: &[(CoreRegisterAddress, u32)],
+    ) -> Vec<Result<(), error::Error>> {
+        self.inner.write_core_regs(self.ap_sel, values)
+    }
 }
 
 pub struct MemoryList<'probe>(Vec<Memory<'probe>>);
@@ -0,0 +1,188 @@
+use crate::config::{OptionBytes, OptionField, RegisterWrite, StatusBit};
+use crate::{Core, Error, MemoryInterface, Session};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// The time after which programming the option bytes is aborted.
+const PROGRAM_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The time the target is given to come out of reset after the option bytes were loaded.
+const LAUNCH_DELAY: Duration = Duration::from_millis(100);
+
+/// An error which occured while reading or modifying the option bytes.
+#[derive(Debug, Error)]
+pub enum OptionBytesError {
+    /// The target description does not describe the option bytes.
+    #[error("The option bytes of this target are not supported.")]
+    NotSupported,
+    /// The target has no option field with the given name.
+    #[error("The target has no option field named '{0}'.")]
+    UnknownField(String),
+    /// The value does not fit into the option field.
+    #[error("The value {value:#x} does not fit into the option field '{name}'.")]
+    ValueTooLarge {
+        /// The name of the option field.
+        name: String,
+        /// The value which was written.
+        value: u32,
+    },
+    /// Programming the option bytes did not complete in time.
+    #[error("Programming the option bytes did not complete within {0:?}.")]
+    Timeout(Duration),
+}
+
+/// Reads and modifies the option bytes of a target, e.g. its read protection level.
+///
+/// This is created with [Session::target_options].
+pub struct TargetOptions<'session> {
+    session: &'session mut Session,
+}
+
+impl<'session> TargetOptions<'session> {
+    pub(crate) fn new(session: &'session mut Session) -> Self {
+        Self { session }
+    }
+
+    fn option_bytes(&self) -> Result<OptionBytes, OptionBytesError> {
+        self.session
+            .target()
+            .option_bytes
+            .clone()
+            .ok_or(OptionBytesError::NotSupported)
+    }
+
+    /// Returns the option fields of the target.
+    pub fn fields(&self) -> Result<Vec<OptionField>, Error> {
+        Ok(self.option_bytes()?.fields.to_vec())
+    }
+
+    /// Reads the current value of the option field `name`.
+    pub fn read(&mut self, name: &str) -> Result<u32, Error> {
+        let option_bytes = self.option_bytes()?;
+        let field = option_bytes
+            .field(name)
+            .ok_or_else(|| OptionBytesError::UnknownField(name.to_string()))?;
+
+        let mut core = self.session.core(0)?;
+        Ok(field.extract(core.read_word_32(field.address)?))
+    }
+
+    /// Reads the current values of all option fields.
+    pub fn read_all(&mut self) -> Result<Vec<(OptionField, u32)>, Error> {
+        let option_bytes = self.option_bytes()?;
+
+        let mut core = self.session.core(0)?;
+        option_bytes
+            .fields
+            .iter()
+            .map(|field| {
+                Ok((
+                    field.clone(),
+                    field.extract(core.read_word_32(field.address)?),
+                ))
+            })
+            .collect()
+    }
+
+    /// Sets the option field `name` to `value` and loads the new option bytes.
+    ///
+    /// Loading the option bytes usually resets the target,
+    /// the session is attached to it again afterwards.
+    pub fn set(&mut self, name: &str, value: u32) -> Result<(), Error> {
+        self.set_all(&[(name, value)])
+    }
+
+    /// Sets multiple option fields at once and loads the new option bytes.
+    ///
+    /// Loading the option bytes usually resets the target,
+    /// the session is attached to it again afterwards.
+    pub fn set_all(&mut self, values: &[(&str, u32)]) -> Result<(), Error> {
+        let option_bytes = self.option_bytes()?;
+
+        // Check all the values before anything is modified.
+        let fields = values
+            .iter()
+            .map(|(name, value)| {
+                option_bytes
+                    .field(name)
+                    .map(|field| (field, *value))
+                    .ok_or_else(|| OptionBytesError::UnknownField(name.to_string()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for (field, value) in &fields {
+            if field.insert(0, *value).is_none() {
+                return Err(OptionBytesError::ValueTooLarge {
+                    name: field.name.to_string(),
+                    value: *value,
+                }
+                .into());
+            }
+        }
+
+        {
+            let mut core = self.session.core(0)?;
+            core.halt(Duration::from_millis(100))?;
+
+            if let Some(lock) = &option_bytes.lock {
+                if is_set(&mut core, lock)? {
+                    log::debug!("Unlocking the option bytes");
+                    write_sequence(&mut core, &option_bytes.unlock)?;
+                }
+            }
+
+            for (field, value) in &fields {
+                let register = core.read_word_32(field.address)?;
+                // The value was checked above, so it always fits.
+                let register = field.insert(register, *value).unwrap_or(register);
+
+                log::debug!("Setting {} to {:#x}", field.name, value);
+                core.write_word_32(field.address, register)?;
+            }
+
+            write_sequence(&mut core, &option_bytes.program)?;
+
+            if let Some(busy) = &option_bytes.busy {
+                let start = Instant::now();
+                while is_set(&mut core, busy)? {
+                    if start.elapsed() > PROGRAM_TIMEOUT {
+                        return Err(OptionBytesError::Timeout(PROGRAM_TIMEOUT).into());
+                    }
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+            }
+
+            if option_bytes.launch.is_empty() {
+                return Ok(());
+            }
+
+            // The target usually resets while the launch sequence is written,
+            // so the connection can break before the writes are acknowledged.
+            if let Err(error) = write_sequence(&mut core, &option_bytes.launch) {
+                log::debug!("Loading the option bytes reset the target: {}", error);
+            }
+        }
+
+        std::thread::sleep(LAUNCH_DELAY);
+
+        self.session.reattach()
+    }
+}
+
+fn is_set(core: &mut Core, bit: &StatusBit) -> Result<bool, Error> {
+    Ok(core.read_word_32(bit.address)? & bit.mask != 0)
+}
+
+fn write_sequence(core: &mut Core, sequence: &[RegisterWrite]) -> Result<(), Error> {
+    for write in sequence {
+        let value = if write.mask == 0xFFFF_FFFF {
+            write.value
+        } else {
+            write.apply(core.read_word_32(write.address)?)
+        };
+
+        core.write_word_32(write.address, value)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,57 @@
+use crate::Error;
+
+/// The operations which are allowed on top of the usual debugging and flashing.
+///
+/// Some operations, like unlocking a read protected chip, irrecoverably destroy
+/// the contents of the chip and have to be explicitly allowed.
+#[derive(Debug, Clone, Default)]
+pub struct Permissions {
+    /// When set to true, all operations which fully erase the chip are allowed.
+    erase_all: bool,
+    /// When set to true, one-time programmable regions can be written.
+    otp_write: bool,
+}
+
+impl Permissions {
+    /// Constructs a new permissions object with the default values.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow operations which fully erase the chip, including its configuration
+    /// and protection settings.
+    pub fn allow_erase_all(self) -> Self {
+        Self {
+            erase_all: true,
+            ..self
+        }
+    }
+
+    /// Allow writing to one-time programmable regions, like eFuses, OTP areas or the UICR of nRF chips.
+    ///
+    /// Bits which are programmed in these regions can not, or only with a full chip erase, be cleared again.
+    pub fn allow_otp_write(self) -> Self {
+        Self {
+            otp_write: true,
+            ..self
+        }
+    }
+
+    /// Returns an error if erasing the complete chip is not allowed.
+    pub(crate) fn erase_all(&self) -> Result<(), Error> {
+        if self.erase_all {
+            Ok(())
+        } else {
+            Err(Error::MissingPermissions("erase_all".into()))
+        }
+    }
+
+    /// Returns an error if writing to one-time programmable regions is not allowed.
+    pub(crate) fn otp_write(&self) -> Result<(), Error> {
+        if self.otp_write {
+            Ok(())
+        } else {
+            Err(Error::MissingPermissions("otp_write".into()))
+        }
+    }
+}
@@ -0,0 +1,859 @@
+//! Support for the Black Magic Probe, using its remote protocol.
+//!
+//! The remote protocol is spoken on the serial port which also provides the GDB server
+//! of the probe. It gives access to raw SWD and JTAG sequences, the DAP transfers and
+//! JTAG register accesses are assembled from these sequences here.
+
+use crate::{
+    architecture::{
+        arm::{
+            communication_interface::ArmProbeInterface, dp::RdBuff, ArmCommunicationInterface,
+            DapError, PortType, Register,
+        },
+        riscv::communication_interface::RiscvCommunicationInterface,
+    },
+    probe::{
+        swd_request, DAPAccess, DebugProbe, DebugProbeError, DebugProbeInfo, DebugProbeSelector,
+        DebugProbeType, JTAGAccess, PowerCapabilities, ProbeCreationError, ProbeFactory,
+        WireProtocol,
+    },
+};
+use serialport::SerialPortType;
+use std::fmt;
+use std::io::{Read, Write};
+use std::time::Duration;
+use thiserror::Error;
+
+const VENDOR_ID: u16 = 0x1d50;
+const PRODUCT_ID: u16 = 0x6018;
+
+/// How long to wait for a response of the probe.
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The start of a packet sent to the probe.
+const PACKET_START: u8 = b'!';
+/// The end of a packet, in both directions.
+const PACKET_END: u8 = b'#';
+/// The start of a response from the probe.
+const RESPONSE_START: u8 = b'&';
+/// Responses are short, longer ones mean that the probe does not speak the remote protocol.
+const MAX_RESPONSE_LENGTH: usize = 1024;
+
+/// The ACK values of an SWD transfer, as they are read from the line.
+const ACK_OK: u32 = 0b001;
+const ACK_WAIT: u32 = 0b010;
+const ACK_FAULT: u32 = 0b100;
+
+/// The number of times a transfer is retried after a WAIT or a missing response.
+const TRANSFER_RETRIES: usize = 5;
+
+/// The maximum number of bits in a single JTAG shift of the remote protocol.
+const MAX_JTAG_SHIFT: usize = 64;
+
+#[derive(Debug, Error)]
+pub enum BlackMagicProbeError {
+    #[error("The probe returned an error for the command '{0}'")]
+    ErrorResponse(String),
+    #[error("The command '{0}' is not supported by the firmware of the probe")]
+    NotSupported(String),
+    #[error("The probe returned the invalid response '{0}'")]
+    InvalidResponse(String),
+    #[error("Error communicating with the probe")]
+    Io(#[from] std::io::Error),
+}
+
+impl From<BlackMagicProbeError> for DebugProbeError {
+    fn from(error: BlackMagicProbeError) -> Self {
+        DebugProbeError::ProbeSpecific(Box::new(error))
+    }
+}
+
+/// The serial connection to the probe.
+trait Connection: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Connection for T {}
+
+pub(crate) struct BlackMagicProbe {
+    connection: Box<dyn Connection>,
+
+    /// The firmware version the probe reported.
+    version: String,
+
+    /// Currently selected protocol
+    protocol: WireProtocol,
+
+    /// Idle cycles necessary between consecutive
+    /// accesses to the DMI register
+    jtag_idle_cycles: u8,
+
+    current_ir_reg: u32,
+}
+
+impl fmt::Debug for BlackMagicProbe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BlackMagicProbe")
+            .field("version", &self.version)
+            .field("protocol", &self.protocol)
+            .finish()
+    }
+}
+
+impl BlackMagicProbe {
+    /// Starts the remote protocol on `connection`.
+    fn new(connection: Box<dyn Connection>) -> Result<Self, DebugProbeError> {
+        let mut probe = Self {
+            connection,
+            version: String::new(),
+            protocol: WireProtocol::Swd,
+            jtag_idle_cycles: 0,
+            current_ir_reg: u32::MAX,
+        };
+
+        probe.version = probe.request("GA")?;
+        log::info!("Black Magic Probe: Firmware version: {}", probe.version);
+
+        // Firmware versions which only support the generic commands do not know this command.
+        match probe.request("HC") {
+            Ok(version) => log::debug!("Black Magic Probe: Remote protocol version {}", version),
+            Err(BlackMagicProbeError::NotSupported(_)) => {
+                log::debug!("Black Magic Probe: Remote protocol version 0")
+            }
+            Err(error) => return Err(error.into()),
+        }
+
+        Ok(probe)
+    }
+
+    /// Sends the packet `command` and returns the response code and the data of the response.
+    fn transact(&mut self, command: &str) -> Result<(u8, String), BlackMagicProbeError> {
+        log::trace!("Sending '{}'", command);
+
+        let mut packet = Vec::with_capacity(command.len() + 2);
+        packet.push(PACKET_START);
+        packet.extend_from_slice(command.as_bytes());
+        packet.push(PACKET_END);
+
+        self.connection.write_all(&packet)?;
+        self.connection.flush()?;
+
+        let mut byte = [0];
+
+        // Output of the GDB server which was left in the buffer is skipped.
+        loop {
+            self.connection.read_exact(&mut byte)?;
+            if byte[0] == RESPONSE_START {
+                break;
+            }
+        }
+
+        let mut response = vec![];
+        loop {
+            self.connection.read_exact(&mut byte)?;
+            if byte[0] == PACKET_END {
+                break;
+            }
+
+            response.push(byte[0]);
+            if response.len() > MAX_RESPONSE_LENGTH {
+                return Err(BlackMagicProbeError::InvalidResponse(
+                    String::from_utf8_lossy(&response).into_owned(),
+                ));
+            }
+        }
+
+        let response = String::from_utf8_lossy(&response).into_owned();
+        log::trace!("Received '{}'", response);
+
+        let mut characters = response.chars();
+        match characters.next() {
+            Some(code) if code.is_ascii() => Ok((code as u8, characters.collect())),
+            _ => Err(BlackMagicProbeError::InvalidResponse(response)),
+        }
+    }
+
+    /// Sends the packet `command` and returns the data of the response, if it was successful.
+    fn request(&mut self, command: &str) -> Result<String, BlackMagicProbeError> {
+        match self.transact(command)? {
+            (b'K', data) => Ok(data),
+            (b'N', _) => Err(BlackMagicProbeError::NotSupported(command.to_owned())),
+            (b'E', _) | (b'P', _) => Err(BlackMagicProbeError::ErrorResponse(command.to_owned())),
+            (code, data) => Err(BlackMagicProbeError::InvalidResponse(format!(
+                "{}{}",
+                code as char, data
+            ))),
+        }
+    }
+
+    /// Sends the packet `command` and returns the number in the response.
+    fn request_value(&mut self, command: &str) -> Result<u64, BlackMagicProbeError> {
+        let data = self.request(command)?;
+        parse_value(&data)
+    }
+
+    fn swd_out(&mut self, value: u32, bits: u8) -> Result<(), BlackMagicProbeError> {
+        self.request(&format!("So{:02x}{:x}", bits, value))
+            .map(|_| ())
+    }
+
+    fn swd_out_parity(&mut self, value: u32, bits: u8) -> Result<(), BlackMagicProbeError> {
+        self.request(&format!("SO{:02x}{:x}", bits, value))
+            .map(|_| ())
+    }
+
+    fn swd_in(&mut self, bits: u8) -> Result<u32, BlackMagicProbeError> {
+        self.request_value(&format!("Si{:02x}", bits))
+            .map(|value| value as u32)
+    }
+
+    /// Reads `bits` bits followed by a parity bit, returns `None` if the parity is wrong.
+    fn swd_in_parity(&mut self, bits: u8) -> Result<Option<u32>, BlackMagicProbeError> {
+        let command = format!("SI{:02x}", bits);
+        match self.transact(&command)? {
+            (b'K', data) => parse_value(&data).map(|value| Some(value as u32)),
+            (b'P', _) => Ok(None),
+            (b'N', _) => Err(BlackMagicProbeError::NotSupported(command)),
+            (b'E', _) => Err(BlackMagicProbeError::ErrorResponse(command)),
+            (code, data) => Err(BlackMagicProbeError::InvalidResponse(format!(
+                "{}{}",
+                code as char, data
+            ))),
+        }
+    }
+
+    /// Performs a SWD line reset, followed by a read of the DPIDR register.
+    ///
+    /// See section B4.3.3 in the ADIv5 Specification.
+    fn swd_line_reset(&mut self) -> Result<(), DebugProbeError> {
+        log::debug!("Performing line reset!");
+
+        // At least 50 cycles with SWDIO high, followed by two idle cycles.
+        self.swd_out(0xffff_ffff, 32)?;
+        self.swd_out(0xffff_ffff, 32)?;
+        self.swd_out(0, 2)?;
+
+        match self.transfer_once(PortType::DebugPort, 0, None)? {
+            Ok(_) => Ok(()),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// Performs a single SWD transfer, which writes `value`, or reads if it is `None`.
+    ///
+    /// The outer result contains errors of the probe, the inner one the response of the target.
+    fn transfer_once(
+        &mut self,
+        port: PortType,
+        address: u16,
+        value: Option<u32>,
+    ) -> Result<Result<u32, DapError>, BlackMagicProbeError> {
+        let request = swd_request(port, value.is_none(), address);
+
+        // The probe inserts the turnaround cycles when the direction of SWDIO changes.
+        self.swd_out(request.into(), 8)?;
+
+        match self.swd_in(3)? {
+            ACK_OK => (),
+            ACK_WAIT => return Ok(Err(DapError::WaitResponse)),
+            ACK_FAULT => return Ok(Err(DapError::FaultResponse)),
+            _ => return Ok(Err(DapError::NoAcknowledge)),
+        }
+
+        match value {
+            Some(value) => {
+                self.swd_out_parity(value, 32)?;
+
+                // Idle cycles to ensure the write is performed.
+                // See section B4.1.1 in the ARM Debug Interface specification.
+                self.swd_out(0, 8)?;
+
+                Ok(Ok(0))
+            }
+            None => Ok(self.swd_in_parity(32)?.ok_or(DapError::IncorrectParity)),
+        }
+    }
+
+    /// Performs a SWD transfer, retrying it after WAIT responses and missing responses.
+    fn transfer(
+        &mut self,
+        port: PortType,
+        address: u16,
+        value: Option<u32>,
+    ) -> Result<u32, DebugProbeError> {
+        let mut result = Err(DapError::NoAcknowledge);
+
+        for retry in 0..TRANSFER_RETRIES {
+            result = self.transfer_once(port, address, value)?;
+
+            match result {
+                Err(DapError::WaitResponse) => {
+                    log::debug!(
+                        "DAP WAIT, retries remaining {}.",
+                        TRANSFER_RETRIES - retry - 1
+                    );
+                }
+                Err(DapError::NoAcknowledge) => {
+                    log::debug!("DAP NACK");
+
+                    // The target might have lost track of the transfers, a line reset
+                    // brings it back into a known state.
+                    if let Err(error) = self.swd_line_reset() {
+                        log::debug!("Line reset failed: {}", error);
+                    }
+                }
+                // The sticky error flags are left set, the ARM interface
+                // reads why the transfer failed and clears them.
+                Err(DapError::FaultResponse) => return Err(DapError::FaultResponse.into()),
+                _ => break,
+            }
+        }
+
+        result.map_err(DebugProbeError::from)
+    }
+
+    fn jtag_tms(&mut self, tms: u32, bits: u8) -> Result<(), BlackMagicProbeError> {
+        self.request(&format!("JT{:02x}{:x}", bits, tms))
+            .map(|_| ())
+    }
+
+    /// Shifts `data` through the selected register, starting in a shift state.
+    ///
+    /// The state is left with the last bit, to the exit state of the register.
+    fn jtag_shift(&mut self, data: &[u8], bits: usize) -> Result<Vec<u8>, DebugProbeError> {
+        let mut result = vec![0; (bits + 7) / 8];
+        let mut offset = 0;
+
+        while offset < bits {
+            let count = (bits - offset).min(MAX_JTAG_SHIFT);
+            let last = offset + count == bits;
+
+            let mut tdi = 0u64;
+            for n in 0..count {
+                let bit = offset + n;
+                if data
+                    .get(bit / 8)
+                    .map_or(false, |byte| byte >> (bit % 8) & 1 == 1)
+                {
+                    tdi |= 1 << n;
+                }
+            }
+
+            let command = if last { 'D' } else { 'd' };
+            let tdo = self.request_value(&format!("J{}{:02x}{:x}", command, count, tdi))?;
+
+            for n in 0..count {
+                if tdo >> n & 1 == 1 {
+                    let bit = offset + n;
+                    result[bit / 8] |= 1 << (bit % 8);
+                }
+            }
+
+            offset += count;
+        }
+
+        Ok(result)
+    }
+
+    /// Write IR register with the specified data, which is truncated to `len` bits.
+    fn write_ir(&mut self, data: &[u8], len: usize) -> Result<(), DebugProbeError> {
+        log::debug!("Write IR: {:?}, len={}", data, len);
+
+        // Select-DR-Scan, Select-IR-Scan, Capture-IR, Shift-IR
+        self.jtag_tms(0b0011, 4)?;
+        self.jtag_shift(data, len)?;
+        // Update-IR, Run-Test/Idle
+        self.jtag_tms(0b01, 2)?;
+
+        self.current_ir_reg = data[0] as u32;
+
+        Ok(())
+    }
+
+    /// Shifts `data` through the data register, and returns the data which was shifted out.
+    fn write_dr(&mut self, data: &[u8], register_bits: usize) -> Result<Vec<u8>, DebugProbeError> {
+        log::debug!("Write DR: {:?}, len={}", data, register_bits);
+
+        // Select-DR-Scan, Capture-DR, Shift-DR
+        self.jtag_tms(0b001, 3)?;
+        let result = self.jtag_shift(data, register_bits)?;
+        // Update-DR, Run-Test/Idle
+        self.jtag_tms(0b01, 2)?;
+
+        // We need to stay in the idle state a bit
+        if self.jtag_idle_cycles > 0 {
+            self.jtag_tms(0, self.jtag_idle_cycles)?;
+        }
+
+        Ok(result)
+    }
+}
+
+impl DebugProbe for BlackMagicProbe {
+    fn new_from_selector(
+        selector: impl Into<DebugProbeSelector>,
+    ) -> Result<Box<Self>, DebugProbeError>
+    where
+        Self: Sized,
+    {
+        let selector = selector.into();
+
+        if selector.vendor_id != VENDOR_ID || selector.product_id != PRODUCT_ID {
+            return Err(DebugProbeError::ProbeCouldNotBeCreated(
+                ProbeCreationError::NotFound,
+            ));
+        }
+
+        // The serial port information does not contain the USB port.
+        if selector.port_path.is_some() {
+            return Err(DebugProbeError::ProbeCouldNotBeCreated(
+                ProbeCreationError::Other(
+                    "Black Magic Probes can not be selected by their USB port",
+                ),
+            ));
+        }
+
+        let (port_name, _) = remote_ports()
+            .into_iter()
+            .find(|(_, serial_number)| {
+                selector.serial_number.is_none() || selector.serial_number == *serial_number
+            })
+            .ok_or(DebugProbeError::ProbeCouldNotBeCreated(
+                ProbeCreationError::NotFound,
+            ))?;
+
+        let port = serialport::new(&port_name, 115_200)
+            .timeout(TIMEOUT)
+            .open()
+            .map_err(|e| {
+                DebugProbeError::ProbeCouldNotBeCreated(ProbeCreationError::ProbeSpecific(
+                    Box::new(e),
+                ))
+            })?;
+
+        log::debug!("Opened the Black Magic Probe on {}", port_name);
+
+        Ok(Box::new(Self::new(Box::new(port))?))
+    }
+
+    fn get_name(&self) -> &str {
+        "Black Magic Probe"
+    }
+
+    /// The probe selects the speed on its own.
+    fn speed(&self) -> u32 {
+        0
+    }
+
+    fn set_speed(&mut self, speed_khz: u32) -> Result<u32, DebugProbeError> {
+        Err(DebugProbeError::UnsupportedSpeed(speed_khz))
+    }
+
+    fn attach(&mut self) -> Result<(), DebugProbeError> {
+        log::debug!("Attaching to the Black Magic Probe");
+
+        match self.request("GV") {
+            Ok(voltage) => log::info!("Black Magic Probe: Target voltage: {}", voltage),
+            Err(error) => log::debug!("Unable to read the target voltage: {}", error),
+        }
+
+        match self.protocol {
+            WireProtocol::Swd => {
+                self.request("SS")?;
+
+                // Send the reset sequence (> 50 1-bits), followed by the JTAG to SWD sequence.
+                self.swd_out(0xffff_ffff, 32)?;
+                self.swd_out(0xffff_ffff, 32)?;
+                self.swd_out(0xe79e, 16)?;
+
+                self.swd_line_reset()?;
+                log::debug!("Sucessfully switched to SWD");
+            }
+            WireProtocol::Jtag => {
+                self.request("JS")?;
+
+                // Test-Logic-Reset, then Run-Test/Idle.
+                self.request("JR")?;
+                self.jtag_tms(0, 1)?;
+
+                // The instruction selected after the reset depends on the TAP.
+                self.current_ir_reg = u32::MAX;
+            }
+        }
+
+        log::debug!("Attached succesfully");
+
+        Ok(())
+    }
+
+    fn detach(&mut self) -> Result<(), DebugProbeError> {
+        Ok(())
+    }
+
+    fn target_reset(&mut self) -> Result<(), DebugProbeError> {
+        self.target_reset_assert()?;
+        std::thread::sleep(Duration::from_millis(10));
+        self.target_reset_deassert()
+    }
+
+    fn target_reset_assert(&mut self) -> Result<(), DebugProbeError> {
+        self.request("GZ1")?;
+        Ok(())
+    }
+
+    fn target_reset_deassert(&mut self) -> Result<(), DebugProbeError> {
+        self.request("GZ0")?;
+        Ok(())
+    }
+
+    fn select_protocol(&mut self, protocol: WireProtocol) -> Result<(), DebugProbeError> {
+        self.protocol = protocol;
+        Ok(())
+    }
+
+    fn power_capabilities(&self) -> PowerCapabilities {
+        PowerCapabilities {
+            target_voltage: true,
+            target_power: true,
+            ..Default::default()
+        }
+    }
+
+    fn target_voltage(&mut self) -> Result<f32, DebugProbeError> {
+        let data = self.request("GV")?;
+
+        // Hardware without voltage measurement reports e.g. "ABSENT!" or "Unknown".
+        data.trim_end_matches('V').parse().map_err(|_| {
+            log::debug!("Black Magic Probe reported the target voltage {:?}", data);
+            DebugProbeError::CommandNotSupportedByProbe
+        })
+    }
+
+    fn set_target_power(&mut self, enabled: bool) -> Result<(), DebugProbeError> {
+        match self.request(if enabled { "GP1" } else { "GP0" }) {
+            Ok(_) => Ok(()),
+            // Only some hardware versions can power the target.
+            Err(BlackMagicProbeError::NotSupported(_)) => {
+                Err(DebugProbeError::CommandNotSupportedByProbe)
+            }
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    fn has_arm_interface(&self) -> bool {
+        self.protocol == WireProtocol::Swd
+    }
+
+    fn get_arm_interface<'probe>(
+        self: Box<Self>,
+    ) -> Result<Option<Box<dyn ArmProbeInterface + 'probe>>, DebugProbeError> {
+        if self.protocol == WireProtocol::Swd {
+            let interface = ArmCommunicationInterface::new(self, false)?;
+
+            Ok(Some(Box::new(interface)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn has_riscv_interface(&self) -> bool {
+        self.protocol == WireProtocol::Jtag
+    }
+
+    fn get_riscv_interface(
+        self: Box<Self>,
+    ) -> Result<Option<RiscvCommunicationInterface>, DebugProbeError> {
+        if self.protocol == WireProtocol::Jtag {
+            Ok(Some(RiscvCommunicationInterface::new(self)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn get_dap_interface_mut(&mut self) -> Option<&mut dyn DAPAccess> {
+        Some(self as _)
+    }
+
+    fn get_jtag_interface_mut(&mut self) -> Option<&mut dyn JTAGAccess> {
+        Some(self as _)
+    }
+}
+
+impl DAPAccess for BlackMagicProbe {
+    fn read_register(&mut self, port: PortType, address: u16) -> Result<u32, DebugProbeError> {
+        let value = self.transfer(port, address, None)?;
+
+        // If we are reading an AP register we only get the actual result in the next transaction.
+        // So we issue a special transaction to get the read value.
+        if let PortType::AccessPort(_) = port {
+            // We read the RDBUFF register to get the value of the last AP transaction.
+            // This special register just returns the last read value with no side-effects like auto-increment.
+            self.transfer(PortType::DebugPort, RdBuff::ADDRESS as u16, None)
+        } else {
+            Ok(value)
+        }
+    }
+
+    fn write_register(
+        &mut self,
+        port: PortType,
+        address: u16,
+        value: u32,
+    ) -> Result<(), DebugProbeError> {
+        self.transfer(port, address, Some(value))?;
+        Ok(())
+    }
+
+    fn into_probe(self: Box<Self>) -> Box<dyn DebugProbe> {
+        self
+    }
+}
+
+impl JTAGAccess for BlackMagicProbe {
+    /// Read the data register
+    fn read_register(&mut self, address: u32, len: u32) -> Result<Vec<u8>, DebugProbeError> {
+        let data = vec![0; (len as usize + 7) / 8];
+        JTAGAccess::write_register(self, address, &data, len)
+    }
+
+    /// Write the data register
+    fn write_register(
+        &mut self,
+        address: u32,
+        data: &[u8],
+        len: u32,
+    ) -> Result<Vec<u8>, DebugProbeError> {
+        // TODO: This is limited to 5 bit addresses for now
+        if address > 0x1f {
+            return Err(DebugProbeError::NotImplemented(
+                "JTAG Register addresses are fixed to 5 bits",
+            ));
+        }
+
+        if self.current_ir_reg != address {
+            // Write IR register
+            self.write_ir(&address.to_le_bytes()[..1], 5)?;
+        }
+
+        self.write_dr(data, len as usize)
+    }
+
+    fn set_idle_cycles(&mut self, idle_cycles: u8) {
+        self.jtag_idle_cycles = idle_cycles;
+    }
+
+    fn into_probe(self: Box<Self>) -> Box<dyn DebugProbe> {
+        self
+    }
+}
+
+impl<'a> AsRef<dyn DebugProbe + 'a> for BlackMagicProbe {
+    fn as_ref(&self) -> &(dyn DebugProbe + 'a) {
+        self
+    }
+}
+
+impl<'a> AsMut<dyn DebugProbe + 'a> for BlackMagicProbe {
+    fn as_mut(&mut self) -> &mut (dyn DebugProbe + 'a) {
+        self
+    }
+}
+
+/// Parses a number in a response, which the probe sends as hex digits.
+fn parse_value(data: &str) -> Result<u64, BlackMagicProbeError> {
+    u64::from_str_radix(data, 16)
+        .map_err(|_| BlackMagicProbeError::InvalidResponse(data.to_owned()))
+}
+
+/// Returns the serial ports which speak the remote protocol, with the serial numbers of their probes.
+///
+/// Each probe provides two serial ports, the GDB port which speaks the remote protocol
+/// is the one which is enumerated first.
+fn remote_ports() -> Vec<(String, Option<String>)> {
+    let ports = match serialport::available_ports() {
+        Ok(ports) => ports,
+        Err(_) => return vec![],
+    };
+
+    let mut ports = ports
+        .into_iter()
+        .filter_map(|port| match port.port_type {
+            SerialPortType::UsbPort(info) if info.vid == VENDOR_ID && info.pid == PRODUCT_ID => {
+                Some((port.port_name, info.serial_number))
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    ports.sort_by(|(name_a, serial_a), (name_b, serial_b)| {
+        serial_a.cmp(serial_b).then_with(|| name_a.cmp(name_b))
+    });
+    ports.dedup_by(|(_, serial_a), (_, serial_b)| serial_a == serial_b);
+
+    ports
+}
+
+/// Finds and opens Black Magic Probes.
+#[derive(Debug)]
+pub(crate) struct BlackMagicFactory;
+
+impl ProbeFactory for BlackMagicFactory {
+    fn list_probes(&self) -> Vec<DebugProbeInfo> {
+        list_blackmagic_devices()
+    }
+
+    fn open(&self, selector: &DebugProbeSelector) -> Result<Box<dyn DebugProbe>, DebugProbeError> {
+        Ok(BlackMagicProbe::new_from_selector(selector.clone())?)
+    }
+}
+
+pub(crate) fn list_blackmagic_devices() -> Vec<DebugProbeInfo> {
+    remote_ports()
+        .into_iter()
+        .map(|(_, serial_number)| {
+            DebugProbeInfo::new(
+                "Black Magic Probe",
+                VENDOR_ID,
+                PRODUCT_ID,
+                serial_number,
+                DebugProbeType::BlackMagicProbe,
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{swd_request, BlackMagicProbe};
+    use crate::architecture::arm::{DAPAccess, PortType};
+    use crate::probe::{DebugProbe, JTAGAccess};
+    use std::collections::VecDeque;
+    use std::io::{Read, Write};
+    use std::sync::{Arc, Mutex};
+
+    /// A connection which replies with prepared responses, and records what was sent.
+    struct FakeConnection {
+        responses: VecDeque<u8>,
+        sent: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl Read for FakeConnection {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let count = buf.len().min(self.responses.len());
+            for byte in &mut buf[..count] {
+                *byte = self.responses.pop_front().unwrap();
+            }
+            Ok(count)
+        }
+    }
+
+    impl Write for FakeConnection {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.sent.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Creates a probe which receives `responses` after the start of the remote protocol.
+    fn probe(responses: &str) -> (BlackMagicProbe, Arc<Mutex<Vec<u8>>>) {
+        let sent = Arc::new(Mutex::new(vec![]));
+        let connection = FakeConnection {
+            responses: format!("&KBlack Magic Probe v1.7#&N#{}", responses)
+                .into_bytes()
+                .into(),
+            sent: sent.clone(),
+        };
+
+        let probe = BlackMagicProbe::new(Box::new(connection)).unwrap();
+        sent.lock().unwrap().clear();
+
+        (probe, sent)
+    }
+
+    fn sent(sent: &Arc<Mutex<Vec<u8>>>) -> String {
+        String::from_utf8(sent.lock().unwrap().clone()).unwrap()
+    }
+
+    #[test]
+    fn swd_requests_are_assembled() {
+        // DPIDR read
+        assert_eq!(swd_request(PortType::DebugPort, true, 0x0), 0xa5);
+        // DRW write
+        assert_eq!(swd_request(PortType::AccessPort(0), false, 0xc), 0xbb);
+        // SELECT write
+        assert_eq!(swd_request(PortType::DebugPort, false, 0x8), 0xb1);
+    }
+
+    #[test]
+    fn start_reports_version() {
+        let (probe, _) = probe("");
+        assert_eq!(probe.version, "Black Magic Probe v1.7");
+    }
+
+    #[test]
+    fn dp_registers_are_read() {
+        let (mut probe, sent_packets) = probe("&K0#&K1#&K2ba01477#");
+
+        let value = DAPAccess::read_register(&mut probe, PortType::DebugPort, 0).unwrap();
+
+        assert_eq!(value, 0x2ba0_1477);
+        assert_eq!(sent(&sent_packets), "!So08a5#!Si03#!SI20#");
+    }
+
+    #[test]
+    fn wait_responses_are_retried() {
+        let (mut probe, sent_packets) = probe("&K0#&K2#&K0#&K1#&K0#&K0#");
+
+        DAPAccess::write_register(&mut probe, PortType::DebugPort, 0x8, 0x1234).unwrap();
+
+        assert_eq!(
+            sent(&sent_packets),
+            "!So08b1#!Si03#!So08b1#!Si03#!SO201234#!So080#"
+        );
+    }
+
+    #[test]
+    fn parity_errors_are_reported() {
+        let (mut probe, _) = probe("&K0#&K1#&P2ba01477#");
+
+        assert!(DAPAccess::read_register(&mut probe, PortType::DebugPort, 0).is_err());
+    }
+
+    #[test]
+    fn target_voltage_is_parsed() {
+        let (mut probe, sent_packets) = probe("&K3.28V#&KABSENT!#");
+
+        assert_eq!(DebugProbe::target_voltage(&mut probe).unwrap(), 3.28);
+        assert!(DebugProbe::target_voltage(&mut probe).is_err());
+        assert_eq!(sent(&sent_packets), "!GV#!GV#");
+    }
+
+    #[test]
+    fn long_jtag_registers_are_shifted_in_parts() {
+        let responses = [
+            "&K0#",                // Shift-IR
+            "&K1#",                // IR
+            "&K0#",                // Run-Test/Idle
+            "&K0#",                // Shift-DR
+            "&Kffffffffffffffff#", // first 64 bits
+            "&K3#",                // last 2 bits
+            "&K0#",                // Run-Test/Idle
+        ]
+        .concat();
+        let (mut probe, sent_packets) = probe(&responses);
+
+        let data = [0x11; 9];
+        let result = JTAGAccess::write_register(&mut probe, 0x11, &data, 66).unwrap();
+
+        assert_eq!(
+            result,
+            vec![0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x03]
+        );
+        assert_eq!(
+            sent(&sent_packets),
+            "!JT043#!JD0511#!JT021#!JT031#!Jd401111111111111111#!JD021#!JT021#"
+        );
+    }
+}
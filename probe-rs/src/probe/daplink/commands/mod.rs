@@ -4,9 +4,11 @@ pub mod swj;
 pub mod swo;
 pub mod transfer;
 
+use super::swo_stream::{self, SwoStream};
 use crate::architecture::arm::DapError;
 use crate::DebugProbeError;
 use core::ops::Deref;
+use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::{anyhow, Context, Result};
@@ -29,6 +31,8 @@ pub enum CmsisDapError {
     SWOTraceStreamError,
     #[error("Requested SWO mode is not available on this probe")]
     SWOModeNotAvailable,
+    #[error("After reconnecting, the debug port ID is {read:#010x} instead of {expected:#010x}. A different target is connected")]
+    TargetChanged { expected: u32, read: u32 },
     #[error("Error in the USB HID access")]
     HidApi(#[from] hidapi::HidError),
     #[error("Error in the USB access")]
@@ -50,8 +54,10 @@ pub enum DAPLinkDevice {
     V1(hidapi::HidDevice),
 
     /// CMSIS-DAP v2 over WinUSB/Bulk. Stores an rusb device handle and out/in EP addresses.
+    ///
+    /// The handle is shared with the reader thread of the SWO stream.
     V2 {
-        handle: rusb::DeviceHandle<rusb::Context>,
+        handle: Arc<rusb::DeviceHandle<rusb::Context>>,
         out_ep: u8,
         in_ep: u8,
         swo_ep: Option<u8>,
@@ -59,6 +65,43 @@ pub enum DAPLinkDevice {
 }
 
 impl DAPLinkDevice {
+    /// Discard any responses which are still queued, as otherwise
+    /// we'll get out of sync between requests and responses.
+    pub(super) fn drain(&self) {
+        let mut discard_buffer = [0u8; 1024];
+        loop {
+            let read = match self {
+                DAPLinkDevice::V1(device) => {
+                    device.read_timeout(&mut discard_buffer[..128], 1).ok()
+                }
+                DAPLinkDevice::V2 { handle, in_ep, .. } => handle
+                    .read_bulk(*in_ep, &mut discard_buffer, Duration::from_millis(1))
+                    .ok(),
+            };
+
+            match read {
+                Some(n) if n != 0 => continue,
+                _ => break,
+            }
+        }
+    }
+
+    /// Returns the serial number of the USB device, if it has one.
+    pub(super) fn serial_number(&self) -> Option<String> {
+        match self {
+            DAPLinkDevice::V1(device) => device.get_serial_number_string().ok().flatten(),
+            DAPLinkDevice::V2 { handle, .. } => {
+                let timeout = Duration::from_millis(100);
+                let descriptor = handle.device().device_descriptor().ok()?;
+                let language = *handle.read_languages(timeout).ok()?.first()?;
+
+                handle
+                    .read_serial_number_string(language, &descriptor, timeout)
+                    .ok()
+            }
+        }
+    }
+
     /// Read from the probe into `buf`, returning the number of bytes read on success.
     fn read(&self, buf: &mut [u8]) -> Result<usize> {
         match self {
@@ -100,26 +143,77 @@ impl DAPLinkDevice {
         }
     }
 
-    /// Read into `buf` from the SWO streaming endpoint.
+    /// Start reading the SWO streaming endpoint in the background.
+    ///
+    /// Data which is still queued from an earlier capture is discarded first.
     ///
     /// Returns SWOModeNotAvailable if this device does not support SWO streaming.
     ///
-    /// On timeout, returns Ok(0).
-    pub(super) fn read_swo_stream(&self, buf: &mut [u8], timeout: Duration) -> Result<usize> {
-        match self {
-            DAPLinkDevice::V1(_) => Err(CmsisDapError::SWOModeNotAvailable.into()),
-            DAPLinkDevice::V2 { handle, swo_ep, .. } => match swo_ep {
-                Some(ep) => match handle.read_bulk(*ep, buf, timeout) {
-                    Ok(n) => Ok(n),
-                    Err(rusb::Error::Timeout) => Ok(0),
-                    Err(e) => Err(e.into()),
-                },
-                None => Err(CmsisDapError::SWOModeNotAvailable.into()),
-            },
+    /// The previous stream has to be stopped before, as it shares the device handle.
+    pub(super) fn start_swo_stream(&mut self) -> Result<SwoStream> {
+        let (handle, ep) = match self {
+            DAPLinkDevice::V2 {
+                handle,
+                swo_ep: Some(ep),
+                ..
+            } => (handle, *ep),
+            _ => return Err(CmsisDapError::SWOModeNotAvailable.into()),
+        };
+
+        // The endpoint may have been left halted, e.g. if a previous session was
+        // aborted during a transfer. Clearing the halt needs the only reference
+        // to the handle, which the stopped stream gave back.
+        match Arc::get_mut(handle) {
+            Some(handle) => handle.clear_halt(ep)?,
+            None => log::warn!("The SWO endpoint is still in use, its halt is not cleared"),
+        }
+
+        let handle = handle.clone();
+        self.drain_swo_stream();
+
+        Ok(SwoStream::start(move |buf| {
+            handle.read_bulk(ep, buf, swo_stream::READ_TIMEOUT)
+        }))
+    }
+
+    /// Discard all data which is queued on the SWO streaming endpoint.
+    ///
+    /// This is done after stopping a capture, so that the next capture
+    /// does not start with stale data.
+    pub(super) fn drain_swo_stream(&self) {
+        if let DAPLinkDevice::V2 {
+            handle,
+            swo_ep: Some(ep),
+            ..
+        } = self
+        {
+            let mut discard_buffer = [0u8; 1024];
+            while let Ok(n) = handle.read_bulk(*ep, &mut discard_buffer, Duration::from_millis(10))
+            {
+                if n == 0 {
+                    break;
+                }
+            }
         }
     }
 }
 
+/// Returns true if `error` means that the USB device was disconnected or stopped responding.
+pub(crate) fn is_connection_lost(error: &anyhow::Error) -> bool {
+    if let Some(error) = error.downcast_ref::<rusb::Error>() {
+        matches!(
+            error,
+            rusb::Error::Io
+                | rusb::Error::NoDevice
+                | rusb::Error::NotFound
+                | rusb::Error::Pipe
+                | rusb::Error::Timeout
+        )
+    } else {
+        error.downcast_ref::<hidapi::HidError>().is_some()
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 pub(crate) enum Status {
     DAPOk = 0x00,
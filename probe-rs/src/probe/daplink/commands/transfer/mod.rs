@@ -167,7 +167,19 @@ pub struct TransferResponse {
     /// register value or match value in the order of the Transfer Request.
     ///- for Read Register transfer request: the register value of the CoreSight register.
     ///- no data is sent for other operations.
-    pub transfer_data: u32,
+    ///
+    /// The values are kept as raw bytes, use [TransferResponse::read_value] to get them.
+    pub transfer_data: Vec<u8>,
+}
+
+impl TransferResponse {
+    /// Returns the value of the read with the given `index`, counting only the reads
+    /// of the request.
+    pub fn read_value(&self, index: usize) -> Result<u32> {
+        self.transfer_data
+            .pread_with(index * 4, LE)
+            .map_err(|_| anyhow!("The response contains no value for read {}.", index))
+    }
 }
 
 impl Response for TransferResponse {
@@ -188,8 +200,9 @@ impl Response for TransferResponse {
             // TODO: implement this properly.
             td_timestamp: 0, // scroll::pread_with(buffer[offset + 2..offset + 2 + 4], LE),
             transfer_data: buffer
-                .pread_with(offset + 2, LE)
-                .map_err(|_| anyhow!("This is a bug. Please report it."))?,
+                .get(offset + 2..)
+                .ok_or_else(|| anyhow!("This is a bug. Please report it."))?
+                .to_vec(),
         })
     }
 }
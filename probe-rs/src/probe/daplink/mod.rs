@@ -1,15 +1,16 @@
 pub mod commands;
+mod swo_stream;
 pub mod tools;
 
 use crate::{
     architecture::arm::{
         communication_interface::ArmProbeInterface,
-        dp::{Abort, Ctrl, DPAccess, DPRegister, DebugPortError},
+        dp::{DPAccess, DPRegister, DebugPortError, RdBuff, DPIDR},
         swo::poll_interval_from_buf_size,
         ArmCommunicationInterface, DAPAccess, DapError, PortType, Register, SwoAccess, SwoConfig,
         SwoMode,
     },
-    probe::{daplink::commands::CmsisDapError, BatchCommand},
+    probe::{daplink::commands::CmsisDapError, BatchCommand, ReconnectPolicy},
     DebugProbe, DebugProbeError, DebugProbeSelector, Error as ProbeRsError, WireProtocol,
 };
 
@@ -53,12 +54,19 @@ pub struct DAPLink {
     capabilities: Option<Capabilities>,
     swo_buffer_size: Option<usize>,
     swo_active: bool,
-    swo_streaming: bool,
+    /// Background reader of the SWO streaming endpoint, if it is used.
+    swo_stream: Option<swo_stream::SwoStream>,
 
     /// Speed in kHz
     speed_khz: u32,
 
     batch: Vec<BatchCommand>,
+
+    /// The selector to open the device again after the connection was lost.
+    selector: Option<DebugProbeSelector>,
+    reconnect_policy: ReconnectPolicy,
+    /// The last DPIDR value which was read, to recognize the target after reconnecting.
+    dpidr: Option<u32>,
 }
 
 impl std::fmt::Debug for DAPLink {
@@ -70,7 +78,7 @@ impl std::fmt::Debug for DAPLink {
             .field("capabilities", &self.capabilities)
             .field("swo_buffer_size", &self.swo_buffer_size)
             .field("swo_active", &self.swo_active)
-            .field("swo_streaming", &self.swo_streaming)
+            .field("swo_streaming", &self.swo_stream.is_some())
             .field("speed_khz", &self.speed_khz)
             .finish()
     }
@@ -78,17 +86,7 @@ impl std::fmt::Debug for DAPLink {
 
 impl DAPLink {
     pub fn new_from_device(device: DAPLinkDevice) -> Self {
-        // Discard anything left in buffer, as otherwise
-        // we'll get out of sync between requests and responses.
-        if let DAPLinkDevice::V1(ref hid_device) = device {
-            let mut discard_buffer = [0u8; 128];
-            loop {
-                match hid_device.read_timeout(&mut discard_buffer, 1) {
-                    Ok(n) if n != 0 => continue,
-                    _ => break,
-                }
-            }
-        }
+        device.drain();
 
         Self {
             device,
@@ -100,9 +98,84 @@ impl DAPLink {
             capabilities: None,
             swo_buffer_size: None,
             swo_active: false,
-            swo_streaming: false,
+            swo_stream: None,
             speed_khz: 1_000,
             batch: Vec::new(),
+            selector: None,
+            reconnect_policy: ReconnectPolicy::default(),
+            dpidr: None,
+        }
+    }
+
+    /// Opens the device again after the connection to it was lost with `error`,
+    /// and initializes the probe again as configured by the [ReconnectPolicy].
+    ///
+    /// Returns `error` if it does not mean that the connection was lost,
+    /// or if reconnecting failed.
+    fn reconnect(&mut self, error: anyhow::Error) -> Result<(), DebugProbeError> {
+        let selector = match &self.selector {
+            Some(selector) if commands::is_connection_lost(&error) => selector.clone(),
+            _ => return Err(error.into()),
+        };
+        let attempts = self.reconnect_policy.attempts;
+
+        if attempts == 0 {
+            return Err(error.into());
+        }
+
+        log::warn!("The connection to the probe was lost: {}", error);
+
+        if self.swo_active {
+            log::warn!("The SWO capture is stopped, it has to be enabled again.");
+            self.stop_swo_stream();
+            self.swo_active = false;
+        }
+
+        for attempt in 1..=attempts {
+            std::thread::sleep(self.reconnect_policy.backoff * attempt);
+
+            // If the device only stopped responding for a while, it can still be used.
+            match tools::open_device_from_selector(selector.clone()) {
+                Ok(device) => self.device = device,
+                Err(e) => debug!("Reopening the probe failed: {}", e),
+            }
+            self.device.drain();
+
+            match self.reinitialize() {
+                Ok(dpidr) => match self.dpidr {
+                    Some(expected) if expected != dpidr => {
+                        return Err(CmsisDapError::TargetChanged {
+                            expected,
+                            read: dpidr,
+                        }
+                        .into());
+                    }
+                    _ => {
+                        log::warn!("Reconnected to the probe ({}/{}).", attempt, attempts);
+                        return Ok(());
+                    }
+                },
+                Err(e) => debug!("Reconnecting failed ({}/{}): {}", attempt, attempts, e),
+            }
+        }
+
+        Err(error.into())
+    }
+
+    /// Initializes the probe and the connection to the target again, and returns DPIDR.
+    fn reinitialize(&mut self) -> Result<u32, DebugProbeError> {
+        DebugProbe::attach(self)?;
+
+        let request =
+            InnerTransferRequest::new(PortType::DebugPort.into(), RW::R, DPIDR::ADDRESS, None);
+        let response = commands::send_command::<TransferRequest, TransferResponse>(
+            &mut self.device,
+            TransferRequest::new(&[request]),
+        )?;
+
+        match response.transfer_response.ack {
+            Ack::Ok if !response.transfer_response.protocol_error => Ok(response.read_value(0)?),
+            _ => Err(DapError::NoAcknowledge.into()),
         }
     }
 
@@ -162,17 +235,17 @@ impl DAPLink {
     /// Immediately send whatever is in our batch if it is not empty.
     ///
     /// This will ensure any pending writes are processed and errors from them
-    /// raised if necessary.
-    fn process_batch(&mut self) -> Result<u32, DebugProbeError> {
+    /// raised if necessary. Returns the values of the reads in the batch.
+    fn process_batch(&mut self) -> Result<Vec<u32>, DebugProbeError> {
         if self.batch.is_empty() {
-            return Ok(0);
+            return Ok(Vec::new());
         }
 
-        let mut batch = std::mem::replace(&mut self.batch, Vec::new());
+        let batch = std::mem::replace(&mut self.batch, Vec::new());
 
         debug!("{} items in batch", batch.len());
 
-        for retry in (0..5).rev() {
+        for _ in 0..5 {
             debug!("Attempting batch of {} items", batch.len());
 
             let transfers: Vec<InnerTransferRequest> = batch
@@ -187,10 +260,22 @@ impl DAPLink {
                 })
                 .collect();
 
-            let response = commands::send_command::<TransferRequest, TransferResponse>(
+            let response = match commands::send_command::<TransferRequest, TransferResponse>(
                 &mut self.device,
                 TransferRequest::new(&transfers),
-            )?;
+            ) {
+                Ok(response) => response,
+                Err(error) => {
+                    self.reconnect(error)?;
+
+                    if !batch.iter().all(is_idempotent) {
+                        return Err(DebugProbeError::ProbeReconnected);
+                    }
+
+                    debug!("Repeating the batch after reconnecting");
+                    continue;
+                }
+            };
 
             let count = response.transfer_count as usize;
 
@@ -202,7 +287,18 @@ impl DAPLink {
                 match response.transfer_response.ack {
                     Ack::Ok => {
                         log::trace!("ack",);
-                        return Ok(response.transfer_data);
+                        let reads = batch
+                            .iter()
+                            .filter(|command| matches!(command, BatchCommand::Read(_, _)))
+                            .count();
+                        let values = (0..reads)
+                            .map(|index| response.read_value(index))
+                            .collect::<Result<Vec<_>, _>>()?;
+
+                        if let Some(BatchCommand::Read(PortType::DebugPort, 0)) = batch.last() {
+                            self.dpidr = values.last().copied();
+                        }
+                        return Ok(values);
                     }
                     Ack::NoAck => {
                         log::trace!("nack",);
@@ -210,34 +306,12 @@ impl DAPLink {
                         return Err(DapError::NoAcknowledge.into());
                     }
                     Ack::Fault => {
-                        log::trace!("fault",);
-
-                        // Check the reason for the fault
-                        let response = DAPAccess::read_register(
-                            self,
-                            PortType::DebugPort,
-                            Ctrl::ADDRESS as u16,
-                        )?;
-                        let ctrl = Ctrl::from(response);
-                        log::trace!("Ctrl/Stat register value is: {:?}", ctrl);
-
-                        if ctrl.sticky_err() {
-                            let mut abort = Abort(0);
-
-                            // Clear sticky error flags
-                            abort.set_stkerrclr(ctrl.sticky_err());
-
-                            DAPAccess::write_register(
-                                self,
-                                PortType::DebugPort,
-                                Abort::ADDRESS as u16,
-                                abort.into(),
-                            )?;
-                        }
+                        log::trace!("fault after {} of {} items", count, batch.len());
 
-                        log::trace!("draining {:?} and retries left {:?}", count, retry);
-                        batch.drain(0..count);
-                        continue;
+                        // The sticky error flags are left set, the ARM interface reads why
+                        // the transfer failed and clears them. The items after the failed one
+                        // are dropped, the debug port would not run them anyway.
+                        return Err(DapError::FaultResponse.into());
                     }
                     Ack::Wait => {
                         log::trace!("wait",);
@@ -267,8 +341,11 @@ impl DAPLink {
         // is as long as can fit in one packet.
         let max_writes = (self.packet_size.unwrap_or(32) as usize - 3) / (1 + 4);
         match command {
-            BatchCommand::Read(_, _) => self.process_batch(),
-            _ if self.batch.len() == max_writes => self.process_batch(),
+            BatchCommand::Read(_, _) => Ok(self.process_batch()?.last().copied().unwrap_or(0)),
+            _ if self.batch.len() == max_writes => {
+                self.process_batch()?;
+                Ok(0)
+            }
             _ => Ok(0),
         }
     }
@@ -354,6 +431,15 @@ impl DAPLink {
         Ok(commands::send_command(&mut self.device, request)?)
     }
 
+    /// Stop the background reader of the SWO streaming endpoint, if it is running,
+    /// and discard the data which is still queued on the endpoint.
+    fn stop_swo_stream(&mut self) {
+        if let Some(mut stream) = self.swo_stream.take() {
+            stream.stop();
+            self.device.drain_swo_stream();
+        }
+    }
+
     /// Fetch latest SWO trace data by sending a DAP_SWO_Data request.
     fn get_swo_data(&mut self) -> Result<Vec<u8>, DebugProbeError> {
         match self.swo_buffer_size {
@@ -380,6 +466,19 @@ impl DAPLink {
     }
 }
 
+/// Returns true if executing the command again has the same effect as executing it once.
+///
+/// Accesses to the data registers of a MEM-AP access the memory and increment TAR,
+/// and reading RDBUFF returns the result of the previous transfer.
+fn is_idempotent(command: &BatchCommand) -> bool {
+    match *command {
+        BatchCommand::Read(PortType::DebugPort, addr) => addr != u16::from(RdBuff::ADDRESS),
+        BatchCommand::Write(PortType::DebugPort, _, _) => true,
+        BatchCommand::Read(PortType::AccessPort(_), addr)
+        | BatchCommand::Write(PortType::AccessPort(_), addr, _) => !(0x0c..=0x1c).contains(&addr),
+    }
+}
+
 impl DPAccess for DAPLink {
     fn read_dp_register<R: DPRegister>(&mut self) -> Result<R, DebugPortError> {
         debug!("Reading DP register {}", R::NAME);
@@ -407,9 +506,18 @@ impl DebugProbe for DAPLink {
     where
         Self: Sized,
     {
-        Ok(Box::new(Self::new_from_device(
-            tools::open_device_from_selector(selector)?,
-        )))
+        let mut selector = selector.into();
+        let device = tools::open_device_from_selector(selector.clone())?;
+
+        // The device is opened again by its serial number after the connection was lost.
+        if selector.serial_number.is_none() {
+            selector.serial_number = device.serial_number();
+        }
+
+        let mut probe = Self::new_from_device(device);
+        probe.selector = Some(selector);
+
+        Ok(Box::new(probe))
     }
 
     fn get_name(&self) -> &str {
@@ -575,6 +683,11 @@ impl DebugProbe for DAPLink {
         Ok(())
     }
 
+    fn set_reconnect_policy(&mut self, policy: ReconnectPolicy) -> Result<(), DebugProbeError> {
+        self.reconnect_policy = policy;
+        Ok(())
+    }
+
     fn get_swo_interface(&self) -> Option<&dyn SwoAccess> {
         Some(self as _)
     }
@@ -594,6 +707,10 @@ impl DebugProbe for DAPLink {
     fn has_arm_interface(&self) -> bool {
         true
     }
+
+    fn get_dap_interface_mut(&mut self) -> Option<&mut dyn DAPAccess> {
+        Some(self as _)
+    }
 }
 
 impl<'a> AsRef<dyn DebugProbe + 'a> for DAPLink {
@@ -657,10 +774,19 @@ impl DAPAccess for DAPLink {
             debug!("Transfer block: chunk={}, len={} bytes", i, chunk.len() * 4);
 
             let resp: TransferBlockResponse =
-                commands::send_command(&mut self.device, request).map_err(DebugProbeError::from)?;
+                match commands::send_command(&mut self.device, request) {
+                    Ok(resp) => resp,
+                    Err(error) => {
+                        // Block transfers access the memory, so they are not repeated.
+                        self.reconnect(error)?;
+                        return Err(DebugProbeError::ProbeReconnected);
+                    }
+                };
 
-            if resp.transfer_response != 1 {
-                return Err(CmsisDapError::ErrorResponse.into());
+            match resp.transfer_response & 0x7 {
+                1 => {}
+                4 => return Err(DapError::FaultResponse.into()),
+                _ => return Err(CmsisDapError::ErrorResponse.into()),
             }
         }
 
@@ -699,10 +825,19 @@ impl DAPAccess for DAPLink {
             debug!("Transfer block: chunk={}, len={} bytes", i, chunk.len() * 4);
 
             let resp: TransferBlockResponse =
-                commands::send_command(&mut self.device, request).map_err(DebugProbeError::from)?;
+                match commands::send_command(&mut self.device, request) {
+                    Ok(resp) => resp,
+                    Err(error) => {
+                        // Block transfers access the memory, so they are not repeated.
+                        self.reconnect(error)?;
+                        return Err(DebugProbeError::ProbeReconnected);
+                    }
+                };
 
-            if resp.transfer_response != 1 {
-                return Err(CmsisDapError::ErrorResponse.into());
+            match resp.transfer_response & 0x7 {
+                1 => {}
+                4 => return Err(DapError::FaultResponse.into()),
+                _ => return Err(CmsisDapError::ErrorResponse.into()),
             }
 
             chunk.clone_from_slice(&resp.transfer_data[..]);
@@ -711,6 +846,39 @@ impl DAPAccess for DAPLink {
         Ok(())
     }
 
+    fn batch(&mut self, commands: &[BatchCommand]) -> Result<Vec<u32>, DebugProbeError> {
+        self.process_batch()?;
+
+        // Both the request, with one byte per read and five per write, and the response,
+        // with four bytes per read, have to fit in one packet.
+        let max_len = self.packet_size.unwrap_or(32) as usize - 3;
+
+        let mut values = Vec::new();
+        let mut request_len = 0;
+        let mut response_len = 0;
+
+        for &command in commands {
+            let (request_bytes, response_bytes) = match command {
+                BatchCommand::Read(_, _) => (1, 4),
+                BatchCommand::Write(_, _, _) => (1 + 4, 0),
+            };
+
+            if request_len + request_bytes > max_len || response_len + response_bytes > max_len {
+                values.extend(self.process_batch()?);
+                request_len = 0;
+                response_len = 0;
+            }
+
+            self.batch.push(command);
+            request_len += request_bytes;
+            response_len += response_bytes;
+        }
+
+        values.extend(self.process_batch()?);
+
+        Ok(values)
+    }
+
     fn flush(&mut self) -> Result<(), DebugProbeError> {
         self.process_batch()?;
         Ok(())
@@ -745,6 +913,7 @@ impl SwoAccess for DAPLink {
 
         // Stop any ongoing trace
         self.stop_swo_capture()?;
+        self.stop_swo_stream();
 
         // Set transport. If the dedicated endpoint is available and we have opened
         // the probe in V2 mode and it has an SWO endpoint, request that, otherwise
@@ -752,11 +921,10 @@ impl SwoAccess for DAPLink {
         if caps.swo_streaming_trace_implemented && self.device.swo_streaming_supported() {
             debug!("Starting SWO capture with WinUSB transport");
             self.set_swo_transport(swo::TransportRequest::WinUsbEndpoint)?;
-            self.swo_streaming = true;
+            self.swo_stream = Some(self.device.start_swo_stream()?);
         } else {
             debug!("Starting SWO capture with polled transport");
             self.set_swo_transport(swo::TransportRequest::DataCommand)?;
-            self.swo_streaming = false;
         }
 
         // Set mode. We've already checked that the requested mode is listed as supported.
@@ -784,16 +952,17 @@ impl SwoAccess for DAPLink {
     fn disable_swo(&mut self) -> Result<(), ProbeRsError> {
         debug!("Stopping SWO capture");
         self.stop_swo_capture()?;
+        self.stop_swo_stream();
         self.swo_active = false;
         Ok(())
     }
 
     fn read_swo_timeout(&mut self, timeout: Duration) -> Result<Vec<u8>, ProbeRsError> {
         if self.swo_active {
-            if self.swo_streaming {
-                let mut buffer = vec![0u8; 1024];
-                let n = self.device.read_swo_stream(&mut buffer, timeout)?;
-                buffer.truncate(n);
+            if let Some(stream) = &self.swo_stream {
+                let buffer = stream
+                    .read(timeout)
+                    .map_err(|e| DebugProbeError::from(CmsisDapError::from(e)))?;
                 log::trace!("SWO streaming buffer: {:?}", buffer);
                 Ok(buffer)
             } else {
@@ -809,7 +978,8 @@ impl SwoAccess for DAPLink {
     fn swo_poll_interval_hint(&mut self, config: &SwoConfig) -> Option<std::time::Duration> {
         let caps = self.capabilities.expect("This is a bug. Please report it.");
         if caps.swo_streaming_trace_implemented && self.device.swo_streaming_supported() {
-            // Streaming reads block waiting for new data so any polling interval is fine
+            // The stream is read in the background and reads wait for new data,
+            // so any polling interval is fine
             Some(std::time::Duration::from_secs(0))
         } else {
             match self.swo_buffer_size {
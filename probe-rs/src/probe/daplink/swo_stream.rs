@@ -0,0 +1,200 @@
+//! Background reception of SWO data from the streaming endpoint of CMSIS-DAP v2 probes.
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Condvar, Mutex,
+};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Maximum amount of SWO data which is buffered until it is read.
+///
+/// If the data is not read in time, the oldest data is dropped.
+const MAX_BUFFERED: usize = 1024 * 1024;
+
+/// Timeout of a single read from the endpoint.
+///
+/// This also limits how long stopping the reader takes.
+pub(super) const READ_TIMEOUT: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Default)]
+struct Buffer {
+    data: Vec<u8>,
+    error: Option<rusb::Error>,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    buffer: Mutex<Buffer>,
+    available: Condvar,
+    stop: AtomicBool,
+}
+
+/// Reads the SWO streaming endpoint in a background thread, so that no data
+/// is lost while the probe is busy with other commands, e.g. flash loading.
+#[derive(Debug)]
+pub(super) struct SwoStream {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SwoStream {
+    /// Starts a thread which calls `read` until the stream is stopped or `read` fails.
+    ///
+    /// `read` has to return `rusb::Error::Timeout` if no data arrived within [READ_TIMEOUT].
+    pub(super) fn start<F>(mut read: F) -> Self
+    where
+        F: FnMut(&mut [u8]) -> Result<usize, rusb::Error> + Send + 'static,
+    {
+        let shared = Arc::new(Shared::default());
+        let thread_shared = shared.clone();
+
+        let thread = std::thread::spawn(move || {
+            let shared = thread_shared;
+            let mut chunk = vec![0u8; 1024];
+
+            while !shared.stop.load(Ordering::Relaxed) {
+                let result = read(&mut chunk);
+
+                let mut buffer = shared.buffer.lock().unwrap();
+                match result {
+                    Ok(n) => {
+                        buffer.data.extend_from_slice(&chunk[..n]);
+
+                        if buffer.data.len() > MAX_BUFFERED {
+                            let dropped = buffer.data.len() - MAX_BUFFERED;
+                            log::warn!("SWO data was not read in time, {} bytes lost", dropped);
+                            buffer.data.drain(..dropped);
+                        }
+                    }
+                    Err(rusb::Error::Timeout) => continue,
+                    Err(e) => {
+                        log::warn!("Reading SWO stream failed: {}", e);
+                        buffer.error = Some(e);
+                        shared.available.notify_all();
+                        break;
+                    }
+                }
+                shared.available.notify_all();
+            }
+        });
+
+        Self {
+            shared,
+            thread: Some(thread),
+        }
+    }
+
+    /// Returns the data received so far, waiting up to `timeout` if there is none yet.
+    pub(super) fn read(&self, timeout: Duration) -> Result<Vec<u8>, rusb::Error> {
+        let end = Instant::now() + timeout;
+        let mut buffer = self.shared.buffer.lock().unwrap();
+
+        loop {
+            if !buffer.data.is_empty() {
+                return Ok(std::mem::take(&mut buffer.data));
+            }
+
+            if let Some(error) = buffer.error {
+                return Err(error);
+            }
+
+            let now = Instant::now();
+            if now >= end {
+                return Ok(vec![]);
+            }
+
+            buffer = self
+                .shared
+                .available
+                .wait_timeout(buffer, end - now)
+                .unwrap()
+                .0;
+        }
+    }
+
+    /// Stops the reader thread and waits until it finished.
+    pub(super) fn stop(&mut self) {
+        self.shared.stop.store(true, Ordering::Relaxed);
+
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                log::warn!("SWO reader thread panicked");
+            }
+        }
+    }
+}
+
+impl Drop for SwoStream {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SwoStream;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    /// Creates a stream which returns the chunks sent over the returned channel.
+    fn channel_stream() -> (mpsc::Sender<Result<Vec<u8>, rusb::Error>>, SwoStream) {
+        let (sender, receiver) = mpsc::channel::<Result<Vec<u8>, rusb::Error>>();
+
+        let read = move |buf: &mut [u8]| match receiver.recv_timeout(super::READ_TIMEOUT) {
+            Ok(Ok(chunk)) => {
+                buf[..chunk.len()].copy_from_slice(&chunk);
+                Ok(chunk.len())
+            }
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err(rusb::Error::Timeout),
+        };
+
+        (sender, SwoStream::start(read))
+    }
+
+    #[test]
+    fn data_is_buffered_until_read() {
+        let (sender, stream) = channel_stream();
+
+        sender.send(Ok(vec![1, 2, 3])).unwrap();
+        sender.send(Ok(vec![4, 5])).unwrap();
+
+        let mut data = vec![];
+        while data.len() < 5 {
+            data.extend(stream.read(Duration::from_secs(1)).unwrap());
+        }
+
+        assert_eq!(data, vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn read_times_out_without_data() {
+        let (_sender, stream) = channel_stream();
+
+        assert!(stream.read(Duration::from_millis(10)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn error_is_reported_after_data() {
+        let (sender, stream) = channel_stream();
+
+        sender.send(Ok(vec![1])).unwrap();
+        sender.send(Err(rusb::Error::Pipe)).unwrap();
+
+        assert_eq!(stream.read(Duration::from_secs(1)).unwrap(), vec![1]);
+        assert_eq!(
+            stream.read(Duration::from_secs(1)).unwrap_err(),
+            rusb::Error::Pipe
+        );
+    }
+
+    #[test]
+    fn stop_ends_the_thread() {
+        let (_sender, mut stream) = channel_stream();
+
+        stream.stop();
+
+        assert!(stream.thread.is_none());
+    }
+}
@@ -1,11 +1,29 @@
 use super::DAPLinkDevice;
 use crate::{
-    probe::{DebugProbeInfo, DebugProbeType, ProbeCreationError},
+    probe::{
+        usb_port_path, DebugProbe, DebugProbeError, DebugProbeInfo, DebugProbeType,
+        ProbeCreationError, ProbeFactory,
+    },
     DebugProbeSelector,
 };
 use rusb::{Device, DeviceDescriptor, UsbContext};
+use std::sync::Arc;
 use std::time::Duration;
 
+/// Finds and opens CMSIS-DAP probes.
+#[derive(Debug)]
+pub(crate) struct DAPLinkFactory;
+
+impl ProbeFactory for DAPLinkFactory {
+    fn list_probes(&self) -> Vec<DebugProbeInfo> {
+        list_daplink_devices()
+    }
+
+    fn open(&self, selector: &DebugProbeSelector) -> Result<Box<dyn DebugProbe>, DebugProbeError> {
+        Ok(super::DAPLink::new_from_selector(selector.clone())?)
+    }
+}
+
 /// Finds all CMSIS-DAP devices, either v1 (HID) or v2 (WinUSB Bulk).
 ///
 /// This method uses rusb to read device strings, which might fail due
@@ -46,6 +64,7 @@ fn get_daplink_info(device: &Device<rusb::Context>) -> Option<DebugProbeInfo> {
             product_id: d_desc.product_id(),
             serial_number: sn_str,
             probe_type: DebugProbeType::DAPLink,
+            port_path: usb_port_path(device),
         })
     } else {
         None
@@ -62,6 +81,8 @@ fn get_daplink_hid_info(device: &hidapi::DeviceInfo) -> Option<DebugProbeInfo> {
                 product_id: device.product_id(),
                 serial_number: device.serial_number().map(|s| s.to_owned()),
                 probe_type: DebugProbeType::DAPLink,
+                // hidapi reports paths which differ between the operating systems.
+                port_path: None,
             });
         }
     }
@@ -135,7 +156,7 @@ pub fn open_v2_device(device: Device<rusb::Context>) -> Option<DAPLinkDevice> {
                 Ok(()) => {
                     log::debug!("Opening {:04x}:{:04x} in CMSIS-DAPv2 mode", vid, pid);
                     return Some(DAPLinkDevice::V2 {
-                        handle,
+                        handle: Arc::new(handle),
                         out_ep,
                         in_ep,
                         swo_ep,
@@ -180,6 +201,9 @@ pub fn open_device_from_selector(
 ) -> Result<DAPLinkDevice, ProbeCreationError> {
     let selector = selector.into();
 
+    // The serial number of the device at the selected port, used to open it with hidapi.
+    let mut port_serial_number = None;
+
     // Try using rusb to open a v2 device. This might fail if
     // the device does not support v2 operation or due to driver
     // or permission issues with opening bulk devices.
@@ -187,6 +211,10 @@ pub fn open_device_from_selector(
         for device in devices.iter() {
             log::debug!("Device {:?}", device);
 
+            if !selector.matches_port_path(&device) {
+                continue;
+            }
+
             let d_desc = match device.device_descriptor() {
                 Ok(d_desc) => d_desc,
                 Err(err) => {
@@ -220,6 +248,10 @@ pub fn open_device_from_selector(
             // multiple open handles are not allowed on Windows.
             drop(handle);
 
+            if selector.port_path.is_some() {
+                port_serial_number = sn_str.clone();
+            }
+
             if device_matches(d_desc, &selector, sn_str) && get_daplink_info(&device).is_some() {
                 // If the VID, PID, and potentially SN all match,
                 // and the device is a valid CMSIS-DAP probe,
@@ -240,7 +272,33 @@ pub fn open_device_from_selector(
     // If rusb failed or the device didn't support v2, try using hidapi to open in v1 mode.
     let vid = selector.vendor_id;
     let pid = selector.product_id;
-    let sn = &selector.serial_number;
+    let sn = match &selector.port_path {
+        // hidapi can not open a device by its port, so it is opened by its serial number,
+        // which has to be unique for this.
+        Some(_) => {
+            let sn = port_serial_number.ok_or(ProbeCreationError::NotFound)?;
+            let count = hidapi::HidApi::new()
+                .map(|api| {
+                    api.device_list()
+                        .filter(|device| {
+                            device.vendor_id() == vid
+                                && device.product_id() == pid
+                                && device.serial_number() == Some(sn.as_str())
+                        })
+                        .count()
+                })
+                .unwrap_or(0);
+
+            if count > 1 {
+                return Err(ProbeCreationError::Other(
+                    "CMSIS-DAP v1 probes with the same serial number can not be selected by their port",
+                ));
+            }
+
+            Some(sn)
+        }
+        None => selector.serial_number.clone(),
+    };
 
     log::debug!(
         "Attempting to open {:04x}:{:04x} in CMSIS-DAP v1 mode",
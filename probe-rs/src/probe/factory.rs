@@ -0,0 +1,93 @@
+//! Registry of the drivers which find and open debug probes.
+
+use super::{
+    blackmagic::BlackMagicFactory, daplink::tools::DAPLinkFactory, jlink::JLinkFactory,
+    stlink::tools::STLinkFactory, wchlink::WchLinkFactory, DebugProbe, DebugProbeError,
+    DebugProbeInfo, DebugProbeSelector,
+};
+use lazy_static::lazy_static;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+lazy_static! {
+    static ref FACTORIES: RwLock<Vec<Arc<dyn ProbeFactory>>> = RwLock::new(builtin_factories());
+}
+
+/// A driver for a kind of debug probe, which finds the connected probes and opens them.
+///
+/// The built-in drivers are factories too. Further drivers, e.g. for probes with their own
+/// USB protocol, are added with [register_probe_factory]. Their probes are then listed by
+/// [Probe::list_all](super::Probe::list_all) and opened by [Probe::open](super::Probe::open)
+/// like the built-in ones.
+pub trait ProbeFactory: Send + Sync + fmt::Debug {
+    /// Returns the probes of this driver which are connected to the host.
+    fn list_probes(&self) -> Vec<DebugProbeInfo>;
+
+    /// Opens the probe described by `selector`.
+    ///
+    /// Returns [ProbeCreationError::NotFound](super::ProbeCreationError::NotFound) if the
+    /// selected probe is not one of this driver, so that the next driver is asked.
+    fn open(&self, selector: &DebugProbeSelector) -> Result<Box<dyn DebugProbe>, DebugProbeError>;
+}
+
+/// Adds a probe driver, which is asked before the built-in drivers.
+///
+/// Drivers registered later are asked first, so they can take over probes of earlier ones.
+pub fn register_probe_factory(factory: impl ProbeFactory + 'static) {
+    FACTORIES
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .insert(0, Arc::new(factory));
+}
+
+/// Registers a probe driver like [register_probe_factory], until it is dropped.
+///
+/// Tests use this to keep their drivers out of the other tests.
+#[cfg(test)]
+pub(crate) struct ScopedRegistration(Arc<dyn ProbeFactory>);
+
+#[cfg(test)]
+impl ScopedRegistration {
+    pub(crate) fn new(factory: impl ProbeFactory + 'static) -> Self {
+        let factory: Arc<dyn ProbeFactory> = Arc::new(factory);
+
+        FACTORIES
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(0, factory.clone());
+
+        ScopedRegistration(factory)
+    }
+}
+
+#[cfg(test)]
+impl Drop for ScopedRegistration {
+    fn drop(&mut self) {
+        let registered = Arc::as_ptr(&self.0) as *const u8;
+
+        FACTORIES
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .retain(|factory| Arc::as_ptr(factory) as *const u8 != registered);
+    }
+}
+
+/// Returns the registered probe drivers, in the order in which they are asked.
+pub(crate) fn factories() -> Vec<Arc<dyn ProbeFactory>> {
+    FACTORIES
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone()
+}
+
+fn builtin_factories() -> Vec<Arc<dyn ProbeFactory>> {
+    vec![
+        Arc::new(DAPLinkFactory),
+        #[cfg(feature = "ftdi")]
+        Arc::new(super::ftdi::FtdiFactory),
+        Arc::new(STLinkFactory),
+        Arc::new(JLinkFactory),
+        Arc::new(BlackMagicFactory),
+        Arc::new(WchLinkFactory),
+    ]
+}
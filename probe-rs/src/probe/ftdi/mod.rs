@@ -1,5 +1,9 @@
+use crate::architecture::arm::{
+    communication_interface::ArmProbeInterface, dp::RdBuff, ArmCommunicationInterface, DAPAccess,
+    PortType, Register,
+};
 use crate::architecture::riscv::communication_interface::RiscvCommunicationInterface;
-use crate::probe::{JTAGAccess, ProbeCreationError};
+use crate::probe::{JTAGAccess, ProbeCreationError, ProbeFactory};
 use crate::{
     DebugProbe, DebugProbeError, DebugProbeInfo, DebugProbeSelector, DebugProbeType, WireProtocol,
 };
@@ -12,6 +16,9 @@ use std::time::Duration;
 mod ftdi_impl;
 use ftdi_impl as ftdi;
 
+mod swd;
+pub use swd::SwdioDirectionPin;
+
 #[derive(Debug)]
 struct JtagChainItem {
     idcode: u32,
@@ -27,10 +34,24 @@ struct ChainParams {
     irlen: usize,
 }
 
+/// The pin of the low byte which drives the reset line of the target, ADBUS5 (GPIOL1).
+///
+/// FTDI based adapters are wired differently, adapters which use another pin
+/// can not reset the target.
+const RESET_PIN: u16 = 0x0020;
+
 #[derive(Debug)]
 pub struct JtagAdapter {
     device: ftdi::Device,
     chain_params: Option<ChainParams>,
+    /// The output values of the GPIO pins.
+    output: u16,
+    /// The directions of the GPIO pins, pins with a set bit are outputs.
+    direction: u16,
+    /// `true` once the MPSSE was set up by [JtagAdapter::attach].
+    attached: bool,
+    /// The pin which switches the direction of an external SWDIO buffer, if there is one.
+    swdio_direction_pin: Option<SwdioDirectionPin>,
 }
 
 impl JtagAdapter {
@@ -42,6 +63,11 @@ impl JtagAdapter {
         Ok(Self {
             device,
             chain_params: None,
+            // Minimal values, may not work with all probes
+            output: 0x0008,
+            direction: 0x000b,
+            attached: false,
+            swdio_direction_pin: None,
         })
     }
 
@@ -54,13 +80,8 @@ impl JtagAdapter {
         let mut junk = vec![];
         let _ = self.device.read_to_end(&mut junk);
 
-        // Minimal values, may not work with all probes
-        let output: u16 = 0x0008;
-        let direction: u16 = 0x000b;
-        self.device
-            .write_all(&[0x80, output as u8, direction as u8])?;
-        self.device
-            .write_all(&[0x82, (output >> 8) as u8, (direction >> 8) as u8])?;
+        self.write_gpio()?;
+        self.attached = true;
 
         // Disable loopback
         self.device.write_all(&[0x85])?;
@@ -68,6 +89,39 @@ impl JtagAdapter {
         Ok(())
     }
 
+    /// Appends the commands which set the output values and directions of the GPIO pins.
+    fn gpio_command(&self, command: &mut Vec<u8>) {
+        let (output, direction) = (self.output, self.direction);
+        command.extend_from_slice(&[0x80, output as u8, direction as u8]);
+        command.extend_from_slice(&[0x82, (output >> 8) as u8, (direction >> 8) as u8]);
+    }
+
+    fn write_gpio(&mut self) -> io::Result<()> {
+        let mut command = vec![];
+        self.gpio_command(&mut command);
+        self.device.write_all(&command)
+    }
+
+    /// Drives the reset line of the target low, or releases it if `asserted` is `false`.
+    ///
+    /// The reset line is open drain: it is only driven while it is asserted,
+    /// otherwise the target pulls it up. Before the adapter is attached, the state
+    /// is only stored, and set when the pins are configured by [JtagAdapter::attach].
+    pub fn set_reset(&mut self, asserted: bool) -> io::Result<()> {
+        self.output &= !RESET_PIN;
+        if asserted {
+            self.direction |= RESET_PIN;
+        } else {
+            self.direction &= !RESET_PIN;
+        }
+
+        if self.attached {
+            self.write_gpio()
+        } else {
+            Ok(())
+        }
+    }
+
     fn read_response(&mut self, size: usize) -> io::Result<Vec<u8>> {
         let timeout = Duration::from_millis(10);
         let mut result = Vec::new();
@@ -388,6 +442,76 @@ pub struct FtdiProbe {
     adapter: JtagAdapter,
     speed_khz: u32,
     idle_cycles: u8,
+    protocol: WireProtocol,
+}
+
+impl FtdiProbe {
+    /// Sets the pin which switches the direction of an external SWDIO buffer.
+    ///
+    /// Without such a pin, SWDIO has to be connected to TDO, and to TDI through a resistor.
+    /// This has to be set before the probe is attached.
+    pub fn set_swdio_direction_pin(&mut self, pin: Option<SwdioDirectionPin>) {
+        self.adapter.swdio_direction_pin = pin;
+    }
+
+    /// Switches the target to SWD and reads the DPIDR register.
+    fn attach_swd(&mut self) -> Result<(), DebugProbeError> {
+        self.adapter
+            .swd_attach()
+            .and_then(|_| self.adapter.swd_line_reset())
+            .map_err(|e| DebugProbeError::ProbeSpecific(Box::new(e)))?;
+
+        let dpidr = DAPAccess::read_register(self, PortType::DebugPort, 0)?;
+        log::debug!("DPIDR: {:#010x}", dpidr);
+
+        Ok(())
+    }
+
+    /// Performs a SWD transfer, which writes `value`, or reads if it is `None`.
+    fn swd_transfer(
+        &mut self,
+        port: PortType,
+        address: u16,
+        value: Option<u32>,
+    ) -> Result<u32, DebugProbeError> {
+        self.adapter
+            .swd_transfer(port, address, value)
+            .map_err(|e| DebugProbeError::ProbeSpecific(Box::new(e)))?
+            .map_err(DebugProbeError::from)
+    }
+
+    /// Attaches to the JTAG chain and selects the target.
+    fn attach_jtag(&mut self) -> Result<(), DebugProbeError> {
+        let taps = self
+            .adapter
+            .scan()
+            .map_err(|e| DebugProbeError::ProbeSpecific(Box::new(e)))?;
+        if taps.is_empty() {
+            log::warn!("no JTAG taps detected");
+            return Err(DebugProbeError::TargetNotFound);
+        }
+        if taps.len() == 1 {
+            self.adapter
+                .select_target(taps[0].idcode)
+                .map_err(|e| DebugProbeError::ProbeSpecific(Box::new(e)))?;
+        } else {
+            let known_idcodes = [
+                0x1000563d, // GD32VF103
+            ];
+            let idcode = taps
+                .iter()
+                .map(|tap| tap.idcode)
+                .find(|idcode| known_idcodes.iter().any(|v| v == idcode));
+            if let Some(idcode) = idcode {
+                self.adapter
+                    .select_target(idcode)
+                    .map_err(|e| DebugProbeError::ProbeSpecific(Box::new(e)))?;
+            } else {
+                return Err(DebugProbeError::TargetNotFound);
+            }
+        }
+        Ok(())
+    }
 }
 
 impl DebugProbe for FtdiProbe {
@@ -406,6 +530,13 @@ impl DebugProbe for FtdiProbe {
             ));
         }
 
+        // libftdi opens the first device with the VID and PID.
+        if selector.port_path.is_some() {
+            return Err(DebugProbeError::ProbeCouldNotBeCreated(
+                ProbeCreationError::Other("FTDI probes can not be selected by their USB port"),
+            ));
+        }
+
         let adapter = JtagAdapter::open(selector.vendor_id, selector.product_id)
             .map_err(|e| DebugProbeError::ProbeSpecific(Box::new(e)))?;
 
@@ -413,6 +544,7 @@ impl DebugProbe for FtdiProbe {
             adapter,
             speed_khz: 0,
             idle_cycles: 0,
+            protocol: WireProtocol::Jtag,
         };
         log::debug!("opened probe: {:?}", probe);
         Ok(Box::new(probe))
@@ -439,35 +571,10 @@ impl DebugProbe for FtdiProbe {
             .attach()
             .map_err(|e| DebugProbeError::ProbeSpecific(Box::new(e)))?;
 
-        let taps = self
-            .adapter
-            .scan()
-            .map_err(|e| DebugProbeError::ProbeSpecific(Box::new(e)))?;
-        if taps.is_empty() {
-            log::warn!("no JTAG taps detected");
-            return Err(DebugProbeError::TargetNotFound);
+        match self.protocol {
+            WireProtocol::Swd => self.attach_swd(),
+            WireProtocol::Jtag => self.attach_jtag(),
         }
-        if taps.len() == 1 {
-            self.adapter
-                .select_target(taps[0].idcode)
-                .map_err(|e| DebugProbeError::ProbeSpecific(Box::new(e)))?;
-        } else {
-            let known_idcodes = [
-                0x1000563d, // GD32VF103
-            ];
-            let idcode = taps
-                .iter()
-                .map(|tap| tap.idcode)
-                .find(|idcode| known_idcodes.iter().any(|v| v == idcode));
-            if let Some(idcode) = idcode {
-                self.adapter
-                    .select_target(idcode)
-                    .map_err(|e| DebugProbeError::ProbeSpecific(Box::new(e)))?;
-            } else {
-                return Err(DebugProbeError::TargetNotFound);
-            }
-        }
-        Ok(())
     }
 
     fn detach(&mut self) -> Result<(), DebugProbeError> {
@@ -475,36 +582,73 @@ impl DebugProbe for FtdiProbe {
     }
 
     fn target_reset(&mut self) -> Result<(), DebugProbeError> {
-        log::error!("FTDI target_reset");
-        unimplemented!()
+        self.target_reset_assert()?;
+        std::thread::sleep(Duration::from_millis(10));
+        self.target_reset_deassert()
     }
 
     fn target_reset_assert(&mut self) -> Result<(), DebugProbeError> {
-        log::error!("FTDI target_reset_assert");
-        unimplemented!()
+        self.adapter
+            .set_reset(true)
+            .map_err(|e| DebugProbeError::ProbeSpecific(Box::new(e)))
     }
 
     fn target_reset_deassert(&mut self) -> Result<(), DebugProbeError> {
-        log::error!("FTDI target_reset_deassert");
-        unimplemented!()
+        self.adapter
+            .set_reset(false)
+            .map_err(|e| DebugProbeError::ProbeSpecific(Box::new(e)))
     }
 
     fn select_protocol(&mut self, protocol: WireProtocol) -> Result<(), DebugProbeError> {
-        if protocol != WireProtocol::Jtag {
-            Err(DebugProbeError::UnsupportedProtocol(protocol))
+        self.protocol = protocol;
+        Ok(())
+    }
+
+    fn uses_overrun_detection(&self) -> bool {
+        true
+    }
+
+    fn has_arm_interface(&self) -> bool {
+        self.protocol == WireProtocol::Swd
+    }
+
+    fn get_arm_interface<'probe>(
+        self: Box<Self>,
+    ) -> Result<Option<Box<dyn ArmProbeInterface + 'probe>>, DebugProbeError> {
+        if self.protocol == WireProtocol::Swd {
+            // Transfers are sent including their data phase, which requires overrun detection.
+            let interface = ArmCommunicationInterface::new(self, true)?;
+
+            Ok(Some(Box::new(interface)))
         } else {
-            Ok(())
+            Ok(None)
         }
     }
 
     fn get_riscv_interface(
         self: Box<Self>,
     ) -> Result<Option<RiscvCommunicationInterface>, DebugProbeError> {
-        Ok(Some(RiscvCommunicationInterface::new(self)?))
+        if self.protocol == WireProtocol::Jtag {
+            Ok(Some(RiscvCommunicationInterface::new(self)?))
+        } else {
+            Ok(None)
+        }
     }
 
     fn has_riscv_interface(&self) -> bool {
-        true
+        self.protocol == WireProtocol::Jtag
+    }
+
+    fn get_dap_interface_mut(&mut self) -> Option<&mut dyn DAPAccess> {
+        if self.protocol == WireProtocol::Swd {
+            Some(self as _)
+        } else {
+            None
+        }
+    }
+
+    fn get_jtag_interface_mut(&mut self) -> Option<&mut dyn JTAGAccess> {
+        Some(self as _)
     }
 }
 
@@ -556,6 +700,36 @@ impl JTAGAccess for FtdiProbe {
     }
 }
 
+impl DAPAccess for FtdiProbe {
+    fn read_register(&mut self, port: PortType, address: u16) -> Result<u32, DebugProbeError> {
+        let value = self.swd_transfer(port, address, None)?;
+
+        // If we are reading an AP register we only get the actual result in the next transaction.
+        // So we issue a special transaction to get the read value.
+        if let PortType::AccessPort(_) = port {
+            // We read the RDBUFF register to get the value of the last AP transaction.
+            // This special register just returns the last read value with no side-effects like auto-increment.
+            self.swd_transfer(PortType::DebugPort, RdBuff::ADDRESS as u16, None)
+        } else {
+            Ok(value)
+        }
+    }
+
+    fn write_register(
+        &mut self,
+        port: PortType,
+        address: u16,
+        value: u32,
+    ) -> Result<(), DebugProbeError> {
+        self.swd_transfer(port, address, Some(value))?;
+        Ok(())
+    }
+
+    fn into_probe(self: Box<Self>) -> Box<dyn DebugProbe> {
+        self
+    }
+}
+
 impl AsRef<dyn DebugProbe> for FtdiProbe {
     fn as_ref(&self) -> &(dyn DebugProbe + 'static) {
         self
@@ -592,9 +766,24 @@ fn get_device_info(device: &rusb::Device<rusb::Context>) -> Option<DebugProbeInf
         product_id: d_desc.product_id(),
         serial_number: sn_str,
         probe_type: DebugProbeType::FTDI,
+        port_path: None,
     })
 }
 
+/// Finds and opens FTDI based probes.
+#[derive(Debug)]
+pub(crate) struct FtdiFactory;
+
+impl ProbeFactory for FtdiFactory {
+    fn list_probes(&self) -> Vec<DebugProbeInfo> {
+        list_ftdi_devices()
+    }
+
+    fn open(&self, selector: &DebugProbeSelector) -> Result<Box<dyn DebugProbe>, DebugProbeError> {
+        Ok(FtdiProbe::new_from_selector(selector.clone())?)
+    }
+}
+
 pub(crate) fn list_ftdi_devices() -> Vec<DebugProbeInfo> {
     match rusb::Context::new().and_then(|ctx| ctx.devices()) {
         Ok(devices) => devices
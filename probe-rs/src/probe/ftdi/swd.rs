@@ -0,0 +1,255 @@
+//! SWD support for FTDI based probes, using MPSSE.
+//!
+//! SWCLK is TCK (ADBUS0). SWDIO is read on TDO (ADBUS2) and driven by TDI (ADBUS1),
+//! which is connected to SWDIO through a resistor. TDI is switched to an input while the
+//! target drives SWDIO. Adapters with a buffer in front of SWDIO switch its direction
+//! with a [SwdioDirectionPin].
+
+use super::JtagAdapter;
+use crate::architecture::arm::{dp::Abort, DapError, PortType, Register};
+use crate::probe::swd_request;
+use std::io::{self, Write};
+
+/// The TDI pin, which drives SWDIO.
+const SWDIO_OUT_PIN: u16 = 0x0002;
+
+const ACK_OK: u8 = 0b001;
+const ACK_WAIT: u8 = 0b010;
+const ACK_FAULT: u8 = 0b100;
+
+/// How often a transfer is repeated after a WAIT response or a missing response.
+const TRANSFER_RETRIES: usize = 5;
+
+/// MPSSE command to clock bits out on the falling edge, LSB first.
+const WRITE_BITS: u8 = 0x1b;
+/// MPSSE command to clock bytes out on the falling edge, LSB first.
+const WRITE_BYTES: u8 = 0x19;
+/// MPSSE command to clock bits in on the rising edge, LSB first.
+const READ_BITS: u8 = 0x2a;
+/// MPSSE command to clock bytes in on the rising edge, LSB first.
+const READ_BYTES: u8 = 0x28;
+
+/// A pin which switches the direction of an external SWDIO buffer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SwdioDirectionPin {
+    /// The mask of the pin, bits 0 to 7 are ADBUS0 to ADBUS7 and bits 8 to 15 are ACBUS0 to ACBUS7.
+    pub mask: u16,
+    /// `true` if the buffer drives SWDIO while the pin is high.
+    pub active_high: bool,
+}
+
+/// Appends the commands to send the lowest `bits` bits of `value`.
+fn write_bits(command: &mut Vec<u8>, mut value: u32, mut bits: usize) {
+    while bits > 0 {
+        let n = bits.min(8);
+        command.extend_from_slice(&[WRITE_BITS, (n - 1) as u8, value as u8]);
+        value = value.checked_shr(8).unwrap_or(0);
+        bits -= n;
+    }
+}
+
+/// Extracts the bits of a response to a [READ_BITS] command of `bits` bits.
+///
+/// The bits are shifted in from the top, so they end up in the highest bits of the byte.
+fn read_bits_value(byte: u8, bits: usize) -> u8 {
+    byte >> (8 - bits)
+}
+
+impl JtagAdapter {
+    /// Appends the command which drives SWDIO, or releases it to the target.
+    fn swdio_output(&mut self, command: &mut Vec<u8>, enabled: bool) {
+        if enabled {
+            self.direction |= SWDIO_OUT_PIN;
+        } else {
+            self.direction &= !SWDIO_OUT_PIN;
+        }
+
+        if let Some(pin) = self.swdio_direction_pin {
+            if pin.active_high == enabled {
+                self.output |= pin.mask;
+            } else {
+                self.output &= !pin.mask;
+            }
+            self.direction |= pin.mask;
+        }
+
+        self.gpio_command(command);
+    }
+
+    /// Sends the lowest `bits` bits of `value` on SWDIO.
+    fn swd_write(&mut self, value: u32, bits: usize) -> io::Result<()> {
+        let mut command = vec![];
+        self.swdio_output(&mut command, true);
+        write_bits(&mut command, value, bits);
+        self.device.write_all(&command)
+    }
+
+    /// Switches the target from JTAG to SWD, see section B5.2.2 in the ADIv5 Specification.
+    pub fn swd_attach(&mut self) -> io::Result<()> {
+        self.swd_write(0xffff_ffff, 32)?;
+        self.swd_write(0xffff_ffff, 32)?;
+        self.swd_write(0xe79e, 16)?;
+        Ok(())
+    }
+
+    /// Sends a line reset, at least 50 cycles with SWDIO high, followed by two idle cycles.
+    ///
+    /// The line reset has to be followed by a read of the DPIDR register.
+    pub fn swd_line_reset(&mut self) -> io::Result<()> {
+        self.swd_write(0xffff_ffff, 32)?;
+        self.swd_write(0xffff_ffff, 32)?;
+        self.swd_write(0, 2)
+    }
+
+    /// Performs a single SWD transfer, which writes `value`, or reads if it is `None`.
+    ///
+    /// The whole transfer is sent at once, so the data phase is always performed. This
+    /// requires overrun detection to be enabled, otherwise the target does not expect the
+    /// data phase after a WAIT or FAULT response.
+    ///
+    /// The outer result contains errors of the adapter, the inner one the response of the target.
+    pub fn swd_transfer_once(
+        &mut self,
+        port: PortType,
+        address: u16,
+        value: Option<u32>,
+    ) -> io::Result<Result<u32, DapError>> {
+        let mut command = vec![];
+
+        self.swdio_output(&mut command, true);
+        write_bits(
+            &mut command,
+            swd_request(port, value.is_none(), address).into(),
+            8,
+        );
+
+        // Turnaround and ack.
+        self.swdio_output(&mut command, false);
+        command.extend_from_slice(&[READ_BITS, 3]);
+
+        let response_len = match value {
+            Some(value) => {
+                // Turnaround
+                command.extend_from_slice(&[READ_BITS, 0]);
+                self.swdio_output(&mut command, true);
+
+                command.extend_from_slice(&[WRITE_BYTES, 3, 0]);
+                command.extend_from_slice(&value.to_le_bytes());
+                write_bits(&mut command, value.count_ones() & 1, 1);
+
+                // Idle cycles to ensure the write is performed.
+                // See section B4.1.1 in the ARM Debug Interface specification.
+                write_bits(&mut command, 0, 8);
+
+                2
+            }
+            None => {
+                // Data, followed by parity and turnaround.
+                command.extend_from_slice(&[READ_BYTES, 3, 0]);
+                command.extend_from_slice(&[READ_BITS, 1]);
+                self.swdio_output(&mut command, true);
+
+                6
+            }
+        };
+
+        self.device.write_all(&command)?;
+        let response = self.read_response(response_len)?;
+
+        // The first bit is the turnaround.
+        match read_bits_value(response[0], 4) >> 1 {
+            ACK_OK => (),
+            ACK_WAIT => return Ok(Err(DapError::WaitResponse)),
+            ACK_FAULT => return Ok(Err(DapError::FaultResponse)),
+            _ => return Ok(Err(DapError::NoAcknowledge)),
+        }
+
+        if value.is_some() {
+            return Ok(Ok(0));
+        }
+
+        let data = u32::from_le_bytes([response[1], response[2], response[3], response[4]]);
+        let parity = read_bits_value(response[5], 2) & 1;
+
+        if data.count_ones() & 1 == parity as u32 {
+            Ok(Ok(data))
+        } else {
+            Ok(Err(DapError::IncorrectParity))
+        }
+    }
+
+    /// Performs a SWD transfer, retrying it after WAIT responses and missing responses.
+    ///
+    /// After a FAULT response, the sticky error flags are cleared and the fault is returned.
+    pub fn swd_transfer(
+        &mut self,
+        port: PortType,
+        address: u16,
+        value: Option<u32>,
+    ) -> io::Result<Result<u32, DapError>> {
+        let mut result = Err(DapError::NoAcknowledge);
+
+        for retry in 0..TRANSFER_RETRIES {
+            result = self.swd_transfer_once(port, address, value)?;
+
+            match result {
+                Err(DapError::WaitResponse) => {
+                    log::debug!(
+                        "DAP WAIT, retries remaining {}.",
+                        TRANSFER_RETRIES - retry - 1
+                    );
+
+                    // Because we use overrun detection, we now have to clear the overrun error.
+                    let mut abort = Abort(0);
+                    abort.set_orunerrclr(true);
+                    let _ = self.swd_transfer_once(
+                        PortType::DebugPort,
+                        Abort::ADDRESS as u16,
+                        Some(abort.into()),
+                    )?;
+                }
+                Err(DapError::NoAcknowledge) => {
+                    log::debug!("DAP NACK");
+
+                    // The target might have lost track of the transfers, a line reset
+                    // brings it back into a known state.
+                    self.swd_line_reset()?;
+                    if let Err(error) = self.swd_transfer_once(PortType::DebugPort, 0, None)? {
+                        log::debug!("Line reset failed: {}", error);
+                    }
+                }
+                // The sticky error flags are left set, the ARM interface
+                // reads why the transfer failed and clears them.
+                Err(DapError::FaultResponse) => return Ok(Err(DapError::FaultResponse)),
+                _ => break,
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_bits_value, write_bits, WRITE_BITS};
+
+    #[test]
+    fn bits_are_written_lsb_first_in_bytes() {
+        let mut command = vec![];
+        write_bits(&mut command, 0xe79e, 16);
+        write_bits(&mut command, 0b10, 2);
+
+        assert_eq!(
+            command,
+            vec![WRITE_BITS, 7, 0x9e, WRITE_BITS, 7, 0xe7, WRITE_BITS, 1, 0b10]
+        );
+    }
+
+    #[test]
+    fn read_bits_are_taken_from_the_top() {
+        // Turnaround bit 0, followed by an OK ack.
+        assert_eq!(read_bits_value(0b0010_0000, 4) >> 1, 0b001);
+        // Parity bit 1, followed by the turnaround.
+        assert_eq!(read_bits_value(0b0100_0000, 2) & 1, 1);
+    }
+}
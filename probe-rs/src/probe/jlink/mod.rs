@@ -20,13 +20,19 @@ use crate::{
     },
     probe::{
         DAPAccess, DebugProbe, DebugProbeError, DebugProbeInfo, DebugProbeType, JTAGAccess,
-        WireProtocol,
+        PowerCapabilities, ProbeFactory, WireProtocol,
     },
     DebugProbeSelector, Error as ProbeRsError,
 };
 
 const SWO_BUFFER_SIZE: u16 = 128;
 
+/// Maximum number of transfers which are sent to the probe in a single SWD sequence.
+const MAX_PIPELINED_TRANSFERS: usize = 32;
+
+/// Length of a transfer built by [build_swd_transfer], in bits.
+const SWD_TRANSFER_BITS: usize = 48;
+
 #[derive(Debug)]
 pub(crate) struct JLink {
     handle: JayLink,
@@ -42,6 +48,9 @@ pub(crate) struct JLink {
     /// Protocols supported by the connected J-Link probe.
     supported_protocols: Vec<WireProtocol>,
 
+    /// The J-Link can power the target through pin 19 (KS_POWER).
+    supports_target_power: bool,
+
     current_ir_reg: u32,
 
     speed_khz: u32,
@@ -401,6 +410,13 @@ impl DebugProbe for JLink {
             return Err(DebugProbeError::ProbeCouldNotBeCreated(
                 super::ProbeCreationError::NotFound,
             ));
+        } else if selector.port_path.is_some() {
+            // jaylink does not report the port of a device.
+            return Err(DebugProbeError::ProbeCouldNotBeCreated(
+                super::ProbeCreationError::Other(
+                    "J-Link probes can not be selected by their USB port",
+                ),
+            ));
         } else if jlinks.len() > 1 {
             log::warn!("More than one matching J-Link was found. Opening the first one.")
         }
@@ -442,10 +458,15 @@ impl DebugProbe for JLink {
             vec![WireProtocol::Jtag]
         };
 
+        let supports_target_power = jlink_handle
+            .read_capabilities()?
+            .contains(jaylink::Capabilities::SET_KS_POWER);
+
         Ok(Box::new(JLink {
             handle: jlink_handle,
             swo_config: None,
             supported_protocols,
+            supports_target_power,
             jtag_idle_cycles: 0,
             protocol: None,
             current_ir_reg: 1,
@@ -622,7 +643,9 @@ impl DebugProbe for JLink {
     }
 
     fn target_reset(&mut self) -> Result<(), super::DebugProbeError> {
-        Err(super::DebugProbeError::NotImplemented("target_reset"))
+        self.target_reset_assert()?;
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        self.target_reset_deassert()
     }
 
     fn target_reset_assert(&mut self) -> Result<(), DebugProbeError> {
@@ -665,6 +688,33 @@ impl DebugProbe for JLink {
         }
     }
 
+    fn power_capabilities(&self) -> PowerCapabilities {
+        PowerCapabilities {
+            target_voltage: true,
+            target_power: self.supports_target_power,
+            ..Default::default()
+        }
+    }
+
+    fn target_voltage(&mut self) -> Result<f32, DebugProbeError> {
+        // The voltage is reported in mV.
+        let target_voltage = self.handle.read_target_voltage()?;
+        Ok(target_voltage as f32 / 1000.0)
+    }
+
+    fn set_target_power(&mut self, enabled: bool) -> Result<(), DebugProbeError> {
+        if !self.supports_target_power {
+            return Err(DebugProbeError::CommandNotSupportedByProbe);
+        }
+
+        self.handle.set_kickstart_power(enabled)?;
+        Ok(())
+    }
+
+    fn uses_overrun_detection(&self) -> bool {
+        true
+    }
+
     fn has_arm_interface(&self) -> bool {
         self.supported_protocols.contains(&WireProtocol::Swd)
     }
@@ -672,6 +722,14 @@ impl DebugProbe for JLink {
     fn has_riscv_interface(&self) -> bool {
         self.supported_protocols.contains(&WireProtocol::Jtag)
     }
+
+    fn get_dap_interface_mut(&mut self) -> Option<&mut dyn DAPAccess> {
+        Some(self as _)
+    }
+
+    fn get_jtag_interface_mut(&mut self) -> Option<&mut dyn JTAGAccess> {
+        Some(self as _)
+    }
 }
 
 impl JTAGAccess for JLink {
@@ -729,6 +787,133 @@ impl JTAGAccess for JLink {
     }
 }
 
+/// DAP access which performs consecutive transfers of an AP register in a single sequence.
+trait PipelinedApAccess: DAPAccess {
+    /// Performs consecutive reads of an AP register in a single sequence.
+    ///
+    /// Returns the number of values which were read. If a transfer was not acknowledged
+    /// with OK, the following transfers were not performed by the target and have to be
+    /// repeated by the caller.
+    fn read_ap_pipelined(
+        &mut self,
+        port: PortType,
+        address: u16,
+        values: &mut [u32],
+    ) -> Result<usize, DebugProbeError>;
+
+    /// Performs consecutive writes to an AP register in a single sequence.
+    ///
+    /// Returns the number of values which were written. If a transfer was not acknowledged
+    /// with OK, the following transfers were not performed by the target and have to be
+    /// repeated by the caller.
+    fn write_ap_pipelined(
+        &mut self,
+        port: PortType,
+        address: u16,
+        values: &[u32],
+    ) -> Result<usize, DebugProbeError>;
+}
+
+/// AP reads are posted, so each read of the SWD sequence returns the value of the previous one,
+/// and the value of the last read is fetched from the RDBUFF register.
+impl PipelinedApAccess for JLink {
+    fn read_ap_pipelined(
+        &mut self,
+        port: PortType,
+        address: u16,
+        values: &mut [u32],
+    ) -> Result<usize, DebugProbeError> {
+        let mut swd_io_sequence = vec![];
+        let mut direction = vec![];
+
+        let transfers = iter::repeat((port, address))
+            .take(values.len())
+            .chain(iter::once((PortType::DebugPort, RdBuff::ADDRESS as u16)));
+
+        for (port, address) in transfers {
+            let (io, dir) = build_swd_transfer(port, TransferType::Read, address);
+            swd_io_sequence.extend(io);
+            direction.extend(dir);
+        }
+
+        let mut result_sequence = self.handle.swd_io(direction, swd_io_sequence)?;
+
+        for transfer in 0..=values.len() {
+            // Idle and request bits, see `read_register` for the phase shift.
+            result_sequence.split_off(2 + 8);
+
+            let ack = result_sequence.split_off(3).collect::<Vec<_>>();
+            let value = bits_to_byte(result_sequence.split_off(32));
+            let parity = result_sequence.next();
+            result_sequence.split_off(SWD_TRANSFER_BITS - 2 - 8 - 3 - 32 - 1);
+
+            if ack[..] != [true, false, false] {
+                log::debug!("Pipelined read {} not acknowledged: {:?}", transfer, ack);
+
+                // The previous read was performed, but its value was not transferred.
+                if transfer > 0 {
+                    values[transfer - 1] = DAPAccess::read_register(
+                        self,
+                        PortType::DebugPort,
+                        RdBuff::ADDRESS as u16,
+                    )?;
+                }
+
+                return Ok(transfer);
+            }
+
+            // The first transfer returns the value of an earlier read.
+            if transfer > 0 {
+                if parity != Some(value.count_ones() % 2 == 1) {
+                    log::error!("DAP read fault.");
+                    return Err(DapError::IncorrectParity.into());
+                }
+
+                values[transfer - 1] = value;
+            }
+        }
+
+        Ok(values.len())
+    }
+
+    fn write_ap_pipelined(
+        &mut self,
+        port: PortType,
+        address: u16,
+        values: &[u32],
+    ) -> Result<usize, DebugProbeError> {
+        let mut swd_io_sequence = vec![];
+        let mut direction = vec![];
+
+        for value in values {
+            let (io, dir) = build_swd_transfer(port, TransferType::Write(*value), address);
+            swd_io_sequence.extend(io);
+            direction.extend(dir);
+        }
+
+        // Add 8 idle cycles to ensure the last write is performed.
+        swd_io_sequence.extend_from_slice(&[false; 8]);
+        direction.extend_from_slice(&[true; 8]);
+
+        let mut result_sequence = self.handle.swd_io(direction, swd_io_sequence)?;
+
+        for transfer in 0..values.len() {
+            // Idle and request bits, see `write_register` for the phase shift.
+            result_sequence.split_off(2 + 8);
+
+            let ack = result_sequence.split_off(3).collect::<Vec<_>>();
+            result_sequence.split_off(SWD_TRANSFER_BITS - 2 - 8 - 3);
+
+            if ack[..] != [true, false, false] {
+                log::debug!("Pipelined write {} not acknowledged: {:?}", transfer, ack);
+                return Ok(transfer);
+            }
+        }
+
+        Ok(values.len())
+    }
+}
+
 impl<'a> AsRef<dyn DebugProbe + 'a> for JLink {
     fn as_ref(&self) -> &(dyn DebugProbe + 'a) {
         self
@@ -928,17 +1113,13 @@ impl DAPAccess for JLink {
                     ctrl
                 );
 
-                // Check the reason for the fault
-                // Other fault reasons than overrun or write error are not handled yet.
-                if ctrl.sticky_orun() || ctrl.sticky_err() {
-                    // We did not handle a WAIT state properly
-
+                // An overrun only means that we did not handle a WAIT state properly,
+                // so the transfer is repeated. Other faults are left to the ARM interface,
+                // which reads why the transfer failed and clears the sticky flags.
+                if ctrl.sticky_orun() && !ctrl.sticky_err() && !ctrl.w_data_err() {
                     // Because we use overrun detection, we now have to clear the overrun error
                     let mut abort = Abort(0);
-
-                    // Clear sticky error flags
-                    abort.set_orunerrclr(ctrl.sticky_orun());
-                    abort.set_stkerrclr(ctrl.sticky_err());
+                    abort.set_orunerrclr(true);
 
                     DAPAccess::write_register(
                         self,
@@ -1085,17 +1266,13 @@ impl DAPAccess for JLink {
                     ctrl
                 );
 
-                // Check the reason for the fault
-                // Other fault reasons than overrun or write error are not handled yet.
-                if ctrl.sticky_orun() || ctrl.sticky_err() {
-                    // We did not handle a WAIT state properly
-
+                // An overrun only means that we did not handle a WAIT state properly,
+                // so the transfer is repeated. Other faults are left to the ARM interface,
+                // which reads why the transfer failed and clears the sticky flags.
+                if ctrl.sticky_orun() && !ctrl.sticky_err() && !ctrl.w_data_err() {
                     // Because we use overrun detection, we now have to clear the overrun error
                     let mut abort = Abort(0);
-
-                    // Clear sticky error flags
-                    abort.set_orunerrclr(ctrl.sticky_orun());
-                    abort.set_stkerrclr(ctrl.sticky_err());
+                    abort.set_orunerrclr(true);
 
                     DAPAccess::write_register(
                         self,
@@ -1120,11 +1297,92 @@ impl DAPAccess for JLink {
         Err(DebugProbeError::Timeout)
     }
 
+    fn read_block(
+        &mut self,
+        port: PortType,
+        address: u16,
+        values: &mut [u32],
+    ) -> Result<(), DebugProbeError> {
+        read_block_pipelined(self, port, address, values)
+    }
+
+    fn write_block(
+        &mut self,
+        port: PortType,
+        address: u16,
+        values: &[u32],
+    ) -> Result<(), DebugProbeError> {
+        write_block_pipelined(self, port, address, values)
+    }
+
     fn into_probe(self: Box<Self>) -> Box<dyn DebugProbe> {
         self
     }
 }
 
+/// Reads a block of values, pipelining up to [MAX_PIPELINED_TRANSFERS] AP reads at once.
+fn read_block_pipelined(
+    probe: &mut impl PipelinedApAccess,
+    port: PortType,
+    address: u16,
+    values: &mut [u32],
+) -> Result<(), DebugProbeError> {
+    // Only AP reads are posted, so DP reads can't be pipelined.
+    if let PortType::DebugPort = port {
+        for value in values {
+            *value = DAPAccess::read_register(probe, port, address)?;
+        }
+        return Ok(());
+    }
+
+    let mut done = 0;
+
+    while done < values.len() {
+        let chunk_end = values.len().min(done + MAX_PIPELINED_TRANSFERS);
+        done += probe.read_ap_pipelined(port, address, &mut values[done..chunk_end])?;
+
+        // The transfer which was not acknowledged is repeated on its own,
+        // which handles WAIT and FAULT responses.
+        if done < chunk_end {
+            values[done] = DAPAccess::read_register(probe, port, address)?;
+            done += 1;
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes a block of values, pipelining up to [MAX_PIPELINED_TRANSFERS] AP writes at once.
+fn write_block_pipelined(
+    probe: &mut impl PipelinedApAccess,
+    port: PortType,
+    address: u16,
+    values: &[u32],
+) -> Result<(), DebugProbeError> {
+    if let PortType::DebugPort = port {
+        for value in values {
+            DAPAccess::write_register(probe, port, address, *value)?;
+        }
+        return Ok(());
+    }
+
+    let mut done = 0;
+
+    while done < values.len() {
+        let chunk_end = values.len().min(done + MAX_PIPELINED_TRANSFERS);
+        done += probe.write_ap_pipelined(port, address, &values[done..chunk_end])?;
+
+        // The transfer which was not acknowledged is repeated on its own,
+        // which handles WAIT and FAULT responses.
+        if done < chunk_end {
+            DAPAccess::write_register(probe, port, address, values[done])?;
+            done += 1;
+        }
+    }
+
+    Ok(())
+}
+
 impl SwoAccess for JLink {
     fn enable_swo(&mut self, config: &SwoConfig) -> Result<(), ProbeRsError> {
         self.swo_config = Some(*config);
@@ -1183,6 +1441,20 @@ fn bits_to_byte(bits: impl IntoIterator<Item = bool>) -> u32 {
     bit_val
 }
 
+/// Finds and opens J-Link probes.
+#[derive(Debug)]
+pub(crate) struct JLinkFactory;
+
+impl ProbeFactory for JLinkFactory {
+    fn list_probes(&self) -> Vec<DebugProbeInfo> {
+        list_jlink_devices()
+    }
+
+    fn open(&self, selector: &DebugProbeSelector) -> Result<Box<dyn DebugProbe>, DebugProbeError> {
+        Ok(JLink::new_from_selector(selector.clone())?)
+    }
+}
+
 pub(crate) fn list_jlink_devices() -> Vec<DebugProbeInfo> {
     match jaylink::scan_usb() {
         Ok(devices) => devices
@@ -1245,3 +1517,128 @@ impl TryFrom<jaylink::Interface> for WireProtocol {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probe::{FakeAck, FakeDap};
+    use std::sync::atomic::Ordering;
+
+    /// The DRW register of a MEM-AP, which is accessed by block transfers.
+    const DRW: u16 = 0xC;
+
+    /// Emulates the sequences of a J-Link with the transfers of the fake, one round trip each.
+    impl PipelinedApAccess for FakeDap {
+        fn read_ap_pipelined(
+            &mut self,
+            port: PortType,
+            address: u16,
+            values: &mut [u32],
+        ) -> Result<usize, DebugProbeError> {
+            assert!(values.len() <= MAX_PIPELINED_TRANSFERS);
+            self.transactions.fetch_add(1, Ordering::SeqCst);
+
+            for (transfer, value) in values.iter_mut().enumerate() {
+                match self.transfer(port, address, None) {
+                    Ok(read) => *value = read,
+                    // The transfers after it are not performed by the target.
+                    Err(_) => return Ok(transfer),
+                }
+            }
+
+            Ok(values.len())
+        }
+
+        fn write_ap_pipelined(
+            &mut self,
+            port: PortType,
+            address: u16,
+            values: &[u32],
+        ) -> Result<usize, DebugProbeError> {
+            assert!(values.len() <= MAX_PIPELINED_TRANSFERS);
+            self.transactions.fetch_add(1, Ordering::SeqCst);
+
+            for (transfer, value) in values.iter().enumerate() {
+                if self.transfer(port, address, Some(*value)).is_err() {
+                    return Ok(transfer);
+                }
+            }
+
+            Ok(values.len())
+        }
+    }
+
+    /// A DRW register which returns consecutive numbers, and answers its read number `wait_at` with WAIT.
+    fn counting_dap(wait_at: Option<usize>) -> FakeDap {
+        let mut reads = 0;
+        let mut next = 0;
+
+        FakeDap::new("J-Link").with_target(move |_, port, address, value| {
+            match (port, address, value) {
+                (PortType::AccessPort(_), DRW, None) => {
+                    reads += 1;
+                    if Some(reads) == wait_at {
+                        return Some(Err(DapError::WaitResponse.into()));
+                    }
+                    next += 1;
+                    Some(Ok(next - 1))
+                }
+                _ => None,
+            }
+        })
+    }
+
+    #[test]
+    fn block_reads_are_split_into_sequences() {
+        let mut dap = counting_dap(None);
+        let mut values = [0; 70];
+
+        read_block_pipelined(&mut dap, PortType::AccessPort(0), DRW, &mut values).unwrap();
+
+        assert_eq!(values.to_vec(), (0..70).collect::<Vec<_>>());
+        assert_eq!(dap.transactions.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn unacknowledged_block_read_is_repeated() {
+        let mut dap = counting_dap(Some(6));
+        let mut values = [0; 10];
+
+        read_block_pipelined(&mut dap, PortType::AccessPort(0), DRW, &mut values).unwrap();
+
+        // No value is skipped or read twice.
+        assert_eq!(values.to_vec(), (0..10).collect::<Vec<_>>());
+        // The sequence up to the WAIT, the repeated read, and a sequence for the rest.
+        assert_eq!(dap.transactions.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn faulted_block_write_is_repeated() {
+        let mut dap =
+            FakeDap::new("J-Link").with_acks(PortType::AccessPort(0), DRW, &[FakeAck::Fault]);
+        let values: Vec<u32> = (0..40).collect();
+
+        write_block_pipelined(&mut dap, PortType::AccessPort(0), DRW, &values).unwrap();
+
+        let writes: Vec<u32> = dap
+            .writes
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|&(_, _, value)| value)
+            .collect();
+        assert_eq!(writes, values);
+    }
+
+    #[test]
+    fn debug_port_block_reads_are_not_pipelined() {
+        let mut dap = FakeDap::new("J-Link").with_register(PortType::DebugPort, 0x8, 0x42);
+        let mut values = [0; 3];
+
+        read_block_pipelined(&mut dap, PortType::DebugPort, 0x8, &mut values).unwrap();
+
+        assert_eq!(values, [0x42; 3]);
+        // Every read is a round trip of its own.
+        assert_eq!(dap.transactions.load(Ordering::SeqCst), 3);
+    }
+}
@@ -1,18 +1,30 @@
+pub(crate) mod blackmagic;
 pub(crate) mod daplink;
+pub(crate) mod factory;
 #[cfg(feature = "ftdi")]
 pub(crate) mod ftdi;
 pub(crate) mod jlink;
+pub(crate) mod remote;
+pub(crate) mod shared;
+pub(crate) mod speed;
 pub(crate) mod stlink;
+pub(crate) mod wakeup;
+pub(crate) mod wchlink;
 
 use crate::architecture::{
     arm::{communication_interface::ArmProbeInterface, DAPAccess, PortType, SwoAccess},
     riscv::communication_interface::RiscvCommunicationInterface,
 };
 use crate::config::{RegistryError, TargetSelector};
+use crate::core::Architecture;
 use crate::error::Error;
 use crate::Session;
-use jlink::list_jlink_devices;
-use std::{convert::TryFrom, fmt};
+pub use factory::{register_probe_factory, ProbeFactory};
+#[cfg(feature = "ftdi")]
+pub use ftdi::{FtdiProbe, SwdioDirectionPin};
+pub use remote::ProbeServer;
+pub use speed::SpeedSelection;
+use std::{convert::TryFrom, fmt, time::Duration};
 use thiserror::Error;
 
 #[derive(Copy, Clone, PartialEq, Debug, serde::Serialize, serde::Deserialize)]
@@ -45,11 +57,26 @@ impl std::str::FromStr for WireProtocol {
     }
 }
 
+/// The power related functions a probe supports.
+///
+/// Some probes only find out whether the hardware supports a function when it is used.
+/// These functions are reported as supported, and return
+/// [DebugProbeError::CommandNotSupportedByProbe] on hardware without them.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct PowerCapabilities {
+    /// [Probe::target_voltage] measures the target voltage.
+    pub target_voltage: bool,
+    /// [Probe::target_current] measures the current drawn by the target.
+    pub target_current: bool,
+    /// [Probe::set_target_power] switches the power supply of the target.
+    pub target_power: bool,
+}
+
 /// A command queued in a batch for later execution
 ///
 /// Mostly used internally but returned in DebugProbeError to indicate
 /// which batched command actually encountered the error.
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum BatchCommand {
     Read(PortType, u16),
     Write(PortType, u16, u32),
@@ -68,6 +95,21 @@ impl fmt::Display for BatchCommand {
     }
 }
 
+/// Assembles the request of an SWD transfer, in the order the bits are sent.
+pub(crate) fn swd_request(port: PortType, read: bool, address: u16) -> u8 {
+    let ap = match port {
+        PortType::DebugPort => 0,
+        PortType::AccessPort(_) => 1,
+    };
+    let read = read as u8;
+    let a2 = (address >> 2) as u8 & 1;
+    let a3 = (address >> 3) as u8 & 1;
+    let parity = (ap + read + a2 + a3) & 1;
+
+    // Start bit, APnDP, RnW, A[2:3], parity, stop bit and park bit.
+    1 | ap << 1 | read << 2 | a2 << 3 | a3 << 4 | parity << 5 | 1 << 7
+}
+
 #[derive(Error, Debug)]
 pub enum DebugProbeError {
     #[error("USB Communication Error")]
@@ -105,8 +147,14 @@ pub enum DebugProbeError {
     BatchError(BatchCommand),
     #[error("Command not supported by probe")]
     CommandNotSupportedByProbe,
+    #[error("The probe '{0}' can not drive the reset line of the target, which is required to attach under reset")]
+    ResetNotSupported(String),
     #[error("Unable to set hardware breakpoint, all available breakpoint units are in use.")]
     BreakpointUnitsExceeded,
+    #[error("The probe was reconnected, the last request was not completed")]
+    ProbeReconnected,
+    #[error("The target did not wake up within {timeout:?}, after {attempts} attempts to connect")]
+    WakeupTimeout { timeout: Duration, attempts: usize },
     #[error(transparent)]
     Other(#[from] anyhow::Error),
 }
@@ -147,6 +195,11 @@ pub enum ProbeCreationError {
 pub struct Probe {
     inner: Box<dyn DebugProbe>,
     attached: bool,
+    speed_selection: SpeedSelection,
+    freeze_watchdogs: Option<bool>,
+    frozen_peripherals: Vec<String>,
+    /// How long the attach waited for the target to wake up.
+    woke_up_after: Option<Duration>,
 }
 
 impl Probe {
@@ -154,6 +207,10 @@ impl Probe {
         Self {
             inner: Box::new(probe),
             attached: false,
+            speed_selection: SpeedSelection::default(),
+            freeze_watchdogs: None,
+            frozen_peripherals: Vec::new(),
+            woke_up_after: None,
         }
     }
 
@@ -161,6 +218,10 @@ impl Probe {
         Self {
             inner: probe,
             attached: true,
+            speed_selection: SpeedSelection::default(),
+            freeze_watchdogs: None,
+            frozen_peripherals: Vec::new(),
+            woke_up_after: None,
         }
     }
 
@@ -168,108 +229,83 @@ impl Probe {
         Probe {
             inner: probe,
             attached: false,
+            speed_selection: SpeedSelection::default(),
+            freeze_watchdogs: None,
+            frozen_peripherals: Vec::new(),
+            woke_up_after: None,
         }
     }
 
     /// Get a list of all debug probes found.
     /// This can be used to select the debug probe which
     /// should be used.
+    ///
+    /// The probes are listed by all drivers, including the ones added with [register_probe_factory].
     pub fn list_all() -> Vec<DebugProbeInfo> {
-        let mut list = daplink::tools::list_daplink_devices();
-        #[cfg(feature = "ftdi")]
-        {
-            list.extend(ftdi::list_ftdi_devices());
-        }
-        list.extend(stlink::tools::list_stlink_devices());
-
-        list.extend(list_jlink_devices());
-
-        list
+        factory::factories()
+            .iter()
+            .flat_map(|factory| factory.list_probes())
+            .collect()
     }
 
     /// Create a `Probe` from `DebugProbeInfo`. Use the
     /// `Probe::list_all()` function to get the information
     /// about all probes available.
+    ///
+    /// If another process shares the probe, see [Probe::open_shared], this connects to it.
     pub fn open(selector: impl Into<DebugProbeSelector> + Clone) -> Result<Self, DebugProbeError> {
-        match daplink::DAPLink::new_from_selector(selector.clone()) {
-            Ok(link) => return Ok(Probe::from_specific_probe(link)),
-            Err(DebugProbeError::ProbeCouldNotBeCreated(ProbeCreationError::NotFound)) => {}
-            Err(e) => return Err(e),
-        };
-        #[cfg(feature = "ftdi")]
-        match ftdi::FtdiProbe::new_from_selector(selector.clone()) {
-            Ok(link) => return Ok(Probe::from_specific_probe(link)),
-            Err(DebugProbeError::ProbeCouldNotBeCreated(ProbeCreationError::NotFound)) => {}
-            Err(e) => return Err(e),
-        };
-        match stlink::STLink::new_from_selector(selector.clone()) {
-            Ok(link) => return Ok(Probe::from_specific_probe(link)),
-            Err(DebugProbeError::ProbeCouldNotBeCreated(ProbeCreationError::NotFound)) => {}
-            Err(e) => return Err(e),
-        };
-        match jlink::JLink::new_from_selector(selector) {
-            Ok(link) => return Ok(Probe::from_specific_probe(link)),
-            Err(DebugProbeError::ProbeCouldNotBeCreated(ProbeCreationError::NotFound)) => {}
-            Err(e) => return Err(e),
-        };
+        let selector = selector.into();
+
+        match shared::connect(&selector) {
+            Some(probe) => Ok(probe),
+            None => Probe::open_local(selector),
+        }
+    }
+
+    /// Opens the probe and makes it available to other probe-rs processes on this host.
+    ///
+    /// While the returned probe exists, [Probe::open] in other processes connects to it
+    /// instead of failing because the probe is in use. These processes can access the memory
+    /// of the running target, e.g. to poll RTT, but only this process may halt and reset the
+    /// target. If another process already shares the probe, this connects to it as well.
+    ///
+    /// Probes which give no raw DAP access, e.g. the ST-Link, are opened without sharing.
+    /// The SWO interface of shared probes is not available.
+    pub fn open_shared(
+        selector: impl Into<DebugProbeSelector> + Clone,
+    ) -> Result<Self, DebugProbeError> {
+        let selector = selector.into();
+
+        if let Some(probe) = shared::connect(&selector) {
+            return Ok(probe);
+        }
+
+        let probe = Probe::open_local(selector.clone())?;
+        shared::share(probe, &selector)
+    }
+
+    /// Opens the probe with the first driver which finds it.
+    fn open_local(selector: DebugProbeSelector) -> Result<Self, DebugProbeError> {
+        for factory in factory::factories() {
+            match factory.open(&selector) {
+                Ok(probe) => return Ok(Probe::from_specific_probe(probe)),
+                Err(DebugProbeError::ProbeCouldNotBeCreated(ProbeCreationError::NotFound)) => {}
+                Err(e) => return Err(e),
+            }
+        }
 
         Err(DebugProbeError::ProbeCouldNotBeCreated(
             ProbeCreationError::NotFound,
         ))
     }
 
-    // /// Tries to mass erase a locked nRF52 chip, this process may timeout, if it does, the chip
-    // /// might be unlocked or not, it is advised to try again if flashing fails
-    // pub fn nrf_recover(&mut self) -> Result<(), DebugProbeError> {
-    //     let ctrl_port = match get_ap_by_idr(self, |idr| idr == CTRL_AP_IDR) {
-    //         Some(port) => CtrlAP::from(port),
-    //         None => {
-    //             return Err(DebugProbeError::AccessPortError(
-    //                 AccessPortError::CtrlAPNotFound,
-    //             ));
-    //         }
-    //     };
-    //     log::info!("Starting mass erase...");
-    //     let mut erase_reg = ERASEALL::from(1);
-    //     let status_reg = ERASEALLSTATUS::from(0);
-    //     let mut reset_reg = RESET::from(1);
-
-    //     // Reset first
-    //     self.write_ap_register(ctrl_port, reset_reg)?;
-    //     reset_reg.RESET = false;
-    //     self.write_ap_register(ctrl_port, reset_reg)?;
-
-    //     self.write_ap_register(ctrl_port, erase_reg)?;
-
-    //     // Prepare timeout
-    //     let now = Instant::now();
-    //     let status = self.read_ap_register(ctrl_port, status_reg)?;
-    //     log::info!("Erase status: {:?}", status.ERASEALLSTATUS);
-    //     let timeout = loop {
-    //         let status = self.read_ap_register(ctrl_port, status_reg)?;
-    //         if !status.ERASEALLSTATUS {
-    //             break false;
-    //         }
-    //         if now.elapsed().as_secs() >= UNLOCK_TIMEOUT {
-    //             break true;
-    //         }
-    //     };
-    //     reset_reg.RESET = true;
-    //     self.write_ap_register(ctrl_port, reset_reg)?;
-    //     reset_reg.RESET = false;
-    //     self.write_ap_register(ctrl_port, reset_reg)?;
-    //     erase_reg.ERASEALL = false;
-    //     self.write_ap_register(ctrl_port, erase_reg)?;
-    //     if timeout {
-    //         log::error!(
-    //             "    {} Mass erase process timeout, the chip might still be locked.",
-    //             "Error".red().bold()
-    //         );
-    //     } else {
-    //         log::info!("Mass erase completed, chip unlocked");
-    //     }
-    //     Ok(())
-    // }
+    /// Opens a probe which is made available by a [ProbeServer] on another host.
+    ///
+    /// The `address` has the form `tcp://token@host:port`.
+    pub fn open_remote(address: &str) -> Result<Self, DebugProbeError> {
+        let probe = remote::RemoteProbe::connect(address)?;
+        Ok(Probe::from_specific_probe(Box::new(probe)))
+    }
 
     /// Get human readable name for the probe
     pub fn get_name(&self) -> String {
@@ -281,37 +317,135 @@ impl Probe {
     /// This runs all the necessary protocol init routines.
     ///
     /// If this doesn't work, you might want to try `attach_under_reset`
-    pub fn attach(mut self, target: impl Into<TargetSelector>) -> Result<Session, Error> {
-        self.inner.attach()?;
+    pub fn attach(self, target: impl Into<TargetSelector>) -> Result<Session, Error> {
+        self.attach_with_method(target, AttachMethod::Normal)
+    }
+
+    /// Attach to the chip with the given [AttachMethod].
+    pub fn attach_with_method(
+        mut self,
+        target: impl Into<TargetSelector>,
+        method: AttachMethod,
+    ) -> Result<Session, Error> {
+        let target = target.into();
+
+        if let AttachMethod::UnderReset { assert_time, .. } = method {
+            log::debug!("Asserting reset");
+            let name = self.get_name();
+            self.inner
+                .target_reset_assert()
+                .map_err(|error| match error {
+                    DebugProbeError::CommandNotSupportedByProbe
+                    | DebugProbeError::NotImplemented(_) => {
+                        DebugProbeError::ResetNotSupported(name)
+                    }
+                    error => error,
+                })?;
+
+            std::thread::sleep(assert_time);
+        }
+
+        if let AttachMethod::OnWakeup {
+            timeout,
+            poll_interval,
+        } = method
+        {
+            // The link can not be checked while the target sleeps, so the configured speed is used.
+            let waited = wakeup::wait_for_wakeup(self.inner.as_mut(), timeout, poll_interval)?;
+            self.woke_up_after = Some(waited);
+        } else {
+            self.attach_with_speed_selection(&target)?;
+        }
         self.attached = true;
 
-        Session::new(self, target, AttachMethod::Normal)
+        // The session will de-assert reset after connecting to the debug interface.
+        Session::new(self, target, method, false)
+    }
+
+    /// Attach to the chip without halting or resetting it.
+    ///
+    /// The cores keep running, only their memory and debug registers are accessed.
+    /// See [Session::is_hot_attached()].
+    ///
+    /// This is only supported for ARM-based targets, and will
+    /// return [Error::ArchitectureRequired] otherwise.
+    pub fn attach_running(mut self, target: impl Into<TargetSelector>) -> Result<Session, Error> {
+        let target = target.into();
+
+        self.attach_with_speed_selection(&target)?;
+        self.attached = true;
+
+        Session::new(self, target, AttachMethod::Normal, true)
     }
 
     pub fn attach_to_unspecified(&mut self) -> Result<(), Error> {
-        self.inner.attach()?;
+        self.attach_with_speed_selection(&TargetSelector::Auto)?;
         self.attached = true;
         Ok(())
     }
 
+    /// Attaches the probe, and selects the speed as configured with [Probe::set_speed_selection].
+    fn attach_with_speed_selection(&mut self, target: &TargetSelector) -> Result<(), Error> {
+        let increase = match self.speed_selection {
+            SpeedSelection::Fixed => return Ok(self.inner.attach()?),
+            SpeedSelection::Adaptive { increase } => increase,
+        };
+
+        let target = match target {
+            TargetSelector::Unspecified(name) => crate::config::get_target_by_name(name).ok(),
+            TargetSelector::Specified(target) => Some(target.clone()),
+            TargetSelector::Auto => None,
+        };
+
+        if let Some(target) = target {
+            // The link check uses the ARM debug port.
+            if target.architecture() != Architecture::Arm {
+                return Ok(self.inner.attach()?);
+            }
+
+            if let Some(speed_khz) = target.recommended_speed_khz {
+                match self.inner.set_speed(speed_khz) {
+                    Ok(actual_speed) => log::debug!(
+                        "Using the speed recommended for {}: {} kHz",
+                        target.name,
+                        actual_speed
+                    ),
+                    Err(e) => log::debug!(
+                        "Unable to use the speed recommended for {}: {}",
+                        target.name,
+                        e
+                    ),
+                }
+            }
+        }
+
+        speed::attach(self.inner.as_mut(), increase)?;
+        Ok(())
+    }
+
     /// Attach to the chip under hard-reset.
     ///
     /// This asserts the reset pin via the probe, plays the protocol init routines and deasserts the pin.
     /// This is necessary if the chip is not responding to the SWD reset sequence.
     /// For example this can happen if the chip has the SWDIO pin remapped.
-    pub fn attach_under_reset(
-        mut self,
+    ///
+    /// The default timing of [AttachMethod::under_reset()] is used,
+    /// use [Probe::attach_with_method()] to change it.
+    pub fn attach_under_reset(self, target: impl Into<TargetSelector>) -> Result<Session, Error> {
+        self.attach_with_method(target, AttachMethod::under_reset())
+    }
+
+    /// Attach to a chip which spends most of its time in a deep sleep mode,
+    /// as soon as it wakes up, see [AttachMethod::OnWakeup].
+    ///
+    /// Fails with [DebugProbeError::WakeupTimeout] if the chip did not wake up within `timeout`.
+    /// [Session::woke_up_after()] returns how long it took.
+    pub fn attach_on_wakeup(
+        self,
         target: impl Into<TargetSelector>,
+        timeout: Duration,
     ) -> Result<Session, Error> {
-        log::debug!("Asserting reset");
-        self.inner.target_reset_assert()?;
-
-        self.inner.attach()?;
-
-        self.attached = true;
-
-        // The session will de-assert reset after connecting to the debug interface.
-        Session::new(self, target, AttachMethod::UnderReset)
+        self.attach_with_method(target, AttachMethod::on_wakeup(timeout))
     }
 
     /// Selects the transport protocol to be used by the debug probe.
@@ -340,10 +474,48 @@ impl Probe {
         self.inner.target_reset_deassert()
     }
 
+    /// Returns which of the power related functions the probe supports.
+    pub fn power_capabilities(&self) -> PowerCapabilities {
+        self.inner.power_capabilities()
+    }
+
+    /// Measures the target voltage in volts, e.g. at the VTref pin of the probe.
+    pub fn target_voltage(&mut self) -> Result<f32, DebugProbeError> {
+        self.inner.target_voltage()
+    }
+
+    /// Measures the current drawn by the target in amperes.
+    pub fn target_current(&mut self) -> Result<f32, DebugProbeError> {
+        self.inner.target_current()
+    }
+
+    /// Switches the power supply of the target, if the probe is able to power it.
+    pub fn set_target_power(&mut self, enabled: bool) -> Result<(), DebugProbeError> {
+        self.inner.set_target_power(enabled)
+    }
+
+    /// Removes the read protection of the flash, for probes which are able to do so.
+    ///
+    /// This erases the flash of the chip.
+    pub fn disable_flash_protection(&mut self) -> Result<(), DebugProbeError> {
+        self.inner.disable_flash_protection()
+    }
+
+    /// Configures how the probe reconnects after its USB connection was lost.
+    ///
+    /// See [ReconnectPolicy] for the behavior after a reconnect.
+    pub fn set_reconnect_policy(&mut self, policy: ReconnectPolicy) -> Result<(), DebugProbeError> {
+        self.inner.set_reconnect_policy(policy)
+    }
+
     /// Configure protocol speed to use in kHz
+    ///
+    /// The speed is used as is when attaching, see [Probe::set_speed_selection].
     pub fn set_speed(&mut self, speed_khz: u32) -> Result<u32, DebugProbeError> {
         if !self.attached {
-            self.inner.set_speed(speed_khz)
+            let speed = self.inner.set_speed(speed_khz)?;
+            self.speed_selection = SpeedSelection::Fixed;
+            Ok(speed)
         } else {
             Err(DebugProbeError::Attached)
         }
@@ -354,6 +526,44 @@ impl Probe {
         self.inner.speed()
     }
 
+    /// Selects how the protocol speed is chosen when attaching to the target.
+    ///
+    /// By default, the speed recommended for the target is used, and lowered if the
+    /// communication fails. [Probe::set_speed] selects [SpeedSelection::Fixed].
+    pub fn set_speed_selection(&mut self, selection: SpeedSelection) {
+        self.speed_selection = selection;
+    }
+
+    /// Selects whether the watchdogs of the target are stopped while its cores are halted,
+    /// see [Session::freeze_watchdogs].
+    ///
+    /// By default, they are stopped unless the session is opened with [Probe::attach_running].
+    pub fn set_freeze_watchdogs(&mut self, freeze: bool) {
+        self.freeze_watchdogs = Some(freeze);
+    }
+
+    /// Returns whether the watchdogs should be stopped, if it was selected
+    /// with [Probe::set_freeze_watchdogs].
+    pub(crate) fn freeze_watchdogs(&self) -> Option<bool> {
+        self.freeze_watchdogs
+    }
+
+    /// Selects the peripherals which are stopped while the cores of the target are halted,
+    /// by the names of their freeze bits, see [Session::freeze_peripherals].
+    pub fn set_frozen_peripherals(&mut self, names: Vec<String>) {
+        self.frozen_peripherals = names;
+    }
+
+    /// Returns the peripherals selected with [Probe::set_frozen_peripherals].
+    pub(crate) fn frozen_peripherals(&self) -> &[String] {
+        &self.frozen_peripherals
+    }
+
+    /// Returns how long the attach with [AttachMethod::OnWakeup] waited for the target.
+    pub(crate) fn woke_up_after(&self) -> Option<Duration> {
+        self.woke_up_after
+    }
+
     /// Check if the probe has an interface to
     /// debug ARM chips.
     pub fn has_arm_interface(&self) -> bool {
@@ -448,6 +658,44 @@ pub trait DebugProbe: Send + fmt::Debug {
     /// Selects the transport protocol to be used by the debug probe.
     fn select_protocol(&mut self, protocol: WireProtocol) -> Result<(), DebugProbeError>;
 
+    /// Returns which of the power related functions the probe supports.
+    fn power_capabilities(&self) -> PowerCapabilities {
+        PowerCapabilities::default()
+    }
+
+    /// Measures the target voltage in volts, for probes which are able to do so.
+    fn target_voltage(&mut self) -> Result<f32, DebugProbeError> {
+        Err(DebugProbeError::CommandNotSupportedByProbe)
+    }
+
+    /// Measures the current drawn by the target in amperes, for probes which are able to do so.
+    fn target_current(&mut self) -> Result<f32, DebugProbeError> {
+        Err(DebugProbeError::CommandNotSupportedByProbe)
+    }
+
+    /// Switches the power supply of the target, for probes which are able to power it.
+    fn set_target_power(&mut self, _enabled: bool) -> Result<(), DebugProbeError> {
+        Err(DebugProbeError::CommandNotSupportedByProbe)
+    }
+
+    /// Removes the read protection of the flash with a command of the probe,
+    /// for probes which are able to do so.
+    fn disable_flash_protection(&mut self) -> Result<(), DebugProbeError> {
+        Err(DebugProbeError::CommandNotSupportedByProbe)
+    }
+
+    /// Configures how the probe reconnects after its USB connection was lost,
+    /// for probes which are able to do so.
+    fn set_reconnect_policy(&mut self, _policy: ReconnectPolicy) -> Result<(), DebugProbeError> {
+        Err(DebugProbeError::CommandNotSupportedByProbe)
+    }
+
+    /// Returns true if the DAP transfers of the probe rely on overrun detection
+    /// being enabled in the debug port.
+    fn uses_overrun_detection(&self) -> bool {
+        false
+    }
+
     /// Check if the proble offers an interface to debug ARM chips.
     fn has_arm_interface(&self) -> bool {
         false
@@ -481,6 +729,50 @@ pub trait DebugProbe: Send + fmt::Debug {
     fn get_swo_interface_mut(&mut self) -> Option<&mut dyn SwoAccess> {
         None
     }
+
+    /// Get the raw DAP register access of the probe, e.g. to forward it to a remote client.
+    fn get_dap_interface_mut(&mut self) -> Option<&mut dyn DAPAccess> {
+        None
+    }
+
+    /// Get the raw JTAG register access of the probe, e.g. to forward it to a remote client.
+    fn get_jtag_interface_mut(&mut self) -> Option<&mut dyn JTAGAccess> {
+        None
+    }
+}
+
+/// How a probe reconnects after its USB device was lost, e.g. because a USB hub was reset.
+///
+/// After reopening the device, the probe is initialized again, and the ID of the debug
+/// port is checked to make sure that it is still connected to the same target. Pending
+/// requests are repeated if this is safe, e.g. for register accesses. Otherwise,
+/// [DebugProbeError::ProbeReconnected] is returned, and the caller has to decide
+/// whether to repeat its request. Memory accesses are never repeated.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ReconnectPolicy {
+    /// How often reopening the probe is attempted. Zero disables reconnecting.
+    pub attempts: u32,
+    /// The delay before the first attempt, which grows by this amount with every attempt.
+    pub backoff: Duration,
+}
+
+impl ReconnectPolicy {
+    /// A policy which never reconnects, errors are returned as they occur.
+    pub fn disabled() -> Self {
+        Self {
+            attempts: 0,
+            backoff: Duration::from_millis(0),
+        }
+    }
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 5,
+            backoff: Duration::from_millis(200),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -489,6 +781,10 @@ pub enum DebugProbeType {
     FTDI,
     STLink,
     JLink,
+    BlackMagicProbe,
+    WchLink,
+    /// A probe of a driver which was added with [register_probe_factory], with the name of the driver.
+    Custom(String),
 }
 
 #[derive(Clone)]
@@ -498,6 +794,8 @@ pub struct DebugProbeInfo {
     pub product_id: u16,
     pub serial_number: Option<String>,
     pub probe_type: DebugProbeType,
+    /// The USB port path of the probe, e.g. `1-3.2`, for probes which can be selected by it.
+    pub port_path: Option<String>,
 }
 
 impl std::fmt::Debug for DebugProbeInfo {
@@ -531,9 +829,16 @@ impl DebugProbeInfo {
             product_id,
             serial_number,
             probe_type,
+            port_path: None,
         }
     }
 
+    /// Sets the USB port path, which allows to select the probe with a `usb:<port path>` selector.
+    pub fn with_port_path(mut self, port_path: Option<String>) -> Self {
+        self.port_path = port_path;
+        self
+    }
+
     /// Open the probe described by this `DebugProbeInfo`.
     pub fn open(&self) -> Result<Probe, DebugProbeError> {
         Probe::open(self)
@@ -544,14 +849,22 @@ impl DebugProbeInfo {
 pub enum DebugProbeSelectorParseError {
     #[error("The VID or PID could not be parsed: {0}")]
     ParseInt(#[from] std::num::ParseIntError),
-    #[error("Please use a string in the form `VID:PID:<Serial>` where Serial is optional.")]
+    #[error("Please use a string in the form `VID:PID:<Serial>` where Serial is optional, or `usb:<port path>`.")]
     Format,
+    #[error("No USB device was found at the port path {0}")]
+    PortPathNotFound(String),
 }
 
 /// A struct to describe the way a probe should be selected.
 ///
 /// Construct this from a set of info or from a string.
 ///
+/// Probes with identical serial numbers can be told apart by the USB port they are
+/// connected to, with a string in the form `usb:<port path>`. The port path consists
+/// of the bus number and the port numbers of the hubs on the way to the probe, e.g.
+/// `usb:1-3.2` for port 2 of the hub at port 3 of bus 1. Parsing such a string looks
+/// up the VID and PID of the device at this port.
+///
 /// Example:
 /// ```
 /// use std::convert::TryInto;
@@ -562,17 +875,72 @@ pub struct DebugProbeSelector {
     pub vendor_id: u16,
     pub product_id: u16,
     pub serial_number: Option<String>,
+    pub port_path: Option<String>,
+}
+
+impl DebugProbeSelector {
+    /// Returns true if the probe described by `info` is selected.
+    pub fn matches(&self, info: &DebugProbeInfo) -> bool {
+        info.vendor_id == self.vendor_id
+            && info.product_id == self.product_id
+            && (self.serial_number.is_none() || info.serial_number == self.serial_number)
+            && (self.port_path.is_none() || info.port_path == self.port_path)
+    }
+
+    /// Returns true if `device` is connected to the selected USB port, or if no port is selected.
+    pub(crate) fn matches_port_path<T: rusb::UsbContext>(&self, device: &rusb::Device<T>) -> bool {
+        self.port_path.is_none() || usb_port_path(device) == self.port_path
+    }
+
+    /// Creates a selector for the USB device at `port_path`.
+    fn from_port_path(port_path: &str) -> Result<Self, DebugProbeSelectorParseError> {
+        use rusb::UsbContext;
+
+        let valid = match port_path.split('-').collect::<Vec<_>>().as_slice() {
+            [bus, ports] => {
+                bus.parse::<u8>().is_ok() && ports.split('.').all(|port| port.parse::<u8>().is_ok())
+            }
+            _ => false,
+        };
+        if !valid {
+            return Err(DebugProbeSelectorParseError::Format);
+        }
+
+        let not_found = || DebugProbeSelectorParseError::PortPathNotFound(port_path.to_owned());
+
+        let devices = rusb::Context::new()
+            .and_then(|context| context.devices())
+            .map_err(|_| not_found())?;
+
+        let descriptor = devices
+            .iter()
+            .find(|device| usb_port_path(device).as_deref() == Some(port_path))
+            .and_then(|device| device.device_descriptor().ok())
+            .ok_or_else(not_found)?;
+
+        Ok(DebugProbeSelector {
+            vendor_id: descriptor.vendor_id(),
+            product_id: descriptor.product_id(),
+            serial_number: None,
+            port_path: Some(port_path.to_owned()),
+        })
+    }
 }
 
 impl TryFrom<&str> for DebugProbeSelector {
     type Error = DebugProbeSelectorParseError;
     fn try_from(value: &str) -> Result<Self, Self::Error> {
+        if let Some(port_path) = value.strip_prefix("usb:") {
+            return Self::from_port_path(port_path);
+        }
+
         let split = value.split(':').collect::<Vec<_>>();
         let mut selector = if split.len() > 1 {
             DebugProbeSelector {
                 vendor_id: u16::from_str_radix(split[0], 16)?,
                 product_id: u16::from_str_radix(split[1], 16)?,
                 serial_number: None,
+                port_path: None,
             }
         } else {
             return Err(DebugProbeSelectorParseError::Format);
@@ -606,6 +974,7 @@ impl From<DebugProbeInfo> for DebugProbeSelector {
             vendor_id: selector.vendor_id,
             product_id: selector.product_id,
             serial_number: selector.serial_number,
+            port_path: selector.port_path,
         }
     }
 }
@@ -616,10 +985,30 @@ impl From<&DebugProbeInfo> for DebugProbeSelector {
             vendor_id: selector.vendor_id,
             product_id: selector.product_id,
             serial_number: selector.serial_number.clone(),
+            port_path: selector.port_path.clone(),
         }
     }
 }
 
+/// Returns the USB port path of `device`, in the form `<bus>-<port>[.<port>...]`.
+///
+/// The path is built from the bus and port numbers reported by libusb,
+/// so it has the same form on all operating systems.
+pub(crate) fn usb_port_path<T: rusb::UsbContext>(device: &rusb::Device<T>) -> Option<String> {
+    let ports = device.port_numbers().ok()?;
+    if ports.is_empty() {
+        return None;
+    }
+
+    let ports = ports
+        .iter()
+        .map(|port| port.to_string())
+        .collect::<Vec<_>>()
+        .join(".");
+
+    Some(format!("{}-{}", device.bus_number(), ports))
+}
+
 #[derive(Default, Debug)]
 pub struct FakeProbe;
 
@@ -667,11 +1056,11 @@ impl DebugProbe for FakeProbe {
     }
 
     fn target_reset_assert(&mut self) -> Result<(), DebugProbeError> {
-        unimplemented!()
+        Err(DebugProbeError::CommandNotSupportedByProbe)
     }
 
     fn target_reset_deassert(&mut self) -> Result<(), DebugProbeError> {
-        unimplemented!()
+        Err(DebugProbeError::CommandNotSupportedByProbe)
     }
 }
 
@@ -708,6 +1097,314 @@ impl<'a> AsMut<dyn DebugProbe + 'a> for FakeProbe {
     }
 }
 
+/// A response other than OK, with which a [FakeDap] answers a transfer.
+#[cfg(test)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FakeAck {
+    Fault,
+    NoAck,
+}
+
+#[cfg(test)]
+impl From<FakeAck> for DebugProbeError {
+    fn from(ack: FakeAck) -> Self {
+        use crate::architecture::arm::DapError;
+
+        match ack {
+            FakeAck::Fault => DapError::FaultResponse,
+            FakeAck::NoAck => DapError::NoAcknowledge,
+        }
+        .into()
+    }
+}
+
+/// Emulates the target of a [FakeDap], see [FakeDap::with_target].
+#[cfg(test)]
+type FakeTarget = Box<
+    dyn FnMut(&mut FakeDap, PortType, u16, Option<u32>) -> Option<Result<u32, DebugProbeError>>
+        + Send,
+>;
+
+/// A probe with raw DAP access for tests, whose target is scripted.
+///
+/// The DAP registers keep the values written to them, and read as zero until then. Their
+/// values and the responses to their transfers can be preset, and targets which need more
+/// than that are emulated with [FakeDap::with_target]. Detaching resets the registers to
+/// their preset values. Like on a real debug port, writes to ABORT do not change DPIDR,
+/// and CTRL/STAT acknowledges the power-up requests written to it.
+///
+/// Writes are posted, so a FAULT in a block write is only reported at the end of the block.
+/// A batch stops at the first failed transfer.
+#[cfg(test)]
+pub(crate) struct FakeDap {
+    name: &'static str,
+    /// The protocol speed in kHz. It can only be changed while the probe is detached.
+    pub(crate) speed: u32,
+    max_speed: u32,
+    pub(crate) attached: bool,
+    /// How often the probe was attached.
+    pub(crate) attaches: usize,
+    presets: std::collections::HashMap<(u16, u16), u32>,
+    registers: std::collections::HashMap<(u16, u16), u32>,
+    acks: std::collections::HashMap<(u16, u16), std::collections::VecDeque<FakeAck>>,
+    target: Option<FakeTarget>,
+    /// The writes which were answered with OK, in their order.
+    pub(crate) writes: std::sync::Arc<std::sync::Mutex<Vec<(PortType, u16, u32)>>>,
+    /// The number of round trips to the probe. Like on CMSIS-DAP probes, the writes
+    /// are queued, and each read or batch is one round trip.
+    pub(crate) transactions: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+}
+
+#[cfg(test)]
+impl FakeDap {
+    pub(crate) fn new(name: &'static str) -> Self {
+        FakeDap {
+            name,
+            speed: 1000,
+            max_speed: u32::MAX,
+            attached: false,
+            attaches: 0,
+            presets: Default::default(),
+            registers: Default::default(),
+            acks: Default::default(),
+            target: None,
+            writes: Default::default(),
+            transactions: Default::default(),
+        }
+    }
+
+    /// Presets the value of a register.
+    pub(crate) fn with_register(mut self, port: PortType, addr: u16, value: u32) -> Self {
+        self.presets.insert((port.into(), addr), value);
+        self.registers.insert((port.into(), addr), value);
+        self
+    }
+
+    /// Answers the next transfers of a register with `acks`, one after the other,
+    /// and the transfers after them with OK.
+    pub(crate) fn with_acks(mut self, port: PortType, addr: u16, acks: &[FakeAck]) -> Self {
+        self.acks
+            .entry((port.into(), addr))
+            .or_default()
+            .extend(acks.iter().copied());
+        self
+    }
+
+    /// Limits the speed of the probe to `max_speed` kHz.
+    pub(crate) fn with_max_speed(mut self, max_speed: u32) -> Self {
+        self.max_speed = max_speed;
+        self
+    }
+
+    /// Passes the transfers to `target`, with the register and the value of a write.
+    ///
+    /// If `target` returns `None`, the transfer is made on the registers of the fake.
+    /// Scripted acks are answered before `target` is asked.
+    pub(crate) fn with_target(
+        mut self,
+        target: impl FnMut(&mut FakeDap, PortType, u16, Option<u32>) -> Option<Result<u32, DebugProbeError>>
+            + Send
+            + 'static,
+    ) -> Self {
+        self.target = Some(Box::new(target));
+        self
+    }
+
+    /// Returns the current value of a register.
+    pub(crate) fn register(&self, port: PortType, addr: u16) -> u32 {
+        self.registers
+            .get(&(port.into(), addr))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    fn transfer(
+        &mut self,
+        port: PortType,
+        addr: u16,
+        value: Option<u32>,
+    ) -> Result<u32, DebugProbeError> {
+        let key = (port.into(), addr);
+
+        if let Some(ack) = self.acks.get_mut(&key).and_then(|acks| acks.pop_front()) {
+            return Err(ack.into());
+        }
+
+        let mut response = None;
+        if let Some(mut target) = self.target.take() {
+            response = target(self, port, addr, value);
+            self.target = Some(target);
+        }
+
+        let response = response.unwrap_or_else(|| match (port, addr, value) {
+            // ABORT is write-only, DPIDR is read at the same address.
+            (PortType::DebugPort, 0x0, Some(_)) => Ok(0),
+            // CDBGPWRUPACK and CSYSPWRUPACK follow their requests.
+            (PortType::DebugPort, 0x4, None) => {
+                let ctrl = self.register(port, addr);
+                Ok(ctrl | (ctrl & 0x5000_0000) << 1)
+            }
+            (_, _, Some(value)) => {
+                self.registers.insert(key, value);
+                Ok(value)
+            }
+            (_, _, None) => Ok(self.register(port, addr)),
+        });
+
+        if let (Ok(_), Some(value)) = (&response, value) {
+            self.writes.lock().unwrap().push((port, addr, value));
+        }
+
+        response
+    }
+}
+
+#[cfg(test)]
+impl fmt::Debug for FakeDap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FakeDap")
+            .field("name", &self.name)
+            .field("speed", &self.speed)
+            .field("attached", &self.attached)
+            .field("registers", &self.registers)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+impl DebugProbe for FakeDap {
+    fn new_from_selector(
+        _selector: impl Into<DebugProbeSelector>,
+    ) -> Result<Box<Self>, DebugProbeError>
+    where
+        Self: Sized,
+    {
+        Err(DebugProbeError::ProbeCouldNotBeCreated(
+            ProbeCreationError::Other("This is a fake probe."),
+        ))
+    }
+
+    fn get_name(&self) -> &str {
+        self.name
+    }
+
+    fn speed(&self) -> u32 {
+        self.speed
+    }
+
+    fn set_speed(&mut self, speed_khz: u32) -> Result<u32, DebugProbeError> {
+        assert!(!self.attached, "The speed was changed while attached");
+        self.speed = speed_khz.min(self.max_speed);
+        Ok(self.speed)
+    }
+
+    fn attach(&mut self) -> Result<(), DebugProbeError> {
+        self.attached = true;
+        self.attaches += 1;
+        Ok(())
+    }
+
+    fn detach(&mut self) -> Result<(), DebugProbeError> {
+        self.attached = false;
+        self.registers = self.presets.clone();
+        Ok(())
+    }
+
+    fn target_reset(&mut self) -> Result<(), DebugProbeError> {
+        Ok(())
+    }
+
+    fn target_reset_assert(&mut self) -> Result<(), DebugProbeError> {
+        Err(DebugProbeError::CommandNotSupportedByProbe)
+    }
+
+    fn target_reset_deassert(&mut self) -> Result<(), DebugProbeError> {
+        Err(DebugProbeError::CommandNotSupportedByProbe)
+    }
+
+    fn select_protocol(&mut self, _protocol: WireProtocol) -> Result<(), DebugProbeError> {
+        Ok(())
+    }
+
+    fn has_arm_interface(&self) -> bool {
+        true
+    }
+
+    fn get_dap_interface_mut(&mut self) -> Option<&mut dyn DAPAccess> {
+        Some(self as _)
+    }
+}
+
+#[cfg(test)]
+impl DAPAccess for FakeDap {
+    fn read_register(&mut self, port: PortType, addr: u16) -> Result<u32, DebugProbeError> {
+        self.transactions
+            .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        self.transfer(port, addr, None)
+    }
+
+    fn write_register(
+        &mut self,
+        port: PortType,
+        addr: u16,
+        value: u32,
+    ) -> Result<(), DebugProbeError> {
+        self.transfer(port, addr, Some(value)).map(|_| ())
+    }
+
+    fn write_block(
+        &mut self,
+        port: PortType,
+        addr: u16,
+        values: &[u32],
+    ) -> Result<(), DebugProbeError> {
+        let mut result = Ok(());
+        for &value in values {
+            let write = self.write_register(port, addr, value);
+            if result.is_ok() {
+                result = write;
+            }
+        }
+
+        result
+    }
+
+    fn batch(&mut self, commands: &[BatchCommand]) -> Result<Vec<u32>, DebugProbeError> {
+        self.transactions
+            .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+
+        let mut values = vec![];
+        for command in commands {
+            match *command {
+                BatchCommand::Read(port, addr) => values.push(self.transfer(port, addr, None)?),
+                BatchCommand::Write(port, addr, value) => {
+                    self.transfer(port, addr, Some(value))?;
+                }
+            }
+        }
+
+        Ok(values)
+    }
+
+    fn into_probe(self: Box<Self>) -> Box<dyn DebugProbe> {
+        self
+    }
+}
+
+#[cfg(test)]
+impl<'a> AsRef<dyn DebugProbe + 'a> for FakeDap {
+    fn as_ref(&self) -> &(dyn DebugProbe + 'a) {
+        self
+    }
+}
+
+#[cfg(test)]
+impl<'a> AsMut<dyn DebugProbe + 'a> for FakeDap {
+    fn as_mut(&mut self) -> &mut (dyn DebugProbe + 'a) {
+        self
+    }
+}
+
 /// Low-Level Access to the JTAG protocol
 ///
 /// This trait should be implemented by all probes which offer low-level access to
@@ -736,8 +1433,178 @@ pub trait JTAGAccess: DebugProbe + AsRef<dyn DebugProbe> + AsMut<dyn DebugProbe>
     fn into_probe(self: Box<Self>) -> Box<dyn DebugProbe>;
 }
 
+/// The way a session connects to the target.
 #[derive(PartialEq, Debug, Copy, Clone)]
 pub enum AttachMethod {
+    /// Connect to the target as it is, without resetting it.
     Normal,
-    UnderReset,
+    /// Connect while the reset line of the target is asserted, and halt the core
+    /// at the reset vector when the reset line is released.
+    ///
+    /// This recovers chips whose firmware disables the debug port or enters a deep sleep
+    /// mode right after reset. It requires a probe which drives the reset line.
+    UnderReset {
+        /// How long the reset line is asserted before the probe connects to the target.
+        assert_time: Duration,
+        /// How long to wait after the reset line was released, before the core is accessed.
+        settle_time: Duration,
+    },
+    /// Connect to the target, then reset it through the debug port and halt the core
+    /// at the reset vector.
+    ///
+    /// This does not use the reset line, for probes which do not have one, or where it is not connected.
+    SoftwareOnly,
+    /// Wait until the debug port of the target can be powered up, then keep it powered in the
+    /// low power modes of the target and halt the core before it can go back to sleep.
+    ///
+    /// This connects to targets which spend most of their time in a deep sleep mode, in which
+    /// the debug port is powered down. The low power debug bits are taken from the
+    /// [LowPowerDebug](crate::config::LowPowerDebug) description of the target. A reset catch
+    /// is armed while the core is halted, so a wakeup from a mode which resets the chip, like
+    /// the STANDBY mode of a STM32, stops the core at the reset vector. Only ARM targets are supported.
+    OnWakeup {
+        /// How long to wait for the target to wake up.
+        timeout: Duration,
+        /// The time between two attempts to power up the debug port.
+        poll_interval: Duration,
+    },
+}
+
+impl AttachMethod {
+    /// The default time the reset line is asserted when connecting under reset.
+    pub const DEFAULT_ASSERT_TIME: Duration = Duration::from_millis(10);
+    /// The default time to wait after the reset line was released when connecting under reset.
+    pub const DEFAULT_SETTLE_TIME: Duration = Duration::from_millis(10);
+
+    /// The default time between two attempts to reach a sleeping target.
+    pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+    /// Connect under reset, with a timing which works for most chips.
+    pub fn under_reset() -> Self {
+        AttachMethod::UnderReset {
+            assert_time: Self::DEFAULT_ASSERT_TIME,
+            settle_time: Self::DEFAULT_SETTLE_TIME,
+        }
+    }
+
+    /// Connect as soon as the target wakes up, waiting at most for `timeout`.
+    pub fn on_wakeup(timeout: Duration) -> Self {
+        AttachMethod::OnWakeup {
+            timeout,
+            poll_interval: Self::DEFAULT_POLL_INTERVAL,
+        }
+    }
+}
+
+impl Default for AttachMethod {
+    fn default() -> Self {
+        AttachMethod::Normal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::factory::{factories, ScopedRegistration};
+    use super::{
+        AttachMethod, DebugProbe, DebugProbeError, DebugProbeInfo, DebugProbeSelector,
+        DebugProbeSelectorParseError, DebugProbeType, FakeProbe, Probe, ProbeCreationError,
+        ProbeFactory,
+    };
+    use crate::Error;
+
+    #[derive(Debug)]
+    struct FakeFactory;
+
+    impl ProbeFactory for FakeFactory {
+        fn list_probes(&self) -> Vec<DebugProbeInfo> {
+            vec![DebugProbeInfo::new(
+                "Fake probe",
+                0xfeed,
+                0xbeef,
+                Some("1".to_owned()),
+                DebugProbeType::Custom("fake".to_owned()),
+            )]
+        }
+
+        fn open(
+            &self,
+            selector: &DebugProbeSelector,
+        ) -> Result<Box<dyn DebugProbe>, DebugProbeError> {
+            if self.list_probes().iter().any(|info| selector.matches(info)) {
+                Ok(Box::new(FakeProbe))
+            } else {
+                Err(DebugProbeError::ProbeCouldNotBeCreated(
+                    ProbeCreationError::NotFound,
+                ))
+            }
+        }
+    }
+
+    #[test]
+    fn attaching_under_reset_requires_a_reset_line() {
+        let probe = Probe::from_specific_probe(Box::new(FakeProbe));
+
+        let error = probe
+            .attach_with_method("nrf51822", AttachMethod::under_reset())
+            .unwrap_err();
+
+        match error {
+            Error::Probe(DebugProbeError::ResetNotSupported(name)) => {
+                assert_eq!(name, "Mock probe for testing")
+            }
+            other => panic!("Unexpected error: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn invalid_port_paths_are_rejected() {
+        for path in &[
+            "usb:",
+            "usb:1",
+            "usb:1-",
+            "usb:a-1",
+            "usb:1-2.x",
+            "usb:1-2-3",
+        ] {
+            match path.parse::<DebugProbeSelector>() {
+                Err(DebugProbeSelectorParseError::Format) => (),
+                other => panic!("Unexpected result for {}: {:?}", path, other),
+            }
+        }
+    }
+
+    #[test]
+    fn selector_matches_port_path() {
+        let info = DebugProbeInfo::new("Probe", 0x1234, 0x5678, None, DebugProbeType::DAPLink)
+            .with_port_path(Some("1-3.2".to_owned()));
+
+        let mut selector: DebugProbeSelector = "1234:5678".parse().unwrap();
+        assert!(selector.matches(&info));
+
+        selector.port_path = Some("1-3.2".to_owned());
+        assert!(selector.matches(&info));
+
+        selector.port_path = Some("1-3.1".to_owned());
+        assert!(!selector.matches(&info));
+    }
+
+    #[test]
+    fn registered_factories_open_their_probes() {
+        let builtin = factories().len();
+        let registration = ScopedRegistration::new(FakeFactory);
+
+        let factories_with_fake = factories();
+        assert_eq!(factories_with_fake.len(), builtin + 1);
+        assert_eq!(
+            factories_with_fake[0].list_probes()[0].identifier,
+            "Fake probe"
+        );
+
+        let selector: DebugProbeSelector = "feed:beef:1".parse().unwrap();
+        let probe = Probe::open(selector).unwrap();
+        assert_eq!(probe.get_name(), "Mock probe for testing");
+
+        drop(registration);
+        assert_eq!(factories().len(), builtin);
+    }
 }
@@ -0,0 +1,689 @@
+//! Access to probes which are connected to another host.
+//!
+//! The host with the probe runs a [ProbeServer], which forwards the requests of a
+//! [RemoteProbe] to its local probe. DAP writes are queued and sent together with the
+//! next read, so the latency of the network only adds up once per read.
+
+mod protocol;
+mod server;
+
+pub use server::ProbeServer;
+
+use crate::{
+    architecture::{
+        arm::{
+            communication_interface::ArmProbeInterface, ArmCommunicationInterface, DAPAccess,
+            PortType,
+        },
+        riscv::communication_interface::RiscvCommunicationInterface,
+    },
+    probe::{
+        BatchCommand, DebugProbe, DebugProbeError, DebugProbeSelector, JTAGAccess,
+        PowerCapabilities, ProbeCreationError, WireProtocol,
+    },
+};
+use protocol::{
+    read_frame, write_frame, RemoteError, Request, Response, MAX_BLOCK_LENGTH, PROTOCOL_VERSION,
+};
+use std::fmt;
+use std::io;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+use thiserror::Error;
+
+/// The scheme of the addresses of remote probes.
+const SCHEME: &str = "tcp://";
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait for a response of the server. Some operations, like attaching, take a while.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Queued writes are sent when this many are waiting, even if no value is read.
+const MAX_BATCH_LENGTH: usize = 256;
+
+#[derive(Debug, Error)]
+pub enum RemoteProbeError {
+    #[error("'{0}' is not a remote probe address of the form tcp://token@host:port")]
+    InvalidAddress(String),
+    #[error("Unable to connect to the probe server")]
+    Connect(#[source] io::Error),
+    #[error("The probe server rejected the connection: {0}")]
+    Rejected(String),
+    #[error("The connection to the probe server was lost, the next access reconnects")]
+    ConnectionLost(#[source] io::Error),
+    #[error("The probe server sent an invalid message")]
+    InvalidMessage,
+    #[error("The probe on the server reported an error: {0}")]
+    Remote(String),
+}
+
+impl From<RemoteProbeError> for DebugProbeError {
+    fn from(error: RemoteProbeError) -> Self {
+        DebugProbeError::ProbeSpecific(Box::new(error))
+    }
+}
+
+/// The parts of an address of the form `tcp://token@host:port`.
+#[derive(Debug, PartialEq)]
+struct RemoteAddress {
+    token: String,
+    /// The host and the port.
+    host: String,
+}
+
+impl RemoteAddress {
+    fn parse(address: &str) -> Result<Self, RemoteProbeError> {
+        let invalid = || RemoteProbeError::InvalidAddress(address.to_owned());
+
+        let rest = address.strip_prefix(SCHEME).ok_or_else(invalid)?;
+        let (token, host) = match rest.rfind('@') {
+            Some(position) => (&rest[..position], &rest[position + 1..]),
+            None => return Err(invalid()),
+        };
+
+        let port = host.rsplit(':').next().ok_or_else(invalid)?;
+        if token.is_empty() || port.parse::<u16>().is_err() || port.len() == host.len() {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            token: token.to_owned(),
+            host: host.to_owned(),
+        })
+    }
+}
+
+/// A probe which is connected to another host, where a [ProbeServer] runs.
+///
+/// If the connection is lost, the access which was performed fails. The next access
+/// reconnects to the server, which kept the probe attached, so e.g. flashing can be resumed.
+pub(crate) struct RemoteProbe {
+    address: RemoteAddress,
+    connection: Option<TcpStream>,
+
+    name: String,
+    speed_khz: u32,
+    has_arm_interface: bool,
+    has_riscv_interface: bool,
+    power_capabilities: PowerCapabilities,
+
+    /// The DAP accesses which were not sent to the server yet.
+    batch: Vec<BatchCommand>,
+}
+
+impl fmt::Debug for RemoteProbe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The token is not printed.
+        f.debug_struct("RemoteProbe")
+            .field("host", &self.address.host)
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
+impl RemoteProbe {
+    /// Connects to the server with the address `address`, of the form `tcp://token@host:port`.
+    pub(crate) fn connect(address: &str) -> Result<Self, DebugProbeError> {
+        let mut probe = Self {
+            address: RemoteAddress::parse(address)?,
+            connection: None,
+            name: String::new(),
+            speed_khz: 0,
+            has_arm_interface: false,
+            has_riscv_interface: false,
+            power_capabilities: PowerCapabilities::default(),
+            batch: vec![],
+        };
+
+        probe.connection = Some(probe.open_connection(false)?);
+        probe.refresh_interfaces()?;
+
+        Ok(probe)
+    }
+
+    /// Opens a new connection to the server, `resume` continues to use the probe as it was left.
+    fn open_connection(&mut self, resume: bool) -> Result<TcpStream, RemoteProbeError> {
+        let addresses = self
+            .address
+            .host
+            .to_socket_addrs()
+            .map_err(RemoteProbeError::Connect)?;
+
+        let mut result = Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "The host name did not resolve to an address",
+        ));
+        for address in addresses {
+            result = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT);
+            if result.is_ok() {
+                break;
+            }
+        }
+
+        let mut stream = result.map_err(RemoteProbeError::Connect)?;
+        stream
+            .set_nodelay(true)
+            .and_then(|()| stream.set_read_timeout(Some(RESPONSE_TIMEOUT)))
+            .map_err(RemoteProbeError::Connect)?;
+
+        let hello = Request::Hello {
+            version: PROTOCOL_VERSION,
+            token: self.address.token.clone(),
+            resume,
+        };
+        write_frame(&mut stream, &hello.encode()).map_err(RemoteProbeError::Connect)?;
+        let response = read_frame(&mut stream).map_err(RemoteProbeError::Connect)?;
+
+        match Response::decode(&response) {
+            Ok(Response::Hello { name, speed_khz }) => {
+                self.name = name;
+                self.speed_khz = speed_khz;
+            }
+            Ok(Response::Error(RemoteError::Other(message))) => {
+                return Err(RemoteProbeError::Rejected(message))
+            }
+            _ => return Err(RemoteProbeError::InvalidMessage),
+        }
+
+        log::debug!("Connected to {} on {}", self.name, self.address.host);
+
+        Ok(stream)
+    }
+
+    /// Sends `request` and returns the response, reconnecting first if the connection was lost.
+    ///
+    /// After an error, the next response might still belong to this request,
+    /// so the connection is closed.
+    fn transfer(&mut self, request: &Request) -> Result<Response, RemoteProbeError> {
+        let mut connection = match self.connection.take() {
+            Some(connection) => connection,
+            None => {
+                log::info!("Reconnecting to the probe server on {}", self.address.host);
+                self.open_connection(true)?
+            }
+        };
+
+        let response = write_frame(&mut connection, &request.encode())
+            .and_then(|()| read_frame(&mut connection))
+            .map_err(RemoteProbeError::ConnectionLost)?;
+        let response = Response::decode(&response).map_err(|_| RemoteProbeError::InvalidMessage)?;
+
+        self.connection = Some(connection);
+
+        Ok(response)
+    }
+
+    /// Sends `request` after the queued DAP accesses, and returns the response.
+    fn call(&mut self, request: Request) -> Result<Response, DebugProbeError> {
+        self.process_batch()?;
+
+        match self.transfer(&request)? {
+            Response::Error(error) => Err(error.into()),
+            response => Ok(response),
+        }
+    }
+
+    /// Sends `request`, which is only answered with [Response::Ok].
+    fn call_ok(&mut self, request: Request) -> Result<(), DebugProbeError> {
+        match self.call(request)? {
+            Response::Ok => Ok(()),
+            _ => Err(RemoteProbeError::InvalidMessage.into()),
+        }
+    }
+
+    fn refresh_interfaces(&mut self) -> Result<(), DebugProbeError> {
+        match self.call(Request::Interfaces)? {
+            Response::Interfaces { arm, riscv, power } => {
+                self.has_arm_interface = arm;
+                self.has_riscv_interface = riscv;
+                self.power_capabilities = power;
+                Ok(())
+            }
+            _ => Err(RemoteProbeError::InvalidMessage.into()),
+        }
+    }
+
+    /// Sends the queued DAP accesses, and returns the values which were read.
+    fn process_batch(&mut self) -> Result<Vec<u32>, DebugProbeError> {
+        if self.batch.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let batch = std::mem::take(&mut self.batch);
+        log::trace!("{} items in batch", batch.len());
+
+        let request = Request::DapBatch(batch);
+        match self.transfer(&request)? {
+            Response::Batch {
+                values,
+                failure: None,
+            } => Ok(values),
+            Response::Batch {
+                failure: Some((index, error)),
+                ..
+            } => {
+                if let Request::DapBatch(batch) = &request {
+                    if let Some(command) = batch.get(index as usize) {
+                        log::debug!("Batched command {} failed", command);
+                    }
+                }
+                Err(error.into())
+            }
+            Response::Error(error) => Err(error.into()),
+            _ => Err(RemoteProbeError::InvalidMessage.into()),
+        }
+    }
+
+    /// Queues `command`. Reads are sent right away, together with the queued writes.
+    fn batch_add(&mut self, command: BatchCommand) -> Result<u32, DebugProbeError> {
+        self.batch.push(command);
+
+        match command {
+            BatchCommand::Read(_, _) => Ok(self.process_batch()?.last().copied().unwrap_or(0)),
+            _ if self.batch.len() >= MAX_BATCH_LENGTH => {
+                self.process_batch()?;
+                Ok(0)
+            }
+            _ => Ok(0),
+        }
+    }
+}
+
+impl DebugProbe for RemoteProbe {
+    /// Remote probes are not found by a selector, they are opened with [crate::Probe::open_remote].
+    fn new_from_selector(
+        _selector: impl Into<DebugProbeSelector>,
+    ) -> Result<Box<Self>, DebugProbeError>
+    where
+        Self: Sized,
+    {
+        Err(DebugProbeError::ProbeCouldNotBeCreated(
+            ProbeCreationError::NotFound,
+        ))
+    }
+
+    fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    fn speed(&self) -> u32 {
+        self.speed_khz
+    }
+
+    fn set_speed(&mut self, speed_khz: u32) -> Result<u32, DebugProbeError> {
+        match self.call(Request::SetSpeed(speed_khz))? {
+            Response::Speed(speed_khz) => {
+                self.speed_khz = speed_khz;
+                Ok(speed_khz)
+            }
+            _ => Err(RemoteProbeError::InvalidMessage.into()),
+        }
+    }
+
+    fn attach(&mut self) -> Result<(), DebugProbeError> {
+        self.call_ok(Request::Attach)?;
+        self.refresh_interfaces()
+    }
+
+    fn detach(&mut self) -> Result<(), DebugProbeError> {
+        self.call_ok(Request::Detach)
+    }
+
+    fn target_reset(&mut self) -> Result<(), DebugProbeError> {
+        self.call_ok(Request::TargetReset)
+    }
+
+    fn target_reset_assert(&mut self) -> Result<(), DebugProbeError> {
+        self.call_ok(Request::TargetResetAssert)
+    }
+
+    fn target_reset_deassert(&mut self) -> Result<(), DebugProbeError> {
+        self.call_ok(Request::TargetResetDeassert)
+    }
+
+    fn select_protocol(&mut self, protocol: WireProtocol) -> Result<(), DebugProbeError> {
+        self.call_ok(Request::SelectProtocol(protocol))?;
+        self.refresh_interfaces()
+    }
+
+    fn power_capabilities(&self) -> PowerCapabilities {
+        self.power_capabilities
+    }
+
+    fn target_voltage(&mut self) -> Result<f32, DebugProbeError> {
+        match self.call(Request::TargetVoltage)? {
+            Response::Measurement(voltage) => Ok(voltage),
+            _ => Err(RemoteProbeError::InvalidMessage.into()),
+        }
+    }
+
+    fn target_current(&mut self) -> Result<f32, DebugProbeError> {
+        match self.call(Request::TargetCurrent)? {
+            Response::Measurement(current) => Ok(current),
+            _ => Err(RemoteProbeError::InvalidMessage.into()),
+        }
+    }
+
+    fn set_target_power(&mut self, enabled: bool) -> Result<(), DebugProbeError> {
+        self.call_ok(Request::SetTargetPower(enabled))
+    }
+
+    fn has_arm_interface(&self) -> bool {
+        self.has_arm_interface
+    }
+
+    fn get_arm_interface<'probe>(
+        self: Box<Self>,
+    ) -> Result<Option<Box<dyn ArmProbeInterface + 'probe>>, DebugProbeError> {
+        if self.has_arm_interface {
+            let interface = ArmCommunicationInterface::new(self, false)?;
+
+            Ok(Some(Box::new(interface)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn has_riscv_interface(&self) -> bool {
+        self.has_riscv_interface
+    }
+
+    fn get_riscv_interface(
+        self: Box<Self>,
+    ) -> Result<Option<RiscvCommunicationInterface>, DebugProbeError> {
+        if self.has_riscv_interface {
+            Ok(Some(RiscvCommunicationInterface::new(self)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn get_dap_interface_mut(&mut self) -> Option<&mut dyn DAPAccess> {
+        Some(self as _)
+    }
+
+    fn get_jtag_interface_mut(&mut self) -> Option<&mut dyn JTAGAccess> {
+        Some(self as _)
+    }
+}
+
+impl DAPAccess for RemoteProbe {
+    fn read_register(&mut self, port: PortType, address: u16) -> Result<u32, DebugProbeError> {
+        self.batch_add(BatchCommand::Read(port, address))
+    }
+
+    fn write_register(
+        &mut self,
+        port: PortType,
+        address: u16,
+        value: u32,
+    ) -> Result<(), DebugProbeError> {
+        self.batch_add(BatchCommand::Write(port, address, value))
+            .map(|_| ())
+    }
+
+    fn read_block(
+        &mut self,
+        port: PortType,
+        register_address: u16,
+        values: &mut [u32],
+    ) -> Result<(), DebugProbeError> {
+        for values in values.chunks_mut(MAX_BLOCK_LENGTH) {
+            let request = Request::DapReadBlock {
+                port,
+                address: register_address,
+                len: values.len() as u32,
+            };
+
+            match self.call(request)? {
+                Response::Values(read) if read.len() == values.len() => {
+                    values.copy_from_slice(&read);
+                }
+                _ => return Err(RemoteProbeError::InvalidMessage.into()),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_block(
+        &mut self,
+        port: PortType,
+        register_address: u16,
+        values: &[u32],
+    ) -> Result<(), DebugProbeError> {
+        self.call_ok(Request::DapWriteBlock {
+            port,
+            address: register_address,
+            values: values.to_vec(),
+        })
+    }
+
+    fn batch(&mut self, commands: &[BatchCommand]) -> Result<Vec<u32>, DebugProbeError> {
+        self.process_batch()?;
+
+        let mut values = Vec::new();
+        for chunk in commands.chunks(MAX_BATCH_LENGTH) {
+            self.batch.extend_from_slice(chunk);
+            values.extend(self.process_batch()?);
+        }
+
+        Ok(values)
+    }
+
+    fn flush(&mut self) -> Result<(), DebugProbeError> {
+        self.process_batch()?;
+        Ok(())
+    }
+
+    fn into_probe(self: Box<Self>) -> Box<dyn DebugProbe> {
+        self
+    }
+}
+
+impl JTAGAccess for RemoteProbe {
+    fn read_register(&mut self, address: u32, len: u32) -> Result<Vec<u8>, DebugProbeError> {
+        match self.call(Request::JtagRead { address, len })? {
+            Response::Bytes(data) => Ok(data),
+            _ => Err(RemoteProbeError::InvalidMessage.into()),
+        }
+    }
+
+    fn write_register(
+        &mut self,
+        address: u32,
+        data: &[u8],
+        len: u32,
+    ) -> Result<Vec<u8>, DebugProbeError> {
+        let request = Request::JtagWrite {
+            address,
+            data: data.to_vec(),
+            len,
+        };
+
+        match self.call(request)? {
+            Response::Bytes(data) => Ok(data),
+            _ => Err(RemoteProbeError::InvalidMessage.into()),
+        }
+    }
+
+    fn set_idle_cycles(&mut self, idle_cycles: u8) {
+        if let Err(e) = self.call_ok(Request::JtagSetIdleCycles(idle_cycles)) {
+            log::warn!("Failed to set the JTAG idle cycles: {}", e);
+        }
+    }
+
+    fn into_probe(self: Box<Self>) -> Box<dyn DebugProbe> {
+        self
+    }
+}
+
+impl<'a> AsRef<dyn DebugProbe + 'a> for RemoteProbe {
+    fn as_ref(&self) -> &(dyn DebugProbe + 'a) {
+        self
+    }
+}
+
+impl<'a> AsMut<dyn DebugProbe + 'a> for RemoteProbe {
+    fn as_mut(&mut self) -> &mut (dyn DebugProbe + 'a) {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ProbeServer, RemoteAddress, RemoteProbe, RemoteProbeError, Request};
+    use crate::architecture::arm::{DAPAccess, DapError, PortType};
+    use crate::probe::{DebugProbe, DebugProbeError, FakeAck, FakeDap, Probe};
+    use std::net::{Shutdown, TcpListener};
+
+    /// Starts a server for a [FakeDap] and returns its address.
+    ///
+    /// The first transfer of CTRL/STAT is answered with FAULT. Detaching resets the registers,
+    /// which shows whether the server detached the probe.
+    fn serve() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        std::thread::spawn(move || {
+            let fake =
+                FakeDap::new("Fake DAP").with_acks(PortType::DebugPort, 0x4, &[FakeAck::Fault]);
+            let probe = Probe::new(fake);
+            ProbeServer::new(probe, "secret").run(listener)
+        });
+
+        format!("tcp://secret@127.0.0.1:{}", port)
+    }
+
+    fn remote_error(error: DebugProbeError) -> RemoteProbeError {
+        match error {
+            DebugProbeError::ProbeSpecific(source) => *source.downcast().unwrap(),
+            error => panic!("Unexpected error {:?}", error),
+        }
+    }
+
+    #[test]
+    fn addresses_are_parsed() {
+        assert_eq!(
+            RemoteAddress::parse("tcp://secret@lab-pi:1338").unwrap(),
+            RemoteAddress {
+                token: "secret".into(),
+                host: "lab-pi:1338".into(),
+            }
+        );
+
+        for invalid in &[
+            "lab-pi:1338",
+            "tcp://lab-pi:1338",
+            "tcp://@lab-pi:1338",
+            "tcp://secret@lab-pi",
+            "tcp://secret@lab-pi:port",
+        ] {
+            assert!(RemoteAddress::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn dap_accesses_are_forwarded() {
+        let mut probe = RemoteProbe::connect(&serve()).unwrap();
+        assert_eq!(probe.get_name(), "Fake DAP");
+        assert!(probe.has_arm_interface());
+
+        probe.attach().unwrap();
+
+        // Writes are only sent with the next read.
+        DAPAccess::write_register(&mut probe, PortType::DebugPort, 0x8, 0x1234).unwrap();
+        DAPAccess::write_register(&mut probe, PortType::AccessPort(0), 0x4, 0x5678).unwrap();
+        assert_eq!(probe.batch.len(), 2);
+
+        let value = DAPAccess::read_register(&mut probe, PortType::AccessPort(0), 0x4).unwrap();
+        assert_eq!(value, 0x5678);
+        assert!(probe.batch.is_empty());
+
+        let mut values = [0; 2];
+        probe
+            .write_block(PortType::AccessPort(0), 0xc, &[1, 2])
+            .unwrap();
+        probe
+            .read_block(PortType::AccessPort(0), 0xc, &mut values)
+            .unwrap();
+        assert_eq!(values, [2, 2]);
+    }
+
+    #[test]
+    fn errors_keep_their_kind() {
+        let mut probe = RemoteProbe::connect(&serve()).unwrap();
+
+        match DAPAccess::read_register(&mut probe, PortType::DebugPort, 0x4) {
+            Err(DebugProbeError::ArchitectureSpecific(source)) => {
+                assert!(matches!(
+                    source.downcast_ref::<DapError>(),
+                    Some(DapError::FaultResponse)
+                ));
+            }
+            result => panic!("Unexpected result {:?}", result),
+        }
+
+        assert!(matches!(
+            probe.target_reset_assert(),
+            Err(DebugProbeError::CommandNotSupportedByProbe)
+        ));
+    }
+
+    #[test]
+    fn servers_only_listen_on_loopback() {
+        let listener = TcpListener::bind("0.0.0.0:0").unwrap();
+        let mut server = ProbeServer::new(Probe::new(FakeDap::new("Fake DAP")), "secret");
+
+        let error = server.run(listener).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn wrong_tokens_are_rejected() {
+        let address = serve().replace("secret", "guess");
+
+        let error = RemoteProbe::connect(&address).unwrap_err();
+        assert!(matches!(remote_error(error), RemoteProbeError::Rejected(_)));
+    }
+
+    #[test]
+    fn oversized_blocks_are_rejected() {
+        let mut probe = RemoteProbe::connect(&serve()).unwrap();
+
+        let error = probe
+            .call(Request::DapReadBlock {
+                port: PortType::AccessPort(0),
+                address: 0xc,
+                len: u32::MAX,
+            })
+            .unwrap_err();
+        assert!(matches!(remote_error(error), RemoteProbeError::Remote(_)));
+    }
+
+    #[test]
+    fn lost_connections_are_resumed() {
+        let mut probe = RemoteProbe::connect(&serve()).unwrap();
+        probe.attach().unwrap();
+        DAPAccess::write_register(&mut probe, PortType::DebugPort, 0x8, 0x1234).unwrap();
+        probe.flush().unwrap();
+
+        probe
+            .connection
+            .as_ref()
+            .unwrap()
+            .shutdown(Shutdown::Both)
+            .unwrap();
+
+        let error = DAPAccess::read_register(&mut probe, PortType::DebugPort, 0x8).unwrap_err();
+        assert!(matches!(
+            remote_error(error),
+            RemoteProbeError::ConnectionLost(_)
+        ));
+
+        // The probe was not detached, so the register still has its value.
+        let value = DAPAccess::read_register(&mut probe, PortType::DebugPort, 0x8).unwrap();
+        assert_eq!(value, 0x1234);
+    }
+}
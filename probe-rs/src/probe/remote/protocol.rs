@@ -0,0 +1,700 @@
+//! The messages exchanged between the probe server and its clients.
+//!
+//! Each message is sent as a frame, which starts with the length of the message
+//! as `u32` in little endian. A message starts with a tag byte, followed by its fields.
+//! Integers are little endian, strings and lists are prefixed with their length as `u32`.
+
+use crate::architecture::arm::{DapError, PortType};
+use crate::probe::{BatchCommand, DebugProbeError, PowerCapabilities, WireProtocol};
+use std::io::{self, Read, Write};
+
+/// The version of the protocol, which has to match between the server and the client.
+pub(crate) const PROTOCOL_VERSION: u16 = 2;
+
+/// Frames are never larger than this, longer ones are treated as invalid.
+const MAX_FRAME_LENGTH: usize = 16 * 1024 * 1024;
+
+/// The most values which are transferred in one block, so that a block fits into a frame
+/// together with the header of its message.
+pub(crate) const MAX_BLOCK_LENGTH: usize = MAX_FRAME_LENGTH / 4 - 2;
+
+/// The longest frame which is accepted for a [Request::Hello], which is read before
+/// the client is known to have the token.
+pub(crate) const MAX_HELLO_LENGTH: usize = 1024;
+
+/// A request of the client, which is answered with exactly one [Response].
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Request {
+    /// The first request on each connection.
+    ///
+    /// If `resume` is set, the client reconnects after the connection was lost and
+    /// continues to use the probe in the state it was left in.
+    Hello {
+        version: u16,
+        token: String,
+        resume: bool,
+    },
+    Interfaces,
+    SelectProtocol(WireProtocol),
+    SetSpeed(u32),
+    Attach,
+    Detach,
+    TargetReset,
+    TargetResetAssert,
+    TargetResetDeassert,
+    SetTargetPower(bool),
+    TargetVoltage,
+    TargetCurrent,
+    /// Performs the commands in order and flushes the DAP accesses afterwards.
+    DapBatch(Vec<BatchCommand>),
+    DapReadBlock {
+        port: PortType,
+        address: u16,
+        len: u32,
+    },
+    DapWriteBlock {
+        port: PortType,
+        address: u16,
+        values: Vec<u32>,
+    },
+    JtagRead {
+        address: u32,
+        len: u32,
+    },
+    JtagWrite {
+        address: u32,
+        data: Vec<u8>,
+        len: u32,
+    },
+    JtagSetIdleCycles(u8),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Response {
+    Ok,
+    Hello {
+        name: String,
+        speed_khz: u32,
+    },
+    Interfaces {
+        arm: bool,
+        riscv: bool,
+        power: PowerCapabilities,
+    },
+    Speed(u32),
+    /// A measured voltage or current.
+    Measurement(f32),
+    Values(Vec<u32>),
+    Bytes(Vec<u8>),
+    /// The values read by a [Request::DapBatch], up to the failed command if there is one.
+    Batch {
+        values: Vec<u32>,
+        failure: Option<(u32, RemoteError)>,
+    },
+    Error(RemoteError),
+}
+
+/// An error of the probe on the server, as it is sent to the client.
+///
+/// The errors which are handled by the callers of a probe keep their kind,
+/// all others are only forwarded as message.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum RemoteError {
+    CommandNotSupportedByProbe,
+    UnsupportedSpeed(u32),
+    UnsupportedProtocol(WireProtocol),
+    InterfaceNotAvailable,
+    Timeout,
+    NotAttached,
+    Attached,
+    Dap(u8),
+    Other(String),
+    ProbeReconnected,
+}
+
+impl From<&DebugProbeError> for RemoteError {
+    fn from(error: &DebugProbeError) -> Self {
+        match error {
+            DebugProbeError::CommandNotSupportedByProbe => RemoteError::CommandNotSupportedByProbe,
+            DebugProbeError::UnsupportedSpeed(speed) => RemoteError::UnsupportedSpeed(*speed),
+            DebugProbeError::UnsupportedProtocol(protocol) => {
+                RemoteError::UnsupportedProtocol(*protocol)
+            }
+            DebugProbeError::InterfaceNotAvailable(_) => RemoteError::InterfaceNotAvailable,
+            DebugProbeError::Timeout => RemoteError::Timeout,
+            DebugProbeError::NotAttached => RemoteError::NotAttached,
+            DebugProbeError::Attached => RemoteError::Attached,
+            DebugProbeError::ProbeReconnected => RemoteError::ProbeReconnected,
+            DebugProbeError::ArchitectureSpecific(source) => {
+                match source.downcast_ref::<DapError>() {
+                    Some(error) => RemoteError::Dap(dap_error_code(error)),
+                    None => RemoteError::Other(format_error(error)),
+                }
+            }
+            error => RemoteError::Other(format_error(error)),
+        }
+    }
+}
+
+impl From<RemoteError> for DebugProbeError {
+    fn from(error: RemoteError) -> Self {
+        match error {
+            RemoteError::CommandNotSupportedByProbe => DebugProbeError::CommandNotSupportedByProbe,
+            RemoteError::UnsupportedSpeed(speed) => DebugProbeError::UnsupportedSpeed(speed),
+            RemoteError::UnsupportedProtocol(protocol) => {
+                DebugProbeError::UnsupportedProtocol(protocol)
+            }
+            RemoteError::InterfaceNotAvailable => {
+                DebugProbeError::InterfaceNotAvailable("of the remote probe")
+            }
+            RemoteError::Timeout => DebugProbeError::Timeout,
+            RemoteError::NotAttached => DebugProbeError::NotAttached,
+            RemoteError::Attached => DebugProbeError::Attached,
+            RemoteError::ProbeReconnected => DebugProbeError::ProbeReconnected,
+            RemoteError::Dap(code) => match dap_error(code) {
+                Some(error) => error.into(),
+                None => super::RemoteProbeError::InvalidMessage.into(),
+            },
+            RemoteError::Other(message) => super::RemoteProbeError::Remote(message).into(),
+        }
+    }
+}
+
+/// Formats `error` with its sources, which would be lost on the client otherwise.
+fn format_error(error: &dyn std::error::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+
+    while let Some(error) = source {
+        message.push_str(": ");
+        message.push_str(&error.to_string());
+        source = error.source();
+    }
+
+    message
+}
+
+fn dap_error_code(error: &DapError) -> u8 {
+    match error {
+        DapError::SwdProtocol => 0,
+        DapError::NoAcknowledge => 1,
+        DapError::FaultResponse => 2,
+        DapError::WaitResponse => 3,
+        DapError::TargetPowerUpFailed => 4,
+        DapError::IncorrectParity => 5,
+    }
+}
+
+fn dap_error(code: u8) -> Option<DapError> {
+    match code {
+        0 => Some(DapError::SwdProtocol),
+        1 => Some(DapError::NoAcknowledge),
+        2 => Some(DapError::FaultResponse),
+        3 => Some(DapError::WaitResponse),
+        4 => Some(DapError::TargetPowerUpFailed),
+        5 => Some(DapError::IncorrectParity),
+        _ => None,
+    }
+}
+
+/// Writes `message` as one frame.
+pub(crate) fn write_frame(mut writer: impl Write, message: &[u8]) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(message.len() + 4);
+    frame.extend_from_slice(&(message.len() as u32).to_le_bytes());
+    frame.extend_from_slice(message);
+
+    writer.write_all(&frame)?;
+    writer.flush()
+}
+
+/// Reads one frame and returns the message in it.
+pub(crate) fn read_frame(reader: impl Read) -> io::Result<Vec<u8>> {
+    read_frame_with_limit(reader, MAX_FRAME_LENGTH)
+}
+
+/// Reads one frame which is at most `max_length` bytes long and returns the message in it.
+pub(crate) fn read_frame_with_limit(
+    mut reader: impl Read,
+    max_length: usize,
+) -> io::Result<Vec<u8>> {
+    let mut length = [0; 4];
+    reader.read_exact(&mut length)?;
+
+    let length = u32::from_le_bytes(length) as usize;
+    if length > max_length {
+        return Err(invalid_data());
+    }
+
+    let mut message = vec![0; length];
+    reader.read_exact(&mut message)?;
+    Ok(message)
+}
+
+fn invalid_data() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "invalid message")
+}
+
+#[derive(Default)]
+struct Encoder {
+    buffer: Vec<u8>,
+}
+
+impl Encoder {
+    fn u8(&mut self, value: u8) -> &mut Self {
+        self.buffer.push(value);
+        self
+    }
+
+    fn bool(&mut self, value: bool) -> &mut Self {
+        self.u8(value as u8)
+    }
+
+    fn u16(&mut self, value: u16) -> &mut Self {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    fn u32(&mut self, value: u32) -> &mut Self {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    fn bytes(&mut self, value: &[u8]) -> &mut Self {
+        self.u32(value.len() as u32);
+        self.buffer.extend_from_slice(value);
+        self
+    }
+
+    fn string(&mut self, value: &str) -> &mut Self {
+        self.bytes(value.as_bytes())
+    }
+
+    fn values(&mut self, values: &[u32]) -> &mut Self {
+        self.u32(values.len() as u32);
+        for value in values {
+            self.u32(*value);
+        }
+        self
+    }
+
+    fn port(&mut self, port: PortType) -> &mut Self {
+        self.u16(port.into())
+    }
+
+    fn protocol(&mut self, protocol: WireProtocol) -> &mut Self {
+        self.u8(match protocol {
+            WireProtocol::Swd => 0,
+            WireProtocol::Jtag => 1,
+        })
+    }
+
+    fn error(&mut self, error: &RemoteError) -> &mut Self {
+        match error {
+            RemoteError::CommandNotSupportedByProbe => self.u8(0),
+            RemoteError::UnsupportedSpeed(speed) => self.u8(1).u32(*speed),
+            RemoteError::UnsupportedProtocol(protocol) => self.u8(2).protocol(*protocol),
+            RemoteError::InterfaceNotAvailable => self.u8(3),
+            RemoteError::Timeout => self.u8(4),
+            RemoteError::NotAttached => self.u8(5),
+            RemoteError::Attached => self.u8(6),
+            RemoteError::Dap(code) => self.u8(7).u8(*code),
+            RemoteError::Other(message) => self.u8(8).string(message),
+            RemoteError::ProbeReconnected => self.u8(9),
+        }
+    }
+}
+
+struct Decoder<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn take(&mut self, count: usize) -> io::Result<&'a [u8]> {
+        if self.data.len() < count {
+            return Err(invalid_data());
+        }
+
+        let (taken, rest) = self.data.split_at(count);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    /// Ensures that the complete message was decoded.
+    fn finish<T>(self, value: T) -> io::Result<T> {
+        if self.data.is_empty() {
+            Ok(value)
+        } else {
+            Err(invalid_data())
+        }
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn bool(&mut self) -> io::Result<bool> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(invalid_data()),
+        }
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        let mut bytes = [0; 2];
+        bytes.copy_from_slice(self.take(2)?);
+        Ok(u16::from_le_bytes(bytes))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn bytes(&mut self) -> io::Result<Vec<u8>> {
+        let length = self.u32()? as usize;
+        Ok(self.take(length)?.to_vec())
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        String::from_utf8(self.bytes()?).map_err(|_| invalid_data())
+    }
+
+    fn values(&mut self) -> io::Result<Vec<u32>> {
+        let length = self.u32()? as usize;
+        if length > self.data.len() / 4 {
+            return Err(invalid_data());
+        }
+
+        (0..length).map(|_| self.u32()).collect()
+    }
+
+    fn port(&mut self) -> io::Result<PortType> {
+        self.u16().map(PortType::from)
+    }
+
+    fn protocol(&mut self) -> io::Result<WireProtocol> {
+        match self.u8()? {
+            0 => Ok(WireProtocol::Swd),
+            1 => Ok(WireProtocol::Jtag),
+            _ => Err(invalid_data()),
+        }
+    }
+
+    fn error(&mut self) -> io::Result<RemoteError> {
+        Ok(match self.u8()? {
+            0 => RemoteError::CommandNotSupportedByProbe,
+            1 => RemoteError::UnsupportedSpeed(self.u32()?),
+            2 => RemoteError::UnsupportedProtocol(self.protocol()?),
+            3 => RemoteError::InterfaceNotAvailable,
+            4 => RemoteError::Timeout,
+            5 => RemoteError::NotAttached,
+            6 => RemoteError::Attached,
+            7 => RemoteError::Dap(self.u8()?),
+            8 => RemoteError::Other(self.string()?),
+            9 => RemoteError::ProbeReconnected,
+            _ => return Err(invalid_data()),
+        })
+    }
+}
+
+impl Request {
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut encoder = Encoder::default();
+
+        match self {
+            Request::Hello {
+                version,
+                token,
+                resume,
+            } => encoder.u8(0).u16(*version).string(token).bool(*resume),
+            Request::Interfaces => encoder.u8(1),
+            Request::SelectProtocol(protocol) => encoder.u8(2).protocol(*protocol),
+            Request::SetSpeed(speed) => encoder.u8(3).u32(*speed),
+            Request::Attach => encoder.u8(4),
+            Request::Detach => encoder.u8(5),
+            Request::TargetReset => encoder.u8(6),
+            Request::TargetResetAssert => encoder.u8(7),
+            Request::TargetResetDeassert => encoder.u8(8),
+            Request::SetTargetPower(enabled) => encoder.u8(9).bool(*enabled),
+            Request::DapBatch(commands) => {
+                encoder.u8(10).u32(commands.len() as u32);
+                for command in commands {
+                    match *command {
+                        BatchCommand::Read(port, address) => encoder.u8(0).port(port).u16(address),
+                        BatchCommand::Write(port, address, value) => {
+                            encoder.u8(1).port(port).u16(address).u32(value)
+                        }
+                    };
+                }
+                &mut encoder
+            }
+            Request::DapReadBlock { port, address, len } => {
+                encoder.u8(11).port(*port).u16(*address).u32(*len)
+            }
+            Request::DapWriteBlock {
+                port,
+                address,
+                values,
+            } => encoder.u8(12).port(*port).u16(*address).values(values),
+            Request::JtagRead { address, len } => encoder.u8(13).u32(*address).u32(*len),
+            Request::JtagWrite { address, data, len } => {
+                encoder.u8(14).u32(*address).bytes(data).u32(*len)
+            }
+            Request::JtagSetIdleCycles(cycles) => encoder.u8(15).u8(*cycles),
+            Request::TargetVoltage => encoder.u8(16),
+            Request::TargetCurrent => encoder.u8(17),
+        };
+
+        encoder.buffer
+    }
+
+    pub(crate) fn decode(data: &[u8]) -> io::Result<Self> {
+        let mut decoder = Decoder::new(data);
+
+        let request = match decoder.u8()? {
+            0 => Request::Hello {
+                version: decoder.u16()?,
+                token: decoder.string()?,
+                resume: decoder.bool()?,
+            },
+            1 => Request::Interfaces,
+            2 => Request::SelectProtocol(decoder.protocol()?),
+            3 => Request::SetSpeed(decoder.u32()?),
+            4 => Request::Attach,
+            5 => Request::Detach,
+            6 => Request::TargetReset,
+            7 => Request::TargetResetAssert,
+            8 => Request::TargetResetDeassert,
+            9 => Request::SetTargetPower(decoder.bool()?),
+            10 => {
+                let count = decoder.u32()? as usize;
+                let mut commands = Vec::with_capacity(count.min(data.len()));
+                for _ in 0..count {
+                    commands.push(match decoder.u8()? {
+                        0 => BatchCommand::Read(decoder.port()?, decoder.u16()?),
+                        1 => BatchCommand::Write(decoder.port()?, decoder.u16()?, decoder.u32()?),
+                        _ => return Err(invalid_data()),
+                    });
+                }
+                Request::DapBatch(commands)
+            }
+            11 => Request::DapReadBlock {
+                port: decoder.port()?,
+                address: decoder.u16()?,
+                len: decoder.u32()?,
+            },
+            12 => Request::DapWriteBlock {
+                port: decoder.port()?,
+                address: decoder.u16()?,
+                values: decoder.values()?,
+            },
+            13 => Request::JtagRead {
+                address: decoder.u32()?,
+                len: decoder.u32()?,
+            },
+            14 => Request::JtagWrite {
+                address: decoder.u32()?,
+                data: decoder.bytes()?,
+                len: decoder.u32()?,
+            },
+            15 => Request::JtagSetIdleCycles(decoder.u8()?),
+            16 => Request::TargetVoltage,
+            17 => Request::TargetCurrent,
+            _ => return Err(invalid_data()),
+        };
+
+        decoder.finish(request)
+    }
+}
+
+impl Response {
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut encoder = Encoder::default();
+
+        match self {
+            Response::Ok => encoder.u8(0),
+            Response::Hello { name, speed_khz } => encoder.u8(1).string(name).u32(*speed_khz),
+            Response::Interfaces { arm, riscv, power } => encoder
+                .u8(2)
+                .bool(*arm)
+                .bool(*riscv)
+                .bool(power.target_voltage)
+                .bool(power.target_current)
+                .bool(power.target_power),
+            Response::Speed(speed) => encoder.u8(3).u32(*speed),
+            Response::Values(values) => encoder.u8(4).values(values),
+            Response::Bytes(bytes) => encoder.u8(5).bytes(bytes),
+            Response::Batch { values, failure } => {
+                encoder.u8(6).values(values);
+                match failure {
+                    Some((index, error)) => encoder.bool(true).u32(*index).error(error),
+                    None => encoder.bool(false),
+                }
+            }
+            Response::Error(error) => encoder.u8(7).error(error),
+            Response::Measurement(value) => encoder.u8(8).u32(value.to_bits()),
+        };
+
+        encoder.buffer
+    }
+
+    pub(crate) fn decode(data: &[u8]) -> io::Result<Self> {
+        let mut decoder = Decoder::new(data);
+
+        let response = match decoder.u8()? {
+            0 => Response::Ok,
+            1 => Response::Hello {
+                name: decoder.string()?,
+                speed_khz: decoder.u32()?,
+            },
+            2 => Response::Interfaces {
+                arm: decoder.bool()?,
+                riscv: decoder.bool()?,
+                power: PowerCapabilities {
+                    target_voltage: decoder.bool()?,
+                    target_current: decoder.bool()?,
+                    target_power: decoder.bool()?,
+                },
+            },
+            3 => Response::Speed(decoder.u32()?),
+            4 => Response::Values(decoder.values()?),
+            5 => Response::Bytes(decoder.bytes()?),
+            6 => {
+                let values = decoder.values()?;
+                let failure = if decoder.bool()? {
+                    Some((decoder.u32()?, decoder.error()?))
+                } else {
+                    None
+                };
+                Response::Batch { values, failure }
+            }
+            7 => Response::Error(decoder.error()?),
+            8 => Response::Measurement(f32::from_bits(decoder.u32()?)),
+            _ => return Err(invalid_data()),
+        };
+
+        decoder.finish(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        read_frame, read_frame_with_limit, write_frame, RemoteError, Request, Response,
+        MAX_HELLO_LENGTH,
+    };
+    use crate::architecture::arm::{DapError, PortType};
+    use crate::probe::{BatchCommand, DebugProbeError, PowerCapabilities, WireProtocol};
+
+    #[test]
+    fn requests_are_decoded_again() {
+        let requests = vec![
+            Request::Hello {
+                version: 1,
+                token: "secret".into(),
+                resume: true,
+            },
+            Request::SelectProtocol(WireProtocol::Jtag),
+            Request::DapBatch(vec![
+                BatchCommand::Write(PortType::DebugPort, 0x8, 0x0100_00f0),
+                BatchCommand::Read(PortType::AccessPort(1), 0xc),
+            ]),
+            Request::DapWriteBlock {
+                port: PortType::AccessPort(0),
+                address: 0xc,
+                values: vec![1, 2, 3],
+            },
+            Request::JtagWrite {
+                address: 0x11,
+                data: vec![0x12, 0x34],
+                len: 12,
+            },
+        ];
+
+        for request in requests {
+            assert_eq!(Request::decode(&request.encode()).unwrap(), request);
+        }
+    }
+
+    #[test]
+    fn responses_are_decoded_again() {
+        let responses = vec![
+            Response::Hello {
+                name: "J-Link".into(),
+                speed_khz: 4000,
+            },
+            Response::Batch {
+                values: vec![0x2ba0_1477],
+                failure: Some((2, RemoteError::Dap(3))),
+            },
+            Response::Interfaces {
+                arm: true,
+                riscv: false,
+                power: PowerCapabilities {
+                    target_voltage: true,
+                    target_current: false,
+                    target_power: true,
+                },
+            },
+            Response::Measurement(3.3),
+            Response::Error(RemoteError::Other("USB error".into())),
+            Response::Error(RemoteError::ProbeReconnected),
+        ];
+
+        for response in responses {
+            assert_eq!(Response::decode(&response.encode()).unwrap(), response);
+        }
+    }
+
+    #[test]
+    fn invalid_messages_are_rejected() {
+        // Unknown tag
+        assert!(Request::decode(&[0xff]).is_err());
+        // Truncated
+        assert!(Request::decode(&Request::SetSpeed(100).encode()[..3]).is_err());
+        // Trailing data
+        assert!(Response::decode(&[0, 0]).is_err());
+        // A list which is longer than the message
+        assert!(Response::decode(&[4, 0xff, 0xff, 0xff, 0xff]).is_err());
+    }
+
+    #[test]
+    fn frames_are_length_prefixed() {
+        let mut buffer = vec![];
+        write_frame(&mut buffer, b"abc").unwrap();
+
+        assert_eq!(buffer, b"\x03\x00\x00\x00abc");
+        assert_eq!(read_frame(&buffer[..]).unwrap(), b"abc");
+
+        let too_long = [0xff, 0xff, 0xff, 0xff];
+        assert!(read_frame(&too_long[..]).is_err());
+
+        let mut long_hello = vec![];
+        write_frame(&mut long_hello, &[0; MAX_HELLO_LENGTH + 1]).unwrap();
+        assert!(read_frame(&long_hello[..]).is_ok());
+        assert!(read_frame_with_limit(&long_hello[..], MAX_HELLO_LENGTH).is_err());
+    }
+
+    #[test]
+    fn dap_errors_keep_their_kind() {
+        let error = DebugProbeError::from(DapError::WaitResponse);
+        let remote = RemoteError::from(&error);
+        assert_eq!(remote, RemoteError::Dap(3));
+
+        match DebugProbeError::from(remote) {
+            DebugProbeError::ArchitectureSpecific(source) => {
+                assert!(matches!(
+                    source.downcast_ref::<DapError>(),
+                    Some(DapError::WaitResponse)
+                ));
+            }
+            error => panic!("Unexpected error {:?}", error),
+        }
+    }
+}
@@ -0,0 +1,279 @@
+use super::protocol::{
+    read_frame, read_frame_with_limit, write_frame, RemoteError, Request, Response,
+    MAX_BLOCK_LENGTH, MAX_HELLO_LENGTH, PROTOCOL_VERSION,
+};
+use crate::architecture::arm::DAPAccess;
+use crate::probe::{BatchCommand, DebugProbeError, JTAGAccess, Probe};
+use std::io;
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+/// How long a client may take to send its [Request::Hello], so that a connection
+/// which never sends anything does not block the clients after it.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Makes a local [Probe] available to clients on other hosts.
+///
+/// Clients open the probe with [Probe::open_remote] and an address of the form
+/// `tcp://token@host:port`. Only one client is served at a time, further clients
+/// wait until the previous one disconnected.
+///
+/// The connection is neither encrypted nor integrity protected, only the token is checked.
+/// Therefore the server only listens on the loopback interface, and clients on other hosts
+/// connect through a tunnel, e.g. SSH port forwarding.
+pub struct ProbeServer {
+    probe: Probe,
+    token: String,
+}
+
+impl ProbeServer {
+    /// Creates a server for `probe`, which only accepts clients which send `token`.
+    pub fn new(probe: Probe, token: impl Into<String>) -> Self {
+        Self {
+            probe,
+            token: token.into(),
+        }
+    }
+
+    /// Serves the clients which connect to `listener`, one after the other.
+    ///
+    /// Returns an error of kind [io::ErrorKind::InvalidInput] if `listener` is not bound
+    /// to a loopback address. Otherwise this only returns if accepting a connection fails.
+    pub fn run(&mut self, listener: TcpListener) -> io::Result<()> {
+        let address = listener.local_addr()?;
+        if !address.ip().is_loopback() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "The connections are not encrypted, so the probe server only listens on \
+                     the loopback interface, not on {}. Use a tunnel, e.g. SSH port forwarding, \
+                     to connect from other hosts.",
+                    address
+                ),
+            ));
+        }
+
+        for stream in listener.incoming() {
+            let stream = stream?;
+            let peer = stream.peer_addr()?;
+
+            log::info!("Client {} connected", peer);
+
+            match self.serve_client(stream) {
+                Ok(()) => log::info!("Client {} disconnected", peer),
+                Err(e) => log::warn!("Connection to client {} lost: {}", peer, e),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Handles the requests of one client, until it disconnects.
+    ///
+    /// The probe stays attached when the connection is lost, so the client can
+    /// reconnect and continue where it stopped, e.g. to resume flashing.
+    pub(crate) fn serve_client(&mut self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_nodelay(true)?;
+
+        stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        let request = read_frame_with_limit(&mut stream, MAX_HELLO_LENGTH)?;
+        stream.set_read_timeout(None)?;
+
+        let (token, resume) = match Request::decode(&request)? {
+            Request::Hello {
+                version: PROTOCOL_VERSION,
+                token,
+                resume,
+            } => (token, resume),
+            Request::Hello { version, .. } => {
+                let message = format!(
+                    "The server speaks protocol version {}, but the client speaks version {}",
+                    PROTOCOL_VERSION, version
+                );
+                return send_error(&mut stream, RemoteError::Other(message));
+            }
+            _ => return send_error(&mut stream, RemoteError::Other("Expected Hello".into())),
+        };
+
+        if !tokens_match(&token, &self.token) {
+            log::warn!("Client sent a wrong token");
+            return send_error(&mut stream, RemoteError::Other("Wrong token".into()));
+        }
+
+        // A new client starts with a probe which is not attached.
+        if !resume && self.probe.attached {
+            if let Err(e) = self.probe.detach() {
+                log::warn!("Failed to detach from the probe: {}", e);
+            }
+        }
+
+        let hello = Response::Hello {
+            name: self.probe.get_name(),
+            speed_khz: self.probe.speed_khz(),
+        };
+        write_frame(&mut stream, &hello.encode())?;
+
+        loop {
+            let request = match read_frame(&mut stream) {
+                Ok(request) => Request::decode(&request)?,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(e) => return Err(e),
+            };
+
+            log::trace!("Request: {:?}", request);
+            let response = self.handle(request);
+            log::trace!("Response: {:?}", response);
+
+            write_frame(&mut stream, &response.encode())?;
+        }
+    }
+
+    fn handle(&mut self, request: Request) -> Response {
+        self.try_handle(request)
+            .unwrap_or_else(|e| Response::Error(RemoteError::from(&e)))
+    }
+
+    fn try_handle(&mut self, request: Request) -> Result<Response, DebugProbeError> {
+        let probe = &mut self.probe;
+
+        match request {
+            Request::Hello { .. } => Err(super::RemoteProbeError::InvalidMessage.into()),
+            Request::Interfaces => Ok(Response::Interfaces {
+                arm: probe.inner.has_arm_interface(),
+                riscv: probe.inner.has_riscv_interface(),
+                power: probe.power_capabilities(),
+            }),
+            Request::SelectProtocol(protocol) => {
+                probe.select_protocol(protocol)?;
+                Ok(Response::Ok)
+            }
+            Request::SetSpeed(speed_khz) => probe.set_speed(speed_khz).map(Response::Speed),
+            Request::Attach => {
+                probe.inner.attach()?;
+                probe.attached = true;
+                Ok(Response::Ok)
+            }
+            Request::Detach => {
+                probe.detach()?;
+                Ok(Response::Ok)
+            }
+            Request::TargetReset => {
+                probe.target_reset()?;
+                Ok(Response::Ok)
+            }
+            Request::TargetResetAssert => {
+                probe.inner.target_reset_assert()?;
+                Ok(Response::Ok)
+            }
+            Request::TargetResetDeassert => {
+                probe.target_reset_deassert()?;
+                Ok(Response::Ok)
+            }
+            Request::SetTargetPower(enabled) => {
+                probe.set_target_power(enabled)?;
+                Ok(Response::Ok)
+            }
+            Request::TargetVoltage => probe.target_voltage().map(Response::Measurement),
+            Request::TargetCurrent => probe.target_current().map(Response::Measurement),
+            Request::DapBatch(commands) => Ok(run_batch(self.dap()?, &commands)),
+            Request::DapReadBlock { len, .. } if len as usize > MAX_BLOCK_LENGTH => {
+                Ok(Response::Error(RemoteError::Other(format!(
+                    "A block of {} values was requested, at most {} can be read at once",
+                    len, MAX_BLOCK_LENGTH
+                ))))
+            }
+            Request::DapReadBlock { port, address, len } => {
+                let mut values = vec![0; len as usize];
+                self.dap()?.read_block(port, address, &mut values)?;
+                Ok(Response::Values(values))
+            }
+            Request::DapWriteBlock {
+                port,
+                address,
+                values,
+            } => {
+                self.dap()?.write_block(port, address, &values)?;
+                Ok(Response::Ok)
+            }
+            Request::JtagRead { address, len } => self
+                .jtag()?
+                .read_register(address, len)
+                .map(Response::Bytes),
+            Request::JtagWrite { address, data, len } => self
+                .jtag()?
+                .write_register(address, &data, len)
+                .map(Response::Bytes),
+            Request::JtagSetIdleCycles(cycles) => {
+                self.jtag()?.set_idle_cycles(cycles);
+                Ok(Response::Ok)
+            }
+        }
+    }
+
+    fn dap(&mut self) -> Result<&mut dyn DAPAccess, DebugProbeError> {
+        self.probe
+            .inner
+            .get_dap_interface_mut()
+            .ok_or(DebugProbeError::InterfaceNotAvailable("DAP access"))
+    }
+
+    fn jtag(&mut self) -> Result<&mut dyn JTAGAccess, DebugProbeError> {
+        self.probe
+            .inner
+            .get_jtag_interface_mut()
+            .ok_or(DebugProbeError::InterfaceNotAvailable("JTAG access"))
+    }
+}
+
+/// Compares the token sent by a client with the expected one, in a time which does
+/// not depend on how much of the token is right.
+fn tokens_match(sent: &str, expected: &str) -> bool {
+    let (sent, expected) = (sent.as_bytes(), expected.as_bytes());
+
+    sent.len() == expected.len()
+        && sent
+            .iter()
+            .zip(expected)
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+fn send_error(stream: &mut TcpStream, error: RemoteError) -> io::Result<()> {
+    write_frame(stream, &Response::Error(error).encode())
+}
+
+/// Performs the commands of a batch, and stops at the first one which fails.
+fn run_batch(dap: &mut dyn DAPAccess, commands: &[BatchCommand]) -> Response {
+    let mut values = vec![];
+
+    for (index, command) in commands.iter().enumerate() {
+        let result = match *command {
+            BatchCommand::Read(port, address) => dap
+                .read_register(port, address)
+                .map(|value| values.push(value)),
+            BatchCommand::Write(port, address, value) => dap.write_register(port, address, value),
+        };
+
+        if let Err(e) = result {
+            return Response::Batch {
+                values,
+                failure: Some((index as u32, RemoteError::from(&e))),
+            };
+        }
+    }
+
+    match dap.flush() {
+        Ok(()) => Response::Batch {
+            values,
+            failure: None,
+        },
+        // The failed write is not known, so the last command is reported.
+        Err(e) => Response::Batch {
+            values,
+            failure: Some((
+                commands.len().saturating_sub(1) as u32,
+                RemoteError::from(&e),
+            )),
+        },
+    }
+}
@@ -0,0 +1,1171 @@
+//! Sharing a probe with other processes on the same host.
+//!
+//! The process which opens a probe with [Probe::open_shared] owns it and runs a broker,
+//! a [ProbeServer] on the loopback interface which serves every other process as a guest.
+//! [Probe::open] finds the broker of a matching probe in a registry in a directory which
+//! only the current user can access, and connects to it, instead of opening the USB device
+//! claimed by the owner.
+//!
+//! The accesses of all clients are serialized. A guest keeps the probe for a whole batch
+//! of register accesses, until it flushes them, and block transfers are performed as a
+//! whole. Before the DAP accesses of a client, the DP SELECT register and the CSW and TAR
+//! registers of the MEM-APs it used are restored, so its accesses continue where they
+//! stopped, even if another client used the probe in between.
+//!
+//! The owner keeps the run control of the target. Guests can not reset or power the target,
+//! select the protocol or speed, or write the DHCSR and AIRCR registers, which halt and reset
+//! Cortex-M cores. They can read and write memory of a running target, e.g. to poll RTT.
+//! The owner also controls the debug power and overrun detection of the DP, writes of guests
+//! to DP CTRL/STAT are dropped. Guests only get DAP access, i.e. they can only debug ARM targets.
+
+use super::remote::ProbeServer;
+use super::{
+    DebugProbe, DebugProbeError, DebugProbeSelector, JTAGAccess, PowerCapabilities, Probe,
+    ReconnectPolicy, WireProtocol,
+};
+use crate::architecture::{
+    arm::{
+        communication_interface::ArmProbeInterface, ArmCommunicationInterface, DAPAccess, PortType,
+    },
+    riscv::communication_interface::RiscvCommunicationInterface,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::path::PathBuf;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Condvar, Mutex, MutexGuard,
+};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use thiserror::Error;
+
+const DP_CTRL_STAT: u16 = 0x4;
+const DP_SELECT: u16 = 0x8;
+
+const AP_CSW: u16 = 0x00;
+const AP_TAR: u16 = 0x04;
+const AP_DRW: u16 = 0x0c;
+const AP_IDR: u16 = 0xfc;
+
+/// The class of MEM-APs in the IDR register.
+const AP_CLASS_MEM_AP: u32 = 0x8;
+
+/// Cortex-M registers which halt and reset the core, only the owner may write them.
+const RUN_CONTROL_REGISTERS: [u32; 2] = [
+    0xE000_EDF0, // DHCSR
+    0xE000_ED0C, // AIRCR
+];
+
+/// How often the broker checks whether the owner closed the probe.
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The directory below the runtime or temporary directory in which the brokers are registered.
+const REGISTRY_DIRECTORY: &str = "probe-rs-shared";
+
+#[derive(Debug, Error)]
+pub enum SharedProbeError {
+    #[error("Only the process which shares the probe may {0}")]
+    NotPermitted(&'static str),
+    #[error("The process which shared the probe closed it")]
+    Closed,
+    #[error("Unable to make the probe available to other processes")]
+    Broker(#[source] io::Error),
+}
+
+impl From<SharedProbeError> for DebugProbeError {
+    fn from(error: SharedProbeError) -> Self {
+        DebugProbeError::ProbeSpecific(Box::new(error))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Role {
+    Owner,
+    Guest,
+}
+
+/// The DAP registers a client wrote, which are restored before its next access.
+#[derive(Debug, Default)]
+struct DapContext {
+    select: u32,
+    /// The CSW and TAR registers, by AP and register address.
+    ap_registers: BTreeMap<(u16, u16), u32>,
+}
+
+impl DapContext {
+    /// Returns the address the next access to the register `address` of `ap` accesses,
+    /// if it is DRW or one of the banked data registers.
+    fn target_address(&self, ap: u16, address: u16) -> Option<u32> {
+        let tar = *self.ap_registers.get(&(ap, AP_TAR))?;
+
+        match address {
+            AP_DRW => Some(tar),
+            0x10..=0x1c => Some((tar & !0xf) + u32::from(address - 0x10)),
+            _ => None,
+        }
+    }
+
+    /// Advances TAR after `count` accesses to DRW, if CSW enables single auto increment.
+    fn increment_tar(&mut self, ap: u16, count: usize) {
+        let csw = match self.ap_registers.get(&(ap, AP_CSW)) {
+            Some(csw) => *csw,
+            None => return,
+        };
+
+        // AddrInc is in bits 5:4, Size in bits 2:0.
+        if (csw >> 4) & 0x3 != 0b01 {
+            return;
+        }
+        let size = 1u32 << (csw & 0x7).min(2);
+
+        if let Some(tar) = self.ap_registers.get_mut(&(ap, AP_TAR)) {
+            *tar = tar.wrapping_add(size.wrapping_mul(count as u32));
+        }
+    }
+}
+
+struct SharedState {
+    probe: Box<dyn DebugProbe>,
+    attached: bool,
+    closed: bool,
+    next_client: u32,
+    /// The client whose DAP context is set up in the probe.
+    active_client: Option<u32>,
+    /// The guest which is in the middle of a batch of register accesses.
+    batch_client: Option<u32>,
+    contexts: HashMap<u32, DapContext>,
+    /// The APs which are MEM-APs, only their CSW and TAR registers are restored.
+    memory_aps: HashSet<u16>,
+    /// Errors of queued writes, which were flushed when another client used the probe.
+    deferred_errors: HashMap<u32, DebugProbeError>,
+}
+
+impl SharedState {
+    fn dap(&mut self) -> Result<&mut dyn DAPAccess, DebugProbeError> {
+        self.probe
+            .get_dap_interface_mut()
+            .ok_or(DebugProbeError::InterfaceNotAvailable("DAP access"))
+    }
+
+    /// Sets up the DAP context of `client` in the probe.
+    fn activate(&mut self, client: u32) -> Result<(), DebugProbeError> {
+        if let Some(error) = self.deferred_errors.remove(&client) {
+            return Err(error);
+        }
+
+        if self.active_client == Some(client) {
+            return Ok(());
+        }
+
+        // The queued writes of the previous client have to be performed in its context.
+        if let Some(previous) = self.active_client.take() {
+            if let Err(error) = self.dap()?.flush() {
+                self.deferred_errors.insert(previous, error);
+            }
+        }
+
+        let context = self.contexts.entry(client).or_default();
+        let select = context.select;
+        let memory_aps = &self.memory_aps;
+        let registers: Vec<_> = context
+            .ap_registers
+            .iter()
+            .filter(|((ap, _), _)| memory_aps.contains(ap))
+            .map(|(key, value)| (*key, *value))
+            .collect();
+
+        let dap = self.dap()?;
+        for ((ap, address), value) in registers {
+            dap.write_register(PortType::DebugPort, DP_SELECT, u32::from(ap) << 24)?;
+            dap.write_register(PortType::AccessPort(ap), address, value)?;
+        }
+        dap.write_register(PortType::DebugPort, DP_SELECT, select)?;
+
+        self.active_client = Some(client);
+        Ok(())
+    }
+
+    /// Updates the context of `client` after `count` accesses to a register.
+    fn record_access(&mut self, client: u32, port: PortType, address: u16, count: usize) {
+        if let PortType::AccessPort(ap) = port {
+            if address == AP_DRW {
+                if let Some(context) = self.contexts.get_mut(&client) {
+                    context.increment_tar(ap, count);
+                }
+            }
+        }
+    }
+
+    /// Updates the context of `client` after it wrote `value` to a register.
+    fn record_write(&mut self, client: u32, port: PortType, address: u16, value: u32) {
+        let context = self.contexts.entry(client).or_default();
+
+        match port {
+            PortType::DebugPort if address == DP_SELECT => context.select = value,
+            PortType::AccessPort(ap) if address == AP_CSW || address == AP_TAR => {
+                context.ap_registers.insert((ap, address), value);
+            }
+            _ => self.record_access(client, port, address, 1),
+        }
+    }
+
+    /// Updates the context of `client` after it read `value` from a register.
+    fn record_read(&mut self, client: u32, port: PortType, address: u16, value: u32) {
+        match port {
+            PortType::AccessPort(ap) if address == AP_IDR => {
+                // The class is in bits 16:13.
+                if (value >> 13) & 0xf == AP_CLASS_MEM_AP {
+                    self.memory_aps.insert(ap);
+                }
+            }
+            _ => self.record_access(client, port, address, 1),
+        }
+    }
+}
+
+struct Shared {
+    state: Mutex<SharedState>,
+    /// Signalled when a guest finished a batch.
+    batch_done: Condvar,
+}
+
+/// Runs the broker thread, which accepts the connections of guests.
+struct Broker {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    registration: PathBuf,
+}
+
+impl Drop for Broker {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                log::warn!("Probe broker thread panicked");
+            }
+        }
+
+        if let Err(e) = fs::remove_file(&self.registration) {
+            log::warn!(
+                "Failed to remove the registration {} of the shared probe: {}",
+                self.registration.display(),
+                e
+            );
+        }
+    }
+}
+
+/// The entry of a broker in the registry.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Registration {
+    vendor_id: u16,
+    product_id: u16,
+    serial_number: Option<String>,
+    port_path: Option<String>,
+    /// The address of the broker, of the form `tcp://token@host:port`.
+    address: String,
+}
+
+impl Registration {
+    fn matches(&self, selector: &DebugProbeSelector) -> bool {
+        fn optional_eq(a: &Option<String>, b: &Option<String>) -> bool {
+            match (a, b) {
+                (Some(a), Some(b)) => a == b,
+                _ => true,
+            }
+        }
+
+        self.vendor_id == selector.vendor_id
+            && self.product_id == selector.product_id
+            && optional_eq(&self.serial_number, &selector.serial_number)
+            && optional_eq(&self.port_path, &selector.port_path)
+    }
+
+    /// Returns `true` if the broker listens on a loopback address, like all brokers do.
+    fn is_local(&self) -> bool {
+        self.address
+            .rsplit('@')
+            .next()
+            .and_then(|host| host.parse::<SocketAddr>().ok())
+            .map_or(false, |address| address.ip().is_loopback())
+    }
+}
+
+/// Returns the directory in which the brokers are registered, and creates it if needed.
+///
+/// On Unix, this is a directory in `$XDG_RUNTIME_DIR` or a directory of the current user
+/// in the temporary directory, which has to be owned by the user and not be accessible
+/// by others. Otherwise, the temporary directory of the user is used.
+#[cfg(unix)]
+fn registry_directory() -> io::Result<PathBuf> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+
+    // getuid always succeeds.
+    let uid = unsafe { libc::getuid() };
+
+    let directory = match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(runtime) if !runtime.is_empty() => PathBuf::from(runtime).join(REGISTRY_DIRECTORY),
+        _ => std::env::temp_dir().join(format!("{}-{}", REGISTRY_DIRECTORY, uid)),
+    };
+
+    match fs::DirBuilder::new().mode(0o700).create(&directory) {
+        Ok(()) => (),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => (),
+        Err(e) => return Err(e),
+    }
+
+    // A directory which was created by another user could be used to redirect clients.
+    let metadata = fs::symlink_metadata(&directory)?;
+    if !metadata.is_dir() || metadata.uid() != uid || metadata.mode() & 0o077 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "The registry {} has to be a directory which only the current user can access",
+                directory.display()
+            ),
+        ));
+    }
+
+    Ok(directory)
+}
+
+#[cfg(not(unix))]
+fn registry_directory() -> io::Result<PathBuf> {
+    let directory = std::env::temp_dir().join(REGISTRY_DIRECTORY);
+    fs::create_dir_all(&directory)?;
+    Ok(directory)
+}
+
+/// Connects to the broker of a process which shares a probe matching `selector`.
+///
+/// Registrations of brokers which are not reachable any more are removed.
+pub(crate) fn connect(selector: &DebugProbeSelector) -> Option<Probe> {
+    let directory = match registry_directory() {
+        Ok(directory) => directory,
+        Err(e) => {
+            log::warn!("Shared probes can not be used: {}", e);
+            return None;
+        }
+    };
+    let entries = fs::read_dir(directory).ok()?;
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+
+        let registration: Registration = match fs::read_to_string(&path)
+            .ok()
+            .and_then(|content| serde_yaml::from_str(&content).ok())
+        {
+            Some(registration) => registration,
+            None => continue,
+        };
+
+        if !registration.matches(selector) {
+            continue;
+        }
+
+        if !registration.is_local() {
+            log::warn!(
+                "Ignoring the registration {}, its broker is not on this host",
+                path.display()
+            );
+            continue;
+        }
+
+        match Probe::open_remote(&registration.address) {
+            Ok(probe) => {
+                log::info!("The probe is shared by another process, connected to it");
+                return Some(probe);
+            }
+            Err(e) => {
+                log::debug!("Removing the stale registration {}: {}", path.display(), e);
+                let _ = fs::remove_file(&path);
+            }
+        }
+    }
+
+    None
+}
+
+/// Returns a random token for the broker, from the random number generator of the OS.
+fn random_token() -> io::Result<String> {
+    let mut bytes = [0; 16];
+    getrandom::getrandom(&mut bytes)
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Writes the registration of a broker, which only the current user can read.
+fn register(registration: &Registration) -> io::Result<PathBuf> {
+    let directory = registry_directory()?;
+
+    let port = registration.address.rsplit(':').next().unwrap_or_default();
+    let path = directory.join(format!("{}-{}.yaml", std::process::id(), port));
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let content =
+        serde_yaml::to_string(registration).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    options.open(&path)?.write_all(content.as_bytes())?;
+
+    Ok(path)
+}
+
+/// Shares `probe`, which was opened with `selector`, with other processes.
+///
+/// Probes without raw DAP access can not be shared, they are returned as they are.
+pub(crate) fn share(
+    mut probe: Probe,
+    selector: &DebugProbeSelector,
+) -> Result<Probe, DebugProbeError> {
+    if probe.inner.get_dap_interface_mut().is_none() {
+        log::warn!(
+            "The {} can not be shared with other processes",
+            probe.get_name()
+        );
+        return Ok(probe);
+    }
+
+    let listener = TcpListener::bind("127.0.0.1:0").map_err(SharedProbeError::Broker)?;
+    listener
+        .set_nonblocking(true)
+        .map_err(SharedProbeError::Broker)?;
+    let port = listener
+        .local_addr()
+        .map_err(SharedProbeError::Broker)?
+        .port();
+    let token = random_token().map_err(SharedProbeError::Broker)?;
+
+    let registration = register(&Registration {
+        vendor_id: selector.vendor_id,
+        product_id: selector.product_id,
+        serial_number: selector.serial_number.clone(),
+        port_path: selector.port_path.clone(),
+        address: format!("tcp://{}@127.0.0.1:{}", token, port),
+    })
+    .map_err(SharedProbeError::Broker)?;
+
+    let attached = probe.attached;
+    let speed_selection = probe.speed_selection;
+    let mut owner = SharedProbe::new_owner(probe.inner, attached);
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let thread = {
+        let shared = owner.shared.clone();
+        let stop = stop.clone();
+        std::thread::spawn(move || run_broker(listener, shared, token, stop))
+    };
+
+    owner.broker = Some(Broker {
+        stop,
+        thread: Some(thread),
+        registration,
+    });
+
+    log::info!("Sharing the probe on port {}", port);
+
+    Ok(Probe {
+        inner: Box::new(owner),
+        attached,
+        speed_selection,
+        freeze_watchdogs: None,
+        frozen_peripherals: Vec::new(),
+        woke_up_after: None,
+    })
+}
+
+/// Accepts guests until `stop` is set, and serves each of them in its own thread.
+fn run_broker(listener: TcpListener, shared: Arc<Shared>, token: String, stop: Arc<AtomicBool>) {
+    while !stop.load(Ordering::Relaxed) {
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                std::thread::sleep(ACCEPT_POLL_INTERVAL);
+                continue;
+            }
+            Err(e) => {
+                log::warn!("The probe broker stopped accepting guests: {}", e);
+                return;
+            }
+        };
+
+        let guest = SharedProbe::new_guest(shared.clone());
+        let token = token.clone();
+
+        std::thread::spawn(move || {
+            let id = guest.id;
+            log::info!("Guest {} connected to the shared probe", id);
+
+            let mut server = ProbeServer::new(Probe::from_attached_probe(Box::new(guest)), token);
+            if let Err(e) = stream.set_nonblocking(false) {
+                log::warn!("Failed to set up the connection to guest {}: {}", id, e);
+                return;
+            }
+
+            match server.serve_client(stream) {
+                Ok(()) => log::info!("Guest {} disconnected", id),
+                Err(e) => log::warn!("Connection to guest {} lost: {}", id, e),
+            }
+        });
+    }
+}
+
+/// A handle to a probe which is shared between the owner and its guests.
+pub(crate) struct SharedProbe {
+    id: u32,
+    role: Role,
+    name: String,
+    shared: Arc<Shared>,
+    broker: Option<Broker>,
+}
+
+impl fmt::Debug for SharedProbe {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SharedProbe")
+            .field("id", &self.id)
+            .field("role", &self.role)
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
+impl SharedProbe {
+    fn new_owner(probe: Box<dyn DebugProbe>, attached: bool) -> Self {
+        let name = format!("{} (shared)", probe.get_name());
+        let state = SharedState {
+            probe,
+            attached,
+            closed: false,
+            next_client: 1,
+            active_client: None,
+            batch_client: None,
+            contexts: HashMap::new(),
+            memory_aps: HashSet::new(),
+            deferred_errors: HashMap::new(),
+        };
+
+        Self {
+            id: 0,
+            role: Role::Owner,
+            name,
+            shared: Arc::new(Shared {
+                state: Mutex::new(state),
+                batch_done: Condvar::new(),
+            }),
+            broker: None,
+        }
+    }
+
+    fn new_guest(shared: Arc<Shared>) -> Self {
+        let (id, name) = {
+            let mut state = shared.state.lock().unwrap();
+            let id = state.next_client;
+            state.next_client += 1;
+            (id, state.probe.get_name().to_string())
+        };
+
+        Self {
+            id,
+            role: Role::Guest,
+            name,
+            shared,
+            broker: None,
+        }
+    }
+
+    /// Locks the shared state, after the batch of another guest finished.
+    fn lock(&self) -> Result<MutexGuard<'_, SharedState>, DebugProbeError> {
+        let mut state = self.shared.state.lock().unwrap();
+
+        while state.batch_client.map_or(false, |client| client != self.id) {
+            state = self.shared.batch_done.wait(state).unwrap();
+        }
+
+        if state.closed && self.role == Role::Guest {
+            return Err(SharedProbeError::Closed.into());
+        }
+
+        Ok(state)
+    }
+
+    /// Ends the batch of this client, if it is in the middle of one.
+    fn end_batch(&self, state: &mut SharedState) {
+        if state.batch_client == Some(self.id) {
+            state.batch_client = None;
+            self.shared.batch_done.notify_all();
+        }
+    }
+
+    fn owner_only(&self, action: &'static str) -> Result<(), DebugProbeError> {
+        match self.role {
+            Role::Owner => Ok(()),
+            Role::Guest => Err(SharedProbeError::NotPermitted(action).into()),
+        }
+    }
+
+    /// Checks whether the client may write `count` values to the register `address`.
+    fn check_write(
+        &self,
+        state: &SharedState,
+        port: PortType,
+        address: u16,
+        count: usize,
+    ) -> Result<(), DebugProbeError> {
+        if self.role == Role::Owner {
+            return Ok(());
+        }
+
+        let ap = match port {
+            PortType::AccessPort(ap) => ap,
+            PortType::DebugPort => return Ok(()),
+        };
+
+        let start = match state
+            .contexts
+            .get(&self.id)
+            .and_then(|context| context.target_address(ap, address))
+        {
+            Some(start) => start,
+            None => return Ok(()),
+        };
+        let end = start.saturating_add(4 * count as u32);
+
+        if RUN_CONTROL_REGISTERS
+            .iter()
+            .any(|register| (start..end).contains(register))
+        {
+            return Err(SharedProbeError::NotPermitted(
+                "halt or reset the core, attach without halting",
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+
+    /// Calls `f` with the probe, while no other client uses it.
+    fn with_probe<T>(
+        &self,
+        f: impl FnOnce(&mut dyn DebugProbe) -> Result<T, DebugProbeError>,
+    ) -> Result<T, DebugProbeError> {
+        let mut state = self.lock()?;
+        f(&mut *state.probe)
+    }
+}
+
+impl Drop for SharedProbe {
+    fn drop(&mut self) {
+        let mut state = match self.shared.state.lock() {
+            Ok(state) => state,
+            Err(_) => return,
+        };
+
+        match self.role {
+            Role::Owner => state.closed = true,
+            Role::Guest => {
+                if state.active_client == Some(self.id) {
+                    if let Ok(dap) = state.dap() {
+                        let _ = dap.flush();
+                    }
+                    state.active_client = None;
+                }
+                state.contexts.remove(&self.id);
+                state.deferred_errors.remove(&self.id);
+            }
+        }
+
+        self.end_batch(&mut state);
+    }
+}
+
+impl DebugProbe for SharedProbe {
+    fn new_from_selector(
+        _selector: impl Into<DebugProbeSelector>,
+    ) -> Result<Box<Self>, DebugProbeError>
+    where
+        Self: Sized,
+    {
+        Err(DebugProbeError::ProbeCouldNotBeCreated(
+            super::ProbeCreationError::NotFound,
+        ))
+    }
+
+    fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    fn speed(&self) -> u32 {
+        match self.lock() {
+            Ok(state) => state.probe.speed(),
+            Err(_) => 0,
+        }
+    }
+
+    fn set_speed(&mut self, speed_khz: u32) -> Result<u32, DebugProbeError> {
+        self.owner_only("change the speed")?;
+        self.with_probe(|probe| probe.set_speed(speed_khz))
+    }
+
+    fn attach(&mut self) -> Result<(), DebugProbeError> {
+        let mut state = self.lock()?;
+
+        match self.role {
+            Role::Owner => {
+                state.probe.attach()?;
+                state.attached = true;
+                Ok(())
+            }
+            Role::Guest if state.attached => Ok(()),
+            Role::Guest => Err(SharedProbeError::NotPermitted("attach to the target").into()),
+        }
+    }
+
+    fn detach(&mut self) -> Result<(), DebugProbeError> {
+        if self.role == Role::Guest {
+            return Ok(());
+        }
+
+        let mut state = self.lock()?;
+        state.attached = false;
+        state.active_client = None;
+        state.probe.detach()
+    }
+
+    fn target_reset(&mut self) -> Result<(), DebugProbeError> {
+        self.owner_only("reset the target")?;
+        self.with_probe(|probe| probe.target_reset())
+    }
+
+    fn target_reset_assert(&mut self) -> Result<(), DebugProbeError> {
+        self.owner_only("reset the target")?;
+        self.with_probe(|probe| probe.target_reset_assert())
+    }
+
+    fn target_reset_deassert(&mut self) -> Result<(), DebugProbeError> {
+        self.owner_only("reset the target")?;
+        self.with_probe(|probe| probe.target_reset_deassert())
+    }
+
+    fn select_protocol(&mut self, protocol: WireProtocol) -> Result<(), DebugProbeError> {
+        self.owner_only("select the protocol")?;
+        self.with_probe(|probe| probe.select_protocol(protocol))
+    }
+
+    fn power_capabilities(&self) -> PowerCapabilities {
+        match self.lock() {
+            Ok(state) => state.probe.power_capabilities(),
+            Err(_) => PowerCapabilities::default(),
+        }
+    }
+
+    fn target_voltage(&mut self) -> Result<f32, DebugProbeError> {
+        self.with_probe(|probe| probe.target_voltage())
+    }
+
+    fn target_current(&mut self) -> Result<f32, DebugProbeError> {
+        self.with_probe(|probe| probe.target_current())
+    }
+
+    fn set_target_power(&mut self, enabled: bool) -> Result<(), DebugProbeError> {
+        self.owner_only("switch the target power")?;
+        self.with_probe(|probe| probe.set_target_power(enabled))
+    }
+
+    fn disable_flash_protection(&mut self) -> Result<(), DebugProbeError> {
+        self.owner_only("disable the flash protection")?;
+        self.with_probe(|probe| probe.disable_flash_protection())
+    }
+
+    fn set_reconnect_policy(&mut self, policy: ReconnectPolicy) -> Result<(), DebugProbeError> {
+        self.owner_only("configure reconnecting")?;
+        self.with_probe(|probe| probe.set_reconnect_policy(policy))
+    }
+
+    fn uses_overrun_detection(&self) -> bool {
+        match self.lock() {
+            Ok(state) => state.probe.uses_overrun_detection(),
+            Err(_) => false,
+        }
+    }
+
+    fn has_arm_interface(&self) -> bool {
+        match self.lock() {
+            Ok(state) => state.probe.has_arm_interface(),
+            Err(_) => false,
+        }
+    }
+
+    fn get_arm_interface<'probe>(
+        self: Box<Self>,
+    ) -> Result<Option<Box<dyn ArmProbeInterface + 'probe>>, DebugProbeError> {
+        if !self.has_arm_interface() {
+            return Ok(None);
+        }
+
+        // Guests can not change the overrun detection, see the module documentation.
+        let use_overrun_detect = self.role == Role::Owner && self.uses_overrun_detection();
+        let interface = ArmCommunicationInterface::new(self, use_overrun_detect)?;
+
+        Ok(Some(Box::new(interface)))
+    }
+
+    fn has_riscv_interface(&self) -> bool {
+        self.role == Role::Owner
+            && match self.lock() {
+                Ok(state) => state.probe.has_riscv_interface(),
+                Err(_) => false,
+            }
+    }
+
+    fn get_riscv_interface(
+        self: Box<Self>,
+    ) -> Result<Option<RiscvCommunicationInterface>, DebugProbeError> {
+        if self.has_riscv_interface() {
+            Ok(Some(RiscvCommunicationInterface::new(self)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn get_dap_interface_mut(&mut self) -> Option<&mut dyn DAPAccess> {
+        Some(self as _)
+    }
+
+    fn get_jtag_interface_mut(&mut self) -> Option<&mut dyn JTAGAccess> {
+        match self.role {
+            Role::Owner => Some(self as _),
+            Role::Guest => None,
+        }
+    }
+}
+
+impl DAPAccess for SharedProbe {
+    fn read_register(&mut self, port: PortType, address: u16) -> Result<u32, DebugProbeError> {
+        let mut state = self.lock()?;
+
+        let result = state
+            .activate(self.id)
+            .and_then(|()| state.dap()?.read_register(port, address));
+
+        match result {
+            Ok(value) => {
+                state.record_read(self.id, port, address, value);
+                if self.role == Role::Guest {
+                    state.batch_client = Some(self.id);
+                }
+                Ok(value)
+            }
+            Err(e) => {
+                self.end_batch(&mut state);
+                Err(e)
+            }
+        }
+    }
+
+    fn write_register(
+        &mut self,
+        port: PortType,
+        address: u16,
+        value: u32,
+    ) -> Result<(), DebugProbeError> {
+        let mut state = self.lock()?;
+
+        if self.role == Role::Guest && port == PortType::DebugPort && address == DP_CTRL_STAT {
+            log::debug!("Dropping the write of guest {} to CTRL/STAT", self.id);
+            return Ok(());
+        }
+
+        let result = self
+            .check_write(&state, port, address, 1)
+            .and_then(|()| state.activate(self.id))
+            .and_then(|()| state.dap()?.write_register(port, address, value));
+
+        match result {
+            Ok(()) => {
+                state.record_write(self.id, port, address, value);
+                if self.role == Role::Guest {
+                    state.batch_client = Some(self.id);
+                }
+                Ok(())
+            }
+            Err(e) => {
+                self.end_batch(&mut state);
+                Err(e)
+            }
+        }
+    }
+
+    fn read_block(
+        &mut self,
+        port: PortType,
+        address: u16,
+        values: &mut [u32],
+    ) -> Result<(), DebugProbeError> {
+        let mut state = self.lock()?;
+
+        state.activate(self.id)?;
+        state.dap()?.read_block(port, address, values)?;
+        state.record_access(self.id, port, address, values.len());
+
+        Ok(())
+    }
+
+    fn write_block(
+        &mut self,
+        port: PortType,
+        address: u16,
+        values: &[u32],
+    ) -> Result<(), DebugProbeError> {
+        let mut state = self.lock()?;
+
+        self.check_write(&state, port, address, values.len())?;
+        state.activate(self.id)?;
+        state.dap()?.write_block(port, address, values)?;
+        state.record_access(self.id, port, address, values.len());
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), DebugProbeError> {
+        let mut state = self.lock()?;
+
+        let result = state.activate(self.id).and_then(|()| state.dap()?.flush());
+        self.end_batch(&mut state);
+
+        result
+    }
+
+    fn into_probe(self: Box<Self>) -> Box<dyn DebugProbe> {
+        self
+    }
+}
+
+impl<'a> AsRef<dyn DebugProbe + 'a> for SharedProbe {
+    fn as_ref(&self) -> &(dyn DebugProbe + 'a) {
+        self
+    }
+}
+
+impl<'a> AsMut<dyn DebugProbe + 'a> for SharedProbe {
+    fn as_mut(&mut self) -> &mut (dyn DebugProbe + 'a) {
+        self
+    }
+}
+
+impl JTAGAccess for SharedProbe {
+    fn read_register(&mut self, address: u32, len: u32) -> Result<Vec<u8>, DebugProbeError> {
+        let mut state = self.lock()?;
+        let jtag = state
+            .probe
+            .get_jtag_interface_mut()
+            .ok_or(DebugProbeError::InterfaceNotAvailable("JTAG access"))?;
+
+        jtag.read_register(address, len)
+    }
+
+    fn write_register(
+        &mut self,
+        address: u32,
+        data: &[u8],
+        len: u32,
+    ) -> Result<Vec<u8>, DebugProbeError> {
+        let mut state = self.lock()?;
+        let jtag = state
+            .probe
+            .get_jtag_interface_mut()
+            .ok_or(DebugProbeError::InterfaceNotAvailable("JTAG access"))?;
+
+        jtag.write_register(address, data, len)
+    }
+
+    fn set_idle_cycles(&mut self, idle_cycles: u8) {
+        if let Ok(mut state) = self.lock() {
+            if let Some(jtag) = state.probe.get_jtag_interface_mut() {
+                jtag.set_idle_cycles(idle_cycles);
+            }
+        }
+    }
+
+    fn into_probe(self: Box<Self>) -> Box<dyn DebugProbe> {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{random_token, Registration, SharedProbe, DP_SELECT};
+    use crate::architecture::arm::{DAPAccess, PortType};
+    use crate::probe::{DebugProbe, DebugProbeSelector, FakeDap};
+    use std::sync::{Arc, Mutex};
+
+    type Log = Arc<Mutex<Vec<(PortType, u16, u32)>>>;
+
+    /// Returns an owner and a guest of a fake probe with a MEM-AP, and the log of its writes.
+    fn shared_probe() -> (SharedProbe, SharedProbe, Log) {
+        let fake =
+            FakeDap::new("Fake DAP").with_register(PortType::AccessPort(0), 0xfc, 0x2477_0011);
+        let writes = fake.writes.clone();
+
+        let owner = SharedProbe::new_owner(Box::new(fake), true);
+        let guest = SharedProbe::new_guest(owner.shared.clone());
+
+        (owner, guest, writes)
+    }
+
+    #[test]
+    fn context_is_restored_when_the_client_changes() {
+        let (mut owner, mut guest, writes) = shared_probe();
+        let ap = PortType::AccessPort(0);
+
+        owner.read_register(ap, 0xfc).unwrap();
+        owner
+            .write_register(PortType::DebugPort, DP_SELECT, 0)
+            .unwrap();
+        owner.write_register(ap, 0x00, 0x2300_0052).unwrap();
+        owner.write_register(ap, 0x04, 0x2000_0000).unwrap();
+
+        guest.write_register(ap, 0x04, 0x2000_1000).unwrap();
+        guest.flush().unwrap();
+
+        writes.lock().unwrap().clear();
+        owner.read_register(ap, 0x0c).unwrap();
+
+        assert_eq!(
+            *writes.lock().unwrap(),
+            vec![
+                (PortType::DebugPort, DP_SELECT, 0),
+                (ap, 0x00, 0x2300_0052),
+                (PortType::DebugPort, DP_SELECT, 0),
+                (ap, 0x04, 0x2000_0000),
+                (PortType::DebugPort, DP_SELECT, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn tar_is_incremented_by_accesses() {
+        let (mut owner, mut guest, writes) = shared_probe();
+        let ap = PortType::AccessPort(0);
+
+        owner.read_register(ap, 0xfc).unwrap();
+        owner.write_register(ap, 0x00, 0x2300_0052).unwrap();
+        owner.write_register(ap, 0x04, 0x2000_0000).unwrap();
+        owner.read_block(ap, 0x0c, &mut [0; 4]).unwrap();
+
+        guest.read_register(ap, 0x0c).unwrap();
+        guest.flush().unwrap();
+
+        writes.lock().unwrap().clear();
+        owner.read_register(ap, 0x0c).unwrap();
+
+        assert!(writes.lock().unwrap().contains(&(ap, 0x04, 0x2000_0010)));
+    }
+
+    #[test]
+    fn guests_can_not_halt_or_reset() {
+        let (_owner, mut guest, writes) = shared_probe();
+        let ap = PortType::AccessPort(0);
+
+        guest.write_register(ap, 0x00, 0x2300_0002).unwrap();
+        guest.write_register(ap, 0x04, 0xE000_EDF0).unwrap();
+        assert!(guest.write_register(ap, 0x0c, 0xA05F_0003).is_err());
+
+        guest.write_register(ap, 0x04, 0xE000_ED00).unwrap();
+        assert!(guest.write_block(ap, 0x0c, &[0; 4]).is_err());
+
+        assert!(guest.target_reset().is_err());
+        assert!(guest.target_reset_assert().is_err());
+        assert!(guest.set_speed(100).is_err());
+
+        assert!(!writes
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|&(port, address, _)| port == ap && address == 0x0c));
+    }
+
+    #[test]
+    fn guest_writes_to_ctrl_stat_are_dropped() {
+        let (mut owner, mut guest, writes) = shared_probe();
+
+        owner
+            .write_register(PortType::DebugPort, 0x4, 0x5000_0001)
+            .unwrap();
+        guest
+            .write_register(PortType::DebugPort, 0x4, 0x5000_0000)
+            .unwrap();
+
+        assert!(!writes
+            .lock()
+            .unwrap()
+            .contains(&(PortType::DebugPort, 0x4, 0x5000_0000)));
+    }
+
+    #[test]
+    fn registration_matches_unspecified_serial_numbers() {
+        let registration = Registration {
+            vendor_id: 0x0d28,
+            product_id: 0x0204,
+            serial_number: None,
+            port_path: Some("1-3.2".to_owned()),
+            address: "tcp://token@127.0.0.1:1234".to_owned(),
+        };
+
+        let mut selector = DebugProbeSelector {
+            vendor_id: 0x0d28,
+            product_id: 0x0204,
+            serial_number: Some("12345".to_owned()),
+            port_path: None,
+        };
+        assert!(registration.matches(&selector));
+
+        selector.port_path = Some("1-3.1".to_owned());
+        assert!(!registration.matches(&selector));
+
+        selector.port_path = None;
+        selector.product_id = 0x0205;
+        assert!(!registration.matches(&selector));
+    }
+
+    #[test]
+    fn only_local_brokers_are_used() {
+        let mut registration = Registration {
+            vendor_id: 0x0d28,
+            product_id: 0x0204,
+            serial_number: None,
+            port_path: None,
+            address: "tcp://token@127.0.0.1:1234".to_owned(),
+        };
+        assert!(registration.is_local());
+
+        registration.address = "tcp://token@[::1]:1234".to_owned();
+        assert!(registration.is_local());
+
+        registration.address = "tcp://token@192.168.1.20:1234".to_owned();
+        assert!(!registration.is_local());
+
+        registration.address = "tcp://token@lab-pi:1234".to_owned();
+        assert!(!registration.is_local());
+    }
+
+    #[test]
+    fn tokens_are_random() {
+        let token = random_token().unwrap();
+
+        assert_eq!(token.len(), 32);
+        assert_ne!(token, random_token().unwrap());
+    }
+}
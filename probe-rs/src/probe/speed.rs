@@ -0,0 +1,371 @@
+//! Selection of the SWD/JTAG clock speed while attaching to a target.
+//!
+//! Long cables, level shifters and slow targets limit the clock speed at which the
+//! communication with the target is reliable. Unless the speed was set explicitly,
+//! [attach] checks the link after attaching by reading the DPIDR register a few times.
+//! If the reads fail with protocol errors, e.g. parity errors, WAIT or FAULT responses,
+//! or return inconsistent values, the clock is lowered along [SPEED_STEPS_KHZ] and the
+//! probe is attached again.
+//!
+//! Optionally, the speed is raised again after a stable attach. At every higher speed,
+//! a pattern is written to the TAR register of the first access port and read back,
+//! and the speed is only kept if the values match the ones read at the stable speed.
+
+use super::{DebugProbe, DebugProbeError};
+use crate::architecture::arm::{
+    dp::{Abort, Ctrl, Select, DPIDR},
+    DAPAccess, DapError, PortType, Register,
+};
+
+use thiserror::Error;
+
+/// The speeds in kHz which are tried, from the highest to the lowest.
+pub(crate) const SPEED_STEPS_KHZ: [u32; 9] = [24000, 16000, 8000, 4000, 2000, 1000, 500, 250, 100];
+
+/// How often DPIDR is read to check the link.
+const LINK_CHECK_READS: usize = 16;
+
+/// The values written to TAR to check the link at a higher speed.
+///
+/// The lowest bits of TAR might not be implemented, so they are left clear.
+const TEST_PATTERNS: [u32; 4] = [0x5555_5554, 0xaaaa_aaa8, 0x0000_0000, 0xffff_fffc];
+
+/// Address of the TAR register of a MEM-AP.
+const AP_TAR: u16 = 0x04;
+
+/// How often CTRL/STAT is polled until the debug domain is powered up.
+const POWER_UP_POLLS: usize = 10;
+
+/// How the communication speed is selected when attaching to a target.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SpeedSelection {
+    /// The configured speed is used as is.
+    Fixed,
+    /// The speed recommended for the target, or the configured speed, is lowered
+    /// if the communication fails.
+    Adaptive {
+        /// Raise the speed again after a stable attach, as long as the link stays reliable.
+        increase: bool,
+    },
+}
+
+impl Default for SpeedSelection {
+    fn default() -> Self {
+        SpeedSelection::Adaptive { increase: false }
+    }
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum LinkCheckError {
+    #[error("The DPIDR value {0:#010x} is invalid.")]
+    InvalidDpidr(u32),
+    #[error("The DPIDR register was read as {0:#010x} and as {1:#010x}.")]
+    InconsistentDpidr(u32, u32),
+    #[error("The test pattern was read back as {read:#010x}, expected {expected:#010x}.")]
+    PatternMismatch { expected: u32, read: u32 },
+}
+
+impl From<LinkCheckError> for DebugProbeError {
+    fn from(error: LinkCheckError) -> Self {
+        DebugProbeError::ProbeSpecific(Box::new(error))
+    }
+}
+
+/// Attaches the probe, and lowers the speed if the communication with the target fails.
+///
+/// If `increase` is set, higher speeds are tried after a stable attach. Probes without
+/// raw DAP access are attached at the configured speed.
+pub(crate) fn attach(probe: &mut dyn DebugProbe, increase: bool) -> Result<(), DebugProbeError> {
+    if !probe.has_arm_interface() || probe.get_dap_interface_mut().is_none() {
+        return probe.attach();
+    }
+
+    let initial_speed = probe.speed();
+    let mut speed = initial_speed;
+
+    loop {
+        probe.attach()?;
+
+        let error = match check_link(probe) {
+            Ok(()) => break,
+            Err(error) if is_link_error(&error) => error,
+            // Errors which are not caused by the link are left to the session.
+            Err(_) => return Ok(()),
+        };
+
+        probe.detach()?;
+
+        let lower_speed = match next_lower_speed(speed) {
+            Some(lower_speed) => lower_speed,
+            None => return attach_at_initial_speed(probe, initial_speed),
+        };
+
+        match probe.set_speed(lower_speed) {
+            Ok(actual_speed) if actual_speed < speed => {
+                log::warn!(
+                    "Communication with the target failed at {} kHz ({}), retrying at {} kHz.",
+                    speed,
+                    error,
+                    actual_speed
+                );
+                speed = actual_speed;
+            }
+            // The probe is not able to lower the speed any further.
+            _ => return attach_at_initial_speed(probe, initial_speed),
+        }
+    }
+
+    if increase {
+        speed = increase_speed(probe, speed)?;
+    }
+
+    log::info!("Communicating with the target at {} kHz.", speed);
+
+    Ok(())
+}
+
+/// Attaches the detached probe at `speed`, after the link check failed at all lower speeds.
+///
+/// The target might not have an ARM debug port at all, e.g. a RISC-V target
+/// behind a J-Link, so the attach is not failed here.
+fn attach_at_initial_speed(probe: &mut dyn DebugProbe, speed: u32) -> Result<(), DebugProbeError> {
+    log::debug!(
+        "The link check failed at all speeds, attaching at {} kHz.",
+        speed
+    );
+
+    if let Err(error) = probe.set_speed(speed) {
+        log::debug!("Unable to restore the speed: {}", error);
+    }
+
+    probe.attach()
+}
+
+/// Tries higher speeds than `speed`, and returns the highest speed at which the
+/// test patterns are read back correctly. The probe is attached at that speed.
+fn increase_speed(probe: &mut dyn DebugProbe, speed: u32) -> Result<u32, DebugProbeError> {
+    let reference = match pattern_test(probe) {
+        Ok(reference) => reference,
+        Err(error) => {
+            log::debug!("Not raising the speed, the pattern test failed: {}", error);
+            return Ok(speed);
+        }
+    };
+
+    let mut stable_speed = speed;
+
+    for &higher_speed in SPEED_STEPS_KHZ.iter().rev().filter(|&&s| s > speed) {
+        probe.detach()?;
+
+        let actual_speed = match probe.set_speed(higher_speed) {
+            Ok(actual_speed) if actual_speed > stable_speed => actual_speed,
+            // The probe does not support a higher speed.
+            _ => break,
+        };
+
+        let result = probe
+            .attach()
+            .and_then(|_| check_link(probe))
+            .and_then(|_| {
+                pattern_test(probe).and_then(|read| {
+                    match reference.iter().zip(read.iter()).find(|(e, r)| e != r) {
+                        Some((&expected, &read)) => {
+                            Err(LinkCheckError::PatternMismatch { expected, read }.into())
+                        }
+                        None => Ok(()),
+                    }
+                })
+            });
+
+        match result {
+            Ok(()) => {
+                log::debug!("The link is stable at {} kHz.", actual_speed);
+                stable_speed = actual_speed;
+            }
+            Err(error) => {
+                log::debug!("The link is unstable at {} kHz: {}", actual_speed, error);
+                break;
+            }
+        }
+    }
+
+    // The loop leaves the probe detached, or attached at a speed which might be unstable.
+    probe.detach()?;
+    if probe.speed() != stable_speed {
+        probe.set_speed(stable_speed)?;
+    }
+    probe.attach()?;
+
+    Ok(stable_speed)
+}
+
+/// Returns the next speed in [SPEED_STEPS_KHZ] which is lower than `speed`.
+fn next_lower_speed(speed: u32) -> Option<u32> {
+    SPEED_STEPS_KHZ.iter().copied().find(|&s| s < speed)
+}
+
+/// Returns true if the error is caused by an unreliable link.
+fn is_link_error(error: &DebugProbeError) -> bool {
+    match error {
+        DebugProbeError::ArchitectureSpecific(source) => match source.downcast_ref::<DapError>() {
+            Some(DapError::TargetPowerUpFailed) => false,
+            Some(_) => true,
+            None => false,
+        },
+        DebugProbeError::ProbeSpecific(source) => source.downcast_ref::<LinkCheckError>().is_some(),
+        _ => false,
+    }
+}
+
+/// Reads DPIDR repeatedly, and checks that the same valid value is read every time.
+fn check_link(probe: &mut dyn DebugProbe) -> Result<(), DebugProbeError> {
+    let dap = dap_interface(probe)?;
+
+    let dpidr = dap.read_register(PortType::DebugPort, DPIDR::ADDRESS as u16)?;
+
+    // Bit 0 of DPIDR always reads as one.
+    if dpidr & 1 == 0 {
+        return Err(LinkCheckError::InvalidDpidr(dpidr).into());
+    }
+
+    for _ in 1..LINK_CHECK_READS {
+        let value = dap.read_register(PortType::DebugPort, DPIDR::ADDRESS as u16)?;
+
+        if value != dpidr {
+            return Err(LinkCheckError::InconsistentDpidr(dpidr, value).into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Powers up the debug domain, writes [TEST_PATTERNS] to TAR of the first
+/// access port, and returns the values read back.
+fn pattern_test(probe: &mut dyn DebugProbe) -> Result<Vec<u32>, DebugProbeError> {
+    power_up(probe)?;
+    let dap = dap_interface(probe)?;
+
+    let ap = PortType::AccessPort(0);
+    let mut read = Vec::with_capacity(TEST_PATTERNS.len());
+
+    for &pattern in TEST_PATTERNS.iter() {
+        dap.write_register(ap, AP_TAR, pattern)?;
+        read.push(dap.read_register(ap, AP_TAR)?);
+    }
+
+    Ok(read)
+}
+
+/// Clears the sticky errors, and requests the power of the debug domain through CTRL/STAT.
+pub(super) fn power_up(probe: &mut dyn DebugProbe) -> Result<(), DebugProbeError> {
+    let overrun_detection = probe.uses_overrun_detection();
+    let dap = dap_interface(probe)?;
+
+    let mut abort = Abort::default();
+    abort.set_orunerrclr(true);
+    abort.set_wderrclr(true);
+    abort.set_stkerrclr(true);
+    abort.set_stkcmpclr(true);
+    dap.write_register(PortType::DebugPort, Abort::ADDRESS as u16, abort.into())?;
+
+    dap.write_register(PortType::DebugPort, Select::ADDRESS as u16, 0)?;
+
+    let mut ctrl = Ctrl::default();
+    ctrl.set_cdbgpwrupreq(true);
+    ctrl.set_csyspwrupreq(true);
+    ctrl.set_orun_detect(overrun_detection);
+    dap.write_register(PortType::DebugPort, Ctrl::ADDRESS as u16, ctrl.into())?;
+
+    for _ in 0..POWER_UP_POLLS {
+        let ctrl = Ctrl::from(dap.read_register(PortType::DebugPort, Ctrl::ADDRESS as u16)?);
+        if ctrl.cdbgpwrupack() && ctrl.csyspwrupack() {
+            return Ok(());
+        }
+    }
+
+    Err(DapError::TargetPowerUpFailed.into())
+}
+
+fn dap_interface(probe: &mut dyn DebugProbe) -> Result<&mut dyn DAPAccess, DebugProbeError> {
+    probe
+        .get_dap_interface_mut()
+        .ok_or(DebugProbeError::InterfaceNotAvailable("ARM"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::attach;
+    use crate::architecture::arm::{DapError, PortType};
+    use crate::probe::FakeDap;
+
+    /// Returns a probe whose reads fail above `max_speed`, and corrupt TAR above `max_tar_speed`.
+    fn fake_link(speed: u32, max_speed: u32, max_tar_speed: u32) -> FakeDap {
+        let mut probe = FakeDap::new("Fake link")
+            .with_max_speed(16000)
+            .with_register(PortType::DebugPort, 0x0, 0x2ba0_1477)
+            .with_target(move |probe, port, addr, value| {
+                assert!(probe.attached);
+
+                match (port, addr, value) {
+                    (_, _, None) if probe.speed > max_speed => {
+                        Some(Err(DapError::IncorrectParity.into()))
+                    }
+                    (PortType::AccessPort(0), 0x4, None) if probe.speed > max_tar_speed => {
+                        Some(Ok(probe.register(port, addr) ^ 0x100))
+                    }
+                    _ => None,
+                }
+            });
+
+        probe.speed = speed;
+        probe
+    }
+
+    #[test]
+    fn speed_is_lowered_on_errors() {
+        let mut probe = fake_link(4000, 1000, 1000);
+
+        attach(&mut probe, false).unwrap();
+
+        assert!(probe.attached);
+        assert_eq!(probe.speed, 1000);
+    }
+
+    #[test]
+    fn stable_speed_is_kept() {
+        let mut probe = fake_link(4000, 8000, 8000);
+
+        attach(&mut probe, false).unwrap();
+
+        assert_eq!(probe.speed, 4000);
+    }
+
+    #[test]
+    fn speed_is_raised_until_the_pattern_test_fails() {
+        let mut probe = fake_link(1000, 16000, 4000);
+
+        attach(&mut probe, true).unwrap();
+
+        assert!(probe.attached);
+        assert_eq!(probe.speed, 4000);
+    }
+
+    #[test]
+    fn speed_is_raised_up_to_the_probe_limit() {
+        let mut probe = fake_link(1000, 24000, 24000);
+
+        attach(&mut probe, true).unwrap();
+
+        assert_eq!(probe.speed, 16000);
+    }
+
+    #[test]
+    fn initial_speed_is_restored_if_no_speed_works() {
+        let mut probe = fake_link(4000, 0, 0);
+
+        attach(&mut probe, false).unwrap();
+
+        assert!(probe.attached);
+        assert_eq!(probe.speed, 4000);
+    }
+}
@@ -3,7 +3,10 @@ pub mod tools;
 mod usb_interface;
 
 use self::usb_interface::{STLinkUSBDevice, StLinkUsb};
-use super::{DAPAccess, DebugProbe, DebugProbeError, PortType, ProbeCreationError, WireProtocol};
+use super::{
+    DAPAccess, DebugProbe, DebugProbeError, PortType, PowerCapabilities, ProbeCreationError,
+    WireProtocol,
+};
 use crate::{
     architecture::arm::communication_interface::MemoryApInformation,
     architecture::arm::{
@@ -11,16 +14,22 @@ use crate::{
             valid_access_ports, APAccess, APClass, APRegister, AccessPort, BaseaddrFormat,
             GenericAP, MemoryAP, BASE, BASE2, CSW, IDR,
         },
-        communication_interface::{ArmCommunicationInterfaceState, ArmProbeInterface},
+        communication_interface::{
+            read_debug_port_ids, ArmCommunicationInterfaceState, ArmProbeInterface,
+        },
         dp::{DPAccess, DPBankSel, DPRegister, DebugPortError, Select},
         memory::{adi_v5_memory_interface::ArmProbe, Component},
-        ApInformation, ArmChipInfo, SwoAccess, SwoConfig, SwoMode,
+        ApInformation, ArmChipInfo, FaultPolicy, Register, SwoAccess, SwoConfig, SwoMode,
     },
-    DebugProbeSelector, Error as ProbeRsError, Memory, Probe,
+    AccessSize, DebugProbeSelector, Error as ProbeRsError, Memory, Probe,
 };
 use constants::{commands, JTagFrequencyToDivider, Mode, Status, SwdFrequencyToDelayCount};
 use scroll::{Pread, Pwrite, BE, LE};
-use std::{cmp::Ordering, convert::TryInto, time::Duration};
+use std::{
+    cmp::Ordering,
+    convert::TryInto,
+    time::{Duration, Instant},
+};
 use thiserror::Error;
 use usb_interface::TIMEOUT;
 
@@ -35,6 +44,12 @@ const STLINK_MAX_READ_LEN: usize = 6144;
 /// is also a multiple of 4.
 const STLINK_MAX_WRITE_LEN: usize = 0xFFFC;
 
+/// Size of the SWO trace buffer on the ST-Link in bytes.
+const SWO_BUFFER_SIZE: u16 = 4096;
+
+/// Interval at which the SWO buffer is polled while waiting for data.
+const SWO_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
 #[derive(Debug)]
 pub struct STLink<D: StLinkUsb> {
     device: D,
@@ -234,12 +249,31 @@ impl DebugProbe for STLink<STLinkUSBDevice> {
         Ok(())
     }
 
+    fn power_capabilities(&self) -> PowerCapabilities {
+        PowerCapabilities {
+            target_voltage: true,
+            ..Default::default()
+        }
+    }
+
+    fn target_voltage(&mut self) -> Result<f32, DebugProbeError> {
+        self.get_target_voltage()
+    }
+
     fn get_swo_interface(&self) -> Option<&dyn SwoAccess> {
-        Some(self as _)
+        if self.device.has_swo() {
+            Some(self as _)
+        } else {
+            None
+        }
     }
 
     fn get_swo_interface_mut(&mut self) -> Option<&mut dyn SwoAccess> {
-        Some(self as _)
+        if self.device.has_swo() {
+            Some(self as _)
+        } else {
+            None
+        }
     }
 
     fn get_arm_interface<'probe>(
@@ -720,9 +754,31 @@ impl<D: StLinkUsb> STLink<D> {
         Err(From::from(StlinkError::CommandFailed(status)))
     }
 
+    /// Returns the highest SWO baud rate the probe can receive.
+    fn max_swo_baud(&self) -> u32 {
+        if self.hw_version >= 3 {
+            24_000_000
+        } else {
+            2_000_000
+        }
+    }
+
     pub fn start_trace_reception(&mut self, config: &SwoConfig) -> Result<(), DebugProbeError> {
+        if !self.device.has_swo() {
+            return Err(StlinkError::SwoNotSupported.into());
+        }
+
+        let max = self.max_swo_baud();
+        if config.baud() == 0 || config.baud() > max {
+            return Err(StlinkError::SwoBaudRateNotSupported {
+                requested: config.baud(),
+                max,
+            }
+            .into());
+        }
+
         let mut buf = [0; 2];
-        let bufsize = 4096u16.to_le_bytes();
+        let bufsize = SWO_BUFFER_SIZE.to_le_bytes();
         let baud = config.baud().to_le_bytes();
         let mut command = vec![commands::JTAG_COMMAND, commands::SWO_START_TRACE_RECEPTION];
         command.extend_from_slice(&bufsize);
@@ -769,7 +825,14 @@ impl<D: StLinkUsb> STLink<D> {
     fn read_swo_data(&mut self, timeout: Duration) -> Result<Vec<u8>, DebugProbeError> {
         // The byte count always needs to be polled first, otherwise
         // the ST-Link won't return any data.
-        let mut buf = vec![0; self.read_swo_available_byte_count()?];
+        let available = self.read_swo_available_byte_count()?;
+
+        // A full buffer means the ST-Link had to drop trace data.
+        if available >= SWO_BUFFER_SIZE as usize {
+            log::warn!("SWO buffer of the ST-Link overflowed, trace data was lost.");
+        }
+
+        let mut buf = vec![0; available];
         let bytes_read = self.device.read_swo(&mut buf, timeout)?;
         buf.truncate(bytes_read);
         Ok(buf)
@@ -1073,8 +1136,22 @@ impl<D: StLinkUsb> SwoAccess for STLink<D> {
     }
 
     fn read_swo_timeout(&mut self, timeout: Duration) -> Result<Vec<u8>, ProbeRsError> {
-        let data = self.read_swo_data(timeout)?;
-        Ok(data)
+        let end = Instant::now() + timeout;
+
+        loop {
+            let data = self.read_swo_data(timeout)?;
+
+            let now = Instant::now();
+            if !data.is_empty() || now >= end {
+                return Ok(data);
+            }
+
+            std::thread::sleep(SWO_POLL_INTERVAL.min(end - now));
+        }
+    }
+
+    fn swo_buffer_size(&mut self) -> Option<usize> {
+        Some(SWO_BUFFER_SIZE as usize)
     }
 }
 
@@ -1096,6 +1173,10 @@ pub(crate) enum StlinkError {
     JTAGNotSupportedOnProbe,
     #[error("Manchester-coded SWO mode not supported")]
     ManchesterSwoNotSupported,
+    #[error("SWO is not supported by this probe")]
+    SwoNotSupported,
+    #[error("SWO baud rate of {requested} Bd not supported, the maximum is {max} Bd")]
+    SwoBaudRateNotSupported { requested: u32, max: u32 },
     #[error("Unaligned")]
     UnalignedAddress,
 }
@@ -1122,19 +1203,24 @@ impl StlinkArmDebug {
     fn new(probe: Box<STLink<STLinkUSBDevice>>) -> Result<Self, DebugProbeError> {
         let state = ArmCommunicationInterfaceState::new();
 
-        // Determine the number and type of available APs.
-
         let mut interface = Self { probe, state };
 
-        for ap in valid_access_ports(&mut interface) {
-            let ap_state = interface.read_ap_information(ap)?;
+        interface.read_all_ap_information()?;
+
+        Ok(interface)
+    }
+
+    /// Determines the number and type of available APs.
+    fn read_all_ap_information(&mut self) -> Result<(), DebugProbeError> {
+        for ap in valid_access_ports(self) {
+            let ap_state = self.read_ap_information(ap)?;
 
             log::debug!("AP {}: {:?}", ap.port_number(), ap_state);
 
-            interface.state.ap_information.push(ap_state);
+            self.state.ap_information.push(ap_state);
         }
 
-        Ok(interface)
+        Ok(())
     }
 
     /// Read information about an AP from its registers.
@@ -1184,6 +1270,9 @@ impl StlinkArmDebug {
             Ok(ApInformation::MemoryAp(MemoryApInformation {
                 port_number: access_port.port_number(),
                 only_32bit_data_size,
+                // The ST-Link only offers 8 and 32 bit memory accesses.
+                supports_16bit_data_size: false,
+                supports_64bit_data_size: false,
                 debug_base_address: base_address,
                 supports_hnonsec: false,
             }))
@@ -1317,6 +1406,8 @@ impl<'probe> ArmProbeInterface for StlinkArmDebug {
     fn read_from_rom_table(
         &mut self,
     ) -> Result<Option<crate::architecture::arm::ArmChipInfo>, ProbeRsError> {
+        let (dpidr, target_id) = read_debug_port_ids(self);
+
         for access_port in valid_access_ports(self) {
             let idr = self
                 .read_ap_register(access_port, IDR::default())
@@ -1340,19 +1431,74 @@ impl<'probe> ArmProbeInterface for StlinkArmDebug {
                         return Ok(Some(ArmChipInfo {
                             manufacturer: jep106,
                             part: component_id.peripheral_id().part(),
+                            dpidr,
+                            target_id,
                         }));
                     }
                 }
             }
         }
 
-        Ok(None)
+        Ok(ArmChipInfo::from_debug_port(dpidr, target_id))
     }
 
     fn num_access_ports(&self) -> usize {
         self.state.ap_information.len()
     }
 
+    fn read_raw_ap_register(&mut self, port: u8, address: u8) -> Result<u32, DebugProbeError> {
+        self.select_ap_and_ap_bank(port, address >> 4)?;
+
+        self.probe
+            .read_register(PortType::AccessPort(u16::from(port)), u16::from(address))
+    }
+
+    fn write_raw_ap_register(
+        &mut self,
+        port: u8,
+        address: u8,
+        value: u32,
+    ) -> Result<(), DebugProbeError> {
+        self.select_ap_and_ap_bank(port, address >> 4)?;
+
+        self.probe.write_register(
+            PortType::AccessPort(u16::from(port)),
+            u16::from(address),
+            value,
+        )
+    }
+
+    fn read_raw_dp_register(&mut self, address: u8) -> Result<u32, DebugProbeError> {
+        self.probe
+            .read_register(PortType::DebugPort, u16::from(address))
+    }
+
+    fn write_raw_dp_register(&mut self, address: u8, value: u32) -> Result<(), DebugProbeError> {
+        self.probe
+            .write_register(PortType::DebugPort, u16::from(address), value)?;
+
+        // Keep the cached selection in sync with the SELECT register.
+        if address == Select::ADDRESS {
+            self.state.update_selection(Select(value));
+        }
+
+        Ok(())
+    }
+
+    fn set_fault_policy(&mut self, policy: FaultPolicy) {
+        // The ST-Link firmware handles FAULT responses itself, and reports them as errors.
+        log::debug!("The ST-Link does not support the fault policy {:?}", policy);
+    }
+
+    fn reinitialize(&mut self) -> Result<(), ProbeRsError> {
+        self.probe.attach()?;
+
+        self.state = ArmCommunicationInterfaceState::new();
+        self.read_all_ap_information()?;
+
+        Ok(())
+    }
+
     fn close(self: Box<Self>) -> Probe {
         Probe::from_attached_probe(self.probe)
     }
@@ -1481,6 +1627,10 @@ impl SwoAccess for StlinkArmDebug {
     fn read_swo_timeout(&mut self, timeout: Duration) -> Result<Vec<u8>, ProbeRsError> {
         self.probe.read_swo_timeout(timeout)
     }
+
+    fn swo_buffer_size(&mut self) -> Option<usize> {
+        self.probe.swo_buffer_size()
+    }
 }
 
 #[derive(Debug)]
@@ -1640,12 +1790,72 @@ impl ArmProbe for StLinkMemoryInterface<'_> {
         Ok(())
     }
 
+    fn supports_access_size(&self, size: AccessSize) -> bool {
+        // Reads of a single byte also read the following byte, and the 16 bit commands
+        // are not used, so only 32 bit accesses are guaranteed to have the requested size.
+        size == AccessSize::U32
+    }
+
+    fn read_with_size(
+        &mut self,
+        ap: MemoryAP,
+        address: u32,
+        data: &mut [u8],
+        size: AccessSize,
+    ) -> Result<(), ProbeRsError> {
+        if !self.supports_access_size(size) {
+            return Err(ProbeRsError::AccessSizeNotSupported(size));
+        }
+
+        if !size.is_aligned(address, data.len()) {
+            return Err(DebugProbeError::from(StlinkError::UnalignedAddress).into());
+        }
+
+        let mut words = vec![0u32; data.len() / 4];
+        self.read_32(ap, address, &mut words)?;
+
+        for (word, bytes) in words.iter().zip(data.chunks_exact_mut(4)) {
+            bytes.copy_from_slice(&word.to_le_bytes());
+        }
+
+        Ok(())
+    }
+
+    fn write_with_size(
+        &mut self,
+        ap: MemoryAP,
+        address: u32,
+        data: &[u8],
+        size: AccessSize,
+    ) -> Result<(), ProbeRsError> {
+        if !self.supports_access_size(size) {
+            return Err(ProbeRsError::AccessSizeNotSupported(size));
+        }
+
+        if !size.is_aligned(address, data.len()) {
+            return Err(DebugProbeError::from(StlinkError::UnalignedAddress).into());
+        }
+
+        let words: Vec<u32> = data
+            .chunks_exact(4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect();
+
+        self.write_32(ap, address, &words)
+    }
+
     fn flush(&mut self) -> Result<(), ProbeRsError> {
         self.probe.probe.flush()?;
 
         Ok(())
     }
 
+    fn target_reset(&mut self) -> Result<(), ProbeRsError> {
+        self.probe.probe.target_reset()?;
+
+        Ok(())
+    }
+
     fn read_core_reg(
         &mut self,
         _ap: MemoryAP,
@@ -1676,7 +1886,10 @@ impl ArmProbe for StLinkMemoryInterface<'_> {
 mod test {
 
     use super::{constants::commands, usb_interface::StLinkUsb, STLink};
-    use crate::{DebugProbeError, WireProtocol};
+    use crate::{
+        architecture::arm::{SwoAccess, SwoConfig},
+        DebugProbeError, WireProtocol,
+    };
 
     use scroll::Pwrite;
 
@@ -1685,6 +1898,7 @@ mod test {
         hw_version: u8,
         jtag_version: u8,
         swim_version: u8,
+        has_swo: bool,
 
         target_voltage_a0: f32,
         target_voltage_a1: f32,
@@ -1757,6 +1971,10 @@ mod test {
         ) -> Result<usize, DebugProbeError> {
             unimplemented!("Not implemented for MockUSB")
         }
+
+        fn has_swo(&self) -> bool {
+            self.has_swo
+        }
     }
 
     #[test]
@@ -1767,6 +1985,7 @@ mod test {
             hw_version: 2,
             jtag_version: 20,
             swim_version: 0,
+            has_swo: true,
 
             target_voltage_a0: 1.0,
             target_voltage_a1: 2.0,
@@ -1791,6 +2010,7 @@ mod test {
             hw_version: 2,
             jtag_version: 26,
             swim_version: 0,
+            has_swo: true,
             target_voltage_a0: 1.0,
             target_voltage_a1: 2.0,
         };
@@ -1816,6 +2036,7 @@ mod test {
             hw_version: 2,
             jtag_version: 30,
             swim_version: 0,
+            has_swo: true,
             target_voltage_a0: 1.0,
             target_voltage_a1: 2.0,
         };
@@ -1831,4 +2052,49 @@ mod test {
             .select_ap(1)
             .expect("Selecting AP other than AP 0 should work");
     }
+
+    #[test]
+    fn swo_not_supported_without_endpoint() {
+        let usb_mock = MockUsb {
+            hw_version: 2,
+            jtag_version: 30,
+            swim_version: 0,
+            has_swo: false,
+            target_voltage_a0: 1.0,
+            target_voltage_a1: 2.0,
+        };
+
+        let mut probe = usb_mock.build();
+
+        probe.init().expect("Init function failed");
+
+        assert!(probe.enable_swo(&SwoConfig::new(16_000_000)).is_err());
+    }
+
+    #[test]
+    fn swo_baud_rate_limited_by_hardware() {
+        let usb_mock = MockUsb {
+            hw_version: 2,
+            jtag_version: 30,
+            swim_version: 0,
+            has_swo: true,
+            target_voltage_a0: 1.0,
+            target_voltage_a1: 2.0,
+        };
+
+        let mut probe = usb_mock.build();
+
+        probe.init().expect("Init function failed");
+
+        probe
+            .enable_swo(&SwoConfig::new(72_000_000).set_baud(4_000_000))
+            .expect_err("4 MBd should be too fast for an ST-Link V2");
+        assert!(!probe.swo_enabled);
+
+        probe
+            .enable_swo(&SwoConfig::new(72_000_000).set_baud(2_000_000))
+            .expect("Enabling SWO at 2 MBd failed");
+        assert!(probe.swo_enabled);
+        assert_eq!(probe.swo_buffer_size(), Some(4096));
+    }
 }
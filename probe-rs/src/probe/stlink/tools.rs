@@ -1,7 +1,10 @@
 use rusb::Device;
 use rusb::UsbContext;
 
-use crate::probe::{DebugProbeInfo, DebugProbeType};
+use crate::probe::{
+    usb_port_path, DebugProbe, DebugProbeError, DebugProbeInfo, DebugProbeSelector, DebugProbeType,
+    ProbeFactory,
+};
 
 use super::usb_interface::USB_PID_EP_MAP;
 use super::usb_interface::USB_VID;
@@ -17,6 +20,20 @@ pub(super) fn is_stlink_device<T: UsbContext>(device: &Device<T>) -> bool {
     }
 }
 
+/// Finds and opens ST-Link probes.
+#[derive(Debug)]
+pub(crate) struct STLinkFactory;
+
+impl ProbeFactory for STLinkFactory {
+    fn list_probes(&self) -> Vec<DebugProbeInfo> {
+        list_stlink_devices()
+    }
+
+    fn open(&self, selector: &DebugProbeSelector) -> Result<Box<dyn DebugProbe>, DebugProbeError> {
+        Ok(super::STLink::new_from_selector(selector.clone())?)
+    }
+}
+
 pub fn list_stlink_devices() -> Vec<DebugProbeInfo> {
     if let Ok(context) = rusb::Context::new() {
         if let Ok(devices) = context.devices() {
@@ -43,16 +60,19 @@ pub fn list_stlink_devices() -> Vec<DebugProbeInfo> {
                         }
                     };
 
-                    Some(DebugProbeInfo::new(
-                        format!(
-                            "STLink {}",
-                            &USB_PID_EP_MAP[&descriptor.product_id()].version_name
-                        ),
-                        descriptor.vendor_id(),
-                        descriptor.product_id(),
-                        sn_str,
-                        DebugProbeType::STLink,
-                    ))
+                    Some(
+                        DebugProbeInfo::new(
+                            format!(
+                                "STLink {}",
+                                &USB_PID_EP_MAP[&descriptor.product_id()].version_name
+                            ),
+                            descriptor.vendor_id(),
+                            descriptor.product_id(),
+                            sn_str,
+                            DebugProbeType::STLink,
+                        )
+                        .with_port_path(usb_port_path(&device)),
+                    )
                 })
                 .collect::<Vec<_>>()
         } else {
@@ -67,6 +67,8 @@ impl STLinkInfo {
 pub(crate) struct STLinkUSBDevice {
     device_handle: DeviceHandle<rusb::Context>,
     info: STLinkInfo,
+    /// Whether the probe has an endpoint for SWO data.
+    has_swo: bool,
 }
 
 impl std::fmt::Debug for STLinkUSBDevice {
@@ -74,6 +76,7 @@ impl std::fmt::Debug for STLinkUSBDevice {
         fmt.debug_struct("STLinkUSBDevice")
             .field("device_handle", &"DeviceHandle<rusb::Context>")
             .field("info", &self.info)
+            .field("has_swo", &self.has_swo)
             .finish()
     }
 }
@@ -96,6 +99,11 @@ pub trait StLinkUsb: std::fmt::Debug {
         read_data: &mut [u8],
         timeout: Duration,
     ) -> Result<usize, DebugProbeError>;
+
+    /// Returns `true` if the probe has an endpoint for SWO data.
+    ///
+    /// Some ST-Link V2 variants are built without the SWO pin.
+    fn has_swo(&self) -> bool;
 }
 
 impl STLinkUSBDevice {
@@ -118,6 +126,7 @@ impl STLinkUSBDevice {
                 // First match the VID & PID.
                 if selector.vendor_id == descriptor.vendor_id()
                     && selector.product_id == descriptor.product_id()
+                    && selector.matches_port_path(&device)
                 {
                     // If the VID & PID match, match the serial if one was given.
                     if let Some(serial) = &selector.serial_number {
@@ -182,12 +191,13 @@ impl STLinkUSBDevice {
         }
 
         if !endpoint_swo {
-            return Err(StlinkError::EndpointNotFound.into());
+            log::debug!("SWO endpoint not found, SWO is not available.");
         }
 
         let usb_stlink = Self {
             device_handle,
             info,
+            has_swo: endpoint_swo,
         };
 
         log::debug!("Succesfully attached to STLink.");
@@ -316,6 +326,10 @@ impl StLinkUsb for STLinkUSBDevice {
 
     /// Reset the USB device. This can be used to recover when the
     /// STLink does not respond to USB requests.
+    fn has_swo(&self) -> bool {
+        self.has_swo
+    }
+
     fn reset(&mut self) -> Result<(), DebugProbeError> {
         log::debug!("Resetting USB device of STLink");
         self.device_handle
@@ -0,0 +1,116 @@
+//! Attaching to targets which spend most of their time in a low power mode.
+//!
+//! Many chips power down their debug port in deep sleep modes, e.g. a STM32 in STOP or
+//! STANDBY, so the probe can only connect in the short moments in which the firmware is awake.
+//! [wait_for_wakeup] tries to power up the debug port at a low rate until this succeeds.
+//! The session then keeps the debug port powered with the
+//! [LowPowerDebug](crate::config::LowPowerDebug) bits of the target, and halts the core
+//! before it can go back to sleep.
+
+use super::{speed, DebugProbe, DebugProbeError};
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Attaches the probe as soon as the debug port of the target can be powered up,
+/// and returns how long it took.
+///
+/// The attach is tried every `poll_interval`, until `timeout` is reached. Probes without raw
+/// DAP access are attached without powering up the debug port, their attach fails while the
+/// target is not reachable.
+pub(crate) fn wait_for_wakeup(
+    probe: &mut dyn DebugProbe,
+    timeout: Duration,
+    poll_interval: Duration,
+) -> Result<Duration, DebugProbeError> {
+    let start = Instant::now();
+    let mut attempts = 0;
+
+    loop {
+        attempts += 1;
+
+        let error = match try_attach(probe) {
+            Ok(()) => {
+                let waited = start.elapsed();
+                log::info!(
+                    "The target woke up after {:?}, at attempt {}.",
+                    waited,
+                    attempts
+                );
+                return Ok(waited);
+            }
+            Err(error) => error,
+        };
+        log::trace!("Attempt {} to reach the target failed: {}", attempts, error);
+
+        if start.elapsed() + poll_interval > timeout {
+            return Err(DebugProbeError::WakeupTimeout { timeout, attempts });
+        }
+        thread::sleep(poll_interval);
+    }
+}
+
+/// Attaches the probe and powers up the debug port, or leaves the probe detached.
+fn try_attach(probe: &mut dyn DebugProbe) -> Result<(), DebugProbeError> {
+    probe.attach()?;
+
+    if probe.get_dap_interface_mut().is_none() {
+        return Ok(());
+    }
+
+    speed::power_up(probe).map_err(|error| {
+        if let Err(detach_error) = probe.detach() {
+            log::trace!("Detaching failed: {}", detach_error);
+        }
+        error
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::wait_for_wakeup;
+    use crate::probe::{DebugProbeError, FakeAck, FakeDap};
+
+    use std::time::Duration;
+
+    /// Returns a target whose debug port only answers from the `awake_at`th attach on.
+    fn sleepy_target(awake_at: usize) -> FakeDap {
+        FakeDap::new("Sleepy target").with_target(move |probe, _, _, _| {
+            if probe.attaches < awake_at {
+                Some(Err(FakeAck::NoAck.into()))
+            } else {
+                None
+            }
+        })
+    }
+
+    #[test]
+    fn attach_is_repeated_until_the_target_wakes_up() {
+        let mut target = sleepy_target(4);
+
+        wait_for_wakeup(
+            &mut target,
+            Duration::from_secs(10),
+            Duration::from_millis(1),
+        )
+        .unwrap();
+        assert_eq!(target.attaches, 4);
+    }
+
+    #[test]
+    fn waiting_for_the_wakeup_times_out() {
+        // The next attempt would be after the timeout.
+        let mut target = sleepy_target(usize::MAX);
+
+        let error = wait_for_wakeup(
+            &mut target,
+            Duration::from_millis(10),
+            Duration::from_millis(50),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            error,
+            DebugProbeError::WakeupTimeout { attempts: 1, .. }
+        ));
+    }
+}
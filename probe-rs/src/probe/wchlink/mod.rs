@@ -0,0 +1,681 @@
+//! Support for the WCH-Link probes, which debug the RISC-V chips of WCH.
+//!
+//! The protocol was reverse engineered by the wlink project. In RV mode the probe
+//! gives access to the debug module of the target with DMI operations, these are
+//! presented to the RISC-V code as accesses to the DTM registers of a JTAG TAP.
+//! In DAP mode the probe is a CMSIS-DAP probe, which only supports ARM targets.
+
+use crate::{
+    architecture::riscv::communication_interface::RiscvCommunicationInterface,
+    probe::{
+        usb_port_path, DebugProbe, DebugProbeError, DebugProbeInfo, DebugProbeSelector,
+        DebugProbeType, JTAGAccess, PowerCapabilities, ProbeCreationError, ProbeFactory,
+        WireProtocol,
+    },
+};
+use rusb::UsbContext;
+use std::fmt;
+use std::time::Duration;
+use thiserror::Error;
+
+const VENDOR_ID: u16 = 0x1a86;
+/// The product ID of a probe in RV mode.
+const PRODUCT_ID_RV: u16 = 0x8010;
+/// The product ID of a probe in DAP mode.
+const PRODUCT_ID_DAP: u16 = 0x8012;
+
+const ENDPOINT_OUT: u8 = 0x01;
+const ENDPOINT_IN: u8 = 0x81;
+
+/// How long to wait for a response of the probe.
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The start of a command packet.
+const COMMAND_START: u8 = 0x81;
+/// The start of a response packet.
+const RESPONSE_START: u8 = 0x82;
+/// The start of a response packet which reports an error.
+const ERROR_START: u8 = 0x81;
+
+const COMMAND_FLASH_PROTECT: u8 = 0x06;
+const COMMAND_DMI_OP: u8 = 0x08;
+const COMMAND_RESET: u8 = 0x0b;
+const COMMAND_SET_SPEED: u8 = 0x0c;
+const COMMAND_CONTROL: u8 = 0x0d;
+
+const CONTROL_PROBE_INFO: u8 = 0x01;
+const CONTROL_ATTACH_CHIP: u8 = 0x02;
+const CONTROL_POWER_3V3_ON: u8 = 0x09;
+const CONTROL_POWER_3V3_OFF: u8 = 0x0a;
+const CONTROL_DETACH_CHIP: u8 = 0xff;
+
+const FLASH_PROTECT_QUERY: u8 = 0x01;
+const FLASH_PROTECT_DISABLE: u8 = 0x02;
+/// The response to a query when the flash is read protected.
+const FLASH_PROTECTED: u8 = 0x01;
+
+const RESET_QUIT: u8 = 0x01;
+
+/// The speed settings of the probe, with their approximate clock in kHz.
+const SPEEDS: [(u8, u32); 3] = [(0x01, 6000), (0x02, 4000), (0x03, 400)];
+
+/// The address of the `dtmcs` register of the emulated DTM.
+const DTMCS_ADDRESS: u32 = 0x10;
+/// The address of the `dmi` register of the emulated DTM.
+const DMI_ADDRESS: u32 = 0x11;
+
+/// The `dtmcs` value of the emulated DTM: version 0.13, 7 address bits and no idle cycles.
+const DTMCS_VALUE: u32 = 0x71;
+const DTMCS_DMIRESET: u32 = 1 << 16;
+const DTMCS_DMIHARDRESET: u32 = 1 << 17;
+
+const DMI_OP_NOP: u8 = 0;
+const DMI_STATUS_BUSY: u8 = 3;
+
+/// How often a DMI operation is repeated while the debug module is busy.
+const DMI_BUSY_RETRIES: usize = 10;
+
+#[derive(Debug, Error)]
+pub enum WchLinkError {
+    #[error("The probe returned the error {code:#04x} for the command {command:#04x}")]
+    ErrorResponse { command: u8, code: u8 },
+    #[error("The probe returned the invalid response {0:02x?}")]
+    InvalidResponse(Vec<u8>),
+    #[error("The probe is in DAP mode, it has to be switched to RV mode to debug RISC-V chips")]
+    DapMode,
+    #[error("No chip is connected to the probe")]
+    NoChip,
+    #[error("Error communicating with the probe")]
+    Usb(#[from] rusb::Error),
+}
+
+impl From<WchLinkError> for DebugProbeError {
+    fn from(error: WchLinkError) -> Self {
+        DebugProbeError::ProbeSpecific(Box::new(error))
+    }
+}
+
+/// The USB connection to the probe.
+trait Connection: Send {
+    fn write(&mut self, data: &[u8]) -> Result<(), rusb::Error>;
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, rusb::Error>;
+}
+
+struct UsbConnection {
+    handle: rusb::DeviceHandle<rusb::Context>,
+}
+
+impl Connection for UsbConnection {
+    fn write(&mut self, data: &[u8]) -> Result<(), rusb::Error> {
+        self.handle.write_bulk(ENDPOINT_OUT, data, TIMEOUT)?;
+        Ok(())
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, rusb::Error> {
+        self.handle.read_bulk(ENDPOINT_IN, buffer, TIMEOUT)
+    }
+}
+
+/// The result of the last DMI operation, which is returned by the next scan of the `dmi` register.
+#[derive(Debug, Default, Clone, Copy)]
+struct DmiResult {
+    address: u8,
+    data: u32,
+}
+
+pub(crate) struct WchLink {
+    connection: Box<dyn Connection>,
+
+    /// The firmware version the probe reported.
+    version: (u8, u8),
+
+    /// The speed setting which is sent to the probe when attaching.
+    speed: (u8, u32),
+
+    /// The chip family the probe detected when attaching.
+    chip_family: Option<u8>,
+
+    last_dmi: DmiResult,
+
+    /// The sticky status of the emulated DTM, which is cleared with `dmireset`.
+    dmi_status: u8,
+}
+
+impl fmt::Debug for WchLink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WchLink")
+            .field("version", &self.version)
+            .field("chip_family", &self.chip_family)
+            .finish()
+    }
+}
+
+impl WchLink {
+    fn new(connection: Box<dyn Connection>) -> Result<Self, WchLinkError> {
+        let mut probe = Self {
+            connection,
+            version: (0, 0),
+            speed: SPEEDS[0],
+            chip_family: None,
+            last_dmi: DmiResult::default(),
+            dmi_status: 0,
+        };
+
+        let info = probe.command(COMMAND_CONTROL, &[CONTROL_PROBE_INFO])?;
+        if info.len() < 2 {
+            return Err(WchLinkError::InvalidResponse(info));
+        }
+        probe.version = (info[0], info[1]);
+        log::info!("WCH-Link: Firmware version {}.{}", info[0], info[1]);
+
+        Ok(probe)
+    }
+
+    /// Sends `command` with the `payload` and returns the payload of the response.
+    fn command(&mut self, command: u8, payload: &[u8]) -> Result<Vec<u8>, WchLinkError> {
+        let mut packet = vec![COMMAND_START, command, payload.len() as u8];
+        packet.extend_from_slice(payload);
+
+        log::trace!("Sending {:02x?}", packet);
+        self.connection.write(&packet)?;
+
+        let mut response = vec![0; 64];
+        let length = self.connection.read(&mut response)?;
+        response.truncate(length);
+        log::trace!("Received {:02x?}", response);
+
+        if response.len() < 3 || response.len() < 3 + response[2] as usize {
+            return Err(WchLinkError::InvalidResponse(response));
+        }
+
+        match response[0] {
+            RESPONSE_START if response[1] == command => {
+                let length = response[2] as usize;
+                Ok(response[3..3 + length].to_vec())
+            }
+            ERROR_START => Err(WchLinkError::ErrorResponse {
+                command,
+                code: response[1],
+            }),
+            _ => Err(WchLinkError::InvalidResponse(response)),
+        }
+    }
+
+    /// Performs a DMI operation and returns the data and the status of the operation.
+    fn dmi_op(&mut self, address: u8, data: u32, op: u8) -> Result<(u32, u8), WchLinkError> {
+        let mut payload = vec![address];
+        payload.extend_from_slice(&data.to_be_bytes());
+        payload.push(op);
+
+        for _ in 0..DMI_BUSY_RETRIES {
+            let response = self.command(COMMAND_DMI_OP, &payload)?;
+            if response.len() != 6 {
+                return Err(WchLinkError::InvalidResponse(response));
+            }
+
+            let data = u32::from_be_bytes([response[1], response[2], response[3], response[4]]);
+            let status = response[5];
+
+            if status != DMI_STATUS_BUSY {
+                return Ok((data, status));
+            }
+        }
+
+        Ok((0, DMI_STATUS_BUSY))
+    }
+
+    /// Returns true if the flash of the attached chip is read protected.
+    fn flash_protected(&mut self) -> Result<bool, WchLinkError> {
+        let response = self.command(COMMAND_FLASH_PROTECT, &[FLASH_PROTECT_QUERY])?;
+
+        match response.first() {
+            Some(&status) => Ok(status == FLASH_PROTECTED),
+            None => Err(WchLinkError::InvalidResponse(response)),
+        }
+    }
+}
+
+impl DebugProbe for WchLink {
+    fn new_from_selector(
+        selector: impl Into<DebugProbeSelector>,
+    ) -> Result<Box<Self>, DebugProbeError>
+    where
+        Self: Sized,
+    {
+        let selector = selector.into();
+
+        if selector.vendor_id != VENDOR_ID {
+            return Err(DebugProbeError::ProbeCouldNotBeCreated(
+                ProbeCreationError::NotFound,
+            ));
+        }
+
+        // Probes in DAP mode are handled by the CMSIS-DAP driver, if it did not open
+        // the probe, the user most likely wants to debug a RISC-V chip.
+        if selector.product_id == PRODUCT_ID_DAP {
+            return Err(DebugProbeError::ProbeCouldNotBeCreated(
+                ProbeCreationError::ProbeSpecific(Box::new(WchLinkError::DapMode)),
+            ));
+        }
+
+        if selector.product_id != PRODUCT_ID_RV {
+            return Err(DebugProbeError::ProbeCouldNotBeCreated(
+                ProbeCreationError::NotFound,
+            ));
+        }
+
+        let context = rusb::Context::new().map_err(ProbeCreationError::from)?;
+        let devices = context.devices().map_err(ProbeCreationError::from)?;
+
+        let device = devices
+            .iter()
+            .find(|device| {
+                is_wchlink_device(device, PRODUCT_ID_RV)
+                    && selector.matches_port_path(device)
+                    && (selector.serial_number.is_none()
+                        || read_serial_number(device) == selector.serial_number)
+            })
+            .ok_or(DebugProbeError::ProbeCouldNotBeCreated(
+                ProbeCreationError::NotFound,
+            ))?;
+
+        let mut handle = device.open().map_err(ProbeCreationError::from)?;
+        handle
+            .claim_interface(0)
+            .map_err(ProbeCreationError::from)?;
+
+        log::debug!("Opened the WCH-Link");
+
+        Ok(Box::new(Self::new(Box::new(UsbConnection { handle }))?))
+    }
+
+    fn get_name(&self) -> &str {
+        "WCH-Link"
+    }
+
+    fn speed(&self) -> u32 {
+        self.speed.1
+    }
+
+    /// Selects the fastest speed setting of the probe which does not exceed `speed_khz`.
+    fn set_speed(&mut self, speed_khz: u32) -> Result<u32, DebugProbeError> {
+        let speed = SPEEDS
+            .iter()
+            .find(|(_, khz)| *khz <= speed_khz)
+            .ok_or(DebugProbeError::UnsupportedSpeed(speed_khz))?;
+
+        self.speed = *speed;
+
+        Ok(speed.1)
+    }
+
+    fn attach(&mut self) -> Result<(), DebugProbeError> {
+        let response = self.command(COMMAND_CONTROL, &[CONTROL_ATTACH_CHIP])?;
+        if response.len() < 5 {
+            return Err(WchLinkError::NoChip.into());
+        }
+
+        let family = response[0];
+        let chip_id = u32::from_be_bytes([response[1], response[2], response[3], response[4]]);
+        log::info!(
+            "WCH-Link: Attached to chip family {:#04x}, chip id {:#010x}",
+            family,
+            chip_id
+        );
+        self.chip_family = Some(family);
+
+        self.command(COMMAND_SET_SPEED, &[family, self.speed.0])?;
+
+        if self.flash_protected()? {
+            log::warn!(
+                "The flash of the chip is read protected. Use 'erase --allow-erase-all' to remove the protection, which erases the flash."
+            );
+        }
+
+        Ok(())
+    }
+
+    fn detach(&mut self) -> Result<(), DebugProbeError> {
+        self.command(COMMAND_CONTROL, &[CONTROL_DETACH_CHIP])?;
+        self.chip_family = None;
+        Ok(())
+    }
+
+    fn target_reset(&mut self) -> Result<(), DebugProbeError> {
+        self.command(COMMAND_RESET, &[RESET_QUIT])?;
+        Ok(())
+    }
+
+    /// The probe has no reset pin.
+    fn target_reset_assert(&mut self) -> Result<(), DebugProbeError> {
+        Err(DebugProbeError::CommandNotSupportedByProbe)
+    }
+
+    fn target_reset_deassert(&mut self) -> Result<(), DebugProbeError> {
+        Err(DebugProbeError::CommandNotSupportedByProbe)
+    }
+
+    /// The probe speaks its own protocol to the chip, which is presented as JTAG.
+    fn select_protocol(&mut self, protocol: WireProtocol) -> Result<(), DebugProbeError> {
+        match protocol {
+            WireProtocol::Jtag => Ok(()),
+            _ => Err(DebugProbeError::UnsupportedProtocol(protocol)),
+        }
+    }
+
+    fn power_capabilities(&self) -> PowerCapabilities {
+        PowerCapabilities {
+            target_power: true,
+            ..Default::default()
+        }
+    }
+
+    fn set_target_power(&mut self, enabled: bool) -> Result<(), DebugProbeError> {
+        let control = if enabled {
+            CONTROL_POWER_3V3_ON
+        } else {
+            CONTROL_POWER_3V3_OFF
+        };
+
+        self.command(COMMAND_CONTROL, &[control])?;
+        Ok(())
+    }
+
+    fn disable_flash_protection(&mut self) -> Result<(), DebugProbeError> {
+        if !self.flash_protected()? {
+            log::info!("The flash of the chip is not protected");
+            return Ok(());
+        }
+
+        self.command(COMMAND_FLASH_PROTECT, &[FLASH_PROTECT_DISABLE])?;
+
+        // The protection is only removed after a reset of the chip.
+        self.target_reset()
+    }
+
+    fn has_riscv_interface(&self) -> bool {
+        true
+    }
+
+    fn get_riscv_interface(
+        self: Box<Self>,
+    ) -> Result<Option<RiscvCommunicationInterface>, DebugProbeError> {
+        Ok(Some(RiscvCommunicationInterface::new(self)?))
+    }
+
+    fn get_jtag_interface_mut(&mut self) -> Option<&mut dyn JTAGAccess> {
+        Some(self as _)
+    }
+}
+
+impl JTAGAccess for WchLink {
+    fn read_register(&mut self, address: u32, len: u32) -> Result<Vec<u8>, DebugProbeError> {
+        match address {
+            DTMCS_ADDRESS => Ok(DTMCS_VALUE.to_le_bytes()[..scan_bytes(len).min(4)].to_vec()),
+            _ => Err(DebugProbeError::NotImplemented(
+                "The WCH-Link only gives access to the DTM registers",
+            )),
+        }
+    }
+
+    /// The probe performs idle cycles on its own.
+    fn set_idle_cycles(&mut self, _idle_cycles: u8) {}
+
+    fn write_register(
+        &mut self,
+        address: u32,
+        data: &[u8],
+        len: u32,
+    ) -> Result<Vec<u8>, DebugProbeError> {
+        match address {
+            DTMCS_ADDRESS => {
+                let value = data
+                    .iter()
+                    .take(4)
+                    .enumerate()
+                    .fold(0u32, |acc, (i, byte)| acc | (*byte as u32) << (8 * i));
+
+                if value & (DTMCS_DMIRESET | DTMCS_DMIHARDRESET) != 0 {
+                    self.dmi_status = 0;
+                }
+
+                JTAGAccess::read_register(self, address, len)
+            }
+            DMI_ADDRESS => {
+                let value = data
+                    .iter()
+                    .take(16)
+                    .enumerate()
+                    .fold(0u128, |acc, (i, byte)| acc | (*byte as u128) << (8 * i));
+
+                let op = (value & 0x3) as u8;
+                let dmi_data = (value >> 2) as u32;
+                let dmi_address = (value >> 34) as u8;
+
+                // The scan returns the result of the previous operation.
+                let captured = ((self.last_dmi.address as u128) << 34)
+                    | ((self.last_dmi.data as u128) << 2)
+                    | self.dmi_status as u128;
+
+                // Like a real DTM, operations are ignored until a sticky error is cleared.
+                if op != DMI_OP_NOP && self.dmi_status == 0 {
+                    let (data, status) = self.dmi_op(dmi_address, dmi_data, op)?;
+
+                    self.last_dmi = DmiResult {
+                        address: dmi_address,
+                        data,
+                    };
+                    self.dmi_status = status;
+                }
+
+                Ok(captured.to_le_bytes()[..scan_bytes(len).min(16)].to_vec())
+            }
+            _ => Err(DebugProbeError::NotImplemented(
+                "The WCH-Link only gives access to the DTM registers",
+            )),
+        }
+    }
+
+    fn into_probe(self: Box<Self>) -> Box<dyn DebugProbe> {
+        self
+    }
+}
+
+impl<'a> AsRef<dyn DebugProbe + 'a> for WchLink {
+    fn as_ref(&self) -> &(dyn DebugProbe + 'a) {
+        self
+    }
+}
+
+impl<'a> AsMut<dyn DebugProbe + 'a> for WchLink {
+    fn as_mut(&mut self) -> &mut (dyn DebugProbe + 'a) {
+        self
+    }
+}
+
+/// The number of bytes which hold a scan of `len` bits.
+fn scan_bytes(len: u32) -> usize {
+    (len as usize + 7) / 8
+}
+
+fn is_wchlink_device<T: UsbContext>(device: &rusb::Device<T>, product_id: u16) -> bool {
+    match device.device_descriptor() {
+        Ok(descriptor) => {
+            descriptor.vendor_id() == VENDOR_ID && descriptor.product_id() == product_id
+        }
+        Err(_) => false,
+    }
+}
+
+/// Reads the serial number of the probe, which fails if the driver is not installed.
+fn read_serial_number<T: UsbContext>(device: &rusb::Device<T>) -> Option<String> {
+    let timeout = Duration::from_millis(100);
+
+    let descriptor = device.device_descriptor().ok()?;
+    let handle = device.open().ok()?;
+    let language = *handle.read_languages(timeout).ok()?.first()?;
+
+    handle
+        .read_serial_number_string(language, &descriptor, timeout)
+        .ok()
+}
+
+/// Finds and opens WCH-Link probes.
+#[derive(Debug)]
+pub(crate) struct WchLinkFactory;
+
+impl ProbeFactory for WchLinkFactory {
+    fn list_probes(&self) -> Vec<DebugProbeInfo> {
+        list_wchlink_devices()
+    }
+
+    fn open(&self, selector: &DebugProbeSelector) -> Result<Box<dyn DebugProbe>, DebugProbeError> {
+        Ok(WchLink::new_from_selector(selector.clone())?)
+    }
+}
+
+pub(crate) fn list_wchlink_devices() -> Vec<DebugProbeInfo> {
+    let devices = match rusb::Context::new().and_then(|context| context.devices()) {
+        Ok(devices) => devices,
+        Err(_) => return vec![],
+    };
+
+    devices
+        .iter()
+        .filter_map(|device| {
+            if is_wchlink_device(&device, PRODUCT_ID_RV) {
+                Some(
+                    DebugProbeInfo::new(
+                        "WCH-Link",
+                        VENDOR_ID,
+                        PRODUCT_ID_RV,
+                        read_serial_number(&device),
+                        DebugProbeType::WchLink,
+                    )
+                    .with_port_path(usb_port_path(&device)),
+                )
+            } else if is_wchlink_device(&device, PRODUCT_ID_DAP) {
+                log::debug!("Found a WCH-Link in DAP mode, which only supports ARM targets");
+                None
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Connection, WchLink, DMI_ADDRESS, DTMCS_ADDRESS};
+    use crate::probe::JTAGAccess;
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+
+    /// A connection which replies with prepared responses, and records what was sent.
+    struct FakeConnection {
+        responses: VecDeque<Vec<u8>>,
+        sent: Arc<Mutex<Vec<Vec<u8>>>>,
+    }
+
+    impl Connection for FakeConnection {
+        fn write(&mut self, data: &[u8]) -> Result<(), rusb::Error> {
+            self.sent.lock().unwrap().push(data.to_vec());
+            Ok(())
+        }
+
+        fn read(&mut self, buffer: &mut [u8]) -> Result<usize, rusb::Error> {
+            let response = self.responses.pop_front().ok_or(rusb::Error::Timeout)?;
+            buffer[..response.len()].copy_from_slice(&response);
+            Ok(response.len())
+        }
+    }
+
+    /// Creates a probe which receives `responses` after the probe information.
+    fn probe(responses: Vec<Vec<u8>>) -> (WchLink, Arc<Mutex<Vec<Vec<u8>>>>) {
+        let sent = Arc::new(Mutex::new(vec![]));
+        let mut all_responses = VecDeque::from(vec![vec![0x82, 0x0d, 0x03, 0x02, 0x09, 0x01]]);
+        all_responses.extend(responses);
+
+        let connection = FakeConnection {
+            responses: all_responses,
+            sent: sent.clone(),
+        };
+
+        let probe = WchLink::new(Box::new(connection)).unwrap();
+        sent.lock().unwrap().clear();
+
+        (probe, sent)
+    }
+
+    /// Scans the `dmi` register with a 7 bit address.
+    fn dmi_scan(probe: &mut WchLink, address: u8, data: u32, op: u8) -> u128 {
+        let value = ((address as u128) << 34) | ((data as u128) << 2) | op as u128;
+        let response =
+            JTAGAccess::write_register(probe, DMI_ADDRESS, &value.to_le_bytes(), 41).unwrap();
+
+        assert_eq!(response.len(), 6);
+        response
+            .iter()
+            .enumerate()
+            .fold(0, |acc, (i, byte)| acc | (*byte as u128) << (8 * i))
+    }
+
+    #[test]
+    fn version_is_read() {
+        let (probe, _) = probe(vec![]);
+        assert_eq!(probe.version, (2, 9));
+    }
+
+    #[test]
+    fn dtmcs_reports_address_bits() {
+        let (mut probe, _) = probe(vec![]);
+
+        let dtmcs = JTAGAccess::read_register(&mut probe, DTMCS_ADDRESS, 32).unwrap();
+
+        assert_eq!(dtmcs, vec![0x71, 0, 0, 0]);
+    }
+
+    #[test]
+    fn dmi_read_is_returned_by_next_scan() {
+        let (mut probe, sent) = probe(vec![vec![
+            0x82, 0x08, 0x06, 0x11, 0x00, 0x03, 0x0c, 0xa2, 0x00,
+        ]]);
+
+        // Read dmstatus
+        dmi_scan(&mut probe, 0x11, 0, 1);
+        let response = dmi_scan(&mut probe, 0, 0, 0);
+
+        assert_eq!(response, (0x11 << 34) | (0x0003_0ca2 << 2));
+        assert_eq!(
+            *sent.lock().unwrap(),
+            vec![vec![0x81, 0x08, 0x06, 0x11, 0, 0, 0, 0, 0x01]]
+        );
+    }
+
+    #[test]
+    fn dmi_errors_are_sticky_until_reset() {
+        let (mut probe, sent) = probe(vec![
+            vec![0x82, 0x08, 0x06, 0x10, 0, 0, 0, 0, 0x02],
+            vec![0x82, 0x08, 0x06, 0x10, 0, 0, 0, 1, 0x00],
+        ]);
+
+        // The failed write is reported by the next scan, which is ignored.
+        dmi_scan(&mut probe, 0x10, 1, 2);
+        assert_eq!(dmi_scan(&mut probe, 0x10, 1, 2) & 0x3, 2);
+        assert_eq!(sent.lock().unwrap().len(), 1);
+
+        JTAGAccess::write_register(&mut probe, DTMCS_ADDRESS, &[0, 0, 1, 0], 32).unwrap();
+
+        dmi_scan(&mut probe, 0x10, 1, 2);
+        assert_eq!(dmi_scan(&mut probe, 0, 0, 0) & 0x3, 0);
+        assert_eq!(sent.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn error_responses_are_reported() {
+        let (mut probe, _) = probe(vec![vec![0x81, 0x55, 0x01, 0x00]]);
+
+        assert!(probe.command(0x0d, &[0x02]).is_err());
+    }
+}
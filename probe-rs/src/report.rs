@@ -0,0 +1,404 @@
+//! Machine readable records of the results of probe-rs operations.
+//!
+//! The probe-rs CLI prints these records as JSON lines when it is started with
+//! `--format json`, while the human readable logs are written to stderr.
+//! Tools which read this output, e.g. test harnesses, can deserialize the records
+//! with the same types.
+//!
+//! Every record has a `record` field with the name of its type, e.g.
+//! `{"record":"probe","identifier":"STLink V2-1",...}`.
+
+use crate::architecture::arm::memory::{ComponentClass, CoresightComponent};
+use crate::clock::ClockMeasurement;
+use crate::flashing::ProgressEvent;
+use crate::{DebugProbeInfo, Probe};
+
+use serde::{Deserialize, Serialize};
+
+/// A single line of machine readable output.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "record", rename_all = "snake_case")]
+pub enum Record {
+    /// A debug probe which is connected to the host.
+    Probe(ProbeRecord),
+    /// An access port of the debug port of an ARM chip.
+    AccessPort {
+        /// The number of the access port.
+        index: u8,
+        /// Whether the access port is a memory access port.
+        memory_ap: bool,
+        /// The address of the debug component table, for memory access ports.
+        debug_base_address: Option<u64>,
+    },
+    /// A CoreSight component which was found in the ROM table of an access port.
+    Component {
+        /// The number of the access port the component belongs to.
+        access_port: u8,
+        /// The base address of the component.
+        address: u64,
+        /// The number of ROM tables the component is nested in.
+        depth: usize,
+        /// The class of the component, e.g. `class1_rom_table`,
+        /// or `None` if the component could not be identified.
+        class: Option<String>,
+        /// The name of the designer of the component, if it is known.
+        designer: Option<String>,
+        /// The part number of the component, if it could be identified.
+        part: Option<u16>,
+    },
+    /// The chip which was identified, and the matching targets in the registry.
+    DetectedChip {
+        /// The identification of the chip.
+        chip_info: String,
+        /// The names of the matching targets.
+        candidates: Vec<String>,
+    },
+    /// Whether the debug access of the chip is locked.
+    LockState {
+        /// Whether the chip is locked.
+        locked: bool,
+        /// The chip family whose protection was found, if the chip is locked.
+        family: Option<String>,
+        /// The ways to recover the chip.
+        recovery_options: Vec<String>,
+    },
+    /// The measured clock frequency of the first core of the target.
+    CoreClock {
+        /// The frequency in Hz.
+        frequency: f64,
+        /// The relative uncertainty of the frequency.
+        accuracy: f64,
+        /// How the frequency was measured, e.g. `DWT cycle counter`.
+        method: String,
+    },
+    /// A progress event of a flash operation.
+    Progress(ProgressRecord),
+    /// The result of a download, which is always the last record of the download.
+    DownloadFinished {
+        /// The path of the downloaded file.
+        path: String,
+        /// Whether the download was successful.
+        success: bool,
+        /// The error which stopped the download, if it failed.
+        error: Option<String>,
+        /// The time the download took, in microseconds.
+        total_time_us: u64,
+    },
+}
+
+impl From<&ClockMeasurement> for Record {
+    fn from(measurement: &ClockMeasurement) -> Self {
+        Record::CoreClock {
+            frequency: measurement.frequency,
+            accuracy: measurement.accuracy,
+            method: measurement.method.to_string(),
+        }
+    }
+}
+
+impl From<&CoresightComponent> for Record {
+    fn from(component: &CoresightComponent) -> Self {
+        let class = component.class.map(|class| match class {
+            ComponentClass::CoreSight if component.is_rom_table() => "class9_rom_table",
+            class => class.name(),
+        });
+
+        Record::Component {
+            access_port: component.access_port,
+            address: component.address,
+            depth: component.depth,
+            class: class.map(ToOwned::to_owned),
+            designer: component.designer().map(ToOwned::to_owned),
+            part: component.peripheral_id.as_ref().map(|id| id.part()),
+        }
+    }
+}
+
+/// The identity of a debug probe, see [DebugProbeInfo].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProbeRecord {
+    /// The name of the probe.
+    pub identifier: String,
+    /// The USB vendor ID of the probe.
+    pub vendor_id: u16,
+    /// The USB product ID of the probe.
+    pub product_id: u16,
+    /// The serial number of the probe, if it has one.
+    pub serial_number: Option<String>,
+    /// The type of the probe, e.g. `STLink`.
+    pub probe_type: String,
+    /// The USB port path of the probe, if it is known.
+    pub port_path: Option<String>,
+    /// The capabilities of the probe, if it could be opened to query them.
+    pub capabilities: Option<ProbeCapabilities>,
+}
+
+impl From<&DebugProbeInfo> for ProbeRecord {
+    fn from(info: &DebugProbeInfo) -> Self {
+        ProbeRecord {
+            identifier: info.identifier.clone(),
+            vendor_id: info.vendor_id,
+            product_id: info.product_id,
+            serial_number: info.serial_number.clone(),
+            probe_type: format!("{:?}", info.probe_type),
+            port_path: info.port_path.clone(),
+            capabilities: None,
+        }
+    }
+}
+
+/// The functions an opened debug probe supports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProbeCapabilities {
+    /// The probe can debug ARM chips.
+    pub arm: bool,
+    /// The probe can debug RISC-V chips.
+    pub riscv: bool,
+    /// The probe measures the target voltage.
+    pub target_voltage: bool,
+    /// The probe measures the current drawn by the target.
+    pub target_current: bool,
+    /// The probe switches the power supply of the target.
+    pub target_power: bool,
+}
+
+impl From<&Probe> for ProbeCapabilities {
+    fn from(probe: &Probe) -> Self {
+        let power = probe.power_capabilities();
+
+        ProbeCapabilities {
+            arm: probe.has_arm_interface(),
+            riscv: probe.has_riscv_interface(),
+            target_voltage: power.target_voltage,
+            target_current: power.target_current,
+            target_power: power.target_power,
+        }
+    }
+}
+
+/// A progress event of a flash operation, see [ProgressEvent].
+///
+/// Durations are given in microseconds.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ProgressRecord {
+    /// See [ProgressEvent::Initialized].
+    Initialized {
+        /// The number of sectors which are erased.
+        sectors: usize,
+        /// The number of pages which are programmed.
+        pages: usize,
+        /// The number of fills of the pages.
+        fills: usize,
+    },
+    /// See [ProgressEvent::SectorsSkipped].
+    SectorsSkipped { count: usize, size: u32 },
+    /// See [ProgressEvent::StartedFilling].
+    StartedFilling,
+    /// See [ProgressEvent::PageFilled].
+    PageFilled {
+        address: u32,
+        size: u32,
+        time_us: u64,
+    },
+    /// See [ProgressEvent::FailedFilling].
+    FailedFilling,
+    /// See [ProgressEvent::FinishedFilling].
+    FinishedFilling {
+        total_bytes: u32,
+        total_time_us: u64,
+    },
+    /// See [ProgressEvent::StartedErasing].
+    StartedErasing,
+    /// See [ProgressEvent::SectorErased].
+    SectorErased {
+        address: u32,
+        size: u32,
+        time_us: u64,
+    },
+    /// See [ProgressEvent::FailedErasing].
+    FailedErasing,
+    /// See [ProgressEvent::FinishedErasing].
+    FinishedErasing {
+        total_bytes: u32,
+        total_time_us: u64,
+    },
+    /// See [ProgressEvent::StartedProgramming].
+    StartedProgramming,
+    /// See [ProgressEvent::PageProgrammed].
+    PageProgrammed {
+        address: u32,
+        size: u32,
+        time_us: u64,
+    },
+    /// See [ProgressEvent::FailedProgramming].
+    FailedProgramming,
+    /// See [ProgressEvent::FinishedProgramming].
+    FinishedProgramming {
+        total_bytes: u32,
+        total_time_us: u64,
+    },
+    /// See [ProgressEvent::StartedVerifying].
+    StartedVerifying,
+    /// See [ProgressEvent::PageVerified].
+    PageVerified {
+        address: u32,
+        size: u32,
+        time_us: u64,
+    },
+    /// See [ProgressEvent::FailedVerifying].
+    FailedVerifying,
+    /// See [ProgressEvent::FinishedVerifying].
+    FinishedVerifying {
+        total_bytes: u32,
+        total_time_us: u64,
+    },
+    /// See [ProgressEvent::StartedReading].
+    StartedReading,
+    /// See [ProgressEvent::DataRead].
+    DataRead {
+        address: u32,
+        size: u32,
+        time_us: u64,
+    },
+    /// See [ProgressEvent::FailedReading].
+    FailedReading,
+    /// See [ProgressEvent::FinishedReading].
+    FinishedReading {
+        total_bytes: u32,
+        total_time_us: u64,
+    },
+}
+
+impl From<&ProgressEvent> for ProgressRecord {
+    fn from(event: &ProgressEvent) -> Self {
+        use ProgressEvent::*;
+
+        match event {
+            Initialized { flash_layout } => ProgressRecord::Initialized {
+                sectors: flash_layout.sectors().len(),
+                pages: flash_layout.pages().len(),
+                fills: flash_layout.fills().len(),
+            },
+            SectorsSkipped { count, size } => ProgressRecord::SectorsSkipped {
+                count: *count,
+                size: *size,
+            },
+            StartedFilling => ProgressRecord::StartedFilling,
+            PageFilled {
+                address,
+                size,
+                time,
+            } => ProgressRecord::PageFilled {
+                address: *address,
+                size: *size,
+                time_us: time.as_micros() as u64,
+            },
+            FailedFilling => ProgressRecord::FailedFilling,
+            FinishedFilling {
+                total_bytes,
+                total_time,
+            } => ProgressRecord::FinishedFilling {
+                total_bytes: *total_bytes,
+                total_time_us: total_time.as_micros() as u64,
+            },
+            StartedErasing => ProgressRecord::StartedErasing,
+            SectorErased {
+                address,
+                size,
+                time,
+            } => ProgressRecord::SectorErased {
+                address: *address,
+                size: *size,
+                time_us: time.as_micros() as u64,
+            },
+            FailedErasing => ProgressRecord::FailedErasing,
+            FinishedErasing {
+                total_bytes,
+                total_time,
+            } => ProgressRecord::FinishedErasing {
+                total_bytes: *total_bytes,
+                total_time_us: total_time.as_micros() as u64,
+            },
+            StartedProgramming => ProgressRecord::StartedProgramming,
+            PageProgrammed {
+                address,
+                size,
+                time,
+            } => ProgressRecord::PageProgrammed {
+                address: *address,
+                size: *size,
+                time_us: time.as_micros() as u64,
+            },
+            FailedProgramming => ProgressRecord::FailedProgramming,
+            FinishedProgramming {
+                total_bytes,
+                total_time,
+            } => ProgressRecord::FinishedProgramming {
+                total_bytes: *total_bytes,
+                total_time_us: total_time.as_micros() as u64,
+            },
+            StartedVerifying => ProgressRecord::StartedVerifying,
+            PageVerified {
+                address,
+                size,
+                time,
+            } => ProgressRecord::PageVerified {
+                address: *address,
+                size: *size,
+                time_us: time.as_micros() as u64,
+            },
+            FailedVerifying => ProgressRecord::FailedVerifying,
+            FinishedVerifying {
+                total_bytes,
+                total_time,
+            } => ProgressRecord::FinishedVerifying {
+                total_bytes: *total_bytes,
+                total_time_us: total_time.as_micros() as u64,
+            },
+            StartedReading => ProgressRecord::StartedReading,
+            DataRead {
+                address,
+                size,
+                time,
+            } => ProgressRecord::DataRead {
+                address: *address,
+                size: *size,
+                time_us: time.as_micros() as u64,
+            },
+            FailedReading => ProgressRecord::FailedReading,
+            FinishedReading {
+                total_bytes,
+                total_time,
+            } => ProgressRecord::FinishedReading {
+                total_bytes: *total_bytes,
+                total_time_us: total_time.as_micros() as u64,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ProgressRecord, Record};
+    use crate::flashing::ProgressEvent;
+    use std::time::Duration;
+
+    #[test]
+    fn progress_records_are_tagged() {
+        let event = ProgressEvent::PageProgrammed {
+            address: 0x0800_0000,
+            size: 1024,
+            time: Duration::from_micros(1500),
+        };
+
+        let record = Record::Progress(ProgressRecord::from(&event));
+        let json = serde_json::to_string(&record).unwrap();
+
+        assert_eq!(
+            json,
+            r#"{"record":"progress","event":"page_programmed","address":134217728,"size":1024,"time_us":1500}"#
+        );
+        assert_eq!(serde_json::from_str::<Record>(&json).unwrap(), record);
+    }
+}
@@ -6,18 +6,32 @@ use crate::architecture::{
             ApInformation::{MemoryAp, Other},
             ArmProbeInterface, MemoryApInformation,
         },
-        core::{debug_core_start, reset_catch_clear, reset_catch_set},
-        memory::Component,
-        SwoConfig,
+        core::{debug_core_start, m4::Dhcsr, reset_catch_clear, reset_catch_set},
+        kinetis, lock,
+        memory::{discover_components, Component, CoresightComponent},
+        sequences::{
+            CoreSequenceInterface, SequenceRunner, DEBUG_CORE_START, DEBUG_DEVICE_UNLOCK,
+            RESET_CATCH_CLEAR, RESET_CATCH_SET,
+        },
+        FaultPolicy, SwoConfig,
     },
     riscv::communication_interface::RiscvCommunicationInterface,
 };
+use crate::clock::{self, ClockError, ClockMeasurement};
 use crate::config::{
-    ChipInfo, MemoryRegion, RawFlashAlgorithm, RegistryError, Target, TargetSelector,
+    ChipInfo, DebugFreeze, FreezeBit, MemoryRegion, RegisterWrite, RegistryError, Target,
+    TargetDetection, TargetSelector, UnlockSequence,
+};
+use crate::core::{Architecture, CoreState, ResetSequence, SpecificCoreState};
+use crate::core_dump::{self, CoreDump};
+use crate::events::{self, EventBus, EventReceiver, SessionEvent};
+use crate::{
+    AttachMethod, Core, CoreInterface, CoreRegister, CoreStatus, CoreType, DebugProbe, Error,
+    MemoryInterface, Probe, ResetType, TargetOptions,
 };
-use crate::core::{Architecture, CoreState, SpecificCoreState};
-use crate::{AttachMethod, Core, CoreType, DebugProbe, Error, Probe};
 use anyhow::anyhow;
+use std::borrow::Cow;
+use std::ops::Range;
 use std::time::Duration;
 
 /// The `Session` struct represents an active debug session.
@@ -30,6 +44,9 @@ use std::time::Duration;
 /// For more control, the [Probe::attach()] and [Probe::attach_under_reset()]
 /// methods can be used to open a `Session` from a specific [Probe].
 ///
+/// [Probe::attach_running()] opens a `Session` without halting or resetting the target,
+/// see [Session::is_hot_attached()].
+///
 /// # Usage
 /// To get access to a single [Core] from the `Session`, the [Session::core()] method
 /// can be used.
@@ -41,6 +58,22 @@ pub struct Session {
     target: Target,
     interface: ArchitectureInterface,
     cores: Vec<(SpecificCoreState, CoreState)>,
+    /// Whether the enable sequence of each core ran, or the core does not need one.
+    enabled_cores: Vec<bool>,
+    hot_attached: bool,
+    attach_method: AttachMethod,
+    /// How long the attach waited for the target to wake up.
+    woke_up_after: Option<Duration>,
+    reset_type: ResetType,
+    /// The writes which restore the watchdog freeze bits, while they are set.
+    watchdog_restore: Option<Vec<RegisterWrite>>,
+    /// The writes which restore the debug freeze bits of the peripherals, in reverse order.
+    freeze_restore: Vec<RegisterWrite>,
+    events: EventBus,
+    /// The status of each core when it was last polled with [Session::poll_events].
+    polled_status: Vec<Option<CoreStatus>>,
+    /// The CoreSight components, once they were discovered.
+    components: Option<Vec<CoresightComponent>>,
 }
 
 #[derive(Debug)]
@@ -63,10 +96,11 @@ impl ArchitectureInterface {
         &'probe mut self,
         core: &'probe mut SpecificCoreState,
         core_state: &'probe mut CoreState,
+        access_port: u8,
     ) -> Result<Core<'probe>, Error> {
         match self {
             ArchitectureInterface::Arm(state) => {
-                let memory = state.memory_interface(0.into())?;
+                let memory = state.memory_interface(access_port.into())?;
 
                 core.attach_arm(core_state, memory)
             }
@@ -75,6 +109,15 @@ impl ArchitectureInterface {
     }
 }
 
+impl<'a> AsRef<dyn DebugProbe + 'a> for ArchitectureInterface {
+    fn as_ref(&self) -> &(dyn DebugProbe + 'a) {
+        match self {
+            ArchitectureInterface::Arm(interface) => interface.as_ref().as_ref(),
+            ArchitectureInterface::Riscv(interface) => interface.as_ref(),
+        }
+    }
+}
+
 impl<'a> AsMut<dyn DebugProbe + 'a> for ArchitectureInterface {
     fn as_mut(&mut self) -> &mut (dyn DebugProbe + 'a) {
         match self {
@@ -90,64 +133,165 @@ impl Session {
         probe: Probe,
         target: impl Into<TargetSelector>,
         attach_method: AttachMethod,
+        hot_attach: bool,
     ) -> Result<Self, Error> {
         let (probe, target) = get_target_from_selector(target, probe)?;
 
+        let reset_type = target.default_reset_type.clone();
+        log::info!("Using reset type {}", reset_type);
+
+        let freeze_watchdogs = probe.freeze_watchdogs().unwrap_or(!hot_attach);
+        let frozen_peripherals = probe.frozen_peripherals().to_vec();
+        let woke_up_after = probe.woke_up_after();
+        let on_wakeup = matches!(attach_method, AttachMethod::OnWakeup { .. });
+
+        // The cores have to be halted to access them through the debug module,
+        // and the low power debug bits are only known for ARM targets.
+        if (hot_attach || on_wakeup) && target.architecture() != Architecture::Arm {
+            return Err(Error::ArchitectureRequired(&["ARMv7", "ARMv8"]));
+        }
+
         let mut session = match target.architecture() {
             Architecture::Arm => {
-                let core = (
-                    SpecificCoreState::from_core_type(target.core_type),
-                    Core::create_state(0),
-                );
+                let cores = core_states(&target);
+                let enabled_cores = enabled_cores(&target);
+
+                let interface = probe.into_arm_interface()?.unwrap();
 
-                let interface = probe.into_arm_interface()?;
+                let has_mdm_ap =
+                    target.unlock_sequence == Some(UnlockSequence::KinetisMdmApMassErase);
 
                 let mut session = Session {
                     target,
-                    interface: ArchitectureInterface::Arm(interface.unwrap()),
-                    cores: vec![core],
+                    interface: ArchitectureInterface::Arm(interface),
+                    polled_status: vec![None; cores.len()],
+                    cores,
+                    enabled_cores,
+                    hot_attached: hot_attach,
+                    attach_method,
+                    woke_up_after,
+                    reset_type,
+                    watchdog_restore: None,
+                    freeze_restore: Vec::new(),
+                    events: EventBus::default(),
+                    components: None,
                 };
 
-                // Enable debug mode
-                debug_core_start(&mut session.core(0)?)?;
+                if on_wakeup {
+                    // The core can go back to sleep at any moment, and power down the debug port again.
+                    session.enable_low_power_debug()?;
+                }
 
-                if attach_method == AttachMethod::UnderReset {
-                    // we need to halt the chip here
-                    reset_catch_set(&mut session.core(0)?)?;
+                match attach_method {
+                    AttachMethod::UnderReset { settle_time, .. } if has_mdm_ap => {
+                        // The AHB-AP is not accessible while the reset pin is asserted,
+                        // so the MDM-AP keeps the core in reset instead of the pin.
+                        kinetis::hold_core_in_reset(&mut **session.get_arm_interface()?, true)?;
+                        session.interface.as_mut().target_reset_deassert()?;
+                        std::thread::sleep(settle_time);
+                    }
+                    _ => (),
+                }
 
-                    // Deassert the reset pin
-                    session.interface.as_mut().target_reset_deassert()?;
+                // The access port of a locked chip can not be used at all, so an error
+                // of the unlock sequence is only reported if the chip is not locked.
+                let unlocked = session.run_debug_sequence(DEBUG_DEVICE_UNLOCK);
+                session.check_locked()?;
+                unlocked?;
 
-                    // Wait for the core to be halted
-                    let mut core = session.core(0)?;
+                // Enable debug mode
+                session.debug_core_start()?;
+
+                match attach_method {
+                    AttachMethod::Normal => (),
+                    AttachMethod::UnderReset { settle_time, .. } => {
+                        // we need to halt the chip here
+                        if !session.run_debug_sequence(RESET_CATCH_SET)? {
+                            reset_catch_set(&mut session.core(0)?)?;
+                        }
+
+                        if has_mdm_ap {
+                            kinetis::hold_core_in_reset(
+                                &mut **session.get_arm_interface()?,
+                                false,
+                            )?;
+                        } else {
+                            // Deassert the reset pin
+                            session.interface.as_mut().target_reset_deassert()?;
+                            std::thread::sleep(settle_time);
+                        }
+
+                        // Wait for the core to be halted
+                        session
+                            .core(0)?
+                            .wait_for_core_halted(Duration::from_millis(100))?;
+
+                        if !session.run_debug_sequence(RESET_CATCH_CLEAR)? {
+                            reset_catch_clear(&mut session.core(0)?)?;
+                        }
+                    }
+                    AttachMethod::SoftwareOnly => {
+                        session
+                            .core(0)?
+                            .reset_and_halt(Duration::from_millis(100))?;
+                    }
+                    AttachMethod::OnWakeup { .. } => {
+                        // A wakeup from a mode which resets the chip stops the core at the reset vector.
+                        if !session.run_debug_sequence(RESET_CATCH_SET)? {
+                            reset_catch_set(&mut session.core(0)?)?;
+                        }
 
-                    core.wait_for_core_halted(Duration::from_millis(100))?;
+                        session.core(0)?.halt(Duration::from_millis(100))?;
 
-                    reset_catch_clear(&mut core)?;
+                        if !session.run_debug_sequence(RESET_CATCH_CLEAR)? {
+                            reset_catch_clear(&mut session.core(0)?)?;
+                        }
+                    }
                 }
 
                 session
             }
             Architecture::Riscv => {
-                // TODO: Handle attach under reset
-
-                let core = (
-                    SpecificCoreState::from_core_type(target.core_type),
-                    Core::create_state(0),
-                );
+                let cores = core_states(&target);
+                let enabled_cores = enabled_cores(&target);
 
                 let interface = probe.into_riscv_interface()?;
 
                 let mut session = Session {
                     target,
                     interface: ArchitectureInterface::Riscv(interface.unwrap()),
-                    cores: vec![core],
+                    polled_status: vec![None; cores.len()],
+                    cores,
+                    enabled_cores,
+                    hot_attached: false,
+                    attach_method,
+                    woke_up_after,
+                    reset_type,
+                    watchdog_restore: None,
+                    freeze_restore: Vec::new(),
+                    events: EventBus::default(),
+                    components: None,
                 };
 
-                {
-                    let mut core = session.core(0)?;
-
-                    core.halt(Duration::from_millis(100))?;
+                match attach_method {
+                    AttachMethod::Normal | AttachMethod::OnWakeup { .. } => {
+                        session.core(0)?.halt(Duration::from_millis(100))?;
+                    }
+                    AttachMethod::UnderReset { settle_time, .. } => {
+                        // The debug module is reset together with the core,
+                        // so the core can only be halted after the reset was released.
+                        session.interface.as_mut().target_reset_deassert()?;
+                        std::thread::sleep(settle_time);
+
+                        session
+                            .core(0)?
+                            .reset_and_halt(Duration::from_millis(100))?;
+                    }
+                    AttachMethod::SoftwareOnly => {
+                        session
+                            .core(0)?
+                            .reset_and_halt(Duration::from_millis(100))?;
+                    }
                 }
 
                 session
@@ -156,6 +300,18 @@ impl Session {
 
         session.clear_all_hw_breakpoints()?;
 
+        if freeze_watchdogs {
+            // The session is still usable if the watchdogs keep running,
+            // the target is only reset when it is halted for too long.
+            if let Err(error) = session.freeze_watchdogs() {
+                log::warn!("Could not freeze the watchdogs: {}", error);
+            }
+        }
+
+        if !frozen_peripherals.is_empty() {
+            session.freeze_peripherals(&frozen_peripherals)?;
+        }
+
         Ok(session)
     }
 
@@ -174,6 +330,68 @@ impl Session {
         probe.attach(target)
     }
 
+    /// Returns `true` if the session was opened with [Probe::attach_running()].
+    ///
+    /// The target of such a session must not be halted or reset, unless the user
+    /// explicitly asks for it, e.g. because it controls hardware which would be damaged
+    /// if the firmware stopped. Tools built on probe-rs should check this flag before
+    /// halting the target on their own, and report an error instead.
+    pub fn is_hot_attached(&self) -> bool {
+        self.hot_attached
+    }
+
+    /// Returns the [AttachMethod] the session was opened with.
+    pub fn attach_method(&self) -> AttachMethod {
+        self.attach_method
+    }
+
+    /// Returns how long the attach waited for the target to wake up,
+    /// if the session was opened with [AttachMethod::OnWakeup].
+    pub fn woke_up_after(&self) -> Option<Duration> {
+        self.woke_up_after
+    }
+
+    /// Returns the speed in kHz at which the probe communicates with the target.
+    ///
+    /// Unless the speed was set with [Probe::set_speed], this is the speed which was
+    /// selected while attaching, see [Probe::set_speed_selection].
+    pub fn current_speed(&self) -> u32 {
+        self.interface.as_ref().speed()
+    }
+
+    /// Captures the registers and fault status registers of all cores and the given memory
+    /// ranges. If no ranges are given, all RAM regions of the target are captured.
+    ///
+    /// The cores are halted while the dump is taken, and resumed afterwards if they were running.
+    pub fn core_dump(&mut self, ranges: &[Range<u32>]) -> Result<CoreDump, Error> {
+        core_dump::capture(self, ranges)
+    }
+
+    /// Reads the identification of the connected chip, and looks up the chips
+    /// in the target registry which match it.
+    ///
+    /// This is independent of the target the session was opened with, so it can be used
+    /// to find out which chip is connected to a session opened for a generic core.
+    /// If there is more than one candidate, the chip can not be determined unambiguously.
+    pub fn detect_target(&mut self) -> Result<TargetDetection, Error> {
+        let chip_info = match &mut self.interface {
+            ArchitectureInterface::Arm(interface) => {
+                interface.read_from_rom_table()?.map(ChipInfo::from)
+            }
+            ArchitectureInterface::Riscv(interface) => {
+                Some(ChipInfo::from(interface.read_chip_info()?))
+            }
+        }
+        .ok_or(Error::ChipNotFound(RegistryError::ChipAutodetectFailed))?;
+
+        let candidates = crate::config::detect_chips(&chip_info)?;
+
+        Ok(TargetDetection {
+            chip_info,
+            candidates,
+        })
+    }
+
     /// Lists the available cores with their number and their type.
     pub fn list_cores(&self) -> Vec<(usize, CoreType)> {
         self.cores
@@ -184,15 +402,420 @@ impl Session {
     }
 
     /// Attaches to the core with the given number.
+    ///
+    /// The core is reset with the reset type of the session, see [Session::set_reset_type()].
     pub fn core(&mut self, n: usize) -> Result<Core<'_>, Error> {
-        let (core, core_state) = self.cores.get_mut(n).ok_or(Error::CoreNotFound(n))?;
+        if n >= self.cores.len() {
+            return Err(Error::CoreNotFound(n));
+        }
+        if !self.enabled_cores[n] {
+            self.enable_core(n)?;
+        }
+
+        let access_port = self.target.cores.get(n).map_or(0, |core| core.access_port);
+        let (core, core_state) = &mut self.cores[n];
+
+        let reset_sequence = match &self.reset_type {
+            ResetType::Custom(name) => {
+                let runner = SequenceRunner::new(
+                    &self.target.debug_sequences,
+                    self.target.debug_vars.as_deref(),
+                );
 
-        self.interface.attach(core, core_state)
+                Some(Box::new(move |core: &mut dyn CoreInterface| {
+                    runner
+                        .run(&mut CoreSequenceInterface(core), name)
+                        .map_err(Error::from)
+                }) as ResetSequence<'_>)
+            }
+            _ => None,
+        };
+
+        let mut core = self.interface.attach(core, core_state, access_port)?;
+        core.set_reset_type(self.reset_type.clone(), reset_sequence);
+        core.set_memory_map(&self.target.memory_map);
+
+        Ok(core)
+    }
+
+    /// Runs the enable sequence of the core `n` through the first core.
+    fn enable_core(&mut self, n: usize) -> Result<(), Error> {
+        let core = self.target.cores[n].clone();
+        log::debug!("Enabling core {} ({})", n, core.name);
+
+        write_registers(&mut self.core(0)?, &core.enable_sequence)?;
+        self.enabled_cores[n] = true;
+
+        Ok(())
+    }
+
+    /// Subscribes to the events of the session.
+    ///
+    /// The receiver can lag behind by a few hundred events, later events are dropped
+    /// until it catches up, see [EventReceiver::dropped].
+    pub fn subscribe(&mut self) -> EventReceiver {
+        self.subscribe_with_capacity(events::DEFAULT_CAPACITY)
+    }
+
+    /// Subscribes to the events of the session, with a receiver which can lag behind by `capacity` events.
+    pub fn subscribe_with_capacity(&mut self, capacity: usize) -> EventReceiver {
+        self.events.subscribe(capacity)
+    }
+
+    /// Sends an event to all subscribers.
+    ///
+    /// This is used by code outside of probe-rs which polls the target, e.g. for RTT data.
+    pub fn emit(&self, event: SessionEvent) {
+        self.events.emit(event);
+    }
+
+    pub(crate) fn event_bus(&self) -> &EventBus {
+        &self.events
+    }
+
+    /// Polls the status of all cores, and sends the changes since the last poll to the subscribers.
+    ///
+    /// Resets are detected on ARM cores, if no one else read the sticky reset bit of the core since.
+    /// A failed poll is sent as [SessionEvent::ProbeError], and returned.
+    pub fn poll_events(&mut self) -> Result<(), Error> {
+        for n in 0..self.cores.len() {
+            if let Err(e) = self.poll_core(n) {
+                self.events.emit(SessionEvent::ProbeError(e.to_string()));
+                return Err(e);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn poll_core(&mut self, n: usize) -> Result<(), Error> {
+        let architecture = self.architecture();
+        let mut core = self.core(n)?;
+
+        let reset = architecture == Architecture::Arm
+            && Dhcsr(core.read_word_32(Dhcsr::ADDRESS)?).s_reset_st();
+        let status = core.status()?;
+        drop(core);
+
+        if reset {
+            self.events.emit(SessionEvent::ResetDetected { core: n });
+        }
+
+        let previous = self.polled_status[n].replace(status);
+        match status {
+            CoreStatus::Halted(reason) if !matches!(previous, Some(CoreStatus::Halted(_))) => {
+                self.events.emit(SessionEvent::Halted { core: n, reason });
+            }
+            CoreStatus::Running | CoreStatus::Sleeping
+                if !matches!(
+                    previous,
+                    Some(CoreStatus::Running) | Some(CoreStatus::Sleeping)
+                ) =>
+            {
+                self.events.emit(SessionEvent::Running { core: n });
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Returns the reset type which is used to reset the cores of the session.
+    ///
+    /// Unless it is changed with [Session::set_reset_type()], this is the default reset type of the target.
+    pub fn reset_type(&self) -> &ResetType {
+        &self.reset_type
+    }
+
+    /// Selects how the cores of the session are reset, e.g. by [Core::reset()] or before flashing.
+    ///
+    /// Returns [Error::UnsupportedResetType] with the reset types of the target
+    /// if the target does not support `reset_type`.
+    pub fn set_reset_type(&mut self, reset_type: ResetType) -> Result<(), Error> {
+        if !self.target.reset_types.contains(&reset_type) {
+            return Err(Error::UnsupportedResetType {
+                reset_type,
+                supported: self.target.reset_types.clone(),
+            });
+        }
+
+        log::info!("Using reset type {}", reset_type);
+        self.reset_type = reset_type;
+
+        Ok(())
+    }
+
+    /// Measures the clock frequency of the first core, see [clock](crate::clock) for the methods
+    /// which are used.
+    ///
+    /// The firmware is stopped for about [MEASUREMENT_DURATION](crate::clock::MEASUREMENT_DURATION),
+    /// and resumed afterwards if it was running.
+    pub fn measure_core_clock(&mut self) -> Result<ClockMeasurement, ClockError> {
+        clock::measure(self, clock::MEASUREMENT_DURATION)
     }
 
-    /// Returns a list of the flash algotithms on the target.
-    pub(crate) fn flash_algorithms(&self) -> &[RawFlashAlgorithm] {
-        &self.target.flash_algorithms
+    /// Keeps the debug port of the target powered in its low power modes, with the
+    /// [LowPowerDebug](crate::config::LowPowerDebug) description of the target.
+    ///
+    /// This is done automatically when attaching with [AttachMethod::OnWakeup].
+    /// If the low power debug bits of the target are not known, nothing is changed.
+    pub fn enable_low_power_debug(&mut self) -> Result<(), Error> {
+        let low_power_debug = match self.target.low_power_debug.clone() {
+            Some(low_power_debug) => low_power_debug,
+            None => {
+                log::warn!(
+                    "No low power debug bits are known for {}, the debug port might be powered down when the target sleeps",
+                    self.target.name
+                );
+                return Ok(());
+            }
+        };
+
+        write_registers(&mut self.core(0)?, &low_power_debug.enable)?;
+        log::debug!(
+            "Enabled debugging in the low power modes of {}",
+            self.target.name
+        );
+
+        Ok(())
+    }
+
+    /// Stops the watchdogs of the target while its cores are halted, with the
+    /// [WatchdogFreeze](crate::config::WatchdogFreeze) description of the target.
+    ///
+    /// This is done automatically when attaching, unless it was disabled with
+    /// [Probe::set_freeze_watchdogs] or the session was opened with [Probe::attach_running].
+    /// The original values of the freeze bits are restored with [Session::unfreeze_watchdogs()],
+    /// or when the session is dropped.
+    ///
+    /// If the watchdogs of the target are not known, nothing is changed.
+    pub fn freeze_watchdogs(&mut self) -> Result<(), Error> {
+        if self.watchdog_restore.is_some() {
+            return Ok(());
+        }
+
+        let watchdog_freeze = match self.target.watchdog_freeze.clone() {
+            Some(watchdog_freeze) => watchdog_freeze,
+            None => {
+                log::info!(
+                    "No watchdog control is known for {}, watchdogs keep running while the target is halted",
+                    self.target.name
+                );
+                return Ok(());
+            }
+        };
+
+        let restore = {
+            let mut core = self.core(0)?;
+
+            write_registers(&mut core, &watchdog_freeze.unlock)?;
+
+            let mut restore = Vec::with_capacity(watchdog_freeze.freeze.len());
+            write_and_record(&mut core, watchdog_freeze.freeze.iter(), &mut restore)?;
+            restore
+        };
+
+        log::debug!("Froze the watchdogs of {}", self.target.name);
+        self.watchdog_restore = Some(restore);
+
+        Ok(())
+    }
+
+    /// Restores the watchdog freeze bits which were set by [Session::freeze_watchdogs()].
+    pub fn unfreeze_watchdogs(&mut self) -> Result<(), Error> {
+        let restore = match self.watchdog_restore.take() {
+            Some(restore) => restore,
+            None => return Ok(()),
+        };
+
+        let unlock = self
+            .target
+            .watchdog_freeze
+            .as_ref()
+            .map(|watchdog_freeze| watchdog_freeze.unlock.to_vec())
+            .unwrap_or_default();
+
+        {
+            let mut core = self.core(0)?;
+
+            write_registers(&mut core, &unlock)?;
+            write_registers(&mut core, &restore)?;
+        }
+
+        log::debug!("Restored the watchdog freeze bits of {}", self.target.name);
+
+        Ok(())
+    }
+
+    /// Returns `true` while the watchdogs are stopped by [Session::freeze_watchdogs()].
+    pub fn watchdogs_frozen(&self) -> bool {
+        self.watchdog_restore.is_some()
+    }
+
+    /// Stops the peripherals with the given names while the cores of the target are halted,
+    /// by setting their [FreezeBit](crate::config::FreezeBit)s. The names are not case sensitive.
+    ///
+    /// The peripherals selected with [Probe::set_frozen_peripherals] are stopped when attaching.
+    /// The original values of the freeze bits are restored with [Session::unfreeze_peripherals()],
+    /// or when the session is dropped.
+    ///
+    /// Returns [Error::UnknownFreezeBit] if the target has no freeze bit with one of the names,
+    /// in which case no bit is changed.
+    pub fn freeze_peripherals(&mut self, names: &[impl AsRef<str>]) -> Result<(), Error> {
+        let debug_freeze = self
+            .target
+            .debug_freeze
+            .clone()
+            .unwrap_or(DebugFreeze {
+                unlock: Cow::Borrowed(&[]),
+                bits: Cow::Borrowed(&[]),
+            });
+
+        let writes = names
+            .iter()
+            .map(|name| {
+                debug_freeze
+                    .bit(name.as_ref())
+                    .map(FreezeBit::write)
+                    .ok_or_else(|| Error::UnknownFreezeBit {
+                        name: name.as_ref().to_string(),
+                        available: debug_freeze.names(),
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let restore = {
+            let mut core = self.core(0)?;
+
+            write_registers(&mut core, &debug_freeze.unlock)?;
+
+            let mut restore = Vec::with_capacity(writes.len());
+            write_and_record(&mut core, writes.iter(), &mut restore)?;
+            restore
+        };
+
+        for name in names {
+            log::debug!("Froze {} while the target is halted", name.as_ref());
+        }
+        self.freeze_restore.extend(restore);
+
+        Ok(())
+    }
+
+    /// Restores the debug freeze bits which were set by [Session::freeze_peripherals()].
+    pub fn unfreeze_peripherals(&mut self) -> Result<(), Error> {
+        if self.freeze_restore.is_empty() {
+            return Ok(());
+        }
+
+        let mut restore = std::mem::take(&mut self.freeze_restore);
+        restore.reverse();
+
+        let unlock = self
+            .target
+            .debug_freeze
+            .as_ref()
+            .map(|debug_freeze| debug_freeze.unlock.to_vec())
+            .unwrap_or_default();
+
+        let mut core = self.core(0)?;
+
+        write_registers(&mut core, &unlock)?;
+        write_registers(&mut core, &restore)?;
+
+        Ok(())
+    }
+
+    /// Sets up the connection to the target again, after it was reset in a way
+    /// which also reset its debug port.
+    ///
+    /// The target might take a moment before it accepts a connection again,
+    /// so attaching is retried a few times.
+    ///
+    /// This method is only supported for ARM-based targets, and will
+    /// return [Error::ArchitectureRequired] otherwise.
+    pub fn reattach(&mut self) -> Result<(), Error> {
+        const ATTEMPTS: usize = 5;
+
+        let mut attempt = 1;
+        loop {
+            let result = self
+                .get_arm_interface()
+                .and_then(|interface| interface.reinitialize());
+
+            match result {
+                Ok(()) => break,
+                Err(Error::ArchitectureRequired(architectures)) => {
+                    return Err(Error::ArchitectureRequired(architectures))
+                }
+                Err(error) if attempt < ATTEMPTS => {
+                    log::debug!("Reattaching failed ({}/{}): {}", attempt, ATTEMPTS, error);
+                    attempt += 1;
+                    std::thread::sleep(Duration::from_millis(100));
+                }
+                Err(error) => return Err(error),
+            }
+        }
+
+        // The state of the cores is lost with the reset.
+        self.cores = core_states(&self.target);
+        self.enabled_cores = enabled_cores(&self.target);
+
+        self.run_debug_sequence(DEBUG_DEVICE_UNLOCK)?;
+        self.debug_core_start()?;
+
+        Ok(())
+    }
+
+    /// Enables debugging of the first core, with the `DebugCoreStart`
+    /// sequence of the target if it has one.
+    fn debug_core_start(&mut self) -> Result<(), Error> {
+        if !self.run_debug_sequence(DEBUG_CORE_START)? {
+            debug_core_start(&mut self.core(0)?)?;
+        }
+
+        Ok(())
+    }
+
+    /// Executes the debug sequence `name` of the target.
+    ///
+    /// Returns false if the target has no sequence with this name,
+    /// in which case the built-in implementation should be used.
+    fn run_debug_sequence(&mut self, name: &str) -> Result<bool, Error> {
+        let runner = SequenceRunner::new(
+            &self.target.debug_sequences,
+            self.target.debug_vars.as_deref(),
+        );
+
+        if !runner.has_sequence(name) {
+            return Ok(false);
+        }
+
+        let interface = match &mut self.interface {
+            ArchitectureInterface::Arm(interface) => interface,
+            _ => return Err(Error::ArchitectureRequired(&["ARMv7", "ARMv8"])),
+        };
+
+        runner.run(interface, name)?;
+
+        Ok(true)
+    }
+
+    /// Returns the option bytes of the target, which can be read and modified.
+    pub fn target_options(&mut self) -> TargetOptions<'_> {
+        TargetOptions::new(self)
+    }
+
+    /// Sets how transfers which the target answers with FAULT are handled, see [FaultPolicy].
+    ///
+    /// By default, a failed transfer is repeated up to five times before the operation which
+    /// contains it fails.
+    ///
+    /// This method is only supported for ARM-based targets, and will
+    /// return [Error::ArchitectureRequired] otherwise.
+    pub fn set_fault_policy(&mut self, policy: FaultPolicy) -> Result<(), Error> {
+        self.get_arm_interface()?.set_fault_policy(policy);
+        Ok(())
     }
 
     /// Read available data from the SWO interface without waiting.
@@ -204,6 +827,23 @@ impl Session {
         interface.read_swo()
     }
 
+    /// Returns the CoreSight components in the ROM tables of all memory APs, including nested ROM tables.
+    ///
+    /// The ROM tables are only scanned on the first call, later calls return the same components.
+    ///
+    /// This method is only supported for ARM-based targets, and will
+    /// return [Error::ArchitectureRequired] otherwise.
+    pub fn discover_components(&mut self) -> Result<Vec<CoresightComponent>, Error> {
+        if let Some(components) = &self.components {
+            return Ok(components.clone());
+        }
+
+        let components = discover_components(&mut **self.get_arm_interface()?)?;
+        self.components = Some(components.clone());
+
+        Ok(components)
+    }
+
     fn get_arm_interface(&mut self) -> Result<&mut Box<dyn ArmProbeInterface>, Error> {
         let interface = match &mut self.interface {
             ArchitectureInterface::Arm(state) => state,
@@ -213,6 +853,22 @@ impl Session {
         Ok(interface)
     }
 
+    /// Fails with [Error::DeviceLocked] if the debug access of the chip is locked.
+    fn check_locked(&mut self) -> Result<(), Error> {
+        let core_ap = self.target.cores.first().map_or(0, |core| core.access_port);
+        let unlock_sequence = self.target.unlock_sequence;
+        let name = self.target.name.clone();
+
+        let interface = self.get_arm_interface()?;
+        match lock::detect(&mut **interface, core_ap, unlock_sequence)? {
+            Some(mut lock) => {
+                lock.family_hint = lock.family_hint.or(Some(name));
+                Err(lock.into())
+            }
+            None => Ok(()),
+        }
+    }
+
     fn get_arm_component(&mut self) -> Result<Component, Error> {
         let interface = self.get_arm_interface()?;
 
@@ -226,6 +882,8 @@ impl Session {
             MemoryAp(MemoryApInformation {
                 port_number,
                 only_32bit_data_size: _,
+                supports_16bit_data_size: _,
+                supports_64bit_data_size: _,
                 debug_base_address,
                 supports_hnonsec: _,
             }) => {
@@ -281,6 +939,13 @@ impl Session {
         )
     }
 
+    /// Begin tracing the entry and exit of exceptions over SWV.
+    pub fn enable_swv_exception_trace(&mut self) -> Result<(), Error> {
+        let component = self.get_arm_component()?;
+        let mut core = self.core(0)?;
+        crate::architecture::arm::component::enable_swv_exception_trace(&mut core, &component)
+    }
+
     /// Stop tracing from a given SWV unit
     pub fn remove_swv_data_trace(&mut self, unit: usize) -> Result<(), Error> {
         let component = self.get_arm_component()?;
@@ -323,7 +988,21 @@ static_assertions::assert_impl_all!(Session: Send);
 
 impl Drop for Session {
     fn drop(&mut self) {
-        let result: Result<(), crate::Error> = { 0..self.cores.len() }
+        if let Err(err) = self.unfreeze_watchdogs() {
+            log::warn!("Could not restore the watchdog freeze bits: {:?}", err);
+        }
+
+        if let Err(err) = self.unfreeze_peripherals() {
+            log::warn!("Could not restore the debug freeze bits: {:?}", err);
+        }
+
+        // Cores which were never enabled can not have any breakpoints.
+        let enabled: Vec<usize> = { 0..self.cores.len() }
+            .filter(|&i| self.enabled_cores[i])
+            .collect();
+
+        let result: Result<(), crate::Error> = enabled
+            .into_iter()
             .map(|i| {
                 self.core(i)
                     .and_then(|mut core| core.clear_all_set_hw_breakpoints())
@@ -335,6 +1014,62 @@ impl Drop for Session {
         }
     }
 }
+/// Creates the state of each core of the `target`.
+fn core_states(target: &Target) -> Vec<(SpecificCoreState, CoreState)> {
+    (0..target.cores.len().max(1))
+        .map(|id| {
+            (
+                SpecificCoreState::from_core_type(target.core_type),
+                Core::create_state(id),
+            )
+        })
+        .collect()
+}
+
+/// Returns for each core of the `target` whether it can be attached without
+/// running its enable sequence first.
+fn enabled_cores(target: &Target) -> Vec<bool> {
+    (0..target.cores.len().max(1))
+        .map(|n| {
+            target
+                .cores
+                .get(n)
+                .map_or(true, |core| n == 0 || core.enable_sequence.is_empty())
+        })
+        .collect()
+}
+
+/// Applies the register writes in order, keeping the bits outside of their masks.
+fn write_registers(core: &mut Core<'_>, writes: &[RegisterWrite]) -> Result<(), Error> {
+    for write in writes {
+        let current = core.read_word_32(write.address)?;
+        core.write_word_32(write.address, write.apply(current))?;
+    }
+
+    Ok(())
+}
+
+/// Applies the register writes in order, and adds the writes which restore
+/// the original values of the modified bits to `restore`.
+fn write_and_record<'a>(
+    core: &mut Core<'_>,
+    writes: impl Iterator<Item = &'a RegisterWrite>,
+    restore: &mut Vec<RegisterWrite>,
+) -> Result<(), Error> {
+    for write in writes {
+        let current = core.read_word_32(write.address)?;
+        core.write_word_32(write.address, write.apply(current))?;
+
+        restore.push(RegisterWrite {
+            address: write.address,
+            value: current,
+            mask: write.mask,
+        });
+    }
+
+    Ok(())
+}
+
 /// Determine the [Target] from a [TargetSelector].
 ///
 /// If the selector is [TargetSelector::Unspecified], the target will be looked up in the registry.
@@ -379,9 +1114,17 @@ fn get_target_from_selector(
                 let interface = probe.take().unwrap().into_riscv_interface()?;
 
                 if let Some(mut interface) = interface {
-                    let idcode = interface.read_idcode();
-
-                    log::debug!("ID Code read over JTAG: {:x?}", idcode);
+                    log::debug!("Autodetect: Trying RISC-V interface...");
+
+                    match interface.read_chip_info() {
+                        Ok(info) => {
+                            log::debug!("Chip information read over JTAG: {}", info);
+                            found_chip = Some(ChipInfo::from(info));
+                        }
+                        Err(e) => {
+                            log::info!("Error during auto-detection of RISC-V chips: {}", e)
+                        }
+                    }
 
                     probe = Some(interface.close());
                 } else {
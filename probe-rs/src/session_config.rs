@@ -0,0 +1,282 @@
+//! Attach profiles, which store how the sessions of a project are set up.
+//!
+//! The profiles are kept in a [PROFILE_FILE] in the project directory, e.g.
+//!
+//! ```toml
+//! [profiles.default]
+//! chip = "nRF52840_xxAA"
+//! probe = "1366:1015"
+//! speed = 4000
+//!
+//! [profiles.bench]
+//! chip = "nRF52840_xxAA"
+//! probe = "usb:1-3.2"
+//! protocol = "swd"
+//! reset_type = "hardware_pin"
+//! rtt_scan_range = { start = 0x20000000, end = 0x20010000 }
+//! ```
+
+use crate::{
+    config::TargetSelector, DebugProbeError, Error, Probe, ResetType, Session, WireProtocol,
+};
+use std::collections::BTreeMap;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// The name of the file which holds the attach profiles of a project.
+pub const PROFILE_FILE: &str = ".probe-rs.toml";
+
+/// The profile which is used if no profile is selected.
+pub const DEFAULT_PROFILE: &str = "default";
+
+#[derive(Debug, Error)]
+pub enum SessionConfigError {
+    #[error("The profile file {} could not be accessed", .0.display())]
+    Io(PathBuf, #[source] io::Error),
+    #[error("The profile file {} is invalid", .0.display())]
+    Parse(PathBuf, #[source] toml::de::Error),
+    #[error("The configuration could not be stored")]
+    Serialize(#[from] toml::ser::Error),
+    #[error("The profile '{}' is not defined in {}", .0, .1.display())]
+    ProfileNotFound(String, PathBuf),
+    #[error(
+        "The profile '{}' was selected, but there is no {} in the directory or its parents",
+        .0,
+        PROFILE_FILE
+    )]
+    NoProfileFile(String),
+}
+
+/// How a session is set up: which probe and target are used, and how they are accessed.
+///
+/// All values are optional. Tools resolve them with [SessionConfig::resolve], in this order,
+/// where the first value which is set wins:
+///
+/// 1. The values which are given explicitly, e.g. with command line flags.
+/// 2. The selected profile of the [PROFILE_FILE] of the project, or its [DEFAULT_PROFILE]
+///    if no profile is selected.
+/// 3. The defaults of the library: the connected chip is detected, the only connected probe
+///    is used with its default protocol and speed, and the target is reset with its default
+///    reset type.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SessionConfig {
+    /// The name of the target, or `auto` to detect it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chip: Option<String>,
+    /// The probe, as a selector like `VID:PID[:serial]` or `usb:<port path>`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub probe: Option<String>,
+    /// The protocol speed in kHz.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub speed: Option<u32>,
+    /// The protocol which connects the probe to the target, `swd` or `jtag`.
+    #[serde(default, with = "as_string", skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<WireProtocol>,
+    /// How the target is reset, in the form which is given on the command line,
+    /// e.g. `hardware_pin` or the name of a debug sequence.
+    #[serde(default, with = "as_string", skip_serializing_if = "Option::is_none")]
+    pub reset_type: Option<ResetType>,
+    /// The memory in which the RTT control block is searched.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rtt_scan_range: Option<Range<u32>>,
+}
+
+/// The content of a [PROFILE_FILE].
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ProfileFile {
+    #[serde(default)]
+    profiles: BTreeMap<String, SessionConfig>,
+}
+
+impl SessionConfig {
+    /// Resolves the configuration of a session, see [SessionConfig] for the order.
+    ///
+    /// The [PROFILE_FILE] is looked up in `dir` and its parents. It is an error if `profile`
+    /// is selected but not defined, while a missing [DEFAULT_PROFILE] is skipped.
+    pub fn resolve(
+        explicit: SessionConfig,
+        dir: &Path,
+        profile: Option<&str>,
+    ) -> Result<SessionConfig, SessionConfigError> {
+        let path = match (find_profile_file(dir), profile) {
+            (Some(path), _) => path,
+            (None, Some(name)) => return Err(SessionConfigError::NoProfileFile(name.to_owned())),
+            (None, None) => return Ok(explicit),
+        };
+
+        let mut profiles = read_profile_file(&path)?.profiles;
+        let profile = match profile {
+            Some(name) => profiles
+                .remove(name)
+                .ok_or_else(|| SessionConfigError::ProfileNotFound(name.to_owned(), path))?,
+            None => profiles.remove(DEFAULT_PROFILE).unwrap_or_default(),
+        };
+
+        Ok(explicit.or(profile))
+    }
+
+    /// Returns the values of `self`, and the values of `fallback` where `self` has none.
+    pub fn or(self, fallback: SessionConfig) -> SessionConfig {
+        SessionConfig {
+            chip: self.chip.or(fallback.chip),
+            probe: self.probe.or(fallback.probe),
+            speed: self.speed.or(fallback.speed),
+            protocol: self.protocol.or(fallback.protocol),
+            reset_type: self.reset_type.or(fallback.reset_type),
+            rtt_scan_range: self.rtt_scan_range.or(fallback.rtt_scan_range),
+        }
+    }
+
+    /// Stores the configuration as the profile `name` of the profile file at `path`.
+    ///
+    /// The other profiles of the file are kept, a profile with the same name is replaced.
+    /// Comments in the file are not kept.
+    pub fn save_profile(&self, path: &Path, name: &str) -> Result<(), SessionConfigError> {
+        let mut file = if path.exists() {
+            read_profile_file(path)?
+        } else {
+            ProfileFile::default()
+        };
+
+        file.profiles.insert(name.to_owned(), self.clone());
+
+        let content = toml::to_string(&file)?;
+        std::fs::write(path, content).map_err(|e| SessionConfigError::Io(path.to_owned(), e))
+    }
+
+    /// Returns the target which is selected by [SessionConfig::chip].
+    pub fn target_selector(&self) -> TargetSelector {
+        match &self.chip {
+            Some(chip) if !chip.eq_ignore_ascii_case("auto") => chip.as_str().into(),
+            _ => TargetSelector::Auto,
+        }
+    }
+
+    /// Selects the protocol and the speed of the configuration on `probe`, before it is attached.
+    pub fn configure_probe(&self, probe: &mut Probe) -> Result<(), DebugProbeError> {
+        if let Some(protocol) = self.protocol {
+            probe.select_protocol(protocol)?;
+        }
+
+        if let Some(speed) = self.speed {
+            probe.set_speed(speed)?;
+        }
+
+        Ok(())
+    }
+
+    /// Selects the reset type of the configuration for `session`.
+    pub fn configure_session(&self, session: &mut Session) -> Result<(), Error> {
+        if let Some(reset_type) = &self.reset_type {
+            session.set_reset_type(reset_type.clone())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the [PROFILE_FILE] in `dir` or the closest of its parents.
+pub fn find_profile_file(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join(PROFILE_FILE))
+        .find(|path| path.is_file())
+}
+
+fn read_profile_file(path: &Path) -> Result<ProfileFile, SessionConfigError> {
+    let content =
+        std::fs::read_to_string(path).map_err(|e| SessionConfigError::Io(path.to_owned(), e))?;
+
+    toml::from_str(&content).map_err(|e| SessionConfigError::Parse(path.to_owned(), e))
+}
+
+/// Stores optional values in the form in which they are given on the command line.
+mod as_string {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+    use std::fmt::Display;
+    use std::str::FromStr;
+
+    pub(super) fn serialize<T: Display, S: Serializer>(
+        value: &Option<T>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(value) => serializer.serialize_some(&value.to_string()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub(super) fn deserialize<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+    where
+        T: FromStr,
+        T::Err: Display,
+        D: Deserializer<'de>,
+    {
+        Option::<String>::deserialize(deserializer)?
+            .map(|value| value.parse().map_err(de::Error::custom))
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ProfileFile, SessionConfig};
+    use crate::{ResetType, WireProtocol};
+    use std::borrow::Cow;
+
+    const PROFILES: &str = r#"
+[profiles.default]
+chip = "nRF52840_xxAA"
+speed = 4000
+
+[profiles.bench]
+chip = "nRF52840_xxAA"
+probe = "usb:1-3.2"
+protocol = "swd"
+reset_type = "connect_sequence"
+rtt_scan_range = { start = 0x20000000, end = 0x20010000 }
+"#;
+
+    #[test]
+    fn profiles_are_parsed() {
+        let file: ProfileFile = toml::from_str(PROFILES).unwrap();
+
+        assert_eq!(
+            file.profiles["bench"],
+            SessionConfig {
+                chip: Some("nRF52840_xxAA".to_owned()),
+                probe: Some("usb:1-3.2".to_owned()),
+                speed: None,
+                protocol: Some(WireProtocol::Swd),
+                reset_type: Some(ResetType::Custom(Cow::Borrowed("connect_sequence"))),
+                rtt_scan_range: Some(0x2000_0000..0x2001_0000),
+            }
+        );
+    }
+
+    #[test]
+    fn profiles_round_trip() {
+        let file: ProfileFile = toml::from_str(PROFILES).unwrap();
+        let content = toml::to_string(&file).unwrap();
+
+        let parsed: ProfileFile = toml::from_str(&content).unwrap();
+        assert_eq!(parsed.profiles, file.profiles);
+    }
+
+    #[test]
+    fn explicit_values_override_the_profile() {
+        let file: ProfileFile = toml::from_str(PROFILES).unwrap();
+        let explicit = SessionConfig {
+            speed: Some(1000),
+            reset_type: Some(ResetType::HardwarePin),
+            ..Default::default()
+        };
+
+        let config = explicit.or(file.profiles["default"].clone());
+        assert_eq!(config.chip.as_deref(), Some("nRF52840_xxAA"));
+        assert_eq!(config.speed, Some(1000));
+        assert_eq!(config.reset_type, Some(ResetType::HardwarePin));
+        assert_eq!(config.probe, None);
+    }
+}
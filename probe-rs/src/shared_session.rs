@@ -0,0 +1,422 @@
+//! Sharing a [Session] between threads.
+//!
+//! A [SharedSession] is a cloneable handle to a session, which can be sent to other threads.
+//! Each thread locks the session for the probe transactions it needs, and releases it again, so
+//! e.g. a GDB server and a thread which reads telemetry from the target memory can use one probe.
+//!
+//! # Fairness
+//!
+//! The threads get the session in the order in which they asked for it, so a thread which locks
+//! the session in a loop can not starve the others. Long memory accesses with [SharedSession::read_8]
+//! and [SharedSession::write_8] are split into batches, and the session is released between the
+//! batches, so e.g. a halt request is not delayed until the whole access is done. Operations on a
+//! locked session, like flashing, keep the session for their whole duration.
+//!
+//! # Deadlocks
+//!
+//! There is a single lock for the whole session, so there is no lock order to get wrong. The lock
+//! is not reentrant: locking the session again on a thread which holds it panics, instead of
+//! blocking forever. A [Core] borrows the [SessionGuard] it was created from, so it can not be
+//! used after the session was released. Threads which must not block indefinitely use
+//! [SharedSession::try_lock_for].
+
+use crate::asynchronous::Cancellation;
+use crate::{Core, Error, MemoryInterface, Session};
+
+use std::collections::BTreeSet;
+use std::ops::{Deref, DerefMut, Range};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant};
+
+/// The number of bytes which are read or written, before the session is released for other threads.
+const BATCH_SIZE: usize = 1024;
+
+/// A cloneable handle to a [Session], which can be used from multiple threads.
+#[derive(Debug, Clone)]
+pub struct SharedSession {
+    session: Arc<FairMutex<Session>>,
+}
+
+impl SharedSession {
+    /// Shares the `session`.
+    pub fn new(session: Session) -> Self {
+        Self {
+            session: Arc::new(FairMutex::new(session)),
+        }
+    }
+
+    /// Waits until the session is free, and locks it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the session is already locked by the calling thread.
+    pub fn lock(&self) -> SessionGuard<'_> {
+        SessionGuard(self.session.lock())
+    }
+
+    /// Waits for the session at most for `timeout`, and locks it.
+    ///
+    /// Returns `None` if another thread held the session for the whole time.
+    pub fn try_lock_for(&self, timeout: Duration) -> Option<SessionGuard<'_>> {
+        self.session.try_lock_for(timeout).map(SessionGuard)
+    }
+
+    /// Locks the session, and calls `f` with the core with the index `n`.
+    ///
+    /// The session is released when `f` returns.
+    pub fn with_core<T>(
+        &self,
+        n: usize,
+        f: impl FnOnce(&mut Core<'_>) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let mut session = self.lock();
+        let mut core = session.core(n)?;
+        f(&mut core)
+    }
+
+    /// Reads `data.len()` bytes from `address` with the core `n`.
+    ///
+    /// The memory is read in batches, between which other threads can use the session.
+    pub fn read_8(&self, n: usize, address: u32, data: &mut [u8]) -> Result<(), Error> {
+        self.read_8_until(n, address, data, None)
+    }
+
+    /// Writes `data` to `address` with the core `n`.
+    ///
+    /// The memory is written in batches, between which other threads can use the session.
+    pub fn write_8(&self, n: usize, address: u32, data: &[u8]) -> Result<(), Error> {
+        self.write_8_until(n, address, data, None)
+    }
+
+    /// Like [SharedSession::read_8], but fails with [Error::Cancelled] before the next batch
+    /// once `cancellation` is cancelled.
+    pub(crate) fn read_8_until(
+        &self,
+        n: usize,
+        address: u32,
+        data: &mut [u8],
+        cancellation: Option<&Cancellation>,
+    ) -> Result<(), Error> {
+        let length = data.len();
+        self.session.batched(length, BATCH_SIZE, |session, range| {
+            check_cancelled(cancellation)?;
+            session
+                .core(n)?
+                .read_8(address + range.start as u32, &mut data[range])
+        })
+    }
+
+    /// Like [SharedSession::write_8], but fails with [Error::Cancelled] before the next batch
+    /// once `cancellation` is cancelled.
+    pub(crate) fn write_8_until(
+        &self,
+        n: usize,
+        address: u32,
+        data: &[u8],
+        cancellation: Option<&Cancellation>,
+    ) -> Result<(), Error> {
+        self.session
+            .batched(data.len(), BATCH_SIZE, |session, range| {
+                check_cancelled(cancellation)?;
+                session
+                    .core(n)?
+                    .write_8(address + range.start as u32, &data[range])
+            })
+    }
+
+    /// Returns the session, if this is the last handle to it.
+    pub fn into_inner(self) -> Result<Session, Self> {
+        match Arc::try_unwrap(self.session) {
+            Ok(session) => Ok(session.into_inner()),
+            Err(session) => Err(Self { session }),
+        }
+    }
+}
+
+fn check_cancelled(cancellation: Option<&Cancellation>) -> Result<(), Error> {
+    match cancellation {
+        Some(cancellation) if cancellation.is_cancelled() => Err(Error::Cancelled),
+        _ => Ok(()),
+    }
+}
+
+/// The session of a [SharedSession], while it is locked by the current thread.
+///
+/// The session is released when the guard is dropped.
+pub struct SessionGuard<'a>(FairMutexGuard<'a, Session>);
+
+impl Deref for SessionGuard<'_> {
+    type Target = Session;
+
+    fn deref(&self) -> &Session {
+        &self.0
+    }
+}
+
+impl DerefMut for SessionGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Session {
+        &mut self.0
+    }
+}
+
+/// The tickets of a [FairMutex], which are served in the order they were drawn.
+#[derive(Debug, Default)]
+struct Tickets {
+    /// The ticket the next waiting thread draws.
+    next: u64,
+    /// The ticket whose thread may lock the value.
+    serving: u64,
+    /// The thread which holds the lock.
+    owner: Option<ThreadId>,
+    /// Tickets of threads which stopped waiting, which are skipped.
+    abandoned: BTreeSet<u64>,
+}
+
+impl Tickets {
+    /// Moves on to the next ticket which is still waiting.
+    fn advance(&mut self) {
+        self.serving += 1;
+        while self.abandoned.remove(&self.serving) {
+            self.serving += 1;
+        }
+    }
+}
+
+/// A mutex which is locked by the waiting threads in the order they asked for it.
+#[derive(Debug)]
+struct FairMutex<T> {
+    tickets: Mutex<Tickets>,
+    turn: Condvar,
+    value: Mutex<T>,
+}
+
+impl<T> FairMutex<T> {
+    fn new(value: T) -> Self {
+        Self {
+            tickets: Mutex::new(Tickets::default()),
+            turn: Condvar::new(),
+            value: Mutex::new(value),
+        }
+    }
+
+    fn tickets(&self) -> MutexGuard<'_, Tickets> {
+        self.tickets.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Draws a ticket, and panics if the calling thread already holds the lock.
+    fn draw(&self) -> (MutexGuard<'_, Tickets>, u64) {
+        let mut tickets = self.tickets();
+        if tickets.owner == Some(thread::current().id()) {
+            panic!("The shared session is already locked by this thread.");
+        }
+
+        let ticket = tickets.next;
+        tickets.next += 1;
+        (tickets, ticket)
+    }
+
+    fn lock(&self) -> FairMutexGuard<'_, T> {
+        let (mut tickets, ticket) = self.draw();
+        while tickets.serving != ticket {
+            tickets = self
+                .turn
+                .wait(tickets)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+
+        self.acquire(tickets)
+    }
+
+    fn try_lock_for(&self, timeout: Duration) -> Option<FairMutexGuard<'_, T>> {
+        let deadline = Instant::now() + timeout;
+
+        let (mut tickets, ticket) = self.draw();
+        while tickets.serving != ticket {
+            let now = Instant::now();
+            if now >= deadline {
+                tickets.abandoned.insert(ticket);
+                return None;
+            }
+
+            tickets = self
+                .turn
+                .wait_timeout(tickets, deadline - now)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+
+        Some(self.acquire(tickets))
+    }
+
+    fn acquire(&self, mut tickets: MutexGuard<'_, Tickets>) -> FairMutexGuard<'_, T> {
+        tickets.owner = Some(thread::current().id());
+        drop(tickets);
+
+        // A panic of the previous owner does not make the value unusable for the others.
+        let value = self.value.lock().unwrap_or_else(PoisonError::into_inner);
+
+        FairMutexGuard {
+            mutex: self,
+            value: Some(value),
+        }
+    }
+
+    fn release(&self) {
+        let mut tickets = self.tickets();
+        tickets.owner = None;
+        tickets.advance();
+        self.turn.notify_all();
+    }
+
+    /// The number of threads which wait for the lock.
+    #[cfg(test)]
+    fn waiting(&self) -> u64 {
+        let tickets = self.tickets();
+        let held = tickets.owner.is_some() as u64;
+        tickets.next - tickets.serving - held - tickets.abandoned.len() as u64
+    }
+
+    /// Calls `f` for consecutive ranges of `0..length` with at most `batch` elements,
+    /// and releases the lock between the calls.
+    fn batched<E>(
+        &self,
+        length: usize,
+        batch: usize,
+        mut f: impl FnMut(&mut T, Range<usize>) -> Result<(), E>,
+    ) -> Result<(), E> {
+        let mut start = 0;
+        while start < length {
+            let end = (start + batch).min(length);
+            f(&mut self.lock(), start..end)?;
+            start = end;
+        }
+
+        Ok(())
+    }
+
+    fn into_inner(self) -> T {
+        self.value
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+struct FairMutexGuard<'a, T> {
+    mutex: &'a FairMutex<T>,
+    value: Option<MutexGuard<'a, T>>,
+}
+
+impl<T> Deref for FairMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value.as_ref().unwrap()
+    }
+}
+
+impl<T> DerefMut for FairMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.value.as_mut().unwrap()
+    }
+}
+
+impl<T> Drop for FairMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        // The value is unlocked before the next thread is woken up.
+        self.value.take();
+        self.mutex.release();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FairMutex;
+
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    /// Waits until `count` threads wait for the lock.
+    fn wait_for_waiting<T>(mutex: &FairMutex<T>, count: u64) {
+        while mutex.waiting() < count {
+            thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn threads_lock_in_order() {
+        let mutex = Arc::new(FairMutex::new(vec![]));
+        let guard = mutex.lock();
+
+        let threads: Vec<_> = (0..4)
+            .map(|n| {
+                let thread = {
+                    let mutex = mutex.clone();
+                    thread::spawn(move || mutex.lock().push(n))
+                };
+                wait_for_waiting(&mutex, n + 1);
+                thread
+            })
+            .collect();
+
+        drop(guard);
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(*mutex.lock(), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    #[should_panic(expected = "already locked by this thread")]
+    fn locking_twice_panics() {
+        let mutex = FairMutex::new(0);
+        let _guard = mutex.lock();
+        let _second = mutex.lock();
+    }
+
+    #[test]
+    fn abandoned_tickets_are_skipped() {
+        let mutex = Arc::new(FairMutex::new(0));
+        let guard = mutex.lock();
+
+        let waiting = {
+            let mutex = mutex.clone();
+            thread::spawn(move || mutex.try_lock_for(Duration::from_millis(10)).is_none())
+        };
+        assert!(waiting.join().unwrap());
+
+        drop(guard);
+        *mutex.lock() += 1;
+        assert_eq!(*mutex.lock(), 1);
+    }
+
+    #[test]
+    fn other_threads_run_between_batches() {
+        let mutex = Arc::new(FairMutex::new(vec![]));
+        let (started, start) = std::sync::mpsc::channel();
+
+        let halt = {
+            let mutex = mutex.clone();
+            thread::spawn(move || {
+                start.recv().unwrap();
+                mutex.lock().push("halt".to_owned());
+            })
+        };
+
+        mutex
+            .batched(10, 4, |log, range| {
+                if range.start == 0 {
+                    started.send(()).unwrap();
+                    wait_for_waiting(&mutex, 1);
+                }
+                log.push(format!("{:?}", range));
+                Ok::<_, ()>(())
+            })
+            .unwrap();
+        halt.join().unwrap();
+
+        assert_eq!(*mutex.lock(), vec!["0..4", "halt", "4..8", "8..10"]);
+    }
+}
@@ -0,0 +1,807 @@
+//! Names the registers of a target, with the description of its peripherals in a CMSIS-SVD file.
+//!
+//! [Device::parse] reads the peripherals, registers, fields and enumerated values of an SVD file.
+//! [Device::resolve] turns a path like `RCC.CFGR` or `RCC.CFGR.SW` into a [RegisterPath], which
+//! reads and writes the register or field through any [MemoryInterface], e.g. a [Core](crate::Core).
+//!
+//! Arrays of registers and clusters are expanded, e.g. `CCR%s` with a `dim` of 4 becomes `CCR0`
+//! to `CCR3`. The registers of a cluster are named `CLUSTER_REGISTER`.
+
+use crate::{Error, MemoryInterface};
+
+use roxmltree::{Document, Node};
+use std::fmt;
+use std::path::Path;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum SvdError {
+    #[error("The SVD file could not be read")]
+    Io(#[source] std::io::Error),
+    #[error("The SVD file is not valid XML")]
+    Xml(#[source] roxmltree::Error),
+    #[error("The element <{element}> is missing in <{parent}>.")]
+    MissingElement {
+        element: &'static str,
+        parent: String,
+    },
+    #[error("'{0}' is not a valid number.")]
+    InvalidNumber(String),
+    #[error("The peripheral {peripheral} is derived from the unknown peripheral {derived_from}.")]
+    UnknownBase {
+        peripheral: String,
+        derived_from: String,
+    },
+    #[error("'{0}' is not a path of the form PERIPHERAL.REGISTER[.FIELD].")]
+    InvalidPath(String),
+    #[error("The device has no peripheral {0}.")]
+    UnknownPeripheral(String),
+    #[error("The peripheral {peripheral} has no register {register}.")]
+    UnknownRegister {
+        peripheral: String,
+        register: String,
+    },
+    #[error("The register {register} has no field {field}.")]
+    UnknownField { register: String, field: String },
+    #[error("{0} can not be read.")]
+    NotReadable(String),
+    #[error("{0} can not be written.")]
+    NotWritable(String),
+    #[error("The value {value:#x} does not fit into the {bits} bits of {name}.")]
+    ValueTooLarge { name: String, value: u32, bits: u32 },
+    #[error("Something during the interaction with the core went wrong")]
+    Memory(#[source] Error),
+}
+
+impl From<Error> for SvdError {
+    fn from(error: Error) -> Self {
+        SvdError::Memory(error)
+    }
+}
+
+/// How a register or field can be accessed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    ReadOnly,
+    WriteOnly,
+    ReadWrite,
+    WriteOnce,
+    ReadWriteOnce,
+}
+
+impl Access {
+    fn parse(text: &str) -> Option<Self> {
+        match text.trim() {
+            "read-only" => Some(Access::ReadOnly),
+            "write-only" => Some(Access::WriteOnly),
+            "read-write" => Some(Access::ReadWrite),
+            "writeOnce" => Some(Access::WriteOnce),
+            "read-writeOnce" => Some(Access::ReadWriteOnce),
+            _ => None,
+        }
+    }
+
+    /// Returns `true` if reading returns the value of the register or field.
+    pub fn is_readable(self) -> bool {
+        !matches!(self, Access::WriteOnly | Access::WriteOnce)
+    }
+
+    /// Returns `true` if the register or field can be written.
+    pub fn is_writable(self) -> bool {
+        self != Access::ReadOnly
+    }
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Access::ReadOnly => write!(f, "read-only"),
+            Access::WriteOnly => write!(f, "write-only"),
+            Access::ReadWrite => write!(f, "read-write"),
+            Access::WriteOnce => write!(f, "write-once"),
+            Access::ReadWriteOnce => write!(f, "read-write-once"),
+        }
+    }
+}
+
+/// The peripherals of a chip, as described by an SVD file.
+#[derive(Debug, Clone)]
+pub struct Device {
+    pub name: String,
+    pub peripherals: Vec<Peripheral>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Peripheral {
+    pub name: String,
+    pub base_address: u32,
+    pub registers: Vec<Register>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Register {
+    pub name: String,
+    /// The offset from the base address of the peripheral.
+    pub address_offset: u32,
+    /// The size of the register in bits, 8, 16 or 32.
+    pub size: u32,
+    pub access: Access,
+    pub reset_value: u32,
+    pub fields: Vec<Field>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Field {
+    pub name: String,
+    /// The position of the lowest bit of the field.
+    pub offset: u32,
+    /// The number of bits of the field.
+    pub width: u32,
+    pub access: Access,
+    /// The names of the values of the field.
+    pub values: Vec<EnumeratedValue>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnumeratedValue {
+    pub name: String,
+    pub value: u32,
+}
+
+/// The properties which registers inherit from their device, peripheral and cluster.
+#[derive(Debug, Clone, Copy)]
+struct Defaults {
+    size: u32,
+    access: Access,
+    reset_value: u32,
+}
+
+impl Defaults {
+    /// Overrides the defaults with the properties of `node`.
+    fn inherit(self, node: Node) -> Result<Self, SvdError> {
+        Ok(Defaults {
+            size: optional_number(node, "size")?.unwrap_or(self.size),
+            access: child_text(node, "access")
+                .and_then(Access::parse)
+                .unwrap_or(self.access),
+            reset_value: optional_number(node, "resetValue")?.unwrap_or(self.reset_value),
+        })
+    }
+}
+
+impl Device {
+    /// Reads the SVD file at `path`.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, SvdError> {
+        let xml = std::fs::read_to_string(path).map_err(SvdError::Io)?;
+
+        Self::parse(&xml)
+    }
+
+    /// Parses the contents of an SVD file.
+    pub fn parse(xml: &str) -> Result<Self, SvdError> {
+        let document = Document::parse(xml).map_err(SvdError::Xml)?;
+        let device = document.root_element();
+
+        let defaults = Defaults {
+            size: 32,
+            access: Access::ReadWrite,
+            reset_value: 0,
+        }
+        .inherit(device)?;
+
+        let mut peripherals = vec![];
+        let mut derived = vec![];
+
+        if let Some(nodes) = child(device, "peripherals") {
+            for node in children(nodes, "peripheral") {
+                let peripheral = parse_peripheral(node, defaults)?;
+                if let Some(base) = node.attribute("derivedFrom") {
+                    if child(node, "registers").is_none() {
+                        derived.push((peripherals.len(), base.to_string()));
+                    }
+                }
+                peripherals.push(peripheral);
+            }
+        }
+
+        for (index, base) in derived {
+            let registers = peripherals
+                .iter()
+                .find(|peripheral| peripheral.name == base)
+                .map(|peripheral| peripheral.registers.clone())
+                .ok_or_else(|| SvdError::UnknownBase {
+                    peripheral: peripherals[index].name.clone(),
+                    derived_from: base,
+                })?;
+            peripherals[index].registers = registers;
+        }
+
+        Ok(Device {
+            name: child_text(device, "name").unwrap_or_default().to_string(),
+            peripherals,
+        })
+    }
+
+    /// Returns the peripheral with the `name`, which is not case sensitive.
+    pub fn peripheral(&self, name: &str) -> Result<&Peripheral, SvdError> {
+        self.peripherals
+            .iter()
+            .find(|peripheral| peripheral.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| SvdError::UnknownPeripheral(name.to_string()))
+    }
+
+    /// Finds the register or field with a `path` of the form `PERIPHERAL.REGISTER[.FIELD]`.
+    ///
+    /// The names are not case sensitive.
+    pub fn resolve(&self, path: &str) -> Result<RegisterPath<'_>, SvdError> {
+        let parts = path.split('.').collect::<Vec<_>>();
+        let (peripheral, register, field) = match parts.as_slice() {
+            [peripheral, register] => (*peripheral, *register, None),
+            [peripheral, register, field] => (*peripheral, *register, Some(*field)),
+            _ => return Err(SvdError::InvalidPath(path.to_string())),
+        };
+
+        let peripheral = self.peripheral(peripheral)?;
+        let register = peripheral
+            .register(register)
+            .ok_or_else(|| SvdError::UnknownRegister {
+                peripheral: peripheral.name.clone(),
+                register: register.to_string(),
+            })?;
+        let field = field
+            .map(|field| {
+                register.field(field).ok_or_else(|| SvdError::UnknownField {
+                    register: format!("{}.{}", peripheral.name, register.name),
+                    field: field.to_string(),
+                })
+            })
+            .transpose()?;
+
+        Ok(RegisterPath {
+            peripheral,
+            register,
+            field,
+        })
+    }
+}
+
+impl Peripheral {
+    /// Returns the register with the `name`, which is not case sensitive.
+    pub fn register(&self, name: &str) -> Option<&Register> {
+        self.registers
+            .iter()
+            .find(|register| register.name.eq_ignore_ascii_case(name))
+    }
+}
+
+impl Register {
+    /// Returns the field with the `name`, which is not case sensitive.
+    pub fn field(&self, name: &str) -> Option<&Field> {
+        self.fields
+            .iter()
+            .find(|field| field.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Splits the `value` of the register into the values of its fields, from the lowest bit up.
+    pub fn decode(&self, value: u32) -> Vec<FieldValue<'_>> {
+        let mut fields = self
+            .fields
+            .iter()
+            .map(|field| FieldValue {
+                field,
+                value: field.extract(value),
+            })
+            .collect::<Vec<_>>();
+        fields.sort_by_key(|field| field.field.offset);
+
+        fields
+    }
+
+    fn mask(&self) -> u32 {
+        mask(self.size)
+    }
+}
+
+impl Field {
+    /// The bits of the field in its register.
+    pub fn mask(&self) -> u32 {
+        mask(self.width) << self.offset
+    }
+
+    /// Returns the value of the field in the `register` value.
+    pub fn extract(&self, register: u32) -> u32 {
+        (register & self.mask()) >> self.offset
+    }
+
+    /// Returns the name of the `value`, if the field has one for it.
+    pub fn value_name(&self, value: u32) -> Option<&str> {
+        self.values
+            .iter()
+            .find(|enumerated| enumerated.value == value)
+            .map(|enumerated| enumerated.name.as_str())
+    }
+
+    /// Returns the value with the `name`, which is not case sensitive.
+    pub fn value_by_name(&self, name: &str) -> Option<u32> {
+        self.values
+            .iter()
+            .find(|enumerated| enumerated.name.eq_ignore_ascii_case(name))
+            .map(|enumerated| enumerated.value)
+    }
+}
+
+/// The value of a field, see [Register::decode].
+#[derive(Debug, Clone, Copy)]
+pub struct FieldValue<'register> {
+    pub field: &'register Field,
+    pub value: u32,
+}
+
+impl FieldValue<'_> {
+    /// The name of the value, if the field has one for it.
+    pub fn name(&self) -> Option<&str> {
+        self.field.value_name(self.value)
+    }
+}
+
+/// A register, or a field of it, which was found with [Device::resolve].
+#[derive(Debug, Clone, Copy)]
+pub struct RegisterPath<'device> {
+    pub peripheral: &'device Peripheral,
+    pub register: &'device Register,
+    pub field: Option<&'device Field>,
+}
+
+impl RegisterPath<'_> {
+    /// The address of the register.
+    pub fn address(&self) -> u32 {
+        self.peripheral.base_address + self.register.address_offset
+    }
+
+    /// Reads the value of the register, or of the field.
+    pub fn read(&self, memory: &mut impl MemoryInterface) -> Result<u32, SvdError> {
+        let readable = self.register.access.is_readable()
+            && self.field.map_or(true, |field| field.access.is_readable());
+        if !readable {
+            return Err(SvdError::NotReadable(self.to_string()));
+        }
+
+        let value = read_sized(memory, self.address(), self.register.size)?;
+
+        Ok(match self.field {
+            Some(field) => field.extract(value),
+            None => value,
+        })
+    }
+
+    /// Writes the `value` to the register, or to the field.
+    ///
+    /// The other fields of the register keep their values when a field is written. They are read
+    /// first, unless the register is write-only, in which case they are set to the reset value.
+    pub fn write(&self, memory: &mut impl MemoryInterface, value: u32) -> Result<(), SvdError> {
+        let writable = self.register.access.is_writable()
+            && self.field.map_or(true, |field| field.access.is_writable());
+        if !writable {
+            return Err(SvdError::NotWritable(self.to_string()));
+        }
+
+        let (bits, mask) = match self.field {
+            Some(field) => (field.width, mask(field.width)),
+            None => (self.register.size, self.register.mask()),
+        };
+        if value & !mask != 0 {
+            return Err(SvdError::ValueTooLarge {
+                name: self.to_string(),
+                value,
+                bits,
+            });
+        }
+
+        let value = match self.field {
+            Some(field) => {
+                let current = if self.register.access.is_readable() {
+                    read_sized(memory, self.address(), self.register.size)?
+                } else {
+                    self.register.reset_value
+                };
+                (current & !field.mask()) | (value << field.offset)
+            }
+            None => value,
+        };
+
+        write_sized(memory, self.address(), self.register.size, value)
+    }
+}
+
+impl fmt::Display for RegisterPath<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.peripheral.name, self.register.name)?;
+        if let Some(field) = self.field {
+            write!(f, ".{}", field.name)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn read_sized(memory: &mut impl MemoryInterface, address: u32, size: u32) -> Result<u32, SvdError> {
+    Ok(match size {
+        8 => u32::from(memory.read_word_8(address)?),
+        16 => {
+            let mut value = [0u16];
+            memory.read_16(address, &mut value)?;
+            u32::from(value[0])
+        }
+        _ => memory.read_word_32(address)?,
+    })
+}
+
+fn write_sized(
+    memory: &mut impl MemoryInterface,
+    address: u32,
+    size: u32,
+    value: u32,
+) -> Result<(), SvdError> {
+    match size {
+        8 => memory.write_word_8(address, value as u8)?,
+        16 => memory.write_16(address, &[value as u16])?,
+        _ => memory.write_word_32(address, value)?,
+    }
+
+    Ok(())
+}
+
+/// Returns a mask of the lowest `bits` bits.
+fn mask(bits: u32) -> u32 {
+    1u32.checked_shl(bits).map_or(u32::MAX, |bit| bit - 1)
+}
+
+fn parse_peripheral(node: Node, defaults: Defaults) -> Result<Peripheral, SvdError> {
+    let defaults = defaults.inherit(node)?;
+
+    let mut registers = vec![];
+    if let Some(nodes) = child(node, "registers") {
+        parse_registers(nodes, "", 0, defaults, &mut registers)?;
+    }
+
+    Ok(Peripheral {
+        name: required_text(node, "name")?.to_string(),
+        base_address: required_number(node, "baseAddress")?,
+        registers,
+    })
+}
+
+/// Parses the registers and clusters in `node`, with the `prefix` of their cluster
+/// at the `offset` of the cluster.
+fn parse_registers(
+    node: Node,
+    prefix: &str,
+    offset: u32,
+    defaults: Defaults,
+    registers: &mut Vec<Register>,
+) -> Result<(), SvdError> {
+    for element in node.children().filter(|child| child.is_element()) {
+        match element.tag_name().name() {
+            "register" => {
+                let defaults = defaults.inherit(element)?;
+                let fields = match child(element, "fields") {
+                    Some(fields) => children(fields, "field")
+                        .map(|field| parse_fields(field, defaults.access))
+                        .collect::<Result<Vec<_>, _>>()?
+                        .into_iter()
+                        .flatten()
+                        .collect(),
+                    None => vec![],
+                };
+
+                for (name, element_offset) in expand(element)? {
+                    registers.push(Register {
+                        name: format!("{}{}", prefix, name),
+                        address_offset: offset
+                            + required_number(element, "addressOffset")?
+                            + element_offset,
+                        size: defaults.size,
+                        access: defaults.access,
+                        reset_value: defaults.reset_value,
+                        fields: fields.clone(),
+                    });
+                }
+            }
+            "cluster" => {
+                let defaults = defaults.inherit(element)?;
+
+                for (name, element_offset) in expand(element)? {
+                    parse_registers(
+                        element,
+                        &format!("{}{}_", prefix, name),
+                        offset + required_number(element, "addressOffset")? + element_offset,
+                        defaults,
+                        registers,
+                    )?;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    Ok(())
+}
+
+/// Parses a field, which is expanded into several fields if it is an array.
+fn parse_fields(node: Node, access: Access) -> Result<Vec<Field>, SvdError> {
+    let (offset, width) = if let Some(range) = child_text(node, "bitRange") {
+        let invalid = || SvdError::InvalidNumber(range.to_string());
+        let range = range.trim().trim_start_matches('[').trim_end_matches(']');
+        let mut bits = range.splitn(2, ':');
+        let msb = bits.next().ok_or_else(invalid)?;
+        let lsb = bits.next().ok_or_else(invalid)?;
+        let msb = parse_number(msb)?;
+        let lsb = parse_number(lsb)?;
+        (lsb, msb.saturating_sub(lsb) + 1)
+    } else if let Some(lsb) = optional_number(node, "lsb")? {
+        (lsb, required_number(node, "msb")?.saturating_sub(lsb) + 1)
+    } else {
+        (
+            required_number(node, "bitOffset")?,
+            optional_number(node, "bitWidth")?.unwrap_or(1),
+        )
+    };
+
+    let access = child_text(node, "access")
+        .and_then(Access::parse)
+        .unwrap_or(access);
+
+    let mut values: Vec<EnumeratedValue> = vec![];
+    for enumerated in
+        children(node, "enumeratedValues").flat_map(|values| children(values, "enumeratedValue"))
+    {
+        let value = match child_text(enumerated, "value") {
+            // Binary values with "don't care" bits can not be named unambiguously.
+            Some(value)
+                if value.trim().starts_with('#') && value.contains(|c| c == 'x' || c == 'X') =>
+            {
+                continue
+            }
+            Some(value) => parse_number(value)?,
+            None => continue,
+        };
+        let value = EnumeratedValue {
+            name: required_text(enumerated, "name")?.to_string(),
+            value,
+        };
+        if !values.contains(&value) {
+            values.push(value);
+        }
+    }
+
+    expand(node)?
+        .into_iter()
+        .map(|(name, element_offset)| {
+            Ok(Field {
+                name,
+                offset: offset + element_offset,
+                width,
+                access,
+                values: values.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Returns the names and offsets of the elements of an array with a `dim`,
+/// or the name of the element which is not an array.
+fn expand(node: Node) -> Result<Vec<(String, u32)>, SvdError> {
+    let name = required_text(node, "name")?;
+
+    let dim = match optional_number(node, "dim")? {
+        Some(dim) => dim,
+        None => return Ok(vec![(name.to_string(), 0)]),
+    };
+    let increment = required_number(node, "dimIncrement")?;
+
+    let indices = match child_text(node, "dimIndex") {
+        Some(indices) => dim_indices(indices)?,
+        None => (0..dim).map(|index| index.to_string()).collect(),
+    };
+
+    Ok(indices
+        .iter()
+        .enumerate()
+        .map(|(element, index)| {
+            let name = name.replace("[%s]", index).replace("%s", index);
+            (name, element as u32 * increment)
+        })
+        .collect())
+}
+
+/// Parses a `dimIndex`, which is either a range like `0-3` or a list like `A,B,C`.
+fn dim_indices(indices: &str) -> Result<Vec<String>, SvdError> {
+    let indices = indices.trim();
+    let mut range = indices.splitn(2, '-');
+
+    match (range.next(), range.next()) {
+        (Some(start), Some(end)) => {
+            let start = parse_number(start)?;
+            let end = parse_number(end)?;
+            Ok((start..=end).map(|index| index.to_string()).collect())
+        }
+        _ => Ok(indices
+            .split(',')
+            .map(|index| index.trim().to_string())
+            .collect()),
+    }
+}
+
+/// Parses a number of an SVD file, which is decimal, hexadecimal with a `0x` prefix,
+/// or binary with a `#` or `0b` prefix.
+fn parse_number(text: &str) -> Result<u32, SvdError> {
+    let trimmed = text.trim().trim_start_matches('+');
+    let lowercase = trimmed.to_ascii_lowercase();
+
+    let result = if let Some(hex) = lowercase.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16)
+    } else if let Some(binary) = lowercase
+        .strip_prefix('#')
+        .or_else(|| lowercase.strip_prefix("0b"))
+    {
+        u32::from_str_radix(binary, 2)
+    } else {
+        lowercase.parse()
+    };
+
+    result.map_err(|_| SvdError::InvalidNumber(text.to_string()))
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children()
+        .find(|child| child.is_element() && child.tag_name().name() == name)
+}
+
+fn children<'a, 'input: 'a>(
+    node: Node<'a, 'input>,
+    name: &'a str,
+) -> impl Iterator<Item = Node<'a, 'input>> + 'a {
+    node.children()
+        .filter(move |child| child.is_element() && child.tag_name().name() == name)
+}
+
+fn child_text<'a>(node: Node<'a, '_>, name: &str) -> Option<&'a str> {
+    child(node, name).and_then(|child| child.text())
+}
+
+fn required_text<'a>(node: Node<'a, '_>, name: &'static str) -> Result<&'a str, SvdError> {
+    child_text(node, name).ok_or_else(|| SvdError::MissingElement {
+        element: name,
+        parent: node.tag_name().name().to_string(),
+    })
+}
+
+fn optional_number(node: Node, name: &str) -> Result<Option<u32>, SvdError> {
+    child_text(node, name).map(parse_number).transpose()
+}
+
+fn required_number(node: Node, name: &'static str) -> Result<u32, SvdError> {
+    parse_number(required_text(node, name)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_number, Access, Device, SvdError};
+
+    const SVD: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<device schemaVersion="1.1">
+  <name>STM32F4</name>
+  <size>0x20</size>
+  <resetValue>0x0</resetValue>
+  <peripherals>
+    <peripheral>
+      <name>RCC</name>
+      <baseAddress>0x40023800</baseAddress>
+      <registers>
+        <register>
+          <name>CFGR</name>
+          <addressOffset>0x8</addressOffset>
+          <access>read-write</access>
+          <fields>
+            <field>
+              <name>SW</name>
+              <bitOffset>0</bitOffset>
+              <bitWidth>2</bitWidth>
+              <enumeratedValues>
+                <enumeratedValue><name>HSI</name><value>0</value></enumeratedValue>
+                <enumeratedValue><name>HSE</name><value>1</value></enumeratedValue>
+                <enumeratedValue><name>PLL</name><value>#10</value></enumeratedValue>
+              </enumeratedValues>
+            </field>
+            <field>
+              <name>SWS</name>
+              <bitRange>[3:2]</bitRange>
+              <access>read-only</access>
+            </field>
+          </fields>
+        </register>
+        <register>
+          <name>CCR%s</name>
+          <dim>2</dim>
+          <dimIncrement>4</dimIncrement>
+          <addressOffset>0x30</addressOffset>
+          <access>write-only</access>
+          <resetValue>0x100</resetValue>
+        </register>
+        <cluster>
+          <name>PSEL</name>
+          <addressOffset>0x50</addressOffset>
+          <register>
+            <name>SCK</name>
+            <addressOffset>0x4</addressOffset>
+            <size>16</size>
+          </register>
+        </cluster>
+      </registers>
+    </peripheral>
+    <peripheral derivedFrom="RCC">
+      <name>RCC2</name>
+      <baseAddress>0x40024800</baseAddress>
+    </peripheral>
+  </peripherals>
+</device>"#;
+
+    #[test]
+    fn registers_are_resolved_by_path() {
+        let device = Device::parse(SVD).unwrap();
+
+        let sw = device.resolve("rcc.cfgr.sw").unwrap();
+        assert_eq!(sw.address(), 0x4002_3808);
+        assert_eq!(sw.to_string(), "RCC.CFGR.SW");
+        assert_eq!(sw.field.unwrap().value_by_name("pll"), Some(2));
+
+        let sws = device.resolve("RCC.CFGR.SWS").unwrap().field.unwrap();
+        assert_eq!((sws.offset, sws.width), (2, 2));
+        assert_eq!(sws.access, Access::ReadOnly);
+
+        let ccr1 = device.resolve("RCC.CCR1").unwrap();
+        assert_eq!(ccr1.address(), 0x4002_3834);
+        assert_eq!(ccr1.register.access, Access::WriteOnly);
+        assert_eq!(ccr1.register.reset_value, 0x100);
+
+        let sck = device.resolve("RCC.PSEL_SCK").unwrap();
+        assert_eq!(sck.address(), 0x4002_3854);
+        assert_eq!(sck.register.size, 16);
+
+        assert_eq!(device.resolve("RCC2.CFGR").unwrap().address(), 0x4002_4808);
+
+        assert!(matches!(
+            device.resolve("RCC.CFGR.XYZ"),
+            Err(SvdError::UnknownField { .. })
+        ));
+        assert!(matches!(
+            device.resolve("RCC"),
+            Err(SvdError::InvalidPath(_))
+        ));
+    }
+
+    #[test]
+    fn register_values_are_decoded() {
+        let device = Device::parse(SVD).unwrap();
+        let cfgr = device.resolve("RCC.CFGR").unwrap().register;
+
+        let fields = cfgr.decode(0b1010);
+
+        assert_eq!(fields.len(), 2);
+        assert_eq!(fields[0].field.name, "SW");
+        assert_eq!(fields[0].value, 2);
+        assert_eq!(fields[0].name(), Some("PLL"));
+        assert_eq!(fields[1].value, 2);
+        assert_eq!(fields[1].name(), None);
+    }
+
+    #[test]
+    fn numbers_in_all_formats() {
+        assert_eq!(parse_number("42").unwrap(), 42);
+        assert_eq!(parse_number("0x2A").unwrap(), 42);
+        assert_eq!(parse_number("#101010").unwrap(), 42);
+        assert_eq!(parse_number("0b101010").unwrap(), 42);
+        assert_eq!(parse_number(" 0xFFFFFFFF ").unwrap(), u32::MAX);
+        assert!(parse_number("x").is_err());
+    }
+}